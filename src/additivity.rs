@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --check-additivity.
+//
+// Features are supposed to be additive: enabling a feature should not break
+// the build, and should not unexpectedly enable other features. This module
+// looks for two kinds of evidence that this is not the case:
+//
+// - A feature set failed while a superset of it passed. This usually means
+//   that the smaller feature set is missing a dependency on the other features.
+//   This is pure post-processing of the results of the runs.
+// - The features that cargo actually passed to rustc (`--cfg feature="..."`),
+//   taken from the `compiler-artifact` messages of `--message-format json`,
+//   differ from the features that the requested features imply according to
+//   the manifest.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+};
+
+use serde_json::Value;
+
+use crate::{
+    summary::{Run, Status},
+    Context, PackageId, ProcessBuilder,
+};
+
+/// The message format used to capture the features passed to rustc.
+///
+/// Diagnostics are still rendered to stderr as usual with this format.
+pub(crate) const MESSAGE_FORMAT: &str = "--message-format=json-render-diagnostics";
+
+/// Returns the features that the features requested by `line` imply according
+/// to the manifest of the package.
+pub(crate) fn requested_features(
+    cx: &Context,
+    id: &PackageId,
+    line: &ProcessBuilder<'_>,
+) -> BTreeSet<String> {
+    let features = &cx.packages(id).features;
+    let mut stack: Vec<&str> = if line.has_arg("--all-features") {
+        features.keys().map(String::as_str).collect()
+    } else {
+        line.features().collect()
+    };
+    if !line.has_arg("--no-default-features") {
+        stack.push("default");
+    }
+    let mut set = BTreeSet::new();
+    while let Some(f) = stack.pop() {
+        let name = match f.split_once('/') {
+            // `dep?/feature` does not enable `dep`.
+            Some((dep, _)) if dep.ends_with('?') => continue,
+            // `dep/feature` enables the implicit feature of `dep` if it exists.
+            Some((dep, _)) => dep,
+            // `dep:name` does not enable any feature.
+            None if f.starts_with("dep:") => continue,
+            None => f,
+        };
+        if let Some(deps) = features.get(name) {
+            if set.insert(name.to_owned()) {
+                stack.extend(deps.iter().map(String::as_str));
+            }
+        }
+    }
+    set
+}
+
+/// Parses the output of `--message-format json` and returns the features that
+/// cargo enabled for the package, or `None` if the package was not compiled.
+pub(crate) fn enabled_features(stdout: &[u8], id: &PackageId) -> Option<BTreeSet<String>> {
+    let mut set: Option<BTreeSet<String>> = None;
+    for line in String::from_utf8_lossy(stdout).lines() {
+        let Ok(Value::Object(msg)) = serde_json::from_str::<Value>(line) else { continue };
+        if msg.get("reason").and_then(Value::as_str) != Some("compiler-artifact")
+            || msg.get("package_id").and_then(Value::as_str).map(|s| PackageId::from(s.to_owned()))
+                != Some(id.clone())
+        {
+            continue;
+        }
+        let features = msg.get("features").and_then(Value::as_array).into_iter().flatten();
+        set.get_or_insert_with(BTreeSet::new)
+            .extend(features.filter_map(Value::as_str).map(str::to_owned));
+    }
+    set
+}
+
+/// Analyzes the results of the runs and returns the findings.
+pub(crate) fn check(runs: &[Run]) -> Vec<String> {
+    let mut findings = vec![];
+    // Runs with different cfgs (--cfg-matrix) are not comparable.
+    let mut groups: BTreeMap<(&str, Option<&str>, &[String]), Vec<&Run>> = BTreeMap::new();
+    for run in runs.iter().filter(|run| run.status.ran()) {
+        groups.entry((&run.package, run.toolchain.as_deref(), &run.cfgs)).or_default().push(run);
+    }
+
+    for ((package, toolchain, cfgs), runs) in groups {
+        let mut label = toolchain.map_or_else(String::new, |t| format!(" (toolchain {t})"));
+        if !cfgs.is_empty() {
+            write!(label, " (cfg {})", cfgs.join(", ")).unwrap();
+        }
+
+        // A feature set fails while a superset of it passes.
+        for failed in runs.iter().filter(|run| run.status == Status::Failed) {
+            let passed = runs
+                .iter()
+                .filter(|run| {
+                    run.status == Status::Passed
+                        && run.requested_features.len() > failed.requested_features.len()
+                        && run.requested_features.is_superset(&failed.requested_features)
+                })
+                .min_by_key(|run| run.requested_features.len());
+            if let Some(passed) = passed {
+                let missing: Vec<_> =
+                    passed.requested_features.difference(&failed.requested_features).collect();
+                findings.push(format!(
+                    "{package}{label}: {} fails alone but passes with {} ({}); likely missing dependency on {}{}",
+                    describe(&failed.requested_features),
+                    join(&missing),
+                    passed.features,
+                    if missing.len() > 1 { "one of " } else { "" },
+                    join(&missing),
+                ));
+            }
+        }
+
+        // Cargo enabled features that the requested features do not imply.
+        for run in &runs {
+            let Some(enabled) = &run.enabled_features else { continue };
+            if !enabled.is_superset(&run.requested_features) {
+                // The features in the metadata do not match what cargo actually
+                // did; avoid reporting false positives.
+                continue;
+            }
+            let unexpected: Vec<_> = enabled.difference(&run.requested_features).collect();
+            if !unexpected.is_empty() {
+                findings.push(format!(
+                    "{package}{label}: requesting {} also enabled {} ({}); they are probably enabled by other packages or targets through feature unification",
+                    describe(&run.requested_features),
+                    join(unexpected),
+                    run.features,
+                ));
+            }
+        }
+    }
+
+    findings
+}
+
+fn describe(features: &BTreeSet<String>) -> String {
+    match features.len() {
+        0 => "no features".to_owned(),
+        1 => format!("feature {}", join(features)),
+        _ => format!("features {}", join(features)),
+    }
+}
+
+fn join(features: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    features.into_iter().map(|f| format!("`{}`", f.as_ref())).collect::<Vec<_>>().join(", ")
+}
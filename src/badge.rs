@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --badge-json.
+//
+// The file is in the format of the endpoint badges of shields.io
+// (https://shields.io/badges/endpoint-badge), so a badge such as
+// "feature matrix | 204/204 passing" can be displayed from the file uploaded
+// by CI.
+
+use std::{fmt::Write as _, path::Path};
+
+use anyhow::Result;
+
+use crate::fs;
+
+const LABEL: &str = "feature matrix";
+
+/// The results of the runs shown in the badge.
+pub(crate) struct Totals {
+    /// The number of commands that succeeded.
+    pub(crate) passed: usize,
+    /// The number of commands that failed.
+    pub(crate) failed: usize,
+    /// The number of commands to run, including the commands that were not
+    /// run because cargo-hack stopped early (e.g., on the first failure or
+    /// when --budget was exceeded). With --partition, only the commands in
+    /// the partition are counted.
+    pub(crate) total: usize,
+    /// --partition, as `M/N` or the name of the partition.
+    pub(crate) partition: Option<String>,
+    /// Whether some of the requested runs were skipped.
+    pub(crate) skipped: bool,
+    /// Whether the skips make the run fail (--fail-on-skipped).
+    pub(crate) fail_on_skipped: bool,
+}
+
+/// Writes the badge of the results to `path`.
+pub(crate) fn write(path: &Path, totals: &Totals) -> Result<()> {
+    fs::write(path, json(totals) + "\n")
+}
+
+fn json(totals: &Totals) -> String {
+    let mut message = format!("{}/{}", totals.passed, totals.total);
+    match &totals.partition {
+        Some(partition) => write!(message, " (partition {partition})").unwrap(),
+        None => message.push_str(" passing"),
+    }
+    if totals.failed > 0 {
+        write!(message, ", {} failed", totals.failed).unwrap();
+    }
+    let color = if totals.failed > 0 || totals.skipped && totals.fail_on_skipped {
+        "red"
+    } else if totals.skipped || totals.passed < totals.total {
+        "yellow"
+    } else {
+        "brightgreen"
+    };
+    // Neither the label nor the message contains characters that need to be
+    // escaped in JSON.
+    format!(
+        "{{\"schemaVersion\":1,\"label\":\"{LABEL}\",\"message\":\"{message}\",\"color\":\"{color}\"}}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json, Totals};
+
+    fn totals(passed: usize, failed: usize, total: usize) -> Totals {
+        Totals { passed, failed, total, partition: None, skipped: false, fail_on_skipped: false }
+    }
+
+    #[test]
+    fn badge() {
+        assert_eq!(
+            json(&totals(204, 0, 204)),
+            r#"{"schemaVersion":1,"label":"feature matrix","message":"204/204 passing","color":"brightgreen"}"#
+        );
+        assert_eq!(
+            json(&totals(2, 1, 3)),
+            r#"{"schemaVersion":1,"label":"feature matrix","message":"2/3 passing, 1 failed","color":"red"}"#
+        );
+        // Stopped on the first failure.
+        assert!(json(&totals(1, 1, 5)).contains(r#""message":"1/5 passing, 1 failed""#));
+        // --budget exceeded.
+        assert!(json(&totals(3, 0, 5)).contains(r#""color":"yellow""#));
+
+        let partition = Totals { partition: Some("2/4".to_owned()), ..totals(51, 0, 51) };
+        assert!(
+            json(&partition).contains(r#""message":"51/51 (partition 2/4)","color":"brightgreen""#)
+        );
+        let partition = Totals { partition: Some("2/4".to_owned()), ..totals(50, 1, 51) };
+        assert!(json(&partition).contains(r#""message":"50/51 (partition 2/4), 1 failed""#));
+
+        let skipped = Totals { skipped: true, ..totals(3, 0, 3) };
+        assert!(json(&skipped).contains(r#""message":"3/3 passing","color":"yellow""#));
+        let skipped = Totals { skipped: true, fail_on_skipped: true, ..totals(3, 0, 3) };
+        assert!(json(&skipped).contains(r#""color":"red""#));
+    }
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --baseline and --fail-on-new-failures-only.
+//
+// The baseline is the file written by --summary-json in a previous run (e.g.,
+// on the main branch). The runs are matched by their ids (see run_id.rs),
+// which are computed from the package, the features, the cfgs, and the
+// toolchain, so the results of the same run can be compared even if the
+// order of the runs changed. At the end, the runs that newly fail, newly
+// pass, or still fail are reported.
+//
+// With --fail-on-new-failures-only (which requires --keep-going), the runs
+// that also failed in the baseline do not make cargo-hack fail, which is
+// useful when checking whether bumping a dependency breaks anything.
+
+use std::{collections::BTreeMap, path::Path, str::FromStr as _};
+
+use anyhow::{bail, format_err, Context as _, Result};
+use serde_json::Value;
+
+use crate::{
+    fs,
+    summary::{self, Status},
+};
+
+/// The results of the runs in the baseline, keyed by the run ids.
+pub(crate) struct Baseline(BTreeMap<String, Status>);
+
+impl Baseline {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .with_context(|| format!("failed to load baseline `{}`", path.display()))
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let summary: Value = serde_json::from_str(s)?;
+        match summary["version"].as_u64() {
+            Some(summary::JSON_VERSION) => {}
+            Some(version) => bail!(
+                "unsupported version {version} of --summary-json (cargo-hack supports version {})",
+                summary::JSON_VERSION
+            ),
+            None => bail!("not a file written by --summary-json"),
+        }
+        let runs =
+            summary["runs"].as_array().ok_or_else(|| format_err!("`runs` is not an array"))?;
+        let mut results = BTreeMap::new();
+        for run in runs {
+            let (Some(id), Some(status)) = (run["id"].as_str(), run["status"].as_str()) else {
+                bail!("a run without `id` or `status`: {run}");
+            };
+            results.insert(id.to_owned(), Status::from_str(status)?);
+        }
+        Ok(Self(results))
+    }
+
+    /// Returns `true` if the run with the given id failed in the baseline.
+    pub(crate) fn failed(&self, id: &str) -> bool {
+        self.0.get(id) == Some(&Status::Failed)
+    }
+
+    /// Compares the runs with the baseline.
+    pub(crate) fn compare<'a>(&self, runs: &'a [summary::Run]) -> Comparison<'a> {
+        let mut comparison = Comparison::default();
+        for run in runs {
+            let before = self.0.get(&run.id);
+            match (run.status, before) {
+                (Status::Failed, Some(Status::Failed)) => comparison.still_failing.push(run),
+                // Also includes the runs that are not in the baseline, or
+                // were not run in the baseline.
+                (Status::Failed, _) => comparison.newly_failing.push(run),
+                (Status::Passed, Some(Status::Failed)) => comparison.newly_passing.push(run),
+                _ => {}
+            }
+        }
+        comparison
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Comparison<'a> {
+    pub(crate) newly_failing: Vec<&'a summary::Run>,
+    pub(crate) newly_passing: Vec<&'a summary::Run>,
+    pub(crate) still_failing: Vec<&'a summary::Run>,
+}
+
+impl Comparison<'_> {
+    pub(crate) fn print(&self, path: &Path) {
+        eprintln!();
+        info!(
+            "compared with the baseline {}: {} newly failing, {} newly passing, {} still failing",
+            path.display(),
+            self.newly_failing.len(),
+            self.newly_passing.len(),
+            self.still_failing.len()
+        );
+        for (title, runs) in [
+            ("newly failing", &self.newly_failing),
+            ("newly passing", &self.newly_passing),
+            ("still failing", &self.still_failing),
+        ] {
+            if runs.is_empty() {
+                continue;
+            }
+            eprintln!("    {title}:");
+            for run in runs {
+                let toolchain = run.toolchain.as_ref().map(|t| format!(" ({t})"));
+                eprintln!(
+                    "        {} on {}{} [{}]",
+                    run.command,
+                    run.package,
+                    toolchain.unwrap_or_default(),
+                    run.id
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, VecDeque},
+        time::Duration,
+    };
+
+    use super::Baseline;
+    use crate::summary::{Run, Status};
+
+    fn run(id: &str, status: Status) -> Run {
+        Run {
+            id: id.to_owned(),
+            package: "a".to_owned(),
+            toolchain: None,
+            command: "`cargo check`".to_owned(),
+            features: String::new(),
+            requested_features: BTreeSet::new(),
+            enabled_features: None,
+            doc_items: None,
+            status,
+            duration: Duration::ZERO,
+            cold_start: false,
+            error: None,
+            stderr: VecDeque::new(),
+            cfgs: vec![],
+            sccache: None,
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let baseline = Baseline::parse(
+            r#"{"version":1,"runs":[
+                {"id":"00000001","status":"passed"},
+                {"id":"00000002","status":"failed"},
+                {"id":"00000003","status":"failed"},
+                {"id":"00000004","status":"skipped"},
+                {"id":"00000005","status":"passed"}
+            ],"skipped_packages":[]}"#,
+        )
+        .unwrap();
+        assert!(baseline.failed("00000002"));
+        assert!(!baseline.failed("00000001"));
+        assert!(!baseline.failed("00000009"));
+        let runs = [
+            run("00000001", Status::Failed),
+            run("00000002", Status::Passed),
+            run("00000003", Status::Failed),
+            run("00000004", Status::Failed),
+            run("00000005", Status::Passed),
+            run("00000006", Status::Failed),
+            run("00000007", Status::Passed),
+        ];
+        let comparison = baseline.compare(&runs);
+        let ids = |runs: &[&Run]| runs.iter().map(|run| run.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&comparison.newly_failing), ["00000001", "00000004", "00000006"]);
+        assert_eq!(ids(&comparison.newly_passing), ["00000002"]);
+        assert_eq!(ids(&comparison.still_failing), ["00000003"]);
+
+        assert!(Baseline::parse(r#"{"version":2,"runs":[]}"#).is_err());
+        assert!(Baseline::parse(r#"{"schemaVersion":1}"#).is_err());
+        assert!(
+            Baseline::parse(r#"{"version":1,"runs":[{"id":"00000001","status":"ok"}]}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --bisect-features.
+//
+// This uses delta debugging (ddmin) to find a 1-minimal failing set of
+// features: the command fails with the set, and passes (or has already been
+// tested) with the set without any one of its features. See "Simplifying and
+// Isolating Failure-Inducing Input" by Andreas Zeller and Ralf Hildebrandt.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+/// The result of [`ddmin`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Minimized {
+    /// The indices of the elements of the smallest failing set found.
+    pub(crate) set: Vec<usize>,
+    /// The number of times the command was run.
+    pub(crate) runs: usize,
+    /// Whether the search was stopped by the limit of runs, in which case
+    /// `set` may not be minimal.
+    pub(crate) limited: bool,
+}
+
+/// Returns a 1-minimal subset of `0..len` for which `fails` returns `true`.
+///
+/// `fails` is assumed to return `true` for `0..len` and `false` for the empty
+/// set, and is called at most `max_runs` times. The same set is never tested
+/// twice.
+pub(crate) fn ddmin(
+    len: usize,
+    max_runs: Option<usize>,
+    fails: impl FnMut(&[usize]) -> Result<bool>,
+) -> Result<Minimized> {
+    let mut tester = Tester { fails, cache: BTreeMap::new(), runs: 0, max_runs };
+    let mut current: Vec<usize> = (0..len).collect();
+    let mut n = 2;
+    'outer: while current.len() >= 2 {
+        let chunks = split(&current, n);
+        for chunk in &chunks {
+            match tester.test(chunk)? {
+                Some(true) => {
+                    current.clone_from(chunk);
+                    n = 2;
+                    continue 'outer;
+                }
+                Some(false) => {}
+                None => return Ok(tester.finish(current, true)),
+            }
+        }
+        // When split into two, the complements are the same as the chunks.
+        if n > 2 {
+            for i in 0..chunks.len() {
+                let complement: Vec<_> = chunks
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .flat_map(|(_, chunk)| chunk.iter().copied())
+                    .collect();
+                match tester.test(&complement)? {
+                    Some(true) => {
+                        current = complement;
+                        n = (n - 1).max(2);
+                        continue 'outer;
+                    }
+                    Some(false) => {}
+                    None => return Ok(tester.finish(current, true)),
+                }
+            }
+        }
+        if n >= current.len() {
+            break;
+        }
+        n = (n * 2).min(current.len());
+    }
+    Ok(tester.finish(current, false))
+}
+
+struct Tester<F> {
+    fails: F,
+    /// The results of the sets already tested.
+    cache: BTreeMap<Vec<usize>, bool>,
+    runs: usize,
+    max_runs: Option<usize>,
+}
+
+impl<F: FnMut(&[usize]) -> Result<bool>> Tester<F> {
+    /// Returns whether the command fails with `set`, or `None` if the limit
+    /// of runs has been reached.
+    fn test(&mut self, set: &[usize]) -> Result<Option<bool>> {
+        if let Some(&fails) = self.cache.get(set) {
+            return Ok(Some(fails));
+        }
+        if self.max_runs.is_some_and(|max| self.runs >= max) {
+            return Ok(None);
+        }
+        self.runs += 1;
+        let fails = (self.fails)(set)?;
+        self.cache.insert(set.to_vec(), fails);
+        Ok(Some(fails))
+    }
+
+    fn finish(self, set: Vec<usize>, limited: bool) -> Minimized {
+        Minimized { set, runs: self.runs, limited }
+    }
+}
+
+/// Splits `set` into `n` chunks whose sizes differ by at most one.
+fn split(set: &[usize], n: usize) -> Vec<Vec<usize>> {
+    (0..n).map(|i| set[i * set.len() / n..(i + 1) * set.len() / n].to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::{ddmin, split, Minimized};
+
+    #[test]
+    fn split_evenly() {
+        assert_eq!(split(&[0, 1, 2, 3], 2), [vec![0, 1], vec![2, 3]]);
+        assert_eq!(split(&[0, 1, 2, 3, 4], 2), [vec![0, 1], vec![2, 3, 4]]);
+        assert_eq!(split(&[0, 1, 2, 3, 4], 4), [vec![0], vec![1], vec![2], vec![3, 4]]);
+        assert_eq!(split(&[0, 1, 2], 3), [vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn minimize() {
+        // Fails if all of `culprits` are enabled.
+        let run = |len, culprits: &[usize], max_runs| {
+            let tested = RefCell::new(vec![]);
+            let res = ddmin(len, max_runs, |set| {
+                tested.borrow_mut().push(set.to_vec());
+                Ok(culprits.iter().all(|c| set.contains(c)))
+            })
+            .unwrap();
+            let tested = tested.into_inner();
+            assert_eq!(res.runs, tested.len());
+            // The same set is never tested twice.
+            for (i, set) in tested.iter().enumerate() {
+                assert!(!tested[..i].contains(set), "{set:?} is tested twice");
+            }
+            res
+        };
+
+        assert_eq!(run(4, &[0, 2], None), Minimized { set: vec![0, 2], runs: 10, limited: false });
+        assert_eq!(run(8, &[5], None).set, [5]);
+        assert_eq!(run(8, &[1, 6, 7], None).set, [1, 6, 7]);
+        assert_eq!(run(1, &[0], None), Minimized { set: vec![0], runs: 0, limited: false });
+        for len in 2..12 {
+            for a in 0..len {
+                for b in a + 1..len {
+                    assert_eq!(run(len, &[a, b], None).set, [a, b], "{len} {a} {b}");
+                }
+            }
+        }
+
+        // The search is stopped when the limit is reached.
+        let res = run(8, &[1, 6], Some(2));
+        assert_eq!(res.runs, 2);
+        assert!(res.limited);
+        assert!(res.set.contains(&1) && res.set.contains(&6));
+    }
+
+    #[test]
+    fn minimize_alternatives() {
+        // Fails if either `0,1` or `3` is enabled: the result is one of them.
+        let res =
+            ddmin(6, None, |set| Ok(set.contains(&3) || set.contains(&0) && set.contains(&1)))
+                .unwrap();
+        assert!(res.set == [3] || res.set == [0, 1], "{:?}", res.set);
+    }
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --budget.
+//
+// The wall time of the executed cargo commands is accumulated, and once it
+// reaches the budget, the remaining runs are not started (commands in flight
+// are never killed). The remaining runs are reported as "not run", and the
+// process exits with EXIT_CODE unless other errors were reported.
+
+use std::{
+    fmt::{self, Write as _},
+    time::Duration,
+};
+
+use anyhow::{bail, Context as _, Result};
+
+/// The exit code used when the budget was exceeded and no other errors
+/// occurred.
+pub(crate) const EXIT_CODE: u8 = 3;
+
+/// Parses a duration such as `45m`, `1h15m`, `90s`, or `90` (seconds).
+pub(crate) fn parse(s: &str) -> Result<Duration> {
+    if s.is_empty() {
+        bail!("duration must not be empty");
+    }
+    let mut secs: u64 = 0;
+    let mut rest = s;
+    // The units must appear in this order, each at most once.
+    let mut units = [('h', 3600), ('m', 60), ('s', 1)].iter();
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if end == 0 {
+            bail!("invalid duration `{s}`: expected a number before `{rest}`");
+        }
+        let n: u64 = rest[..end].parse().with_context(|| format!("invalid duration `{s}`"))?;
+        rest = &rest[end..];
+        let unit = match rest.chars().next() {
+            // Plain seconds.
+            None if secs == 0 && end == s.len() => 1,
+            None => bail!("invalid duration `{s}`: missing unit after `{n}`"),
+            Some(c) => match units.find(|&&(u, _)| u == c) {
+                Some(&(_, unit)) => {
+                    rest = &rest[1..];
+                    unit
+                }
+                None => bail!(
+                    "invalid duration `{s}`: unit must be `h`, `m`, or `s` in this order, but \
+                     found `{c}`"
+                ),
+            },
+        };
+        secs = n
+            .checked_mul(unit)
+            .and_then(|n| secs.checked_add(n))
+            .with_context(|| format!("duration `{s}` is too long"))?;
+    }
+    if secs == 0 {
+        bail!("duration must be greater than zero");
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Displays a duration in the format accepted by [`parse`], rounded down to
+/// seconds (e.g., `1h15m`, `44m59s`, `0s`).
+pub(crate) struct Display(pub(crate) Duration);
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+        if h != 0 {
+            write!(f, "{h}h")?;
+        }
+        if m != 0 {
+            write!(f, "{m}m")?;
+        }
+        if s != 0 || secs == 0 {
+            write!(f, "{s}s")?;
+        }
+        Ok(())
+    }
+}
+
+/// The first run that was not run because the budget was exceeded.
+pub(crate) struct Cutoff {
+    /// The position of the run in the plan (1-based).
+    pub(crate) index: usize,
+    /// The number of runs in the plan.
+    pub(crate) total: usize,
+    pub(crate) package: String,
+    /// The command, as displayed in the log (with backticks).
+    pub(crate) command: String,
+    /// The toolchain of the run. `None` if --version-range is not used.
+    pub(crate) toolchain: Option<String>,
+}
+
+/// The error returned when the budget was exceeded.
+#[derive(Debug)]
+pub(crate) struct Exceeded(pub(crate) String);
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Exceeded {}
+
+/// Returns the report of the exceeded budget.
+pub(crate) fn report(budget: Duration, elapsed: Duration, cutoff: &Cutoff) -> String {
+    let not_run = cutoff.total + 1 - cutoff.index;
+    let mut msg = format!(
+        "--budget ({}) exceeded after running commands for {}; {not_run} of {} commands {} not \
+         run, starting at {} on {}",
+        Display(budget),
+        Display(elapsed),
+        cutoff.total,
+        if not_run == 1 { "was" } else { "were" },
+        cutoff.command,
+        cutoff.package
+    );
+    if let Some(toolchain) = &cutoff.toolchain {
+        write!(msg, " with toolchain {toolchain}").unwrap();
+    }
+    write!(msg, " ({}/{})", cutoff.index, cutoff.total).unwrap();
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse, report, Cutoff, Display};
+
+    #[test]
+    fn parse_duration() {
+        let secs = |s| parse(s).unwrap().as_secs();
+        assert_eq!(secs("45m"), 45 * 60);
+        assert_eq!(secs("1h15m"), 75 * 60);
+        assert_eq!(secs("1h15m30s"), 75 * 60 + 30);
+        assert_eq!(secs("2h"), 2 * 3600);
+        assert_eq!(secs("90s"), 90);
+        assert_eq!(secs("90"), 90);
+        assert_eq!(secs("1h0m"), 3600);
+
+        for s in ["", "m", "1x", "1m1h", "1m1m", "1h30", "-1s", "1.5h", "0", "0m", "1 m"] {
+            assert!(parse(s).is_err(), "{s}");
+        }
+        assert!(parse("99999999999999999999h").is_err());
+        assert!(parse(&format!("{}h", u64::MAX / 60)).is_err());
+    }
+
+    #[test]
+    fn display_duration() {
+        let display = |secs| Display(Duration::from_secs(secs)).to_string();
+        assert_eq!(display(0), "0s");
+        assert_eq!(display(59), "59s");
+        assert_eq!(display(45 * 60), "45m");
+        assert_eq!(display(75 * 60), "1h15m");
+        assert_eq!(display(3601), "1h1s");
+        assert_eq!(Display(Duration::from_millis(1999)).to_string(), "1s");
+        for s in ["45m", "1h15m", "1h1s", "59s"] {
+            assert_eq!(Display(parse(s).unwrap()).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn cutoff_report() {
+        let cutoff = Cutoff {
+            index: 13,
+            total: 40,
+            package: "a".to_owned(),
+            command: "`cargo check --features x`".to_owned(),
+            toolchain: None,
+        };
+        assert_eq!(
+            report(Duration::from_secs(45 * 60), Duration::from_secs(46 * 60 + 12), &cutoff),
+            "--budget (45m) exceeded after running commands for 46m12s; 28 of 40 commands were \
+             not run, starting at `cargo check --features x` on a (13/40)"
+        );
+        let cutoff = Cutoff { index: 40, toolchain: Some("1.70".to_owned()), ..cutoff };
+        assert_eq!(
+            report(Duration::from_secs(1), Duration::from_secs(2), &cutoff),
+            "--budget (1s) exceeded after running commands for 2s; 1 of 40 commands was not \
+             run, starting at `cargo check --features x` on a with toolchain 1.70 (40/40)"
+        );
+    }
+}
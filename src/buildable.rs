@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --ensure-targets-buildable.
+//
+// Cargo silently skips targets whose `required-features` are not enabled
+// (unless the target is selected explicitly, e.g., by --bins), so excluding
+// the feature a target needs means the target is never built by the feature
+// combinations. This checks the planned runs of each package before running
+// them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{context::Context, features::Feature, skips_all_features, term, Kind, PackageRuns};
+
+/// A planned run of a package.
+struct Run {
+    /// The feature flags of the run, e.g., `--no-default-features --features a,b`.
+    flags: String,
+    /// The features enabled by the run, or `None` if all features are enabled.
+    features: Option<BTreeSet<String>>,
+}
+
+/// Checks that every target with `required-features` is built by at least one
+/// of the planned runs.
+pub(crate) fn check(cx: &Context, packages: &[PackageRuns<'_>]) -> Result<()> {
+    let mut unbuildable = String::new();
+    for pkg in packages {
+        let package = cx.packages(pkg.id);
+        let targets: Vec<_> =
+            package.targets.iter().filter(|t| !t.required_features.is_empty()).collect();
+        if targets.is_empty() {
+            continue;
+        }
+        let runs = runs(cx, pkg);
+        for target in targets {
+            let kind = target.kind.first().map_or("target", String::as_str);
+            let required = target.required_features.join(",");
+            let covered: Vec<_> = runs
+                .iter()
+                .filter(|run| match &run.features {
+                    None => true,
+                    Some(enabled) => {
+                        let enabled = enable(&package.features, enabled);
+                        target.required_features.iter().all(|f| enabled.contains(f))
+                    }
+                })
+                .collect();
+            if covered.is_empty() {
+                let _ = writeln!(
+                    unbuildable,
+                    "    {kind} `{}` of package `{}` (required-features: {required})",
+                    target.name, package.name
+                );
+            } else if term::verbose() {
+                info!(
+                    "{kind} `{}` of package `{}` (required-features: {required}) is built by: {}",
+                    target.name,
+                    package.name,
+                    covered.iter().map(|run| run.flags.as_str()).collect::<Vec<_>>().join("; ")
+                );
+            }
+        }
+    }
+    if !unbuildable.is_empty() {
+        bail!(
+            "the following targets are not built by any of the feature combinations because \
+             their required-features are never enabled:\n{unbuildable}"
+        );
+    }
+    Ok(())
+}
+
+/// Returns the runs planned for the package.
+///
+/// This follows the order of runs in `exec_on_package`.
+fn runs(cx: &Context, pkg: &PackageRuns<'_>) -> Vec<Run> {
+    let combination = |features: &[&Feature]| {
+        let enabled: BTreeSet<_> = cx
+            .features
+            .iter()
+            .cloned()
+            .chain(features.iter().flat_map(|f| f.as_group()).cloned())
+            .collect();
+        let mut flags = "--no-default-features".to_owned();
+        if !enabled.is_empty() {
+            let list: Vec<_> = cx
+                .features
+                .iter()
+                .chain(features.iter().flat_map(|f| f.as_group()))
+                .map(String::as_str)
+                .collect();
+            let _ = write!(flags, " --features {}", list.join(","));
+        }
+        Run { flags, features: Some(enabled) }
+    };
+
+    let mut runs = vec![];
+    let combinations = match &pkg.kind {
+        Kind::Normal => {
+            // Only run with the default features, because --no-default-features
+            // conflicts with --each-feature and --feature-powerset.
+            let mut enabled: BTreeSet<_> = cx.features.iter().cloned().collect();
+            enabled.insert("default".to_owned());
+            let flags = if cx.features.is_empty() {
+                "(default features)".to_owned()
+            } else {
+                format!("--features {}", cx.features.join(","))
+            };
+            runs.push(Run { flags, features: Some(enabled) });
+            return runs;
+        }
+        Kind::Each { features } => features.iter().map(|&f| vec![f]).collect(),
+        Kind::Powerset { features } => features.clone(),
+    };
+    if !pkg.skip_all_features && !skips_all_features(cx, pkg.id, &pkg.kind) {
+        runs.push(Run { flags: "--all-features".to_owned(), features: None });
+    }
+    if !cx.exclude_no_default_features && !pkg.skip_no_default_features {
+        runs.push(combination(&[]));
+    }
+    runs.extend(combinations.iter().map(|fs| combination(fs)));
+    runs
+}
+
+/// Returns the features enabled by enabling `features`, including the features
+/// they enable transitively.
+pub(crate) fn enable(
+    map: &BTreeMap<String, Vec<String>>,
+    features: &BTreeSet<String>,
+) -> BTreeSet<String> {
+    let mut enabled = BTreeSet::new();
+    let mut weak = vec![];
+    let mut stack: Vec<_> = features.iter().map(String::as_str).collect();
+    while let Some(f) = stack.pop() {
+        if !enabled.insert(f.to_owned()) {
+            continue;
+        }
+        for next in map.get(f).into_iter().flatten() {
+            if next.starts_with("dep:") {
+                // `dep:` enables the optional dependency, not a feature.
+                enabled.insert(next.clone());
+            } else if let Some((dep, dep_feature)) = next.split_once('/') {
+                match dep.strip_suffix('?') {
+                    // `dep?/feature` does not enable the optional dependency `dep`.
+                    Some(dep) => weak.push((dep, dep_feature)),
+                    // `dep/feature` also enables the optional dependency `dep`.
+                    None => {
+                        enabled.insert(next.clone());
+                        stack.push(dep);
+                    }
+                }
+            } else {
+                stack.push(next);
+            }
+        }
+    }
+    for (dep, dep_feature) in weak {
+        if enabled.contains(dep) || enabled.contains(&format!("dep:{dep}")) {
+            enabled.insert(format!("{dep}/{dep_feature}"));
+        }
+    }
+    enabled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::enable;
+
+    #[test]
+    fn enabled_features() {
+        let map: BTreeMap<String, Vec<String>> = [
+            ("default", vec!["std"]),
+            ("std", vec!["alloc", "serde?/std"]),
+            ("serde", vec!["dep:serde"]),
+            ("alloc", vec![]),
+            ("cli", vec!["dep:clap", "std"]),
+            ("json", vec!["serde_json/std"]),
+            ("serde_json", vec!["dep:serde_json"]),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.into_iter().map(str::to_owned).collect()))
+        .collect();
+        let enabled = |features: &[&str]| {
+            let features: BTreeSet<_> = features.iter().map(|&f| f.to_owned()).collect();
+            enable(&map, &features).into_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(enabled(&["default"]), ["alloc", "default", "std"]);
+        assert_eq!(enabled(&["cli"]), ["alloc", "cli", "dep:clap", "std"]);
+        // `serde?/std` is enabled only if `serde` is enabled.
+        assert_eq!(enabled(&["std", "serde"]), ["alloc", "dep:serde", "serde", "serde/std", "std"]);
+        assert_eq!(enabled(&["json"]), ["dep:serde_json", "json", "serde_json", "serde_json/std"]);
+        assert_eq!(enabled(&["alloc"]), ["alloc"]);
+        assert_eq!(enabled(&["unknown"]), ["unknown"]);
+    }
+}
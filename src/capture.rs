@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --capture-stdout-dir.
+//
+// The stdout of each run is written to `<DIR>/<package>/<feature set>.txt`
+// (or `<DIR>/<package>/<toolchain>/<feature set>.txt` with --version-range),
+// so that the output of commands like `cargo rustc -- --print cfg` can be
+// collected per feature combination without the progress messages.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{fs, ProcessBuilder};
+
+/// File names longer than this are truncated and suffixed with a hash, to stay
+/// within the limits of common file systems (255 bytes).
+const MAX_NAME_LEN: usize = 200;
+
+/// Writes the stdout of the run to the file for its feature set.
+pub(crate) fn write(
+    dir: &Path,
+    package: &str,
+    toolchain: Option<&str>,
+    line: &ProcessBuilder<'_>,
+    stdout: &[u8],
+) -> Result<()> {
+    let path = path(dir, package, toolchain, line);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, stdout)
+}
+
+fn path(dir: &Path, package: &str, toolchain: Option<&str>, line: &ProcessBuilder<'_>) -> PathBuf {
+    let mut path = dir.join(sanitize(package));
+    if let Some(toolchain) = toolchain {
+        path.push(sanitize(toolchain));
+    }
+    let all_features = line.has_arg("--all-features");
+    let default_features = !line.has_arg("--no-default-features");
+    let features: Vec<_> = line.features().collect();
+    path.push(format!("{}.txt", file_stem(all_features, default_features, &features, line.cfgs())));
+    path
+}
+
+/// Returns the canonical name of the feature set.
+///
+/// Combinations that enable the same set of features get the same name
+/// regardless of the order of features (`--features b,a` and `--features a,b`)
+/// or how the default features are enabled (`--features default` and no
+/// flags). Different sets never get the same name, because the characters
+/// used as separators and markers are always escaped in feature names.
+fn file_stem(
+    all_features: bool,
+    default_features: bool,
+    features: &[&str],
+    cfgs: &[String],
+) -> String {
+    let mut name = if all_features {
+        "(all-features)".to_owned()
+    } else {
+        let features: BTreeSet<_> = features
+            .iter()
+            .copied()
+            .chain(default_features.then_some("default"))
+            .map(sanitize)
+            .collect();
+        if features.is_empty() {
+            "(no-features)".to_owned()
+        } else {
+            features.into_iter().collect::<Vec<_>>().join(",")
+        }
+    };
+    if !cfgs.is_empty() {
+        let cfgs: BTreeSet<_> = cfgs.iter().map(|cfg| sanitize(cfg)).collect();
+        write!(name, "@cfg={}", cfgs.into_iter().collect::<Vec<_>>().join(",")).unwrap();
+    }
+    if name.len() > MAX_NAME_LEN {
+        // The name only contains ASCII characters after sanitization.
+        let hash = hash(name.as_bytes());
+        name.truncate(MAX_NAME_LEN - 17);
+        write!(name, "-{hash:016x}").unwrap();
+    }
+    name
+}
+
+/// Percent-encodes the characters other than ASCII alphanumerics, `-`, `_`,
+/// `.`, and `+`, so that the result can be used as a file name and does not
+/// contain the separators used in [`file_stem`].
+fn sanitize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'+') {
+            out.push(b as char);
+        } else {
+            write!(out, "%{b:02X}").unwrap();
+        }
+    }
+    // `.` and `..` are not valid file names.
+    if out.bytes().all(|b| b == b'.') {
+        out = out.replace('.', "%2E");
+    }
+    out
+}
+
+/// Returns the FNV-1a hash of the bytes.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_stem, sanitize, MAX_NAME_LEN};
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize("a-b_c.d+e"), "a-b_c.d+e");
+        assert_eq!(sanitize("serde/std"), "serde%2Fstd");
+        assert_eq!(sanitize("a,b"), "a%2Cb");
+        assert_eq!(sanitize("(no-features)"), "%28no-features%29");
+        assert_eq!(sanitize("é"), "%C3%A9");
+        assert_eq!(sanitize(".."), "%2E%2E");
+    }
+
+    #[test]
+    fn canonical_file_stem() {
+        let stem = |all, default, features: &[&str], cfgs: &[&str]| {
+            let cfgs: Vec<_> = cfgs.iter().map(|&c| c.to_owned()).collect();
+            file_stem(all, default, features, &cfgs)
+        };
+        assert_eq!(stem(false, true, &[], &[]), "default");
+        assert_eq!(stem(false, false, &["default"], &[]), "default");
+        assert_eq!(stem(false, false, &[], &[]), "(no-features)");
+        assert_eq!(stem(true, true, &["a"], &[]), "(all-features)");
+        assert_eq!(stem(false, false, &["b", "a"], &[]), "a,b");
+        assert_eq!(stem(false, false, &["a", "b"], &[]), "a,b");
+        assert_eq!(stem(false, true, &["b", "a", "b"], &[]), "a,b,default");
+        assert_eq!(stem(false, false, &["dep/f"], &[]), "dep%2Ff");
+        assert_eq!(stem(false, false, &["a"], &["y", "x=\"1\""]), "a@cfg=x%3D%221%22,y");
+
+        let features: Vec<_> = (0..100).map(|i| format!("feature{i}")).collect();
+        let features: Vec<_> = features.iter().map(String::as_str).collect();
+        let long = stem(false, false, &features, &[]);
+        assert_eq!(long.len(), MAX_NAME_LEN);
+        // Names that differ only after the truncation point are still different.
+        let mut other = features.clone();
+        other[99] = "other";
+        assert_ne!(long, stem(false, false, &other, &[]));
+    }
+}
@@ -21,3 +21,14 @@ pub(crate) fn version(mut cmd: ProcessBuilder<'_>) -> Result<Version> {
 
     Ok(version)
 }
+
+/// Returns `true` if `cmd` is a nightly (or locally built) cargo.
+pub(crate) fn is_nightly(mut cmd: ProcessBuilder<'_>) -> Result<bool> {
+    cmd.arg("-vV");
+    let verbose_version = cmd.read()?;
+    let release = verbose_version
+        .lines()
+        .find_map(|line| line.strip_prefix("release: "))
+        .ok_or_else(|| format_err!("unexpected output from {cmd}: {verbose_version}"))?;
+    Ok(release.ends_with("-nightly") || release.ends_with("-dev"))
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --and-then.
+//
+// Each feature combination is often checked by several subcommands (e.g.,
+// clippy, check, and doc). The commands chained by --and-then are run after
+// the subcommand with the same feature flags, so the feature combinations are
+// only determined (and the manifests and toolchains only prepared) once. The
+// chained commands of a combination are not run after a command of the
+// combination failed.
+
+use anyhow::{bail, Result};
+
+use crate::feature_args;
+
+/// A command chained by --and-then.
+#[derive(Debug)]
+pub(crate) struct Command {
+    /// The subcommand and its arguments, e.g., `clippy --no-deps`.
+    pub(crate) leading_args: Vec<String>,
+    /// The arguments after `--`, e.g., `-D warnings`.
+    pub(crate) trailing_args: Vec<String>,
+}
+
+impl Command {
+    pub(crate) fn subcommand(&self) -> &str {
+        &self.leading_args[0]
+    }
+}
+
+/// Parses the value of --and-then (`<SUBCOMMAND> [ARGS]...`).
+pub(crate) fn parse(val: &str) -> Result<Command> {
+    let mut args: Vec<_> = val.split_whitespace().map(str::to_owned).collect();
+    if args.first().map_or(true, |subcommand| subcommand.starts_with('-')) {
+        bail!("argument for --and-then must start with a subcommand, but found `{val}`");
+    }
+    let trailing_args = match args.iter().position(|arg| arg == "--") {
+        Some(pos) => args.split_off(pos).split_off(1),
+        None => vec![],
+    };
+    for arg in &args {
+        if let Some(flag) = feature_args::controlled_flag(arg) {
+            bail!(
+                "--and-then cannot pass `{flag}`, which is controlled by cargo-hack (found in \
+                 `{val}`)"
+            );
+        }
+    }
+    Ok(Command { leading_args: args, trailing_args })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parse_value() {
+        let command = parse("clippy --no-deps -- -D warnings").unwrap();
+        assert_eq!(command.subcommand(), "clippy");
+        assert_eq!(command.leading_args, ["clippy", "--no-deps"]);
+        assert_eq!(command.trailing_args, ["-D", "warnings"]);
+        let command = parse(" doc ").unwrap();
+        assert_eq!(command.leading_args, ["doc"]);
+        assert!(command.trailing_args.is_empty());
+
+        for val in ["", " ", "--no-deps", "-- doc"] {
+            assert!(parse(val).unwrap_err().to_string().contains("must start with a subcommand"));
+        }
+        for val in ["check --features a", "check --all-features", "check --manifest-path x"] {
+            assert!(parse(val).unwrap_err().to_string().contains("controlled by cargo-hack"));
+        }
+    }
+}
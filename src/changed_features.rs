@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --changed-features-only.
+//
+// The features of each selected package (the `[features]` table of its
+// manifest, and the implicit features of optional dependencies) are compared
+// with those in its manifest at the given git revision. A feature is changed
+// if it was added, or if the features it enables changed. The feature
+// combinations are then limited to those that contain at least one changed
+// feature, in addition to the run with --all-features of --each-feature. The
+// run with just --no-default-features contains no features, so it is skipped,
+// and packages whose features did not change are skipped entirely.
+//
+// Unlike --skip-unless-manifest-changed, failing to compare the manifests
+// (e.g., an unknown revision) is an error, because the runs would otherwise
+// be silently limited to nothing or everything.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    context::Context, fs, manifest, manifest_changes, skip::SkipKind, summary, Kind, PackageRuns,
+};
+
+/// Removes the runs that contain no features changed since `rev`, and the
+/// packages whose features did not change.
+pub(crate) fn filter<'a>(
+    cx: &Context,
+    packages: Vec<PackageRuns<'a>>,
+    rev: &str,
+    skipped: &mut Vec<summary::SkippedPackage>,
+) -> Result<Vec<PackageRuns<'a>>> {
+    manifest_changes::verify_rev(cx.workspace_root(), rev).with_context(|| {
+        format!(
+            "--changed-features-only: `{rev}` is not a valid git revision, or `{}` is not in a \
+             git repository",
+            cx.workspace_root().display()
+        )
+    })?;
+
+    let mut filtered = vec![];
+    for mut pkg in packages {
+        let package = cx.packages(pkg.id);
+        let path = &package.manifest_path;
+        let current = manifest::declared_features(&fs::read_to_string(path)?)
+            .with_context(|| format!("failed to parse manifest `{}`", path.display()))?;
+        let old = match manifest_changes::show(rev, path) {
+            Some(old) => {
+                manifest::declared_features(&String::from_utf8_lossy(&old)).with_context(|| {
+                    format!("failed to parse manifest `{}` at {rev}", path.display())
+                })?
+            }
+            // The package was added after `rev`.
+            None => BTreeMap::new(),
+        };
+        let changed = changed(&old, &current);
+        if changed.is_empty() {
+            let reason = format!("no features changed since {rev} (--changed-features-only)");
+            info!("skipped running on package `{}`: {reason}", cx.name_verbose(pkg.id));
+            cx.skips.record(SkipKind::ExcludedPackage, &package.name, &reason);
+            skipped.push(summary::SkippedPackage { package: package.name.clone(), reason });
+            continue;
+        }
+
+        let is_changed = |f: &String| changed.contains(f.as_str());
+        let removed = match &mut pkg.kind {
+            // Runs only once, with the default features.
+            Kind::Normal => {
+                filtered.push(pkg);
+                continue;
+            }
+            Kind::Each { features } => {
+                let len = features.len();
+                features.retain(|f| f.as_group().iter().any(is_changed));
+                len - features.len()
+            }
+            Kind::Powerset { features } => {
+                let len = features.len();
+                features.retain(|fs| fs.iter().flat_map(|f| f.as_group()).any(is_changed));
+                len - features.len()
+            }
+        };
+        pkg.feature_count -= removed + usize::from(!cx.exclude_no_default_features);
+        pkg.skip_no_default_features = true;
+        let list: Vec<_> = changed.iter().map(|f| format!("`{f}`")).collect();
+        info!(
+            "running {} of the feature combinations of package `{}`, which contain features \
+             changed since {rev}: {}",
+            pkg.feature_count,
+            cx.name_verbose(pkg.id),
+            list.join(", ")
+        );
+        if pkg.feature_count != 0 {
+            filtered.push(pkg);
+        }
+    }
+    Ok(filtered)
+}
+
+/// Returns the features that were added or modified.
+fn changed<'a>(
+    old: &BTreeMap<String, Vec<String>>,
+    current: &'a BTreeMap<String, Vec<String>>,
+) -> BTreeSet<&'a str> {
+    current
+        .iter()
+        .filter(|&(name, enables)| {
+            old.get(name).map_or(true, |old| {
+                // The order of the features a feature enables does not matter.
+                old.iter().collect::<BTreeSet<_>>() != enables.iter().collect::<BTreeSet<_>>()
+            })
+        })
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::changed;
+    use crate::manifest::declared_features;
+
+    #[test]
+    fn changed_features() {
+        let features = |s: &str| declared_features(s).unwrap();
+        let old = features(
+            r#"
+            [features]
+            a = []
+            b = ["a"]
+            c = ["a", "b"]
+            d = ["dep:x"]
+            [dependencies]
+            x = { version = "1", optional = true }
+            y = { version = "1", optional = true }
+            z = "1"
+            "#,
+        );
+        assert_eq!(
+            old,
+            [
+                ("a", vec![]),
+                ("b", vec!["a"]),
+                ("c", vec!["a", "b"]),
+                ("d", vec!["dep:x"]),
+                ("y", vec!["dep:y"]),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.into_iter().map(str::to_owned).collect()))
+            .collect::<BTreeMap<_, Vec<_>>>()
+        );
+        let current = features(
+            r#"
+            [features]
+            a = []
+            b = []
+            c = ["b", "a"]
+            d = ["dep:x"]
+            zstd = ["dep:zstd"]
+            [dependencies]
+            x = { version = "1", optional = true }
+            y = { version = "1", optional = true }
+            [target.'cfg(unix)'.dependencies]
+            w = { version = "1", optional = true }
+            zstd = { version = "1", optional = true }
+            "#,
+        );
+        assert_eq!(changed(&old, &current).into_iter().collect::<Vec<_>>(), ["b", "w", "zstd"]);
+        assert!(changed(&current, &current).is_empty());
+        assert_eq!(changed(&BTreeMap::new(), &current).len(), current.len());
+    }
+}
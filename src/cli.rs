@@ -1,34 +1,70 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     env,
     ffi::{OsStr, OsString},
     fmt, mem,
+    path::PathBuf,
+    time::Duration,
 };
 
-use anyhow::{bail, format_err, Result};
+use anyhow::{bail, format_err, Context as _, Result};
 use lexopt::{
     Arg::{Long, Short, Value},
     ValueExt as _,
 };
 
-use crate::{term, version::VersionRange, Feature, LogGroup, Partition, Rustup};
+use crate::{
+    budget, chain, completion, env_config, examples_once, feature_args, features, fs,
+    lockfile_strategy::LockfileStrategy,
+    manifest::KeepLockfileChanges,
+    run_id, shuffle,
+    skip::{self, SkipKind},
+    term,
+    version::{MaybeVersion, VersionOrder, VersionRange},
+    Feature, LogGroup, Partition, Rustup,
+};
 
 pub(crate) struct Args {
     pub(crate) leading_args: Vec<String>,
     pub(crate) trailing_args: Vec<String>,
 
     pub(crate) subcommand: Option<String>,
+    /// --and-then <SUBCOMMAND> [ARGS]...
+    pub(crate) and_then: Vec<chain::Command>,
+    /// Arguments of `cargo llvm-cov report`, which are withheld from each run.
+    ///
+    /// This is `None` unless the subcommand is llvm-cov.
+    pub(crate) llvm_cov_report_args: Option<Vec<String>>,
+    /// Whether the subcommand does not depend on features (e.g., fmt).
+    ///
+    /// --each-feature and --feature-powerset run such a subcommand only once
+    /// per package.
+    pub(crate) ignores_features: bool,
 
     /// --manifest-path <PATH>
     pub(crate) manifest_path: Option<String>,
     /// --no-manifest-path
     pub(crate) no_manifest_path: bool,
+    /// --chdir-instead-of-manifest-path
+    pub(crate) chdir_instead_of_manifest_path: bool,
+    /// --skip-flag-validation
+    pub(crate) skip_flag_validation: bool,
     /// --locked
     pub(crate) locked: bool,
+    /// --locked=auto
+    pub(crate) auto_locked: bool,
+    /// --no-locked-hint
+    pub(crate) no_locked_hint: bool,
     /// -p, --package <SPEC>...
     pub(crate) package: Vec<String>,
+    /// --package-with-deps <SPEC>...
+    pub(crate) package_with_deps: Vec<String>,
+    /// --package-with-dependents <SPEC>...
+    pub(crate) package_with_dependents: Vec<String>,
+    /// --with-dev-deps-closure
+    pub(crate) with_dev_deps_closure: bool,
     /// --exclude <SPEC>...
     pub(crate) exclude: Vec<String>,
     /// --workspace, (--all)
@@ -41,28 +77,136 @@ pub(crate) struct Args {
     pub(crate) no_dev_deps: bool,
     /// --remove-dev-deps
     pub(crate) remove_dev_deps: bool,
+    /// --check
+    pub(crate) check: bool,
+    /// --no-wait
+    pub(crate) no_wait: bool,
+    /// --manifest-backup-dir <DIR>
+    pub(crate) manifest_backup_dir: Option<PathBuf>,
+    /// --keep-lockfile-changes[=<WHEN>]
+    pub(crate) keep_lockfile_changes: Option<KeepLockfileChanges>,
     /// --no-private
     pub(crate) no_private: bool,
     /// --ignore-private
     pub(crate) ignore_private: bool,
+    /// --only-packages-with-feature <FEATURE>...
+    pub(crate) only_packages_with_feature: Vec<String>,
+    /// --report-redundant-packages
+    ///
+    /// This is also set by --skip-redundant-packages.
+    pub(crate) report_redundant_packages: bool,
+    /// --skip-redundant-packages
+    pub(crate) skip_redundant_packages: bool,
     /// --ignore-unknown-features
     pub(crate) ignore_unknown_features: bool,
     /// --clean-per-run
     pub(crate) clean_per_run: bool,
     /// --clean-per-version
     pub(crate) clean_per_version: bool,
+    /// --skip-unavailable-targets
+    pub(crate) skip_unavailable_targets: bool,
+    /// --no-rustup-lock
+    pub(crate) no_rustup_lock: bool,
+    /// --no-install
+    pub(crate) no_install: bool,
+    /// --verify-lockfile-per-version
+    pub(crate) verify_lockfile_per_version: bool,
+    /// --strip-unsupported-profile-keys
+    pub(crate) strip_unsupported_profile_keys: bool,
+    /// --compat-strip-lints
+    pub(crate) compat_strip_lints: bool,
+    /// --lockfile-strategy <STRATEGY>
+    pub(crate) lockfile_strategy: LockfileStrategy,
+    /// --check-msrv-consistency
+    pub(crate) check_msrv_consistency: bool,
     /// --keep-going
     pub(crate) keep_going: bool,
+    /// --quiet-success
+    pub(crate) quiet_success: bool,
+    /// --compact-output
+    pub(crate) compact_output: bool,
+    /// --tag-output
+    pub(crate) tag_output: bool,
+    /// --show-output-lines <N>
+    pub(crate) show_output_lines: Option<usize>,
+    /// --porcelain
+    pub(crate) porcelain: bool,
     /// --partition
     pub(crate) partition: Option<Partition>,
+    /// --only-run <ID>...
+    pub(crate) only_run: BTreeSet<String>,
+    /// --shuffle [SEED]
+    ///
+    /// The seed is chosen randomly if it is not specified.
+    pub(crate) shuffle: Option<u64>,
+    /// --shuffle-keep-warmup
+    pub(crate) shuffle_keep_warmup: bool,
     /// --print-command-list
+    ///
+    /// This is also set by --emit-execution-plan and
+    /// --print-toolchain-requirements, which plan the runs in the same way,
+    /// without side effects.
     pub(crate) print_command_list: bool,
+    /// --emit-execution-plan <PATH>
+    pub(crate) emit_execution_plan: Option<PathBuf>,
+    /// --print-toolchain-requirements
+    pub(crate) print_toolchain_requirements: bool,
+    /// --generate-feature-docs <PATH>
+    pub(crate) generate_feature_docs: Option<PathBuf>,
     /// --version-range/--rust-version
-    pub(crate) version_range: Option<VersionRange>,
+    ///
+    /// This is empty if neither --version-range nor --rust-version is used.
+    pub(crate) version_range: Vec<VersionRange>,
     /// --version-step
     pub(crate) version_step: u16,
+    /// --version-order <ORDER>
+    pub(crate) version_order: VersionOrder,
     /// --log-group
     pub(crate) log_group: LogGroup,
+    /// --summary-markdown <PATH>
+    pub(crate) summary_markdown: Option<PathBuf>,
+    /// --trace-file <PATH>
+    pub(crate) trace_file: Option<PathBuf>,
+    /// --summary-json <PATH>
+    pub(crate) summary_json: Option<PathBuf>,
+    /// --baseline <PATH>
+    pub(crate) baseline: Option<PathBuf>,
+    /// --fail-on-new-failures-only
+    pub(crate) fail_on_new_failures_only: bool,
+    /// --junit <PATH>
+    pub(crate) junit: Option<PathBuf>,
+    /// --badge-json <PATH>
+    pub(crate) badge_json: Option<PathBuf>,
+    /// --fail-on-skipped[=KINDS]
+    pub(crate) fail_on_skipped: Option<Vec<SkipKind>>,
+    /// --allow-empty-plan
+    pub(crate) allow_empty_plan: bool,
+    /// --budget <DURATION>
+    pub(crate) budget: Option<Duration>,
+    /// --capture-stdout-dir <DIR>
+    pub(crate) capture_stdout_dir: Option<PathBuf>,
+    /// --skip-unless-manifest-changed <GIT_REF>
+    pub(crate) skip_unless_manifest_changed: Option<String>,
+    /// --changed-features-only <GIT_REF>
+    pub(crate) changed_features_only: Option<String>,
+    /// --check-additivity
+    pub(crate) check_additivity: bool,
+    /// --ensure-targets-buildable
+    pub(crate) ensure_targets_buildable: bool,
+    /// --rustdoc-all-features-check
+    pub(crate) rustdoc_all_features_check: bool,
+    /// --doc-json
+    pub(crate) doc_json: bool,
+    /// --slow-threshold-factor <FACTOR>
+    pub(crate) slow_threshold_factor: Option<f64>,
+    /// --discard-warmup-timing
+    pub(crate) discard_warmup_timing: bool,
+    /// --sccache-stats
+    pub(crate) sccache_stats: bool,
+    /// --strict-metadata
+    pub(crate) strict_metadata: bool,
+    /// --ignore-minimum-version
+    pub(crate) ignore_minimum_version: bool,
 
     // options for --each-feature and --feature-powerset
     /// --optional-deps [DEPS]...
@@ -78,12 +222,26 @@ pub(crate) struct Args {
     pub(crate) exclude_features: Vec<String>,
     /// --exclude-no-default-features
     pub(crate) exclude_no_default_features: bool,
+    /// --strict-coverage
+    pub(crate) strict_coverage: bool,
+    /// --only-code-gated-features
+    pub(crate) only_code_gated_features: bool,
     /// --exclude-all-features
     pub(crate) exclude_all_features: bool,
+    /// --examples-once
+    pub(crate) examples_once: bool,
+    /// --tests-once
+    pub(crate) tests_once: bool,
+    /// --exclude-all-features=<PKG>...
+    pub(crate) exclude_all_features_packages: Vec<String>,
 
     // options for --feature-powerset
     /// --depth <NUM>
     pub(crate) depth: Option<usize>,
+    /// --depth-counts-group-members
+    pub(crate) depth_counts_group_members: bool,
+    /// --max-group-combo <NUM>
+    pub(crate) max_group_combo: Option<usize>,
     /// --group-features <FEATURES>...
     pub(crate) group_features: Vec<Feature>,
     /// `--mutually-exclusive-features <FEATURES>`
@@ -91,6 +249,23 @@ pub(crate) struct Args {
     /// --at-least-one-of <FEATURES>...
     /// Implies --exclude-no-default-features. Can be specified multiple times.
     pub(crate) at_least_one_of: Vec<Feature>,
+    /// --exactly-one-of <FEATURES>...
+    /// Implies --exclude-no-default-features and --exclude-all-features. Can be specified multiple times.
+    pub(crate) exactly_one_of: Vec<Feature>,
+    /// --why <FEATURES>
+    pub(crate) why: Option<Vec<String>>,
+    /// --bisect-features
+    pub(crate) bisect_features: bool,
+    /// --bisect-start <FEATURES>...
+    pub(crate) bisect_start: Vec<String>,
+    /// --bisect-max-runs <NUM>
+    pub(crate) bisect_max_runs: Option<usize>,
+    /// --cfg-matrix <CFGS>...
+    pub(crate) cfg_matrix: Vec<String>,
+    /// --deny-warnings-for <SPEC>...
+    pub(crate) deny_warnings_for: Vec<String>,
+    /// --args-if-feature <FEATURE>:<ARGS>
+    pub(crate) args_if_feature: Vec<(String, Vec<String>)>,
 
     // options that will be propagated to cargo
     /// --features <FEATURES>...
@@ -105,6 +280,34 @@ pub(crate) struct Args {
 
 impl Args {
     pub(crate) fn parse(cargo: &OsStr) -> Result<Option<Self>> {
+        Self::parse_from(cargo, env::args_os(), &env_config::vars())
+    }
+
+    /// Parses the arguments.
+    ///
+    /// Value-taking options accept both `--flag value` and `--flag=value`
+    /// forms. Values starting with `-` are only accepted in the latter form,
+    /// because in the former form they are likely to be a flag with a missing
+    /// value.
+    ///
+    /// Regarding repeated occurrences:
+    ///
+    /// - `-v`, `--verbose` are counted.
+    /// - Options taking a list or a package accumulate: `-p`, `--package`,
+    ///   `--exclude`, `-F`, `--features`, `--exclude-features`, `--include-features`,
+    ///   `--group-features`, `--mutually-exclusive-features`, `--at-least-one-of`, `--exactly-one-of`,
+    ///   `--only-packages-with-feature`, `--exclude-all-features=<PKG>`,
+    ///   `--version-range`, and `--target`. `--target` is also deduplicated, like
+    ///   cargo does.
+    /// - All other options and flags reject repeats.
+    ///
+    /// Options not in `args` are read from the CARGO_HACK_* variables in `env`,
+    /// unless --no-env-config is used.
+    fn parse_from(
+        cargo: &OsStr,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+        env: &BTreeMap<String, OsString>,
+    ) -> Result<Option<Self>> {
         const SUBCMD: &str = "hack";
 
         // rustc/cargo args must be valid Unicode
@@ -120,7 +323,7 @@ impl Args {
             })
         }
 
-        let mut raw_args = handle_args(env::args_os());
+        let mut raw_args = handle_args(args);
         raw_args.next(); // cargo
         match raw_args.next().transpose()? {
             Some(a) if a == SUBCMD => {}
@@ -137,6 +340,16 @@ impl Args {
         }
         let rest = raw_args.collect::<Result<Vec<_>>>()?;
 
+        let env_args = if args.iter().any(|arg| arg == "--no-env-config") {
+            vec![]
+        } else {
+            env_config::args(&args, env)?
+        };
+        args.extend(env_args.iter().map(|(_, arg)| arg.clone()));
+        Self::parse_args(cargo, args, rest).map_err(|e| env_config::attribute(e, &env_args))
+    }
+
+    fn parse_args(cargo: &OsStr, args: Vec<String>, rest: Vec<String>) -> Result<Option<Self>> {
         let mut cargo_args = vec![];
         let mut subcommand: Option<String> = None;
 
@@ -144,12 +357,22 @@ impl Args {
         let mut color = None;
 
         let mut package = vec![];
+        let mut package_with_deps = vec![];
+        let mut package_with_dependents = vec![];
+        let mut with_dev_deps_closure = false;
         let mut exclude = vec![];
+        let mut only_packages_with_feature = vec![];
+        let mut report_redundant_packages = false;
+        let mut skip_redundant_packages = false;
         let mut features = vec![];
 
         let mut workspace = false;
         let mut no_dev_deps = false;
         let mut remove_dev_deps = false;
+        let mut check = false;
+        let mut no_wait = false;
+        let mut manifest_backup_dir: Option<String> = None;
+        let mut keep_lockfile_changes: Option<Option<String>> = None;
         let mut each_feature = false;
         let mut feature_powerset = false;
         let mut no_private = false;
@@ -157,29 +380,96 @@ impl Args {
         let mut ignore_unknown_features = false;
         let mut clean_per_run = false;
         let mut clean_per_version = false;
+        let mut skip_unavailable_targets = false;
+        let mut no_rustup_lock = false;
+        let mut no_install = false;
+        let mut verify_lockfile_per_version = false;
+        let mut strip_unsupported_profile_keys = false;
+        let mut compat_strip_lints = false;
+        let mut lockfile_strategy: Option<String> = None;
+        let mut check_msrv_consistency = false;
         let mut keep_going = false;
+        let mut quiet_success = false;
+        let mut compact_output = false;
+        let mut tag_output = false;
+        let mut show_output_lines: Option<String> = None;
+        let mut porcelain = false;
         let mut partition = None;
+        let mut only_run = vec![];
+        let mut shuffle: Option<Option<String>> = None;
+        let mut shuffle_keep_warmup = false;
         let mut print_command_list = false;
         let mut no_manifest_path = false;
+        let mut chdir_instead_of_manifest_path = false;
+        let mut skip_flag_validation = false;
+        let mut no_env_config = false;
         let mut locked = false;
+        let mut auto_locked = false;
+        let mut no_locked_hint = false;
         let mut rust_version = false;
-        let mut version_range = None;
+        let mut version_range: Vec<String> = vec![];
+        let mut version_range_file: Option<String> = None;
         let mut version_step = None;
+        let mut version_order: Option<String> = None;
         let mut log_group: Option<String> = None;
         let mut disable_log_grouping = false;
+        let mut summary_markdown: Option<String> = None;
+        let mut trace_file: Option<String> = None;
+        let mut summary_json: Option<String> = None;
+        let mut baseline: Option<String> = None;
+        let mut fail_on_new_failures_only = false;
+        let mut junit: Option<String> = None;
+        let mut badge_json: Option<String> = None;
+        let mut emit_execution_plan: Option<String> = None;
+        let mut print_toolchain_requirements = false;
+        let mut generate_feature_docs: Option<String> = None;
+        let mut capture_stdout_dir: Option<String> = None;
+        let mut skip_unless_manifest_changed: Option<String> = None;
+        let mut fail_on_skipped: Option<Option<String>> = None;
+        let mut allow_empty_plan = false;
+        let mut budget: Option<String> = None;
+        let mut check_additivity = false;
+        let mut ensure_targets_buildable = false;
+        let mut rustdoc_all_features_check = false;
+        let mut doc_json = false;
+        let mut slow_threshold_factor: Option<String> = None;
+        let mut discard_warmup_timing = false;
+        let mut sccache_stats = false;
+        let mut strict_metadata = false;
+        let mut ignore_minimum_version = false;
 
         let mut optional_deps = None;
+        // The value of --optional-deps given as the next argument instead of
+        // `--optional-deps=<DEPS>` form.
+        let mut optional_deps_separate_value: Option<String> = None;
         let mut include_features = vec![];
         let mut at_least_one_of = vec![];
+        let mut exactly_one_of = vec![];
         let mut include_deps_features = false;
 
         let mut exclude_features = vec![];
         let mut exclude_no_default_features = false;
+        let mut strict_coverage = false;
+        let mut only_code_gated_features = false;
+        let mut changed_features_only: Option<String> = None;
         let mut exclude_all_features = false;
+        let mut exclude_all_features_packages = vec![];
+        let mut examples_once = false;
+        let mut tests_once = false;
 
         let mut group_features: Vec<String> = vec![];
         let mut mutually_exclusive_features: Vec<String> = vec![];
         let mut depth = None;
+        let mut depth_counts_group_members = false;
+        let mut max_group_combo = None;
+        let mut cfg_matrix = vec![];
+        let mut deny_warnings_for = vec![];
+        let mut args_if_feature = vec![];
+        let mut and_then = vec![];
+        let mut why: Option<String> = None;
+        let mut bisect_features = false;
+        let mut bisect_start = vec![];
+        let mut bisect_max_runs: Option<String> = None;
 
         let mut verbose = 0;
         let mut no_default_features = false;
@@ -207,6 +497,22 @@ impl Args {
                 },
             };
 
+            macro_rules! value {
+                () => {{
+                    let flag = format_flag(&arg);
+                    match parser.optional_value() {
+                        Some(val) => val.string()?,
+                        None => {
+                            let val = parser.value()?.string()?;
+                            if val.starts_with('-') {
+                                dash_value(&flag, &val)?;
+                            }
+                            val
+                        }
+                    }
+                }};
+            }
+
             macro_rules! parse_opt {
                 ($opt:ident, $propagate:expr $(,)?) => {{
                     if $opt.is_some() {
@@ -215,7 +521,7 @@ impl Args {
                     if $propagate {
                         cargo_args.push(format_flag(&arg));
                     }
-                    let val = parser.value()?.string()?;
+                    let val = value!();
                     if $propagate {
                         cargo_args.push(val.clone());
                     }
@@ -225,8 +531,8 @@ impl Args {
 
             macro_rules! parse_multi_opt {
                 ($v:ident $(,)?) => {{
-                    let val = parser.value()?;
-                    let mut val = val.to_str().unwrap();
+                    let val = value!();
+                    let mut val = val.as_str();
                     if val.starts_with('\'') && val.ends_with('\'')
                         || val.starts_with('"') && val.ends_with('"')
                     {
@@ -248,26 +554,73 @@ impl Args {
             match arg {
                 Long("color") => parse_opt!(color, true),
                 Long("target") => {
-                    target.insert(parser.value()?.parse()?);
+                    target.insert(value!().trim().to_owned());
                 }
 
                 Long("manifest-path") => parse_opt!(manifest_path, false),
                 Long("depth") => parse_opt!(depth, false),
+                Long("why") => parse_opt!(why, false),
+                Long("bisect-features") => parse_flag!(bisect_features),
+                Long("bisect-start") => parse_multi_opt!(bisect_start),
+                Long("bisect-max-runs") => parse_opt!(bisect_max_runs, false),
+                Long("depth-counts-group-members") => parse_flag!(depth_counts_group_members),
+                Long("max-group-combo") => parse_opt!(max_group_combo, false),
                 Long("rust-version") => parse_flag!(rust_version),
-                Long("version-range") => parse_opt!(version_range, false),
+                Long("version-range") => version_range.push(value!()),
+                Long("version-range-file") => parse_opt!(version_range_file, false),
                 Long("version-step") => parse_opt!(version_step, false),
+                Long("version-order") => parse_opt!(version_order, false),
                 Long("log-group") => parse_opt!(log_group, false),
-
-                Short('p') | Long("package") => package.push(parser.value()?.parse()?),
-                Long("exclude") => exclude.push(parser.value()?.parse()?),
-                Long("group-features") => group_features.push(parser.value()?.parse()?),
+                Long("summary-markdown") => parse_opt!(summary_markdown, false),
+                Long("trace-file") => parse_opt!(trace_file, false),
+                Long("summary-json") => parse_opt!(summary_json, false),
+                Long("baseline") => parse_opt!(baseline, false),
+                Long("fail-on-new-failures-only") => parse_flag!(fail_on_new_failures_only),
+                Long("junit") => parse_opt!(junit, false),
+                Long("badge-json") => parse_opt!(badge_json, false),
+                Long("emit-execution-plan") => parse_opt!(emit_execution_plan, false),
+                Long("generate-feature-docs") => parse_opt!(generate_feature_docs, false),
+                Long("print-toolchain-requirements") => parse_flag!(print_toolchain_requirements),
+                Long("capture-stdout-dir") => parse_opt!(capture_stdout_dir, false),
+                Long("skip-unless-manifest-changed") => {
+                    parse_opt!(skip_unless_manifest_changed, false);
+                }
+                Long("fail-on-skipped") => {
+                    if fail_on_skipped.is_some() {
+                        multi_arg(&arg, subcommand.as_deref())?;
+                    }
+                    let val = parser.optional_value().map(lexopt::ValueExt::string).transpose()?;
+                    fail_on_skipped = Some(val);
+                }
+                Long("budget") => parse_opt!(budget, false),
+                Long("slow-threshold-factor") => parse_opt!(slow_threshold_factor, false),
+                Long("discard-warmup-timing") => parse_flag!(discard_warmup_timing),
+                Long("sccache-stats") => parse_flag!(sccache_stats),
+                Long("strict-metadata") => parse_flag!(strict_metadata),
+                Long("ignore-minimum-version") => parse_flag!(ignore_minimum_version),
+
+                Short('p') | Long("package") => package.push(value!()),
+                Long("package-with-deps") => package_with_deps.push(value!()),
+                Long("package-with-dependents") => package_with_dependents.push(value!()),
+                Long("with-dev-deps-closure") => parse_flag!(with_dev_deps_closure),
+                Long("exclude") => exclude.push(value!()),
+                Long("only-packages-with-feature") => {
+                    only_packages_with_feature.push(value!());
+                }
+                Long("report-redundant-packages") => parse_flag!(report_redundant_packages),
+                Long("skip-redundant-packages") => parse_flag!(skip_redundant_packages),
+                Long("group-features") => group_features.push(value!()),
                 Long("mutually-exclusive-features") => {
-                    mutually_exclusive_features.push(parser.value()?.parse()?);
+                    mutually_exclusive_features.push(value!());
                 }
 
                 Short('F') | Long("features") => parse_multi_opt!(features),
                 Long("skip" | "exclude-features") => parse_multi_opt!(exclude_features),
                 Long("include-features") => parse_multi_opt!(include_features),
+                Long("cfg-matrix") => parse_multi_opt!(cfg_matrix),
+                Long("deny-warnings-for") => parse_multi_opt!(deny_warnings_for),
+                Long("args-if-feature") => args_if_feature.push(feature_args::parse(&value!())?),
+                Long("and-then") => and_then.push(chain::parse(&value!())?),
 
                 Long("optional-deps") => {
                     if optional_deps.is_some() {
@@ -275,9 +628,17 @@ impl Args {
                     }
                     let optional_deps = optional_deps.get_or_insert_with(Vec::new);
                     let val = match parser.optional_value() {
-                        Some(val) => val,
+                        Some(val) => val.string()?,
                         None => match parser.next()? {
-                            Some(Value(val)) => val,
+                            Some(Value(val)) => {
+                                let val = val.string()?;
+                                // `-` is parsed as a value by lexopt.
+                                if val.starts_with('-') {
+                                    dash_value("--optional-deps", &val)?;
+                                }
+                                optional_deps_separate_value = Some(val.clone());
+                                val
+                            }
                             Some(arg) => {
                                 next_flag = Some(arg.into());
                                 continue;
@@ -285,7 +646,7 @@ impl Args {
                             None => break,
                         },
                     };
-                    let mut val = val.to_str().unwrap();
+                    let mut val = val.as_str();
                     if val.starts_with('\'') && val.ends_with('\'')
                         || val.starts_with('"') && val.ends_with('"')
                     {
@@ -301,31 +662,126 @@ impl Args {
                 Long("workspace" | "all") => parse_flag!(workspace),
                 Long("no-dev-deps") => parse_flag!(no_dev_deps),
                 Long("remove-dev-deps") => parse_flag!(remove_dev_deps),
+                Long("no-wait") => parse_flag!(no_wait),
+                Long("manifest-backup-dir") => parse_opt!(manifest_backup_dir, false),
+                Long("keep-lockfile-changes") => {
+                    if keep_lockfile_changes.is_some() {
+                        multi_arg(&arg, subcommand.as_deref())?;
+                    }
+                    // Only `--keep-lockfile-changes=<WHEN>` form is accepted for the value,
+                    // because the value is optional.
+                    let val = parser.optional_value().map(lexopt::ValueExt::string).transpose()?;
+                    keep_lockfile_changes = Some(val);
+                }
                 Long("each-feature") => parse_flag!(each_feature),
                 Long("feature-powerset") => parse_flag!(feature_powerset),
-                Long("at-least-one-of") => at_least_one_of.push(parser.value()?.parse()?),
+                Long("at-least-one-of") => at_least_one_of.push(value!()),
+                Long("exactly-one-of") => exactly_one_of.push(value!()),
                 Long("no-private") => parse_flag!(no_private),
                 Long("ignore-private") => parse_flag!(ignore_private),
                 Long("exclude-no-default-features") => parse_flag!(exclude_no_default_features),
-                Long("exclude-all-features") => parse_flag!(exclude_all_features),
+                Long("strict-coverage") => parse_flag!(strict_coverage),
+                Long("only-code-gated-features") => parse_flag!(only_code_gated_features),
+                Long("changed-features-only") => parse_opt!(changed_features_only, false),
+                Long("examples-once") => parse_flag!(examples_once),
+                Long("tests-once") => parse_flag!(tests_once),
+                Long("exclude-all-features") => {
+                    // Only `--exclude-all-features=<PKG>` form is accepted for the
+                    // package, because `--exclude-all-features <PKG>` is ambiguous
+                    // with the subcommand and its arguments.
+                    if let Some(val) = parser.optional_value() {
+                        exclude_all_features_packages.push(val.string()?);
+                    } else if mem::replace(&mut exclude_all_features, true) {
+                        // `arg` cannot be used here as it borrows `parser`.
+                        multi_arg(&Long("exclude-all-features"), subcommand.as_deref())?;
+                    }
+                }
                 Long("include-deps-features") => parse_flag!(include_deps_features),
                 Long("clean-per-run") => parse_flag!(clean_per_run),
                 Long("clean-per-version") => parse_flag!(clean_per_version),
+                Long("skip-unavailable-targets") => parse_flag!(skip_unavailable_targets),
+                Long("no-rustup-lock") => parse_flag!(no_rustup_lock),
+                Long("no-install") => parse_flag!(no_install),
+                Long("verify-lockfile-per-version") => parse_flag!(verify_lockfile_per_version),
+                Long("strip-unsupported-profile-keys") => {
+                    parse_flag!(strip_unsupported_profile_keys);
+                }
+                Long("compat-strip-lints") => parse_flag!(compat_strip_lints),
+                Long("lockfile-strategy") => parse_opt!(lockfile_strategy, false),
+                Long("check-msrv-consistency") => parse_flag!(check_msrv_consistency),
                 Long("keep-going") => parse_flag!(keep_going),
+                Long("allow-empty-plan") => parse_flag!(allow_empty_plan),
+                Long("quiet-success") => parse_flag!(quiet_success),
+                Long("compact-output") => parse_flag!(compact_output),
+                Long("tag-output") => parse_flag!(tag_output),
+                Long("porcelain") => parse_flag!(porcelain),
+                Long("show-output-lines") => parse_opt!(show_output_lines, false),
+                Long("check-additivity") => parse_flag!(check_additivity),
+                Long("ensure-targets-buildable") => parse_flag!(ensure_targets_buildable),
+                Long("rustdoc-all-features-check") => parse_flag!(rustdoc_all_features_check),
+                Long("doc-json") => parse_flag!(doc_json),
                 Long("partition") => parse_opt!(partition, false),
+                Long("only-run") => parse_multi_opt!(only_run),
+                Long("shuffle") => {
+                    if shuffle.is_some() {
+                        multi_arg(&arg, subcommand.as_deref())?;
+                    }
+                    // The seed is optional, so only a number that follows is taken as it.
+                    let val = match parser.optional_value() {
+                        Some(val) => Some(val.string()?),
+                        None => parser
+                            .try_raw_args()
+                            .and_then(|mut raw| {
+                                raw.next_if(|val| {
+                                    val.to_str().is_some_and(|val| {
+                                        !val.is_empty() && val.bytes().all(|b| b.is_ascii_digit())
+                                    })
+                                })
+                            })
+                            .map(lexopt::ValueExt::string)
+                            .transpose()?,
+                    };
+                    shuffle = Some(val);
+                }
+                Long("shuffle-keep-warmup") => parse_flag!(shuffle_keep_warmup),
                 Long("print-command-list") => parse_flag!(print_command_list),
                 Long("no-manifest-path") => parse_flag!(no_manifest_path),
-                Long("locked") => parse_flag!(locked),
+                Long("chdir-instead-of-manifest-path") => {
+                    parse_flag!(chdir_instead_of_manifest_path);
+                }
+                Long("skip-flag-validation") => parse_flag!(skip_flag_validation),
+                // Handled before parsing the arguments.
+                Long("no-env-config") => parse_flag!(no_env_config),
+                Long("locked") => {
+                    parse_flag!(locked);
+                    // `auto` is optional, so only `auto` that follows is taken as the value.
+                    let val = match parser.optional_value() {
+                        Some(val) => Some(val.string()?),
+                        None => parser
+                            .try_raw_args()
+                            .and_then(|mut raw| raw.next_if(|val| val == "auto"))
+                            .map(lexopt::ValueExt::string)
+                            .transpose()?,
+                    };
+                    match val.as_deref() {
+                        Some("auto") => auto_locked = true,
+                        Some(val) => {
+                            bail!("invalid value `{val}` for --locked; the only value is `auto`")
+                        }
+                        None => {}
+                    }
+                }
+                Long("no-locked-hint") => parse_flag!(no_locked_hint),
                 Long("ignore-unknown-features") => parse_flag!(ignore_unknown_features),
                 Short('v') | Long("verbose") => verbose += 1,
 
                 // propagated
                 Long("no-default-features") => {
-                    no_default_features = true;
+                    parse_flag!(no_default_features);
                     cargo_args.push("--no-default-features".to_owned());
                 }
                 Long("all-features") => {
-                    all_features = true;
+                    parse_flag!(all_features);
                     cargo_args.push("--all-features".to_owned());
                 }
 
@@ -337,6 +793,18 @@ impl Args {
                     print!("{}", Help::long());
                     return Ok(None);
                 }
+                Long("help-hack") => {
+                    print!("{}", Help::long());
+                    return Ok(None);
+                }
+                // `cargo fmt --check` is a common usage, so this is only
+                // handled before the subcommand.
+                Long("check") if subcommand.is_none() => parse_flag!(check),
+                Long("generate-completion") if subcommand.is_none() => {
+                    let shell: completion::Shell = value!().parse()?;
+                    print!("{}", completion::generate(shell));
+                    return Ok(None);
+                }
                 Short('V') | Long("version") if subcommand.is_none() => {
                     println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
                     return Ok(None);
@@ -380,6 +848,56 @@ impl Args {
 
         term::set_coloring(color.as_deref())?;
 
+        // These are forwarded to cargo without running on packages, so the other
+        // flags are not validated.
+        match subcommand.as_deref() {
+            None if cargo_args.iter().any(|a| a == "--list") => {
+                cmd!(cargo, "--list").run()?;
+                return Ok(None);
+            }
+            // `cargo hack help <COMMAND>` is forwarded to `cargo help <COMMAND>`.
+            Some("help") => {
+                if cargo_args.iter().any(|a| a != "help" && !a.starts_with('-')) {
+                    cmd!(cargo).args(&cargo_args).run()?;
+                } else {
+                    print!("{}", Help::long());
+                }
+                return Ok(None);
+            }
+            Some(subcommand) if cargo_args.iter().any(|a| a == "-h" || a == "--help") => {
+                info!(
+                    "showing the help of `cargo {subcommand}`; run `cargo hack {subcommand} \
+                     --help-hack` or `cargo hack help` to show the help of cargo-hack"
+                );
+                cmd!(cargo).args(&cargo_args).run()?;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        if target.contains("") {
+            bail!("--target requires a non-empty value");
+        }
+        // Target triples are case-sensitive, so targets that differ only in case
+        // are likely a typo of one of them.
+        let mut lowercase = BTreeMap::new();
+        for t in &target {
+            if let Some(other) = lowercase.insert(t.to_ascii_lowercase(), t) {
+                bail!(
+                    "--target `{other}` and `{t}` differ only in case; target triples are \
+                     case-sensitive"
+                );
+            }
+        }
+
+        if let Some(val) = &optional_deps_separate_value {
+            if let Some(hint) =
+                optional_deps_hint(val, subcommand.as_deref(), &features, &exclude_features)
+            {
+                warn!("{hint}");
+            }
+        }
+
         if ignore_unknown_features {
             if features.is_empty() && include_features.is_empty() && group_features.is_empty() {
                 requires("--ignore-unknown-features", &[
@@ -396,7 +914,7 @@ impl Args {
                 );
             }
         }
-        if !each_feature && !feature_powerset {
+        if !each_feature && !feature_powerset && !bisect_features {
             if optional_deps.is_some() {
                 requires("--optional-deps", &["--each-feature", "--feature-powerset"])?;
             } else if !exclude_features.is_empty() {
@@ -406,48 +924,209 @@ impl Args {
                     "--each-feature",
                     "--feature-powerset",
                 ])?;
-            } else if exclude_all_features {
+            } else if strict_coverage {
+                requires("--strict-coverage", &["--each-feature", "--feature-powerset"])?;
+            } else if only_code_gated_features {
+                requires("--only-code-gated-features", &["--each-feature", "--feature-powerset"])?;
+            } else if changed_features_only.is_some() {
+                requires("--changed-features-only", &["--each-feature", "--feature-powerset"])?;
+            } else if exclude_all_features || !exclude_all_features_packages.is_empty() {
                 requires("--exclude-all-features", &["--each-feature", "--feature-powerset"])?;
             } else if !include_features.is_empty() {
                 requires("--include-features", &["--each-feature", "--feature-powerset"])?;
             } else if include_deps_features {
                 requires("--include-deps-features", &["--each-feature", "--feature-powerset"])?;
+            } else if check_additivity {
+                requires("--check-additivity", &["--each-feature", "--feature-powerset"])?;
+            } else if ensure_targets_buildable {
+                requires("--ensure-targets-buildable", &["--each-feature", "--feature-powerset"])?;
+            } else if examples_once {
+                requires("--examples-once", &["--each-feature", "--feature-powerset"])?;
+            } else if tests_once {
+                requires("--tests-once", &["--each-feature", "--feature-powerset"])?;
             }
         }
 
-        if !at_least_one_of.is_empty() {
+        if !at_least_one_of.is_empty() || !exactly_one_of.is_empty() {
             // there will always be a feature set
+            // (even if a member of --exactly-one-of is in the default features,
+            // --no-default-features disables it, so no member is enabled in that run)
             exclude_no_default_features = true;
         }
 
         if !feature_powerset {
             if depth.is_some() {
                 requires("--depth", &["--feature-powerset"])?;
-            } else if !group_features.is_empty() {
+            } else if max_group_combo.is_some() {
+                requires("--max-group-combo", &["--feature-powerset"])?;
+            } else if !group_features.is_empty() && !bisect_features {
                 requires("--group-features", &["--feature-powerset"])?;
             } else if !mutually_exclusive_features.is_empty() {
                 requires("--mutually-exclusive-features", &["--feature-powerset"])?;
             } else if !at_least_one_of.is_empty() {
                 requires("--at-least-one-of", &["--feature-powerset"])?;
+            } else if !exactly_one_of.is_empty() {
+                requires("--exactly-one-of", &["--feature-powerset"])?;
+            } else if why.is_some() {
+                requires("--why", &["--feature-powerset"])?;
             }
         }
+        if max_group_combo.is_some() && group_features.is_empty() {
+            requires("--max-group-combo", &["--group-features"])?;
+        }
 
-        let depth = depth.as_deref().map(str::parse::<usize>).transpose()?;
+        if depth_counts_group_members && depth.is_none() {
+            requires("--depth-counts-group-members", &["--depth"])?;
+        }
+        let depth = depth
+            .as_deref()
+            .map(str::parse::<usize>)
+            .transpose()
+            .context("failed to parse argument for --depth")?;
+        let max_group_combo = max_group_combo
+            .as_deref()
+            .map(str::parse::<usize>)
+            .transpose()
+            .context("failed to parse argument for --max-group-combo")?;
+        for cfg in &cfg_matrix {
+            // The cfgs are passed via RUSTFLAGS, which is split by whitespace.
+            if cfg.contains(char::is_whitespace) {
+                bail!("cfg `{cfg}` specified by --cfg-matrix may not contain whitespace");
+            }
+        }
+        let mut seen = BTreeSet::new();
+        cfg_matrix.retain(|cfg| seen.insert(cfg.clone()));
         let group_features = parse_grouped_features(&group_features, "group-features")?;
         let mutually_exclusive_features =
             parse_grouped_features(&mutually_exclusive_features, "mutually-exclusive-features")?;
         let at_least_one_of = parse_grouped_features(&at_least_one_of, "at-least-one-of")?;
+        let exactly_one_of = parse_grouped_features(&exactly_one_of, "exactly-one-of")?;
+        if emit_execution_plan.is_some() {
+            if subcommand.is_none() {
+                bail!("--emit-execution-plan requires a subcommand");
+            } else if print_command_list {
+                conflicts("--emit-execution-plan", "--print-command-list")?;
+            } else if bisect_features {
+                conflicts("--emit-execution-plan", "--bisect-features")?;
+            } else if porcelain {
+                conflicts("--emit-execution-plan", "--porcelain")?;
+            }
+        }
+        if print_toolchain_requirements {
+            if subcommand.is_none() {
+                bail!("--print-toolchain-requirements requires a subcommand");
+            } else if print_command_list {
+                conflicts("--print-toolchain-requirements", "--print-command-list")?;
+            } else if emit_execution_plan.is_some() {
+                conflicts("--print-toolchain-requirements", "--emit-execution-plan")?;
+            } else if bisect_features {
+                conflicts("--print-toolchain-requirements", "--bisect-features")?;
+            } else if no_install {
+                conflicts("--print-toolchain-requirements", "--no-install")?;
+            }
+        }
+        // The runs are planned without side effects as with --print-command-list,
+        // and recorded to the plan (or the toolchain requirements are printed)
+        // instead of being printed.
+        let print_command_list =
+            print_command_list || emit_execution_plan.is_some() || print_toolchain_requirements;
+        let why = why
+            .map(|why| {
+                let why: Vec<_> =
+                    why.split([',', ' ']).filter(|f| !f.is_empty()).map(str::to_owned).collect();
+                if why.is_empty() {
+                    bail!(
+                        "--why requires a list of one or more features separated by space or comma"
+                    );
+                }
+                Ok(why)
+            })
+            .transpose()?;
+
+        if bisect_features {
+            if each_feature {
+                conflicts("--bisect-features", "--each-feature")?;
+            } else if feature_powerset {
+                conflicts("--bisect-features", "--feature-powerset")?;
+            } else if print_command_list {
+                conflicts("--bisect-features", "--print-command-list")?;
+            } else if partition.is_some() {
+                conflicts("--bisect-features", "--partition")?;
+            } else if !only_run.is_empty() {
+                conflicts("--bisect-features", "--only-run")?;
+            } else if changed_features_only.is_some() {
+                conflicts("--bisect-features", "--changed-features-only")?;
+            } else if !version_range.is_empty() {
+                conflicts("--bisect-features", "--version-range")?;
+            } else if version_range_file.is_some() {
+                conflicts("--bisect-features", "--version-range-file")?;
+            } else if rust_version {
+                conflicts("--bisect-features", "--rust-version")?;
+            } else if !cfg_matrix.is_empty() {
+                conflicts("--bisect-features", "--cfg-matrix")?;
+            } else if check_additivity {
+                conflicts("--bisect-features", "--check-additivity")?;
+            } else if ensure_targets_buildable {
+                conflicts("--bisect-features", "--ensure-targets-buildable")?;
+            } else if budget.is_some() {
+                conflicts("--bisect-features", "--budget")?;
+            } else if porcelain {
+                conflicts("--bisect-features", "--porcelain")?;
+            } else if !and_then.is_empty() {
+                conflicts("--bisect-features", "--and-then")?;
+            } else if examples_once {
+                conflicts("--bisect-features", "--examples-once")?;
+            } else if tests_once {
+                conflicts("--bisect-features", "--tests-once")?;
+            }
+        } else if !bisect_start.is_empty() {
+            requires("--bisect-start", &["--bisect-features"])?;
+        } else if bisect_max_runs.is_some() {
+            requires("--bisect-max-runs", &["--bisect-features"])?;
+        }
+        let bisect_max_runs = bisect_max_runs
+            .as_deref()
+            .map(str::parse::<usize>)
+            .transpose()
+            .context("failed to parse argument for --bisect-max-runs")?;
+        if bisect_max_runs.is_some_and(|n| n < 2) {
+            // The first two runs check that the starting set fails and the empty set passes.
+            bail!("--bisect-max-runs must be at least 2");
+        }
 
-        if let Some(subcommand) = subcommand.as_deref() {
+        if !and_then.is_empty() {
+            if subcommand.is_none() {
+                bail!("--and-then requires a subcommand to chain commands after");
+            } else if subcommand.as_deref() == Some("llvm-cov") {
+                bail!("--and-then may not be used together with llvm-cov subcommand");
+            }
+        }
+        // The commands chained by --and-then are validated like the subcommand.
+        let commands = subcommand
+            .as_deref()
+            .map(|subcommand| (subcommand, &cargo_args[..]))
+            .into_iter()
+            .chain(and_then.iter().map(|c| (c.subcommand(), &c.leading_args[..])));
+        for (subcommand, cargo_args) in commands {
+            if check_additivity && !matches!(subcommand, "check" | "c" | "build" | "b") {
+                bail!(
+                    "--check-additivity may only be used together with check or build subcommand"
+                );
+            }
             match subcommand {
                 "test" | "bench" => {
                     if remove_dev_deps {
                         bail!(
                             "--remove-dev-deps may not be used together with {subcommand} subcommand",
                         );
-                    } else if no_dev_deps {
+                    } else if no_dev_deps
+                        && !(subcommand == "test" && cargo_args.iter().any(|a| a == "--doc"))
+                    {
+                        // `cargo test --doc` only runs doctests, which often do not
+                        // require dev-dependencies.
                         bail!(
-                            "--no-dev-deps may not be used together with {subcommand} subcommand",
+                            "--no-dev-deps may not be used together with {subcommand} subcommand \
+                             (except for `test --doc`)",
                         );
                     }
                 }
@@ -457,19 +1136,69 @@ impl Args {
                 }
                 _ => {}
             }
-        }
 
-        if let Some(pos) = cargo_args.iter().position(|a| match &**a {
-            "--example" | "--examples" | "--test" | "--tests" | "--bench" | "--benches"
-            | "--all-targets" => true,
-            _ => {
-                a.starts_with("--example=") || a.starts_with("--test=") || a.starts_with("--bench=")
+            if let Some(pos) = cargo_args.iter().position(|a| match &**a {
+                "--example" | "--examples" | "--test" | "--tests" | "--bench" | "--benches"
+                | "--all-targets" => true,
+                _ => {
+                    a.starts_with("--example=")
+                        || a.starts_with("--test=")
+                        || a.starts_with("--bench=")
+                }
+            }) {
+                if remove_dev_deps {
+                    conflicts("--remove-dev-deps", &cargo_args[pos])?;
+                } else if no_dev_deps {
+                    conflicts("--no-dev-deps", &cargo_args[pos])?;
+                }
             }
+        }
+
+        if let Some(f) = include_features.iter().find(|f| {
+            f.starts_with('@') && *f != features::INCLUDE_DEFAULTS && *f != features::INCLUDE_ALL
         }) {
-            if remove_dev_deps {
-                conflicts("--remove-dev-deps", &cargo_args[pos])?;
-            } else if no_dev_deps {
-                conflicts("--no-dev-deps", &cargo_args[pos])?;
+            bail!(
+                "unknown keyword `{f}` in --include-features; expected `{}` or `{}`",
+                features::INCLUDE_DEFAULTS,
+                features::INCLUDE_ALL
+            );
+        }
+        // Typos in feature names are reported by cargo (or as features that
+        // do not exist), but names that cargo never accepts are rejected here,
+        // because they would otherwise be split or matched unexpectedly.
+        let feature_lists = [
+            ("--features", &features),
+            ("--exclude-features", &exclude_features),
+            ("--only-packages-with-feature", &only_packages_with_feature),
+            ("--bisect-start", &bisect_start),
+        ];
+        let grouped = [
+            ("--group-features", &group_features),
+            ("--mutually-exclusive-features", &mutually_exclusive_features),
+            ("--at-least-one-of", &at_least_one_of),
+            ("--exactly-one-of", &exactly_one_of),
+        ];
+        let names = feature_lists
+            .into_iter()
+            .flat_map(|(flag, list)| list.iter().map(move |f| (flag, f.as_str())))
+            .chain(
+                include_features
+                    .iter()
+                    .filter(|f| !f.starts_with('@'))
+                    .map(|f| ("--include-features", f.as_str())),
+            )
+            .chain(grouped.into_iter().flat_map(|(flag, list)| {
+                list.iter().flat_map(Feature::as_group).map(move |f| (flag, f.as_str()))
+            }))
+            .chain(why.iter().flatten().map(|f| ("--why", f.as_str())))
+            .chain(args_if_feature.iter().map(|(f, _)| ("--args-if-feature", f.as_str())));
+        for (flag, name) in names {
+            // `<dep>/<feature>` and `<dep>?/<feature>` enable a feature of a dependency.
+            for part in name.split('/') {
+                let part = part.strip_suffix('?').unwrap_or(part);
+                if let Err(reason) = features::check_name(part) {
+                    bail!("invalid feature name `{name}` specified by {flag}: {reason}");
+                }
             }
         }
 
@@ -478,12 +1207,101 @@ impl Args {
                 conflicts("--include-features", "--optional-deps")?;
             } else if include_deps_features {
                 conflicts("--include-features", "--include-deps-features")?;
+            } else if only_code_gated_features {
+                conflicts("--include-features", "--only-code-gated-features")?;
+            }
+        }
+
+        if examples_once || tests_once {
+            let flag = if examples_once { "--examples-once" } else { "--tests-once" };
+            if let Some(arg) = cargo_args.iter().find_map(|a| examples_once::target_flag(a)) {
+                conflicts(flag, arg)?;
+            } else if !and_then.is_empty() {
+                conflicts(flag, "--and-then")?;
+            }
+            if !matches!(subcommand.as_deref(), Some("test" | "t"))
+                && !cargo_args.iter().any(|a| a == "--all-targets")
+            {
+                bail!(
+                    "{flag} requires `test` subcommand or --all-targets flag, because examples \
+                     and tests are not compiled otherwise"
+                );
             }
         }
 
+        if check_additivity {
+            if let Some(flag) = cargo_args.iter().find(|a| a.starts_with("--message-format")) {
+                conflicts("--check-additivity", flag)?;
+            }
+            if capture_stdout_dir.is_some() {
+                // The stdout is replaced by the JSON messages used by --check-additivity.
+                conflicts("--check-additivity", "--capture-stdout-dir")?;
+            }
+        }
+
+        if rustdoc_all_features_check {
+            if !each_feature {
+                requires("--rustdoc-all-features-check", &["--each-feature"])?;
+            } else if subcommand.as_deref() != Some("doc") {
+                bail!("--rustdoc-all-features-check may only be used together with doc subcommand");
+            } else if check_additivity {
+                conflicts("--rustdoc-all-features-check", "--check-additivity")?;
+            }
+        }
+        if doc_json {
+            if !rustdoc_all_features_check {
+                requires("--doc-json", &["--rustdoc-all-features-check"])?;
+            } else if !version_range.is_empty() {
+                conflicts("--doc-json", "--version-range")?;
+            } else if version_range_file.is_some() {
+                conflicts("--doc-json", "--version-range-file")?;
+            } else if rust_version {
+                conflicts("--doc-json", "--rust-version")?;
+            }
+        }
+
+        if workspace {
+            if !package_with_deps.is_empty() {
+                conflicts("--workspace", "--package-with-deps")?;
+            } else if !package_with_dependents.is_empty() {
+                conflicts("--workspace", "--package-with-dependents")?;
+            }
+        }
+        if with_dev_deps_closure
+            && package_with_deps.is_empty()
+            && package_with_dependents.is_empty()
+        {
+            requires("--with-dev-deps-closure", &[
+                "--package-with-deps",
+                "--package-with-dependents",
+            ])?;
+        }
         if no_dev_deps && remove_dev_deps {
             conflicts("--no-dev-deps", "--remove-dev-deps")?;
         }
+        if check {
+            if !remove_dev_deps {
+                requires("--check", &["--remove-dev-deps"])?;
+            } else if let Some(subcommand) = &subcommand {
+                bail!("--check may not be used together with subcommand (`{subcommand}`)");
+            } else if no_private {
+                conflicts("--check", "--no-private")?;
+            }
+        }
+        if no_wait && !no_dev_deps && !remove_dev_deps && !no_private {
+            requires("--no-wait", &["--no-dev-deps", "--remove-dev-deps", "--no-private"])?;
+        }
+        if manifest_backup_dir.is_some() && !no_dev_deps && !no_private {
+            // --remove-dev-deps does not restore the manifests.
+            requires("--manifest-backup-dir", &["--no-dev-deps", "--no-private"])?;
+        }
+        if keep_lockfile_changes.is_some() && !no_dev_deps && !no_private {
+            // --remove-dev-deps does not restore Cargo.lock.
+            requires("--keep-lockfile-changes", &["--no-dev-deps", "--no-private"])?;
+        }
+        if no_manifest_path && chdir_instead_of_manifest_path {
+            conflicts("--no-manifest-path", "--chdir-instead-of-manifest-path")?;
+        }
         if each_feature && feature_powerset {
             conflicts("--each-feature", "--feature-powerset")?;
         }
@@ -515,65 +1333,242 @@ impl Args {
             if mutually_exclusive_features.iter().any(|v| v.matches(f)) {
                 bail!("feature `{f}` specified by both --exclude-features and --mutually-exclusive-features");
             }
+            if exactly_one_of.iter().any(|v| v.matches(f)) {
+                bail!("feature `{f}` specified by both --exclude-features and --exactly-one-of");
+            }
             if include_features.contains(f) {
                 bail!("feature `{f}` specified by both --exclude-features and --include-features");
             }
         }
 
-        if subcommand.is_none() {
-            if cargo_args.iter().any(|a| a == "--list") {
-                cmd!(cargo, "--list").run()?;
-                return Ok(None);
-            } else if !remove_dev_deps {
-                // TODO: improve this
-                mini_usage("no subcommand or valid flag specified")?;
+        if generate_feature_docs.is_some() {
+            if subcommand.is_some() {
+                bail!("--generate-feature-docs may not be used together with a subcommand");
+            } else if remove_dev_deps {
+                conflicts("--generate-feature-docs", "--remove-dev-deps")?;
             }
+        } else if subcommand.is_none() && !remove_dev_deps {
+            // TODO: improve this
+            mini_usage("no subcommand or valid flag specified")?;
         }
 
-        let version_range = match (version_range, rust_version) {
-            (Some(_), true) => {
+        if version_range_file.is_some() {
+            if !version_range.is_empty() {
+                conflicts("--version-range-file", "--version-range")?;
+            }
+            if rust_version {
+                conflicts("--version-range-file", "--rust-version")?;
+            }
+        }
+        let version_range = match (version_range.is_empty(), rust_version) {
+            (false, true) => {
                 conflicts("--version-range", "--rust-version")?;
                 unreachable!()
             }
-            (Some(version_range), false) => {
+            (false, false) => {
                 let rustup = Rustup::new();
                 if rustup.version < 23 {
                     bail!("--version-range requires rustup 1.23 or later");
                 }
-                Some(version_range.parse()?)
+                version_range.iter().map(|s| s.parse()).collect::<Result<_>>()?
+            }
+            (true, false) if version_range_file.is_some() => {
+                let path = version_range_file.as_deref().unwrap();
+                let (ranges, exact) = parse_version_range_file(&fs::read_to_string(path)?)
+                    .with_context(|| format!("failed to parse --version-range-file {path}"))?;
+                if exact && version_step.is_some() {
+                    // Each version of the list is a range of a single version.
+                    conflicts("--version-step", "`versions` of --version-range-file")?;
+                }
+                let rustup = Rustup::new();
+                if rustup.version < 23 {
+                    bail!("--version-range-file requires rustup 1.23 or later");
+                }
+                ranges
             }
-            (None, true) => {
+            (true, true) => {
                 let rustup = Rustup::new();
                 if rustup.version < 23 {
                     bail!("--rust-version requires rustup 1.23 or later");
                 }
-                Some(VersionRange::msrv())
+                vec![VersionRange::msrv()]
             }
-            (None, false) => None,
+            (true, false) => vec![],
         };
-        if version_range.is_none() {
+        if version_range.is_empty() {
             if version_step.is_some() {
                 requires("--version-step", &["--version-range"])?;
             }
+            if version_order.is_some() {
+                requires("--version-order", &["--version-range"])?;
+            }
             if clean_per_version {
                 requires("--clean-per-version", &["--version-range"])?;
             }
+            if skip_unavailable_targets {
+                requires("--skip-unavailable-targets", &["--version-range", "--rust-version"])?;
+            }
+            if no_rustup_lock {
+                requires("--no-rustup-lock", &["--version-range", "--rust-version"])?;
+            }
+            if verify_lockfile_per_version {
+                requires("--verify-lockfile-per-version", &["--version-range"])?;
+            }
+            if lockfile_strategy.is_some() {
+                requires("--lockfile-strategy", &["--version-range", "--rust-version"])?;
+            }
+            if check_msrv_consistency {
+                requires("--check-msrv-consistency", &["--version-range", "--rust-version"])?;
+            }
+            if strip_unsupported_profile_keys {
+                requires("--strip-unsupported-profile-keys", &[
+                    "--version-range",
+                    "--rust-version",
+                ])?;
+            }
+            if compat_strip_lints {
+                requires("--compat-strip-lints", &["--version-range", "--rust-version"])?;
+            }
+        }
+        if verify_lockfile_per_version && locked {
+            // The lockfile is never updated with --locked.
+            conflicts("--verify-lockfile-per-version", "--locked")?;
+        }
+        let lockfile_strategy = match lockfile_strategy.as_deref() {
+            Some(val) => {
+                let strategy = val.parse()?;
+                if locked
+                    && matches!(
+                        strategy,
+                        LockfileStrategy::GeneratePerVersion | LockfileStrategy::GenerateOnce
+                    )
+                {
+                    // The lockfile cannot be generated with --locked.
+                    conflicts(&format!("--lockfile-strategy={val}"), "--locked")?;
+                }
+                strategy
+            }
+            None => LockfileStrategy::Auto,
+        };
+        if keep_lockfile_changes.is_some()
+            && !version_range.is_empty()
+            && lockfile_strategy != LockfileStrategy::Keep
+        {
+            bail!(
+                "--keep-lockfile-changes may not be used together with --version-range or \
+                 --rust-version unless --lockfile-strategy=keep is used, because Cargo.lock \
+                 generated by old cargo would be kept"
+            );
+        }
+        let keep_lockfile_changes = keep_lockfile_changes
+            .map(|val| val.as_deref().map_or(Ok(KeepLockfileChanges::Always), str::parse))
+            .transpose()?;
+
+        let llvm_cov_report_args = if subcommand.as_deref() == Some("llvm-cov") {
+            if !version_range.is_empty() {
+                let flag = if rust_version { "--rust-version" } else { "--version-range" };
+                // Profile data generated by different toolchains cannot be merged.
+                bail!(
+                    "{flag} may not be used together with llvm-cov subcommand, because coverage \
+                     data of different toolchains cannot be merged"
+                );
+            }
+            if clean_per_run {
+                // cargo-llvm-cov manages its own target directory.
+                bail!("--clean-per-run may not be used together with llvm-cov subcommand");
+            }
+            Some(split_llvm_cov_args(&mut cargo_args)?)
+        } else {
+            None
+        };
+
+        let ignores_features =
+            subcommand.as_deref().is_some_and(|s| FEATURE_INSENSITIVE_SUBCOMMANDS.contains(&s));
+        if ignores_features && (each_feature || feature_powerset) {
+            let flag = if each_feature { "--each-feature" } else { "--feature-powerset" };
+            warn!(
+                "{flag} has no effect with {} subcommand, which does not depend on features; it \
+                 is run only once per package",
+                subcommand.as_deref().unwrap()
+            );
         }
 
-        let version_step = version_step.as_deref().map(str::parse::<u16>).transpose()?.unwrap_or(1);
+        let version_step = version_step
+            .as_deref()
+            .map(str::parse::<u16>)
+            .transpose()
+            .context("failed to parse argument for --version-step")?
+            .unwrap_or(1);
         if version_step == 0 {
             bail!("--version-step cannot be zero");
         }
+        let version_order = match version_order.as_deref() {
+            Some(val) => val.parse()?,
+            None => VersionOrder::OldestFirst,
+        };
 
         let log_group = match log_group {
             Some(v) => v.parse()?,
-            None if disable_log_grouping => LogGroup::None,
+            // --porcelain uses stdout for the records.
+            None if disable_log_grouping || porcelain => LogGroup::None,
             None => LogGroup::auto(),
         };
+        if porcelain {
+            if print_command_list {
+                conflicts("--porcelain", "--print-command-list")?;
+            } else if log_group == LogGroup::GithubActions {
+                conflicts("--porcelain", "--log-group=github-actions")?;
+            }
+        }
 
         let partition = partition.as_deref().map(str::parse).transpose()?;
+        let only_run = run_id::parse(&only_run)?;
+        if shuffle_keep_warmup && shuffle.is_none() {
+            requires("--shuffle-keep-warmup", &["--shuffle"])?;
+        }
+        let shuffle = match shuffle {
+            Some(Some(seed)) => {
+                Some(seed.parse::<u64>().context("failed to parse argument for --shuffle")?)
+            }
+            Some(None) => Some(shuffle::random_seed()),
+            None => None,
+        };
+        let fail_on_skipped =
+            fail_on_skipped.map(|val| skip::parse_kinds(val.as_deref())).transpose()?;
+        let budget = budget
+            .as_deref()
+            .map(|s| budget::parse(s).context("failed to parse argument for --budget"))
+            .transpose()?;
+
+        if show_output_lines.is_some() && !quiet_success {
+            requires("--show-output-lines", &["--quiet-success"])?;
+        }
+        let show_output_lines = show_output_lines
+            .as_deref()
+            .map(str::parse::<usize>)
+            .transpose()
+            .context("failed to parse argument for --show-output-lines")?;
+
+        let slow_threshold_factor = match slow_threshold_factor.as_deref().map(str::parse::<f64>) {
+            Some(Ok(factor)) if factor.is_finite() && factor > 0.0 => Some(factor),
+            Some(_) => bail!("--slow-threshold-factor must be a positive number"),
+            None => None,
+        };
+        if discard_warmup_timing && slow_threshold_factor.is_none() {
+            requires("--discard-warmup-timing", &["--slow-threshold-factor"])?;
+        }
+        if fail_on_new_failures_only {
+            if baseline.is_none() {
+                requires("--fail-on-new-failures-only", &["--baseline"])?;
+            } else if !keep_going {
+                // Otherwise, cargo-hack stops at the first failure, even if
+                // it also failed in the baseline.
+                requires("--fail-on-new-failures-only", &["--keep-going"])?;
+            }
+        }
 
-        if no_dev_deps || no_private {
+        // With --emit-execution-plan, the modifications are only written to the plan.
+        if (no_dev_deps || no_private) && emit_execution_plan.is_none() {
             let flag = if no_dev_deps && no_private {
                 "--no-dev-deps and --no-private modify"
             } else if no_dev_deps {
@@ -591,7 +1586,8 @@ impl Args {
         exclude_no_default_features |= !include_features.is_empty();
         exclude_all_features |= !include_features.is_empty()
             || !exclude_features.is_empty()
-            || !mutually_exclusive_features.is_empty();
+            || !mutually_exclusive_features.is_empty()
+            || !exactly_one_of.is_empty();
         exclude_features.extend_from_slice(&features);
 
         term::verbose::set(verbose != 0);
@@ -605,40 +1601,114 @@ impl Args {
             trailing_args: rest,
 
             subcommand,
+            ignores_features,
+            llvm_cov_report_args,
 
             manifest_path,
             locked,
+            auto_locked,
+            no_locked_hint,
             package,
+            package_with_deps,
+            package_with_dependents,
+            with_dev_deps_closure,
             exclude,
             workspace,
             each_feature,
             feature_powerset,
             no_dev_deps,
             remove_dev_deps,
+            check,
+            no_wait,
+            manifest_backup_dir: manifest_backup_dir.map(Into::into),
+            keep_lockfile_changes,
             no_private,
             ignore_private: ignore_private | no_private,
+            only_packages_with_feature,
+            report_redundant_packages: report_redundant_packages || skip_redundant_packages,
+            skip_redundant_packages,
             ignore_unknown_features,
             optional_deps,
             clean_per_run,
             clean_per_version,
+            skip_unavailable_targets,
+            no_rustup_lock,
+            no_install,
+            verify_lockfile_per_version,
+            strip_unsupported_profile_keys,
+            compat_strip_lints,
+            lockfile_strategy,
+            check_msrv_consistency,
             keep_going,
+            quiet_success,
+            compact_output,
+            tag_output,
+            porcelain,
+            show_output_lines,
             partition,
+            only_run,
+            shuffle,
+            shuffle_keep_warmup,
             print_command_list,
+            emit_execution_plan: emit_execution_plan.map(Into::into),
+            generate_feature_docs: generate_feature_docs.map(Into::into),
+            print_toolchain_requirements,
             no_manifest_path,
+            chdir_instead_of_manifest_path,
+            skip_flag_validation,
             include_features: include_features.into_iter().map(Into::into).collect(),
             at_least_one_of,
+            exactly_one_of,
+            why,
+            bisect_features,
+            bisect_start,
+            bisect_max_runs,
+            cfg_matrix,
+            deny_warnings_for,
+            args_if_feature,
+            and_then,
             include_deps_features,
             version_range,
             version_step,
+            version_order,
             log_group,
+            summary_markdown: summary_markdown.map(Into::into),
+            trace_file: trace_file.map(Into::into),
+            summary_json: summary_json.map(Into::into),
+            baseline: baseline.map(Into::into),
+            fail_on_new_failures_only,
+            junit: junit.map(Into::into),
+            badge_json: badge_json.map(Into::into),
+            capture_stdout_dir: capture_stdout_dir.map(Into::into),
+            skip_unless_manifest_changed,
+            fail_on_skipped,
+            allow_empty_plan,
+            budget,
+            check_additivity,
+            ensure_targets_buildable,
+            rustdoc_all_features_check,
+            doc_json,
+            slow_threshold_factor,
+            discard_warmup_timing,
+            sccache_stats,
+            strict_metadata,
+            ignore_minimum_version,
 
             depth,
+            depth_counts_group_members,
+            max_group_combo,
             group_features,
             mutually_exclusive_features,
 
             exclude_features,
             exclude_no_default_features,
+            strict_coverage,
+            only_code_gated_features,
+            changed_features_only,
             exclude_all_features,
+            exclude_all_features_packages,
+            examples_once,
+            tests_once,
 
             features,
 
@@ -648,6 +1718,51 @@ impl Args {
     }
 }
 
+/// Subcommands whose behavior does not depend on features.
+///
+/// tree and metadata are not included, because the dependency graph they print
+/// depends on the features.
+const FEATURE_INSENSITIVE_SUBCOMMANDS: &[&str] = &["fmt", "clean", "locate-project", "pkgid"];
+
+/// Returns a hint if the value taken by `--optional-deps <DEPS>` from the next
+/// argument was likely not intended to be its value.
+///
+/// --optional-deps takes an optional value, so in `--optional-deps serde
+/// --features serde`, `serde` is the value of --optional-deps, and in
+/// `cargo hack --each-feature --optional-deps check`, `check` is not the subcommand.
+fn optional_deps_hint(
+    val: &str,
+    subcommand: Option<&str>,
+    features: &[String],
+    exclude_features: &[String],
+) -> Option<String> {
+    let greedy = format!(
+        "`{val}` is parsed as the value of --optional-deps because --optional-deps takes the \
+         next argument that is not a flag as its value"
+    );
+    let all = "to use all optional dependencies as features, put --optional-deps before another \
+               flag or at the end of the arguments";
+    if subcommand.is_none()
+        && matches!(val, "check" | "c" | "build" | "b" | "test" | "t" | "run" | "r" | "clippy")
+    {
+        return Some(format!("{greedy}, not as the subcommand; {all}"));
+    }
+    let mut deps = val.split(if val.contains(',') { ',' } else { ' ' });
+    let (flag, dep) = deps.find_map(|d| {
+        if features.iter().any(|f| f == d) {
+            Some(("--features", d))
+        } else if exclude_features.iter().any(|f| f == d) {
+            Some(("--exclude-features", d))
+        } else {
+            None
+        }
+    })?;
+    Some(format!(
+        "{greedy}, and `{dep}` is also specified by {flag}; {all} in addition to {flag}, or \
+         `--optional-deps={val}` to make it clear that `{val}` is the value of --optional-deps"
+    ))
+}
+
 fn parse_grouped_features(
     group_features: &[String],
     option_name: &str,
@@ -671,21 +1786,62 @@ fn parse_grouped_features(
 }
 
 // (short flag, long flag, value name, short descriptions, additional descriptions)
-type HelpText<'a> = (&'a str, &'a str, &'a str, &'a str, &'a [&'a str]);
+pub(crate) type HelpText<'a> = (&'a str, &'a str, &'a str, &'a str, &'a [&'a str]);
 
-const HELP: &[HelpText<'_>] = &[
+pub(crate) const HELP: &[HelpText<'_>] = &[
     ("-p", "--package", "<SPEC>...", "Package(s) to check", &[]),
     ("", "--all", "", "Alias for --workspace", &[]),
     ("", "--workspace", "", "Perform command for all packages in the workspace", &[]),
-    ("", "--exclude", "<SPEC>...", "Exclude packages from the check", &[]),
+    ("", "--package-with-deps", "<SPEC>...", "Package(s) to check, together with the workspace members they depend on", &[
+        "The workspace members that the package depends on directly or indirectly through \
+         normal and build dependencies are also checked (dev-dependencies are included with \
+         --with-dev-deps-closure).",
+        "This can be used together with --package and --exclude.",
+    ]),
+    ("", "--package-with-dependents", "<SPEC>...", "Package(s) to check, together with the workspace members that depend on them", &[
+        "The workspace members that depend on the package directly or indirectly through normal \
+         and build dependencies are also checked (dev-dependencies are included with \
+         --with-dev-deps-closure).",
+        "This can be used together with --package and --exclude.",
+    ]),
+    ("", "--with-dev-deps-closure", "", "Follow dev-dependencies for --package-with-deps and --package-with-dependents", &[]),
+    ("", "--exclude", "<SPEC>...", "Exclude packages from the check", &[
+        "SPEC may be a glob pattern containing `*`, `?`, and `[]` (e.g., `--exclude \
+         'foo-internal-*'`), which excludes all workspace members whose names match it.",
+    ]),
+    ("", "--only-packages-with-feature", "<FEATURE>...", "Perform only on packages that have the specified feature", &[
+        "If this option is used multiple times, only packages that have all of the specified \
+         features are selected.",
+        "Other packages are skipped entirely, unlike --ignore-unknown-features flag.",
+    ]),
+    ("", "--report-redundant-packages", "", "Report packages that only re-export another selected package with the same feature combinations", &[
+        "A package is reported if its only target is the library whose source file only \
+         contains `pub use` items (and attributes and comments) re-exporting a dependency that \
+         is also selected, and the feature combinations to run on both packages are the same. \
+         Chains of such wrapper packages are followed to the deepest package.",
+        "The runs on such packages are likely redundant, but since this is determined by a \
+         heuristic, they are only reported. Use --skip-redundant-packages to skip them.",
+    ]),
+    ("", "--skip-redundant-packages", "", "Skip packages reported by --report-redundant-packages", &[
+        "Only the deepest package of each chain of wrapper packages is run on.",
+    ]),
     ("", "--manifest-path", "<PATH>", "Path to Cargo.toml", &[]),
-    ("", "--locked", "", "Require Cargo.lock is up to date", &[]),
+    ("", "--locked", "", "Require Cargo.lock is up to date", &[
+        "With `--locked=auto` (or `--locked auto`, or CARGO_HACK_AUTO_LOCKED=1), a command that \
+         fails because Cargo.lock needs to be updated is run again without --locked with a \
+         warning.",
+        "If Cargo.lock exists in the workspace root and this flag is not used, a hint to use it \
+         is printed.",
+    ]),
+    ("", "--no-locked-hint", "", "Do not print the hint to use --locked", &[]),
     ("-F", "--features", "<FEATURES>...", "Space or comma separated list of features to activate", &[]),
     ("", "--each-feature", "", "Perform for each feature of the package", &[
         "This also includes runs with just --no-default-features flag, and default features.",
         "When this flag is not used together with --exclude-features (--skip) and \
          --include-features and there are multiple features, this also includes runs with \
-         just --all-features flag."
+         just --all-features flag.",
+        "Subcommands that do not depend on features (fmt, clean, locate-project, and pkgid) are \
+         run only once per package.",
     ]),
     ("", "--feature-powerset", "", "Perform for the feature powerset of the package", &[
         "This also includes runs with just --no-default-features flag, and default features.",
@@ -693,10 +1849,16 @@ const HELP: &[HelpText<'_>] = &[
         "When this flag is used together with --depth or namespaced features \
          (-Z namespaced-features) and not used together with --exclude-features (--skip) and \
          --include-features and there are multiple features, this also includes runs with just \
-         --all-features flag."
+         --all-features flag.",
+        "Subcommands that do not depend on features (fmt, clean, locate-project, and pkgid) are \
+         run only once per package.",
     ]),
     ("", "--optional-deps", "[DEPS]...", "Use optional dependencies as features", &[
         "If DEPS are not specified, all optional dependencies are considered as features.",
+        "If the next argument is not a flag, it is parsed as DEPS (e.g., `--optional-deps serde \
+         --features serde` uses only `serde`). Use `--optional-deps=<DEPS>` form to avoid the \
+         ambiguity. `--optional-deps=` explicitly specifies an empty list, i.e., no optional \
+         dependencies are used.",
         "This flag can only be used together with either --each-feature flag or --feature-powerset \
          flag.",
     ]),
@@ -713,10 +1875,55 @@ const HELP: &[HelpText<'_>] = &[
         "This flag can only be used together with either --each-feature flag or --feature-powerset \
          flag.",
     ]),
+    ("", "--strict-coverage", "", "Warn about packages whose default features are never disabled", &[
+        "If no run of a package with non-empty default features disables all of them (e.g., \
+         because --include-features implies --exclude-no-default-features), a note is printed \
+         at the end. This flag makes it a warning.",
+        "This flag can only be used together with either --each-feature flag or --feature-powerset \
+         flag.",
+    ]),
+    ("", "--only-code-gated-features", "", "Iterate only features that are referenced in the code or enable nothing", &[
+        "Features that only enable other features or optional dependencies (e.g., `full = \
+         [\"a\", \"b\"]`) and are not referenced in the code of the package are not iterated \
+         individually, because running with such a feature alone is the same as running with \
+         the features it enables. They are still covered by --all-features and the \
+         combinations of the features they enable.",
+        "A feature is referenced if `feature = \"<name>\"` (e.g., in `#[cfg]` or `cfg!`) appears \
+         in the sources of the targets of the package, or CARGO_FEATURE_<NAME> appears in its \
+         build script.",
+        "This flag can only be used together with either --each-feature flag or --feature-powerset \
+         flag.",
+    ]),
+    ("", "--changed-features-only", "<GIT_REF>", "Run only feature combinations that contain features changed since the specified git revision", &[
+        "The features of each package (including the implicit features of optional \
+         dependencies) are compared with those in its Cargo.toml at GIT_REF (e.g., \
+         `origin/main`). A feature is changed if it was added or the features it enables \
+         changed. Only the combinations that contain at least one changed feature and the run \
+         with --all-features are run, and packages whose features did not change are skipped.",
+        "It is an error if GIT_REF is not a valid git revision.",
+        "This flag can only be used together with either --each-feature flag or --feature-powerset \
+         flag.",
+    ]),
     ("", "--exclude-all-features", "", "Exclude run of just --all-features flag", &[
+        "If --exclude-all-features=<PKG> is used, the run is excluded only for the specified \
+         package. This form can be specified multiple times.",
         "This flag can only be used together with either --each-feature flag or --feature-powerset \
          flag.",
     ]),
+    ("", "--examples-once", "", "Compile examples only on the runs with all features and default features", &[
+        "The other runs select the targets other than examples explicitly (e.g., `cargo test --lib \
+         --bins --tests`, or `--lib --bins --tests --benches` instead of --all-targets). The run \
+         with default features is the run with `--features default`, or the run without \
+         features if the package has no `default` feature.",
+        "Since cargo runs doctests only when no targets are selected, `cargo test` also runs them \
+         only on those runs.",
+        "This flag can only be used together with either --each-feature flag or --feature-powerset \
+         flag, and either `test` subcommand or --all-targets flag. This flag may not be used \
+         together with the other target selection flags of cargo.",
+    ]),
+    ("", "--tests-once", "", "Compile tests only on the runs with all features and default features", &[
+        "This is the same as --examples-once, but for tests. These flags can be used together.",
+    ]),
     (
         "",
         "--depth",
@@ -727,15 +1934,85 @@ const HELP: &[HelpText<'_>] = &[
             "This flag can only be used together with --feature-powerset flag.",
         ],
     ),
+    ("", "--depth-counts-group-members", "", "Count each group of --group-features as the number of its members toward --depth", &[
+        "By default, each group counts as one feature. With this flag, for example, \
+         `--group-features a,b --depth 2` does not combine the group with other features, and \
+         groups that have more members than NUM of --depth are not used at all.",
+        "This flag can only be used together with --depth flag.",
+    ]),
+    (
+        "",
+        "--max-group-combo",
+        "<NUM>",
+        "Specify a max number of groups of --group-features combined in a run of --feature-powerset",
+        &[
+            "For example, with three groups, `--max-group-combo 2` combines each group with each \
+             other group, but never all three. Features not in a group are not counted.",
+            "This flag can only be used together with --feature-powerset and --group-features \
+             flags.",
+        ],
+    ),
     ("", "--group-features", "<FEATURES>...", "Space or comma separated list of features to group", &[
         "This treats the specified features as if it were a single feature.",
+        "`default` in the list is replaced with the features that the `default` feature of each \
+         package enables, e.g., `--group-features default,serde` treats the default features \
+         and `serde` as a single feature.",
         "To specify multiple groups, use this option multiple times: `--group-features a,b \
          --group-features c,d`",
+        "Optional dependencies can be grouped only together with --optional-deps flag. \
+         Optional dependencies that are only referenced with `dep:` syntax cannot be grouped; \
+         group the features that enable them instead.",
         "This flag can only be used together with --feature-powerset flag.",
     ]),
+    ("", "--cfg-matrix", "<CFGS>...", "Space or comma separated list of cfgs to iterate the powerset of", &[
+        "Each run is additionally performed with every combination of the specified cfgs, \
+         which are passed via RUSTFLAGS (e.g., `--cfg-matrix tokio_unstable,loom` runs with no \
+         cfgs, `--cfg tokio_unstable`, `--cfg loom`, and both).",
+        "The cfgs are appended to RUSTFLAGS (or CARGO_ENCODED_RUSTFLAGS) in the environment. \
+         Note that RUSTFLAGS overrides `build.rustflags` in cargo config.",
+        "The number of simultaneous cfgs is limited by --depth if specified.",
+        "Since changing RUSTFLAGS invalidates the build cache, the runs are grouped by cfgs per \
+         package.",
+    ]),
+    ("", "--deny-warnings-for", "<SPEC>...", "Package(s) whose runs are performed with warnings denied", &[
+        "`-D warnings` is appended to RUSTFLAGS and RUSTDOCFLAGS (or CARGO_ENCODED_RUSTFLAGS \
+         and CARGO_ENCODED_RUSTDOCFLAGS) in the environment of the runs on the specified \
+         packages. The runs on other packages are not affected.",
+        "This is useful to deny warnings only in your own workspace members, e.g., not in \
+         vendored ones.",
+        "Note that the build cache is invalidated each time RUSTFLAGS changes between runs, so \
+         running on packages both with and without this option rebuilds some dependencies; \
+         consider using a separate target directory for each of them in that case.",
+    ]),
+    ("", "--and-then", "<SUBCOMMAND> [ARGS]...", "Run another cargo subcommand with the same feature flags after each command", &[
+        "For example, `cargo hack clippy --each-feature --and-then 'check --all-targets' \
+         --and-then 'doc --no-deps'` runs clippy, check, and doc in this order on each feature \
+         combination. The value is split by whitespace, and args after `--` are passed after \
+         `--` of the chained command.",
+        "The chained commands of a feature combination are not run if a command of the \
+         combination failed (with --keep-going, the other combinations are still run). Each \
+         chained command counts as a separate run in the progress.",
+        "The args of the subcommand (including args after `--`) are not passed to the chained \
+         commands, and flags controlled by cargo-hack (--manifest-path, --features, \
+         --no-default-features, and --all-features) cannot be passed.",
+        "This flag can be specified multiple times.",
+    ]),
+    ("", "--args-if-feature", "<FEATURE>:<ARGS>", "Append space-separated args to the commands that enable the specified feature", &[
+        "For example, `--args-if-feature bench-internal:'--profile bench-opt'` passes `--profile \
+         bench-opt` to every command whose feature set enables `bench-internal`, directly, via \
+         other features (including the default features), or via --all-features.",
+        "The args are passed before the feature flags and shown in the output of \
+         --print-command-list. Flags controlled by cargo-hack (--manifest-path, --features, \
+         --no-default-features, and --all-features) cannot be passed.",
+        "This flag can be specified multiple times.",
+    ]),
     ("", "--target", "<TRIPLE>", "Build for specified target triple", &[
         "Comma-separated lists of targets are not supported, but you can specify the whole --target option multiple times to do multiple targets.",
         "This is actually not a cargo-hack option, it is interpreted by Cargo itself.",
+        "Before running, the target triples are checked against the targets listed by `rustup \
+         target list` or `rustc --print target-list`, so that a typo fails early. Custom targets \
+         (a path to a target specification JSON file, or a name found in RUST_TARGET_PATH) are \
+         not checked.",
     ]),
     ("", "--mutually-exclusive-features", "<FEATURES>...", "Space or comma separated list of features to not use together", &[
         "To specify multiple groups, use this option multiple times: `--mutually-exclusive-features \
@@ -747,6 +2024,40 @@ const HELP: &[HelpText<'_>] = &[
          --at-least-one-of c,d`",
         "This flag can only be used together with --feature-powerset flag.",
     ]),
+    ("", "--exactly-one-of", "<FEATURES>...", "Space or comma separated list of features. Skips sets of features that don't enable exactly one of the features listed", &[
+        "This is equivalent to specifying the same list to both --at-least-one-of and \
+         --mutually-exclusive-features. Features that enable any of the features listed are also \
+         taken into account.",
+        "To specify multiple groups, use this option multiple times: `--exactly-one-of a,b \
+         --exactly-one-of c,d`",
+        "This flag can only be used together with --feature-powerset flag.",
+    ]),
+    ("", "--why", "<FEATURES>", "Explain whether the specified combination of features is run, without running anything", &[
+        "For each package, this shows whether the combination of features (space or comma \
+         separated list) is in the feature combinations of --feature-powerset, and if not, why \
+         (e.g., deduplicated because a feature implies another, --depth, \
+         --mutually-exclusive-features, --exclude-features, or --at-least-one-of).",
+        "This flag can only be used together with --feature-powerset flag.",
+    ]),
+    ("", "--bisect-features", "", "Find a minimal set of features with which the command fails", &[
+        "Starting from all features that --each-feature would use (or the features specified by \
+         --bisect-start), this repeatedly runs the command with subsets of the features \
+         (delta debugging) to find a set of features that fails while removing any single \
+         feature from it passes, and reports it with the number of runs used.",
+        "The runs are performed with --no-default-features, so `default` is treated like the \
+         other features. --exclude-features, --group-features, --optional-deps, and \
+         --include-features are respected. Each selected package is bisected separately.",
+        "This flag cannot be used together with --each-feature or --feature-powerset flag.",
+    ]),
+    ("", "--bisect-start", "<FEATURES>...", "Space or comma separated list of features to start --bisect-features from", &[
+        "The command must fail with these features.",
+        "This flag can only be used together with --bisect-features flag.",
+    ]),
+    ("", "--bisect-max-runs", "<NUM>", "Specify a max number of runs per package of --bisect-features", &[
+        "If the limit is reached, the smallest failing set of features found so far is \
+         reported, which may not be minimal.",
+        "This flag can only be used together with --bisect-features flag.",
+    ]),
     (
         "",
         "--include-features",
@@ -754,6 +2065,10 @@ const HELP: &[HelpText<'_>] = &[
         "Include only the specified features in the feature combinations instead of package \
          features",
         &[
+            "The keyword `@defaults` expands to the direct members of the `default` feature of \
+             each package, and `@all` expands to all features of each package except optional \
+             dependencies. Features specified by --exclude-features are removed after the \
+             expansion, e.g., `--include-features @all --exclude-features a`.",
             "This flag can only be used together with either --each-feature flag or \
              --feature-powerset flag.",
         ],
@@ -761,6 +2076,8 @@ const HELP: &[HelpText<'_>] = &[
     ("", "--no-dev-deps", "", "Perform without dev-dependencies", &[
         "Note that this flag removes dev-dependencies from real `Cargo.toml` while cargo-hack is \
          running and restores it when finished.",
+        "This flag can be used with `test` subcommand only together with `--doc` flag. In that \
+         case, doctests that use dev-dependencies will fail.",
     ]),
     (
         "",
@@ -770,7 +2087,61 @@ const HELP: &[HelpText<'_>] = &[
          after performed",
         &[],
     ),
+    ("", "--check", "", "Check that --remove-dev-deps would not change the manifests", &[
+        "Instead of writing the manifests, reports for each manifest of the workspace members \
+         whether --remove-dev-deps would change it, and exits with an error if any would be \
+         changed, like `rustfmt --check`. If --verbose is used, the diffs are shown.",
+        "This flag can only be used together with --remove-dev-deps flag and without \
+         subcommand.",
+    ]),
     ("", "--no-private", "", "Perform without `publish = false` crates", &[]),
+    (
+        "",
+        "--no-wait",
+        "",
+        "Exit with an error instead of waiting if another cargo-hack is modifying the manifests \
+         of the same workspace",
+        &[
+            "--no-dev-deps, --remove-dev-deps, and --no-private flags hold a lock \
+             (`target/.cargo-hack.lock` in the workspace root) while modifying `Cargo.toml`, and \
+             by default wait until the lock held by another cargo-hack is released.",
+            "The original contents of the modified files are also saved in \
+             `target/cargo-hack-backup` in the workspace root until they are restored. If \
+             cargo-hack is killed before restoring them, run `cargo hack restore` to restore them.",
+            "This flag can only be used together with --no-dev-deps, --remove-dev-deps, or \
+             --no-private flag.",
+        ],
+    ),
+    (
+        "",
+        "--manifest-backup-dir",
+        "<DIR>",
+        "Also save the original contents of the modified files to the specified directory",
+        &[
+            "The backups are written before modifying the files, along with an `index` file that \
+             lists the paths of the original files, so the files can be restored manually even if \
+             cargo-hack is killed and `target/cargo-hack-backup` is not available. The backups \
+             are removed after the files are restored.",
+            "This flag can only be used together with either --no-dev-deps or --no-private flag.",
+        ],
+    ),
+    (
+        "",
+        "--keep-lockfile-changes",
+        "[WHEN]",
+        "Keep the changes cargo made to Cargo.lock instead of restoring it when finished",
+        &[
+            "By default, --no-dev-deps and --no-private restore Cargo.lock as well as the \
+             manifests when finished. With this flag, only the manifests are restored.",
+            "If `--keep-lockfile-changes=on-success` is used, Cargo.lock is still restored if \
+             cargo-hack fails. `--keep-lockfile-changes=always` is the same as this flag without \
+             value.",
+            "This flag can only be used together with either --no-dev-deps or --no-private flag. \
+             This flag cannot be used together with --version-range or --rust-version unless \
+             --lockfile-strategy=keep is used, because Cargo.lock generated by old cargo would \
+             be kept.",
+        ],
+    ),
     ("", "--ignore-private", "", "Skip to perform on `publish = false` packages", &[]),
     (
         "",
@@ -797,10 +2168,24 @@ const HELP: &[HelpText<'_>] = &[
             "If the upper bound of the range is omitted, the latest stable compiler is used as the \
              upper bound.",
             "If the lower bound of the range is omitted, the value of the `rust-version` field in \
-             `Cargo.toml` is used as the lower bound.",
+             `Cargo.toml` is used as the lower bound. For packages without the `rust-version` \
+             field, the first Rust version that supports the `edition` of the package is used \
+             instead (1.26 for 2015, 1.31 for 2018, 1.56 for 2021, 1.85 for 2024) with a \
+             warning.",
             "Note that ranges are always inclusive ranges.",
+            "To specify multiple ranges, use this option multiple times: `--version-range \
+             1.65..=1.65 --version-range 1.70..=1.72`. In this case, commands are performed \
+             on the union of the ranges.",
         ],
     ),
+    ("", "--version-range-file", "<PATH>", "Read the Rust versions of --version-range from a TOML file", &[
+        "The file contains either `versions`, the exact list of versions to run on (e.g., \
+         `versions = [\"1.70\", \"1.74\", \"stable\"]`), or `start` and `end`, the inclusive \
+         bounds of a range that are handled like those of --version-range (e.g., `start = \
+         \"1.70\"`). Versions are either `1.<minor>` or `stable`.",
+        "--version-step cannot be used with the list of versions.",
+        "This cannot be used with --version-range or --rust-version.",
+    ]),
     (
         "",
         "--version-step",
@@ -808,6 +2193,21 @@ const HELP: &[HelpText<'_>] = &[
         "Specify the version interval of --version-range (default to `1`)",
         &["This flag can only be used together with --version-range flag."],
     ),
+    (
+        "",
+        "--version-order",
+        "<ORDER>",
+        "Specify the order of the runs on the Rust versions of --version-range",
+        &[
+            "Possible values are: oldest-first (default), newest-first",
+            "newest-first is useful to find breakage on recent toolchains first. The flags of \
+             cargo-hack are validated with the first toolchain that is run, and the progress \
+             totals are the same as with oldest-first.",
+            "If Cargo.lock is generated with the oldest toolchain (see --lockfile-strategy), it \
+             is still generated before any runs.",
+            "This flag can only be used together with --version-range flag.",
+        ],
+    ),
     ("", "--clean-per-run", "", "Remove artifacts for that package before running the command", &[
         "If used this flag with --workspace, --each-feature, or --feature-powerset, artifacts will \
          be removed before each run.",
@@ -817,18 +2217,429 @@ const HELP: &[HelpText<'_>] = &[
         "Note that dependencies artifacts will also be removed.",
         "This flag can only be used together with --version-range flag.",
     ]),
+    (
+        "",
+        "--skip-unavailable-targets",
+        "",
+        "Skip the runs for targets that are not available on a Rust version",
+        &[
+            "Before running, cargo-hack checks that each target specified by --target is \
+             available on each Rust version of --version-range (or --rust-version), and fails \
+             with the list of unavailable pairs if any. For example, `wasm32-wasip1` is not \
+             available before Rust 1.78, where it is called `wasm32-wasi`. With this flag, the \
+             runs for such targets on such versions are skipped with warnings instead.",
+            "This flag can only be used together with either --version-range or --rust-version \
+             flag.",
+        ],
+    ),
+    ("", "--no-rustup-lock", "", "Install toolchains without locking RUSTUP_HOME", &[
+        "By default, cargo-hack holds an exclusive lock on `$RUSTUP_HOME/.cargo-hack-rustup.lock` \
+         while installing toolchains and targets, so that cargo-hack processes sharing the same \
+         RUSTUP_HOME (e.g., concurrent CI jobs on a self-hosted runner) do not install at the \
+         same time, which may corrupt the download cache of rustup. The commands are still run \
+         in parallel. The path of the lock file can be changed by CARGO_HACK_RUSTUP_LOCK_FILE \
+         environment variable.",
+        "This flag can only be used together with either --version-range or --rust-version \
+         flag.",
+    ]),
+    ("", "--no-install", "", "Fail instead of installing toolchains and targets that are not installed", &[
+        "Before the runs, cargo-hack checks that the toolchains of --version-range (or \
+         --rust-version), the targets, and the components that the subcommand needs (clippy \
+         for `cargo clippy`, rustfmt for `cargo fmt`, and miri and rust-src for `cargo miri`) \
+         are installed, and fails with the list of the missing ones if any, instead of \
+         installing them. This is useful for air-gapped CI, whose runner images are \
+         provisioned ahead of time (see also --print-toolchain-requirements).",
+        "Without --version-range, the targets and components are checked on the toolchain \
+         cargo-hack is run with. The checks use rustup.",
+    ]),
+    ("", "--verify-lockfile-per-version", "", "Report changes of Cargo.lock per Rust version", &[
+        "Compares Cargo.lock before and after the runs of each Rust version, and reports the \
+         packages whose versions changed, e.g., because the lockfile was generated by an old \
+         cargo.",
+        "This does not change how dependencies are resolved.",
+        "This flag can only be used together with --version-range flag, and cannot be used \
+         together with --locked flag.",
+    ]),
+    (
+        "",
+        "--strip-unsupported-profile-keys",
+        "",
+        "Remove profile keys that old toolchains of --version-range do not support",
+        &[
+            "Old cargo fails to parse manifests that use profile keys stabilized later (e.g., \
+             `strip`, stabilized in 1.59, and named debuginfo levels such as `debug = \
+             \"line-tables-only\"`, stabilized in 1.71). With this flag, such keys are removed \
+             from the manifests of the workspace while running on those toolchains, and the \
+             manifests are restored afterwards.",
+            "This flag can only be used together with either --version-range flag or \
+             --rust-version flag.",
+        ],
+    ),
+    (
+        "",
+        "--compat-strip-lints",
+        "",
+        "Remove `[lints]` tables while running on toolchains of --version-range older than 1.74",
+        &[
+            "Cargo older than 1.74 fails to parse manifests that use `[lints]` or \
+             `[workspace.lints]`. With this flag, these tables are removed from the manifests of \
+             the workspace while running on those toolchains, and the manifests are restored \
+             afterwards. Note that the lint configuration is not applied on those toolchains.",
+            "Without this flag, cargo-hack fails before running any commands if the manifests \
+             use `[lints]` and --version-range includes such toolchains.",
+            "This flag can only be used together with either --version-range flag or \
+             --rust-version flag.",
+        ],
+    ),
+    (
+        "",
+        "--lockfile-strategy",
+        "<STRATEGY>",
+        "Control when Cargo.lock is generated with the old toolchains of --version-range",
+        &[
+            "Possible values are:",
+            "auto (default): generate with the oldest toolchain if it is older than 1.60, and \
+             regenerate with the first toolchain 1.51 or later after runs on toolchains older \
+             than 1.51 (workaround for spurious \"failed to select a version\" error)",
+            "keep: never generate, and error if the existing Cargo.lock cannot be parsed by the \
+             oldest toolchain",
+            "generate-per-version: generate with each toolchain",
+            "generate-once: generate with the oldest toolchain",
+            "Each generation is logged together with its reason.",
+            "This flag can only be used together with either --version-range flag or \
+             --rust-version flag. generate-per-version and generate-once cannot be used together \
+             with --locked flag.",
+        ],
+    ),
+    (
+        "",
+        "--check-msrv-consistency",
+        "",
+        "Check whether `package.rust-version` of each package is consistent with the results",
+        &[
+            "Each package is also run on the Rust versions older than its rust-version (with \
+             --ignore-rust-version), and failures on those versions are not treated as errors.",
+            "After all runs, reports packages whose rust-version is higher than needed \
+             (informational), or lower than the oldest Rust version on which all runs passed \
+             (error).",
+            "This flag works best with --keep-going flag, because otherwise the runs stop at the \
+             first failure.",
+            "This flag can only be used together with either --version-range flag or \
+             --rust-version flag.",
+        ],
+    ),
     ("", "--keep-going", "", "Keep going on failure", &[]),
-    ("", "--partition", "<M/N>", "Partition runs and execute only its subset according to M/N", &[]),
+    ("", "--fail-on-skipped", "", "Fail if any part of the requested runs was skipped", &[
+        "Some skips are otherwise only reported as info or warning messages. With this flag, \
+         the run fails at the end with a summary of the skips, even if all commands succeeded.",
+        "If --fail-on-skipped=<KINDS> is used, only the specified comma-separated kinds of skips \
+         are checked. Possible kinds are: unknown-feature (a feature unknown to the package is \
+         not applied with --ignore-unknown-features), group-skipped (a group of \
+         --group-features is not applied with --ignore-unknown-features), msrv-out-of-range (a \
+         package whose rust-version is not in --version-range is not run), package-empty-plan \
+         (a package has no features to combine with --each-feature or --feature-powerset, or no \
+         binary target for `cargo run`), private-package (a private package is not run with \
+         --ignore-private), and excluded-package (a package is excluded by --exclude or \
+         --only-packages-with-feature).",
+        "By default, all kinds except private-package and excluded-package are checked.",
+    ]),
+    ("", "--allow-empty-plan", "", "Succeed even if no packages are left to run", &[
+        "If options such as --exclude, --ignore-private, --only-packages-with-feature, or the \
+         rust-version check of --version-range filter out all packages, cargo-hack fails with \
+         the reasons why each package was filtered out. With this flag, the reasons are only \
+         reported as a warning.",
+    ]),
+    ("", "--budget", "<DURATION>", "Stop starting new commands once they have run for the specified total time", &[
+        "DURATION is a number of hours, minutes, and seconds such as `45m`, `1h15m`, or `90s`, or \
+         a plain number of seconds.",
+        "The wall time of the executed commands is accumulated, and once it reaches DURATION, \
+         the remaining commands are not run. A running command is never interrupted, so the \
+         total time may exceed DURATION.",
+        "The remaining budget is shown in the progress output, and the commands that were not \
+         run are reported as \"not run (budget exceeded)\" in --summary-markdown and --junit. \
+         At the end, the first command that was not run is reported, and cargo-hack exits with \
+         status 3 unless other errors occurred.",
+    ]),
+    ("", "--skip-unless-manifest-changed", "<GIT_REF>", "Skip all runs if no manifest changed since the specified git revision", &[
+        "The manifests of the selected packages, the manifest of the workspace root, and \
+         Cargo.lock are compared with their contents at GIT_REF (e.g., `origin/main` or \
+         `HEAD~1`). If none of them changed, cargo-hack exits successfully without running \
+         anything. This is useful to skip the feature matrix on pushes that change only code \
+         or documentation.",
+        "Cargo.lock is not compared if it is ignored by git. If the comparison fails (e.g., git \
+         is not available), the runs are performed as usual.",
+    ]),
+    (
+        "",
+        "--quiet-success",
+        "",
+        "Print the output of cargo only when the command fails",
+        &[
+            "The output (both stdout and stderr) of each command is captured to a temporary file, \
+             and printed to stderr only if the command fails. For successful commands, only a \
+             line with the command and the elapsed time is printed.",
+        ],
+    ),
+    (
+        "",
+        "--show-output-lines",
+        "<N>",
+        "Print only the last N lines of the output of failed commands",
+        &[
+            "If this option is not used, the whole output is printed.",
+            "This option can only be used together with --quiet-success flag.",
+        ],
+    ),
+    ("", "--compact-output", "", "Print fewer lines for each command", &[
+        "The blank line between commands is not printed, and if a command prints nothing (e.g., \
+         when `--quiet` is passed to cargo), its result and the elapsed time are appended to the \
+         line with the command, like --quiet-success does.",
+        "This flag has no effect with --log-group=github-actions, which prints each command in \
+         its own group.",
+    ]),
+    ("", "--tag-output", "", "Prefix each line of the output of cargo with the package and the id of the run", &[
+        "Each line printed by cargo is prefixed with `[<package>:<id>] `, where <id> is the id \
+         used by --only-run, so that the output of a run can be found with grep. The progress \
+         bar of cargo is disabled (CARGO_TERM_PROGRESS_WHEN=never) because it cannot be \
+         prefixed.",
+    ]),
+    ("", "--porcelain", "", "Print the records of the runs to stdout in a stable format for other tools", &[
+        "The first line is `porcelain-version 2`, and each of the following lines is a record \
+         with tab-separated fields: `RUN <id> <package> <features> <index> <total>` when a \
+         command is started, and `OK ...`, `FAIL ... <exit code>`, or `SKIP ... <reason>` \
+         (`partition`, `only-run`, or `budget`) when it finished. <id> is the id used by \
+         --only-run.",
+        "Everything else, including the output of cargo, is printed to stderr. The format is \
+         only changed together with the version in the first line.",
+    ]),
+    ("", "--partition", "<M/N>", "Partition runs and execute only its subset according to M/N", &[
+        "`--partition NAME=START%..END%` (e.g., `--partition big=0%..60%`) executes only the \
+         runs in the half-open percentage interval of the planned runs instead, which is useful \
+         to split the runs unevenly between runners of different sizes. The intervals of the \
+         partitions are not checked against each other, so they should be chosen to neither \
+         overlap nor leave gaps (e.g., `0%..60%`, `60%..80%`, and `80%..100%`).",
+    ]),
+    ("", "--shuffle", "[SEED]", "Shuffle the order of packages and feature combinations", &[
+        "This is useful to find hidden dependencies between runs. The seed is printed, and the \
+         same order can be reproduced by passing it. If the seed is not specified, it is chosen \
+         randomly.",
+        "The runs with just --all-features flag and just --no-default-features flag are not \
+         moved. --partition and --only-run are applied to the shuffled order.",
+    ]),
+    ("", "--shuffle-keep-warmup", "", "Keep running the biggest feature combination early with --shuffle", &[
+        "By default, --shuffle also shuffles the biggest feature combination of --feature-powerset, \
+         which is otherwise run early to warm the build cache.",
+    ]),
+    ("", "--only-run", "<ID>...", "Run only the runs with the specified ids", &[
+        "Each run has an id of 8 hexadecimal digits, which is printed with the command before it \
+         is run, in the list of failed commands, and by --print-command-list. The id is computed \
+         from the package, the features, the cfgs of --cfg-matrix, and the toolchain of \
+         --version-range, so it is stable across machines and the order of the flags.",
+        "The other runs are skipped like with --partition. This is an error if any of the \
+         specified ids does not match any run.",
+        "To specify multiple ids, use this option multiple times or pass a comma-separated list.",
+    ]),
     ("", "--log-group", "<KIND>", "Log grouping: none, github-actions", &[
         "If this option is not used, the environment will be automatically detected."
     ]),
-    ("", "--print-command-list", "", "Print commands without run (Unstable)", &[]),
+    ("", "--summary-markdown", "<PATH>", "Write a summary of the results in Markdown format to the specified file", &[
+        "The summary includes the number of passed/failed/skipped commands per package, the \
+         last lines of stderr of failed commands, and results per Rust version when \
+         --version-range is used.",
+        "Note that stderr of cargo is piped when this option is used.",
+    ]),
+    ("", "--summary-json", "<PATH>", "Write the results in JSON format to the specified file", &[
+        "Each run has an `id` (see --only-run), `package`, `toolchain`, `command`, `features`, \
+         `cfgs`, `status` (`passed`, `failed`, `skipped`, or `not-run`), `duration` in seconds, \
+         and `error`.",
+        "The file can be passed to --baseline of a later run.",
+    ]),
+    ("", "--baseline", "<PATH>", "Compare the results with a file written by --summary-json in a previous run", &[
+        "At the end, the runs that newly fail, newly pass, and still fail are reported. The runs \
+         are matched by their ids, which are computed from the package, the features, the cfgs \
+         of --cfg-matrix, and the toolchain. Failing runs that are not in the baseline are \
+         reported as newly failing.",
+    ]),
+    ("", "--fail-on-new-failures-only", "", "Do not fail on runs that also failed in the baseline", &[
+        "The failures of such runs are reported, but do not affect the exit code.",
+        "This flag can only be used together with --baseline and --keep-going flags.",
+    ]),
+    ("", "--junit", "<PATH>", "Write the results in JUnit XML format to the specified file", &[
+        "Each command becomes a test case whose class name is the package and whose name is \
+         the feature flags (and the toolchain when --version-range is used). Failed commands \
+         include the error and the last lines of stderr, and commands skipped by --partition \
+         and packages skipped entirely (e.g., private packages with --ignore-private) are \
+         reported as skipped.",
+        "Note that stderr of cargo is piped when this option is used.",
+    ]),
+    ("", "--badge-json", "<PATH>", "Write the results as a shields.io endpoint badge to the specified file", &[
+        "The file is a JSON like `{\"schemaVersion\":1,\"label\":\"feature matrix\",\"message\":\"204/204 passing\",\"color\":\"brightgreen\"}`, \
+         which can be displayed by https://shields.io/badges/endpoint-badge.",
+        "The message includes the number of failed commands, and the partition when \
+         --partition is used. The color is red if some commands failed, and yellow if some \
+         commands were not run (e.g., due to --budget) or some parts of the requested runs \
+         were skipped (see --fail-on-skipped).",
+    ]),
+    ("", "--capture-stdout-dir", "<DIR>", "Also write stdout of each command to a file in the specified directory", &[
+        "The file is DIR/<package>/<features>.txt, or DIR/<package>/<toolchain>/<features>.txt \
+         when --version-range is used, where <features> is the sorted list of the enabled \
+         features (including `default` if the default features are enabled), \
+         `(no-features)`, or `(all-features)`. Characters not allowed in file names are \
+         percent-encoded.",
+        "This is useful to collect the output of commands such as `cargo hack rustc \
+         --each-feature -- --print cfg` per feature combination.",
+        "Note that stdout of cargo is piped when this option is used.",
+    ]),
+    ("", "--slow-threshold-factor", "<FACTOR>", "Warn about commands that are pathologically slower than others on the same package", &[
+        "A warning is emitted when a command takes longer than FACTOR times the median of the \
+         previously completed commands on the same package (e.g., `3`).",
+        "The slowest commands are also listed at the end, and in the summary of \
+         --summary-markdown.",
+    ]),
+    ("", "--discard-warmup-timing", "", "Exclude the first command on each package from the median used by --slow-threshold-factor", &[
+        "The first command on each package (and each toolchain of --version-range) builds the \
+         dependencies with a cold cache, so it is usually much slower than the others. It is \
+         marked as a cold start in the timing outputs regardless of this flag.",
+        "This flag can only be used together with --slow-threshold-factor.",
+    ]),
+    ("", "--sccache-stats", "", "Show sccache statistics per command", &[
+        "If RUSTC_WRAPPER is sccache, sccache statistics are reset before each command and \
+         collected after it, and the cache hits, misses, and errors of each command, each \
+         package, and in total are listed at the end.",
+    ]),
+    ("", "--strict-metadata", "", "Fail on fields of unexpected shape in the output of cargo metadata", &[
+        "By default, fields that cargo-hack does not strictly need (e.g., `publish` and \
+         `rust_version`) are ignored with a warning if their shapes are unexpected, e.g., because \
+         of a newer cargo.",
+    ]),
+    ("", "--ignore-minimum-version", "", "Do not check the minimum version of cargo-hack required by the workspace", &[
+        "By default, cargo-hack fails if it is older than `minimum-version` in \
+         `[workspace.metadata.cargo-hack]` of the root manifest of the workspace.",
+    ]),
+    ("", "--check-additivity", "", "Check that features are additive", &[
+        "Reports feature sets that fail while a superset of them passes (likely a missing \
+         dependency between features), and features that cargo enabled although the requested \
+         features do not imply them.",
+        "This passes --message-format=json-render-diagnostics to cargo to get the features \
+         actually enabled, and stdout of cargo is captured.",
+        "Consider using this flag together with --keep-going flag.",
+        "This flag can only be used together with either --each-feature flag or \
+         --feature-powerset flag, and with check or build subcommand.",
+    ]),
+    ("", "--ensure-targets-buildable", "", "Fail if a target with required-features is not built by any feature combination", &[
+        "Cargo silently skips targets (e.g., binaries) whose required-features are not \
+         enabled. With this flag, cargo-hack checks before running that each such target is \
+         built by at least one of the feature combinations, e.g., to catch excluding the feature \
+         a binary needs.",
+        "If --verbose is used, the feature combinations that build each target are shown.",
+        "This flag can only be used together with either --each-feature flag or \
+         --feature-powerset flag.",
+    ]),
+    ("", "--rustdoc-all-features-check", "", "Check that each feature documents some items", &[
+        "Builds the documentation of each package with each feature alone, with \
+         `--cfg docsrs -D warnings` in RUSTDOCFLAGS, and counts the public items in the generated \
+         documentation. Reports features that document no more items than the build without \
+         features (the items gated by the feature are likely missing from the documentation), \
+         and features with which the documentation fails to build while it builds without \
+         features (e.g., because of broken intra-doc links).",
+        "Items are counted from `all.html` of the documentation, or from the JSON output of \
+         rustdoc if --doc-json is used. --no-deps is passed to cargo if it is not specified.",
+        "Consider using this flag together with --keep-going flag.",
+        "This flag can only be used together with --each-feature flag and doc subcommand.",
+    ]),
+    ("", "--doc-json", "", "Count documented items from the JSON output of rustdoc", &[
+        "This passes `-Z unstable-options --output-format json` to rustdoc, so it requires a \
+         nightly toolchain.",
+        "This flag can only be used together with --rustdoc-all-features-check flag.",
+    ]),
+    ("", "--print-command-list", "", "Print commands without run (Unstable)", &[
+        "When used together with --version-range, this does not install toolchains or \
+         generate lockfiles; the toolchain installation commands are printed as comments \
+         instead.",
+    ]),
+    ("", "--emit-execution-plan", "<PATH>", "Write the execution plan to the specified file as JSON without running commands (Unstable)", &[
+        "The plan contains `mutations`, the manifests that cargo-hack would modify before the \
+         runs (e.g., by --no-dev-deps or --no-private) with their new contents, `runs`, the \
+         commands with their environment variables, working directory, and toolchain, and \
+         `restore`, the original contents of the files to restore after the runs, including \
+         Cargo.lock, which cargo may update.",
+        "The contents are included inline with their FNV-1a 64-bit hashes. Like \
+         --print-command-list, no files in the workspace are modified and no toolchains are \
+         installed.",
+        "This flag cannot be used together with --print-command-list, --bisect-features, or \
+         --porcelain.",
+    ]),
+    ("", "--print-toolchain-requirements", "", "Print the toolchains, targets, and components required by the runs as JSON without running commands (Unstable)", &[
+        "The output contains `toolchains`, the toolchains of --version-range (or \
+         --rust-version) that the runs use, each with `targets`, the targets to install, and \
+         `components`, the components that the subcommand needs (e.g., clippy for `cargo \
+         clippy`). Without --version-range, the runs use the toolchain cargo-hack is run with, \
+         whose `toolchain` is null.",
+        "Like --print-command-list, no files in the workspace are modified and no toolchains are \
+         installed. The runs can then be checked against the provisioned toolchains with \
+         --no-install.",
+        "This flag cannot be used together with --print-command-list, --emit-execution-plan, \
+         --bisect-features, or --no-install.",
+    ]),
+    ("", "--generate-feature-docs", "<PATH>", "Write a Markdown table of the features of each selected package to the specified file", &[
+        "Each table lists the features of the package, whether they are enabled by default, and \
+         the features and optional dependencies they enable, with an empty Description column \
+         to be filled in by hand. When the file is regenerated, the descriptions of the features \
+         that still exist are kept, and the rest of the file is overwritten.",
+        "No cargo commands are run. This flag may not be used together with a subcommand.",
+    ]),
     ("", "--no-manifest-path", "", "Do not pass --manifest-path option to cargo (Unstable)", &[]),
+    (
+        "",
+        "--chdir-instead-of-manifest-path",
+        "",
+        "Run cargo in the directory of each package instead of passing --manifest-path option to \
+         cargo (Unstable)",
+        &[
+            "This is useful for cargo subcommands that do not support --manifest-path flag.",
+            "This flag cannot be used together with --no-manifest-path flag.",
+        ],
+    ),
+    ("", "--skip-flag-validation", "", "Do not validate flags propagated to cargo", &[
+        "By default, the long flags propagated to cargo are checked against the output of \
+         `cargo <SUBCOMMAND> --help` (of the oldest toolchain when --version-range is used) \
+         before running commands, and unknown flags are reported as errors.",
+        "If the help refers to the help of another subcommand (e.g., `cargo clippy --help` \
+         refers to `cargo check --help`), the flags listed there are also accepted. If the help \
+         does not list the usual flags of cargo (`--manifest-path` and `--features`), unknown \
+         flags are only warned.",
+        "This is useful for cargo wrappers whose help does not list all accepted flags.",
+    ]),
+    ("", "--no-env-config", "", "Do not read options from CARGO_HACK_* environment variables", &[
+        "By default, each option can also be specified by an environment variable named \
+         `CARGO_HACK_<OPTION>`, where <OPTION> is the name of the option in uppercase with `-` \
+         replaced by `_` (e.g., `CARGO_HACK_EACH_FEATURE=true` for --each-feature, and \
+         `CARGO_HACK_EXCLUDE_FEATURES=a,b` for --exclude-features a,b).",
+        "Options specified on the command line take precedence over the environment variables. \
+         The variables for flags accept 1, true, yes, 0, false, or no.",
+    ]),
     ("-v", "--verbose", "", "Use verbose output", &[]),
+    ("", "--trace-file", "<PATH>", "Write a log of the decisions of cargo-hack to the file", &[
+        "The log contains the parsed arguments, a summary of the metadata, the classification \
+         and filtering of the features of each package, the construction of the plan, the \
+         changes to the manifests, and every command with its exit status, regardless of \
+         --verbose. This is useful to attach to bug reports.",
+        "The values of the environment variables set for the commands are redacted.",
+    ]),
     ("", "--color", "<WHEN>", "Coloring: auto, always, never", &[
         "This flag will be propagated to cargo.",
     ]),
-    ("-h", "--help", "", "Prints help information", &[]),
+    ("", "--generate-completion", "<SHELL>", "Print a completion script for the specified shell", &[
+        "Possible values are bash, zsh, fish, and powershell.",
+        "For example, `cargo hack --generate-completion bash > \
+         ~/.local/share/bash-completion/completions/cargo-hack`. The script completes both \
+         `cargo hack` and `cargo-hack`.",
+    ]),
+    ("-h", "--help", "", "Prints help information", &[
+        "After the subcommand, this flag is propagated to cargo to show the help of the \
+         subcommand. Use --help-hack or `cargo hack help` to show this help instead.",
+    ]),
+    ("", "--help-hack", "", "Prints help information, even after the subcommand", &[]),
     ("-V", "--version", "", "Prints version information", &[]),
 ];
 
@@ -926,6 +2737,9 @@ Some common cargo commands are (see all commands with --list):
     check       Analyze the current package and report errors, but don't build object files
     run         Run a binary or example of the local package
     test        Run the tests
+
+Commands provided by cargo-hack itself are:
+    restore     Restore the files modified by a cargo-hack that did not exit cleanly
 ",
         )
     }
@@ -978,6 +2792,134 @@ fn similar_flags(flag: &str, subcommand: Option<&str>) -> Result<()> {
     similar_arg(&Long(flag), subcommand, expected)
 }
 
+/// Flags of cargo-llvm-cov that only affect reports.
+const LLVM_COV_REPORT_FLAGS: &[&str] = &[
+    "--json",
+    "--lcov",
+    "--cobertura",
+    "--codecov",
+    "--text",
+    "--html",
+    "--open",
+    "--summary-only",
+    "--show-instantiations",
+    "--show-missing-lines",
+    "--include-build-script",
+];
+/// Options of cargo-llvm-cov that only affect reports and take a value.
+const LLVM_COV_REPORT_OPTIONS: &[&str] = &[
+    "--output-path",
+    "--output-dir",
+    "--failure-mode",
+    "--ignore-filename-regex",
+    "--fail-under-functions",
+    "--fail-under-lines",
+    "--fail-under-regions",
+    "--fail-uncovered-functions",
+    "--fail-uncovered-lines",
+    "--fail-uncovered-regions",
+];
+
+/// Parses the content of the file passed to --version-range-file.
+///
+/// The file contains either `versions`, the list of the versions to run on, or
+/// `start` and `end`, the (inclusive) bounds of the range, which are the same
+/// as those of --version-range when omitted. Versions are either `1.<minor>` or
+/// `stable`.
+///
+/// Returns the ranges, and whether they are the exact list of versions.
+fn parse_version_range_file(s: &str) -> Result<(Vec<VersionRange>, bool)> {
+    let doc: toml_edit::DocumentMut = s.parse()?;
+    let version = |key: &str, value: &toml_edit::Item| -> Result<MaybeVersion> {
+        let Some(value) = value.as_str() else {
+            bail!("`{key}` must be a string, but found `{}`", value.to_string().trim());
+        };
+        match value {
+            "stable" => Ok(MaybeVersion::Stable),
+            _ => match value.parse() {
+                Ok(version) => Ok(MaybeVersion::Version(version)),
+                Err(e) => bail!(
+                    "invalid version `{value}` in `{key}`; versions must be `1.<minor>` or \
+                     `stable`: {e}"
+                ),
+            },
+        }
+    };
+    for (key, _) in doc.iter() {
+        if !matches!(key, "versions" | "start" | "end") {
+            bail!("unknown key `{key}`; expected either `versions`, or `start` and `end`");
+        }
+    }
+    if let Some(versions) = doc.get("versions") {
+        if doc.contains_key("start") || doc.contains_key("end") {
+            bail!("`versions` may not be used together with `start` or `end`");
+        }
+        let Some(versions) = versions.as_array() else {
+            bail!("`versions` must be an array of strings");
+        };
+        if versions.is_empty() {
+            bail!("`versions` may not be empty");
+        }
+        let ranges = versions
+            .iter()
+            .map(|v| {
+                let v = version("versions", &toml_edit::Item::Value(v.clone()))?;
+                Ok(VersionRange { start_inclusive: v, end_inclusive: v })
+            })
+            .collect::<Result<_>>()?;
+        return Ok((ranges, true));
+    }
+    if !doc.contains_key("start") && !doc.contains_key("end") {
+        bail!("either `versions`, or `start` and `end` must be specified");
+    }
+    let start_inclusive = doc.get("start").map(|v| version("start", v)).transpose()?;
+    let end_inclusive = doc.get("end").map(|v| version("end", v)).transpose()?;
+    let range = VersionRange {
+        start_inclusive: start_inclusive.unwrap_or(MaybeVersion::Msrv),
+        end_inclusive: end_inclusive.unwrap_or(MaybeVersion::Stable),
+    };
+    Ok((vec![range], false))
+}
+
+/// Validates the arguments of llvm-cov subcommand, moves the report-related
+/// arguments out of `cargo_args`, and adds `--no-report` to `cargo_args`.
+///
+/// Returns the arguments for `cargo llvm-cov report`.
+fn split_llvm_cov_args(cargo_args: &mut Vec<String>) -> Result<Vec<String>> {
+    debug_assert_eq!(cargo_args[0], "llvm-cov");
+    // `cargo llvm-cov` without a subcommand is the same as `cargo llvm-cov test`.
+    let mut pos = 1;
+    match cargo_args.get(1).map(String::as_str) {
+        Some("test" | "run" | "nextest") => pos = 2,
+        Some(subcommand) if !subcommand.starts_with('-') => bail!(
+            "cargo-hack only supports test, run, and nextest subcommands of llvm-cov, but found \
+             `{subcommand}`"
+        ),
+        _ => {}
+    }
+
+    let mut report_args = vec![];
+    let mut args = cargo_args.drain(pos..).collect::<Vec<_>>().into_iter();
+    let mut run_args = vec![];
+    while let Some(arg) = args.next() {
+        let flag = arg.split_once('=').map_or(&*arg, |(flag, _)| flag);
+        if LLVM_COV_REPORT_OPTIONS.contains(&flag) {
+            let has_value = arg.contains('=');
+            report_args.push(arg);
+            if !has_value {
+                report_args.extend(args.next());
+            }
+        } else if LLVM_COV_REPORT_FLAGS.contains(&&*arg) {
+            report_args.push(arg);
+        } else if arg != "--no-report" {
+            run_args.push(arg);
+        }
+    }
+    cargo_args.push("--no-report".to_owned());
+    cargo_args.extend(run_args);
+    Ok(report_args)
+}
+
 #[cold]
 #[inline(never)]
 fn mini_usage(msg: &str) -> Result<()> {
@@ -1046,6 +2988,20 @@ For more information try --help
     )
 }
 
+#[cold]
+#[inline(never)]
+fn dash_value(flag: &str, val: &str) -> Result<()> {
+    bail!(
+        "\
+The value '{val}' for '{flag}' starts with '-', which is likely a missing value
+
+If this is intended, use '{flag}={val}' instead
+
+For more information try --help
+",
+    )
+}
+
 /// `flag` requires one of `requires`.
 #[cold]
 #[inline(never)]
@@ -1073,3 +3029,531 @@ fn requires(flag: &str, requires: &[&str]) -> Result<()> {
 fn conflicts(a: &str, b: &str) -> Result<()> {
     bail!("{a} may not be used together with {b}");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        ffi::OsStr,
+    };
+
+    use anyhow::Result;
+
+    use super::{optional_deps_hint, parse_version_range_file, Args, HELP};
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        let args = ["cargo", "hack"].iter().chain(args);
+        Ok(Args::parse_from(OsStr::new("cargo"), args, &BTreeMap::new())?.unwrap())
+    }
+
+    #[test]
+    fn values() {
+        type Field = fn(&Args) -> String;
+        let package: Field = |args| format!("{:?}", args.package);
+        let features: Field = |args| format!("{:?}", args.features);
+        let exclude_features: Field = |args| format!("{:?}", args.exclude_features);
+        let group_features: Field = |args| format!("{:?}", args.group_features);
+        let optional_deps: Field = |args| format!("{:?}", args.optional_deps);
+        let depth: Field = |args| format!("{:?}", args.depth);
+        let max_group_combo: Field = |args| format!("{:?}", args.max_group_combo);
+        let cfg_matrix: Field = |args| format!("{:?}", args.cfg_matrix);
+        let llvm_cov_report_args: Field = |args| format!("{:?}", args.llvm_cov_report_args);
+        let target: Field = |args| format!("{:?}", args.target);
+        let leading_args: Field = |args| format!("{:?}", args.leading_args);
+        let exclude_all_features: Field = |args| {
+            format!("{} {:?}", args.exclude_all_features, args.exclude_all_features_packages)
+        };
+
+        let cases: &[(&[&str], Field, &str)] = &[
+            // `--flag value`, `--flag=value`, and `-fvalue` forms.
+            (&["check", "--package", "a"], package, r#"["a"]"#),
+            (&["check", "--package=a"], package, r#"["a"]"#),
+            (&["check", "-p", "a"], package, r#"["a"]"#),
+            (&["check", "-pa"], package, r#"["a"]"#),
+            (&["check", "-p=a"], package, r#"["a"]"#),
+            (&["check", "--feature-powerset", "--depth", "2"], depth, "Some(2)"),
+            (&["check", "--feature-powerset", "--depth=2"], depth, "Some(2)"),
+            (
+                &[
+                    "check",
+                    "--feature-powerset",
+                    "--group-features",
+                    "a,b",
+                    "--max-group-combo",
+                    "2",
+                ],
+                max_group_combo,
+                "Some(2)",
+            ),
+            (
+                &["check", "--feature-powerset", "--group-features", "a,b"],
+                group_features,
+                "[[a,b]]",
+            ),
+            (&["check", "--feature-powerset", "--group-features=a,b"], group_features, "[[a,b]]"),
+            (&["check", "--feature-powerset", "--group-features=_a,b"], group_features, "[[_a,b]]"),
+            (&["check", "--features", "a b"], features, r#"["a", "b"]"#),
+            (&["check", "--features='a,b'"], features, r#"["a", "b"]"#),
+            (&["check", "-Fa,b"], features, r#"["a", "b"]"#),
+            // `=`-joined values of passthrough flags are kept as is.
+            (
+                &["check", "--message-format=short"],
+                leading_args,
+                r#"["check", "--message-format=short"]"#,
+            ),
+            (
+                &["check", "--message-format", "short"],
+                leading_args,
+                r#"["check", "--message-format", "short"]"#,
+            ),
+            // --optional-deps takes an optional value.
+            (&["check", "--feature-powerset", "--optional-deps"], optional_deps, "Some([])"),
+            // (`--optional-deps=` means no optional dependencies.)
+            (&["check", "--feature-powerset", "--optional-deps="], optional_deps, r#"Some([""])"#),
+            // ...and explicitly terminates the list.
+            (
+                &["--feature-powerset", "--optional-deps=", "check", "--features", "a"],
+                optional_deps,
+                r#"Some([""])"#,
+            ),
+            // The next argument that is not a flag is the value, even if it is
+            // also specified by --features or looks like a subcommand.
+            (
+                &["check", "--feature-powerset", "--optional-deps", "a", "--features", "a"],
+                optional_deps,
+                r#"Some(["a"])"#,
+            ),
+            (
+                &["check", "--feature-powerset", "--features", "a", "--optional-deps", "a"],
+                features,
+                r#"["a"]"#,
+            ),
+            (
+                &["--feature-powerset", "--optional-deps", "check", "build"],
+                optional_deps,
+                r#"Some(["check"])"#,
+            ),
+            (
+                &["check", "--feature-powerset", "--optional-deps", "a,b"],
+                optional_deps,
+                r#"Some(["a", "b"])"#,
+            ),
+            (
+                &["check", "--feature-powerset", "--optional-deps=a"],
+                optional_deps,
+                r#"Some(["a"])"#,
+            ),
+            (
+                &["check", "--feature-powerset", "--optional-deps", "--depth", "2"],
+                optional_deps,
+                "Some([])",
+            ),
+            (&["check", "--feature-powerset", "--optional-deps", "--depth", "2"], depth, "Some(2)"),
+            // --exclude-all-features takes an optional value only in `=`-joined form.
+            (
+                &["check", "--feature-powerset", "--exclude-all-features"],
+                exclude_all_features,
+                "true []",
+            ),
+            (
+                &[
+                    "check",
+                    "--feature-powerset",
+                    "--exclude-all-features=a",
+                    "--exclude-all-features=b",
+                ],
+                exclude_all_features,
+                r#"false ["a", "b"]"#,
+            ),
+            // Repeatable options accumulate.
+            (&["check", "-p", "a", "--package=b"], package, r#"["a", "b"]"#),
+            (&["check", "-F", "a", "--features", "b,c"], features, r#"["a", "b", "c"]"#),
+            (
+                &["check", "--feature-powerset", "--skip", "a", "--exclude-features=b"],
+                exclude_features,
+                r#"["a", "b"]"#,
+            ),
+            (
+                &["check", "--feature-powerset", "--group-features", "a,b", "--group-features=c,d"],
+                group_features,
+                "[[a,b], [c,d]]",
+            ),
+            // --cfg-matrix is deduplicated, but the order is kept.
+            (
+                &["check", "--cfg-matrix", "b,a", "--cfg-matrix=b", "--cfg-matrix", r#"c="x""#],
+                cfg_matrix,
+                r#"["b", "a", "c=\"x\""]"#,
+            ),
+            // Report-related arguments of llvm-cov are only passed to `cargo llvm-cov report`.
+            (
+                &["llvm-cov", "--lcov", "--output-path", "a", "--lib", "--", "--ignored"],
+                leading_args,
+                r#"["llvm-cov", "--no-report", "--lib"]"#,
+            ),
+            (
+                &["llvm-cov", "nextest", "--output-dir=a", "--html", "--no-report", "-p", "x"],
+                leading_args,
+                r#"["llvm-cov", "nextest", "--no-report"]"#,
+            ),
+            (
+                &["llvm-cov", "test", "--lcov", "--output-path", "a", "--each-feature"],
+                llvm_cov_report_args,
+                r#"Some(["--lcov", "--output-path", "a"])"#,
+            ),
+            (&["check", "--lcov"], llvm_cov_report_args, "None"),
+            // ...and --target is also deduplicated and sorted, like cargo does.
+            (&["check", "--target=y", "--target", "x", "--target=y"], target, r#"["x", "y"]"#),
+        ];
+        for (args, field, expected) in cases {
+            let res = parse(args).unwrap_or_else(|e| panic!("failed to parse {args:?}: {e:#}"));
+            assert_eq!(field(&res), *expected, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let cases: &[(&[&str], &str)] = &[
+            // Values starting with `-` must use `=`-joined form.
+            (&["check", "--package", "-a"], "The value '-a' for '--package' starts with '-'"),
+            (&["check", "-p", "-a"], "The value '-a' for '-p' starts with '-'"),
+            (
+                &["check", "--feature-powerset", "--group-features", "-a,b"],
+                "use '--group-features=-a,b' instead",
+            ),
+            // ...but feature names cannot start with `-`.
+            (
+                &["check", "--feature-powerset", "--group-features=-a,b"],
+                "invalid feature name `-a` specified by --group-features",
+            ),
+            (
+                &["check", "--feature-powerset", "--depth", "--keep-going"],
+                "The value '--keep-going' for '--depth' starts with '-'",
+            ),
+            (
+                &["check", "--each-feature", "--optional-deps", "-"],
+                "The value '-' for '--optional-deps' starts with '-'",
+            ),
+            (&["check", "--manifest-path"], "missing argument for option '--manifest-path'"),
+            (
+                &["check", "--show-output-lines", "10"],
+                "--show-output-lines can only be used together with --quiet-success",
+            ),
+            (
+                &["check", "--exactly-one-of", "a,b"],
+                "--exactly-one-of can only be used together with --feature-powerset",
+            ),
+            (
+                &["check", "--max-group-combo", "2", "--group-features", "a,b"],
+                "--max-group-combo can only be used together with --feature-powerset",
+            ),
+            (
+                &["check", "--feature-powerset", "--max-group-combo", "2"],
+                "--max-group-combo can only be used together with --group-features",
+            ),
+            (
+                &["check", "--no-wait"],
+                "--no-wait can only be used together with --no-dev-deps, --remove-dev-deps, or --no-private",
+            ),
+            (
+                &["check", "--feature-powerset", "--depth-counts-group-members"],
+                "--depth-counts-group-members can only be used together with --depth",
+            ),
+            (&["llvm-cov", "report"], "but found `report`"),
+            (
+                &["llvm-cov", "--version-range", "1.70..", "--each-feature"],
+                "--version-range may not be used together with llvm-cov subcommand",
+            ),
+            (&["llvm-cov", "--clean-per-run"], "--clean-per-run may not be used together with"),
+            (&["--and-then", "check"], "--and-then requires a subcommand"),
+            (&["llvm-cov", "--and-then", "check"], "--and-then may not be used together with llvm-cov"),
+            (&["check", "--and-then=--all-targets"], "must start with a subcommand"),
+            (
+                &["check", "--bisect-features", "--and-then", "build"],
+                "--bisect-features may not be used together with --and-then",
+            ),
+            (
+                &["check", "--check-additivity", "--feature-powerset", "--and-then", "doc"],
+                "--check-additivity may only be used together with check or build subcommand",
+            ),
+            (
+                &["check", "--verify-lockfile-per-version"],
+                "--verify-lockfile-per-version can only be used together with --version-range",
+            ),
+            (
+                &["check", "--strip-unsupported-profile-keys"],
+                "--strip-unsupported-profile-keys can only be used together with either \
+                 --version-range or --rust-version",
+            ),
+            (
+                &["check", "--compat-strip-lints"],
+                "--compat-strip-lints can only be used together with either --version-range or \
+                 --rust-version",
+            ),
+            (
+                &["check", "--version-range=1.70..", "--verify-lockfile-per-version", "--locked"],
+                "--verify-lockfile-per-version may not be used together with --locked",
+            ),
+            (
+                &["check", "--fail-on-skipped=unknown-feature,foo"],
+                "argument for --fail-on-skipped must be a comma-separated list of unknown-feature, \
+                 group-skipped, msrv-out-of-range, package-empty-plan, private-package, \
+                 excluded-package, but found `foo`",
+            ),
+            (
+                &["check", "--bisect-features", "--budget=1h"],
+                "--bisect-features may not be used together with --budget",
+            ),
+            (&["check", "--budget=0"], "duration must be greater than zero"),
+            (&["check", "--version-order=newest-first"], "--version-order can only be used together with --version-range"),
+            (
+                &["check", "--version-range=1.70..", "--version-order", "newest"],
+                "argument for --version-order must be newest-first or oldest-first, but found \
+                 `newest`",
+            ),
+            (
+                &["check", "--lockfile-strategy=keep"],
+                "--lockfile-strategy can only be used together with either --version-range or \
+                 --rust-version",
+            ),
+            (
+                &["check", "--version-range=1.70..", "--lockfile-strategy", "never"],
+                "argument for --lockfile-strategy must be auto, keep, generate-per-version, or \
+                 generate-once, but found `never`",
+            ),
+            (
+                &["check", "--version-range=1.70..", "--lockfile-strategy=generate-once", "--locked"],
+                "--lockfile-strategy=generate-once may not be used together with --locked",
+            ),
+            (
+                &["check", "--check-msrv-consistency"],
+                "--check-msrv-consistency can only be used together with either --version-range or --rust-version",
+            ),
+            (
+                &["check", "--cfg-matrix", "a,b c"],
+                "cfg `b c` specified by --cfg-matrix may not contain whitespace",
+            ),
+            // Non-repeatable options and flags reject repeats.
+            (
+                &["check", "--depth=1", "--depth=2", "--feature-powerset"],
+                "The argument '--depth' was provided more than once",
+            ),
+            (
+                &["check", "--manifest-path=a", "--manifest-path", "b"],
+                "The argument '--manifest-path' was provided more than once",
+            ),
+            (
+                &["check", "--each-feature", "--optional-deps", "--optional-deps=a"],
+                "The argument '--optional-deps' was provided more than once",
+            ),
+            (
+                &["check", "--each-feature", "--exclude-all-features", "--exclude-all-features"],
+                "The argument '--exclude-all-features' was provided more than once",
+            ),
+            (
+                &["check", "--workspace", "--all"],
+                "The argument '--all' was provided more than once",
+            ),
+            (
+                &["check", "--no-default-features", "--no-default-features"],
+                "The argument '--no-default-features' was provided more than once",
+            ),
+            (
+                &["check", "--all-features", "--all-features"],
+                "The argument '--all-features' was provided more than once",
+            ),
+        ];
+        for (args, expected) in cases {
+            let Err(e) = parse(args) else { panic!("expected error for {args:?}") };
+            let e = format!("{e:#}");
+            assert!(e.contains(expected), "expected `{expected}` for {args:?}, got `{e}`");
+        }
+    }
+
+    #[test]
+    fn uncommon_feature_names() {
+        let parse = |args: &[&str]| {
+            let args = ["cargo", "hack", "check"].iter().chain(args);
+            Args::parse_from(OsStr::new("cargo"), args, &BTreeMap::new()).map(Option::unwrap)
+        };
+        let error = |args: &[&str]| {
+            let Err(e) = parse(args) else { panic!("expected error for {args:?}") };
+            format!("{e:#}")
+        };
+        let args = parse(&[
+            "--features",
+            "1.0,dep/c++",
+            "--exclude-features",
+            "a.b c+d",
+            "--group-features",
+            "1.0,c++",
+            "--feature-powerset",
+        ])
+        .unwrap();
+        assert_eq!(args.features, ["1.0", "dep/c++"]);
+        // --features are excluded from the powerset.
+        assert_eq!(args.exclude_features, ["a.b", "c+d", "1.0", "dep/c++"]);
+        assert_eq!(format!("{:?}", args.group_features), "[[1.0,c++]]");
+        assert!(args.group_features[0].matches("c++"));
+        let args = parse(&[
+            "--each-feature",
+            "--features",
+            "dep?/v1.2",
+            "--include-features",
+            "@defaults,x.y",
+        ]);
+        assert_eq!(format!("{:?}", args.unwrap().include_features), "[@defaults, x.y]");
+
+        assert_eq!(
+            error(&["--features", "a;b"]),
+            "invalid feature name `a;b` specified by --features: the character `;` is not \
+             allowed; feature names may only contain letters, digits, `_`, `-`, `+`, and `.`"
+        );
+        assert_eq!(
+            error(&["--feature-powerset", "--group-features", "a,.b"]),
+            "invalid feature name `.b` specified by --group-features: the first character `.` is \
+             not allowed; feature names must start with a letter, a digit, or `_`"
+        );
+        assert!(error(&["--features", "dep/+a"]).starts_with("invalid feature name `dep/+a`"));
+        assert!(error(&["--feature-powerset", "--why", "a:b"]).contains("specified by --why"));
+    }
+
+    #[test]
+    fn env_config() {
+        let parse = |args: &[&str], env: &[(&str, &str)]| {
+            let args = ["cargo", "hack"].iter().chain(args);
+            let env = env.iter().map(|&(k, v)| (k.to_owned(), v.into())).collect();
+            Args::parse_from(OsStr::new("cargo"), args, &env).map(Option::unwrap)
+        };
+        let error = |args: &[&str], env: &[(&str, &str)]| {
+            let Err(e) = parse(args, env) else { panic!("expected error for {args:?}") };
+            format!("{e:#}")
+        };
+        let env = [
+            ("CARGO_HACK_FEATURE_POWERSET", "yes"),
+            ("CARGO_HACK_EXCLUDE_FEATURES", "a,b"),
+            ("CARGO_HACK_GROUP_FEATURES", "c d"),
+            ("CARGO_HACK_KEEP_GOING", "false"),
+        ];
+        let args = parse(&["check", "--depth", "2"], &env).unwrap();
+        assert!(args.feature_powerset);
+        assert_eq!(args.depth, Some(2));
+        assert_eq!(args.exclude_features, ["a", "b"]);
+        assert_eq!(format!("{:?}", args.group_features), "[[c,d]]");
+        assert!(!args.keep_going);
+
+        // The command line takes precedence.
+        let args = parse(&["check", "--skip", "c", "--group-features=e,f"], &env).unwrap();
+        assert_eq!(args.exclude_features, ["c"]);
+        assert_eq!(format!("{:?}", args.group_features), "[[e,f]]");
+
+        let args = parse(&["check", "--no-env-config", "--each-feature"], &env).unwrap();
+        assert!(!args.feature_powerset);
+        assert!(args.exclude_features.is_empty());
+
+        // Errors are prefixed with the variable name.
+        assert_eq!(
+            error(&["check"], &[("CARGO_HACK_DEPTH", "x")]),
+            "CARGO_HACK_DEPTH: --depth can only be used together with --feature-powerset"
+        );
+        assert_eq!(
+            error(&["check", "--feature-powerset"], &[("CARGO_HACK_DEPTH", "x")]),
+            "CARGO_HACK_DEPTH: failed to parse argument for --depth: invalid digit found in string"
+        );
+        assert_eq!(
+            error(&["check", "--each-feature"], &[("CARGO_HACK_FEATURE_POWERSET", "1")]),
+            "CARGO_HACK_FEATURE_POWERSET: --each-feature may not be used together with \
+             --feature-powerset"
+        );
+        assert_eq!(
+            error(&["check"], &[("CARGO_HACK_EACH_FEATURE", "2")]),
+            "CARGO_HACK_EACH_FEATURE: expected 1, true, yes, 0, false, or no, but found `2`"
+        );
+    }
+
+    #[test]
+    fn optional_deps_hints() {
+        let strings = |v: &[&str]| v.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+        let hint = |val, subcommand, features: &[&str], exclude_features: &[&str]| {
+            optional_deps_hint(val, subcommand, &strings(features), &strings(exclude_features))
+        };
+
+        // `--optional-deps serde --features serde`
+        let h = hint("serde", Some("check"), &["serde"], &[]).unwrap();
+        assert!(h.starts_with("`serde` is parsed as the value of --optional-deps"), "{h}");
+        assert!(h.contains("`serde` is also specified by --features"), "{h}");
+        assert!(h.contains("`--optional-deps=serde`"), "{h}");
+        // `--optional-deps a,serde --exclude-features serde`
+        let h = hint("a,serde", Some("check"), &[], &["serde"]).unwrap();
+        assert!(h.contains("`serde` is also specified by --exclude-features"), "{h}");
+        // `cargo hack --each-feature --optional-deps check`
+        let h = hint("check", None, &[], &[]).unwrap();
+        assert!(h.contains("not as the subcommand; to use all optional dependencies"), "{h}");
+        // Not ambiguous.
+        assert_eq!(hint("serde", Some("check"), &["std"], &["tokio"]), None);
+        assert_eq!(hint("check", Some("build"), &[], &[]), None);
+    }
+
+    #[test]
+    fn version_range_file() {
+        let ranges = |s: &str| {
+            parse_version_range_file(s).map(|(ranges, exact)| {
+                (ranges.iter().map(ToString::to_string).collect::<Vec<_>>(), exact)
+            })
+        };
+        assert_eq!(
+            ranges("versions = [\"1.70\", \"1.74.1\"]").unwrap(),
+            (vec!["1.70..=1.70".to_owned(), "1.74.1..=1.74.1".to_owned()], true)
+        );
+        assert_eq!(ranges("versions = [\"stable\"]").unwrap(), (vec!["..".to_owned()], true));
+        assert_eq!(ranges("start = \"1.70\"").unwrap(), (vec!["1.70..".to_owned()], false));
+        assert_eq!(
+            ranges("start = \"1.70\"\nend = \"1.74\"").unwrap(),
+            (vec!["1.70..=1.74".to_owned()], false)
+        );
+        assert_eq!(ranges("end = \"1.74\"").unwrap(), (vec!["..=1.74".to_owned()], false));
+
+        for (s, expected) in [
+            ("", "either `versions`, or `start` and `end` must be specified"),
+            ("versions = []", "`versions` may not be empty"),
+            ("versions = \"1.70\"", "`versions` must be an array of strings"),
+            ("versions = [1.70]", "`versions` must be a string, but found `1.70`"),
+            ("versions = [\"beta\"]", "invalid version `beta` in `versions`"),
+            ("start = \"1\"", "invalid version `1` in `start`"),
+            ("versions = [\"1.70\"]\nend = \"1.74\"", "may not be used together with `start`"),
+            ("step = 2", "unknown key `step`"),
+            ("versions = [", "expected"),
+        ] {
+            let Err(e) = parse_version_range_file(s) else { panic!("{s}") };
+            assert!(format!("{e:#}").contains(expected), "{s}: {e:#}");
+        }
+    }
+
+    #[test]
+    fn help_consistency() {
+        // The long flags matched in `Args::parse_args`.
+        let src = include_str!("cli.rs");
+        let src = &src[..src.find("\n#[cfg(test)]").unwrap()];
+        let mut parsed = BTreeSet::new();
+        for (pos, _) in src.match_indices("Long(\"") {
+            let pat = &src[pos + "Long(".len()..];
+            let pat = &pat[..pat.find(')').unwrap()];
+            parsed.extend(pat.split('|').map(|s| format!("--{}", s.trim().trim_matches('"'))));
+        }
+        // Flags of cargo that are also used by cargo-hack, and the experimental
+        // --include-deps-features, are not documented.
+        for flag in ["--no-default-features", "--all-features", "--include-deps-features"] {
+            assert!(parsed.remove(flag), "{flag}");
+        }
+        let help: BTreeSet<_> = HELP.iter().map(|&(_, long, ..)| long.to_owned()).collect();
+        assert_eq!(
+            parsed.difference(&help).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "parsed but not in HELP"
+        );
+        assert_eq!(
+            help.difference(&parsed).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "in HELP but not parsed"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --package-with-deps and --package-with-dependents.
+//
+// The specified packages are selected together with the workspace members
+// reachable from them in the resolved dependency graph: the members they
+// depend on (--package-with-deps), or the members that depend on them
+// (--package-with-dependents). Only normal and build dependencies are followed
+// unless --with-dev-deps-closure is used, since dev-dependencies do not affect
+// the build of the library.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    context::Context,
+    metadata::{DepKindInfo, PackageId},
+};
+
+/// Returns the packages selected by --package-with-deps and
+/// --package-with-dependents.
+pub(crate) fn packages(cx: &Context) -> Result<HashSet<&PackageId>> {
+    let members: HashSet<_> = cx.workspace_members().collect();
+    // The edges between workspace members, from dependents to dependencies.
+    let mut deps: HashMap<&PackageId, Vec<&PackageId>> = HashMap::new();
+    let mut dependents: HashMap<&PackageId, Vec<&PackageId>> = HashMap::new();
+    for (id, node) in &cx.metadata.resolve.nodes {
+        if !members.contains(id) {
+            continue;
+        }
+        for dep in &node.deps {
+            if !members.contains(&dep.pkg) || !follows(cx, &dep.dep_kinds) {
+                continue;
+            }
+            deps.entry(id).or_default().push(&dep.pkg);
+            dependents.entry(&dep.pkg).or_default().push(id);
+        }
+    }
+
+    let mut selected = HashSet::new();
+    for (flag, specs, edges) in [
+        ("--package-with-deps", &cx.package_with_deps, &deps),
+        ("--package-with-dependents", &cx.package_with_dependents, &dependents),
+    ] {
+        for spec in specs {
+            let Some(&root) = members.iter().find(|&&id| cx.packages(id).name == *spec) else {
+                bail!("package ID specification `{spec}` specified by {flag} matched no packages")
+            };
+            reachable(root, edges, &mut selected);
+        }
+    }
+    Ok(selected)
+}
+
+/// Returns `true` if the dependency of the kinds is followed.
+fn follows(cx: &Context, dep_kinds: &[DepKindInfo]) -> bool {
+    // The kinds are not available with cargo older than 1.41, so treat such
+    // dependencies as normal dependencies.
+    dep_kinds.is_empty()
+        || cx.with_dev_deps_closure
+        || dep_kinds.iter().any(|k| k.kind.as_deref() != Some("dev"))
+}
+
+/// Adds `root` and the nodes reachable from it to `visited`.
+fn reachable<'a>(
+    root: &'a PackageId,
+    edges: &HashMap<&'a PackageId, Vec<&'a PackageId>>,
+    visited: &mut HashSet<&'a PackageId>,
+) {
+    let mut queue = VecDeque::from([root]);
+    while let Some(id) = queue.pop_front() {
+        if visited.insert(id) {
+            queue.extend(edges.get(id).into_iter().flatten().copied());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --only-code-gated-features.
+//
+// A feature that only enables other features or optional dependencies (e.g.,
+// `full = ["a", "b"]` or `serde = ["dep:serde"]`) and is not referenced in the
+// code of the package has no code of its own, so running with it alone is the
+// same as running with the features it enables together. Such forwarding
+// features are removed from the features to iterate; they are still covered by
+// --all-features and by the combinations of the features they enable.
+//
+// The sources of the targets of the package are scanned for `feature = "<name>"`
+// (in `#[cfg]`, `cfg!`, `#[cfg_attr]`, etc.) and, in build scripts,
+// `CARGO_FEATURE_<NAME>`. The scan is textual, so a feature mentioned in a
+// comment is treated as referenced, which only keeps more features than needed.
+// Features that enable nothing are not forwarding features, so they are kept.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{context::Context, features::Feature, fs, metadata::PackageId};
+
+const FEATURE: &str = "feature";
+/// The prefix of the environment variables that cargo sets for the enabled
+/// features when running build scripts.
+const ENV_PREFIX: &str = "CARGO_FEATURE_";
+
+/// Removes the forwarding features that are not referenced in the code of the
+/// package from `features`.
+pub(crate) fn retain(cx: &Context, id: &PackageId, features: &mut Vec<&Feature>) -> Result<()> {
+    let package = cx.packages(id);
+    let mut referenced = References::default();
+    for path in sources(cx, id)? {
+        referenced.scan(&fs::read_to_string(&path)?);
+    }
+    let mut removed = vec![];
+    features.retain(|f| {
+        let Feature::Normal { name } = f else { return true };
+        let forwarding = package.features.get(name).is_some_and(|enables| !enables.is_empty());
+        if forwarding && !referenced.contains(name) {
+            removed.push(format!("`{name}`"));
+            return false;
+        }
+        true
+    });
+    if !removed.is_empty() {
+        info!(
+            "skipped iterating features {} of package `{}`, which are not referenced in the code \
+             and only enable other features or dependencies (--only-code-gated-features)",
+            removed.join(", "),
+            package.name
+        );
+    }
+    Ok(())
+}
+
+/// Returns the source files of the targets of the package.
+fn sources(cx: &Context, id: &PackageId) -> Result<BTreeSet<PathBuf>> {
+    let package = cx.packages(id);
+    let root = package.manifest_path.parent().unwrap();
+    let mut files = BTreeSet::new();
+    let mut dirs = BTreeSet::new();
+    for target in &package.targets {
+        let dir = target.src_path.parent().unwrap_or(root);
+        // Walking the package root would also walk the target directory and
+        // other packages, so only the file itself is scanned in that case
+        // (e.g., build.rs).
+        if dir == root || target.kind.iter().any(|k| k == "custom-build") {
+            files.insert(target.src_path.clone());
+        } else {
+            dirs.insert(dir.to_owned());
+        }
+    }
+    for dir in &dirs {
+        // Nested directories are walked with their parent.
+        if !dirs.iter().any(|d| d != dir && dir.starts_with(d)) {
+            walk(dir, &mut files)
+                .with_context(|| format!("failed to read directory `{}`", dir.display()))?;
+        }
+    }
+    Ok(files)
+}
+
+/// Collects the Rust source files in `dir` recursively.
+fn walk(dir: &Path, files: &mut BTreeSet<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            files.insert(path);
+        }
+    }
+    Ok(())
+}
+
+/// The features referenced in the code.
+#[derive(Default)]
+struct References {
+    /// The names in `feature = "<name>"`.
+    names: BTreeSet<String>,
+    /// The names of `CARGO_FEATURE_<NAME>` environment variables.
+    env: BTreeSet<String>,
+}
+
+impl References {
+    fn scan(&mut self, s: &str) {
+        for (pos, _) in s.match_indices(FEATURE) {
+            // Exclude e.g. `target_feature = "sse2"`.
+            if s[..pos].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                continue;
+            }
+            let name = s[pos + FEATURE.len()..]
+                .trim_start()
+                .strip_prefix('=')
+                .map(str::trim_start)
+                .and_then(|rest| rest.strip_prefix('"'))
+                .and_then(|rest| rest.split_once('"'));
+            if let Some((name, _)) = name {
+                self.names.insert(name.to_owned());
+            }
+        }
+        for (pos, _) in s.match_indices(ENV_PREFIX) {
+            let rest = &s[pos + ENV_PREFIX.len()..];
+            let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+            self.env.insert(rest[..end].to_owned());
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        // Cargo sets CARGO_FEATURE_<name> with the name uppercased and `-`
+        // replaced with `_`.
+        self.names.contains(name) || self.env.contains(&name.to_uppercase().replace('-', "_"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::References;
+
+    #[test]
+    fn references() {
+        let mut r = References::default();
+        r.scan(
+            r#"
+            #[cfg(feature = "a")]
+            mod a;
+            #[cfg(all(feature="b", not(feature =  "c")))]
+            mod b;
+            #[cfg_attr(feature = "d-e", derive(Debug))]
+            struct S;
+            fn f() -> bool { cfg!(feature = "f") }
+            #[cfg(target_feature = "sse2")]
+            fn g() {}
+            fn h() { std::env::var_os("CARGO_FEATURE_STD_X").is_some(); }
+            "#,
+        );
+        for name in ["a", "b", "c", "d-e", "f", "std-x", "std_x"] {
+            assert!(r.contains(name), "{name}");
+        }
+        for name in ["sse2", "std", "g"] {
+            assert!(!r.contains(name), "{name}");
+        }
+    }
+}
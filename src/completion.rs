@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --generate-completion.
+//
+// The completion scripts are generated from the table of options used for
+// --help, so new options are completed without changes here. Only the values
+// of options that are not free-form (e.g., --log-group) are listed here.
+
+use std::{fmt::Write as _, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+
+use crate::cli::{HelpText, HELP};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            "powershell" => Ok(Self::Powershell),
+            other => bail!(
+                "argument for --generate-completion must be bash, zsh, fish, or powershell, but \
+                 found `{other}`"
+            ),
+        }
+    }
+}
+
+/// The kind of the value of an option.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Value {
+    /// The option does not take a value.
+    None,
+    /// The value is free-form.
+    Any,
+    /// The value is a path to a file.
+    File,
+    /// The value is a path to a directory.
+    Dir,
+    /// The value is one of the listed values.
+    OneOf(&'static [&'static str]),
+}
+
+fn value(&(_, long, value_name, ..): &HelpText<'_>) -> Value {
+    match long {
+        "--color" => Value::OneOf(&["auto", "always", "never"]),
+        "--log-group" => Value::OneOf(&["none", "github-actions"]),
+        "--lockfile-strategy" => {
+            Value::OneOf(&["auto", "keep", "generate-per-version", "generate-once"])
+        }
+        "--generate-completion" => Value::OneOf(&["bash", "zsh", "fish", "powershell"]),
+        _ => match value_name {
+            "" => Value::None,
+            "<PATH>" => Value::File,
+            "<DIR>" => Value::Dir,
+            _ => Value::Any,
+        },
+    }
+}
+
+/// Returns the completion script for the shell.
+pub(crate) fn generate(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+        Shell::Powershell => powershell(),
+    }
+}
+
+fn bash() -> String {
+    let mut flags = vec![];
+    let mut cases = String::new();
+    for help in HELP {
+        let (short, long, ..) = *help;
+        flags.extend([short, long].into_iter().filter(|f| !f.is_empty()));
+        let pattern = if short.is_empty() { long.to_owned() } else { format!("{short}|{long}") };
+        let reply = match value(help) {
+            Value::None => continue,
+            Value::Any => "COMPREPLY=()".to_owned(),
+            Value::File => "COMPREPLY=($(compgen -f -- \"${cur}\"))".to_owned(),
+            Value::Dir => "COMPREPLY=($(compgen -d -- \"${cur}\"))".to_owned(),
+            Value::OneOf(values) => {
+                format!("COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))", values.join(" "))
+            }
+        };
+        let _ = write!(
+            cases,
+            "\n        {pattern})\n            {reply}\n            return 0\n            ;;"
+        );
+    }
+    format!(
+        r#"# bash completion for cargo-hack
+
+_cargo_hack() {{
+    local cur prev
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "${{prev}}" in{cases}
+    esac
+    if [[ "${{cur}}" == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags}" -- "${{cur}}"))
+    fi
+}}
+
+# `cargo hack` is completed by wrapping the completion of cargo registered
+# before this script is loaded, if any.
+if [[ -z "${{_cargo_hack_cargo_completion+x}}" ]]; then
+    _cargo_hack_cargo_completion=""
+    if [[ "$(complete -p cargo 2>/dev/null)" =~ -F\ ([^ ]+) ]]; then
+        _cargo_hack_cargo_completion="${{BASH_REMATCH[1]}}"
+    fi
+fi
+_cargo_hack_cargo() {{
+    if [[ "${{COMP_WORDS[1]}}" == hack ]] && [[ "${{COMP_CWORD}}" -gt 1 ]]; then
+        _cargo_hack
+    elif [[ -n "${{_cargo_hack_cargo_completion}}" ]]; then
+        "${{_cargo_hack_cargo_completion}}" "$@"
+    fi
+}}
+
+complete -F _cargo_hack -o bashdefault -o default cargo-hack
+complete -F _cargo_hack_cargo -o bashdefault -o default cargo
+"#,
+        flags = flags.join(" ")
+    )
+}
+
+fn zsh() -> String {
+    // Escapes the characters that have special meaning in the option
+    // descriptions of `_arguments`, inside a single-quoted string.
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('\'', "'\\''")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace(':', "\\:")
+    }
+
+    let mut specs = String::new();
+    for help in HELP {
+        let (short, long, value_name, desc, _) = *help;
+        let action = match value(help) {
+            Value::None => String::new(),
+            Value::Any => format!(":{}: ", escape(value_name)),
+            Value::File => format!(":{}:_files", escape(value_name)),
+            Value::Dir => format!(":{}:_files -/", escape(value_name)),
+            Value::OneOf(values) => format!(":{}:({})", escape(value_name), values.join(" ")),
+        };
+        let takes_value = !action.is_empty();
+        for (flag, suffix) in [(short, "+"), (long, "=")] {
+            if flag.is_empty() {
+                continue;
+            }
+            let suffix = if takes_value { suffix } else { "" };
+            let _ = write!(specs, "\n        '*{flag}{suffix}[{}]{action}' \\", escape(desc));
+        }
+    }
+    format!(
+        r#"#compdef cargo-hack
+
+# zsh completion for cargo-hack
+#
+# The completion of cargo calls `_cargo-hack` to complete `cargo hack`.
+
+_cargo-hack() {{
+    _arguments -s -S \{specs}
+        '*::arg:_default'
+}}
+
+if [[ "${{funcstack[1]}}" == "_cargo-hack" ]]; then
+    _cargo-hack "$@"
+else
+    compdef _cargo-hack cargo-hack
+fi
+"#
+    )
+}
+
+fn fish() -> String {
+    fn quote(s: &str) -> String {
+        format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+
+    let mut out = String::from(
+        "# fish completion for cargo-hack\n\n\
+         function __cargo_hack_needs_completion\n    \
+         set -l cmd (commandline -opc)\n    \
+         test \"$cmd[1]\" = cargo-hack; or begin; test \"$cmd[1]\" = cargo; and contains -- hack $cmd; end\n\
+         end\n",
+    );
+    for cmd in ["cargo", "cargo-hack"] {
+        out.push('\n');
+        for help in HELP {
+            let (short, long, _, desc, _) = *help;
+            let _ = write!(out, "complete -c {cmd} -n __cargo_hack_needs_completion");
+            if !short.is_empty() {
+                let _ = write!(out, " -s {}", &short[1..]);
+            }
+            let _ = write!(out, " -l {}", &long[2..]);
+            match value(help) {
+                Value::None => {}
+                Value::Any => out.push_str(" -x"),
+                Value::File => out.push_str(" -r -F"),
+                Value::Dir => out.push_str(" -x -a '(__fish_complete_directories)'"),
+                Value::OneOf(values) => {
+                    let _ = write!(out, " -x -a {}", quote(&values.join(" ")));
+                }
+            }
+            let _ = writeln!(out, " -d {}", quote(desc));
+        }
+    }
+    out
+}
+
+fn powershell() -> String {
+    fn quote(s: &str) -> String {
+        format!("'{}'", s.replace('\'', "''"))
+    }
+
+    let mut options = String::new();
+    let mut values = String::new();
+    for help in HELP {
+        let (short, long, _, desc, _) = *help;
+        for flag in [short, long] {
+            if !flag.is_empty() {
+                let _ = write!(
+                    options,
+                    "\n        [CompletionResult]::new({0}, {0}, [CompletionResultType]::ParameterName, {1})",
+                    quote(flag),
+                    quote(desc)
+                );
+            }
+        }
+        let pattern = [short, long]
+            .into_iter()
+            .filter(|f| !f.is_empty())
+            .map(quote)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let reply = match value(help) {
+            Value::None => continue,
+            // Fall back to the default (path) completion of PowerShell.
+            Value::Any | Value::File | Value::Dir => "return".to_owned(),
+            Value::OneOf(list) => format!(
+                "$candidates = @({}) | ForEach-Object {{ [CompletionResult]::new($_, $_, \
+                 [CompletionResultType]::ParameterValue, $_) }}",
+                list.iter().map(|v| quote(v)).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        let _ = write!(values, "\n        {{ $_ -in @({pattern}) }} {{ {reply} }}");
+    }
+    format!(
+        r#"# PowerShell completion for cargo-hack
+
+using namespace System.Management.Automation
+
+Register-ArgumentCompleter -Native -CommandName @('cargo', 'cargo-hack') -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $elements = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    if ($elements[0] -notlike '*cargo-hack*' -and $elements[1] -ne 'hack') {{
+        return
+    }}
+    $prev = if ($wordToComplete) {{ $elements[-2] }} else {{ $elements[-1] }}
+    $candidates = @({options}
+    )
+    switch ($prev) {{{values}
+    }}
+    $candidates | Where-Object {{ $_.CompletionText -like "$wordToComplete*" }}
+}}
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::Command};
+
+    use super::{generate, Shell};
+    use crate::cli::HELP;
+
+    const SHELLS: [Shell; 4] = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Powershell];
+
+    #[test]
+    fn all_options() {
+        for shell in SHELLS {
+            let script = generate(shell);
+            for &(short, long, ..) in HELP {
+                let long_name = match shell {
+                    Shell::Fish => format!("-l {}", &long[2..]),
+                    _ => long.to_owned(),
+                };
+                assert!(script.contains(&long_name), "{long} not found in {shell:?} script");
+                if !short.is_empty() {
+                    let short_name = match shell {
+                        Shell::Fish => format!("-s {}", &short[1..]),
+                        _ => short.to_owned(),
+                    };
+                    assert!(script.contains(&short_name), "{short} not found in {shell:?} script");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn values() {
+        let bash = generate(Shell::Bash);
+        assert!(bash.contains("compgen -W \"none github-actions\""));
+        assert!(bash.contains("--manifest-path)\n            COMPREPLY=($(compgen -f"));
+        let zsh = generate(Shell::Zsh);
+        assert!(zsh.contains(":<WHEN>:(auto always never)"));
+        assert!(zsh.contains("'*--manifest-path=[Path to Cargo.toml]:<PATH>:_files'"));
+        let fish = generate(Shell::Fish);
+        assert!(fish.contains("-l log-group -x -a 'none github-actions'"));
+        assert!(fish.contains("-l manifest-path -r -F"));
+        let powershell = generate(Shell::Powershell);
+        assert!(powershell.contains("{ $_ -in @('--color') }"));
+    }
+
+    fn check_syntax(shell: Shell, program: &str, args: &[&str]) {
+        // The directory is removed on drop, even if the shell is not available.
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("completion");
+        fs::write(&path, generate(shell)).unwrap();
+        let Ok(output) = Command::new(program).args(args).arg(&path).output() else {
+            // The shell is not available.
+            return;
+        };
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn syntax() {
+        check_syntax(Shell::Bash, "bash", &["-n"]);
+        check_syntax(Shell::Zsh, "zsh", &["-n"]);
+        check_syntax(Shell::Fish, "fish", &["--no-execute"]);
+    }
+}
@@ -10,14 +10,18 @@ use std::{
 };
 
 use anyhow::{bail, Context as _, Result};
+use cargo_config2::Config;
 
 use crate::{
     cargo,
     cli::Args,
-    features::Features,
-    manifest::Manifest,
+    execution_plan,
+    features::{self, Features},
+    manifest::{self, Manifest},
     metadata::{Metadata, Package, PackageId},
-    restore, term, ProcessBuilder,
+    restore, skip, targets, term,
+    version::{self, Version},
+    workspace, ProcessBuilder,
 };
 
 pub(crate) struct Context {
@@ -27,15 +31,32 @@ pub(crate) struct Context {
     pkg_features: HashMap<PackageId, Features>,
     cargo: PathBuf,
     pub(crate) cargo_version: u32,
+    /// Targets that cargo builds for: the targets specified by `--target`, or
+    /// `CARGO_BUILD_TARGET` environment variable or `build.target` in cargo
+    /// config if `--target` is not specified. Empty if all are unset (i.e.,
+    /// building for the host).
+    ///
+    /// Note that targets from the environment variable and cargo config must
+    /// not be passed to cargo explicitly, because cargo already applies them.
+    pub(crate) effective_targets: Vec<String>,
+    /// Sets of cfgs to run each command with: the powerset of `--cfg-matrix`.
+    /// This contains only an empty set if `--cfg-matrix` is not used.
+    pub(crate) cfg_sets: Vec<Vec<String>>,
     pub(crate) restore: restore::Manager,
     pub(crate) current_dir: PathBuf,
     pub(crate) current_package: Option<PackageId>,
+    /// Skips that occurred during the run, used for --fail-on-skipped.
+    pub(crate) skips: skip::Recorder,
+    /// The plan recorded for --emit-execution-plan.
+    pub(crate) execution_plan: execution_plan::Recorder,
 }
 
 impl Context {
     pub(crate) fn new(args: Args, cargo: OsString) -> Result<Self> {
         assert!(
-            args.subcommand.is_some() || args.remove_dev_deps,
+            args.subcommand.is_some()
+                || args.remove_dev_deps
+                || args.generate_feature_docs.is_some(),
             "no subcommand or valid flag specified"
         );
 
@@ -45,26 +66,177 @@ impl Context {
             .map(|v| v.minor)
             .unwrap_or(0);
 
+        // Validating targets runs rustup or rustc, so skip it if no commands
+        // are run.
+        if args.subcommand.is_some() && !args.print_command_list && args.why.is_none() {
+            targets::validate(&args.target)?;
+        }
+
+        if args.doc_json && !cargo::is_nightly(cmd!(&cargo)).unwrap_or(false) {
+            bail!("--doc-json requires a nightly toolchain");
+        }
+
         // if `--remove-dev-deps` flag is off, restore manifest file.
-        let mut restore = restore::Manager::new(!args.remove_dev_deps);
+        let mut restore =
+            restore::Manager::new(!args.remove_dev_deps, args.manifest_backup_dir.as_deref());
+        // Run `cargo metadata` on the root of the workspace that cargo selects
+        // for the current directory or --manifest-path, so that cargo-hack
+        // always works on the same workspace as the commands it runs.
+        let root_manifest = match workspace::locate_root(args.manifest_path.as_deref(), &cargo) {
+            Ok(path) => {
+                if term::verbose() {
+                    let from = match &args.manifest_path {
+                        Some(manifest_path) => format!("--manifest-path `{manifest_path}`"),
+                        None => "the current directory".to_owned(),
+                    };
+                    info!(
+                        "using workspace at `{}` (located by `cargo locate-project --workspace` \
+                         from {from})",
+                        path.parent().unwrap().display()
+                    );
+                }
+                Some(path)
+            }
+            Err(e) => {
+                // Old cargo does not support `cargo locate-project --workspace`.
+                if term::verbose() {
+                    info!(
+                        "unable to locate the workspace by `cargo locate-project --workspace`: \
+                         {e:#}"
+                    );
+                }
+                None
+            }
+        };
+        let manifest_path =
+            root_manifest.as_deref().or_else(|| args.manifest_path.as_deref().map(Path::new));
+        // Load cargo config from the workspace root, as cargo does for the
+        // commands run on the workspace.
+        let config_dir = match manifest_path.and_then(Path::parent) {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => env::current_dir()?,
+        };
+        let config = Config::load_with_cwd(&config_dir)
+            .map_err(|e| warn!("unable to load cargo config: {e:#}"))
+            .ok();
         let metadata = Metadata::new(
-            args.manifest_path.as_deref(),
+            manifest_path,
             &cargo,
             cargo_version,
             &args,
+            config.as_ref(),
             &mut restore,
         )?;
+        if root_manifest.is_none() && term::verbose() {
+            info!(
+                "using workspace at `{}` (reported by `cargo metadata`)",
+                metadata.workspace_root.display()
+            );
+        }
+        trace!(
+            "metadata",
+            "workspace root: {} ({} members, {} packages), cargo version: 1.{} (metadata parsed as \
+             1.{})",
+            metadata.workspace_root.display(),
+            metadata.workspace_members.len(),
+            metadata.packages.len(),
+            cargo_version,
+            metadata.cargo_version
+        );
+        if !args.ignore_minimum_version {
+            let root_manifest = metadata.workspace_root.join("Cargo.toml");
+            if let Some(required) = manifest::minimum_version(&root_manifest)? {
+                let current = env!("CARGO_PKG_VERSION");
+                // Ignore the pre-release part, e.g., `-dev`.
+                if current.split('-').next().unwrap().parse::<Version>()? < required {
+                    bail!(
+                        "this workspace requires cargo-hack >= {required}, but you have \
+                         {current}; run `cargo install cargo-hack --locked` to upgrade \
+                         (use --ignore-minimum-version to skip this check)"
+                    );
+                }
+            }
+        }
         if metadata.cargo_version < 41 && args.include_deps_features {
             bail!("--include-deps-features requires Cargo 1.41 or later");
         }
 
+        let env_target = env::var("CARGO_BUILD_TARGET").ok().filter(|t| !t.trim().is_empty());
+        let effective_targets = if !args.target.is_empty() {
+            args.target.clone()
+        } else if let Some(target) = env_target {
+            // The environment variable takes precedence over cargo config.
+            if term::verbose() {
+                info!(
+                    "detected build.target from CARGO_BUILD_TARGET environment variable: {target}"
+                );
+            }
+            vec![target]
+        } else {
+            let targets = config
+                .as_ref()
+                .and_then(|config| {
+                    config
+                        .build_target_for_cli::<_, &str>([])
+                        .map_err(|e| {
+                            warn!("unable to determine build.target from cargo config: {e:#}");
+                        })
+                        .ok()
+                })
+                .unwrap_or_default();
+            if !targets.is_empty() && term::verbose() {
+                info!("detected build.target from cargo config: {}", targets.join(", "));
+            }
+            targets
+        };
+
+        let cfg_sets: Vec<Vec<String>> = features::powerset(&args.cfg_matrix, args.depth)
+            .map(|cfgs| cfgs.into_iter().cloned().collect())
+            .collect();
+        if cfg_sets.len() > 1 && !args.print_command_list {
+            warn!(
+                "--cfg-matrix runs commands with different RUSTFLAGS, which invalidates the build \
+                 cache each time the cfgs change; consider using a separate target directory \
+                 (e.g., CARGO_TARGET_DIR) for cargo-hack to keep the cache of normal builds"
+            );
+        }
+
+        // Targets to determine whether target-specific optional dependencies
+        // are used. The host is only needed if there are such dependencies.
+        let has_target_specific_optional_deps = metadata.workspace_members.iter().any(|id| {
+            metadata.packages[id].dependencies.iter().any(|d| d.optional && d.target.is_some())
+        });
+        let feature_targets = if !effective_targets.is_empty() {
+            effective_targets.clone()
+        } else if has_target_specific_optional_deps {
+            config
+                .as_ref()
+                .and_then(|config| {
+                    config
+                        .host_triple()
+                        .map(|host| vec![host.to_owned()])
+                        .map_err(|e| warn!("unable to determine host triple: {e:#}"))
+                        .ok()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+
         let mut manifests = HashMap::with_capacity(metadata.workspace_members.len());
         let mut pkg_features = HashMap::with_capacity(metadata.workspace_members.len());
 
         for id in &metadata.workspace_members {
             let manifest_path = &metadata.packages[id].manifest_path;
             let manifest = Manifest::new(manifest_path, metadata.cargo_version)?;
-            let features = Features::new(&metadata, &manifest, id, args.include_deps_features);
+            let features = Features::new(
+                &metadata,
+                &manifest,
+                id,
+                args.include_deps_features,
+                &args.group_features,
+                &feature_targets,
+            );
             manifests.insert(id.clone(), manifest);
             pkg_features.insert(id.clone(), features);
         }
@@ -98,9 +270,13 @@ impl Context {
             pkg_features,
             cargo: cargo.into(),
             cargo_version,
+            effective_targets,
+            cfg_sets,
             restore,
             current_dir: env::current_dir()?,
             current_package,
+            skips: skip::Recorder::default(),
+            execution_plan: execution_plan::Recorder::default(),
         };
 
         // TODO: Ideally, we should do this, but for now, we allow it as cargo-hack
@@ -132,6 +308,10 @@ impl Context {
         &self.metadata.workspace_root
     }
 
+    pub(crate) fn target_directory(&self) -> &Path {
+        &self.metadata.target_directory
+    }
+
     pub(crate) fn manifests(&self, id: &PackageId) -> &Manifest {
         &self.manifests[id]
     }
@@ -156,6 +336,35 @@ impl Context {
         }
     }
 
+    /// Returns the rust-version of the package, parsed by
+    /// [`version::parse_rust_version`].
+    pub(crate) fn msrv(&self, id: &PackageId) -> Result<Option<Version>> {
+        let Some(msrv) = self.rust_version(id) else { return Ok(None) };
+        let package = self.packages(id);
+        let msrv = version::parse_rust_version(msrv).with_context(|| {
+            format!(
+                "invalid rust-version `{msrv}` in package {} ({})",
+                package.name,
+                package.manifest_path.display()
+            )
+        })?;
+        Ok(Some(msrv))
+    }
+
+    /// Returns the edition of the package and the first Rust version that
+    /// supports it, which is used as the lower bound of --version-range in
+    /// place of the rust-version if the package has no rust-version.
+    pub(crate) fn edition_msrv(&self, id: &PackageId) -> Option<(&str, Version)> {
+        let edition = self.packages(id).edition.as_deref()?;
+        Some((edition, version::edition_msrv(edition)?))
+    }
+
+    /// Returns `true` if the run with `--all-features` is excluded for the package.
+    pub(crate) fn excludes_all_features(&self, id: &PackageId) -> bool {
+        self.exclude_all_features
+            || self.exclude_all_features_packages.contains(&self.packages(id).name)
+    }
+
     pub(crate) fn name_verbose(&self, id: &PackageId) -> Cow<'_, str> {
         let package = self.packages(id);
         if term::verbose() {
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of the coverage note of disabled default features, and
+// --strict-coverage.
+//
+// --include-features, --at-least-one-of, and --exactly-one-of imply
+// --exclude-no-default-features, and --include-features also limits the
+// combinations to the specified features, so a package with non-empty default
+// features may never be tested with them disabled (e.g., its no_std support is
+// never built). This checks the planned runs of each package, and reports
+// packages with default features where no run disables all of them, i.e.,
+// neither the run with only --no-default-features nor any combination that
+// enables none of the features enabled by `default`.
+
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use crate::{context::Context, features, Feature, Kind, PackageRuns};
+
+/// Returns the notes on the packages whose planned runs never disable their
+/// default features.
+pub(crate) fn check(cx: &Context, packages: &[PackageRuns<'_>]) -> Vec<String> {
+    // --changed-features-only intentionally runs only some of the combinations.
+    if !cx.each_feature && !cx.feature_powerset
+        || cx.ignores_features
+        || cx.changed_features_only.is_some()
+    {
+        return vec![];
+    }
+    let excluded_by = if !cx.include_features.is_empty() {
+        "--include-features"
+    } else if !cx.at_least_one_of.is_empty() {
+        "--at-least-one-of"
+    } else if !cx.exactly_one_of.is_empty() {
+        "--exactly-one-of"
+    } else {
+        "--exclude-no-default-features"
+    };
+    let mut notes = vec![];
+    for pkg in packages {
+        let package = cx.packages(pkg.id);
+        let (no_default_run, combinations) = match &pkg.kind {
+            // Runs only once, with the default features (--no-default-features
+            // cannot be used together with --each-feature and --feature-powerset).
+            Kind::Normal => (false, vec![]),
+            Kind::Each { features } => {
+                (!cx.exclude_no_default_features, features.iter().map(|&f| vec![f]).collect())
+            }
+            Kind::Powerset { features } => (!cx.exclude_no_default_features, features.clone()),
+        };
+        if no_default_run
+            || !has_default_features(&package.features)
+            || combinations
+                .iter()
+                .any(|c| disables_default_features(&package.features, c, &cx.features))
+        {
+            continue;
+        }
+        let mut note =
+            format!("package `{}`: no combination tests disabled default features", package.name);
+        if !matches!(pkg.kind, Kind::Normal) {
+            write!(note, " (the run with only --no-default-features is excluded by {excluded_by})")
+                .unwrap();
+        }
+        notes.push(note);
+    }
+    notes
+}
+
+/// Prints the notes, as warnings with --strict-coverage.
+pub(crate) fn print(cx: &Context, notes: &[String]) {
+    if notes.is_empty() {
+        return;
+    }
+    eprintln!();
+    for note in notes {
+        if cx.strict_coverage {
+            warn!("{note}");
+        } else {
+            info!("{note}");
+        }
+    }
+}
+
+fn has_default_features(map: &BTreeMap<String, Vec<String>>) -> bool {
+    map.get("default").is_some_and(|default| !default.is_empty())
+}
+
+/// Returns `true` if the combination (with the features passed by --features)
+/// enables none of the features enabled by `default`.
+fn disables_default_features(
+    map: &BTreeMap<String, Vec<String>>,
+    combination: &[&Feature],
+    extra: &[String],
+) -> bool {
+    let deps = features::feature_deps(map);
+    let default = &deps["default"];
+    combination.iter().flat_map(|f| f.as_group()).chain(extra).map(String::as_str).all(|f| {
+        f != "default"
+            && !default.contains(f)
+            && deps.get(f).map_or(true, |enabled| enabled.is_disjoint(default))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{disables_default_features, has_default_features};
+    use crate::Feature;
+
+    #[test]
+    fn default_features() {
+        let map: BTreeMap<_, _> = [
+            ("default", vec!["std"]),
+            ("std", vec!["alloc"]),
+            ("alloc", vec![]),
+            ("full", vec!["std", "serde"]),
+            ("serde", vec![]),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.into_iter().map(str::to_owned).collect()))
+        .collect();
+        assert!(has_default_features(&map));
+        let f = |names: &[&str]| names.iter().map(|&n| Feature::from(n)).collect::<Vec<_>>();
+        let check = |names: &[&str], extra: &[&str]| {
+            let combination = f(names);
+            let extra: Vec<_> = extra.iter().map(|&s| s.to_owned()).collect();
+            disables_default_features(&map, &combination.iter().collect::<Vec<_>>(), &extra)
+        };
+        assert!(check(&[], &[]));
+        assert!(check(&["serde"], &[]));
+        // `alloc` is enabled by `std`, which is enabled by `default`.
+        assert!(!check(&["alloc"], &[]));
+        assert!(!check(&["full"], &[]));
+        assert!(!check(&["default"], &[]));
+        assert!(!check(&["serde"], &["std"]));
+        assert!(!check(&[], &["default"]));
+
+        let mut map = map;
+        map.insert("default".to_owned(), vec![]);
+        assert!(!has_default_features(&map));
+        map.remove("default");
+        assert!(!has_default_features(&map));
+    }
+}
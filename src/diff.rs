@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// A simple line-based diff, used to display modifications of manifests.
+//
+// Manifests are usually tiny, so this uses the classic O(N*M) LCS table
+// instead of a more sophisticated algorithm.
+
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+pub(crate) struct Diff<'a> {
+    ops: Vec<Op<'a>>,
+}
+
+impl<'a> Diff<'a> {
+    pub(crate) fn new(old: &'a str, new: &'a str) -> Self {
+        let old: Vec<_> = old.lines().collect();
+        let new: Vec<_> = new.lines().collect();
+        // lcs[i][j] is the length of the LCS of old[i..] and new[j..].
+        let mut lcs = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let mut ops = Vec::with_capacity(old.len().max(new.len()));
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() && j < new.len() {
+            if old[i] == new[j] {
+                ops.push(Op::Equal(old[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                ops.push(Op::Delete(old[i]));
+                i += 1;
+            } else {
+                ops.push(Op::Insert(new[j]));
+                j += 1;
+            }
+        }
+        ops.extend(old[i..].iter().map(|l| Op::Delete(l)));
+        ops.extend(new[j..].iter().map(|l| Op::Insert(l)));
+        Self { ops }
+    }
+
+    /// Returns the number of removed lines.
+    pub(crate) fn removed(&self) -> usize {
+        self.ops.iter().filter(|op| matches!(op, Op::Delete(_))).count()
+    }
+
+    /// Returns the number of added lines.
+    pub(crate) fn added(&self) -> usize {
+        self.ops.iter().filter(|op| matches!(op, Op::Insert(_))).count()
+    }
+
+    /// Formats the diff in unified format with `context` lines of context.
+    ///
+    /// At most `max_lines` lines of hunks are displayed.
+    pub(crate) fn unified(&self, context: usize, max_lines: usize) -> String {
+        let changes: Vec<_> =
+            (0..self.ops.len()).filter(|&i| !matches!(self.ops[i], Op::Equal(_))).collect();
+        let mut out = String::new();
+        let mut lines = 0;
+        let mut pos = 0;
+        while pos < changes.len() {
+            // Merge changes whose contexts overlap into a single hunk.
+            let first = changes[pos];
+            let mut last = first;
+            pos += 1;
+            while pos < changes.len() && changes[pos] - last <= context * 2 + 1 {
+                last = changes[pos];
+                pos += 1;
+            }
+            let start = first.saturating_sub(context);
+            let end = (last + context + 1).min(self.ops.len());
+
+            let old_start =
+                self.ops[..start].iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+            let new_start =
+                self.ops[..start].iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+            let hunk = &self.ops[start..end];
+            let old_len = hunk.iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+            let new_len = hunk.iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+            // Like GNU diff, an empty range starts at the line before it.
+            let old_start = if old_len == 0 { old_start } else { old_start + 1 };
+            let new_start = if new_len == 0 { new_start } else { new_start + 1 };
+            writeln!(out, "@@ -{old_start},{old_len} +{new_start},{new_len} @@").unwrap();
+            for op in hunk {
+                if lines == max_lines {
+                    writeln!(out, "... (diff truncated)").unwrap();
+                    return out;
+                }
+                lines += 1;
+                match op {
+                    Op::Equal(l) => writeln!(out, " {l}").unwrap(),
+                    Op::Delete(l) => writeln!(out, "-{l}").unwrap(),
+                    Op::Insert(l) => writeln!(out, "+{l}").unwrap(),
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diff;
+
+    #[test]
+    fn unified() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nc\nd\ne\nf\ng\nh\ni\nx\nj\n";
+        let diff = Diff::new(old, new);
+        assert_eq!(diff.removed(), 1);
+        assert_eq!(diff.added(), 1);
+        assert_eq!(
+            diff.unified(1, usize::MAX),
+            "@@ -1,3 +1,2 @@\n a\n-b\n c\n@@ -9,2 +8,3 @@\n i\n+x\n j\n"
+        );
+        assert_eq!(
+            diff.unified(3, usize::MAX),
+            "@@ -1,5 +1,4 @@\n a\n-b\n c\n d\n e\n@@ -7,4 +6,5 @@\n g\n h\n i\n+x\n j\n"
+        );
+        assert_eq!(
+            diff.unified(4, usize::MAX),
+            "@@ -1,10 +1,10 @@\n a\n-b\n c\n d\n e\n f\n g\n h\n i\n+x\n j\n"
+        );
+        assert_eq!(diff.unified(1, 2), "@@ -1,3 +1,2 @@\n a\n-b\n... (diff truncated)\n");
+
+        assert_eq!(Diff::new(old, old).unified(3, usize::MAX), "");
+        assert_eq!(Diff::new("", "a\n").unified(3, usize::MAX), "@@ -0,0 +1,1 @@\n+a\n");
+    }
+}
@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --rustdoc-all-features-check.
+//
+// Items gated by a feature are documented only when the feature is enabled,
+// and `#[cfg_attr(docsrs, doc(cfg(...)))]` tells the readers which feature they
+// require. A feature whose items are not gated correctly (e.g., a typo in the
+// feature name of the cfg) silently documents nothing, so this counts the
+// public items in the documentation built with each feature alone, and reports
+// features that document no more items than the build without features. The
+// documentation is built with `-D warnings`, so features with which it fails
+// to build (e.g., because of broken intra-doc links to items gated by another
+// feature) are also reported.
+//
+// The items are counted from `all.html`, or from the JSON output of rustdoc
+// with --doc-json (requires nightly). The documentation is overwritten by the
+// next run, so the items are counted right after each run.
+
+use std::{collections::BTreeMap, fmt::Write as _, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    context::Context,
+    fs,
+    summary::{Run, Status},
+    PackageId, ProcessBuilder,
+};
+
+/// Adds the flags used to check the documentation to `line`.
+pub(crate) fn apply(cx: &Context, line: &mut ProcessBuilder<'_>) {
+    if !line.has_arg("--no-deps") {
+        line.arg("--no-deps");
+    }
+    let mut flags = vec!["--cfg", "docsrs", "-D", "warnings"];
+    if cx.doc_json {
+        flags.extend(["-Z", "unstable-options", "--output-format", "json"]);
+    }
+    line.append_rustdocflags(&flags);
+}
+
+/// Counts the public items in the documentation of the library of the package
+/// built by `line`, or returns `None` if the package has no library or the
+/// documentation is not found.
+pub(crate) fn count_items(
+    cx: &Context,
+    id: &PackageId,
+    line: &ProcessBuilder<'_>,
+) -> Option<usize> {
+    let lib = cx.packages(id).targets.iter().find(|target| {
+        target.kind.iter().any(|kind| kind.ends_with("lib") || kind == "proc-macro")
+    })?;
+    let crate_name = lib.name.replace('-', "_");
+    let mut doc_dir = cx.target_directory().to_owned();
+    let target =
+        line.arg_value("--target").or_else(|| cx.effective_targets.first().map(String::as_str));
+    if let Some(target) = target {
+        // The output directory of a custom target is named after its file.
+        let target = match target.strip_suffix(".json") {
+            Some(_) => Path::new(target).file_stem()?.to_str()?,
+            None => target,
+        };
+        doc_dir.push(target);
+    }
+    doc_dir.push("doc");
+    if cx.doc_json {
+        let json = fs::read_to_string(doc_dir.join(format!("{crate_name}.json"))).ok()?;
+        count_json_items(&json)
+    } else {
+        let html = fs::read_to_string(doc_dir.join(crate_name).join("all.html")).ok()?;
+        Some(count_html_items(&html))
+    }
+}
+
+/// Counts the items listed in `all.html`.
+fn count_html_items(html: &str) -> usize {
+    html.matches("<li><a href=").count()
+}
+
+/// Counts the public items of the local crate in the JSON output of rustdoc.
+fn count_json_items(json: &str) -> Option<usize> {
+    let json: Value = serde_json::from_str(json).ok()?;
+    let index = json.get("index")?.as_object()?;
+    Some(
+        index
+            .values()
+            .filter(|item| {
+                item.get("crate_id").and_then(Value::as_u64) == Some(0)
+                    && item.get("visibility").and_then(Value::as_str) == Some("public")
+            })
+            .count(),
+    )
+}
+
+/// Compares the results of the runs with the run without features, and
+/// returns the findings.
+pub(crate) fn check(runs: &[Run]) -> Vec<String> {
+    let mut findings = vec![];
+    let mut groups: BTreeMap<(&str, Option<&str>, &[String]), Vec<&Run>> = BTreeMap::new();
+    for run in runs.iter().filter(|run| run.status.ran()) {
+        groups.entry((&run.package, run.toolchain.as_deref(), &run.cfgs)).or_default().push(run);
+    }
+
+    for ((package, toolchain, cfgs), runs) in groups {
+        let mut label = toolchain.map_or_else(String::new, |t| format!(" (toolchain {t})"));
+        if !cfgs.is_empty() {
+            write!(label, " (cfg {})", cfgs.join(", ")).unwrap();
+        }
+        // The run with --no-default-features and no features. This is not
+        // run with --exclude-no-default-features.
+        let Some(baseline) = runs.iter().find(|run| run.requested_features.is_empty()) else {
+            continue;
+        };
+        for run in runs.iter().filter(|run| !run.requested_features.is_empty()) {
+            match (baseline.status, run.status) {
+                (Status::Passed, Status::Failed) => findings.push(format!(
+                    "{package}{label}: documentation fails to build with {} but builds without \
+                     features; it may have broken intra-doc links to items gated by other features",
+                    run.features
+                )),
+                (_, Status::Passed) => {
+                    if let (Some(base), Some(items)) = (baseline.doc_items, run.doc_items) {
+                        if items <= base {
+                            findings.push(format!(
+                                "{package}{label}: {} documents no more public items than the \
+                                 build without features ({items}); the items it enables may be \
+                                 missing from the documentation",
+                                run.features
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_html_items, count_json_items};
+
+    #[test]
+    fn count() {
+        let html = r#"<h3 id="structs">Structs</h3><ul class="all-items"><li><a href="struct.A.html">A</a></li><li><a href="b/struct.B.html">b::B</a></li></ul><h3 id="functions">Functions</h3><ul class="all-items"><li><a href="fn.f.html">f</a></li></ul>"#;
+        assert_eq!(count_html_items(html), 3);
+        let json = r#"{"index": {
+            "0:0": {"crate_id": 0, "visibility": "public"},
+            "0:1": {"crate_id": 0, "visibility": "crate"},
+            "1:0": {"crate_id": 1, "visibility": "public"},
+            "0:2": {"crate_id": 0, "visibility": "public"}
+        }}"#;
+        assert_eq!(count_json_items(json), Some(2));
+        assert_eq!(count_json_items("{}"), None);
+    }
+}
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --allow-empty-plan.
+//
+// Options that filter packages (e.g., --exclude, --ignore-private, and
+// --only-packages-with-feature) and the rust-version check of --version-range
+// can leave no commands to run. Succeeding without running anything hides
+// misconfigured CI, so such an empty plan is an error unless
+// --allow-empty-plan is passed. The reasons why the packages were left out
+// are taken from the skips recorded for --fail-on-skipped.
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Result};
+
+use crate::context::Context;
+
+/// Reports that no commands are left to run, and fails unless
+/// --allow-empty-plan is passed.
+pub(crate) fn check(cx: &Context) -> Result<()> {
+    eprintln!();
+    warn!("{}", message(&cx.skips.packages()));
+    if !cx.allow_empty_plan {
+        bail!("no commands to run; use --allow-empty-plan to succeed without running anything");
+    }
+    Ok(())
+}
+
+fn message(filtered: &[(String, String)]) -> String {
+    let mut msg = "no commands to run".to_owned();
+    if !filtered.is_empty() {
+        msg.push_str("; packages were left out for the following reasons:");
+    }
+    for (package, reason) in filtered {
+        write!(msg, "\n    {package}: {reason}").unwrap();
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::message;
+
+    #[test]
+    fn reasons() {
+        let filtered = [
+            ("a".to_owned(), "excluded by --exclude".to_owned()),
+            ("b".to_owned(), "private package".to_owned()),
+        ];
+        assert_eq!(
+            message(&filtered),
+            "no commands to run; packages were left out for the following reasons:\n    a: \
+             excluded by --exclude\n    b: private package"
+        );
+        assert_eq!(message(&[]), "no commands to run");
+    }
+}
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of CARGO_HACK_* environment variables (--no-env-config).
+//
+// Each option in the table of options used for --help can also be specified
+// by an environment variable named `CARGO_HACK_` followed by the option name
+// in uppercase with `-` replaced by `_`, e.g., `CARGO_HACK_EACH_FEATURE=true`
+// or `CARGO_HACK_EXCLUDE_FEATURES=a,b`. The variables are converted to
+// arguments and parsed together with the command line, so their values are
+// handled exactly like the arguments, and options specified on the command
+// line take precedence. A few variables that are not named after an option
+// (e.g., `CARGO_HACK_AUTO_LOCKED=1` for --locked=auto) are also accepted.
+
+use std::{collections::BTreeMap, env, ffi::OsString};
+
+use anyhow::{bail, Error, Result};
+
+use crate::cli::HELP;
+
+const PREFIX: &str = "CARGO_HACK_";
+
+/// Options that cannot be specified by environment variables.
+const EXCLUDED: &[&str] = &["--help", "--version", "--no-env-config"];
+
+/// Boolean variables that are not named after an option, as pairs of the name
+/// of the variable and the argument.
+const EXTRA: &[(&str, &str)] = &[("CARGO_HACK_AUTO_LOCKED", "--locked=auto")];
+
+/// Returns the environment variables of the process that may specify options.
+pub(crate) fn vars() -> BTreeMap<String, OsString> {
+    env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value)))
+        .filter(|(name, _)| name.starts_with(PREFIX))
+        .collect()
+}
+
+/// Returns the name of the environment variable for the long option.
+pub(crate) fn var_name(long: &str) -> String {
+    format!("{PREFIX}{}", long.trim_start_matches('-').to_ascii_uppercase().replace('-', "_"))
+}
+
+/// Returns the arguments specified by the environment variables in `env` for
+/// the options not in `args`, as pairs of the name of the variable and the
+/// argument.
+pub(crate) fn args(
+    args: &[String],
+    env: &BTreeMap<String, OsString>,
+) -> Result<Vec<(String, String)>> {
+    // Aliases (e.g., --all for --workspace) are spelled by the options they
+    // alias, but are checked when looking for the option in `args`.
+    let aliases: Vec<_> = HELP
+        .iter()
+        .filter_map(|&(_, long, _, desc, _)| Some((desc.strip_prefix("Alias for ")?, long)))
+        .collect();
+    let specified = |short: &str, long: &str| {
+        let longs: Vec<_> = aliases
+            .iter()
+            .filter(|&&(target, _)| target == long)
+            .map(|&(_, alias)| alias)
+            .chain([long])
+            .collect();
+        args.iter().any(|arg| {
+            if let Some(flag) = arg.strip_prefix("--") {
+                let flag = flag.split_once('=').map_or(flag, |(flag, _)| flag);
+                longs.iter().any(|long| long[2..] == *flag)
+            } else {
+                !short.is_empty() && arg.starts_with(short)
+            }
+        })
+    };
+
+    let mut env_args = vec![];
+    for &(short, long, value_name, desc, _) in HELP {
+        if EXCLUDED.contains(&long) || desc.starts_with("Alias for ") {
+            continue;
+        }
+        let name = var_name(long);
+        let Some(value) = env.get(&name) else { continue };
+        if specified(short, long) {
+            continue;
+        }
+        let Some(value) = value.to_str() else {
+            bail!("{name}: value is not valid Unicode: {value:?}");
+        };
+        let arg = if value_name.is_empty() {
+            if !parse_bool(&name, value)? {
+                continue;
+            }
+            long.to_owned()
+        } else if value_name.starts_with('[') && value_name.ends_with("]...") {
+            // The value is optional (e.g., --optional-deps).
+            match parse_bool(&name, value) {
+                Ok(true) => long.to_owned(),
+                Ok(false) => continue,
+                Err(_) => format!("{long}={value}"),
+            }
+        } else {
+            format!("{long}={value}")
+        };
+        env_args.push((name, arg));
+    }
+    for &(name, arg) in EXTRA {
+        let Some(value) = env.get(name) else { continue };
+        let long = arg.split_once('=').map_or(arg, |(long, _)| long);
+        if specified("", long) {
+            continue;
+        }
+        let Some(value) = value.to_str() else {
+            bail!("{name}: value is not valid Unicode: {value:?}");
+        };
+        if parse_bool(name, value)? {
+            // Takes precedence over the variable named after the option.
+            env_args.retain(|(_, a)| a.split_once('=').map_or(a.as_str(), |(a, _)| a) != long);
+            env_args.push((name.to_owned(), arg.to_owned()));
+        }
+    }
+    Ok(env_args)
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "" | "0" | "false" | "no" => Ok(false),
+        _ => bail!("{name}: expected 1, true, yes, 0, false, or no, but found `{value}`"),
+    }
+}
+
+/// Prefixes the error with the names of the environment variables whose
+/// options are mentioned in the error.
+pub(crate) fn attribute(e: Error, env_args: &[(String, String)]) -> Error {
+    let msg = format!("{e:#}");
+    let names: Vec<_> = env_args
+        .iter()
+        .filter(|(_, arg)| {
+            let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+            msg.match_indices(flag).any(|(i, _)| {
+                !msg[i + flag.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-')
+            })
+        })
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if names.is_empty() {
+        e
+    } else {
+        e.context(names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, ffi::OsString};
+
+    use anyhow::format_err;
+
+    use super::{args, attribute, var_name};
+
+    fn env_map(vars: &[(&str, &str)]) -> BTreeMap<String, OsString> {
+        vars.iter().map(|&(k, v)| (k.to_owned(), v.into())).collect()
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|&s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(var_name("--each-feature"), "CARGO_HACK_EACH_FEATURE");
+        assert_eq!(var_name("--version-range"), "CARGO_HACK_VERSION_RANGE");
+    }
+
+    #[test]
+    fn env_args() {
+        let env = env_map(&[
+            ("CARGO_HACK_EACH_FEATURE", "true"),
+            ("CARGO_HACK_EXCLUDE_FEATURES", "a,b"),
+            ("CARGO_HACK_VERSION_RANGE", "..=stable"),
+            ("CARGO_HACK_KEEP_GOING", "0"),
+            ("CARGO_HACK_CARGO_SRC", "x"),
+        ]);
+        let env_args = |cli: &[&str]| {
+            args(&strings(cli), &env).unwrap().into_iter().map(|(_, arg)| arg).collect::<Vec<_>>()
+        };
+        assert_eq!(env_args(&["check"]), [
+            "--each-feature",
+            "--exclude-features=a,b",
+            "--version-range=..=stable",
+        ]);
+        // The command line takes precedence.
+        assert_eq!(env_args(&["check", "--exclude-features", "c", "--version-range=1.70.."]), [
+            "--each-feature"
+        ]);
+        assert_eq!(env_args(&["check", "--skip=c", "--each-feature"]), [
+            "--version-range=..=stable"
+        ]);
+    }
+
+    #[test]
+    fn bool_values() {
+        for value in ["1", "true", "yes", "TRUE", "Yes"] {
+            let env = env_map(&[("CARGO_HACK_WORKSPACE", value)]);
+            assert_eq!(args(&[], &env).unwrap(), [(
+                "CARGO_HACK_WORKSPACE".to_owned(),
+                "--workspace".to_owned()
+            )]);
+        }
+        for value in ["", "0", "false", "no"] {
+            let env = env_map(&[("CARGO_HACK_WORKSPACE", value)]);
+            assert!(args(&[], &env).unwrap().is_empty());
+        }
+        let env = env_map(&[("CARGO_HACK_WORKSPACE", "on")]);
+        assert_eq!(
+            args(&[], &env).unwrap_err().to_string(),
+            "CARGO_HACK_WORKSPACE: expected 1, true, yes, 0, false, or no, but found `on`"
+        );
+        // --all is an alias for --workspace.
+        let env = env_map(&[("CARGO_HACK_WORKSPACE", "1")]);
+        assert!(args(&strings(&["--all"]), &env).unwrap().is_empty());
+
+        // The value of --optional-deps is optional.
+        let env = env_map(&[("CARGO_HACK_OPTIONAL_DEPS", "yes")]);
+        assert_eq!(args(&[], &env).unwrap()[0].1, "--optional-deps");
+        let env = env_map(&[("CARGO_HACK_OPTIONAL_DEPS", "a,b")]);
+        assert_eq!(args(&[], &env).unwrap()[0].1, "--optional-deps=a,b");
+
+        // Variables not named after an option.
+        let env = env_map(&[("CARGO_HACK_AUTO_LOCKED", "1"), ("CARGO_HACK_LOCKED", "1")]);
+        assert_eq!(args(&[], &env).unwrap(), [(
+            "CARGO_HACK_AUTO_LOCKED".to_owned(),
+            "--locked=auto".to_owned()
+        )]);
+        assert!(args(&strings(&["--locked"]), &env).unwrap().is_empty());
+        let env = env_map(&[("CARGO_HACK_AUTO_LOCKED", "0")]);
+        assert!(args(&[], &env).unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let env_args = [
+            ("CARGO_HACK_DEPTH".to_owned(), "--depth=x".to_owned()),
+            ("CARGO_HACK_EXCLUDE".to_owned(), "--exclude=a".to_owned()),
+        ];
+        let e = attribute(format_err!("failed to parse argument for --depth"), &env_args);
+        assert_eq!(format!("{e:#}"), "CARGO_HACK_DEPTH: failed to parse argument for --depth");
+        // --exclude-features is not --exclude.
+        let e = attribute(format_err!("--exclude-features can only be used ..."), &env_args);
+        assert_eq!(format!("{e:#}"), "--exclude-features can only be used ...");
+    }
+}
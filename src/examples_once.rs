@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --examples-once and --tests-once.
+//
+// Examples (and often tests) only use the default features, but they are
+// compiled again for every feature combination. With these flags, they are
+// compiled only on the full runs of each package: the run with --all-features
+// and the run with the default features (`--features default`, or the run
+// without features if the package has no `default` feature or
+// --no-default-features is passed). The other runs select the remaining
+// targets explicitly, e.g., `cargo test --lib --bins --tests` with
+// --examples-once, or `cargo check --lib --bins --tests --benches` instead of
+// `cargo check --all-targets`.
+//
+// These flags are only meaningful for commands that compile examples or tests
+// by default (`cargo test`) or with --all-targets, and cannot be combined with
+// explicit target selection. Since cargo runs doctests only when no targets
+// are selected, `cargo test` also runs them only on the full runs.
+
+use crate::{context::Context, features::Feature, metadata::PackageId, ProcessBuilder};
+
+/// The target selection flags of cargo, except for --all-targets.
+const TARGET_FLAGS: &[&str] = &[
+    "--lib",
+    "--bin",
+    "--bins",
+    "--example",
+    "--examples",
+    "--test",
+    "--tests",
+    "--bench",
+    "--benches",
+    "--doc",
+];
+
+/// Returns the target selection flag that `arg` specifies, if any.
+pub(crate) fn target_flag(arg: &str) -> Option<&'static str> {
+    let flag = arg.split_once('=').map_or(arg, |(flag, _)| flag);
+    TARGET_FLAGS.iter().copied().find(|&f| f == flag)
+}
+
+/// Returns `true` if the run with `features` (after --no-default-features,
+/// unless it is passed by the user) is a full run of the package.
+fn is_full_run(cx: &Context, id: &PackageId, features: &[&Feature]) -> bool {
+    match features {
+        [] => cx.no_default_features || !cx.packages(id).features.contains_key("default"),
+        [Feature::Normal { name }] => name == "default",
+        _ => false,
+    }
+}
+
+/// Selects the targets other than examples (--examples-once) and tests
+/// (--tests-once), unless the run with `features` is a full run.
+pub(crate) fn apply(
+    cx: &Context,
+    id: &PackageId,
+    features: &[&Feature],
+    line: &mut ProcessBuilder<'_>,
+) {
+    if !cx.examples_once && !cx.tests_once || is_full_run(cx, id, features) {
+        return;
+    }
+    let all_targets = line.has_arg("--all-targets");
+    if all_targets {
+        line.remove_propagated_arg("--all-targets");
+    }
+    // --lib fails on packages without a library.
+    let package = cx.packages(id);
+    if package
+        .targets
+        .iter()
+        .any(|t| t.kind.iter().any(|k| k.ends_with("lib") || k == "proc-macro"))
+    {
+        line.arg("--lib");
+    }
+    line.arg("--bins");
+    if !cx.examples_once {
+        line.arg("--examples");
+    }
+    if !cx.tests_once {
+        line.arg("--tests");
+    }
+    if all_targets {
+        line.arg("--benches");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::target_flag;
+
+    #[test]
+    fn target_flags() {
+        assert_eq!(target_flag("--lib"), Some("--lib"));
+        assert_eq!(target_flag("--example=a"), Some("--example"));
+        assert_eq!(target_flag("--bins"), Some("--bins"));
+        assert_eq!(target_flag("--all-targets"), None);
+        assert_eq!(target_flag("--library"), None);
+        assert_eq!(target_flag("--doc-json"), None);
+    }
+}
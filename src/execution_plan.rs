@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --emit-execution-plan.
+//
+// The runs are planned as with --print-command-list (no files in the
+// workspace are modified and no toolchains are installed), and written to a
+// JSON file instead of being printed, so that another tool can execute them:
+//
+// - `mutations`: the manifests to modify before the runs (--no-dev-deps,
+//   --remove-dev-deps, and --no-private), in order, with their new contents.
+// - `runs`: the commands to run, in order. `kind` is `run` for the runs on the
+//   packages, and `setup` for the other commands (e.g., `cargo clean` and the
+//   installation of toolchains of --version-range).
+// - `restore`: the files to restore after the runs, with their original
+//   contents. This also contains Cargo.lock if the manifests are modified,
+//   because cargo may update it (unless --keep-lockfile-changes always keeps
+//   it).
+//
+// The contents are included inline, with their FNV-1a 64-bit hashes (as 16
+// hexadecimal digits) to check that the files have not been modified since
+// the plan was created.
+
+use std::{cell::RefCell, ffi::OsStr, path::Path};
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    context::Context,
+    fs,
+    manifest::{KeepLockfileChanges, Mutation},
+    ProcessBuilder,
+};
+
+/// The version of the format of the plan.
+const VERSION: u32 = 1;
+
+/// Records the plan while the runs are planned.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    mutations: RefCell<Vec<Mutation>>,
+    runs: RefCell<Vec<Value>>,
+}
+
+impl Recorder {
+    pub(crate) fn set_mutations(&self, mutations: Vec<Mutation>) {
+        *self.mutations.borrow_mut() = mutations;
+    }
+
+    /// Records the command. `run` is the package name and the id of the run,
+    /// or `None` if the command is not a run on a package.
+    pub(crate) fn record(
+        &self,
+        cx: &Context,
+        line: &ProcessBuilder<'_>,
+        run: Option<(&str, &str)>,
+    ) {
+        let cmd = line.build();
+        let args: Vec<_> = cmd.get_args().map(OsStr::to_string_lossy).collect();
+        // The commands of --version-range are run by `rustup run <toolchain> cargo`.
+        let toolchain = match (cmd.get_program().to_string_lossy().as_ref(), &args[..]) {
+            ("rustup", [run, toolchain, ..]) if run == "run" => Some(toolchain.to_string()),
+            _ => None,
+        };
+        // The environment variables of cargo-hack are also inherited.
+        let env: Map<_, _> = cmd
+            .get_envs()
+            .filter_map(|(k, v)| {
+                Some((k.to_string_lossy().into_owned(), v?.to_string_lossy().into_owned().into()))
+            })
+            .collect();
+        let cwd = cmd.get_current_dir().unwrap_or(&cx.current_dir);
+        let mut record = json!({
+            "kind": if run.is_some() { "run" } else { "setup" },
+            "program": cmd.get_program().to_string_lossy(),
+            "args": args,
+            "env": env,
+            "cwd": cwd.to_string_lossy(),
+            "toolchain": toolchain,
+        });
+        if let Some((package, id)) = run {
+            record["package"] = package.into();
+            record["id"] = id.into();
+        }
+        self.runs.borrow_mut().push(record);
+    }
+
+    /// Writes the plan to `path`.
+    pub(crate) fn write(&self, cx: &Context, path: &Path) -> Result<()> {
+        let mutations = self.mutations.borrow();
+        let mut restore: Vec<_> =
+            mutations.iter().map(|m| file(&m.path, m.orig.as_bytes(), json!({}))).collect();
+        if !mutations.is_empty() && cx.keep_lockfile_changes != Some(KeepLockfileChanges::Always) {
+            let lockfile = cx.workspace_root().join("Cargo.lock");
+            if lockfile.exists() {
+                restore.push(file(&lockfile, &fs::read(&lockfile)?, json!({})));
+            }
+        }
+        let plan = json!({
+            "version": VERSION,
+            "workspace_root": cx.workspace_root().to_string_lossy(),
+            "mutations": mutations
+                .iter()
+                .map(|m| {
+                    let extra = json!({
+                        "original_hash": hash(m.orig.as_bytes()),
+                        "removes": m.removes,
+                        "restore": true,
+                    });
+                    file(&m.path, m.new.as_bytes(), extra)
+                })
+                .collect::<Vec<_>>(),
+            "runs": *self.runs.borrow(),
+            "restore": restore,
+        });
+        fs::write(path, serde_json::to_string_pretty(&plan)? + "\n")?;
+        info!("wrote the execution plan to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Returns the record of the file with `contents`, merged with `extra`.
+fn file(path: &Path, contents: &[u8], mut extra: Value) -> Value {
+    let record = extra.as_object_mut().unwrap();
+    record.insert("path".to_owned(), path.to_string_lossy().into());
+    record.insert("hash".to_owned(), hash(contents).into());
+    record.insert("content".to_owned(), String::from_utf8_lossy(contents).into());
+    extra
+}
+
+/// Returns the FNV-1a 64-bit hash of `bytes` as 16 hexadecimal digits.
+fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash;
+
+    #[test]
+    fn fnv1a() {
+        assert_eq!(hash(b""), "cbf29ce484222325");
+        assert_eq!(hash(b"a"), "af63dc4c8601ec8c");
+        assert_eq!(hash(b"foobar"), "85944171f73967e8");
+    }
+}
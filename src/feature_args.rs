@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --args-if-feature.
+//
+// Some features need extra flags to build (e.g., a feature that requires a
+// custom profile). The specified args are appended to each command whose
+// feature set enables the feature, so that such requirements do not have to
+// be re-derived from the commands by wrapper scripts.
+
+use anyhow::{bail, format_err, Result};
+
+use crate::{context::Context, features, metadata::PackageId, ProcessBuilder};
+
+/// The flags that cargo-hack controls for each command.
+const CONTROLLED_FLAGS: &[&str] =
+    &["--manifest-path", "--features", "-F", "--no-default-features", "--all-features"];
+
+/// Parses the value of --args-if-feature (`<FEATURE>:<ARGS>`).
+pub(crate) fn parse(val: &str) -> Result<(String, Vec<String>)> {
+    let (feature, args) = val.split_once(':').ok_or_else(|| {
+        format_err!("argument for --args-if-feature must be `<FEATURE>:<ARGS>`, but found `{val}`")
+    })?;
+    let feature = feature.trim();
+    let args: Vec<_> = args.split_whitespace().map(str::to_owned).collect();
+    if feature.is_empty() || args.is_empty() {
+        bail!("argument for --args-if-feature must be `<FEATURE>:<ARGS>`, but found `{val}`");
+    }
+    for arg in &args {
+        if let Some(flag) = controlled_flag(arg) {
+            bail!(
+                "--args-if-feature cannot pass `{flag}`, which is controlled by cargo-hack (found \
+                 in `{val}`)"
+            );
+        }
+    }
+    Ok((feature.to_owned(), args))
+}
+
+/// Returns the flag controlled by cargo-hack that `arg` specifies, if any.
+pub(crate) fn controlled_flag(arg: &str) -> Option<&'static str> {
+    CONTROLLED_FLAGS.iter().copied().find(|&flag| {
+        arg.strip_prefix(flag)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('=') || flag == "-F")
+    })
+}
+
+/// Appends the args of --args-if-feature whose feature is enabled by `line`.
+pub(crate) fn apply(cx: &Context, id: &PackageId, line: &mut ProcessBuilder<'_>) {
+    for (feature, args) in &cx.args_if_feature {
+        if enables(cx, id, line, feature) {
+            line.args(args);
+        }
+    }
+}
+
+/// Returns `true` if the command enables `feature`, directly or via other
+/// features.
+fn enables(cx: &Context, id: &PackageId, line: &ProcessBuilder<'_>, feature: &str) -> bool {
+    if !cx.pkg_features(id).contains(feature) {
+        return false;
+    }
+    if line.has_arg("--all-features") {
+        return true;
+    }
+    let map = &cx.packages(id).features;
+    let default = !line.has_arg("--no-default-features") && map.contains_key("default");
+    line.features()
+        .chain(default.then_some("default"))
+        .any(|f| f == feature || features::enable_chain(map, f, feature).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parse_value() {
+        assert_eq!(
+            parse("bench-internal:--profile bench-opt").unwrap(),
+            ("bench-internal".to_owned(), vec!["--profile".to_owned(), "bench-opt".to_owned()])
+        );
+        assert_eq!(parse("a: --release ").unwrap(), ("a".to_owned(), vec!["--release".to_owned()]));
+        for val in ["a", "a:", ":--release", "a: "] {
+            assert!(parse(val).unwrap_err().to_string().contains("must be `<FEATURE>:<ARGS>`"));
+        }
+        for val in [
+            "a:--features b",
+            "a:--features=b",
+            "a:-Fb",
+            "a:--no-default-features",
+            "a:--release --manifest-path x/Cargo.toml",
+        ] {
+            assert!(parse(val).unwrap_err().to_string().contains("controlled by cargo-hack"));
+        }
+        assert!(parse("a:--featuresx").is_ok());
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --generate-feature-docs.
+//
+// Writes a Markdown table of the features of each selected package, so that
+// the tables of features in READMEs do not drift from Cargo.toml:
+//
+// ## `my-crate`
+//
+// | Feature | Default | Enables | Description |
+// | ------- | ------- | ------- | ----------- |
+// | `std` | yes | `alloc`, `serde` (optional dependency) | Enables std. |
+//
+// The Default column is `yes` if the feature is enabled by the `default`
+// feature, directly or through other features. The descriptions are written
+// by humans: if the file already exists, the description of each feature that
+// still exists is carried over from it, and the descriptions of new features
+// are left empty. The rest of the file is regenerated. No cargo commands are
+// run.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{context::Context, fs, metadata::PackageId};
+
+const HEADER: &str = "<!-- Generated by `cargo hack --generate-feature-docs`. Only the \
+                      Description column is kept when regenerating. -->";
+
+/// Writes the tables of the features of `ids` to `path`.
+pub(crate) fn generate(cx: &Context, ids: &[&PackageId], path: &Path) -> Result<()> {
+    let existing = if path.exists() { fs::read_to_string(path)? } else { String::new() };
+    let descriptions = descriptions(&existing);
+    let mut out = format!("{HEADER}\n");
+    for &id in ids {
+        let package = cx.packages(id);
+        let default = enabled_by_default(&package.features);
+        write!(
+            out,
+            "\n## `{}`\n\n| Feature | Default | Enables | Description |\n\
+             | ------- | ------- | ------- | ----------- |\n",
+            package.name
+        )
+        .unwrap();
+        for (name, enables) in package.features.iter().filter(|(name, _)| *name != "default") {
+            let enables: Vec<_> = enables
+                .iter()
+                .map(|f| match f.strip_prefix("dep:") {
+                    Some(dep) => format!("`{dep}` (optional dependency)"),
+                    None => format!("`{f}`"),
+                })
+                .collect();
+            let description = descriptions
+                .get(&(package.name.as_str(), name.as_str()))
+                .copied()
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "| `{name}` | {} | {} | {description} |",
+                if default.contains(name.as_str()) { "yes" } else { "no" },
+                enables.join(", ")
+            )
+            .unwrap();
+        }
+    }
+    fs::write(path, out)?;
+    info!(
+        "wrote the features of {} {} to {}",
+        ids.len(),
+        if ids.len() == 1 { "package" } else { "packages" },
+        path.display()
+    );
+    Ok(())
+}
+
+/// Returns the features enabled by the `default` feature.
+fn enabled_by_default(features: &BTreeMap<String, Vec<String>>) -> BTreeSet<&str> {
+    let mut enabled = BTreeSet::new();
+    let mut stack = vec!["default"];
+    while let Some(f) = stack.pop() {
+        for f in features.get(f).into_iter().flatten() {
+            // `dep:<dep>` and `<dep>/<feature>` are not features of the package.
+            if features.contains_key(f) && enabled.insert(f.as_str()) {
+                stack.push(f);
+            }
+        }
+    }
+    enabled
+}
+
+/// Returns the descriptions in the existing file, keyed by the package name
+/// and the feature name.
+fn descriptions(s: &str) -> BTreeMap<(&str, &str), &str> {
+    let mut descriptions = BTreeMap::new();
+    let mut package = None;
+    for line in s.lines() {
+        let line = line.trim();
+        if let Some(heading) = line.strip_prefix("## ") {
+            package = Some(heading.trim().trim_matches('`'));
+            continue;
+        }
+        let (Some(package), Some(row)) =
+            (package, line.strip_prefix('|').and_then(|l| l.strip_suffix('|')))
+        else {
+            continue;
+        };
+        let cells = cells(row);
+        let (Some(feature), Some(description)) = (cells.first(), cells.last()) else { continue };
+        if let Some(feature) = feature.strip_prefix('`').and_then(|f| f.strip_suffix('`')) {
+            if cells.len() == 4 && !description.is_empty() {
+                descriptions.insert((package, feature), *description);
+            }
+        }
+    }
+    descriptions
+}
+
+/// Splits the row of a table (without the leading and trailing `|`) into the
+/// trimmed cells. `\|` in a cell is not a separator.
+fn cells(row: &str) -> Vec<&str> {
+    let mut cells = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in row.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '|' if !escaped => {
+                cells.push(row[start..i].trim());
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    cells.push(row[start..].trim());
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{descriptions, enabled_by_default};
+
+    #[test]
+    fn default_features() {
+        let features: BTreeMap<_, _> = [
+            ("default", &["std", "dep:log"][..]),
+            ("std", &["alloc", "serde?/std"]),
+            ("alloc", &[]),
+            ("serde", &["dep:serde"]),
+            ("log", &["dep:log"]),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.iter().map(|&s| s.to_owned()).collect()))
+        .collect();
+        assert_eq!(enabled_by_default(&features).into_iter().collect::<Vec<_>>(), ["alloc", "std"]);
+    }
+
+    #[test]
+    fn parse_descriptions() {
+        let s = "\
+<!-- header -->
+
+## `a`
+
+| Feature | Default | Enables | Description |
+| ------- | ------- | ------- | ----------- |
+| `std` | yes | `alloc` | Enables `std` \\| `alloc`. |
+| `alloc` | no |  |  |
+
+## `b`
+
+| Feature | Default | Enables | Description |
+| ------- | ------- | ------- | ----------- |
+| `std` | no | `serde` (optional dependency) | Uses std. |
+| `x` | no |
+";
+        let d = descriptions(s);
+        assert_eq!(d.len(), 2, "{d:?}");
+        assert_eq!(d[&("a", "std")], "Enables `std` \\| `alloc`.");
+        assert_eq!(d[&("b", "std")], "Uses std.");
+    }
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of `[package.metadata.cargo-hack.feature-min-versions]`.
+//
+// Some features may require a newer Rust than the rest of the package. With
+// --version-range (or --rust-version), the feature combinations that enable
+// such a feature, directly or via other features, are skipped on toolchains
+// older than the declared version instead of failing there.
+
+use std::collections::BTreeMap;
+
+use crate::{context::Context, features, version::Version, Kind, PackageRuns};
+
+/// Removes the runs on `pkg` that enable features requiring a newer toolchain
+/// than `toolchain`, or returns `None` if no runs remain.
+pub(crate) fn filter<'a>(
+    cx: &Context,
+    mut pkg: PackageRuns<'a>,
+    toolchain: Version,
+) -> Option<PackageRuns<'a>> {
+    let pkg_features = cx.pkg_features(pkg.id);
+    let too_new: BTreeMap<_, _> = cx
+        .manifests(pkg.id)
+        .config
+        .feature_min_versions
+        .iter()
+        .filter(|&(name, &version)| toolchain < version && pkg_features.contains(name))
+        .collect();
+    if too_new.is_empty() {
+        return Some(pkg);
+    }
+    let map = &cx.packages(pkg.id).features;
+    // Returns `true` if enabling the features (in addition to --features)
+    // enables any of the features in `too_new`.
+    let enables_too_new =
+        |enabled: &[&str]| {
+            enabled.iter().copied().chain(cx.features.iter().map(String::as_str)).any(|f| {
+                too_new.keys().any(|&t| f == t || features::enable_chain(map, f, t).is_some())
+            })
+        };
+
+    let total = pkg.feature_count;
+    if let Kind::Normal = pkg.kind {
+        let default = !cx.no_default_features && map.contains_key("default");
+        if enables_too_new(if default { &["default"] } else { &[] }) {
+            pkg.feature_count = 0;
+        }
+    } else if enables_too_new(&[]) {
+        // --features is applied to all runs, including the run with no default features.
+        pkg.feature_count = 0;
+    } else {
+        let combinations = match &mut pkg.kind {
+            Kind::Each { features } => {
+                features.retain(|f| !enables_too_new(&names(f.as_group())));
+                features.len()
+            }
+            Kind::Powerset { features } => {
+                features
+                    .retain(|fs| !enables_too_new(&names(fs.iter().flat_map(|f| f.as_group()))));
+                features.len()
+            }
+            Kind::Normal => unreachable!(),
+        };
+        // The run with all features always enables them.
+        pkg.skip_all_features = true;
+        pkg.feature_count = combinations
+            + usize::from(!cx.exclude_no_default_features && !pkg.skip_no_default_features);
+    }
+
+    let skipped = total - pkg.feature_count;
+    if skipped != 0 {
+        let list: Vec<_> =
+            too_new.iter().map(|(name, version)| format!("`{name}` ({version})")).collect();
+        info!(
+            "skipping {skipped} of {total} feature combinations of {} on {toolchain}, because \
+             they enable features that require a newer Rust: {} (feature-min-versions)",
+            cx.packages(pkg.id).name,
+            list.join(", ")
+        );
+    }
+    (pkg.feature_count != 0).then_some(pkg)
+}
+
+fn names<'a>(features: impl IntoIterator<Item = &'a String>) -> Vec<&'a str> {
+    features.into_iter().map(String::as_str).collect()
+}
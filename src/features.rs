@@ -1,17 +1,43 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fmt, slice,
 };
 
-use crate::{manifest::Manifest, metadata::Metadata, PackageId};
+use crate::{
+    manifest::Manifest,
+    metadata::{Dependency, Metadata, Package},
+    platform, term, PackageId,
+};
 
 #[derive(Debug)]
 pub(crate) struct Features {
     features: Vec<Feature>,
     optional_deps_start: usize,
     deps_features_start: usize,
+    /// --group-features, with `default` expanded to the default features of this package.
+    groups: Vec<Feature>,
+    /// Target-specific optional dependencies that are not used on any of the targets.
+    unused_optional_deps: Vec<String>,
+    /// Optional dependencies that are only referenced with `dep:` syntax, so
+    /// they are not features.
+    namespaced_optional_deps: Vec<String>,
+}
+
+/// The kind of a member of a group of --group-features in a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GroupMember {
+    /// A feature defined in `[features]`.
+    Normal,
+    /// The implicit feature of an optional dependency, which is only combined
+    /// with --optional-deps.
+    OptionalDep,
+    /// An optional dependency that is only referenced with `dep:` syntax,
+    /// which cannot be enabled by its name.
+    NamespacedOptionalDep,
+    Unknown,
 }
 
 impl Features {
@@ -20,6 +46,8 @@ impl Features {
         manifest: &Manifest,
         id: &PackageId,
         include_deps_features: bool,
+        group_features: &[Feature],
+        targets: &[String],
     ) -> Self {
         let package = &metadata.packages[id];
 
@@ -35,15 +63,31 @@ impl Features {
             }
         }
         let optional_deps_start = features.len();
+        let mut unused_optional_deps = vec![];
+        let mut namespaced_optional_deps = vec![];
         for name in package.optional_deps() {
             // Dependencies explicitly referenced with dep: are no longer implicit features.
+            // Note that they also cannot be enabled individually: cargo rejects
+            // `--features dep:<name>` ("feature `dep:<name>` is not allowed to use
+            // explicit `dep:` syntax"), so they are only tested via the features
+            // that reference them.
+            let feature = Feature::from(name);
+            if features.contains(&feature) {
+                continue;
+            }
             if referenced_deps.contains(name) {
+                if !namespaced_optional_deps.iter().any(|d| d == name) {
+                    namespaced_optional_deps.push(name.to_owned());
+                }
                 continue;
             }
-            let feature = Feature::from(name);
-            if !features.contains(&feature) {
-                features.push(feature);
+            if !is_used_on(package, name, targets) {
+                if !unused_optional_deps.iter().any(|d| d == name) {
+                    unused_optional_deps.push(name.to_owned());
+                }
+                continue;
             }
+            features.push(feature);
         }
         let deps_features_start = features.len();
 
@@ -66,7 +110,19 @@ impl Features {
             }
         }
 
-        Self { features, optional_deps_start, deps_features_start }
+        let groups = group_features
+            .iter()
+            .filter_map(|group| expand_default(group, manifest.features.get("default")))
+            .collect();
+
+        Self {
+            features,
+            optional_deps_start,
+            deps_features_start,
+            groups,
+            unused_optional_deps,
+            namespaced_optional_deps,
+        }
     }
 
     pub(crate) fn normal(&self) -> &[Feature] {
@@ -81,11 +137,158 @@ impl Features {
         &self.features[self.deps_features_start..]
     }
 
+    pub(crate) fn groups(&self) -> &[Feature] {
+        &self.groups
+    }
+
+    pub(crate) fn unused_optional_deps(&self) -> &[String] {
+        &self.unused_optional_deps
+    }
+
+    /// Returns the kind of `name` as a member of a group of --group-features.
+    pub(crate) fn group_member(&self, name: &str) -> GroupMember {
+        if self.normal().iter().any(|f| f == name) {
+            GroupMember::Normal
+        } else if self.optional_deps().iter().any(|f| f == name)
+            || self.unused_optional_deps.iter().any(|d| d == name)
+        {
+            GroupMember::OptionalDep
+        } else if self.namespaced_optional_deps.iter().any(|d| d == name) {
+            GroupMember::NamespacedOptionalDep
+        } else {
+            GroupMember::Unknown
+        }
+    }
+
+    /// Returns the origin of `feature` when it is passed by `--features` in
+    /// a combination of features.
+    pub(crate) fn origin(&self, feature: &Feature) -> Origin {
+        match feature {
+            Feature::Group { name, .. } => Origin::Group(name.clone()),
+            Feature::Path { .. } => Origin::DepFeature,
+            Feature::Normal { .. } if self.optional_deps().contains(feature) => Origin::OptionalDep,
+            Feature::Normal { .. } => Origin::Iterated,
+        }
+    }
+
     pub(crate) fn contains(&self, name: &str) -> bool {
         self.features.iter().any(|f| f == name)
     }
 }
 
+/// Returns `false` if all declarations of the optional dependency `name` are
+/// specific to platforms that do not match any of `targets`.
+///
+/// If `targets` is empty, or a platform cannot be evaluated, the dependency is
+/// assumed to be used.
+fn is_used_on(package: &Package, name: &str, targets: &[String]) -> bool {
+    if targets.is_empty() {
+        return true;
+    }
+    let mut unknown = None;
+    for dep in package.dependencies.iter().filter(|d| d.as_feature() == Some(name)) {
+        let Some(platform) = &dep.target else { return true };
+        for target in targets {
+            match platform::matches(platform, target) {
+                Some(true) => return true,
+                Some(false) => {}
+                None => unknown = Some(platform),
+            }
+        }
+    }
+    if let Some(platform) = unknown {
+        info!(
+            "unable to determine whether optional dependency `{name}` of package `{}` is used on \
+             {} (`{platform}`); treating it as a feature",
+            package.name,
+            targets.join(", ")
+        );
+        return true;
+    }
+    if term::verbose() {
+        info!(
+            "skipped optional dependency `{name}` of package `{}` that is not used on {}",
+            package.name,
+            targets.join(", ")
+        );
+    }
+    false
+}
+
+/// Replaces `default` in the group with the features that `default` directly
+/// enables, or removes it if the package has no `default` feature.
+///
+/// Returns `None` if the group becomes empty.
+fn expand_default(group: &Feature, default: Option<&Vec<String>>) -> Option<Feature> {
+    if !group.matches("default") {
+        return Some(Feature::group(group.as_group()));
+    }
+    let mut list: Vec<&str> = vec![];
+    for f in group.as_group() {
+        if f == "default" {
+            // `dep:` entries cannot be enabled by --features.
+            let members = default.into_iter().flatten().filter(|f| !f.starts_with("dep:"));
+            for f in members {
+                if !list.contains(&&**f) {
+                    list.push(f);
+                }
+            }
+        } else if !list.contains(&&**f) {
+            list.push(f);
+        }
+    }
+    if list.is_empty() {
+        None
+    } else {
+        Some(Feature::group(list))
+    }
+}
+
+/// The keyword of --include-features that expands to the direct members of
+/// the `default` feature of each package.
+pub(crate) const INCLUDE_DEFAULTS: &str = "@defaults";
+/// The keyword of --include-features that expands to all features of each
+/// package except optional dependencies.
+pub(crate) const INCLUDE_ALL: &str = "@all";
+
+/// Expands the keywords of --include-features for a package.
+///
+/// `default` is the value of the `default` feature of the package. Features
+/// that appear multiple times after the expansion are included only once.
+pub(crate) fn expand_include<'a>(
+    include: &'a [Feature],
+    pkg_features: &'a Features,
+    default: Option<&Vec<String>>,
+) -> Vec<&'a Feature> {
+    let mut list: Vec<&Feature> = vec![];
+    let mut push = |f: &'a Feature| {
+        if !list.contains(&f) {
+            list.push(f);
+        }
+    };
+    for f in include {
+        match f.name() {
+            INCLUDE_DEFAULTS => {
+                // `dep:` entries and features of dependencies (`dep/feature`)
+                // cannot be combined as features of the package.
+                for name in default.into_iter().flatten() {
+                    if let Some(f) = pkg_features
+                        .normal()
+                        .iter()
+                        .chain(pkg_features.optional_deps())
+                        .find(|f| *f == name)
+                    {
+                        push(f);
+                    }
+                }
+            }
+            INCLUDE_ALL => pkg_features.normal().iter().for_each(&mut push),
+            _ => push(f),
+        }
+    }
+    list
+}
+
 /// The representation of Cargo feature.
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) enum Feature {
@@ -204,95 +407,428 @@ impl AsRef<str> for Feature {
     }
 }
 
+/// Checks that `name` is a valid feature name, and returns the reason if not.
+///
+/// This accepts the same names as cargo: the first character must be a
+/// letter, a digit, or `_`, and the other characters must be letters, digits,
+/// `_`, `-`, `+`, or `.`.
+pub(crate) fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return Err("feature names may not be empty".to_owned());
+    };
+    if !(first.is_alphanumeric() || first == '_') {
+        return Err(format!(
+            "the first character `{first}` is not allowed; feature names must start with a \
+             letter, a digit, or `_`"
+        ));
+    }
+    if let Some(c) = chars.find(|&c| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '+' | '.'))) {
+        return Err(format!(
+            "the character `{c}` is not allowed; feature names may only contain letters, \
+             digits, `_`, `-`, `+`, and `.`"
+        ));
+    }
+    Ok(())
+}
+
+/// Quotes the feature name if it contains characters other than ASCII
+/// letters, digits, `-`, and `_` (or `/` between a dependency and its
+/// feature), so that it is not confused with the surrounding punctuation in
+/// messages (e.g., `1.0` or `a+b`).
+pub(crate) fn quote(name: &str) -> Cow<'_, str> {
+    if name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '/')) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{name}\""))
+    }
+}
+
+/// Where a feature passed by `--features` comes from.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Origin {
+    /// Specified by `--features`.
+    UserFeatures,
+    /// A feature in the combination of features.
+    Iterated,
+    /// A member of a group specified by `--group-features`.
+    Group(String),
+    /// An optional dependency in the combination of features.
+    OptionalDep,
+    /// A feature of a dependency (`--include-deps-features`).
+    DepFeature,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UserFeatures => f.write_str("--features"),
+            Self::Iterated => f.write_str("iterated"),
+            Self::Group(name) => {
+                let list: Vec<_> = name.split(',').map(quote).collect();
+                write!(f, "group {}", list.join(","))
+            }
+            Self::OptionalDep => f.write_str("optional dependency"),
+            Self::DepFeature => f.write_str("dependency feature"),
+        }
+    }
+}
+
+/// Returns the combinations of features to run.
+///
+/// If `removed` is `Some`, the combinations removed from the powerset are
+/// recorded to it with the reason for each. Combinations exceeding --depth or
+/// --max-group-combo are not generated in the first place, so they are not
+/// recorded.
 pub(crate) fn feature_powerset<'a>(
     features: impl IntoIterator<Item = &'a Feature>,
     depth: Option<usize>,
+    depth_counts_group_members: bool,
+    max_group_combo: Option<usize>,
     at_least_one_of: &[Feature],
     mutually_exclusive_features: &[Feature],
+    exactly_one_of: &[Feature],
     package_features: &BTreeMap<String, Vec<String>>,
+    mut removed: Option<&mut Vec<(Vec<&'a Feature>, Removed)>>,
 ) -> Vec<Vec<&'a Feature>> {
     let deps_map = feature_deps(package_features);
-    let at_least_one_of = at_least_one_of_for_package(at_least_one_of, &deps_map);
-
-    powerset(features, depth)
-        .into_iter()
-        .skip(1) // The first element of a powerset is `[]` so it should be skipped.
-        .filter(|fs| {
-            !fs.iter().any(|f| {
-                f.as_group().iter().filter_map(|f| deps_map.get(&&**f)).any(|deps| {
-                    fs.iter().any(|f| f.as_group().iter().all(|f| deps.contains(&&**f)))
-                })
-            })
-        })
-        .filter(move |fs| {
-            // all() returns true if at_least_one_of is empty
-            at_least_one_of.iter().all(|required_set| {
-                fs
-                    .iter()
-                    .flat_map(|f| f.as_group())
-                    .any(|f| required_set.contains(f.as_str()))
-            })
-        })
-        .filter(move |fs| {
-            // Filter any feature set containing more than one feature from the same mutually
-            // exclusive group.
-            for group in mutually_exclusive_features {
-                let mut count = 0;
-                for f in fs.iter().flat_map(|f| f.as_group()) {
-                    if group.matches_recursive(f, package_features) {
-                        count += 1;
-                        if count > 1 {
-                            return false;
-                        }
-                    }
+    // --exactly-one-of is the combination of --at-least-one-of and
+    // --mutually-exclusive-features with the same list.
+    let mut at_least_one_of = at_least_one_of_for_package(at_least_one_of, &deps_map);
+    let exactly_one_of_start = at_least_one_of.len();
+    at_least_one_of.extend(at_least_one_of_for_package(exactly_one_of, &deps_map));
+
+    let removal_reason = |fs: &[&Feature]| -> Option<Removed> {
+        for f in fs.iter().flat_map(|f| f.as_group()) {
+            let Some(deps) = deps_map.get(f.as_str()) else { continue };
+            if let Some(implied) =
+                fs.iter().find(|g| g.as_group().iter().all(|g| deps.contains(g.as_str())))
+            {
+                return Some(Removed::Implied {
+                    feature: f.clone(),
+                    implied: implied.as_group().join(","),
+                });
+            }
+        }
+        if let Some(i) = at_least_one_of.iter().position(|required_set| {
+            !fs.iter().flat_map(|f| f.as_group()).any(|f| required_set.contains(f.as_str()))
+        }) {
+            return Some(Removed::AtLeastOneOf {
+                flag: if i < exactly_one_of_start { "at-least-one-of" } else { "exactly-one-of" },
+                required: at_least_one_of[i].iter().map(|&f| f.to_owned()).collect(),
+            });
+        }
+        // Remove any feature set containing more than one feature from the same mutually
+        // exclusive group.
+        let groups = mutually_exclusive_features
+            .iter()
+            .map(|g| ("mutually-exclusive-features", g))
+            .chain(exactly_one_of.iter().map(|g| ("exactly-one-of", g)));
+        for (flag, group) in groups {
+            let mut matched = fs
+                .iter()
+                .flat_map(|f| f.as_group())
+                .filter(|f| group.matches_recursive(f, package_features));
+            if let (Some(a), Some(b)) = (matched.next(), matched.next()) {
+                return Some(Removed::MutuallyExclusive {
+                    flag,
+                    group: group.as_group().to_vec(),
+                    features: [a.clone(), b.clone()],
+                });
+            }
+        }
+        None
+    };
+
+    // With --depth-counts-group-members, a group counts as the number of its
+    // members toward --depth, instead of 1.
+    let weight = |f: &Feature| if depth_counts_group_members { f.as_group().len() } else { 1 };
+    let mut kept = vec![];
+    // The first element of a powerset is `[]` so it should be skipped.
+    // With --max-group-combo, combinations containing more than the specified
+    // number of groups of --group-features are not run.
+    let too_many_groups =
+        |fs: &[&Feature]| max_group_combo.is_some_and(|max| group_count(fs) > max);
+    for fs in weighted_powerset(features, depth, weight).skip(1) {
+        if too_many_groups(&fs) {
+            continue;
+        }
+        match removal_reason(&fs) {
+            Some(reason) => {
+                if let Some(removed) = &mut removed {
+                    removed.push((fs, reason));
                 }
             }
-            true
-        })
-        .collect()
-}
-
-fn feature_deps(map: &BTreeMap<String, Vec<String>>) -> BTreeMap<&str, BTreeSet<&str>> {
-    fn rec<'a>(
-        map: &'a BTreeMap<String, Vec<String>>,
-        set: &mut BTreeSet<&'a str>,
-        cur: &str,
-        root: &str,
-    ) {
-        if let Some(v) = map.get(cur) {
-            for cur in v {
+            None => kept.push(fs),
+        }
+    }
+    kept
+}
+
+/// Returns the number of groups of --group-features in the combination.
+pub(crate) fn group_count(fs: &[&Feature]) -> usize {
+    fs.iter().filter(|f| matches!(f, Feature::Group { .. })).count()
+}
+
+/// The reason why a combination of features is removed from the powerset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Removed {
+    /// `feature` enables `implied`, so the combination is the same as the one
+    /// without `implied`.
+    Implied { feature: String, implied: String },
+    /// The combination enables none of the `required` features, which are
+    /// the features of --at-least-one-of (or --exactly-one-of) and the
+    /// features that enable them.
+    AtLeastOneOf { flag: &'static str, required: Vec<String> },
+    /// The combination contains more than one feature from the group of
+    /// --mutually-exclusive-features (or --exactly-one-of).
+    MutuallyExclusive { flag: &'static str, group: Vec<String>, features: [String; 2] },
+    /// The combination enables the same features as the combination
+    /// `same_as`, because groups overlap.
+    Overlap { same_as: String },
+}
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Implied { feature, implied } => {
+                write!(f, "deduplicated because `{feature}` implies `{implied}`")
+            }
+            Self::AtLeastOneOf { flag, required } => {
+                write!(f, "removed by --{flag} because it enables none of `{}`", required.join(","))
+            }
+            Self::MutuallyExclusive { flag, group, features: [a, b] } => write!(
+                f,
+                "removed by --{flag} because `{a}` and `{b}` are in the same group `{}`",
+                group.join(",")
+            ),
+            Self::Overlap { same_as } => {
+                write!(f, "merged because `{same_as}` enables the same features (groups overlap)")
+            }
+        }
+    }
+}
+
+/// Removes the combinations that enable the same features as an earlier
+/// combination, and returns the number of removed combinations.
+///
+/// This happens when groups overlap, e.g., with groups `a,b`, `b,c`, and
+/// `a,c`, any two of them enable `a`, `b`, and `c`.
+pub(crate) fn merge_overlapping<'a>(
+    features: &mut Vec<Vec<&'a Feature>>,
+    mut removed: Option<&mut Vec<(Vec<&'a Feature>, Removed)>>,
+) -> usize {
+    let before = features.len();
+    let mut seen: BTreeMap<BTreeSet<&'a str>, String> = BTreeMap::new();
+    features.retain(|fs| {
+        let enabled = fs.iter().copied().flat_map(Feature::as_group).map(String::as_str).collect();
+        if let Some(same_as) = seen.get(&enabled) {
+            if let Some(removed) = &mut removed {
+                removed.push((fs.clone(), Removed::Overlap { same_as: same_as.clone() }));
+            }
+            return false;
+        }
+        seen.insert(enabled, fs.iter().map(|f| f.name()).collect::<Vec<_>>().join(" + "));
+        true
+    });
+    before - features.len()
+}
+
+/// Rewrites the dependency features (`name/feature` and `name?/feature`) in the
+/// values of `map` that refer to a renamed dependency by its package name to
+/// refer to it by its name in the manifest, which is the name used by
+/// `Feature::Path`.
+///
+/// Depending on the version of cargo, `packages[].features` of `cargo metadata`
+/// spells the dependency feature `et/std` of `et = { package = "easytime" }`
+/// either as written or as `easytime/std`, and features that enable each other
+/// are only deduplicated if both sides use the same spelling.
+pub(crate) fn normalize_dep_features<'a>(
+    map: &'a BTreeMap<String, Vec<String>>,
+    dependencies: &[Dependency],
+) -> Cow<'a, BTreeMap<String, Vec<String>>> {
+    let mut renames: BTreeMap<&str, Option<&str>> = BTreeMap::new();
+    for d in dependencies {
+        if let Some(rename) = &d.rename {
+            // A package renamed to different names cannot be mapped to one of them.
+            renames
+                .entry(&d.name)
+                .and_modify(|r| {
+                    if *r != Some(rename) {
+                        *r = None;
+                    }
+                })
+                .or_insert(Some(rename));
+        }
+    }
+    // The package name is also the name of a dependency that is not renamed.
+    for d in dependencies.iter().filter(|d| d.rename.is_none()) {
+        renames.remove(d.name.as_str());
+    }
+    let rename = |f: &str| {
+        let (dep, feature) = f.split_once('/')?;
+        let (name, weak) = dep.strip_suffix('?').map_or((dep, ""), |name| (name, "?"));
+        let rename = (*renames.get(name)?)?;
+        Some(format!("{rename}{weak}/{feature}"))
+    };
+    if !map.values().flatten().any(|f| rename(f).is_some()) {
+        return Cow::Borrowed(map);
+    }
+    Cow::Owned(
+        map.iter()
+            .map(|(k, v)| {
+                (k.clone(), v.iter().map(|f| rename(f).unwrap_or_else(|| f.clone())).collect())
+            })
+            .collect(),
+    )
+}
+
+/// Returns the features enabled by each feature, directly or via other features.
+///
+/// A feature is not included in its own set even if it is in a cycle, but the
+/// other members of the cycle are, so the members of a cycle imply each other.
+pub(crate) fn feature_deps(map: &BTreeMap<String, Vec<String>>) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut feat_deps = BTreeMap::new();
+    for root in map.keys() {
+        let mut set = BTreeSet::new();
+        let mut stack = vec![root.as_str()];
+        while let Some(cur) = stack.pop() {
+            for next in map.get(cur).into_iter().flatten() {
                 // dep: actions aren't features, and can't enable other features in the same crate
-                if cur.starts_with("dep:") {
+                if next.starts_with("dep:") {
                     continue;
                 }
-                if cur != root && set.insert(cur) {
-                    rec(map, set, cur, root);
+                if next != root && set.insert(next.as_str()) {
+                    stack.push(next);
                 }
             }
         }
-    }
-    let mut feat_deps = BTreeMap::new();
-    for feat in map.keys() {
-        let mut set = BTreeSet::new();
-        rec(map, &mut set, feat, feat);
-        feat_deps.insert(&**feat, set);
+        feat_deps.insert(root.as_str(), set);
     }
     feat_deps
 }
 
-fn powerset<T: Copy>(iter: impl IntoIterator<Item = T>, depth: Option<usize>) -> Vec<Vec<T>> {
-    iter.into_iter().fold(vec![vec![]], |mut acc, elem| {
-        let ext = acc.clone().into_iter().map(|mut cur| {
-            cur.push(elem);
-            cur
-        });
-        if let Some(depth) = depth {
-            acc.extend(ext.filter(|f| f.len() <= depth));
-        } else {
-            acc.extend(ext);
+/// Returns the features that enable each other in a cycle, i.e., the strongly
+/// connected components of the features with more than one feature or with a
+/// feature that enables itself, e.g., `[["a", "b", "c"]]`.
+///
+/// Cargo rejects such features, but they may slip past old cargo through
+/// weak dependency features or hand-edited metadata.
+pub(crate) fn feature_cycles(map: &BTreeMap<String, Vec<String>>) -> Vec<Vec<&str>> {
+    let deps_map = feature_deps(map);
+    let mut seen = BTreeSet::new();
+    let mut cycles = vec![];
+    for (&f, deps) in &deps_map {
+        if seen.contains(f) {
+            continue;
+        }
+        // The features are visited in order, so `f` is the first member of its cycle.
+        let mut cycle = vec![f];
+        cycle.extend(
+            deps.iter().copied().filter(|&g| deps_map.get(g).is_some_and(|d| d.contains(f))),
+        );
+        if cycle.len() > 1 || map[f].iter().any(|g| g == f) {
+            seen.extend(cycle.iter().copied());
+            cycles.push(cycle);
         }
-        acc
-    })
+    }
+    cycles
+}
+
+/// Returns the shortest chain of features through which `from` enables `to`,
+/// e.g., `["full", "serde"]`, or `None` if `from` does not enable `to`.
+///
+/// This follows the same rules as `feature_deps`.
+pub(crate) fn enable_chain<'a>(
+    map: &'a BTreeMap<String, Vec<String>>,
+    from: &'a str,
+    to: &str,
+) -> Option<Vec<&'a str>> {
+    let mut parents: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(cur) = queue.pop_front() {
+        for next in map.get(cur).into_iter().flatten() {
+            if next.starts_with("dep:") || next == from || parents.contains_key(&**next) {
+                continue;
+            }
+            parents.insert(next, cur);
+            if next == to {
+                let mut chain = vec![&**next];
+                let mut cur = &**next;
+                while let Some(&parent) = parents.get(cur) {
+                    chain.push(parent);
+                    cur = parent;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            queue.push_back(next);
+        }
+    }
+    None
+}
+
+/// Returns the subsets of the elements with at most `depth` elements, in the
+/// order of binary counting (the `i`-th element is the `i`-th bit), starting
+/// with the empty set.
+///
+/// The subsets are generated lazily, so subsets that are not kept by the
+/// caller are never allocated at the same time.
+pub(crate) fn powerset<T: Copy>(
+    iter: impl IntoIterator<Item = T>,
+    depth: Option<usize>,
+) -> Powerset<T> {
+    weighted_powerset(iter, depth, |_| 1)
+}
+
+/// Like [`powerset`], but the size of each set compared with `depth` is the
+/// sum of the weights of its elements.
+fn weighted_powerset<T: Copy>(
+    iter: impl IntoIterator<Item = T>,
+    depth: Option<usize>,
+    weight: impl Fn(T) -> usize,
+) -> Powerset<T> {
+    let elems: Vec<_> = iter.into_iter().collect();
+    let weights = elems.iter().map(|&elem| weight(elem)).collect();
+    Powerset { elems, weights, depth: depth.unwrap_or(usize::MAX), bits: None }
+}
+
+/// The iterator returned by [`powerset`].
+pub(crate) struct Powerset<T> {
+    elems: Vec<T>,
+    weights: Vec<usize>,
+    depth: usize,
+    /// The elements in the last subset, or `None` if no subset has been
+    /// returned yet.
+    bits: Option<Vec<bool>>,
+}
+
+impl<T: Copy> Iterator for Powerset<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(bits) = &mut self.bits else {
+            self.bits = Some(vec![false; self.elems.len()]);
+            return Some(vec![]);
+        };
+        // The next subset in the order of binary counting has the same
+        // elements as the current one after some bit `j` that is not set,
+        // `j` set, and no elements before `j`. Take the lowest `j` for which
+        // the subset is within the depth; the subsets in between exceed it.
+        let mut above = vec![0; bits.len()];
+        let mut sum = 0_usize;
+        for j in (0..bits.len()).rev() {
+            above[j] = sum;
+            if bits[j] {
+                sum = sum.saturating_add(self.weights[j]);
+            }
+        }
+        let j = (0..bits.len())
+            .find(|&j| !bits[j] && above[j].saturating_add(self.weights[j]) <= self.depth)?;
+        bits[..j].fill(false);
+        bits[j] = true;
+        Some(self.elems.iter().zip(&*bits).filter(|(_, &b)| b).map(|(&elem, _)| elem).collect())
+    }
 }
 
 // Leave only features that are possible to enable in the package.
@@ -330,9 +866,17 @@ pub(crate) fn at_least_one_of_for_package<'a>(
 
 #[cfg(test)]
 mod tests {
-    use std::collections::{BTreeMap, BTreeSet};
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        time::{Duration, Instant},
+    };
 
-    use super::{at_least_one_of_for_package, feature_deps, feature_powerset, powerset, Feature};
+    use super::{
+        at_least_one_of_for_package, check_name, enable_chain, feature_cycles, feature_deps,
+        feature_powerset, group_count, merge_overlapping, normalize_dep_features, powerset, quote,
+        Feature, Origin, Removed,
+    };
+    use crate::metadata::Dependency;
 
     macro_rules! v {
         ($($expr:expr),* $(,)?) => {
@@ -361,27 +905,149 @@ mod tests {
         assert_eq!(filtered, vec![set!("b", "c", "d")]);
     }
 
+    #[test]
+    fn renamed_dep_features() {
+        let dep = |name: &str, rename: Option<&str>| Dependency {
+            name: name.to_owned(),
+            kind: None,
+            optional: false,
+            target: None,
+            rename: rename.map(str::to_owned),
+        };
+        let deps = [dep("easytime", Some("et")), dep("serde", None)];
+        let list = vec![Feature::from("std"), Feature::path("et", "std")];
+        // Both spellings reported by cargo are deduplicated.
+        for spelling in ["et/std", "easytime/std"] {
+            let map = map![("std", v![spelling, "serde/std"])];
+            let map = normalize_dep_features(&map, &deps);
+            assert_eq!(map["std"], vec!["et/std", "serde/std"]);
+            let filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+            assert_eq!(filtered, vec![vec!["std"], vec!["et/std"]]);
+        }
+        let map = map![("std", v!["easytime?/std", "dep:easytime"])];
+        assert_eq!(normalize_dep_features(&map, &deps)["std"], vec!["et?/std", "dep:easytime"]);
+        // The package is also a dependency that is not renamed.
+        let deps = [dep("easytime", Some("et")), dep("easytime", None)];
+        let map = map![("std", v!["easytime/std"])];
+        assert_eq!(normalize_dep_features(&map, &deps)["std"], vec!["easytime/std"]);
+        // The package is renamed to different names.
+        let deps = [dep("easytime", Some("et")), dep("easytime", Some("et2"))];
+        assert_eq!(normalize_dep_features(&map, &deps)["std"], vec!["easytime/std"]);
+    }
+
+    #[test]
+    fn enable_chain_path() {
+        let map = map![
+            ("full", v!["std", "derive"]),
+            ("std", v!["alloc", "dep:serde"]),
+            ("derive", v!["serde", "alloc"]),
+            ("alloc", v![]),
+            ("serde", v![]),
+            ("cycle", v!["full", "cycle"])
+        ];
+        assert_eq!(enable_chain(&map, "full", "alloc"), Some(vec!["full", "std", "alloc"]));
+        assert_eq!(enable_chain(&map, "full", "serde"), Some(vec!["full", "derive", "serde"]));
+        assert_eq!(enable_chain(&map, "std", "alloc"), Some(vec!["std", "alloc"]));
+        // `dep:` is not a feature.
+        assert_eq!(enable_chain(&map, "std", "serde"), None);
+        assert_eq!(enable_chain(&map, "alloc", "full"), None);
+        assert_eq!(enable_chain(&map, "unknown", "full"), None);
+        assert_eq!(enable_chain(&map, "cycle", "cycle"), None);
+        assert_eq!(
+            enable_chain(&map, "cycle", "alloc"),
+            Some(vec!["cycle", "full", "std", "alloc"])
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        // a -> b -> c -> a, with `dep:` and weak dependency features mixed in.
+        let map = map![
+            ("a", v!["b", "dep:x"]),
+            ("b", v!["c", "x?/f"]),
+            ("c", v!["a", "x/g"]),
+            ("d", v!["a"]),
+            ("x", v!["dep:x"])
+        ];
+        assert_eq!(feature_cycles(&map), vec![vec!["a", "b", "c"]]);
+        let fd = feature_deps(&map);
+        assert_eq!(fd["a"], set!["b", "c", "x/g", "x?/f"]);
+        assert_eq!(fd["b"], set!["a", "c", "x/g", "x?/f"]);
+        assert_eq!(fd["d"], set!["a", "b", "c", "x/g", "x?/f"]);
+        // The members of the cycle imply each other.
+        let list = v!["a", "b", "c", "d", "x"];
+        let filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![
+            vec!["a"],
+            vec!["b"],
+            vec!["c"],
+            vec!["d"],
+            vec!["x"],
+            vec!["a", "x"],
+            vec!["b", "x"],
+            vec!["c", "x"],
+            vec!["d", "x"],
+        ]);
+
+        // a -> b -> c -> b: the cycle does not include a.
+        let map = map![("a", v!["b"]), ("b", v!["c"]), ("c", v!["b"]), ("e", v!["e"])];
+        assert_eq!(feature_cycles(&map), vec![vec!["b", "c"], vec!["e"]]);
+        let fd = feature_deps(&map);
+        assert_eq!(fd["a"], set!["b", "c"]);
+        assert_eq!(fd["b"], set!["c"]);
+        assert_eq!(fd["c"], set!["b"]);
+        assert_eq!(fd["e"], set![]);
+        let list = v!["a", "b", "c"];
+        let filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![vec!["a"], vec!["b"], vec!["c"]]);
+
+        let map = map![("a", v![]), ("b", v!["a"]), ("c", v!["b"]), ("d", v!["a", "b"])];
+        assert!(feature_cycles(&map).is_empty());
+    }
+
     #[test]
     fn powerset_with_filter() {
         let map = map![("a", v![]), ("b", v!["a"]), ("c", v!["b"]), ("d", v!["a", "b"])];
 
         let list = v!["a", "b", "c", "d"];
-        let filtered = feature_powerset(&list, None, &[], &[], &map);
+        let filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
         assert_eq!(filtered, vec![vec!["a"], vec!["b"], vec!["c"], vec!["d"], vec!["c", "d"]]);
 
-        let filtered = feature_powerset(&list, None, &["a".into()], &[], &map);
+        let filtered =
+            feature_powerset(&list, None, false, None, &["a".into()], &[], &[], &map, None);
         assert_eq!(filtered, vec![vec!["a"], vec!["b"], vec!["c"], vec!["d"], vec!["c", "d"]]);
 
-        let filtered = feature_powerset(&list, None, &["c".into()], &[], &map);
+        let filtered =
+            feature_powerset(&list, None, false, None, &["c".into()], &[], &[], &map, None);
         assert_eq!(filtered, vec![vec!["c"], vec!["c", "d"]]);
 
-        let filtered = feature_powerset(&list, None, &["a".into(), "c".into()], &[], &map);
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &["a".into(), "c".into()],
+            &[],
+            &[],
+            &map,
+            None,
+        );
         assert_eq!(filtered, vec![vec!["c"], vec!["c", "d"]]);
 
         let map = map![("tokio", v![]), ("async-std", v![]), ("a", v![]), ("b", v!["a"])];
         let list = v!["a", "b", "tokio", "async-std"];
         let mutually_exclusive_features = [Feature::group(["tokio", "async-std"])];
-        let filtered = feature_powerset(&list, None, &[], &mutually_exclusive_features, &map);
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &[],
+            &mutually_exclusive_features,
+            &[],
+            &map,
+            None,
+        );
         assert_eq!(filtered, vec![
             vec!["a"],
             vec!["b"],
@@ -395,7 +1061,17 @@ mod tests {
 
         let mutually_exclusive_features =
             [Feature::group(["tokio", "a"]), Feature::group(["tokio", "async-std"])];
-        let filtered = feature_powerset(&list, None, &[], &mutually_exclusive_features, &map);
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &[],
+            &mutually_exclusive_features,
+            &[],
+            &map,
+            None,
+        );
         assert_eq!(filtered, vec![
             vec!["a"],
             vec!["b"],
@@ -413,7 +1089,17 @@ mod tests {
         ];
         let list = v!["a", "b", "tokio", "async-std"];
         let mutually_exclusive_features = [Feature::group(["tokio", "async-std"])];
-        let filtered = feature_powerset(&list, None, &[], &mutually_exclusive_features, &map);
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &[],
+            &mutually_exclusive_features,
+            &[],
+            &map,
+            None,
+        );
         assert_eq!(filtered, vec![
             vec!["a"],
             vec!["b"],
@@ -427,10 +1113,164 @@ mod tests {
         let map = map![("a", v![]), ("b", v!["a"]), ("c", v![]), ("d", v!["b"])];
         let list = v!["a", "b", "c", "d"];
         let mutually_exclusive_features = [Feature::group(["a", "c"])];
-        let filtered = feature_powerset(&list, None, &[], &mutually_exclusive_features, &map);
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &[],
+            &mutually_exclusive_features,
+            &[],
+            &map,
+            None,
+        );
         assert_eq!(filtered, vec![vec!["a"], vec!["b"], vec!["c"], vec!["d"]]);
     }
 
+    #[test]
+    fn powerset_removal_reasons() {
+        fn reason<'a>(
+            removed: &'a [(Vec<&Feature>, Removed)],
+            combination: &[&str],
+        ) -> Option<&'a Removed> {
+            removed
+                .iter()
+                .find(|(fs, _)| fs.iter().map(|f| f.name()).eq(combination.iter().copied()))
+                .map(|(_, reason)| reason)
+        }
+
+        let map = map![("a", v![]), ("b", v!["a"]), ("c", v!["b"]), ("d", v!["a", "b"])];
+        let list = v!["a", "b", "c", "d"];
+        let mut removed = vec![];
+        let filtered = feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &["c".into()],
+            &[],
+            &[],
+            &map,
+            Some(&mut removed),
+        );
+        assert_eq!(filtered, vec![vec!["c"], vec!["c", "d"]]);
+        assert_eq!(removed.len(), 15 - filtered.len());
+        assert_eq!(
+            reason(&removed, &["a", "b"]),
+            Some(&Removed::Implied { feature: "b".into(), implied: "a".into() })
+        );
+        // `c` enables `a` via `b`.
+        assert_eq!(
+            reason(&removed, &["a", "c"]),
+            Some(&Removed::Implied { feature: "c".into(), implied: "a".into() })
+        );
+        assert_eq!(
+            reason(&removed, &["d"]),
+            Some(&Removed::AtLeastOneOf { flag: "at-least-one-of", required: v!["c"] })
+        );
+        assert_eq!(reason(&removed, &["c", "d"]), None);
+        assert_eq!(
+            reason(&removed, &["a", "b"]).unwrap().to_string(),
+            "deduplicated because `b` implies `a`"
+        );
+
+        let map = map![("tokio", v![]), ("async-std", v![]), ("a", v![]), ("b", v!["a"])];
+        let list = v!["a", "b", "tokio", "async-std"];
+        let mutually_exclusive_features = [Feature::group(["tokio", "async-std"])];
+        let mut removed = vec![];
+        feature_powerset(
+            &list,
+            Some(2),
+            false,
+            None,
+            &[],
+            &mutually_exclusive_features,
+            &[],
+            &map,
+            Some(&mut removed),
+        );
+        assert_eq!(
+            reason(&removed, &["tokio", "async-std"]),
+            Some(&Removed::MutuallyExclusive {
+                flag: "mutually-exclusive-features",
+                group: v!["tokio", "async-std"],
+                features: ["tokio".into(), "async-std".into()],
+            })
+        );
+        assert_eq!(
+            reason(&removed, &["tokio", "async-std"]).unwrap().to_string(),
+            "removed by --mutually-exclusive-features because `tokio` and `async-std` are in the \
+             same group `tokio,async-std`"
+        );
+        // Combinations exceeding --depth are not generated.
+        assert_eq!(reason(&removed, &["a", "tokio", "async-std"]), None);
+
+        let mut removed = vec![];
+        feature_powerset(
+            &list,
+            None,
+            false,
+            None,
+            &[],
+            &[],
+            &mutually_exclusive_features,
+            &map,
+            Some(&mut removed),
+        );
+        assert_eq!(
+            reason(&removed, &["b"]),
+            Some(&Removed::AtLeastOneOf {
+                flag: "exactly-one-of",
+                required: v!["async-std", "tokio"],
+            })
+        );
+        assert_eq!(
+            reason(&removed, &["a", "tokio", "async-std"]),
+            Some(&Removed::MutuallyExclusive {
+                flag: "exactly-one-of",
+                group: v!["tokio", "async-std"],
+                features: ["tokio".into(), "async-std".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn exactly_one_of() {
+        let exactly_one_of = [Feature::group(["tokio", "async-std"])];
+
+        // A member is in the default features.
+        let map = map![("default", v!["tokio"]), ("a", v![]), ("tokio", v![]), ("async-std", v![])];
+        let list = v!["default", "a", "tokio", "async-std"];
+        let filtered =
+            feature_powerset(&list, None, false, None, &[], &[], &exactly_one_of, &map, None);
+        assert_eq!(filtered, vec![
+            vec!["default"],
+            vec!["default", "a"],
+            vec!["tokio"],
+            vec!["a", "tokio"],
+            vec!["async-std"],
+            vec!["a", "async-std"]
+        ]);
+
+        // A feature that transitively enables a member counts as selecting it.
+        let map =
+            map![("full", v!["rt"]), ("rt", v!["tokio"]), ("tokio", v![]), ("async-std", v![])];
+        let list = v!["full", "tokio", "async-std"];
+        let filtered =
+            feature_powerset(&list, None, false, None, &[], &[], &exactly_one_of, &map, None);
+        assert_eq!(filtered, vec![vec!["full"], vec!["tokio"], vec!["async-std"]]);
+        // --at-least-one-of alone allows enabling both.
+        let filtered =
+            feature_powerset(&list, None, false, None, &exactly_one_of, &[], &[], &map, None);
+        assert_eq!(filtered, vec![
+            vec!["full"],
+            vec!["tokio"],
+            vec!["async-std"],
+            vec!["full", "async-std"],
+            vec!["tokio", "async-std"]
+        ]);
+    }
+
     #[test]
     fn feature_deps1() {
         let map = map![("a", v![]), ("b", v!["a"]), ("c", v!["b"]), ("d", v!["a", "b"])];
@@ -442,7 +1282,7 @@ mod tests {
             ("d", set!["a", "b"])
         ]);
         let list: Vec<Feature> = v!["a", "b", "c", "d"];
-        let ps = powerset(&list, None);
+        let ps = powerset(&list, None).collect::<Vec<_>>();
         assert_eq!(ps, vec![
             vec![],
             vec!["a"],
@@ -461,13 +1301,13 @@ mod tests {
             vec!["b", "c", "d"],
             vec!["a", "b", "c", "d"],
         ]);
-        let filtered = feature_powerset(&list, None, &[], &[], &map);
+        let filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
         assert_eq!(filtered, vec![vec!["a"], vec!["b"], vec!["c"], vec!["d"], vec!["c", "d"]]);
     }
 
     #[test]
     fn powerset_full() {
-        let v = powerset(vec![1, 2, 3, 4], None);
+        let v = powerset(vec![1, 2, 3, 4], None).collect::<Vec<_>>();
         assert_eq!(v, vec![
             vec![],
             vec![1],
@@ -490,13 +1330,13 @@ mod tests {
 
     #[test]
     fn powerset_depth1() {
-        let v = powerset(vec![1, 2, 3, 4], Some(1));
+        let v = powerset(vec![1, 2, 3, 4], Some(1)).collect::<Vec<_>>();
         assert_eq!(v, vec![vec![], vec![1], vec![2], vec![3], vec![4],]);
     }
 
     #[test]
     fn powerset_depth2() {
-        let v = powerset(vec![1, 2, 3, 4], Some(2));
+        let v = powerset(vec![1, 2, 3, 4], Some(2)).collect::<Vec<_>>();
         assert_eq!(v, vec![
             vec![],
             vec![1],
@@ -514,7 +1354,7 @@ mod tests {
 
     #[test]
     fn powerset_depth3() {
-        let v = powerset(vec![1, 2, 3, 4], Some(3));
+        let v = powerset(vec![1, 2, 3, 4], Some(3)).collect::<Vec<_>>();
         assert_eq!(v, vec![
             vec![],
             vec![1],
@@ -533,4 +1373,178 @@ mod tests {
             vec![2, 3, 4],
         ]);
     }
+
+    #[test]
+    fn powerset_large() {
+        // With 22 features, the full powerset has ~4M sets, which must not be
+        // generated for --depth 2.
+        let names: Vec<_> = (0..22).map(|i| format!("f{i}")).collect();
+        let map: BTreeMap<_, _> = names.iter().map(|n| (n.clone(), vec![])).collect();
+        let list: Vec<Feature> = names.iter().map(|n| n.as_str().into()).collect();
+        let start = Instant::now();
+        let filtered = feature_powerset(&list, Some(2), false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered.len(), 22 + 22 * 21 / 2);
+        assert_eq!(filtered[..4], [
+            vec![&list[0]],
+            vec![&list[1]],
+            vec![&list[0], &list[1]],
+            vec![&list[2]]
+        ]);
+        assert_eq!(filtered.last().unwrap(), &[&list[20], &list[21]]);
+        assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+        // The subsets after the last one within the depth are skipped at once.
+        assert_eq!(powerset(0..64, Some(1)).count(), 65);
+    }
+
+    #[test]
+    fn powerset_depth_counts_group_members() {
+        let map =
+            map![("a", v![]), ("b", v![]), ("c", v![]), ("d", v![]), ("e", v![]), ("f", v![])];
+        let list = [Feature::group(["a", "b"]), Feature::group(["c", "d", "e"]), "f".into()];
+
+        // By default, each group counts as 1.
+        let filtered = feature_powerset(&list, Some(2), false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![
+            vec!["a,b"],
+            vec!["c,d,e"],
+            vec!["a,b", "c,d,e"],
+            vec!["f"],
+            vec!["a,b", "f"],
+            vec!["c,d,e", "f"],
+        ]);
+
+        // With --depth-counts-group-members, each group counts as the number of its members.
+        let filtered = feature_powerset(&list, Some(2), true, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![vec!["a,b"], vec!["f"]]);
+        let filtered = feature_powerset(&list, Some(3), true, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![vec!["a,b"], vec!["c,d,e"], vec!["f"], vec!["a,b", "f"]]);
+        let filtered = feature_powerset(&list, Some(4), true, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![vec!["a,b"], vec!["c,d,e"], vec!["f"], vec!["a,b", "f"], vec![
+            "c,d,e", "f"
+        ],]);
+        // No effect without --depth.
+        assert_eq!(
+            feature_powerset(&list, None, true, None, &[], &[], &[], &map, None),
+            feature_powerset(&list, None, false, None, &[], &[], &[], &map, None)
+        );
+    }
+
+    #[test]
+    fn powerset_max_group_combo() {
+        let map = map![
+            ("postgres", v![]),
+            ("sqlite", v![]),
+            ("rustls", v![]),
+            ("native-tls", v![]),
+            ("tokio", v![]),
+            ("async-std", v![]),
+            ("f", v![])
+        ];
+        let list = [
+            Feature::group(["postgres", "sqlite"]),
+            Feature::group(["rustls", "native-tls"]),
+            Feature::group(["tokio", "async-std"]),
+        ];
+
+        let filtered = feature_powerset(&list, None, false, Some(2), &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![
+            vec!["postgres,sqlite"],
+            vec!["rustls,native-tls"],
+            vec!["postgres,sqlite", "rustls,native-tls"],
+            vec!["tokio,async-std"],
+            vec!["postgres,sqlite", "tokio,async-std"],
+            vec!["rustls,native-tls", "tokio,async-std"],
+        ]);
+        let filtered = feature_powerset(&list, None, false, Some(1), &[], &[], &[], &map, None);
+        assert_eq!(filtered, vec![vec!["postgres,sqlite"], vec!["rustls,native-tls"], vec![
+            "tokio,async-std"
+        ]]);
+
+        // Plain features are not counted.
+        let list = [
+            Feature::group(["postgres", "sqlite"]),
+            Feature::group(["rustls", "native-tls"]),
+            Feature::group(["tokio", "async-std"]),
+            "f".into(),
+        ];
+        let filtered = feature_powerset(&list, None, false, Some(2), &[], &[], &[], &map, None);
+        assert_eq!(filtered.len(), 13);
+        assert!(filtered.iter().all(|fs| group_count(fs) <= 2));
+        assert!(filtered.contains(&vec![&list[0], &list[2], &list[3]]));
+        assert_eq!(
+            feature_powerset(&list, None, false, Some(3), &[], &[], &[], &map, None).len(),
+            15
+        );
+    }
+
+    #[test]
+    fn overlapping_groups() {
+        let map = map![("a", v![]), ("b", v![]), ("c", v![])];
+        // Any two of the groups enable all of `a`, `b`, and `c`.
+        let list =
+            [Feature::group(["a", "b"]), Feature::group(["b", "c"]), Feature::group(["a", "c"])];
+        let mut filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered.len(), 7);
+        let mut removed = vec![];
+        assert_eq!(merge_overlapping(&mut filtered, Some(&mut removed)), 3);
+        assert_eq!(filtered, vec![vec!["a,b"], vec!["b,c"], vec!["a,b", "b,c"], vec!["a,c"]]);
+        assert_eq!(removed.len(), 3);
+        assert_eq!(removed[0].0, vec!["a,b", "a,c"]);
+        assert_eq!(removed[0].1, Removed::Overlap { same_as: "a,b + b,c".to_owned() });
+        assert_eq!(
+            removed[0].1.to_string(),
+            "merged because `a,b + b,c` enables the same features (groups overlap)"
+        );
+
+        // A group that contains another group.
+        let list = [Feature::group(["a", "b"]), Feature::group(["a", "b", "c"]), "d".into()];
+        let mut filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+        assert_eq!(filtered.len(), 7);
+        assert_eq!(merge_overlapping(&mut filtered, None), 2);
+        assert_eq!(filtered, vec![vec!["a,b"], vec!["a,b,c"], vec!["d"], vec!["a,b", "d"], vec![
+            "a,b,c", "d"
+        ]]);
+
+        // Combinations without overlapping groups are not changed.
+        let list = [Feature::group(["a", "b"]), "c".into()];
+        let mut filtered = feature_powerset(&list, None, false, None, &[], &[], &[], &map, None);
+        assert_eq!(merge_overlapping(&mut filtered, None), 0);
+        assert_eq!(filtered.len(), 3);
+    }
+
+    #[test]
+    fn uncommon_names() {
+        for name in ["a", "_a", "1.0", "v1.2.3", "c++", "a+b", "a-b_c", "\u{e9}t\u{e9}"] {
+            assert_eq!(check_name(name), Ok(()), "{name}");
+        }
+        for name in ["", "-a", ".a", "+a", "a,b", "a b", "a:b", "a/b", "a?"] {
+            assert!(check_name(name).is_err(), "{name}");
+        }
+        assert_eq!(
+            check_name("a;b").unwrap_err(),
+            "the character `;` is not allowed; feature names may only contain letters, digits, \
+             `_`, `-`, `+`, and `.`"
+        );
+
+        assert_eq!(quote("a-b_c"), "a-b_c");
+        assert_eq!(quote("dep/a"), "dep/a");
+        assert_eq!(quote("1.0"), "\"1.0\"");
+        assert_eq!(quote("c++"), "\"c++\"");
+        assert_eq!(Origin::Group("1.0,c++".to_owned()).to_string(), "group \"1.0\",\"c++\"");
+
+        // Dots and plus signs are part of the name.
+        let a: Feature = "1.0".into();
+        assert_eq!(a, *"1.0");
+        assert_ne!(a, *"1");
+        assert_ne!(Feature::from("c++"), Feature::from("c"));
+        let group = Feature::group(["1.0", "c++"]);
+        assert_eq!(group.name(), "1.0,c++");
+        assert!(group.matches("1.0"));
+        assert!(group.matches("c++"));
+        assert!(!group.matches("1"));
+        assert!(!group.matches("c"));
+        let map = map![("1.0", v![]), ("c++", v!["1.0"]), ("all", v!["c++"])];
+        assert!(Feature::group(["1.0", "x"]).matches_recursive("all", &map));
+        assert!(!Feature::group(["c", "x"]).matches_recursive("all", &map));
+    }
 }
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Validation of the flags propagated to cargo.
+//
+// A typo in a propagated flag makes every run fail, so the long flags are
+// checked against the help of the subcommand before starting the runs.
+// The help is parsed on a best-effort basis; the check is skipped if the help
+// is not available (e.g., third-party subcommands without --help), and can be
+// skipped with --skip-flag-validation.
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+
+use crate::{context::Context, process::ProcessBuilder, term};
+
+/// Checks that the long flags propagated to cargo are accepted by the subcommand
+/// (and the commands chained by --and-then).
+///
+/// `cargo` is the cargo to run the subcommand with.
+pub(crate) fn validate(cx: &Context, cargo: &ProcessBuilder<'_>) -> Result<()> {
+    if cx.skip_flag_validation {
+        return Ok(());
+    }
+    validate_args(&cx.leading_args, cargo)?;
+    for command in &cx.and_then {
+        validate_args(&command.leading_args, cargo)?;
+    }
+    Ok(())
+}
+
+fn validate_args(args: &[String], cargo: &ProcessBuilder<'_>) -> Result<()> {
+    // The subcommand and its nested subcommands (e.g., `llvm-cov test`).
+    let subcommand: Vec<_> =
+        args.iter().map(String::as_str).take_while(|a| !a.starts_with('-')).collect();
+    if subcommand.is_empty() {
+        return Ok(());
+    }
+    let Some(help) = read_help(cargo.clone(), &subcommand) else { return Ok(()) };
+    // Some subcommands only list their own flags and refer to the help of
+    // another subcommand for the rest, e.g., `cargo clippy --help` says "See
+    // all options with cargo check --help".
+    let mut referred = referred_subcommands(&help, subcommand[0]);
+    if subcommand[0] == "clippy" && !referred.contains(&"check") {
+        referred.push("check");
+    }
+    let mut helps = vec![];
+    for other in referred {
+        if let Some(help) = read_help(cargo.clone(), &[other]) {
+            helps.push(help);
+        }
+    }
+    let mut known = parse_help(&help);
+    known.extend(helps.iter().flat_map(|help| parse_help(help)));
+    if known.is_empty() {
+        return Ok(());
+    }
+    // If the help does not list the usual flags of cargo, it is likely that
+    // the subcommand accepts flags not listed in it, so unknown flags are
+    // only warned.
+    let complete = ["manifest-path", "features"].iter().all(|f| known.contains(f));
+
+    let subcommand = subcommand.join(" ");
+    for arg in args {
+        let Some(name) = arg.strip_prefix("--") else { continue };
+        let name = name.split_once('=').map_or(name, |(name, _)| name);
+        if name.is_empty() || known.contains(name) {
+            continue;
+        }
+        let suggestion = most_similar(name, &known)
+            .map_or_else(String::new, |similar| format!("; did you mean `--{similar}`?"));
+        if !complete {
+            warn!(
+                "flag `--{name}` is not listed in the help of `cargo {subcommand}`{suggestion}\n\n\
+                 use --skip-flag-validation to skip this check"
+            );
+            continue;
+        }
+        bail!(
+            "unknown flag `--{name}` for `cargo {subcommand}`{suggestion}\n\n\
+             if cargo accepts this flag, use --skip-flag-validation to skip this check"
+        );
+    }
+    Ok(())
+}
+
+/// Returns the help of the subcommand, or `None` if it is not available.
+fn read_help(mut cargo: ProcessBuilder<'_>, subcommand: &[&str]) -> Option<String> {
+    cargo.args(subcommand);
+    cargo.arg("--help");
+    match cargo.read() {
+        Ok(help) => Some(help),
+        Err(e) => {
+            if term::verbose() {
+                info!("skipped validation of flags: {e:#}");
+            }
+            None
+        }
+    }
+}
+
+/// Returns the subcommands whose help is referred to in the help text (e.g.,
+/// `check` for "See all options with cargo check --help.").
+fn referred_subcommands<'a>(help: &'a str, subcommand: &str) -> Vec<&'a str> {
+    let words: Vec<_> = help.split_whitespace().collect();
+    let mut referred = vec![];
+    for w in words.windows(3) {
+        let help_flag = w[2].trim_end_matches(|c: char| !c.is_ascii_alphanumeric());
+        if w[0].trim_start_matches(['`', '\'', '"']) == "cargo"
+            && help_flag == "--help"
+            && w[1] != subcommand
+            && !w[1].starts_with('-')
+            && !referred.contains(&w[1])
+        {
+            referred.push(w[1]);
+        }
+    }
+    referred
+}
+
+/// Returns the names of long flags in the help text.
+fn parse_help(help: &str) -> BTreeSet<&str> {
+    let mut flags = BTreeSet::new();
+    for word in help.split_whitespace() {
+        // e.g., `--features`, `[--features`, `--features=<FEATURES>`, `--features,`
+        let Some(word) = word.trim_start_matches(['[', '(']).strip_prefix("--") else {
+            continue;
+        };
+        let end = word.find(|c: char| !c.is_ascii_alphanumeric() && c != '-').unwrap_or(word.len());
+        if end != 0 {
+            flags.insert(&word[..end]);
+        }
+    }
+    flags
+}
+
+/// Returns the flag most similar to `name`, if it is similar enough.
+pub(crate) fn most_similar<'a>(name: &str, known: &BTreeSet<&'a str>) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|&flag| (edit_distance(name, flag), flag))
+        .filter(|&(distance, _)| distance * 3 <= name.len().max(3))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, flag)| flag)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut prev: Vec<_> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, most_similar, parse_help, referred_subcommands};
+
+    #[test]
+    fn similar_flags() {
+        let help = "\
+Check a local package and all of its dependencies for errors
+
+Usage: cargo check [OPTIONS]
+
+Options:
+      --ignore-rust-version     Ignore `rust-version` specification in packages
+      --message-format <FMT>    Error format
+  -v, --verbose...              Use verbose output (-vv very verbose/build.rs output)
+      --config <KEY=VALUE>      Override a configuration value
+
+Feature Selection:
+  -F, --features <FEATURES>  Space or comma separated list of features to activate
+      --all-features         Activate all available features
+      --no-default-features  Do not activate the `default` feature
+
+Run `cargo help check` for more detailed information.
+";
+        let known = parse_help(help);
+        assert_eq!(known.into_iter().collect::<Vec<_>>(), [
+            "all-features",
+            "config",
+            "features",
+            "ignore-rust-version",
+            "message-format",
+            "no-default-features",
+            "verbose",
+        ]);
+        // Old cargo (docopt) style.
+        let old_help = "\
+Usage:
+    cargo check [options]
+
+Options:
+    --all-features           Activate all available features
+    --target TRIPLE          Check for the target triple
+";
+        assert_eq!(parse_help(old_help).into_iter().collect::<Vec<_>>(), [
+            "all-features",
+            "target"
+        ]);
+
+        assert_eq!(edit_distance("al-features", "all-features"), 1);
+        assert_eq!(edit_distance("feature", "features"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let known = parse_help(help);
+        assert_eq!(most_similar("al-features", &known), Some("all-features"));
+        assert_eq!(most_similar("featuers", &known), Some("features"));
+        assert_eq!(most_similar("verbos", &known), Some("verbose"));
+        assert_eq!(most_similar("release", &known), None);
+    }
+
+    #[test]
+    fn referred_help() {
+        let help = "\
+Checks a package to catch common mistakes and improve your Rust code.
+
+Usage:
+    cargo clippy [OPTIONS] [--] [<ARGS>...]
+
+Common options:
+    --no-deps                Run Clippy only on the given crate, without linting the dependencies
+    --fix                    Automatically apply lint suggestions. This flag implies --no-deps and --all-targets
+
+See all options with cargo check --help.
+";
+        assert_eq!(referred_subcommands(help, "clippy"), ["check"]);
+        assert_eq!(referred_subcommands("Run `cargo help check` for more", "check"), [] as [&str;
+            0]);
+        assert_eq!(referred_subcommands("see `cargo clippy --help`", "clippy"), [] as [&str; 0]);
+    }
+}
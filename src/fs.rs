@@ -24,3 +24,10 @@ pub(crate) fn read_to_string(path: impl AsRef<Path>) -> Result<String> {
     let res = std::fs::read_to_string(path);
     res.with_context(|| format!("failed to read from file `{}`", path.display()))
 }
+
+/// A wrapper for [`std::fs::create_dir_all`].
+pub(crate) fn create_dir_all(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let res = std::fs::create_dir_all(path);
+    res.with_context(|| format!("failed to create directory `{}`", path.display()))
+}
@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --junit.
+//
+// Each command becomes a <testcase> of a single <testsuite>, whose class name
+// is the package and whose name is the feature flags. Packages skipped
+// entirely (e.g., private packages) become a skipped <testcase> per package.
+// The first run on each package is marked with the `cold-start` property.
+
+use std::{fmt::Write as _, path::Path, time::Duration};
+
+use anyhow::Result;
+
+use crate::{
+    fs,
+    summary::{Run, SkippedPackage, Status},
+};
+
+/// Writes a JUnit XML report of the given runs to `path`.
+pub(crate) fn write(path: &Path, runs: &[Run], skipped_packages: &[SkippedPackage]) -> Result<()> {
+    fs::write(path, xml(runs, skipped_packages))
+}
+
+fn xml(runs: &[Run], skipped_packages: &[SkippedPackage]) -> String {
+    let tests = runs.len() + skipped_packages.len();
+    let failures = runs.iter().filter(|run| run.status == Status::Failed).count();
+    let skipped = runs.iter().filter(|run| !run.status.ran()).count() + skipped_packages.len();
+    let time = seconds(runs.iter().map(|run| run.duration).sum());
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let counts =
+        format!("tests=\"{tests}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\"");
+    writeln!(out, "<testsuites name=\"cargo-hack\" {counts} time=\"{time}\">").unwrap();
+    writeln!(out, "  <testsuite name=\"cargo-hack\" {counts} time=\"{time}\">").unwrap();
+    for run in runs {
+        let mut name = if run.features.is_empty() {
+            "default features".to_owned()
+        } else {
+            run.features.clone()
+        };
+        if !run.cfgs.is_empty() {
+            write!(name, " [cfg: {}]", run.cfgs.join(",")).unwrap();
+        }
+        if let Some(toolchain) = &run.toolchain {
+            write!(name, " on {toolchain}").unwrap();
+        }
+        write!(
+            out,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{}\"",
+            escape(&run.package),
+            escape(&name),
+            seconds(run.duration)
+        )
+        .unwrap();
+        if run.status == Status::Passed && !run.cold_start {
+            out.push_str("/>\n");
+            continue;
+        }
+        out.push_str(">\n");
+        if run.cold_start {
+            // The first run on the package builds its dependencies, so it is
+            // usually much slower than the others.
+            out.push_str("      <properties>\n");
+            out.push_str("        <property name=\"cold-start\" value=\"true\"/>\n");
+            out.push_str("      </properties>\n");
+        }
+        match run.status {
+            Status::Passed => {}
+            Status::Failed => {
+                let message = run.error.as_deref().unwrap_or("failed");
+                write!(out, "      <failure message=\"{}\">", escape(message)).unwrap();
+                for line in &run.stderr {
+                    writeln!(out, "{}", escape(line)).unwrap();
+                }
+                out.push_str("</failure>\n");
+            }
+            Status::Skipped | Status::NotRun => {
+                let message = if run.status == Status::Skipped {
+                    "not in the partition specified by --partition"
+                } else {
+                    "not run (budget exceeded)"
+                };
+                writeln!(out, "      <skipped message=\"{message}\"/>").unwrap();
+            }
+        }
+        out.push_str("    </testcase>\n");
+    }
+    for skipped in skipped_packages {
+        writeln!(
+            out,
+            "    <testcase classname=\"{}\" name=\"all feature combinations\" time=\"0.000\">",
+            escape(&skipped.package)
+        )
+        .unwrap();
+        writeln!(out, "      <skipped message=\"{}\"/>", escape(&skipped.reason)).unwrap();
+        out.push_str("    </testcase>\n");
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+/// Escapes the string for use in the text or attribute values of XML.
+///
+/// Characters that are not allowed in XML 1.0 (e.g., control characters in
+/// the output of cargo) are replaced with U+FFFD.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => out.push('\u{FFFD}'),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, VecDeque},
+        time::Duration,
+    };
+
+    use super::{escape, xml};
+    use crate::summary::{Run, SkippedPackage, Status};
+
+    #[test]
+    fn escape_xml() {
+        assert_eq!(escape("a,b"), "a,b");
+        assert_eq!(
+            escape("cargo check --manifest-path \"it's <dir>/Cargo.toml\" && true"),
+            "cargo check --manifest-path &quot;it&apos;s &lt;dir&gt;/Cargo.toml&quot; &amp;&amp; true"
+        );
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+        assert_eq!(
+            escape("\u{1b}[31merror\u{1b}[0m\tx\r\n"),
+            "\u{FFFD}[31merror\u{FFFD}[0m\tx\r\n"
+        );
+        assert_eq!(escape("\u{0}\u{FFFE}é"), "\u{FFFD}\u{FFFD}é");
+    }
+
+    #[test]
+    fn report() {
+        let run = |features: &str, status, error: Option<&str>, stderr: &[&str]| Run {
+            id: String::new(),
+            package: "a\"b".to_owned(),
+            toolchain: None,
+            command: String::new(),
+            features: features.to_owned(),
+            requested_features: BTreeSet::new(),
+            enabled_features: None,
+            doc_items: None,
+            status,
+            duration: Duration::from_millis(1500),
+            cold_start: false,
+            error: error.map(str::to_owned),
+            stderr: stderr.iter().map(|&s| s.to_owned()).collect::<VecDeque<_>>(),
+            cfgs: vec![],
+            sccache: None,
+        };
+        let runs = [
+            Run { cold_start: true, ..run("", Status::Passed, None, &[]) },
+            run("--features x", Status::Failed, Some("exit status: 101"), &["error<E0425>"]),
+            Run { duration: Duration::ZERO, ..run("--features y", Status::Skipped, None, &[]) },
+            Run { duration: Duration::ZERO, ..run("--features z", Status::NotRun, None, &[]) },
+        ];
+        let skipped =
+            [SkippedPackage { package: "c".to_owned(), reason: "private package".to_owned() }];
+        assert_eq!(
+            xml(&runs, &skipped),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="cargo-hack" tests="5" failures="1" errors="0" skipped="3" time="3.000">
+  <testsuite name="cargo-hack" tests="5" failures="1" errors="0" skipped="3" time="3.000">
+    <testcase classname="a&quot;b" name="default features" time="1.500">
+      <properties>
+        <property name="cold-start" value="true"/>
+      </properties>
+    </testcase>
+    <testcase classname="a&quot;b" name="--features x" time="1.500">
+      <failure message="exit status: 101">error&lt;E0425&gt;
+</failure>
+    </testcase>
+    <testcase classname="a&quot;b" name="--features y" time="0.000">
+      <skipped message="not in the partition specified by --partition"/>
+    </testcase>
+    <testcase classname="a&quot;b" name="--features z" time="0.000">
+      <skipped message="not run (budget exceeded)"/>
+    </testcase>
+    <testcase classname="c" name="all feature combinations" time="0.000">
+      <skipped message="private package"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of the hint to use --locked (--no-locked-hint), and
+// --locked=auto.
+//
+// Without --locked, cargo silently updates Cargo.lock when it is out of date,
+// so the runs on different toolchains (or different CI jobs) may use different
+// versions of the dependencies. A hint to use --locked is printed once if the
+// workspace has Cargo.lock.
+//
+// --locked=auto passes --locked, but if a command fails because Cargo.lock
+// needs to be updated (detected from the captured stderr), it is run again
+// without --locked with a warning, so a stale lockfile does not fail the
+// whole run.
+
+use std::collections::VecDeque;
+
+use crate::{context::Context, lockfile_strategy::LockfileStrategy};
+
+/// Prints the hint to use --locked if Cargo.lock exists in the workspace root
+/// and neither --locked nor --frozen is used.
+pub(crate) fn hint(cx: &Context) {
+    if cx.locked
+        || cx.no_locked_hint
+        || cx.print_command_list
+        || cx.ignores_features
+        || cx.verify_lockfile_per_version
+        || matches!(
+            cx.lockfile_strategy,
+            LockfileStrategy::GeneratePerVersion | LockfileStrategy::GenerateOnce
+        )
+        || cx.leading_args.iter().any(|a| a == "--frozen")
+        || !cx.workspace_root().join("Cargo.lock").exists()
+    {
+        return;
+    }
+    info!(
+        "Cargo.lock exists but --locked is not used, so cargo may update it between runs; \
+         consider using --locked (or --locked=auto) to use the same versions of dependencies \
+         on all runs (use --no-locked-hint to hide this)"
+    );
+}
+
+/// Returns `true` if the captured stderr shows that the command failed
+/// because Cargo.lock needs to be updated but --locked was passed.
+pub(crate) fn needs_update(stderr: &VecDeque<String>) -> bool {
+    // Older cargo: "the lock file <path> needs to be updated but --locked was passed to prevent this"
+    // Newer cargo: "cannot update the lock file <path> because --locked was passed to prevent this"
+    stderr.iter().any(|line| line.contains("--locked was passed to prevent this"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::needs_update;
+
+    #[test]
+    fn lock_file_errors() {
+        let stderr = |lines: &[&str]| lines.iter().map(|&s| s.to_owned()).collect::<VecDeque<_>>();
+        assert!(needs_update(&stderr(&[
+            "    Updating crates.io index",
+            "error: the lock file /a/Cargo.lock needs to be updated but --locked was passed to \
+             prevent this",
+        ])));
+        assert!(needs_update(&stderr(&[
+            "error: cannot update the lock file /a/Cargo.lock because --locked was passed to \
+             prevent this",
+            "help: to generate the lock file without accessing the network, remove the --locked \
+             flag and use --offline instead.",
+        ])));
+        assert!(!needs_update(&stderr(&["error[E0425]: cannot find value `x` in this scope"])));
+        assert!(!needs_update(&stderr(&[])));
+    }
+}
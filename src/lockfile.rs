@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --verify-lockfile-per-version.
+//
+// In --version-range runs without --locked, the lockfile may be (re)generated
+// by an old cargo and then used by the subsequent toolchains. This module
+// compares the lockfile before and after the runs of each toolchain, to show
+// how the resolution drifted.
+//
+// Only the names and versions of packages are compared, so this only needs
+// a minimal parser of the lockfile.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::fs;
+
+/// Versions of packages in the lockfile, keyed by package name.
+type Packages = BTreeMap<String, BTreeSet<String>>;
+
+/// The lockfile at the start of the runs of a toolchain.
+pub(crate) struct Snapshot {
+    path: PathBuf,
+    /// `None` if the lockfile does not exist.
+    packages: Option<Packages>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(workspace_root: &Path) -> Result<Self> {
+        let path = workspace_root.join("Cargo.lock");
+        let packages = read(&path)?;
+        Ok(Self { path, packages })
+    }
+
+    /// Returns the changes of the lockfile since the snapshot was taken.
+    pub(crate) fn changes(&self) -> Result<Vec<String>> {
+        let new = read(&self.path)?;
+        Ok(match (&self.packages, &new) {
+            (Some(old), Some(new)) => changes(old, new),
+            (None, Some(_)) => vec!["Cargo.lock was generated".to_owned()],
+            (Some(_), None) => vec!["Cargo.lock was removed".to_owned()],
+            (None, None) => vec![],
+        })
+    }
+}
+
+fn read(path: &Path) -> Result<Option<Packages>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let s = fs::read_to_string(path)?;
+    let packages =
+        parse(&s).with_context(|| format!("failed to parse lockfile `{}`", path.display()))?;
+    Ok(Some(packages))
+}
+
+fn parse(s: &str) -> Result<Packages> {
+    let doc: toml_edit::DocumentMut = s.parse()?;
+    let mut packages = Packages::new();
+    let Some(array) = doc.get("package").and_then(toml_edit::Item::as_array_of_tables) else {
+        // A lockfile of a workspace without packages (should be rare).
+        return Ok(packages);
+    };
+    for package in array {
+        let (Some(name), Some(version)) = (
+            package.get("name").and_then(toml_edit::Item::as_str),
+            package.get("version").and_then(toml_edit::Item::as_str),
+        ) else {
+            continue;
+        };
+        packages.entry(name.to_owned()).or_default().insert(version.to_owned());
+    }
+    Ok(packages)
+}
+
+/// Returns the changes between two lockfiles, such as `foo: 1.0.0 -> 1.0.1`.
+fn changes(old: &Packages, new: &Packages) -> Vec<String> {
+    let names: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    let mut changes = vec![];
+    for name in names {
+        let old = old.get(name).filter(|v| !v.is_empty());
+        let new = new.get(name).filter(|v| !v.is_empty());
+        match (old, new) {
+            (Some(old), Some(new)) if old == new => {}
+            (Some(old), Some(new)) => {
+                changes.push(format!("{name}: {} -> {}", join(old), join(new)));
+            }
+            (Some(old), None) => changes.push(format!("{name}: {} (removed)", join(old))),
+            (None, Some(new)) => changes.push(format!("{name}: {} (added)", join(new))),
+            (None, None) => {}
+        }
+    }
+    changes
+}
+
+fn join(versions: &BTreeSet<String>) -> String {
+    versions.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+}
+
+/// Prints the changes of the lockfile per toolchain.
+pub(crate) fn print(drift: &[(String, Vec<String>)]) {
+    info!("lockfile drift per toolchain:");
+    for (toolchain, changes) in drift {
+        if changes.is_empty() {
+            eprintln!("    {toolchain}: no changes");
+            continue;
+        }
+        eprintln!("    {toolchain}:");
+        for change in changes {
+            eprintln!("        {change}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changes, parse};
+
+    #[test]
+    fn lockfile_changes() {
+        let old = parse(
+            r#"
+version = 3
+
+[[package]]
+name = "a"
+version = "1.0.0"
+
+[[package]]
+name = "b"
+version = "0.1.0"
+
+[[package]]
+name = "b"
+version = "0.2.0"
+
+[[package]]
+name = "c"
+version = "1.0.0"
+"#,
+        )
+        .unwrap();
+        let new = parse(
+            r#"
+# This file is automatically @generated by Cargo.
+[[package]]
+name = "a"
+version = "1.0.1"
+dependencies = ["b"]
+
+[[package]]
+name = "b"
+version = "0.2.0"
+
+[[package]]
+name = "d"
+version = "0.1.0"
+
+[metadata]
+"checksum d 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)" = "..."
+"#,
+        )
+        .unwrap();
+        assert_eq!(changes(&old, &new), [
+            "a: 1.0.0 -> 1.0.1",
+            "b: 0.1.0, 0.2.0 -> 0.2.0",
+            "c: 1.0.0 (removed)",
+            "d: 0.1.0 (added)",
+        ]);
+        assert!(changes(&new, &new).is_empty());
+        assert!(parse("version = 3").unwrap().is_empty());
+        assert!(parse("[[package]").is_err());
+    }
+}
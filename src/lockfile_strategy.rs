@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --lockfile-strategy.
+//
+// With --version-range, cargo-hack may run `cargo generate-lockfile` with an
+// old toolchain, because the lockfile generated by a newer cargo may not be
+// parsed by old cargo, and old cargo may fail to resolve dependencies with it.
+// This module decides when to do so.
+
+use std::{path::Path, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+
+use crate::{
+    fs,
+    version::{Version, VersionOrder},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LockfileStrategy {
+    /// Generate the lockfile when needed (the default).
+    ///
+    /// - With the oldest toolchain, if it is older than 1.60.
+    ///   <https://github.com/taiki-e/cargo-hack/issues/105>
+    /// - With the first toolchain 1.51 or later, after runs on toolchains
+    ///   older than 1.51, as a workaround for spurious "failed to select a
+    ///   version" error. <https://github.com/rust-lang/cargo/issues/10623>
+    Auto,
+    /// Never generate the lockfile.
+    Keep,
+    /// Generate the lockfile with each toolchain.
+    GeneratePerVersion,
+    /// Generate the lockfile with the oldest toolchain.
+    GenerateOnce,
+}
+
+impl FromStr for LockfileStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "keep" => Ok(Self::Keep),
+            "generate-per-version" => Ok(Self::GeneratePerVersion),
+            "generate-once" => Ok(Self::GenerateOnce),
+            other => bail!(
+                "argument for --lockfile-strategy must be auto, keep, generate-per-version, or \
+                 generate-once, but found `{other}`"
+            ),
+        }
+    }
+}
+
+/// Decides whether to generate the lockfile before the runs on each toolchain.
+pub(crate) struct Generator {
+    strategy: LockfileStrategy,
+    locked: bool,
+    /// The toolchains to run, in ascending order.
+    versions: Vec<Version>,
+    order: VersionOrder,
+    /// Whether the lockfile has not been generated yet, but will be generated
+    /// with the oldest toolchain before the runs.
+    pending: bool,
+    /// The minor version of the cargo that last generated the lockfile or
+    /// ran without --locked, which may update the lockfile (only used by
+    /// `auto`).
+    written_by: Option<u32>,
+}
+
+impl Generator {
+    /// `versions` are the toolchains to run, in ascending order.
+    pub(crate) fn new(
+        strategy: LockfileStrategy,
+        locked: bool,
+        versions: Vec<Version>,
+        order: VersionOrder,
+    ) -> Self {
+        let oldest = versions[0].minor;
+        let pending = match strategy {
+            LockfileStrategy::Auto => !locked && oldest < 60,
+            LockfileStrategy::GenerateOnce => true,
+            LockfileStrategy::GeneratePerVersion | LockfileStrategy::Keep => false,
+        };
+        Self { strategy, locked, versions, order, pending, written_by: None }
+    }
+
+    /// Returns the toolchain to generate the lockfile with before any runs,
+    /// and the reason, or `None` if it is not generated.
+    ///
+    /// With --version-order=newest-first, the lockfile is generated with the
+    /// oldest toolchain here instead of before the runs on it, which are the
+    /// last runs.
+    pub(crate) fn prepare(&mut self) -> Option<(Version, &'static str)> {
+        if self.order == VersionOrder::OldestFirst || !self.pending {
+            return None;
+        }
+        self.pending = false;
+        let oldest = self.versions[0];
+        self.written_by = Some(oldest.minor);
+        Some((oldest, self.pending_reason()))
+    }
+
+    /// Returns the toolchain to generate the lockfile with before the runs on
+    /// `version`, and the reason, or `None` if it is not generated.
+    ///
+    /// This must be called for each toolchain in the order of the runs, after
+    /// [`prepare`](Self::prepare).
+    pub(crate) fn next(&mut self, version: Version) -> Option<(Version, &'static str)> {
+        let minor = version.minor;
+        match self.strategy {
+            LockfileStrategy::Auto => {
+                let generate = if self.pending {
+                    self.pending = false;
+                    Some((version, self.pending_reason()))
+                } else if self.written_by.is_some_and(|v| v < 51) && minor >= 51 {
+                    // With --version-order=newest-first, the runs on the
+                    // toolchains 1.51 or later use the lockfile generated by
+                    // the oldest of them, as with oldest-first.
+                    let first = *self.versions.iter().find(|v| v.minor >= 51).unwrap();
+                    Some((
+                        first,
+                        "the lockfile was written by cargo older than 1.51 (workaround for \
+                         spurious \"failed to select a version\" error)",
+                    ))
+                } else if self.written_by.is_some_and(|v| v >= 51) && minor < 51 {
+                    // Only with --version-order=newest-first.
+                    Some((
+                        self.versions[0],
+                        "the lockfile was written by cargo 1.51 or later, and the oldest \
+                         toolchain is older than 1.60",
+                    ))
+                } else {
+                    None
+                };
+                if let Some((version, _)) = generate {
+                    self.written_by = Some(version.minor);
+                }
+                if !self.locked {
+                    self.written_by = Some(minor);
+                }
+                generate
+            }
+            LockfileStrategy::Keep => None,
+            LockfileStrategy::GeneratePerVersion => {
+                Some((version, "--lockfile-strategy=generate-per-version"))
+            }
+            LockfileStrategy::GenerateOnce => {
+                let generate = self.pending.then(|| (version, self.pending_reason()));
+                self.pending = false;
+                generate
+            }
+        }
+    }
+
+    fn pending_reason(&self) -> &'static str {
+        match self.strategy {
+            LockfileStrategy::GenerateOnce => "--lockfile-strategy=generate-once",
+            _ => "the oldest toolchain is older than 1.60",
+        }
+    }
+}
+
+/// Checks that the existing lockfile can be parsed by all of the toolchains,
+/// for --lockfile-strategy=keep.
+pub(crate) fn check_keep(
+    workspace_root: &Path,
+    versions: impl IntoIterator<Item = Version>,
+) -> Result<()> {
+    let path = workspace_root.join("Cargo.lock");
+    if !path.exists() {
+        return Ok(());
+    }
+    let Some(version) = lockfile_version(&fs::read_to_string(&path)?) else { return Ok(()) };
+    let Some(required) = required_cargo(version) else { return Ok(()) };
+    if let Some(v) = versions.into_iter().find(|v| v.minor < required) {
+        bail!(
+            "`{}` is lockfile version {version}, which cannot be parsed by cargo {v} (cargo \
+             1.{required} or later is required); --lockfile-strategy=keep does not regenerate \
+             the lockfile, so consider using another --lockfile-strategy or a newer start of \
+             --version-range",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the version of the lockfile format, or `None` if it has no
+/// `version` field (version 1 or 2).
+fn lockfile_version(s: &str) -> Option<u32> {
+    // The `version` field is placed before any tables.
+    s.lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .find_map(|line| line.strip_prefix("version")?.trim_start().strip_prefix('='))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Returns the minor version of the oldest cargo that can parse the lockfile
+/// version, or `None` if all versions of cargo supported by cargo-hack can.
+fn required_cargo(version: u32) -> Option<u32> {
+    match version {
+        ..=2 => None,
+        3 => Some(47),
+        _ => Some(78),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lockfile_version, Generator, LockfileStrategy};
+    use crate::version::{Version, VersionOrder};
+
+    fn v(minor: u32) -> Version {
+        Version { major: 1, minor, patch: None }
+    }
+
+    #[test]
+    fn strategies() {
+        let run = |strategy, locked, versions: &[u32]| {
+            let versions: Vec<_> = versions.iter().map(|&m| v(m)).collect();
+            let mut generator =
+                Generator::new(strategy, locked, versions.clone(), VersionOrder::OldestFirst);
+            assert_eq!(generator.prepare(), None);
+            versions.iter().map(|&v| generator.next(v).is_some()).collect::<Vec<_>>()
+        };
+
+        // auto
+        assert_eq!(run(LockfileStrategy::Auto, false, &[70, 71]), [false, false]);
+        assert_eq!(run(LockfileStrategy::Auto, false, &[59, 60, 61]), [true, false, false]);
+        assert_eq!(run(LockfileStrategy::Auto, false, &[49, 50, 51, 52]), [
+            true, false, true, false
+        ]);
+        assert_eq!(run(LockfileStrategy::Auto, true, &[49, 50, 51, 52]), [
+            false, false, false, false
+        ]);
+        let mut generator = Generator::new(
+            LockfileStrategy::Auto,
+            false,
+            vec![v(50), v(51), v(52)],
+            VersionOrder::OldestFirst,
+        );
+        assert_eq!(generator.next(v(50)), Some((v(50), "the oldest toolchain is older than 1.60")));
+        let (version, reason) = generator.next(v(51)).unwrap();
+        assert_eq!(version, v(51));
+        assert!(reason.contains("failed to select a version"));
+        assert_eq!(generator.next(v(52)), None);
+
+        // keep
+        assert_eq!(run(LockfileStrategy::Keep, false, &[49, 50, 51, 59, 60]), [false; 5]);
+        // generate-once
+        assert_eq!(run(LockfileStrategy::GenerateOnce, false, &[49, 51, 70]), [true, false, false]);
+        assert_eq!(run(LockfileStrategy::GenerateOnce, false, &[70, 71]), [true, false]);
+        // generate-per-version
+        assert_eq!(run(LockfileStrategy::GeneratePerVersion, false, &[49, 51, 70]), [true; 3]);
+    }
+
+    #[test]
+    fn newest_first() {
+        // Returns the version to generate the lockfile with before any runs,
+        // and before the runs on each toolchain, from newest to oldest.
+        let run = |strategy, locked, versions: &[u32]| {
+            let versions: Vec<_> = versions.iter().map(|&m| v(m)).collect();
+            let mut generator =
+                Generator::new(strategy, locked, versions.clone(), VersionOrder::NewestFirst);
+            let prepare = generator.prepare().map(|(v, _)| v.minor);
+            let next = versions
+                .iter()
+                .rev()
+                .map(|&v| generator.next(v).map(|(v, _)| v.minor))
+                .collect::<Vec<_>>();
+            (prepare, next)
+        };
+
+        // auto
+        assert_eq!(run(LockfileStrategy::Auto, false, &[70, 71]), (None, vec![None, None]));
+        assert_eq!(run(LockfileStrategy::Auto, false, &[59, 60, 61]), (Some(59), vec![None; 3]));
+        assert_eq!(
+            run(LockfileStrategy::Auto, false, &[49, 50, 51, 52]),
+            (Some(49), vec![Some(51), None, Some(49), None])
+        );
+        assert_eq!(run(LockfileStrategy::Auto, true, &[49, 50, 51, 52]), (None, vec![None; 4]));
+
+        // keep
+        assert_eq!(run(LockfileStrategy::Keep, false, &[49, 50, 51]), (None, vec![None; 3]));
+        // generate-once
+        assert_eq!(
+            run(LockfileStrategy::GenerateOnce, false, &[49, 51, 70]),
+            (Some(49), vec![None; 3])
+        );
+        // generate-per-version
+        assert_eq!(
+            run(LockfileStrategy::GeneratePerVersion, false, &[49, 51, 70]),
+            (None, vec![Some(70), Some(51), Some(49)])
+        );
+    }
+
+    #[test]
+    fn parse_strategy() {
+        assert_eq!("keep".parse::<LockfileStrategy>().unwrap(), LockfileStrategy::Keep);
+        assert_eq!(
+            "generate-once".parse::<LockfileStrategy>().unwrap(),
+            LockfileStrategy::GenerateOnce
+        );
+        assert!("never".parse::<LockfileStrategy>().is_err());
+    }
+
+    #[test]
+    fn version_of_lockfile() {
+        assert_eq!(lockfile_version("# This file is automatically @generated by Cargo.\n# It is not intended for manual editing.\nversion = 4\n\n[[package]]\nname = \"a\"\nversion = \"0.1.0\"\n"), Some(4));
+        assert_eq!(lockfile_version("version = 3\n"), Some(3));
+        assert_eq!(lockfile_version("[[package]]\nname = \"a\"\nversion = \"0.1.0\"\n"), None);
+    }
+}
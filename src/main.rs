@@ -5,44 +5,101 @@
 #[macro_use]
 mod term;
 
+#[macro_use]
+mod trace;
 #[macro_use]
 mod process;
 
+mod additivity;
+mod badge;
+mod baseline;
+mod bisect;
+mod budget;
+mod buildable;
+mod capture;
 mod cargo;
+mod chain;
+mod changed_features;
 mod cli;
+mod closure;
+mod code_gated;
+mod completion;
 mod context;
+mod default_coverage;
+mod diff;
+mod doc_features;
+mod empty_plan;
+mod env_config;
+mod examples_once;
+mod execution_plan;
+mod feature_args;
+mod feature_docs;
+mod feature_versions;
 mod features;
+mod flags;
 mod fs;
+mod junit;
+mod locked;
+mod lockfile;
+mod lockfile_strategy;
 mod manifest;
+mod manifest_changes;
 mod metadata;
+mod msrv;
+mod output;
+mod placeholder;
+mod platform;
+mod porcelain;
+mod redundant;
+mod requirements;
 mod restore;
+mod run_id;
 mod rustup;
+mod sccache;
+mod shuffle;
+mod skip;
+mod summary;
+mod targets;
+mod timing;
 mod version;
+mod why;
+mod workspace;
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
     env,
     ffi::OsString,
     fmt::{self, Write as _},
+    io::{self, Write as _},
+    ops::Range,
     process::ExitCode,
+    ptr,
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, format_err, Error, Result};
 
 use crate::{
     context::Context,
-    features::Feature,
+    features::{Feature, GroupMember},
+    lockfile_strategy::LockfileStrategy,
     metadata::PackageId,
     process::ProcessBuilder,
+    requirements::Requirement,
     rustup::Rustup,
-    version::{Version, VersionRange},
+    skip::SkipKind,
+    version::{MaybeVersion, Version, VersionOrder, VersionRange},
 };
 
 fn main() -> ExitCode {
     term::init_coloring();
     if let Err(e) = try_main() {
         error!("{e:#}");
+        if e.is::<budget::Exceeded>() {
+            return ExitCode::from(budget::EXIT_CODE);
+        }
     }
     if term::error() || term::warn() && env::var_os("CARGO_HACK_DENY_WARNINGS").is_some() {
         ExitCode::FAILURE
@@ -55,123 +112,494 @@ fn try_main() -> Result<()> {
     let cargo = env::var_os("CARGO_HACK_CARGO_SRC")
         .unwrap_or_else(|| env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")));
     let Some(args) = cli::Args::parse(&cargo)? else { return Ok(()) };
+    if let Some(path) = &args.trace_file {
+        trace::init(path)?;
+        trace::args(&args, &cargo);
+    }
+    if args.subcommand.as_deref() == Some("restore") {
+        let workspace = workspace::Workspace::discover(args.manifest_path.as_deref(), &cargo)?;
+        return manifest::recover(&workspace.root, true);
+    }
+    // Restore the files left modified by a previous instance that was killed
+    // (and whose lock file was removed) before `cargo metadata` reads them.
+    if let Ok(workspace) = workspace::Workspace::from_manifests(args.manifest_path.as_deref()) {
+        manifest::recover(&workspace.root, false)?;
+    }
+    if args.subcommand.is_none() && !args.no_private && args.generate_feature_docs.is_none() {
+        // --remove-dev-deps only needs the manifests of the workspace members,
+        // so do not require `cargo metadata` to succeed.
+        let workspace = workspace::Workspace::discover(args.manifest_path.as_deref(), &cargo)?;
+        if args.check {
+            return manifest::check_dev_deps_removed(&workspace);
+        }
+        return manifest::remove_dev_deps_in(&workspace, args.no_wait);
+    }
     let cx = &Context::new(args, cargo)?;
 
-    manifest::with(cx, || {
-        if cx.subcommand.is_none() {
+    // Validate flags before modifying manifests. With --version-range, flags
+    // are validated with the first toolchain that is run after it is installed.
+    if cx.subcommand.is_some() && cx.version_range.is_empty() && cx.why.is_none() {
+        flags::validate(cx, &cx.cargo())?;
+    }
+
+    if let Some(path) = &cx.generate_feature_docs {
+        return feature_docs::generate(cx, &select_packages(cx)?, path);
+    }
+    if cx.subcommand.is_none() {
+        return manifest::with(cx, None, || Ok(()));
+    }
+
+    // Determine the packages before modifying manifests, so that only the
+    // manifests of the packages that will be executed are modified.
+    let mut skipped_packages = vec![];
+    let mut packages = determine_package_list(cx, &mut skipped_packages)?;
+    if cx.why.is_some() {
+        return Ok(());
+    }
+    locked::hint(cx);
+    if let Some(seed) = cx.shuffle {
+        shuffle::apply(cx, seed, &mut packages);
+    }
+    if let Some(rev) = &cx.skip_unless_manifest_changed {
+        if manifest_changes::unchanged(cx, &packages, rev) {
             return Ok(());
         }
-
-        let packages = determine_package_list(cx)?;
-        let mut progress = Progress::default();
-        let mut keep_going = KeepGoing::default();
-        if let Some(range) = cx.version_range {
-            let mut versions = BTreeMap::new();
-            let steps = rustup::version_range(range, cx.version_step, &packages, cx)?;
-            for pkg in packages {
-                let msrv = cx
-                    .rust_version(pkg.id)
-                    .map(str::parse::<Version>)
-                    .transpose()?
-                    .map(Version::strip_patch);
-                if range == VersionRange::msrv() {
-                    let msrv = msrv.ok_or_else(|| {
-                        format_err!(
-                            "no rust-version field in {}'s Cargo.toml is specified",
-                            cx.packages(pkg.id).name
-                        )
-                    })?;
-                    versions.entry(msrv).or_insert_with(Vec::new).push(pkg);
-                } else {
-                    let mut seen = false;
-                    for cargo_version in &steps {
-                        if msrv.is_some() && Some(*cargo_version) < msrv {
-                            continue;
-                        }
-                        if !seen {
-                            if Some(*cargo_version) != msrv {
-                                if let Some(msrv) = msrv {
-                                    versions.entry(msrv).or_insert_with(Vec::new).push(pkg.clone());
-                                }
-                            }
-                            seen = true;
-                        }
-                        versions.entry(*cargo_version).or_insert_with(Vec::new).push(pkg.clone());
-                    }
-                    if !seen {
-                        let package = cx.packages(pkg.id);
-                        let name = &package.name;
-                        let msrv = msrv.expect("always `seen` if no msrv");
-                        warn!("skipping {name}, rust-version ({msrv}) is not in specified range ({range})");
-                    }
+    }
+    if let Some(rev) = &cx.changed_features_only {
+        packages = changed_features::filter(cx, packages, rev, &mut skipped_packages)?;
+    }
+    if cx.report_redundant_packages {
+        packages = redundant::check(cx, packages, &mut skipped_packages);
+    }
+    if !cx.deny_warnings_for.is_empty() && !cx.print_command_list {
+        let denied = packages
+            .iter()
+            .filter(|pkg| cx.deny_warnings_for.contains(&cx.packages(pkg.id).name))
+            .count();
+        if denied != 0 && denied != packages.len() {
+            warn!(
+                "--deny-warnings-for runs commands on some of the packages with different \
+                 RUSTFLAGS, which invalidates the build cache each time the package changes; \
+                 consider running on those packages separately with a separate target directory \
+                 (e.g., CARGO_TARGET_DIR)"
+            );
+        }
+    }
+    if cx.print_toolchain_requirements {
+        let requirements = if cx.version_range.is_empty() {
+            vec![Requirement::new(cx, None, &cx.target)]
+        } else {
+            let (versions, targets) = plan_versions(cx, packages, &mut skipped_packages)?;
+            toolchain_requirements(cx, &versions, &targets)
+        };
+        return requirements::print(&requirements);
+    }
+    let coverage = default_coverage::check(cx, &packages);
+    let baseline = cx.baseline.as_deref().map(baseline::Baseline::load).transpose()?;
+    let selected: Vec<_> = packages.iter().map(|pkg| pkg.id).collect();
+    manifest::with(cx, Some(&selected), || {
+        if cx.bisect_features {
+            return bisect_on_packages(cx, &packages);
+        }
+        if cx.ensure_targets_buildable {
+            buildable::check(cx, &packages)?;
+        }
+        let mut declared_msrv = BTreeMap::new();
+        if cx.check_msrv_consistency {
+            for pkg in &packages {
+                if let Some(msrv) = cx.msrv(pkg.id)? {
+                    declared_msrv.insert(cx.packages(pkg.id).name.clone(), msrv);
                 }
             }
-            let versions = versions; // make immutable
-            if versions.is_empty() {
-                // TODO: emit warning
-                return Ok(());
+        }
+        let mut progress = Progress {
+            durations: timing::Durations::new(cx.discard_warmup_timing),
+            ..Progress::default()
+        };
+        if cx.sccache_stats && !cx.print_command_list {
+            progress.sccache = sccache::Sccache::new();
+        }
+        let mut keep_going = KeepGoing { skipped_packages, baseline, ..KeepGoing::default() };
+        if cx.llvm_cov_report_args.is_some() {
+            // Remove the coverage data of previous runs, which would otherwise be merged.
+            cargo_llvm_cov(cx, "clean", &["--workspace".to_owned()])?;
+        }
+        if cx.porcelain {
+            porcelain::header();
+        }
+        let mut res = exec_on_workspace(cx, packages, &mut progress, &mut keep_going);
+        match &cx.llvm_cov_report_args {
+            Some(args) if res.is_ok() => {
+                // Each run is performed with --no-report, so merge them into a single report.
+                if !cx.print_command_list {
+                    eprintln!();
+                }
+                res = cargo_llvm_cov(cx, "report", args);
             }
-
-            for (cargo_version, packages) in &versions {
-                for package in packages {
-                    if cx.target.is_empty() || cargo_version.minor >= 64 {
-                        progress.total += package.feature_count;
-                    } else {
-                        progress.total += package.feature_count * cx.target.len();
-                    }
+            _ => {}
+        }
+        let mut additivity = vec![];
+        if cx.check_additivity && !cx.print_command_list {
+            additivity = additivity::check(&keep_going.runs);
+            eprintln!();
+            if additivity.is_empty() {
+                info!("no feature additivity issues found");
+            }
+            for finding in &additivity {
+                warn!("{finding}");
+            }
+        }
+        if cx.rustdoc_all_features_check && !cx.print_command_list {
+            let findings = doc_features::check(&keep_going.runs);
+            eprintln!();
+            if findings.is_empty() {
+                info!("no documentation issues found across features");
+            }
+            for finding in &findings {
+                warn!("{finding}");
+            }
+        }
+        let slowest = cx.slow_threshold_factor.is_some() && !cx.print_command_list;
+        if slowest {
+            eprintln!();
+            info!("slowest commands:");
+            for run in timing::slowest(&keep_going.runs, timing::SLOWEST_COUNT) {
+                eprintln!(
+                    "    {:.1}s: {} on {}{}",
+                    run.duration.as_secs_f64(),
+                    run.command,
+                    run.package,
+                    if run.cold_start { " (cold start)" } else { "" }
+                );
+            }
+        }
+        if cx.sccache_stats && !cx.print_command_list {
+            eprintln!();
+            sccache::print(&keep_going.runs);
+        }
+        if cx.verify_lockfile_per_version && !cx.print_command_list {
+            eprintln!();
+            lockfile::print(&keep_going.lockfile_drift);
+        }
+        if cx.check_msrv_consistency && !cx.print_command_list {
+            eprintln!();
+            msrv::print(&msrv::check(&keep_going.runs, &declared_msrv));
+        }
+        default_coverage::print(cx, &coverage);
+        if let Some(path) = &cx.summary_markdown {
+            if let Err(e) = summary::write_markdown(path, &keep_going.runs, &additivity, slowest) {
+                error!("{e:#}");
+            }
+        }
+        if let Some(path) = &cx.summary_json {
+            let skipped = &keep_going.skipped_packages;
+            if let Err(e) = summary::write_json(path, &keep_going.runs, skipped) {
+                error!("{e:#}");
+            }
+        }
+        if let Some(path) = &cx.junit {
+            if let Err(e) = junit::write(path, &keep_going.runs, &keep_going.skipped_packages) {
+                error!("{e:#}");
+            }
+        }
+        if let (Some(baseline), Some(path)) = (&keep_going.baseline, &cx.baseline) {
+            if !cx.print_command_list {
+                baseline.compare(&keep_going.runs).print(path);
+            }
+        }
+        if let Some(path) = &cx.emit_execution_plan {
+            cx.execution_plan.write(cx, path)?;
+        }
+        if let Some(path) = &cx.badge_json {
+            if !cx.print_command_list {
+                let totals = badge_totals(cx, &progress, &keep_going);
+                if let Err(e) = badge::write(path, &totals) {
+                    error!("{e:#}");
                 }
             }
-
-            // First, generate the lockfile using the oldest cargo specified.
-            // https://github.com/taiki-e/cargo-hack/issues/105
-            // For now, only generate lockfile if min version is pre-1.60.
-            // (If future cargo introduces compatibility issues, the number of
-            // versions requiring generate-lockfile will probably increase.)
-            // https://github.com/taiki-e/cargo-hack/issues/234#issuecomment-2028517197
-            let mut generate_lockfile =
-                !cx.locked && versions.first_key_value().unwrap().0.minor < 60;
-            // Workaround for spurious "failed to select a version" error.
-            // (This does not work around the underlying cargo bug: https://github.com/rust-lang/cargo/issues/10623)
-            let mut regenerate_lockfile_on_51_or_up = false;
-            for (cargo_version, packages) in versions {
-                versioned_cargo_exec_on_packages(
-                    cx,
-                    &packages,
-                    cargo_version.minor,
-                    &mut progress,
-                    &mut keep_going,
-                    &mut generate_lockfile,
-                    &mut regenerate_lockfile_on_51_or_up,
-                )?;
+        }
+        res?;
+        let unmatched: Vec<_> = cx.only_run.difference(&progress.matched_runs).collect();
+        if !unmatched.is_empty() {
+            bail!(
+                "the following ids passed to --only-run did not match any run: {}",
+                unmatched.iter().map(|id| id.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+        if let Some(kinds) = &cx.fail_on_skipped {
+            if let Some(summary) = cx.skips.summary(kinds) {
+                eprintln!();
+                error!("{summary}");
             }
-        } else {
-            let total = packages.iter().map(|p| p.feature_count).sum();
-            progress.total = total;
-            default_cargo_exec_on_packages(cx, &packages, &mut progress, &mut keep_going)?;
+        }
+        if progress.total == 0 {
+            empty_plan::check(cx)?;
         }
         if keep_going.count > 0 {
             eprintln!();
             error!("{keep_going}");
         }
+        if let Some(cutoff) = &progress.budget_cutoff {
+            let report = budget::report(cx.budget.unwrap(), progress.elapsed, cutoff);
+            eprintln!();
+            // Other errors take precedence over the exit code of --budget.
+            if term::error() {
+                error!("{report}");
+            } else {
+                return Err(budget::Exceeded(report).into());
+            }
+        }
         Ok(())
     })
 }
 
+fn badge_totals(cx: &Context, progress: &Progress, keep_going: &KeepGoing) -> badge::Totals {
+    let count = |status| keep_going.runs.iter().filter(|run| run.status == status).count();
+    let kinds = cx.fail_on_skipped.clone().unwrap_or_else(skip::default_kinds);
+    badge::Totals {
+        passed: count(summary::Status::Passed),
+        failed: count(summary::Status::Failed),
+        total: match &cx.partition {
+            Some(partition) => progress.partition_len(partition),
+            None => progress.total,
+        },
+        partition: cx.partition.as_ref().map(Partition::label),
+        skipped: cx.skips.summary(&kinds).is_some(),
+        fail_on_skipped: cx.fail_on_skipped.is_some(),
+    }
+}
+
+fn exec_on_workspace(
+    cx: &Context,
+    packages: Vec<PackageRuns<'_>>,
+    progress: &mut Progress,
+    keep_going: &mut KeepGoing,
+) -> Result<()> {
+    if cx.version_range.is_empty() {
+        if cx.no_install {
+            requirements::check_installed(&[Requirement::new(cx, None, &cx.target)])?;
+        }
+        let total: usize = packages.iter().map(|p| p.feature_count).sum();
+        progress.total = total * cx.cfg_sets.len() * (1 + cx.and_then.len());
+        progress.check_partition(cx);
+        default_cargo_exec_on_packages(cx, &packages, progress, keep_going)?;
+    } else {
+        let (versions, targets) = plan_versions(cx, packages, &mut keep_going.skipped_packages)?;
+        if versions.is_empty() {
+            // Reported by empty_plan::check after the runs.
+            return Ok(());
+        }
+        if cx.no_install {
+            requirements::check_installed(&toolchain_requirements(cx, &versions, &targets))?;
+        }
+        manifest::check_lints_supported(cx, *versions.first_key_value().unwrap().0)?;
+
+        for (cargo_version, packages) in &versions {
+            for package in packages {
+                let feature_count =
+                    package.feature_count * cx.cfg_sets.len() * (1 + cx.and_then.len());
+                if cx.target.is_empty() || cargo_version.minor >= 64 {
+                    progress.total += feature_count;
+                } else {
+                    progress.total += feature_count * targets[cargo_version].len();
+                }
+            }
+        }
+        progress.check_partition(cx);
+
+        if cx.lockfile_strategy == LockfileStrategy::Keep {
+            lockfile_strategy::check_keep(cx.workspace_root(), versions.keys().copied())?;
+        }
+        let mut lockfile = lockfile_strategy::Generator::new(
+            cx.lockfile_strategy,
+            cx.locked,
+            versions.keys().copied().collect(),
+            cx.version_order,
+        );
+        if let Some((version, reason)) = lockfile.prepare() {
+            generate_lockfile_with(cx, version, reason)?;
+        }
+        let mut versions: Vec<_> = versions.into_iter().collect();
+        if cx.version_order == VersionOrder::NewestFirst {
+            versions.reverse();
+        }
+        // --print-command-list must not install toolchains, so flags cannot be validated.
+        let mut validate_flags = !cx.print_command_list;
+        for (cargo_version, packages) in versions {
+            versioned_cargo_exec_on_packages(
+                cx,
+                &packages,
+                cargo_version,
+                &targets[&cargo_version],
+                progress,
+                keep_going,
+                &mut lockfile,
+                &mut validate_flags,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The packages to run on each toolchain, and the targets to run with on each
+/// toolchain.
+type VersionPlan<'a> = (BTreeMap<Version, Vec<PackageRuns<'a>>>, BTreeMap<Version, Vec<String>>);
+
+/// Determines the toolchains of --version-range to run on.
+fn plan_versions<'a>(
+    cx: &Context,
+    packages: Vec<PackageRuns<'a>>,
+    skipped: &mut Vec<summary::SkippedPackage>,
+) -> Result<VersionPlan<'a>> {
+    let mut versions = BTreeMap::new();
+    let mut steps = vec![];
+    for &range in &cx.version_range {
+        steps.extend(rustup::version_range(range, cx.version_step, &packages, cx)?);
+    }
+    steps.sort_unstable();
+    steps.dedup();
+    let msrv_bound = cx
+        .version_range
+        .iter()
+        .any(|r| r.start_inclusive == MaybeVersion::Msrv || r.end_inclusive == MaybeVersion::Msrv);
+    for pkg in packages {
+        // Unlike the steps of the range, the patch version of the
+        // rust-version is preserved, e.g., to avoid testing on a patch
+        // release with a known bug.
+        let declared_msrv = cx.msrv(pkg.id)?;
+        let mut msrv = declared_msrv;
+        if msrv.is_none() && msrv_bound {
+            if let Some((edition, edition_msrv)) = cx.edition_msrv(pkg.id) {
+                warn!(
+                    "no rust-version field in {}'s Cargo.toml is specified; using \
+                     {edition_msrv} derived from edition {edition} as its minimum Rust version",
+                    cx.packages(pkg.id).name
+                );
+                msrv = Some(edition_msrv);
+            }
+        }
+        if cx.version_range == [VersionRange::msrv()] {
+            let msrv = msrv.ok_or_else(|| {
+                format_err!(
+                    "no rust-version or edition field in {}'s Cargo.toml is specified",
+                    cx.packages(pkg.id).name
+                )
+            })?;
+            versions.entry(msrv).or_insert_with(Vec::new).push(pkg);
+        } else {
+            let mut pkg_versions = rustup::package_versions(msrv, &steps);
+            if cx.check_msrv_consistency {
+                // Also run on the versions older than the rust-version to
+                // see whether the rust-version is higher than needed.
+                // Versions older than the edition are never supported.
+                pkg_versions.extend(
+                    steps.iter().filter(|&&v| declared_msrv.is_some_and(|msrv| v < msrv)).copied(),
+                );
+                pkg_versions.sort_unstable();
+            }
+            for &cargo_version in &pkg_versions {
+                versions.entry(cargo_version).or_insert_with(Vec::new).push(pkg.clone());
+            }
+            if pkg_versions.is_empty() {
+                let package = cx.packages(pkg.id);
+                let name = &package.name;
+                let msrv = msrv.expect("always non-empty if no msrv");
+                let range = cx.version_range.iter().map(ToString::to_string).collect::<Vec<_>>();
+                warn!(
+                    "skipping {name}, rust-version ({msrv}) is not in specified range ({})",
+                    range.join(", ")
+                );
+                cx.skips.record(
+                    SkipKind::MsrvOutOfRange,
+                    name,
+                    format!("rust-version ({msrv}) is not in {}", range.join(", ")),
+                );
+                skipped.push(summary::SkippedPackage {
+                    package: name.clone(),
+                    reason: format!(
+                        "rust-version ({msrv}) is not in specified range ({})",
+                        range.join(", ")
+                    ),
+                });
+            }
+        }
+    }
+    // Skip the runs that enable features requiring a newer toolchain.
+    let mut versions: BTreeMap<_, Vec<_>> = versions
+        .into_iter()
+        .map(|(cargo_version, packages)| {
+            let packages = packages
+                .into_iter()
+                .filter_map(|pkg| feature_versions::filter(cx, pkg, cargo_version))
+                .collect();
+            (cargo_version, packages)
+        })
+        .filter(|(_, packages): &(_, Vec<_>)| !packages.is_empty())
+        .collect();
+    let targets = targets::for_versions(cx, &versions.keys().copied().collect::<Vec<_>>())?;
+    // Skip the versions on which none of the targets are available.
+    versions.retain(|version, _| cx.target.is_empty() || !targets[version].is_empty());
+    Ok((versions, targets))
+}
+
+/// Returns the toolchains, targets, and components that the runs on `versions`
+/// require.
+fn toolchain_requirements(
+    cx: &Context,
+    versions: &BTreeMap<Version, Vec<PackageRuns<'_>>>,
+    targets: &BTreeMap<Version, Vec<String>>,
+) -> Vec<Requirement> {
+    versions
+        .keys()
+        .map(|version| Requirement::new(cx, Some(version.to_string()), &targets[version]))
+        .collect()
+}
+
 #[derive(Default)]
 struct Progress {
     total: usize,
     count: usize,
+    /// Durations of completed runs, used for --slow-threshold-factor.
+    durations: timing::Durations,
+    /// Used to mark the first run on each package as a cold start.
+    warmup: timing::Warmup,
+    /// Used for --sccache-stats.
+    sccache: sccache::Sccache,
+    /// Cumulative wall time of the executed commands, used for --budget.
+    elapsed: Duration,
+    /// The first run that was not run because --budget was exceeded.
+    budget_cutoff: Option<budget::Cutoff>,
+    /// The ids of the runs that matched --only-run.
+    matched_runs: BTreeSet<String>,
+    /// Why the last skipped run was skipped, used for --porcelain.
+    skip_reason: &'static str,
 }
 
 impl Progress {
+    fn over_budget(&self, cx: &Context) -> bool {
+        cx.budget.is_some_and(|budget| self.elapsed >= budget)
+    }
+
     fn in_partition(&self, partition: &Partition) -> bool {
-        // div_ceil (stabilized at 1.73) can't be used due to MSRV = 1.70...
-        let mut chunk_count = self.total / partition.count;
-        if self.total % partition.count != 0 {
-            chunk_count += 1;
+        partition.range(self.total).contains(&self.count)
+    }
+
+    /// Returns the number of runs in the partition, used for --badge-json.
+    fn partition_len(&self, partition: &Partition) -> usize {
+        partition.range(self.total).len()
+    }
+
+    /// Warns if the partition contains none of the planned runs.
+    fn check_partition(&self, cx: &Context) {
+        if let Some(partition @ Partition::Named { .. }) = &cx.partition {
+            if self.total != 0 && partition.range(self.total).is_empty() {
+                warn!(
+                    "partition {partition} contains none of the {} planned runs; no commands are \
+                     run in this partition",
+                    self.total
+                );
+            }
         }
-        let current_index = self.count / chunk_count;
-        current_index == partition.index
     }
 }
 
@@ -182,29 +610,106 @@ enum Kind<'a> {
     Powerset { features: Vec<Vec<&'a Feature>> },
 }
 
+/// Returns the runs on the package, or `None` if the package is skipped.
+///
+/// The reasons for skipped packages are recorded to `skipped`.
 fn determine_kind<'a>(
     cx: &'a Context,
     id: &'a PackageId,
     multiple_packages: bool,
-) -> Option<PackageRuns<'a>> {
+    skipped: &mut Vec<summary::SkippedPackage>,
+) -> Result<Option<PackageRuns<'a>>> {
     assert!(cx.subcommand.is_some());
+    let mut skip = |reason: String| {
+        let package = cx.packages(id).name.clone();
+        skipped.push(summary::SkippedPackage { package, reason });
+    };
     if cx.ignore_private && cx.is_private(id) {
         info!("skipped running on private package `{}`", cx.name_verbose(id));
-        return None;
+        cx.skips.record(SkipKind::PrivatePackage, &cx.packages(id).name, "private package");
+        skip("private package".to_owned());
+        return Ok(None);
+    }
+    if let Some(f) =
+        cx.only_packages_with_feature.iter().find(|&f| !cx.pkg_features(id).contains(f))
+    {
+        info!(
+            "skipped running on package `{}` that does not have feature `{f}`",
+            cx.name_verbose(id)
+        );
+        let reason = format!("does not have feature `{f}` (--only-packages-with-feature)");
+        cx.skips.record(SkipKind::ExcludedPackage, &cx.packages(id).name, &reason);
+        skip(reason);
+        return Ok(None);
     }
-    if !cx.each_feature && !cx.feature_powerset {
+    if matches!(cx.subcommand.as_deref(), Some("run" | "r")) && !has_run_target(cx, id)? {
+        cx.skips.record(
+            SkipKind::PackageEmptyPlan,
+            &cx.packages(id).name,
+            "no binary target to run",
+        );
+        skip("no binary target to run".to_owned());
+        return Ok(None);
+    }
+    // Subcommands that do not depend on features (e.g., fmt) are run only once.
+    if !cx.bisect_features && (cx.ignores_features || !cx.each_feature && !cx.feature_powerset) {
+        trace!("plan", "package `{}`: run once without combining features", cx.name_verbose(id));
         let feature_count = 1;
         let kind = Kind::Normal;
-        return Some(PackageRuns { id, kind, feature_count });
+        return Ok(Some(PackageRuns::new(id, kind, feature_count)));
     }
 
     let package = cx.packages(id);
     let pkg_features = cx.pkg_features(id);
+    trace!(
+        "features",
+        "package `{}`: normal features: {:?}, optional dependencies: {:?} (unused on the target: \
+         {:?}), dependency features: {:?}, groups: {:?}",
+        package.name,
+        pkg_features.normal(),
+        pkg_features.optional_deps(),
+        pkg_features.unused_optional_deps(),
+        pkg_features.deps_features(),
+        pkg_features.groups()
+    );
+    // Note: cx.exclude_features also contains cx.features.
+    for excluded in cx.exclude_features.iter().filter(|&f| !cx.features.contains(f)) {
+        for f in &cx.features {
+            if let Some(chain) = features::enable_chain(&package.features, f, excluded) {
+                warn!(
+                    "--exclude-features {excluded} has no effect on package `{}`: `{f}` (from \
+                     --features) enables `{excluded}` via {}",
+                    package.name,
+                    chain.join(" -> ")
+                );
+            }
+        }
+    }
     let filter = |&f: &&Feature| {
-        !cx.exclude_features.iter().any(|s| f == s)
-            && !cx.group_features.iter().any(|g| g.matches(f.name()))
+        if cx.exclude_features.iter().any(|s| f == s) {
+            trace!(
+                "features",
+                "package `{}`: filtered out `{}` by --exclude-features or --features",
+                package.name,
+                f.name()
+            );
+            return false;
+        }
+        if let Some(g) =
+            cx.group_features.iter().chain(pkg_features.groups()).find(|g| g.matches(f.name()))
+        {
+            trace!(
+                "features",
+                "package `{}`: filtered out `{}` as a member of group `{}`",
+                package.name,
+                f.name(),
+                g.as_group().join(",")
+            );
+            return false;
+        }
+        true
     };
-    let features = if cx.include_features.is_empty() {
+    let mut features = if cx.include_features.is_empty() {
         // TODO
         if !multiple_packages {
             for name in &cx.exclude_features {
@@ -222,7 +727,13 @@ fn determine_kind<'a>(
             } else if !multiple_packages {
                 // TODO
                 for d in opt_deps {
-                    if !pkg_features.optional_deps().iter().any(|f| f == d) {
+                    if pkg_features.unused_optional_deps().contains(d) {
+                        info!(
+                            "skipped optional dependency `{d}` of package `{}` that is not used \
+                             on the target",
+                            package.name
+                        );
+                    } else if !pkg_features.optional_deps().iter().any(|f| f == d) {
                         warn!(
                             "specified optional dependency `{d}` not found in package `{}`",
                             package.name
@@ -241,6 +752,16 @@ fn determine_kind<'a>(
         }
 
         if !cx.group_features.is_empty() {
+            check_group_members(cx, package, pkg_features)?;
+            if cx.group_features.iter().any(|g| g.matches("default"))
+                && !pkg_features.contains("default")
+            {
+                warn!(
+                    "package `{}` has no default feature; `default` in --group-features is \
+                     ignored for this package",
+                    package.name
+                );
+            }
             if cx.ignore_unknown_features {
                 let all_valid_features: HashSet<_> = pkg_features
                     .normal()
@@ -249,7 +770,7 @@ fn determine_kind<'a>(
                     .flat_map(Feature::as_group)
                     .map(String::as_str)
                     .collect();
-                features.extend(cx.group_features.iter().filter(|&f| {
+                features.extend(pkg_features.groups().iter().filter(|&f| {
                     let all_valid =
                         f.as_group().iter().all(|f| all_valid_features.contains(f.as_str()));
                     if !all_valid {
@@ -258,96 +779,400 @@ fn determine_kind<'a>(
                             f.as_group().join(","),
                             package.name
                         );
+                        cx.skips.record(
+                            SkipKind::GroupSkipped,
+                            &package.name,
+                            format!("group `{}`", f.as_group().join(",")),
+                        );
                     }
                     all_valid
                 }));
             } else {
-                features.extend(cx.group_features.iter());
+                features.extend(pkg_features.groups());
             }
         }
 
         features
     } else {
-        cx.include_features.iter().filter(filter).collect()
+        let features: Vec<_> = features::expand_include(
+            &cx.include_features,
+            pkg_features,
+            package.features.get("default"),
+        )
+        .into_iter()
+        .filter(filter)
+        .collect();
+        if features.is_empty() && cx.include_features.iter().any(|f| f.name().starts_with('@')) {
+            info!(
+                "--include-features {} expanded to no features on package `{}`",
+                cx.include_features
+                    .iter()
+                    .map(|f| match f.name() {
+                        keyword if keyword.starts_with('@') => keyword.into(),
+                        name => features::quote(name),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                package.name
+            );
+        }
+        features
     };
+    if cx.only_code_gated_features {
+        code_gated::retain(cx, id, &mut features)?;
+    }
+    trace!("features", "package `{}`: features to combine: {features:?}", package.name);
 
-    if cx.each_feature {
+    // --bisect-features uses the same features as --each-feature.
+    if cx.each_feature || cx.bisect_features {
         if (pkg_features.normal().is_empty() && pkg_features.optional_deps().is_empty()
             || !cx.include_features.is_empty())
             && features.is_empty()
         {
+            cx.skips.record(
+                SkipKind::PackageEmptyPlan,
+                &package.name,
+                "no features to combine; run only with the default features",
+            );
             let feature_count = 1;
             let kind = Kind::Normal;
-            Some(PackageRuns { id, kind, feature_count })
+            Ok(Some(PackageRuns::new(id, kind, feature_count)))
         } else {
             // See exec_on_package
-            let feature_count = features.len()
-                + (!cx.exclude_no_default_features) as usize
-                + (!(cx.exclude_all_features
-                    || pkg_features.optional_deps().is_empty() && pkg_features.normal().len() <= 1))
-                    as usize;
+            let all_features = !(cx.excludes_all_features(id)
+                || pkg_features.optional_deps().is_empty() && pkg_features.normal().len() <= 1);
+            let feature_count =
+                features.len() + (!cx.exclude_no_default_features) as usize + all_features as usize;
+            trace!(
+                "plan",
+                "package `{}`: each feature: {feature_count} runs (all features: {all_features})",
+                package.name
+            );
             let kind = Kind::Each { features };
-            Some(PackageRuns { id, kind, feature_count })
+            Ok(Some(skip_redundant_all_features(
+                cx,
+                PackageRuns::new(id, kind, feature_count),
+                all_features,
+            )))
         }
     } else if cx.feature_powerset {
-        let features = features::feature_powerset(
+        let candidates = features.clone();
+        let mut removed = vec![];
+        let record_removed = cx.why.is_some() || trace::enabled();
+        let mut features = features::feature_powerset(
             features,
             cx.depth,
+            cx.depth_counts_group_members,
+            cx.max_group_combo,
             &cx.at_least_one_of,
             &cx.mutually_exclusive_features,
-            &package.features,
+            &cx.exactly_one_of,
+            &features::normalize_dep_features(&package.features, &package.dependencies),
+            record_removed.then_some(&mut removed),
         );
+        let merged =
+            features::merge_overlapping(&mut features, record_removed.then_some(&mut removed));
+        for (fs, reason) in &removed {
+            trace!("plan", "package `{}`: combination {fs:?} {reason}", package.name);
+        }
+        trace!(
+            "plan",
+            "package `{}`: powerset of {} features (depth: {:?}): kept {} combinations: \
+             {features:?}",
+            package.name,
+            candidates.len(),
+            cx.depth,
+            features.len()
+        );
+        if merged != 0 {
+            info!(
+                "merged {merged} feature combinations of package `{}` into other combinations that \
+                 enable the same features, because groups of features overlap",
+                package.name
+            );
+        }
+        if cx.why.is_some() {
+            why::explain(cx, id, &candidates, &features, &removed);
+        }
+        for cycle in features::feature_cycles(&package.features) {
+            warn!(
+                "features {} of package `{}` enable each other in a cycle; they are treated as \
+                 implying each other when combining features",
+                cycle.iter().map(|f| format!("`{f}`")).collect::<Vec<_>>().join(", "),
+                package.name
+            );
+        }
 
         if (pkg_features.normal().is_empty() && pkg_features.optional_deps().is_empty()
             || !cx.include_features.is_empty())
             && features.is_empty()
         {
+            cx.skips.record(
+                SkipKind::PackageEmptyPlan,
+                &package.name,
+                "no features to combine; run only with the default features",
+            );
             let feature_count = 1;
             let kind = Kind::Normal;
-            Some(PackageRuns { id, kind, feature_count })
+            Ok(Some(PackageRuns::new(id, kind, feature_count)))
         } else {
             // See exec_on_package
-            let feature_count = features.len()
-                + (!cx.exclude_no_default_features) as usize
-                + (!(cx.exclude_all_features
-                    || (pkg_features.optional_deps().is_empty()
-                        || match &cx.optional_deps {
-                            // Skip when all optional deps are already included in powerset
-                            Some(opt_deps) => opt_deps.is_empty(),
-                            None => false,
-                        }))) as usize;
+            let all_features = !(cx.excludes_all_features(id)
+                || (pkg_features.optional_deps().is_empty()
+                    || match &cx.optional_deps {
+                        // Skip when all optional deps are already included in powerset
+                        Some(opt_deps) => opt_deps.is_empty(),
+                        None => false,
+                    }));
+            let feature_count =
+                features.len() + (!cx.exclude_no_default_features) as usize + all_features as usize;
             let kind = Kind::Powerset { features };
-            Some(PackageRuns { id, kind, feature_count })
+            Ok(Some(skip_redundant_all_features(
+                cx,
+                PackageRuns::new(id, kind, feature_count),
+                all_features,
+            )))
         }
     } else {
         unreachable!()
     }
 }
 
+/// Checks that the members of the groups of --group-features can be enabled
+/// by their names in the package.
+///
+/// Optional dependencies are only combined with --optional-deps, so a group
+/// containing one is rejected without --optional-deps instead of silently
+/// enabling the dependency. Optional dependencies that are only referenced
+/// with `dep:` syntax cannot be enabled by their names at all, so groups
+/// containing them are rejected unless --ignore-unknown-features is used, in
+/// which case such groups are skipped.
+fn check_group_members(
+    cx: &Context,
+    package: &metadata::Package,
+    pkg_features: &features::Features,
+) -> Result<()> {
+    for group in pkg_features.groups() {
+        for member in group.as_group() {
+            match pkg_features.group_member(member) {
+                GroupMember::OptionalDep if cx.optional_deps.is_none() => bail!(
+                    "group `{}` of --group-features contains `{member}`, which is an optional \
+                     dependency of package `{}`; optional dependencies are only combined with \
+                     --optional-deps, so use --optional-deps together with this group",
+                    group.as_group().join(","),
+                    package.name
+                ),
+                GroupMember::NamespacedOptionalDep if !cx.ignore_unknown_features => {
+                    let dep = format!("dep:{member}");
+                    let enabled_by: Vec<_> = package
+                        .features
+                        .iter()
+                        .filter(|(_, v)| v.contains(&dep))
+                        .map(|(f, _)| format!("`{f}`"))
+                        .collect();
+                    bail!(
+                        "group `{}` of --group-features contains `{member}`, which is an optional \
+                         dependency of package `{}` that is only enabled via `{dep}` and cannot \
+                         be enabled by its name; use the feature(s) that enable it ({}) in the \
+                         group instead",
+                        group.as_group().join(","),
+                        package.name,
+                        enabled_by.join(", ")
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Skips the run with --all-features if it enables the same features and
+/// optional dependencies as one of the feature combinations.
+///
+/// `all_features` is whether the run with --all-features is planned.
+fn skip_redundant_all_features<'a>(
+    cx: &Context,
+    mut pkg: PackageRuns<'a>,
+    all_features: bool,
+) -> PackageRuns<'a> {
+    if !all_features {
+        return pkg;
+    }
+    let package = cx.packages(pkg.id);
+    let combinations: Vec<Vec<&Feature>> = match &pkg.kind {
+        Kind::Each { features } => features.iter().map(|&f| vec![f]).collect(),
+        Kind::Powerset { features } => features.clone(),
+        Kind::Normal => return pkg,
+    };
+    // Since cargo 1.47, --all-features also enables the optional dependencies
+    // that are not enabled by any feature: https://github.com/rust-lang/cargo/pull/8799
+    let optional_deps: BTreeSet<_> = package.optional_deps().collect();
+    let enabled = |features: BTreeSet<String>| {
+        let mut enabled = buildable::enable(&package.features, &features);
+        for &dep in &optional_deps {
+            if enabled.contains(dep) {
+                enabled.insert(format!("dep:{dep}"));
+            }
+        }
+        enabled
+    };
+    let mut all = enabled(package.features.keys().cloned().collect());
+    all.extend(optional_deps.iter().map(|dep| format!("dep:{dep}")));
+    let same = combinations.iter().find(|fs| {
+        let requested =
+            cx.features.iter().chain(fs.iter().flat_map(|f| f.as_group())).cloned().collect();
+        enabled(requested) == all
+    });
+    if let Some(same) = same {
+        let list: Vec<_> = same.iter().flat_map(|f| f.as_group()).map(String::as_str).collect();
+        info!(
+            "skipped the run with --all-features on package `{}`, because it enables the same \
+             features as --features {}",
+            cx.name_verbose(pkg.id),
+            list.join(",")
+        );
+        pkg.skip_all_features = true;
+        pkg.feature_count -= 1;
+    }
+    pkg
+}
+
+/// Returns `true` if `cargo run` can run a binary of the package.
+///
+/// Packages without binary targets (or without the binary target specified by
+/// `--bin`) are skipped, and packages with multiple binary targets without
+/// `default-run` are rejected because `cargo run` cannot determine which one to run.
+fn has_run_target(cx: &Context, id: &PackageId) -> Result<bool> {
+    let mut args = cx.leading_args.iter();
+    let mut bin = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Examples are not binary targets; keep the default behavior.
+            "--example" => return Ok(true),
+            _ if arg.starts_with("--example=") => return Ok(true),
+            "--bin" => bin = args.next().map(String::as_str),
+            _ => {
+                if let Some(name) = arg.strip_prefix("--bin=") {
+                    bin = Some(name);
+                }
+            }
+        }
+    }
+
+    let package = cx.packages(id);
+    let bins: Vec<_> = package.bins().collect();
+    if let Some(bin) = bin {
+        if !bins.contains(&bin) {
+            info!(
+                "skipped running on package `{}` that does not have binary target `{bin}`",
+                cx.name_verbose(id)
+            );
+            return Ok(false);
+        }
+    } else if bins.is_empty() {
+        info!(
+            "skipped running on package `{}` that does not have binary targets",
+            cx.name_verbose(id)
+        );
+        return Ok(false);
+    } else if bins.len() > 1 && package.default_run.is_none() {
+        bail!(
+            "package `{}` has multiple binary targets ({}); use --bin to specify which one to run, \
+             or set `default-run` in its Cargo.toml",
+            cx.name_verbose(id),
+            bins.join(", ")
+        );
+    }
+    Ok(true)
+}
+
 #[derive(Clone)]
 struct PackageRuns<'a> {
     id: &'a PackageId,
     kind: Kind<'a>,
     feature_count: usize,
+    /// Whether the run with --all-features is skipped in addition to the
+    /// cases checked by [`skips_all_features`], e.g., because some features
+    /// require a newer toolchain.
+    skip_all_features: bool,
+    /// Whether the run with just --no-default-features is skipped in addition
+    /// to the case of --exclude-no-default-features, e.g., because it
+    /// contains no features changed since the revision of
+    /// --changed-features-only.
+    skip_no_default_features: bool,
 }
 
-fn determine_package_list(cx: &Context) -> Result<Vec<PackageRuns<'_>>> {
+impl<'a> PackageRuns<'a> {
+    fn new(id: &'a PackageId, kind: Kind<'a>, feature_count: usize) -> Self {
+        Self { id, kind, feature_count, skip_all_features: false, skip_no_default_features: false }
+    }
+}
+
+fn determine_package_list<'a>(
+    cx: &'a Context,
+    skipped: &mut Vec<summary::SkippedPackage>,
+) -> Result<Vec<PackageRuns<'a>>> {
+    let ids = select_packages(cx)?;
+    trace!(
+        "plan",
+        "selected packages: {:?}",
+        ids.iter().map(|&id| &cx.packages(id).name).collect::<Vec<_>>()
+    );
+    let multiple_packages = ids.len() > 1;
+    ids.into_iter()
+        .filter_map(|id| determine_kind(cx, id, multiple_packages, skipped).transpose())
+        .collect()
+}
+
+/// Returns the packages selected by --workspace, --package, --exclude, etc.
+fn select_packages(cx: &Context) -> Result<Vec<&PackageId>> {
     for spec in &cx.exclude {
-        if !cx.workspace_members().any(|id| cx.packages(id).name == *spec) {
+        if !cx.workspace_members().any(|id| workspace::wildcard(spec, &cx.packages(id).name)) {
             warn!(
                 "excluded package(s) `{spec}` not found in workspace `{}`",
                 cx.workspace_root().display()
             );
         }
     }
+    for spec in &cx.exclude_all_features_packages {
+        if !cx.workspace_members().any(|id| cx.packages(id).name == *spec) {
+            warn!(
+                "package `{spec}` specified by --exclude-all-features not found in workspace `{}`",
+                cx.workspace_root().display()
+            );
+        }
+    }
+    for spec in &cx.deny_warnings_for {
+        if !cx.workspace_members().any(|id| cx.packages(id).name == *spec) {
+            warn!(
+                "package `{spec}` specified by --deny-warnings-for not found in workspace `{}`",
+                cx.workspace_root().display()
+            );
+        }
+    }
+    for f in &cx.only_packages_with_feature {
+        if !cx.workspace_members().any(|id| cx.pkg_features(id).contains(f)) {
+            warn!(
+                "feature `{f}` specified by --only-packages-with-feature not found in workspace `{}`",
+                cx.workspace_root().display()
+            );
+        }
+    }
+    let is_excluded = |id: &PackageId| {
+        let name = &cx.packages(id).name;
+        if !cx.exclude.iter().any(|spec| workspace::wildcard(spec, name)) {
+            return false;
+        }
+        cx.skips.record(SkipKind::ExcludedPackage, name, "excluded by --exclude");
+        true
+    };
     Ok(if cx.workspace {
-        let ids: Vec<_> = cx
-            .workspace_members()
-            .filter(|id| !cx.exclude.contains(&cx.packages(id).name))
-            .collect();
-        let multiple_packages = ids.len() > 1;
-        ids.iter().filter_map(|id| determine_kind(cx, id, multiple_packages)).collect()
-    } else if !cx.package.is_empty() {
+        cx.workspace_members().filter(|id| !is_excluded(id)).collect()
+    } else if !cx.package.is_empty()
+        || !cx.package_with_deps.is_empty()
+        || !cx.package_with_dependents.is_empty()
+    {
         if let Some(spec) = cx
             .package
             .iter()
@@ -355,71 +1180,74 @@ fn determine_package_list(cx: &Context) -> Result<Vec<PackageRuns<'_>>> {
         {
             bail!("package ID specification `{spec}` matched no packages")
         }
+        let closure = closure::packages(cx)?;
 
-        let ids: Vec<_> = cx
-            .workspace_members()
-            .filter(|id| cx.package.contains(&cx.packages(id).name))
-            .filter(|id| !cx.exclude.contains(&cx.packages(id).name))
-            .collect();
-        let multiple_packages = ids.len() > 1;
-        ids.iter().filter_map(|id| determine_kind(cx, id, multiple_packages)).collect()
+        cx.workspace_members()
+            .filter(|id| cx.package.contains(&cx.packages(id).name) || closure.contains(id))
+            .filter(|id| !is_excluded(id))
+            .collect()
     } else if cx.current_package().is_none() {
-        let ids: Vec<_> = cx
-            .workspace_members()
-            .filter(|id| !cx.exclude.contains(&cx.packages(id).name))
-            .collect();
-        let multiple_packages = ids.len() > 1;
-        ids.iter().filter_map(|id| determine_kind(cx, id, multiple_packages)).collect()
+        cx.workspace_members().filter(|id| !is_excluded(id)).collect()
     } else {
         let current_package = &cx.packages(cx.current_package().unwrap()).name;
-        let multiple_packages = false;
         cx.workspace_members()
             .find(|id| cx.packages(id).name == *current_package)
-            .filter(|id| !cx.exclude.contains(&cx.packages(id).name))
-            .and_then(|id| determine_kind(cx, id, multiple_packages).map(|p| vec![p]))
-            .unwrap_or_default()
+            .filter(|id| !is_excluded(id))
+            .into_iter()
+            .collect()
     })
 }
 
 fn versioned_cargo_exec_on_packages(
     cx: &Context,
     packages: &[PackageRuns<'_>],
-    cargo_version: u32,
+    cargo_version: Version,
+    targets: &[String],
     progress: &mut Progress,
     keep_going: &mut KeepGoing,
-    generate_lockfile: &mut bool,
-    regenerate_lockfile_on_51_or_up: &mut bool,
+    lockfile: &mut lockfile_strategy::Generator,
+    validate_flags: &mut bool,
 ) -> Result<()> {
     // Do not use `cargo +<toolchain>` due to a rustup bug: https://github.com/rust-lang/rustup/issues/3036
     let mut line = cmd!("rustup");
     line.leading_arg("run");
 
-    let toolchain = format!("1.{cargo_version}");
-    let print_output = true;
-    rustup::install_toolchain(&toolchain, &cx.target, print_output, cx.log_group)?;
-    if *generate_lockfile || *regenerate_lockfile_on_51_or_up && cargo_version >= 51 {
-        let mut line = line.clone();
+    let current = cargo_version;
+    let toolchain = cargo_version.to_string();
+    let cargo_version = cargo_version.minor;
+    keep_going.toolchain = Some(toolchain.clone());
+    if progress.over_budget(cx) {
+        // No commands are run on this toolchain, so do not install it or
+        // generate the lockfile; the runs are only recorded as not run.
         line.leading_arg(&toolchain);
         line.leading_arg("cargo");
-        line.arg("generate-lockfile");
-        if let Some(pid) = cx.current_package() {
-            let package = cx.packages(pid);
-            if !cx.no_manifest_path {
-                line.arg("--manifest-path");
-                line.arg(
-                    package
-                        .manifest_path
-                        .strip_prefix(&cx.current_dir)
-                        .unwrap_or(&package.manifest_path),
-                );
-            }
-        }
-        line.run_with_output()?;
-        *generate_lockfile = false;
-        *regenerate_lockfile_on_51_or_up = false;
+        line.apply_context(cx);
+        return exec_on_packages(cx, packages, line, targets, progress, keep_going, cargo_version);
     }
-    if !cx.locked && cargo_version < 51 {
-        *regenerate_lockfile_on_51_or_up = true;
+    install_toolchain(cx, &toolchain, targets)?;
+    if *validate_flags {
+        flags::validate(cx, &cmd!("rustup", "run", &toolchain, "cargo"))?;
+        *validate_flags = false;
+    }
+    let stripped =
+        if (cx.strip_unsupported_profile_keys || cx.compat_strip_lints) && !cx.print_command_list {
+            Some(manifest::strip_for_toolchain(cx, &toolchain, cargo_version)?)
+        } else {
+            None
+        };
+    // Take the snapshot before generate-lockfile, because the lockfile
+    // generated by the old cargo is also a drift.
+    let snapshot = if cx.verify_lockfile_per_version && !cx.print_command_list {
+        Some(lockfile::Snapshot::new(cx.workspace_root())?)
+    } else {
+        None
+    };
+    if let Some((version, reason)) = lockfile.next(current) {
+        if version == current {
+            generate_lockfile(cx, &toolchain, reason)?;
+        } else {
+            generate_lockfile_with(cx, version, reason)?;
+        }
     }
 
     if cx.clean_per_version {
@@ -430,7 +1258,92 @@ fn versioned_cargo_exec_on_packages(
     line.leading_arg(&toolchain);
     line.leading_arg("cargo");
     line.apply_context(cx);
-    exec_on_packages(cx, packages, line, progress, keep_going, cargo_version)
+    exec_on_packages(cx, packages, line, targets, progress, keep_going, cargo_version)?;
+    if let Some(stripped) = stripped {
+        stripped.restore()?;
+    }
+
+    if let Some(snapshot) = snapshot {
+        let changes = snapshot.changes()?;
+        if !changes.is_empty() {
+            info!("Cargo.lock changed during runs on {toolchain}: {}", changes.join(", "));
+        }
+        keep_going.lockfile_drift.push((toolchain, changes));
+    }
+    Ok(())
+}
+
+/// Installs the toolchain of --version-range with the targets, or prints the
+/// command to install it with --print-command-list.
+fn install_toolchain(cx: &Context, toolchain: &str, targets: &[String]) -> Result<()> {
+    let requirement = Requirement::new(cx, Some(toolchain.to_owned()), targets);
+    if cx.print_command_list {
+        // --print-command-list must not have side effects, so show the
+        // command to install the toolchain instead of running it.
+        if cx.emit_execution_plan.is_none() {
+            print!("# ");
+        }
+        print_command(cx, rustup::install_toolchain_command(toolchain, &requirement.targets), None);
+    } else if !cx.no_install {
+        // With --no-install, the requirements were checked before the runs.
+        let print_output = true;
+        rustup::install_toolchain(
+            toolchain,
+            &requirement.targets,
+            print_output,
+            cx.log_group,
+            !cx.no_rustup_lock,
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs `cargo generate-lockfile` with the toolchain of --version-range.
+fn generate_lockfile(cx: &Context, toolchain: &str, reason: &str) -> Result<()> {
+    info!("generating Cargo.lock with cargo {toolchain} because {reason}");
+    // Do not use `cargo +<toolchain>` due to a rustup bug: https://github.com/rust-lang/rustup/issues/3036
+    let mut line = cmd!("rustup");
+    line.leading_arg("run");
+    line.leading_arg(toolchain);
+    line.leading_arg("cargo");
+    line.arg("generate-lockfile");
+    if let Some(pid) = cx.current_package() {
+        let package = cx.packages(pid);
+        if !cx.no_manifest_path {
+            line.arg("--manifest-path");
+            line.arg(
+                package
+                    .manifest_path
+                    .strip_prefix(&cx.current_dir)
+                    .unwrap_or(&package.manifest_path),
+            );
+        }
+    }
+    if cx.print_command_list {
+        print_command(cx, line, None);
+    } else {
+        line.run_with_output()?;
+    }
+    Ok(())
+}
+
+/// Runs `cargo generate-lockfile` with a toolchain other than the one of the
+/// next runs, which is installed and for which the manifests are stripped as
+/// needed.
+fn generate_lockfile_with(cx: &Context, version: Version, reason: &str) -> Result<()> {
+    let toolchain = version.to_string();
+    install_toolchain(cx, &toolchain, &[])?;
+    let stripped =
+        if (cx.strip_unsupported_profile_keys || cx.compat_strip_lints) && !cx.print_command_list {
+            Some(manifest::strip_for_toolchain(cx, &toolchain, version.minor)?)
+        } else {
+            None
+        };
+    generate_lockfile(cx, &toolchain, reason)?;
+    if let Some(stripped) = stripped {
+        stripped.restore()?;
+    }
+    Ok(())
 }
 
 fn default_cargo_exec_on_packages(
@@ -441,13 +1354,14 @@ fn default_cargo_exec_on_packages(
 ) -> Result<()> {
     let mut line = cx.cargo();
     line.apply_context(cx);
-    exec_on_packages(cx, packages, line, progress, keep_going, cx.cargo_version)
+    exec_on_packages(cx, packages, line, &cx.target, progress, keep_going, cx.cargo_version)
 }
 
 fn exec_on_packages(
     cx: &Context,
     packages: &[PackageRuns<'_>],
     mut line: ProcessBuilder<'_>,
+    targets: &[String],
     progress: &mut Progress,
     keep_going: &mut KeepGoing,
     cargo_version: u32,
@@ -455,22 +1369,31 @@ fn exec_on_packages(
     if cx.locked {
         line.arg("--locked");
     }
-    if cx.target.is_empty() || cargo_version >= 64 {
+    if cx.target.is_empty() && cx.effective_targets.len() > 1 && cargo_version < 64 {
+        // Unlike --target, we cannot split targets from cargo config into
+        // separate runs, because they are applied by cargo itself.
+        warn!(
+            "multi-target build.target in cargo config ({}) is not supported on cargo 1.{cargo_version}; \
+             consider using --target instead",
+            cx.effective_targets.join(", ")
+        );
+    }
+    if targets.is_empty() || cargo_version >= 64 {
         // TODO: We should test that cargo's multi-target build does not break the resolver behavior required for a correct check.
-        for target in &cx.target {
+        for target in targets {
             line.arg("--target");
             line.arg(target);
         }
-        packages
-            .iter()
-            .try_for_each(|pkg| exec_on_package(cx, pkg.id, &pkg.kind, &line, progress, keep_going))
+        packages.iter().try_for_each(|pkg| {
+            exec_on_package(cx, pkg, &line, progress, keep_going, cargo_version)
+        })
     } else {
-        cx.target.iter().try_for_each(|target| {
+        targets.iter().try_for_each(|target| {
             let mut line = line.clone();
             line.arg("--target");
             line.arg(target);
             packages.iter().try_for_each(|pkg| {
-                exec_on_package(cx, pkg.id, &pkg.kind, &line, progress, keep_going)
+                exec_on_package(cx, pkg, &line, progress, keep_going, cargo_version)
             })
         })
     }
@@ -478,24 +1401,72 @@ fn exec_on_packages(
 
 fn exec_on_package(
     cx: &Context,
-    id: &PackageId,
-    kind: &Kind<'_>,
+    pkg: &PackageRuns<'_>,
     line: &ProcessBuilder<'_>,
     progress: &mut Progress,
     keep_going: &mut KeepGoing,
+    cargo_version: u32,
 ) -> Result<()> {
+    let id = pkg.id;
     let package = cx.packages(id);
 
     let mut line = line.clone();
     line.append_features_from_args(cx, id);
 
-    if !cx.no_manifest_path {
+    keep_going.below_msrv = false;
+    if cx.check_msrv_consistency {
+        let msrv = cx.msrv(id)?;
+        // Compare with the toolchain instead of cargo_version to respect the
+        // patch version of the rust-version.
+        let toolchain = keep_going.toolchain.as_deref().map(str::parse::<Version>).transpose()?;
+        if let (Some(msrv), Some(toolchain)) = (msrv, toolchain) {
+            keep_going.below_msrv = toolchain < msrv;
+        }
+        if keep_going.below_msrv {
+            // cargo 1.56+ refuses to build packages on versions older than the rust-version.
+            if cargo_version >= 56 {
+                line.arg("--ignore-rust-version");
+            }
+        }
+    }
+
+    if cx.chdir_instead_of_manifest_path {
+        line.current_dir(package.manifest_path.parent().unwrap());
+    } else if !cx.no_manifest_path {
         line.arg("--manifest-path");
         line.arg(
             package.manifest_path.strip_prefix(&cx.current_dir).unwrap_or(&package.manifest_path),
         );
     }
+    if cx.check_additivity {
+        line.arg(additivity::MESSAGE_FORMAT);
+    }
+    if cx.rustdoc_all_features_check {
+        doc_features::apply(cx, &mut line);
+    }
+    if cx.deny_warnings_for.contains(&package.name) {
+        line.append_rustflags(&["-D", "warnings"]);
+        line.append_rustdocflags(&["-D", "warnings"]);
+    }
 
+    // Run all feature combinations with the same cfgs in a row, because
+    // changing RUSTFLAGS invalidates the build cache.
+    for cfgs in &cx.cfg_sets {
+        let mut line = line.clone();
+        line.append_cfgs(cfgs);
+        exec_on_package_with_cfgs(cx, pkg, line, progress, keep_going)?;
+    }
+    Ok(())
+}
+
+fn exec_on_package_with_cfgs(
+    cx: &Context,
+    pkg: &PackageRuns<'_>,
+    mut line: ProcessBuilder<'_>,
+    progress: &mut Progress,
+    keep_going: &mut KeepGoing,
+) -> Result<()> {
+    let (id, kind) = (pkg.id, &pkg.kind);
     match kind {
         Kind::Normal => {
             // only run with default features
@@ -508,22 +1479,7 @@ fn exec_on_package(
     // https://github.com/taiki-e/cargo-hack/issues/42
     // https://github.com/rust-lang/cargo/pull/8799
     // > --all-features will now enable features for inactive optional dependencies.
-    let pkg_features = cx.pkg_features(id);
-    let exclude_all_features = cx.exclude_all_features
-        || match kind {
-            Kind::Each { .. } => {
-                pkg_features.optional_deps().is_empty() && pkg_features.normal().len() <= 1
-            }
-            Kind::Powerset { .. } => {
-                pkg_features.optional_deps().is_empty()
-                    || match &cx.optional_deps {
-                        // Skip when all optional deps are already included in powerset
-                        Some(opt_deps) => opt_deps.is_empty() && cx.depth.is_none(),
-                        None => false,
-                    }
-            }
-            Kind::Normal => unreachable!(),
-        };
+    let exclude_all_features = pkg.skip_all_features || skips_all_features(cx, id, kind);
     if !exclude_all_features {
         let mut line = line.clone();
         // run with all features
@@ -541,8 +1497,10 @@ fn exec_on_package(
     // Otherwise, "run with default features" is basically the same as
     // "run with no default features".
 
-    if !cx.exclude_no_default_features {
+    if !cx.exclude_no_default_features && !pkg.skip_no_default_features {
         // run with no default features if the package has other features
+        let mut line = line.clone();
+        examples_once::apply(cx, id, &[], &mut line);
         exec_cargo(cx, id, &line, progress, keep_going)?;
     }
 
@@ -553,19 +1511,19 @@ fn exec_on_package(
             }
         }
         Kind::Powerset { features } => {
-            let features =
-                if exclude_all_features && features.len() > 1 && cx.depth.unwrap_or(usize::MAX) > 1
-                {
-                    // If --all-features case is skipped, run with the biggest feature combination early (first or second): https://github.com/taiki-e/cargo-hack/issues/246
-                    // TODO: The last combination is usually the biggest feature combination, but
-                    //       in some cases this is not the case due to deduplication of the powerset.
-                    //       See todo comment in powerset_deduplication test for example.
-                    let last = features.last().unwrap();
-                    exec_cargo_with_features(cx, id, &line, progress, keep_going, last)?;
-                    &features[..features.len() - 1]
-                } else {
-                    features
-                };
+            // --shuffle moves the biggest combination unless --shuffle-keep-warmup is used.
+            let warmup = exclude_all_features && (cx.shuffle.is_none() || cx.shuffle_keep_warmup);
+            let features = if warmup && features.len() > 1 && cx.depth.unwrap_or(usize::MAX) > 1 {
+                // If --all-features case is skipped, run with the biggest feature combination early (first or second): https://github.com/taiki-e/cargo-hack/issues/246
+                // TODO: The last combination is usually the biggest feature combination, but
+                //       in some cases this is not the case due to deduplication of the powerset.
+                //       See todo comment in powerset_deduplication test for example.
+                let last = features.last().unwrap();
+                exec_cargo_with_features(cx, id, &line, progress, keep_going, last)?;
+                &features[..features.len() - 1]
+            } else {
+                features
+            };
             for f in features {
                 exec_cargo_with_features(cx, id, &line, progress, keep_going, f)?;
             }
@@ -576,6 +1534,26 @@ fn exec_on_package(
     Ok(())
 }
 
+/// Returns `true` if the run with --all-features is skipped for the package.
+fn skips_all_features(cx: &Context, id: &PackageId, kind: &Kind<'_>) -> bool {
+    let pkg_features = cx.pkg_features(id);
+    cx.excludes_all_features(id)
+        || match kind {
+            Kind::Each { .. } => {
+                pkg_features.optional_deps().is_empty() && pkg_features.normal().len() <= 1
+            }
+            Kind::Powerset { .. } => {
+                pkg_features.optional_deps().is_empty()
+                    || match &cx.optional_deps {
+                        // Skip when all optional deps are already included in powerset
+                        Some(opt_deps) => opt_deps.is_empty() && cx.depth.is_none(),
+                        None => false,
+                    }
+            }
+            Kind::Normal => unreachable!(),
+        }
+}
+
 fn exec_cargo_with_features(
     cx: &Context,
     id: &PackageId,
@@ -584,15 +1562,155 @@ fn exec_cargo_with_features(
     keep_going: &mut KeepGoing,
     features: &[&Feature],
 ) -> Result<()> {
-    let mut line = line.clone();
-    line.append_features(features);
+    let mut line = with_features(cx, id, line, features);
+    examples_once::apply(cx, id, features, &mut line);
     exec_cargo(cx, id, &line, progress, keep_going)
 }
 
+fn with_features<'a>(
+    cx: &Context,
+    id: &PackageId,
+    line: &ProcessBuilder<'a>,
+    features: &[&Feature],
+) -> ProcessBuilder<'a> {
+    let mut line = line.clone();
+    let pkg_features = cx.pkg_features(id);
+    for &f in features {
+        let origin = pkg_features.origin(f);
+        for name in f.as_group() {
+            line.append_feature(name, origin.clone());
+        }
+    }
+    line
+}
+
+/// Runs --bisect-features on each package.
+fn bisect_on_packages(cx: &Context, packages: &[PackageRuns<'_>]) -> Result<()> {
+    // The number of runs is not known in advance.
+    let mut progress = Progress::default();
+    let mut base = cx.cargo();
+    base.apply_context(cx);
+    if cx.locked {
+        base.arg("--locked");
+    }
+    for target in &cx.target {
+        base.arg("--target");
+        base.arg(target);
+    }
+    for pkg in packages {
+        let package = cx.packages(pkg.id);
+        let Kind::Each { features: candidates } = &pkg.kind else {
+            info!("skipped bisecting package `{}` that has no features to bisect", package.name);
+            continue;
+        };
+        let start: Vec<&Feature> = if cx.bisect_start.is_empty() {
+            candidates.clone()
+        } else {
+            let mut start: Vec<&Feature> = vec![];
+            for name in &cx.bisect_start {
+                let Some(&f) = candidates.iter().find(|f| f.matches(name)) else {
+                    bail!(
+                        "feature `{name}` specified by --bisect-start is not in the features to \
+                         bisect of package `{}`",
+                        package.name
+                    );
+                };
+                if !start.iter().any(|&g| ptr::eq(f, g)) {
+                    start.push(f);
+                }
+            }
+            start
+        };
+
+        let mut line = base.clone();
+        line.append_features_from_args(cx, pkg.id);
+        if cx.chdir_instead_of_manifest_path {
+            line.current_dir(package.manifest_path.parent().unwrap());
+        } else if !cx.no_manifest_path {
+            line.arg("--manifest-path");
+            line.arg(
+                package
+                    .manifest_path
+                    .strip_prefix(&cx.current_dir)
+                    .unwrap_or(&package.manifest_path),
+            );
+        }
+        if !cx.no_default_features {
+            line.arg("--no-default-features");
+        }
+        let mut fails = |features: &[&Feature]| {
+            let mut line = with_features(cx, pkg.id, &line, features);
+            feature_args::apply(cx, pkg.id, &mut line);
+            placeholder::apply(cx, pkg.id, None, &mut line);
+            let run_id = run_id::compute(cx, pkg.id, None, &line);
+            let res = exec_cargo_inner(
+                cx,
+                pkg.id,
+                &line,
+                &run_id,
+                &mut progress,
+                &mut vec![],
+                &mut VecDeque::new(),
+            );
+            res.is_err()
+        };
+        let describe = |features: &[&Feature]| {
+            features.iter().flat_map(|f| f.as_group()).cloned().collect::<Vec<_>>().join(",")
+        };
+
+        if !fails(&start) {
+            eprintln!();
+            info!(
+                "package `{}` does not fail with `{}`; nothing to bisect",
+                package.name,
+                describe(&start)
+            );
+            continue;
+        }
+        if fails(&[]) {
+            eprintln!();
+            info!("package `{}` fails even without any of the features to bisect", package.name);
+            continue;
+        }
+        let res = bisect::ddmin(start.len(), cx.bisect_max_runs.map(|max| max - 2), |set| {
+            Ok(fails(&set.iter().map(|&i| start[i]).collect::<Vec<_>>()))
+        })?;
+        let minimal: Vec<_> = res.set.iter().map(|&i| start[i]).collect();
+        eprintln!();
+        info!(
+            "minimal failing feature set of package `{}`: `{}` (found in {} runs)",
+            package.name,
+            describe(&minimal),
+            res.runs + 2
+        );
+        if res.limited {
+            info!(
+                "bisecting package `{}` stopped after reaching the limit of --bisect-max-runs; \
+                 the feature set may not be minimal",
+                package.name
+            );
+        }
+    }
+    Ok(())
+}
+
 #[derive(Default)]
 struct KeepGoing {
     count: u64,
     failed_commands: BTreeMap<String, Vec<String>>,
+    /// Results of all runs, used for reports such as --summary-markdown.
+    runs: Vec<summary::Run>,
+    /// Packages on which no command was run, used for --junit.
+    skipped_packages: Vec<summary::SkippedPackage>,
+    /// The toolchain currently in use. `None` if --version-range is not used.
+    toolchain: Option<String>,
+    /// Changes of the lockfile per toolchain, used for --verify-lockfile-per-version.
+    lockfile_drift: Vec<(String, Vec<String>)>,
+    /// Whether the current toolchain is older than the rust-version of the
+    /// current package, used for --check-msrv-consistency.
+    below_msrv: bool,
+    /// The results of the runs in the baseline, used for --baseline.
+    baseline: Option<baseline::Baseline>,
 }
 
 impl fmt::Display for KeepGoing {
@@ -659,39 +1777,251 @@ impl FromStr for LogGroup {
     }
 }
 
-pub(crate) struct Partition {
-    index: usize,
-    count: usize,
+pub(crate) enum Partition {
+    /// `M/N`: the M-th of N partitions of (almost) equal size.
+    Index { index: usize, count: usize },
+    /// `NAME=START%..END%`: the runs in the half-open percentage interval of
+    /// the planned runs, for partitions of uneven sizes.
+    Named { name: String, start: usize, end: usize },
+}
+
+impl Partition {
+    /// Returns the range of the indices of the runs in the partition.
+    fn range(&self, total: usize) -> Range<usize> {
+        match *self {
+            Self::Index { index, count } => {
+                // div_ceil (stabilized at 1.73) can't be used due to MSRV = 1.70...
+                let mut chunk_count = total / count;
+                if total % count != 0 {
+                    chunk_count += 1;
+                }
+                let start = (index * chunk_count).min(total);
+                start..(start + chunk_count).min(total)
+            }
+            // Rounding down both ends makes adjacent intervals (e.g.,
+            // `0%..50%` and `50%..100%`) cover the runs without overlap.
+            Self::Named { start, end, .. } => total * start / 100..total * end / 100,
+        }
+    }
+
+    /// Returns the label of the partition, used for --badge-json.
+    fn label(&self) -> String {
+        match self {
+            Self::Index { index, count } => format!("{}/{count}", index + 1),
+            Self::Named { name, .. } => name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index { .. } => f.write_str(&self.label()),
+            Self::Named { name, start, end } => write!(f, "{name}={start}%..{end}%"),
+        }
+    }
 }
 
 impl FromStr for Partition {
     type Err = Error;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some((name, interval)) = s.split_once('=') {
+            let percent = |s: &str| s.strip_suffix('%').and_then(|s| s.parse::<usize>().ok());
+            if let Some((Some(start), Some(end))) =
+                interval.split_once("..").map(|(start, end)| (percent(start), percent(end)))
+            {
+                // The name is included in the badge of --badge-json as is.
+                if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                    && start < end
+                    && end <= 100
+                {
+                    return Ok(Self::Named { name: name.to_owned(), start, end });
+                }
+            }
+            bail!(
+                "bad or out-of-range partition: {s} (expected M/N or NAME=START%..END%, where \
+                 NAME consists of alphanumerics, `-`, `_`, and `.`, and 0 <= START < END <= 100)"
+            );
+        }
         match s.split('/').map(str::parse::<usize>).collect::<Vec<_>>()[..] {
-            [Ok(m), Ok(n)] if 0 < m && m <= n => Ok(Self { index: m - 1, count: n }),
+            [Ok(m), Ok(n)] if 0 < m && m <= n => Ok(Self::Index { index: m - 1, count: n }),
             _ => bail!("bad or out-of-range partition: {s}"),
         }
     }
 }
 
-fn exec_cargo(
+/// Runs the subcommand, and then the commands chained by --and-then, with the
+/// feature flags of `line`.
+fn exec_cargo<'a>(
+    cx: &'a Context,
+    id: &PackageId,
+    line: &ProcessBuilder<'a>,
+    progress: &mut Progress,
+    keep_going: &mut KeepGoing,
+) -> Result<()> {
+    let failed = keep_going.count;
+    exec_cargo_command(cx, id, line, progress, keep_going)?;
+    for (i, command) in cx.and_then.iter().enumerate() {
+        if keep_going.count != failed {
+            // A command failed with --keep-going; the remaining commands of
+            // this feature combination are not run.
+            let remaining = cx.and_then.len() - i;
+            progress.count += remaining;
+            info!(
+                "skipped {remaining} {} chained by --and-then on {}, because a previous command \
+                 failed",
+                if remaining == 1 { "command" } else { "commands" },
+                cx.packages(id).name
+            );
+            break;
+        }
+        let mut line = line.clone();
+        line.apply_chained(command);
+        exec_cargo_command(cx, id, &line, progress, keep_going)?;
+    }
+    Ok(())
+}
+
+fn exec_cargo_command(
     cx: &Context,
     id: &PackageId,
     line: &ProcessBuilder<'_>,
     progress: &mut Progress,
     keep_going: &mut KeepGoing,
 ) -> Result<()> {
-    let res = exec_cargo_inner(cx, id, line, progress);
+    let mut line = line.clone();
+    feature_args::apply(cx, id, &mut line);
+    placeholder::apply(cx, id, keep_going.toolchain.as_deref(), &mut line);
+    let line = &line;
+    let run_id = run_id::compute(cx, id, keep_going.toolchain.as_deref(), line);
+    let mut stdout = vec![];
+    let mut stderr = VecDeque::new();
+    progress.sccache.zero_stats();
+    let start = Instant::now();
+    let res = if progress.over_budget(cx) {
+        progress.count += 1;
+        if progress.budget_cutoff.is_none() {
+            info!(
+                "--budget exceeded; not running the remaining {} commands",
+                progress.total + 1 - progress.count
+            );
+            progress.budget_cutoff = Some(budget::Cutoff {
+                index: progress.count,
+                total: progress.total,
+                package: cx.packages(id).name.clone(),
+                command: line.to_string(),
+                toolchain: keep_going.toolchain.clone(),
+            });
+        }
+        Ok(Some(summary::Status::NotRun))
+    } else {
+        exec_cargo_inner(cx, id, line, &run_id, progress, &mut stdout, &mut stderr)
+    };
+    let duration = start.elapsed();
+    let status = match res {
+        // --print-command-list
+        Ok(None) => return Ok(()),
+        Ok(Some(status)) => status,
+        Err(_) => summary::Status::Failed,
+    };
+    if let Some(dir) = &cx.capture_stdout_dir {
+        if status.ran() {
+            let name = &cx.packages(id).name;
+            capture::write(dir, name, keep_going.toolchain.as_deref(), line, &stdout)?;
+        }
+    }
+    let sccache = if status.ran() { progress.sccache.show_stats() } else { None };
+    let duration = if status.ran() { duration } else { Duration::ZERO };
+    progress.elapsed += duration;
+    let cold_start = status.ran()
+        && progress.warmup.cold_start(&cx.packages(id).name, keep_going.toolchain.as_deref());
+    if let Some(factor) = cx.slow_threshold_factor {
+        if status.ran() {
+            let name = &cx.packages(id).name;
+            if let Some((median, ratio)) =
+                progress.durations.record(name, duration, cold_start, factor)
+            {
+                warn!(
+                    "{line} on {name} took {:.1}s, {ratio:.1}x the median of previous runs on \
+                     this package ({:.1}s)",
+                    duration.as_secs_f64(),
+                    median.as_secs_f64()
+                );
+            }
+        }
+    }
+    keep_going.runs.push(summary::Run {
+        id: run_id,
+        package: cx.packages(id).name.clone(),
+        toolchain: keep_going.toolchain.clone(),
+        command: line.to_string(),
+        cfgs: line.cfgs().to_vec(),
+        features: line.feature_flags(),
+        requested_features: additivity::requested_features(cx, id, line),
+        enabled_features: if cx.check_additivity {
+            additivity::enabled_features(&stdout, id)
+        } else {
+            None
+        },
+        doc_items: if cx.rustdoc_all_features_check && status == summary::Status::Passed {
+            doc_features::count_items(cx, id, line)
+        } else {
+            None
+        },
+        status,
+        duration,
+        cold_start,
+        error: res.as_ref().err().map(|e| format!("{e:#}")),
+        stderr,
+        sccache,
+    });
+    if cx.porcelain {
+        let run = keep_going.runs.last().unwrap();
+        let skip_reason = progress.skip_reason;
+        porcelain::finish(run, res.as_ref().err(), skip_reason, progress.count, progress.total);
+    }
+    let res = res.map(drop);
+    if keep_going.below_msrv {
+        if let Err(e) = res {
+            // Failures on versions older than the rust-version are expected.
+            info!(
+                "{line} failed on {}, which is older than the rust-version of {}: {e:#}",
+                keep_going.toolchain.as_deref().unwrap_or_default(),
+                cx.packages(id).name
+            );
+        }
+        return Ok(());
+    }
     if cx.keep_going {
         if let Err(e) = res {
+            let run_id = &keep_going.runs.last().unwrap().id;
+            if cx.fail_on_new_failures_only
+                && keep_going.baseline.as_ref().is_some_and(|baseline| baseline.failed(run_id))
+            {
+                info!("{e:#}");
+                info!(
+                    "{line} on {} also failed in the baseline; ignoring it \
+                     (--fail-on-new-failures-only)",
+                    cx.packages(id).name
+                );
+                return Ok(());
+            }
             error!("{e:#}");
             keep_going.count = keep_going.count.saturating_add(1);
             let name = cx.packages(id).name.clone();
             if !keep_going.failed_commands.contains_key(&name) {
                 keep_going.failed_commands.insert(name.clone(), vec![]);
             }
-            keep_going.failed_commands.get_mut(&name).unwrap().push(format!("{line:#}"));
+            let mut cmd = format!("{line:#}");
+            if line.features().next().is_some() {
+                write!(cmd, " (features: {})", line.feature_origins()).unwrap();
+            }
+            write!(cmd, " [{}]", keep_going.runs.last().unwrap().id).unwrap();
+            keep_going.failed_commands.get_mut(&name).unwrap().push(cmd);
         }
         Ok(())
     } else {
@@ -703,17 +2033,44 @@ fn exec_cargo_inner(
     cx: &Context,
     id: &PackageId,
     line: &ProcessBuilder<'_>,
+    run_id: &str,
     progress: &mut Progress,
-) -> Result<()> {
-    if progress.count != 0 && !cx.print_command_list && cx.log_group == LogGroup::None {
+    stdout: &mut Vec<u8>,
+    stderr: &mut VecDeque<String>,
+) -> Result<Option<summary::Status>> {
+    if progress.count != 0
+        && !cx.print_command_list
+        && cx.log_group == LogGroup::None
+        && !cx.quiet_success
+        && !cx.compact_output
+    {
         eprintln!();
     }
 
     if let Some(partition) = &cx.partition {
         if !progress.in_partition(partition) {
-            let _guard = log_and_update_progress(cx, id, line, progress, "skipping");
-            return Ok(());
+            let _guard = log_and_update_progress(cx, id, line, run_id, progress, "skipping");
+            trace!(
+                "plan",
+                "run {run_id} on package `{}`: skipped by --partition",
+                cx.packages(id).name
+            );
+            progress.skip_reason = "partition";
+            return Ok(Some(summary::Status::Skipped));
+        }
+    }
+    if !cx.only_run.is_empty() {
+        if !cx.only_run.contains(run_id) {
+            let _guard = log_and_update_progress(cx, id, line, run_id, progress, "skipping");
+            trace!(
+                "plan",
+                "run {run_id} on package `{}`: skipped by --only-run",
+                cx.packages(id).name
+            );
+            progress.skip_reason = "only-run";
+            return Ok(Some(summary::Status::Skipped));
         }
+        progress.matched_runs.insert(run_id.to_owned());
     }
 
     if cx.clean_per_run {
@@ -721,13 +2078,129 @@ fn exec_cargo_inner(
     }
 
     if cx.print_command_list {
-        print_command(line.clone());
-        return Ok(());
+        if cx.emit_execution_plan.is_some() {
+            cx.execution_plan.record(cx, line, Some((&cx.packages(id).name, run_id)));
+        } else {
+            print_command(cx, line.clone(), Some(run_id));
+        }
+        return Ok(None);
     }
 
-    let _guard = log_and_update_progress(cx, id, line, progress, "running");
+    let _guard = log_and_update_progress(cx, id, line, run_id, progress, "running");
+    trace!(
+        "command",
+        "run {run_id} on package `{}` with {} ({}/{})",
+        cx.packages(id).name,
+        line.feature_flags(),
+        progress.count,
+        progress.total
+    );
+    if cx.porcelain {
+        let name = &cx.packages(id).name;
+        let features = line.feature_flags();
+        porcelain::start(run_id, name, &features, progress.count, progress.total);
+    }
 
-    line.run()
+    let tag = cx.tag_output.then(|| format!("{}:{run_id}", cx.packages(id).name));
+    let mut res = run_command(cx, line, stdout, stderr, tag.as_deref());
+    if res.is_err() && cx.auto_locked && line.has_arg("--locked") && locked::needs_update(stderr) {
+        warn!("Cargo.lock needs to be updated; running {line} again without --locked");
+        let mut line = line.clone();
+        line.remove_arg("--locked");
+        stdout.clear();
+        stderr.clear();
+        res = run_command(cx, &line, stdout, stderr, tag.as_deref());
+    }
+    if res.is_err() && cx.no_dev_deps && line.has_arg("--doc") {
+        info!(
+            "if this failure is caused by doctests that use dev-dependencies \
+             (e.g., `use of undeclared crate`), they cannot be run with --no-dev-deps"
+        );
+    }
+    res?;
+    Ok(Some(summary::Status::Passed))
+}
+
+/// Runs the command, capturing or redirecting its output as needed.
+///
+/// If `tag` is `Some`, each line of the output is prefixed with it (--tag-output).
+fn run_command(
+    cx: &Context,
+    line: &ProcessBuilder<'_>,
+    stdout: &mut Vec<u8>,
+    stderr: &mut VecDeque<String>,
+    tag: Option<&str>,
+) -> Result<()> {
+    // The stderr is also captured to detect the stale lockfile with --locked=auto.
+    let capture_stderr = cx.summary_markdown.is_some() || cx.junit.is_some() || cx.auto_locked;
+    let capture_stdout = cx.check_additivity || cx.capture_stdout_dir.is_some();
+    // --porcelain uses stdout for the records, so stdout of cargo is printed to
+    // stderr. (With --quiet-success, it is already written to the log.)
+    let redirect_stdout = cx.porcelain && !cx.quiet_success;
+    // With --compact-output, the result is appended to the line printed by
+    // log_and_update_progress if the command printed nothing.
+    let compact = cx.compact_output && cx.log_group == LogGroup::None;
+    if cx.quiet_success {
+        let output = output::CapturedOutput::new()?;
+        let start = Instant::now();
+        let res = line.run_with_log(capture_stdout.then_some(&mut *stdout), output.file());
+        if cx.capture_stdout_dir.is_some() {
+            // Keep the stdout in the output replayed on failure.
+            output.file().write_all(stdout)?;
+        }
+        let result = if res.is_ok() { "ok" } else { "failed" };
+        let elapsed = start.elapsed().as_secs_f64();
+        if cx.log_group == LogGroup::None {
+            // Complete the line printed by log_and_update_progress.
+            eprintln!(" ... {result} ({elapsed:.1}s)");
+            if term::verbose() && line.features().next().is_some() {
+                info!("features: {}", line.feature_origins());
+            }
+        } else {
+            info!("{result} ({elapsed:.1}s)");
+        }
+        if res.is_err() {
+            output.replay(cx.show_output_lines, tag)?;
+            if capture_stderr {
+                *stderr = output.tail(summary::STDERR_TAIL_LINES)?.0;
+            }
+        }
+        res
+    } else if capture_stderr || capture_stdout || redirect_stdout || compact || tag.is_some() {
+        // Capture the stderr to include it in the report if the command fails,
+        // and the stdout to get the features that cargo enabled, to write it
+        // to --capture-stdout-dir, or to print it to stderr.
+        let open_line = AtomicBool::new(compact);
+        let start = Instant::now();
+        let res = line.run_with_capture(
+            (capture_stdout || redirect_stdout).then_some(&mut *stdout),
+            capture_stderr.then_some((summary::STDERR_TAIL_LINES, stderr)),
+            compact.then_some(&open_line),
+            tag,
+        );
+        if compact {
+            if open_line.load(Ordering::Relaxed) {
+                // Complete the line printed by log_and_update_progress.
+                let result = if res.is_ok() { "ok" } else { "failed" };
+                eprintln!(" ... {result} ({:.1}s)", start.elapsed().as_secs_f64());
+            }
+            if term::verbose() && line.features().next().is_some() {
+                info!("features: {}", line.feature_origins());
+            }
+        }
+        if redirect_stdout {
+            // The JSON messages used by --check-additivity are not printed.
+            if !cx.check_additivity {
+                process::write_tagged(&mut io::stderr(), stdout, tag)?;
+            }
+        } else if cx.capture_stdout_dir.is_some() {
+            // The stdout is written to the file in addition to being printed.
+            process::write_tagged(&mut io::stdout(), stdout, tag)?;
+        }
+        res
+    } else {
+        line.run()
+    }
 }
 
 fn cargo_clean(cx: &Context, id: Option<&PackageId>) -> Result<()> {
@@ -742,7 +2215,7 @@ fn cargo_clean(cx: &Context, id: Option<&PackageId>) -> Result<()> {
     }
 
     if cx.print_command_list {
-        print_command(line);
+        print_command(cx, line, None);
         return Ok(());
     }
 
@@ -754,21 +2227,54 @@ fn cargo_clean(cx: &Context, id: Option<&PackageId>) -> Result<()> {
     line.run()
 }
 
-fn print_command(mut line: ProcessBuilder<'_>) {
+/// Runs `cargo llvm-cov <subcommand> <args>` for the whole workspace.
+fn cargo_llvm_cov(cx: &Context, subcommand: &str, args: &[String]) -> Result<()> {
+    let mut line = cx.cargo();
+    line.arg("llvm-cov");
+    line.arg(subcommand);
+    line.args(args);
+    if let Some(manifest_path) = &cx.manifest_path {
+        line.arg("--manifest-path");
+        line.arg(manifest_path);
+    }
+
+    if cx.print_command_list {
+        print_command(cx, line, None);
+        return Ok(());
+    }
+
+    // running `cargo llvm-cov <subcommand>`
+    info!("running {line}");
+    line.run()
+}
+
+/// Prints the command for --print-command-list, followed by the id of the run
+/// as a shell comment.
+fn print_command(cx: &Context, mut line: ProcessBuilder<'_>, run_id: Option<&str>) {
+    if cx.emit_execution_plan.is_some() {
+        cx.execution_plan.record(cx, &line, None);
+        return;
+    }
     let _guard = term::verbose::scoped(true);
     line.strip_program_path = true;
     let l = line.to_string();
-    println!("{}", &l[1..l.len() - 1]);
+    // Strip backticks, but keep the working directory suffix if present.
+    let end = l.rfind('`').unwrap();
+    match run_id {
+        Some(run_id) => println!("{}{}  # {run_id}", &l[1..end], &l[end + 1..]),
+        None => println!("{}{}", &l[1..end], &l[end + 1..]),
+    }
 }
 
 fn log_and_update_progress(
     cx: &Context,
     id: &PackageId,
     line: &ProcessBuilder<'_>,
+    run_id: &str,
     progress: &mut Progress,
     action: &str,
 ) -> Option<LogGroupGuard> {
-    // running/skipping `<command>` (on <package>) (<count>/<total>)
+    // running/skipping `<command>` (on <package>) (<count>/<total>) [<id>]
     let mut msg = String::new();
     if term::verbose() {
         write!(msg, "{action} {line}").unwrap();
@@ -776,6 +2282,35 @@ fn log_and_update_progress(
         write!(msg, "{action} {line} on {}", cx.packages(id).name).unwrap();
     }
     progress.count += 1;
-    write!(msg, " ({}/{})", progress.count, progress.total).unwrap();
-    cx.log_group.print(&msg)
+    if progress.total == 0 {
+        // The number of runs is not known in advance (--bisect-features).
+        write!(msg, " ({})", progress.count).unwrap();
+    } else if let Some(budget) = cx.budget {
+        let left = budget.saturating_sub(progress.elapsed);
+        write!(
+            msg,
+            " ({}/{}, {} of --budget left)",
+            progress.count,
+            progress.total,
+            budget::Display(left)
+        )
+        .unwrap();
+    } else {
+        write!(msg, " ({}/{})", progress.count, progress.total).unwrap();
+    }
+    write!(msg, " [{run_id}]").unwrap();
+    if (cx.quiet_success || cx.compact_output)
+        && action == "running"
+        && cx.log_group == LogGroup::None
+    {
+        // The result is appended to this line after the command finished.
+        let mut stream = term::print_status("info", None);
+        let _ = io::Write::write_all(&mut stream, msg.as_bytes());
+        return None;
+    }
+    let guard = cx.log_group.print(&msg);
+    if term::verbose() && line.features().next().is_some() {
+        info!("features: {}", line.feature_origins());
+    }
+    guard
 }
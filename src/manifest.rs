@@ -2,15 +2,41 @@
 
 use std::{
     collections::{BTreeMap, BTreeSet},
-    path::Path,
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use anyhow::{bail, format_err, Context as _, Result};
+use anyhow::{bail, format_err, Context as _, Error, Result};
 
-use crate::{context::Context, fs, term};
+use crate::{
+    context::Context, diff::Diff, fs, metadata::PackageId, restore, term, version::Version,
+    workspace::Workspace,
+};
 
 type ParseResult<T> = Result<T, &'static str>;
 
+/// The maximum number of lines of diff displayed per manifest in verbose mode.
+const MAX_DIFF_LINES: usize = 50;
+
+/// The name of the lock file in the `target` directory of the workspace, which
+/// is held while the manifests are modified.
+const LOCK_FILE: &str = ".cargo-hack.lock";
+
+/// Profile keys that cargo rejects before they were stabilized, with the minor
+/// version of cargo that stabilized them, for --strip-unsupported-profile-keys.
+///
+/// Keys that old cargo only warns about as unused are not listed (e.g.,
+/// `package.rust-version`, which is ignored before 1.56).
+const UNSUPPORTED_PROFILE_KEYS: &[(&str, u32)] = &[("strip", 59)];
+/// The minor version of cargo that supports named debuginfo levels (e.g.,
+/// `debug = "line-tables-only"`).
+const NAMED_DEBUG_SINCE: u32 = 71;
+/// The minor version of cargo that supports the `[lints]` table, for
+/// --compat-strip-lints.
+const LINTS_SINCE: u32 = 74;
+
 // Cargo manifest
 // https://doc.rust-lang.org/nightly/cargo/reference/manifest.html
 pub(crate) struct Manifest {
@@ -18,6 +44,7 @@ pub(crate) struct Manifest {
     doc: toml_edit::DocumentMut,
     pub(crate) package: Package,
     pub(crate) features: BTreeMap<String, Vec<String>>,
+    pub(crate) config: Config,
 }
 
 impl Manifest {
@@ -32,7 +59,10 @@ impl Manifest {
         let features = Features::from_table(&doc).map_err(|s| {
             format_err!("failed to parse `{s}` field from manifest `{}`", path.display())
         })?;
-        Ok(Self { raw, doc, package, features })
+        let config = Config::from_table(&doc).map_err(|s| {
+            format_err!("failed to parse `{s}` field from manifest `{}`", path.display())
+        })?;
+        Ok(Self { raw, doc, package, features, config })
     }
 }
 
@@ -74,6 +104,66 @@ impl Package {
     }
 }
 
+/// The configuration of cargo-hack in `[package.metadata.cargo-hack]`.
+#[derive(Default)]
+pub(crate) struct Config {
+    /// `feature-min-versions`: the minimum Rust version required by each
+    /// feature, e.g., `simd = "1.72"`.
+    pub(crate) feature_min_versions: BTreeMap<String, Version>,
+}
+
+impl Config {
+    fn from_table(doc: &toml_edit::DocumentMut) -> Result<Self, String> {
+        const KEY: &str = "package.metadata.cargo-hack";
+        let Some(config) = doc
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("cargo-hack"))
+        else {
+            return Ok(Self::default());
+        };
+        let config = config.as_table_like().ok_or(KEY)?;
+
+        let mut feature_min_versions = BTreeMap::new();
+        if let Some(versions) = config.get("feature-min-versions") {
+            let versions =
+                versions.as_table_like().ok_or_else(|| format!("{KEY}.feature-min-versions"))?;
+            for (name, version) in versions.iter() {
+                let version = version
+                    .as_str()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| format!("{KEY}.feature-min-versions.{name}"))?;
+                feature_min_versions.insert(name.to_owned(), version);
+            }
+        }
+        Ok(Self { feature_min_versions })
+    }
+}
+
+/// Returns `minimum-version` in `[workspace.metadata.cargo-hack]` of the root
+/// manifest: the minimum version of cargo-hack required by the workspace.
+///
+/// This works for virtual manifests as well.
+pub(crate) fn minimum_version(root_manifest: &Path) -> Result<Option<Version>> {
+    const KEY: &str = "workspace.metadata.cargo-hack.minimum-version";
+    let doc: toml_edit::DocumentMut =
+        fs::read_to_string(root_manifest)?.parse().with_context(|| {
+            format!("failed to parse manifest `{}` as toml", root_manifest.display())
+        })?;
+    let Some(version) = doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("metadata"))
+        .and_then(|metadata| metadata.get("cargo-hack"))
+        .and_then(|config| config.get("minimum-version"))
+    else {
+        return Ok(None);
+    };
+    match version.as_str().and_then(|v| v.parse().ok()) {
+        Some(version) => Ok(Some(version)),
+        None => bail!("failed to parse `{KEY}` field from manifest `{}`", root_manifest.display()),
+    }
+}
+
 struct Features {}
 
 impl Features {
@@ -99,95 +189,517 @@ impl Features {
     }
 }
 
-pub(crate) fn with(cx: &Context, f: impl FnOnce() -> Result<()>) -> Result<()> {
-    // TODO: provide option to keep updated Cargo.lock
-    let restore_lockfile = true;
+/// Parses the features declared in the manifest, including the implicit
+/// features of optional dependencies that are not referred to by `dep:`, for
+/// --changed-features-only.
+pub(crate) fn declared_features(raw: &str) -> Result<BTreeMap<String, Vec<String>>> {
+    let doc: toml_edit::DocumentMut = raw.parse()?;
+    let mut features =
+        Features::from_table(&doc).map_err(|s| format_err!("failed to parse `{s}` field"))?;
+    let mut tables = vec![];
+    for key in ["dependencies", "build-dependencies"] {
+        tables.extend(doc.get(key).and_then(toml_edit::Item::as_table_like));
+    }
+    if let Some(targets) = doc.get("target").and_then(toml_edit::Item::as_table_like) {
+        for (_, target) in targets.iter() {
+            for key in ["dependencies", "build-dependencies"] {
+                tables.extend(target.get(key).and_then(toml_edit::Item::as_table_like));
+            }
+        }
+    }
+    let mut optional_deps = BTreeSet::new();
+    for table in tables {
+        for (name, dep) in table.iter() {
+            let optional = dep
+                .as_table_like()
+                .and_then(|dep| dep.get("optional"))
+                .and_then(toml_edit::Item::as_bool);
+            if optional == Some(true) {
+                optional_deps.insert(name.to_owned());
+            }
+        }
+    }
+    for name in optional_deps {
+        let dep = format!("dep:{name}");
+        if !features.values().flatten().any(|f| *f == dep) {
+            features.entry(name).or_insert_with(|| vec![dep]);
+        }
+    }
+    Ok(features)
+}
+
+/// A modification of a manifest requested by --no-dev-deps, --remove-dev-deps,
+/// or --no-private.
+pub(crate) struct Mutation {
+    pub(crate) path: PathBuf,
+    /// The contents of the manifest read at startup.
+    pub(crate) orig: String,
+    pub(crate) new: String,
+    /// What is removed from the manifest, e.g., `dev-dependencies`.
+    pub(crate) removes: Vec<&'static str>,
+}
+
+/// Returns the modifications of the manifests requested by --no-dev-deps,
+/// --remove-dev-deps, and --no-private, without modifying anything.
+///
+/// With --no-dev-deps and --remove-dev-deps, dev-dependencies are only removed
+/// from `selected` packages and the workspace members they depend on, or from
+/// all workspace members if `selected` is `None`.
+pub(crate) fn mutations(cx: &Context, selected: Option<&[&PackageId]>) -> Result<Vec<Mutation>> {
     let no_dev_deps = cx.no_dev_deps | cx.remove_dev_deps;
     let no_private = cx.no_private;
-    if no_dev_deps || no_private {
-        let workspace_root = &cx.metadata.workspace_root;
-        let root_manifest = &workspace_root.join("Cargo.toml");
-        let mut root_id = None;
-        let mut private_crates = BTreeSet::new();
-        for id in &cx.metadata.workspace_members {
-            let package = cx.packages(id);
-            let manifest_path = &*package.manifest_path;
-            let is_root = manifest_path == root_manifest;
+    let mut mutations = vec![];
+    if !no_dev_deps && !no_private {
+        return Ok(mutations);
+    }
+    let workspace_root = &cx.metadata.workspace_root;
+    let root_manifest = &workspace_root.join("Cargo.toml");
+    let used = selected.map(|selected| used_members(cx, selected));
+    let mut root_id = None;
+    let mut private_crates = BTreeSet::new();
+    let mut dev_deps_removed = vec![];
+    for id in &cx.metadata.workspace_members {
+        let package = cx.packages(id);
+        let manifest_path = &*package.manifest_path;
+        let is_root = manifest_path == root_manifest;
+        let is_used = used.as_ref().map_or(true, |used| used.contains(id));
+        if is_root && is_used {
+            root_id = Some(id);
+        }
+        let is_private = cx.is_private(id);
+        if is_private && no_private {
             if is_root {
-                root_id = Some(id);
+                bail!("--no-private is not supported yet with workspace with private root crate");
             }
-            let is_private = cx.is_private(id);
-            if is_private && no_private {
-                if is_root {
-                    bail!(
-                        "--no-private is not supported yet with workspace with private root crate"
-                    );
-                }
-                private_crates.insert(manifest_path);
-            } else if is_root && no_private {
-                // This case is handled in the if block after loop.
-            } else if no_dev_deps {
+            private_crates.insert(manifest_path);
+        } else if is_root && no_private {
+            // This case is handled in the if block after loop.
+        } else if no_dev_deps && is_used {
+            dev_deps_removed.push(id);
+        }
+    }
+
+    for id in dev_deps_removed {
+        let manifest = cx.manifests(id);
+        let mut doc = manifest.doc.clone();
+        remove_dev_deps(&mut doc);
+        mutations.push(Mutation {
+            path: cx.packages(id).manifest_path.clone(),
+            orig: manifest.raw.clone(),
+            new: doc.to_string(),
+            removes: vec!["dev-dependencies"],
+        });
+    }
+    if no_private && (no_dev_deps && root_id.is_some() || !private_crates.is_empty()) {
+        let manifest_path = root_manifest;
+        let (mut doc, orig) = match root_id {
+            Some(id) => {
                 let manifest = cx.manifests(id);
-                let mut doc = manifest.doc.clone();
-                if term::verbose() {
-                    info!("removing dev-dependencies from {}", manifest_path.display());
-                }
-                remove_dev_deps(&mut doc);
-                cx.restore.register(manifest.raw.clone(), manifest_path);
-                fs::write(manifest_path, doc.to_string())?;
+                (manifest.doc.clone(), manifest.raw.clone())
             }
-        }
-        if no_private && (no_dev_deps && root_id.is_some() || !private_crates.is_empty()) {
-            let manifest_path = root_manifest;
-            let (mut doc, orig) = match root_id {
-                Some(id) => {
-                    let manifest = cx.manifests(id);
-                    (manifest.doc.clone(), manifest.raw.clone())
-                }
-                None => {
-                    let orig = fs::read_to_string(manifest_path)?;
-                    (
-                        orig.parse().with_context(|| {
-                            format!(
-                                "failed to parse manifest `{}` as toml",
-                                manifest_path.display()
-                            )
-                        })?,
-                        orig,
-                    )
-                }
-            };
-            if no_dev_deps && root_id.is_some() {
-                if term::verbose() {
-                    info!("removing dev-dependencies from {}", manifest_path.display());
-                }
-                remove_dev_deps(&mut doc);
+            None => {
+                let orig = fs::read_to_string(manifest_path)?;
+                (
+                    orig.parse().with_context(|| {
+                        format!("failed to parse manifest `{}` as toml", manifest_path.display())
+                    })?,
+                    orig,
+                )
             }
-            if !private_crates.is_empty() {
-                if term::verbose() {
-                    info!("removing private crates from {}", manifest_path.display());
+        };
+        let mut removes = vec![];
+        if no_dev_deps && root_id.is_some() {
+            remove_dev_deps(&mut doc);
+            removes.push("dev-dependencies");
+        }
+        if !private_crates.is_empty() {
+            remove_private_crates(&mut doc, workspace_root, private_crates);
+            removes.push("private crates");
+        }
+        mutations.push(Mutation {
+            path: manifest_path.clone(),
+            orig,
+            new: doc.to_string(),
+            removes,
+        });
+    }
+    Ok(mutations)
+}
+
+/// Modifies the manifests as requested by --no-dev-deps, --remove-dev-deps,
+/// and --no-private (see [`mutations`]), runs `f`, and then restores them.
+///
+/// With --emit-execution-plan, the modifications are only recorded to the plan.
+pub(crate) fn with(
+    cx: &Context,
+    selected: Option<&[&PackageId]>,
+    f: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if cx.emit_execution_plan.is_some() {
+        cx.execution_plan.set_mutations(mutations(cx, selected)?);
+        return f();
+    }
+    let restore_lockfile = cx.keep_lockfile_changes != Some(KeepLockfileChanges::Always);
+    if cx.no_dev_deps || cx.remove_dev_deps || cx.no_private {
+        let workspace_root = &cx.metadata.workspace_root;
+        cx.restore.lock(&workspace_root.join("target").join(LOCK_FILE), !cx.no_wait)?;
+        let mutations = mutations(cx, selected)?;
+        let lockfile = &workspace_root.join("Cargo.lock");
+        let registers_lockfile = restore_lockfile && lockfile.exists();
+
+        // Check all files before modifying any of them, so that the workspace
+        // is not left half-modified.
+        let mut modified: Vec<_> = mutations.iter().map(|m| &*m.path).collect();
+        if registers_lockfile {
+            modified.push(lockfile);
+        }
+        check_writable(&modified)?;
+
+        for mutation in mutations {
+            let manifest_path = &mutation.path;
+            check_unchanged(manifest_path, &mutation.orig)?;
+            if term::verbose() {
+                for removes in &mutation.removes {
+                    info!("removing {removes} from {}", manifest_path.display());
                 }
-                remove_private_crates(&mut doc, workspace_root, private_crates);
             }
-            cx.restore.register(orig, manifest_path);
-            fs::write(manifest_path, doc.to_string())?;
+            trace!("manifest", "removing {:?} from {}", mutation.removes, manifest_path.display());
+            cx.restore.register(mutation.orig.clone(), manifest_path)?;
+            write(manifest_path, &mutation.orig, mutation.new)?;
         }
-        if restore_lockfile {
-            let lockfile = &workspace_root.join("Cargo.lock");
-            if lockfile.exists() {
-                cx.restore.register(fs::read(lockfile)?, lockfile);
-            }
+        if registers_lockfile {
+            cx.restore.register(fs::read(lockfile)?, lockfile)?;
         }
     }
 
+    // If f() returns an error, Cargo.lock is restored with the manifests when
+    // the restore manager is dropped.
     f()?;
 
+    if cx.keep_lockfile_changes == Some(KeepLockfileChanges::OnSuccess) && !term::error() {
+        cx.restore.unregister(&cx.metadata.workspace_root.join("Cargo.lock"))?;
+    }
     // Restore original Cargo.toml and Cargo.lock.
     cx.restore.restore_all();
 
     Ok(())
 }
 
+/// When to keep the changes cargo made to Cargo.lock while the manifests are
+/// modified, for --keep-lockfile-changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum KeepLockfileChanges {
+    Always,
+    /// Keep the changes only if cargo-hack succeeds.
+    OnSuccess,
+}
+
+impl FromStr for KeepLockfileChanges {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "on-success" => Ok(Self::OnSuccess),
+            other => bail!(
+                "argument for --keep-lockfile-changes must be always or on-success, but found \
+                 `{other}`"
+            ),
+        }
+    }
+}
+
+/// The manifests modified by --strip-unsupported-profile-keys and
+/// --compat-strip-lints for a toolchain, with their contents before the
+/// modification.
+#[must_use]
+pub(crate) struct Stripped(Vec<(PathBuf, String)>);
+
+impl Stripped {
+    /// Writes back the contents of the manifests before the modification.
+    ///
+    /// The original contents are restored at the end even if this is not
+    /// called (e.g., if a command failed).
+    pub(crate) fn restore(self) -> Result<()> {
+        for (path, contents) in self.0 {
+            trace!("manifest", "restoring {}", path.display());
+            fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the profile keys (--strip-unsupported-profile-keys) and the
+/// `[lints]` tables (--compat-strip-lints) that the cargo of `toolchain` does
+/// not support from the manifests of the workspace.
+///
+/// Cargo parses the profiles and lints of all manifests of the workspace, so
+/// they fail every command even if the profile or the package is not used.
+pub(crate) fn strip_for_toolchain(
+    cx: &Context,
+    toolchain: &str,
+    cargo_version: u32,
+) -> Result<Stripped> {
+    let workspace_root = cx.workspace_root();
+    let mut stripped = vec![];
+    for path in workspace_manifests(cx) {
+        let orig = fs::read_to_string(&path)?;
+        let mut doc: toml_edit::DocumentMut = orig
+            .parse()
+            .with_context(|| format!("failed to parse manifest `{}` as toml", path.display()))?;
+        let mut removed = vec![];
+        if cx.strip_unsupported_profile_keys {
+            removed = remove_unsupported_profile_keys(&mut doc, cargo_version);
+        }
+        let mut removed_lints = vec![];
+        if cx.compat_strip_lints && cargo_version < LINTS_SINCE {
+            removed_lints = remove_lints(&mut doc);
+        }
+        if removed.is_empty() && removed_lints.is_empty() {
+            continue;
+        }
+        cx.restore.lock(&workspace_root.join("target").join(LOCK_FILE), !cx.no_wait)?;
+        let list = |keys: &[&str]| keys.iter().map(|key| format!("`{key}`")).collect::<Vec<_>>();
+        if !removed.is_empty() {
+            let removed: Vec<_> = removed.iter().map(String::as_str).collect();
+            info!(
+                "removing {} from {} for cargo {toolchain}, which does not support {}",
+                list(&removed).join(", "),
+                path.display(),
+                if removed.len() == 1 { "it" } else { "them" }
+            );
+        }
+        if !removed_lints.is_empty() {
+            // Unlike the profile keys, this changes the result of the commands.
+            warn!(
+                "removing {} from {} for cargo {toolchain}, which does not support `[lints]`; \
+                 the lint configuration is not applied on this toolchain (--compat-strip-lints)",
+                list(&removed_lints).join(" and "),
+                path.display()
+            );
+        }
+        trace!(
+            "manifest",
+            "removing {removed:?} and {removed_lints:?} from {} for cargo {toolchain}",
+            path.display()
+        );
+        // The manifest may have already been registered by --no-dev-deps, in
+        // which case the original contents are restored at the end.
+        if !cx.restore.is_registered(&path) {
+            cx.restore.register_always(orig.clone(), &path)?;
+        }
+        write(&path, &orig, doc.to_string())?;
+        stripped.push((path, orig));
+    }
+    Ok(Stripped(stripped))
+}
+
+/// Fails if the manifests of the workspace have `[lints]` tables but the
+/// oldest toolchain to run on does not support them, unless
+/// --compat-strip-lints is used.
+///
+/// Otherwise, every command on the old toolchains would fail with the same
+/// error when cargo parses the manifest.
+pub(crate) fn check_lints_supported(cx: &Context, oldest: Version) -> Result<()> {
+    if cx.compat_strip_lints || oldest.minor >= LINTS_SINCE {
+        return Ok(());
+    }
+    let mut found = vec![];
+    for path in workspace_manifests(cx) {
+        let mut doc: toml_edit::DocumentMut = fs::read_to_string(&path)?
+            .parse()
+            .with_context(|| format!("failed to parse manifest `{}` as toml", path.display()))?;
+        for table in remove_lints(&mut doc) {
+            found.push(format!("`{table}` in {}", path.display()));
+        }
+    }
+    if !found.is_empty() {
+        bail!(
+            "the workspace uses {}, which cargo older than 1.{LINTS_SINCE} does not support, but \
+             the runs include cargo {oldest}, so every command on such toolchains would fail to \
+             parse the manifest; use --compat-strip-lints to remove them while running on \
+             those toolchains (the lint configuration is not applied there), or start \
+             --version-range at 1.{LINTS_SINCE}",
+            found.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Returns the manifests of the workspace members and the workspace root.
+fn workspace_manifests(cx: &Context) -> BTreeSet<PathBuf> {
+    let mut paths: BTreeSet<_> =
+        cx.workspace_members().map(|id| cx.packages(id).manifest_path.clone()).collect();
+    paths.insert(cx.workspace_root().join("Cargo.toml"));
+    paths
+}
+
+/// Returns the `selected` packages and the workspace members they depend on
+/// (directly or transitively), ignoring dev-dependencies.
+///
+/// The dependencies are matched by name, because `cargo metadata` is run with
+/// `--no-deps` and the resolve graph is usually not available.
+fn used_members<'a>(cx: &'a Context, selected: &[&'a PackageId]) -> BTreeSet<&'a PackageId> {
+    let members: BTreeMap<_, _> =
+        cx.workspace_members().map(|id| (&*cx.packages(id).name, id)).collect();
+    let mut used = BTreeSet::new();
+    let mut stack = selected.to_vec();
+    while let Some(id) = stack.pop() {
+        if !used.insert(id) {
+            continue;
+        }
+        for dep in &cx.packages(id).dependencies {
+            if dep.kind.as_deref() == Some("dev") {
+                continue;
+            }
+            if let Some(&dep_id) = members.get(&*dep.name) {
+                stack.push(dep_id);
+            }
+        }
+    }
+    used
+}
+
+/// Removes dev-dependencies from the manifests of all workspace members.
+///
+/// Unlike [`with`], this does not require [`Context`], which requires `cargo
+/// metadata`, and is used for `--remove-dev-deps` without a subcommand.
+pub(crate) fn remove_dev_deps_in(workspace: &Workspace, no_wait: bool) -> Result<()> {
+    // The changes are not restored, but the lock is released when `restore` is dropped.
+    let restore = restore::Manager::new(false, None);
+    restore.lock(&workspace.root.join("target").join(LOCK_FILE), !no_wait)?;
+    for manifest_path in &workspace.members {
+        if term::verbose() {
+            info!("removing dev-dependencies from {}", manifest_path.display());
+        }
+        let (orig, new) = without_dev_deps(manifest_path)?;
+        write(manifest_path, &orig, new)?;
+    }
+    Ok(())
+}
+
+/// Checks that removing dev-dependencies from the manifests of all workspace
+/// members would not change them, without writing anything (`--remove-dev-deps
+/// --check`).
+pub(crate) fn check_dev_deps_removed(workspace: &Workspace) -> Result<()> {
+    let mut changed = 0;
+    for manifest_path in &workspace.members {
+        let (orig, new) = without_dev_deps(manifest_path)?;
+        if orig == new {
+            info!("{} has no dev-dependencies to remove", manifest_path.display());
+            continue;
+        }
+        changed += 1;
+        let diff = Diff::new(&orig, &new);
+        info!(
+            "--remove-dev-deps would remove {} {} from {}",
+            diff.removed(),
+            if diff.removed() == 1 { "line" } else { "lines" },
+            manifest_path.display()
+        );
+        if term::verbose() {
+            info!(
+                "diff of {}:\n{}",
+                manifest_path.display(),
+                diff.unified(3, MAX_DIFF_LINES).trim_end()
+            );
+        }
+    }
+    if changed != 0 {
+        bail!(
+            "{changed} of {} {} would be changed by --remove-dev-deps",
+            workspace.members.len(),
+            if workspace.members.len() == 1 { "manifest" } else { "manifests" }
+        );
+    }
+    Ok(())
+}
+
+/// Returns the contents of the manifest, and the contents with dev-dependencies removed.
+fn without_dev_deps(manifest_path: &Path) -> Result<(String, String)> {
+    let orig = fs::read_to_string(manifest_path)?;
+    let mut doc: toml_edit::DocumentMut = orig.parse().with_context(|| {
+        format!("failed to parse manifest `{}` as toml", manifest_path.display())
+    })?;
+    remove_dev_deps(&mut doc);
+    let new = doc.to_string();
+    Ok((orig, new))
+}
+
+/// Restores the files modified by a cargo-hack instance that did not exit cleanly.
+///
+/// If `force` is `true` (`cargo hack restore`), this also removes the lock file
+/// left by that instance. Otherwise, this only restores the files if the lock
+/// file has already been removed.
+pub(crate) fn recover(workspace_root: &Path, force: bool) -> Result<()> {
+    restore::recover(&workspace_root.join("target").join(LOCK_FILE), force)
+}
+
+/// Checks that all `files` are writable, and fails with the list of the files
+/// that are not.
+///
+/// The files (and the file system) may become read-only while cargo-hack is
+/// running (e.g., in some sandboxed build systems), but checking them upfront
+/// catches the common case without modifying anything.
+fn check_writable(files: &[&Path]) -> Result<()> {
+    let mut not_writable = String::new();
+    for path in files {
+        let res = std::fs::metadata(path).and_then(|metadata| {
+            if metadata.permissions().readonly() {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file"));
+            }
+            // This fails on a read-only file system.
+            std::fs::OpenOptions::new().append(true).open(path).map(drop)
+        });
+        if let Err(e) = res {
+            let _ = write!(not_writable, "\n    {}: {e}", path.display());
+        }
+    }
+    if !not_writable.is_empty() {
+        bail!(
+            "the following files need to be modified temporarily, but they are not \
+             writable:{not_writable}"
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the manifest has not been modified since it was read at startup.
+///
+/// Otherwise, the manifest may have been modified by another process, and
+/// restoring the contents read at startup may lose the actual original contents.
+fn check_unchanged(manifest_path: &Path, raw: &str) -> Result<()> {
+    if fs::read_to_string(manifest_path)? != raw {
+        bail!(
+            "`{}` has been modified since cargo-hack started (possibly by another cargo-hack \
+             instance); please check its contents and run cargo-hack again",
+            manifest_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes the modified manifest and reports how it was modified.
+fn write(manifest_path: &Path, orig: &str, new: String) -> Result<()> {
+    let diff = Diff::new(orig, &new);
+    let removed = diff.removed();
+    if removed != 0 {
+        info!(
+            "removed {removed} {} from {}",
+            if removed == 1 { "line" } else { "lines" },
+            manifest_path.display()
+        );
+    }
+    if term::verbose() && (removed != 0 || diff.added() != 0) {
+        info!(
+            "diff of {}:\n{}",
+            manifest_path.display(),
+            diff.unified(3, MAX_DIFF_LINES).trim_end()
+        );
+    }
+    fs::write(manifest_path, new)
+}
+
 fn remove_dev_deps(doc: &mut toml_edit::DocumentMut) {
     const KEY: &str = "dev-dependencies";
     let table = doc.as_table_mut();
@@ -201,6 +713,73 @@ fn remove_dev_deps(doc: &mut toml_edit::DocumentMut) {
     }
 }
 
+/// Removes the profile keys that cargo 1.`cargo_version` does not support, and
+/// returns the removed keys (e.g., `profile.release.strip`).
+fn remove_unsupported_profile_keys(
+    doc: &mut toml_edit::DocumentMut,
+    cargo_version: u32,
+) -> Vec<String> {
+    fn remove(
+        table: &mut dyn toml_edit::TableLike,
+        path: &str,
+        cargo_version: u32,
+        removed: &mut Vec<String>,
+    ) {
+        for &(key, since) in UNSUPPORTED_PROFILE_KEYS {
+            if cargo_version < since && table.remove(key).is_some() {
+                removed.push(format!("{path}.{key}"));
+            }
+        }
+        if cargo_version < NAMED_DEBUG_SINCE
+            && table.get("debug").is_some_and(toml_edit::Item::is_str)
+        {
+            table.remove("debug");
+            removed.push(format!("{path}.debug"));
+        }
+    }
+
+    let mut removed = vec![];
+    let Some(profiles) = doc.get_mut("profile").and_then(toml_edit::Item::as_table_like_mut) else {
+        return removed;
+    };
+    for (name, profile) in profiles.iter_mut() {
+        let Some(profile) = profile.as_table_like_mut() else { continue };
+        let path = format!("profile.{}", name.get());
+        remove(profile, &path, cargo_version, &mut removed);
+        if let Some(table) =
+            profile.get_mut("build-override").and_then(toml_edit::Item::as_table_like_mut)
+        {
+            remove(table, &format!("{path}.build-override"), cargo_version, &mut removed);
+        }
+        if let Some(packages) =
+            profile.get_mut("package").and_then(toml_edit::Item::as_table_like_mut)
+        {
+            for (package, table) in packages.iter_mut() {
+                if let Some(table) = table.as_table_like_mut() {
+                    let path = format!("{path}.package.{}", package.get());
+                    remove(table, &path, cargo_version, &mut removed);
+                }
+            }
+        }
+    }
+    removed
+}
+
+/// Removes the `[lints]` table of the package and the `[workspace.lints]`
+/// table, and returns the removed tables.
+fn remove_lints(doc: &mut toml_edit::DocumentMut) -> Vec<&'static str> {
+    let mut removed = vec![];
+    if doc.as_table_mut().remove("lints").is_some() {
+        removed.push("lints");
+    }
+    if let Some(workspace) = doc.get_mut("workspace").and_then(toml_edit::Item::as_table_like_mut) {
+        if workspace.remove("lints").is_some() {
+            removed.push("workspace.lints");
+        }
+    }
+    removed
+}
+
 fn remove_private_crates(
     doc: &mut toml_edit::DocumentMut,
     workspace_root: &Path,
@@ -262,7 +841,7 @@ fn remove_private_crates(
 
 #[cfg(test)]
 mod tests {
-    use super::remove_dev_deps;
+    use super::{remove_dev_deps, remove_lints, remove_unsupported_profile_keys, Config};
 
     macro_rules! test {
         ($name:ident, $input:expr, $expected:expr) => {
@@ -435,4 +1014,126 @@ foo = [
 ]
 "
     );
+
+    #[test]
+    fn unsupported_profile_keys() {
+        let input = "\
+[package]
+name = \"a\"
+strip = true
+
+[profile.release]
+strip = \"symbols\"
+lto = true
+
+[profile.dev]
+debug = \"line-tables-only\"
+
+[profile.dev.build-override]
+strip = true
+debug = 0
+
+[profile.dev.package.foo]
+debug = \"limited\"
+";
+        let strip = |cargo_version| {
+            let mut doc: toml_edit::DocumentMut = input.parse().unwrap();
+            let removed = remove_unsupported_profile_keys(&mut doc, cargo_version);
+            (removed, doc.to_string())
+        };
+        let (removed, output) = strip(58);
+        assert_eq!(removed, [
+            "profile.release.strip",
+            "profile.dev.debug",
+            "profile.dev.build-override.strip",
+            "profile.dev.package.foo.debug",
+        ]);
+        assert_eq!(
+            output,
+            "\
+[package]
+name = \"a\"
+strip = true
+
+[profile.release]
+lto = true
+
+[profile.dev]
+
+[profile.dev.build-override]
+debug = 0
+
+[profile.dev.package.foo]
+"
+        );
+        let (removed, _) = strip(59);
+        assert_eq!(removed, ["profile.dev.debug", "profile.dev.package.foo.debug"]);
+        let (removed, output) = strip(71);
+        assert!(removed.is_empty());
+        assert_eq!(output, input);
+        let mut doc: toml_edit::DocumentMut = "[package]".parse().unwrap();
+        assert!(remove_unsupported_profile_keys(&mut doc, 50).is_empty());
+    }
+
+    #[test]
+    fn lints() {
+        let input = "\
+[package]
+name = \"a\"
+
+[lints]
+workspace = true
+
+[workspace]
+members = [\"b\"]
+
+[workspace.lints.rust]
+unsafe_code = \"forbid\"
+
+[workspace.lints.clippy]
+all = \"warn\"
+
+[dependencies]
+";
+        let mut doc: toml_edit::DocumentMut = input.parse().unwrap();
+        assert_eq!(remove_lints(&mut doc), ["lints", "workspace.lints"]);
+        assert_eq!(
+            doc.to_string(),
+            "\
+[package]
+name = \"a\"
+
+[workspace]
+members = [\"b\"]
+
+[dependencies]
+"
+        );
+        assert!(remove_lints(&mut doc).is_empty());
+
+        let mut doc: toml_edit::DocumentMut =
+            "lints.workspace = true\n[package]\nname = \"b\"\n".parse().unwrap();
+        assert_eq!(remove_lints(&mut doc), ["lints"]);
+        assert_eq!(doc.to_string(), "[package]\nname = \"b\"\n");
+    }
+
+    #[test]
+    fn config() {
+        let parse = |s: &str| Config::from_table(&s.parse().unwrap());
+        assert!(parse("[package]").unwrap().feature_min_versions.is_empty());
+        let config = parse(
+            "[package.metadata.cargo-hack.feature-min-versions]\nsimd = \"1.72\"\nb = \"1.70.1\"",
+        )
+        .unwrap();
+        assert_eq!(config.feature_min_versions["simd"].to_string(), "1.72");
+        assert_eq!(config.feature_min_versions["b"].to_string(), "1.70.1");
+        assert_eq!(
+            parse("[package.metadata.cargo-hack.feature-min-versions]\nsimd = 1").err().unwrap(),
+            "package.metadata.cargo-hack.feature-min-versions.simd"
+        );
+        assert_eq!(
+            parse("[package.metadata]\ncargo-hack = 1").err().unwrap(),
+            "package.metadata.cargo-hack"
+        );
+    }
 }
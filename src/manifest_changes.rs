@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --skip-unless-manifest-changed.
+//
+// The manifests of the selected packages, the root manifest, and Cargo.lock
+// are compared with their contents at the given git revision, and if none of
+// them changed, all runs are skipped, e.g., for pushes that only change
+// documentation or CI config. If the comparison fails (e.g., git is not
+// available), the runs are performed as usual.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{context::Context, fs, process::ProcessBuilder, term, PackageRuns};
+
+/// Returns `true` if none of the manifests changed since `rev`.
+pub(crate) fn unchanged(cx: &Context, packages: &[PackageRuns<'_>], rev: &str) -> bool {
+    match changed_file(cx, packages, rev) {
+        Ok(None) => {
+            info!("no manifest changed since {rev}; skipping all runs (--skip-unless-manifest-changed)");
+            true
+        }
+        Ok(Some(path)) => {
+            if term::verbose() {
+                info!("`{}` changed since {rev}", path.display());
+            }
+            false
+        }
+        Err(e) => {
+            info!("failed to compare manifests with {rev}; performing all runs: {e:#}");
+            false
+        }
+    }
+}
+
+/// Returns the first file that differs from its content at `rev`, or `None`
+/// if all files are the same.
+fn changed_file(cx: &Context, packages: &[PackageRuns<'_>], rev: &str) -> Result<Option<PathBuf>> {
+    let root = cx.workspace_root();
+    verify_rev(root, rev)?;
+
+    let mut paths = BTreeSet::new();
+    paths.insert(root.join("Cargo.toml"));
+    let lockfile = root.join("Cargo.lock");
+    // Cargo.lock of libraries is often ignored, but generated by the runs.
+    if !is_ignored(&lockfile) {
+        paths.insert(lockfile);
+    }
+    paths.extend(packages.iter().map(|pkg| cx.packages(pkg.id).manifest_path.clone()));
+    for path in paths {
+        let current = if path.exists() { Some(fs::read(&path)?) } else { None };
+        if current != show(rev, &path) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Fails if git is not available, `dir` is not in a git repository, or `rev`
+/// is not a valid revision.
+pub(crate) fn verify_rev(dir: &Path, rev: &str) -> Result<()> {
+    git(dir)
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{rev}^{{commit}}"))
+        .run_with_output()?;
+    Ok(())
+}
+
+/// Returns the content of the file at `rev`, or `None` if it does not exist
+/// in `rev`.
+pub(crate) fn show(rev: &str, path: &Path) -> Option<Vec<u8>> {
+    let name = path.file_name().unwrap().to_string_lossy();
+    git(path.parent().unwrap())
+        .arg("show")
+        .arg(format!("{rev}:./{name}"))
+        .run_with_output()
+        .ok()
+        .map(|output| output.stdout)
+}
+
+fn is_ignored(path: &Path) -> bool {
+    git(path.parent().unwrap())
+        .args(["check-ignore", "--quiet"])
+        .arg(path.file_name().unwrap())
+        .run_with_output()
+        .is_ok()
+}
+
+fn git(dir: &Path) -> ProcessBuilder<'static> {
+    let mut cmd = cmd!("git");
+    cmd.current_dir(dir);
+    cmd
+}
@@ -7,13 +7,13 @@
 // - https://github.com/oli-obk/cargo_metadata
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     ffi::OsStr,
     path::{Path, PathBuf},
     rc::Rc,
 };
 
-use anyhow::{format_err, Context as _, Result};
+use anyhow::{bail, format_err, Context as _, Result};
 use cargo_config2::Config;
 use serde_json::{Map, Value};
 
@@ -46,22 +46,26 @@ pub(crate) struct Metadata {
     pub(crate) resolve: Resolve,
     /// The absolute path to the root of the workspace.
     pub(crate) workspace_root: PathBuf,
+    /// The absolute path to the build directory where Cargo places its output.
+    pub(crate) target_directory: PathBuf,
 }
 
 impl Metadata {
     pub(crate) fn new(
-        manifest_path: Option<&str>,
+        manifest_path: Option<&Path>,
         cargo: &OsStr,
         mut cargo_version: u32,
         args: &Args,
+        config: Option<&Config>,
         restore: &mut restore::Manager,
     ) -> Result<Self> {
         let stable_cargo_version =
             cargo::version(cmd!("rustup", "run", "stable", "cargo")).map(|v| v.minor).unwrap_or(0);
 
-        let config;
         let include_deps_features = if args.include_deps_features {
-            config = Config::load()?;
+            let Some(config) = config else {
+                bail!("--include-deps-features requires cargo config to be loaded");
+            };
             let targets = config.build_target_for_cli(&args.target)?;
             let host = config.host_triple()?;
             Some((targets, host))
@@ -69,6 +73,11 @@ impl Metadata {
             None
         };
 
+        // --package-with-deps and --package-with-dependents need the dependency graph.
+        let needs_resolve = include_deps_features.is_some()
+            || !args.package_with_deps.is_empty()
+            || !args.package_with_dependents.is_empty();
+
         let mut cmd;
         let append_metadata_args = |cmd: &mut ProcessBuilder<'_>| {
             cmd.arg("metadata");
@@ -90,7 +99,7 @@ impl Metadata {
                 // features-related flags are unneeded when --no-deps is used.
                 // TODO:
                 // cmd.arg("--all-features");
-            } else {
+            } else if !needs_resolve {
                 cmd.arg("--no-deps");
             }
         };
@@ -114,7 +123,7 @@ impl Metadata {
                 cmd.run_with_output()?;
             }
             let guard = term::verbose::scoped(false);
-            restore.register_always(fs::read(&lockfile)?, lockfile);
+            restore.register_always(fs::read(&lockfile)?, lockfile)?;
             // Try with stable cargo because if workspace member has
             // a dependency that requires newer cargo features, `cargo metadata`
             // with older cargo may fail.
@@ -125,11 +134,20 @@ impl Metadata {
             drop(guard);
             match json {
                 Ok(json) => {
+                    trace!(
+                        "metadata",
+                        "used `cargo metadata` of stable cargo 1.{stable_cargo_version}, which is \
+                         newer than cargo 1.{cargo_version}"
+                    );
                     cargo_version = stable_cargo_version;
                     json
                 }
-                Err(_e) => {
-                    if include_deps_features.is_some() {
+                Err(e) => {
+                    trace!(
+                        "metadata",
+                        "`cargo metadata` of stable cargo 1.{stable_cargo_version} failed: {e:#}"
+                    );
+                    if needs_resolve {
                         // If failed, try again with the version of cargo we will actually use.
                         cmd = cmd!(cargo);
                         append_metadata_args(&mut cmd);
@@ -140,6 +158,15 @@ impl Metadata {
                 }
             }
         } else {
+            trace!(
+                "metadata",
+                "used `cargo metadata` of cargo 1.{cargo_version} ({})",
+                if stable_cargo_version == 0 {
+                    "stable cargo is not available".to_owned()
+                } else {
+                    format!("stable cargo 1.{stable_cargo_version} is not newer")
+                }
+            );
             cmd = cmd!(cargo);
             append_metadata_args(&mut cmd);
             cmd.read()?
@@ -147,33 +174,73 @@ impl Metadata {
 
         let map = serde_json::from_str(&json)
             .with_context(|| format!("failed to parse output from {cmd}"))?;
-        Self::from_obj(map, cargo_version)
-            .map_err(|s| format_err!("failed to parse `{s}` field from metadata"))
+        let mut cx = ParseContext { strict: args.strict_metadata, ignored: BTreeSet::new() };
+        let metadata = Self::from_obj(map, cargo_version, &mut cx)
+            .map_err(|s| format_err!("failed to parse `{s}` field from metadata"))?;
+        if !cx.ignored.is_empty() {
+            let fields: Vec<_> = cx.ignored.iter().map(|f| format!("`{f}`")).collect();
+            warn!(
+                "ignored {} field(s) of unexpected shape in the output of `cargo metadata`; this may be \
+                 caused by a newer cargo than this version of cargo-hack supports, so consider \
+                 upgrading cargo-hack (use --strict-metadata to make this an error)",
+                fields.join(", ")
+            );
+        }
+        Ok(metadata)
     }
 
-    fn from_obj(mut map: Object, cargo_version: u32) -> ParseResult<Self> {
+    fn from_obj(mut map: Object, cargo_version: u32, cx: &mut ParseContext) -> ParseResult<Self> {
         let workspace_members: Vec<_> = map
             .remove_array("workspace_members")?
             .into_iter()
             .map(|v| into_string(v).ok_or("workspace_members"))
             .collect::<Result<_, _>>()?;
+        let workspace_root: PathBuf = map.remove_string("workspace_root")?;
+        let target_directory =
+            map.remove_string("target_directory").unwrap_or_else(|_| workspace_root.join("target"));
         Ok(Self {
             cargo_version,
             packages: map
                 .remove_array("packages")?
                 .into_iter()
-                .map(|v| Package::from_value(v, cargo_version))
+                .map(|v| Package::from_value(v, cargo_version, cx))
                 .collect::<Result<_, _>>()?,
             workspace_members,
             resolve: match map.remove_nullable("resolve", into_object)? {
-                Some(resolve) => Resolve::from_obj(resolve, cargo_version)?,
+                Some(resolve) => Resolve::from_obj(resolve, cargo_version, cx)?,
                 None => Resolve { nodes: HashMap::default() },
             },
-            workspace_root: map.remove_string("workspace_root")?,
+            target_directory,
+            workspace_root,
         })
     }
 }
 
+/// State for parsing the output of `cargo metadata`.
+///
+/// Fields that cargo-hack does not strictly need (e.g., `publish`) fall back
+/// to defaults if their shapes are unexpected, so that a change in a newer
+/// cargo does not break cargo-hack. Such fields are collected in `ignored`.
+struct ParseContext {
+    /// --strict-metadata
+    strict: bool,
+    ignored: BTreeSet<&'static str>,
+}
+
+impl ParseContext {
+    /// Returns `default` instead of the error of a non-essential field, unless
+    /// --strict-metadata is used.
+    fn or<T>(&mut self, res: ParseResult<T>, default: T) -> ParseResult<T> {
+        match res {
+            Err(key) if !self.strict => {
+                self.ignored.insert(key);
+                Ok(default)
+            }
+            res => res,
+        }
+    }
+}
+
 /// The resolved dependency graph for the entire workspace.
 pub(crate) struct Resolve {
     /// Nodes in a dependency graph.
@@ -183,12 +250,12 @@ pub(crate) struct Resolve {
 }
 
 impl Resolve {
-    fn from_obj(mut map: Object, cargo_version: u32) -> ParseResult<Self> {
+    fn from_obj(mut map: Object, cargo_version: u32, cx: &mut ParseContext) -> ParseResult<Self> {
         Ok(Self {
             nodes: map
                 .remove_array("nodes")?
                 .into_iter()
-                .map(|v| Node::from_value(v, cargo_version))
+                .map(|v| Node::from_value(v, cargo_version, cx))
                 .collect::<Result<_, _>>()?,
         })
     }
@@ -203,7 +270,11 @@ pub(crate) struct Node {
 }
 
 impl Node {
-    fn from_value(mut value: Value, cargo_version: u32) -> ParseResult<(PackageId, Self)> {
+    fn from_value(
+        mut value: Value,
+        cargo_version: u32,
+        cx: &mut ParseContext,
+    ) -> ParseResult<(PackageId, Self)> {
         let map = value.as_object_mut().ok_or("nodes")?;
 
         let id = map.remove_string("id")?;
@@ -212,7 +283,7 @@ impl Node {
             deps: if cargo_version >= 30 {
                 map.remove_array("deps")?
                     .into_iter()
-                    .map(|v| NodeDep::from_value(v, cargo_version))
+                    .map(|v| NodeDep::from_value(v, cargo_version, cx))
                     .collect::<Result<_, _>>()?
             } else {
                 vec![]
@@ -232,17 +303,24 @@ pub(crate) struct NodeDep {
 }
 
 impl NodeDep {
-    fn from_value(mut value: Value, cargo_version: u32) -> ParseResult<Self> {
+    fn from_value(
+        mut value: Value,
+        cargo_version: u32,
+        cx: &mut ParseContext,
+    ) -> ParseResult<Self> {
         let map = value.as_object_mut().ok_or("deps")?;
 
         Ok(Self {
             pkg: map.remove_string("pkg")?,
             // This field was added in Rust 1.41.
             dep_kinds: if cargo_version >= 41 {
-                map.remove_array("dep_kinds")?
-                    .into_iter()
-                    .map(DepKindInfo::from_value)
-                    .collect::<Result<_, _>>()?
+                let dep_kinds = map.remove_array("dep_kinds").and_then(|a| {
+                    a.into_iter()
+                        .map(DepKindInfo::from_value)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| "dep_kinds")
+                });
+                cx.or(dep_kinds, vec![])?
             } else {
                 vec![]
             },
@@ -281,6 +359,13 @@ pub(crate) struct Package {
     pub(crate) features: BTreeMap<String, Vec<String>>,
     /// Absolute path to this package's manifest.
     pub(crate) manifest_path: PathBuf,
+    /// Cargo targets.
+    pub(crate) targets: Vec<Target>,
+    /// The name of the binary that `cargo run` runs by default.
+    ///
+    /// This is always `None` if running with a version of Cargo that doesn't
+    /// provide this field.
+    pub(crate) default_run: Option<String>,
     /// List of registries to which this package may be published.
     ///
     /// This is always `true` if running with a version of Cargo older than 1.39.
@@ -289,10 +374,19 @@ pub(crate) struct Package {
     ///
     /// This is always `None` if running with a version of Cargo older than 1.58.
     pub(crate) rust_version: Option<String>,
+    /// The Rust edition of this package.
+    ///
+    /// This is always `None` if running with a version of Cargo that doesn't
+    /// provide this field.
+    pub(crate) edition: Option<String>,
 }
 
 impl Package {
-    fn from_value(mut value: Value, cargo_version: u32) -> ParseResult<(PackageId, Self)> {
+    fn from_value(
+        mut value: Value,
+        cargo_version: u32,
+        cx: &mut ParseContext,
+    ) -> ParseResult<(PackageId, Self)> {
         let map = value.as_object_mut().ok_or("packages")?;
 
         let id = map.remove_string("id")?;
@@ -315,25 +409,81 @@ impl Package {
                 .collect::<Option<_>>()
                 .ok_or("features")?,
             manifest_path: map.remove_string("manifest_path")?,
+            targets: map
+                .remove_array("targets")?
+                .into_iter()
+                .map(Target::from_value)
+                .collect::<Result<_, _>>()?,
+            // This field is not provided by old versions of Cargo.
+            default_run: map.remove("default_run").and_then(into_string),
             // This field was added in Rust 1.39.
             publish: if cargo_version >= 39 {
                 // Publishing is unrestricted if null, and forbidden if an empty array.
-                map.remove_nullable("publish", into_array)?.map_or(true, |a| !a.is_empty())
+                let publish = map
+                    .remove_nullable("publish", into_array)
+                    .map(|a| a.map_or(true, |a| !a.is_empty()));
+                cx.or(publish, true)?
             } else {
                 true
             },
             // This field was added in Rust 1.58.
             rust_version: if cargo_version >= 58 {
-                map.remove_nullable("rust_version", into_string)?
+                cx.or(map.remove_nullable("rust_version", into_string), None)?
             } else {
                 None
             },
+            // This field is not provided by old versions of Cargo.
+            edition: match map.remove("edition") {
+                Some(edition) => cx.or(allow_null(edition, into_string).ok_or("edition"), None)?,
+                None => None,
+            },
         }))
     }
 
     pub(crate) fn optional_deps(&self) -> impl Iterator<Item = &str> + '_ {
         self.dependencies.iter().filter_map(Dependency::as_feature)
     }
+
+    /// Returns the names of the binary targets.
+    pub(crate) fn bins(&self) -> impl Iterator<Item = &str> + '_ {
+        self.targets.iter().filter(|t| t.kind.iter().any(|k| k == "bin")).map(|t| &*t.name)
+    }
+}
+
+/// A single target (lib, bin, example, ...) provided by a crate.
+pub(crate) struct Target {
+    /// Name as given in the `Cargo.toml` or generated from the file name.
+    pub(crate) name: String,
+    /// Kind of target ("bin", "example", "test", "bench", "lib", "custom-build").
+    pub(crate) kind: Vec<String>,
+    /// Features required to build this target (`required-features` in Cargo.toml).
+    pub(crate) required_features: Vec<String>,
+    /// Path to the main source file of the target.
+    pub(crate) src_path: PathBuf,
+}
+
+impl Target {
+    fn from_value(mut value: Value) -> ParseResult<Self> {
+        let map = value.as_object_mut().ok_or("targets")?;
+
+        Ok(Self {
+            name: map.remove_string("name")?,
+            kind: map
+                .remove_array("kind")?
+                .into_iter()
+                .map(into_string)
+                .collect::<Option<_>>()
+                .ok_or("kind")?,
+            // This field is omitted if the target has no required features.
+            required_features: match map.remove("required-features") {
+                Some(v) => into_array(v)
+                    .and_then(|v| v.into_iter().map(into_string).collect::<Option<_>>())
+                    .ok_or("required-features")?,
+                None => vec![],
+            },
+            src_path: map.remove_string("src_path")?,
+        })
+    }
 }
 
 /// A dependency of the main crate.
@@ -342,12 +492,14 @@ pub(crate) struct Dependency {
     pub(crate) name: String,
     // /// The version requirement for the dependency.
     // pub(crate) req: String,
+    /// The dependency kind: `"dev"`, `"build"`, or `None` for a normal dependency.
+    pub(crate) kind: Option<String>,
     /// Whether or not this is an optional dependency.
     pub(crate) optional: bool,
-    // TODO: support this
-    // /// The target platform for the dependency.
-    // /// This is `None` if it is not a target dependency.
-    // pub(crate) target: Option<String>,
+    /// The target platform for the dependency, e.g., `cfg(windows)` or
+    /// `x86_64-pc-windows-msvc`.
+    /// This is `None` if it is not a target dependency.
+    pub(crate) target: Option<String>,
     /// If the dependency is renamed, this is the new name for the dependency
     /// as a string.
     /// This is `None` if it is not renamed.
@@ -363,7 +515,9 @@ impl Dependency {
         Ok(Self {
             name: map.remove_string("name")?,
             // req: map.remove_string("req")?,
+            kind: map.remove_nullable("kind", into_string)?,
             optional: map.get("optional").and_then(Value::as_bool).ok_or("optional")?,
+            target: map.remove_nullable("target", into_string)?,
             // This field was added in Rust 1.26.
             rename: map.remove_nullable("rename", into_string)?,
         })
@@ -438,3 +592,86 @@ impl ObjectExt for Object {
         self.remove(key).and_then(|v| allow_null(v, f)).ok_or(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde_json::{json, Value};
+
+    use super::{Metadata, ParseContext};
+
+    fn metadata() -> Value {
+        json!({
+            "packages": [{
+                "id": "a 0.1.0",
+                "name": "a",
+                "dependencies": [],
+                "features": { "f": [] },
+                "manifest_path": "/a/Cargo.toml",
+                "targets": [{ "name": "a", "kind": ["lib"], "src_path": "/a/src/lib.rs" }],
+                "publish": [],
+                "rust_version": "1.70",
+                "edition": "2021",
+            }],
+            "workspace_members": ["a 0.1.0"],
+            "resolve": {
+                "nodes": [{
+                    "id": "a 0.1.0",
+                    "deps": [{ "pkg": "b 0.1.0", "dep_kinds": [{ "kind": null, "target": null }] }],
+                }],
+            },
+            "workspace_root": "/a",
+        })
+    }
+
+    fn parse(value: Value, strict: bool) -> (Result<Metadata, &'static str>, Vec<&'static str>) {
+        let mut cx = ParseContext { strict, ignored: BTreeSet::new() };
+        let Value::Object(map) = value else { unreachable!() };
+        let res = Metadata::from_obj(map, 80, &mut cx);
+        (res, cx.ignored.into_iter().collect())
+    }
+
+    #[test]
+    fn unexpected_shapes() {
+        let id = "a 0.1.0".to_owned().into();
+        let (res, ignored) = parse(metadata(), false);
+        let m = res.unwrap();
+        assert!(!m.packages[&id].publish);
+        assert_eq!(m.packages[&id].rust_version.as_deref(), Some("1.70"));
+        assert_eq!(m.packages[&id].edition.as_deref(), Some("2021"));
+        assert_eq!(m.resolve.nodes[&id].deps[0].dep_kinds.len(), 1);
+        assert!(ignored.is_empty());
+
+        // Unexpected shapes of non-essential fields fall back to defaults.
+        let mut mutated = metadata();
+        mutated["packages"][0]["publish"] = json!({ "registries": [] });
+        mutated["packages"][0]["rust_version"] = json!({ "version": "1.70" });
+        mutated["packages"][0]["edition"] = json!(2021);
+        mutated["resolve"]["nodes"][0]["deps"][0]["dep_kinds"][0]["kind"] =
+            json!({ "name": "normal" });
+        let (res, ignored) = parse(mutated.clone(), false);
+        let m = res.unwrap();
+        assert!(m.packages[&id].publish);
+        assert_eq!(m.packages[&id].rust_version, None);
+        assert_eq!(m.packages[&id].edition, None);
+        assert_eq!(m.packages[&id].features.len(), 1);
+        assert!(m.resolve.nodes[&id].deps[0].dep_kinds.is_empty());
+        // All of them are reported together.
+        assert_eq!(ignored, ["dep_kinds", "edition", "publish", "rust_version"]);
+
+        // ...but not with --strict-metadata.
+        assert_eq!(parse(mutated, true).0.err(), Some("publish"));
+        let mut mutated = metadata();
+        mutated["packages"][0]["rust_version"] = json!(17);
+        assert_eq!(parse(mutated, true).0.err(), Some("rust_version"));
+
+        // Essential fields are always fatal.
+        let mut mutated = metadata();
+        mutated["packages"][0]["features"] = json!(["f"]);
+        assert_eq!(parse(mutated, false).0.err(), Some("features"));
+        let mut mutated = metadata();
+        mutated["packages"][0]["manifest_path"] = json!(null);
+        assert_eq!(parse(mutated, false).0.err(), Some("manifest_path"));
+    }
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --check-msrv-consistency.
+//
+// With this flag, each package is also run on the toolchains older than its
+// rust-version (with --ignore-rust-version), and the results of the runs per
+// toolchain are compared with the declared rust-version at the end.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    summary::{Run, Status},
+    version::Version,
+};
+
+/// An inconsistency between the declared rust-version of a package and the
+/// results of the runs.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Finding {
+    /// The package failed on the declared rust-version or later.
+    TooLow {
+        package: String,
+        declared: Version,
+        /// The newest toolchain on which any run failed.
+        failed: Version,
+        /// The oldest toolchain after `failed` on which all runs passed.
+        passed: Option<Version>,
+    },
+    /// All runs passed on toolchains older than the declared rust-version.
+    TooHigh {
+        package: String,
+        declared: Version,
+        /// The oldest toolchain from which all runs passed.
+        passed: Version,
+    },
+}
+
+impl Finding {
+    pub(crate) fn is_error(&self) -> bool {
+        matches!(self, Self::TooLow { .. })
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLow { package, declared, failed, passed } => {
+                write!(f, "{package}: rust-version is {declared}, but runs failed on {failed}")?;
+                if let Some(passed) = passed {
+                    write!(f, " (all runs passed on {passed} and later)")?;
+                }
+                Ok(())
+            }
+            Self::TooHigh { package, declared, passed } => write!(
+                f,
+                "{package}: rust-version is {declared}, but all runs passed on {passed} and later; \
+                 rust-version may be lowered"
+            ),
+        }
+    }
+}
+
+/// Compares the results of the runs with the declared rust-version of each package.
+///
+/// `declared` is the rust-version of each package, keyed by package name.
+/// Packages without rust-version and skipped runs are ignored.
+pub(crate) fn check(runs: &[Run], declared: &BTreeMap<String, Version>) -> Vec<Finding> {
+    // Whether all runs passed, per package and toolchain.
+    let mut results: BTreeMap<&str, BTreeMap<Version, bool>> = BTreeMap::new();
+    for run in runs {
+        if !run.status.ran() || !declared.contains_key(&run.package) {
+            continue;
+        }
+        let Some(toolchain) = run.toolchain.as_deref().and_then(|t| t.parse().ok()) else {
+            continue;
+        };
+        let passed = results.entry(&run.package).or_default().entry(toolchain).or_insert(true);
+        *passed &= run.status == Status::Passed;
+    }
+
+    let mut findings = vec![];
+    for (package, results) in results {
+        let declared = declared[package];
+        let failed = results.iter().rev().find(|(_, &passed)| !passed).map(|(&v, _)| v);
+        let passed = results.keys().copied().find(|&v| failed.map_or(true, |failed| v > failed));
+        let package = package.to_owned();
+        match (failed, passed) {
+            (Some(failed), passed) if failed >= declared => {
+                findings.push(Finding::TooLow { package, declared, failed, passed });
+            }
+            (_, Some(passed)) if passed < declared => {
+                findings.push(Finding::TooHigh { package, declared, passed });
+            }
+            _ => {}
+        }
+    }
+    findings
+}
+
+/// Prints the findings of --check-msrv-consistency.
+pub(crate) fn print(findings: &[Finding]) {
+    if findings.is_empty() {
+        info!("rust-version of all packages is consistent with the results");
+    }
+    for finding in findings {
+        if finding.is_error() {
+            error!("{finding}");
+        } else {
+            info!("{finding}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet, VecDeque},
+        time::Duration,
+    };
+
+    use super::{check, Finding};
+    use crate::{
+        summary::{Run, Status},
+        version::Version,
+    };
+
+    fn run(package: &str, minor: u32, status: Status) -> Run {
+        Run {
+            id: String::new(),
+            package: package.to_owned(),
+            toolchain: Some(format!("1.{minor}")),
+            command: "`cargo check`".to_owned(),
+            features: String::new(),
+            requested_features: BTreeSet::new(),
+            enabled_features: None,
+            doc_items: None,
+            status,
+            duration: Duration::ZERO,
+            cold_start: false,
+            error: None,
+            stderr: VecDeque::new(),
+            cfgs: vec![],
+            sccache: None,
+        }
+    }
+
+    fn version(minor: u32) -> Version {
+        Version { major: 1, minor, patch: None }
+    }
+
+    #[test]
+    fn msrv_consistency() {
+        let declared: BTreeMap<_, _> = [("a", 72), ("b", 72), ("c", 72), ("d", 72)]
+            .map(|(p, v)| (p.to_owned(), version(v)))
+            .into();
+        let runs = [
+            // Consistent.
+            run("a", 70, Status::Failed),
+            run("a", 72, Status::Passed),
+            run("a", 74, Status::Passed),
+            // Higher than needed.
+            run("b", 70, Status::Passed),
+            run("b", 72, Status::Passed),
+            // Lower than reality: one of the runs on 1.74 failed.
+            run("c", 70, Status::Failed),
+            run("c", 72, Status::Passed),
+            run("c", 74, Status::Passed),
+            run("c", 74, Status::Failed),
+            run("c", 76, Status::Passed),
+            // Skipped runs are ignored.
+            run("d", 70, Status::Skipped),
+            run("d", 72, Status::Passed),
+            // Packages without rust-version are ignored.
+            run("e", 70, Status::Failed),
+        ];
+        assert_eq!(check(&runs, &declared), [
+            Finding::TooHigh {
+                package: "b".to_owned(),
+                declared: version(72),
+                passed: version(70)
+            },
+            Finding::TooLow {
+                package: "c".to_owned(),
+                declared: version(72),
+                failed: version(74),
+                passed: Some(version(76)),
+            },
+        ]);
+        assert_eq!(
+            check(&runs[5..9], &declared)[0].to_string(),
+            "c: rust-version is 1.72, but runs failed on 1.74"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --quiet-success.
+//
+// The output of each command is written to a temporary file instead of being
+// printed, and is replayed only if the command fails. A file is used instead
+// of an in-memory buffer because the output of cargo (e.g., `cargo test` with
+// many tests) can be huge.
+
+use std::{
+    collections::VecDeque,
+    env,
+    fs::{self, File},
+    io::{self, BufRead as _, Write as _},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context as _, Result};
+
+/// The output of a command, captured to a temporary file.
+pub(crate) struct CapturedOutput {
+    path: PathBuf,
+    file: File,
+}
+
+impl CapturedOutput {
+    pub(crate) fn new() -> Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "cargo-hack-{}-{}.log",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("failed to create file `{}`", path.display()))?;
+        Ok(Self { path, file })
+    }
+
+    /// Returns the file to which the command writes its output.
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Prints the captured output to stderr.
+    ///
+    /// If `max_lines` is `Some`, only the last `max_lines` lines are printed.
+    /// The lines are prefixed with `tag` if it is `Some` (--tag-output).
+    pub(crate) fn replay(&self, max_lines: Option<usize>, tag: Option<&str>) -> Result<()> {
+        let mut stderr = io::stderr().lock();
+        match max_lines {
+            None if tag.is_none() => {
+                io::copy(&mut self.open()?, &mut stderr)?;
+            }
+            None => {
+                let mut reader = io::BufReader::new(self.open()?);
+                let mut buf = vec![];
+                while reader.read_until(b'\n', &mut buf)? != 0 {
+                    crate::process::write_tagged(&mut stderr, &buf, tag)?;
+                    buf.clear();
+                }
+            }
+            Some(max_lines) => {
+                let (tail, omitted) = self.tail(max_lines)?;
+                if omitted != 0 {
+                    writeln!(
+                        stderr,
+                        "... ({omitted} {} omitted)",
+                        if omitted == 1 { "line" } else { "lines" }
+                    )?;
+                }
+                for line in tail {
+                    crate::process::write_tagged(&mut stderr, format!("{line}\n").as_bytes(), tag)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the last `max_lines` lines of the captured output, and the
+    /// number of the other lines.
+    pub(crate) fn tail(&self, max_lines: usize) -> Result<(VecDeque<String>, usize)> {
+        let mut reader = io::BufReader::new(self.open()?);
+        let mut tail = VecDeque::with_capacity(max_lines.min(1024));
+        let mut omitted = 0;
+        let mut buf = vec![];
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            if max_lines == 0 {
+                omitted += 1;
+                continue;
+            }
+            if tail.len() == max_lines {
+                tail.pop_front();
+                omitted += 1;
+            }
+            tail.push_back(String::from_utf8_lossy(&buf).trim_end().to_owned());
+        }
+        Ok((tail, omitted))
+    }
+
+    fn open(&self) -> Result<File> {
+        File::open(&self.path)
+            .with_context(|| format!("failed to read from file `{}`", self.path.display()))
+    }
+}
+
+impl Drop for CapturedOutput {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of placeholders in the arguments propagated to cargo.
+//
+// `{toolchain}`, `{package}`, and `{features}` in the arguments of the
+// subcommand and the arguments after `--` are replaced per run with the
+// toolchain of the run (e.g., `1.74`), the name of the package, and the
+// comma-separated list of the features passed by --features, so that the
+// programs run by cargo (e.g., tests) can know them without detecting them at
+// runtime. Without --version-range (or --rust-version), the toolchain is the
+// minor version of the cargo in use (e.g., `1.80`).
+//
+// Braces in an argument that contains a placeholder can be escaped by
+// doubling them (`{{` and `}}`). Arguments that contain no placeholder are
+// passed as is, so that existing arguments that contain braces keep working.
+
+use crate::{context::Context, metadata::PackageId, ProcessBuilder};
+
+const NAMES: [&str; 3] = ["toolchain", "package", "features"];
+
+/// Replaces the placeholders in the arguments propagated to `line`.
+pub(crate) fn apply(
+    cx: &Context,
+    id: &PackageId,
+    toolchain: Option<&str>,
+    line: &mut ProcessBuilder<'_>,
+) {
+    if !line.propagated_args().any(has_placeholder) {
+        return;
+    }
+    let toolchain = toolchain.map_or_else(|| format!("1.{}", cx.cargo_version), str::to_owned);
+    let features = line.features().collect::<Vec<_>>().join(",");
+    let values = [toolchain.as_str(), cx.packages(id).name.as_str(), features.as_str()];
+    line.map_propagated_args(|arg| has_placeholder(arg).then(|| substitute(arg, &values)));
+}
+
+fn has_placeholder(arg: &str) -> bool {
+    NAMES.iter().any(|name| arg.contains(&format!("{{{name}}}")))
+}
+
+/// Replaces the placeholders in `arg` with `values`, in the order of `NAMES`.
+fn substitute(arg: &str, values: &[&str; 3]) -> String {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            out.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = NAMES.iter().zip(values).find(|(name, _)| {
+            rest[1..].strip_prefix(**name).is_some_and(|after| after.starts_with('}'))
+        });
+        if let Some((name, value)) = placeholder {
+            out.push_str(value);
+            rest = &rest[name.len() + 2..];
+        } else {
+            out.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_placeholder, substitute};
+
+    #[test]
+    fn substitution() {
+        let values = ["1.74", "member1", "a,b"];
+        assert_eq!(substitute("--test-arg-{toolchain}", &values), "--test-arg-1.74");
+        assert_eq!(substitute("{package}:{features}", &values), "member1:a,b");
+        assert_eq!(substitute("{{toolchain}}={toolchain}", &values), "{toolchain}=1.74");
+        assert_eq!(substitute("{{{package}}}", &values), "{member1}");
+        // Unknown placeholders and unpaired braces are kept.
+        assert_eq!(substitute("{x}{toolchain}{", &values), "{x}1.74{");
+
+        assert!(has_placeholder("--x={features}"));
+        assert!(!has_placeholder("{}"));
+        assert!(!has_placeholder("{{x}}"));
+    }
+}
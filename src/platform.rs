@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Evaluation of the platform of target-specific dependencies
+// (`[target.'<platform>'.dependencies]`).
+//
+// Only target triples and a subset of cfgs that can be determined from the
+// target triple (`windows`, `unix`, `target_family`, and `target_os`) are
+// supported. Unlike cargo, this does not run rustc to get the cfgs of the
+// target, so other cfgs (e.g., `target_pointer_width`) cannot be evaluated.
+
+/// Returns whether a dependency with the given platform is used when building
+/// for `target`, or `None` if it cannot be determined.
+pub(crate) fn matches(platform: &str, target: &str) -> Option<bool> {
+    let Some(cfg) = platform.strip_prefix("cfg(") else {
+        return Some(platform == target);
+    };
+    let mut parser = Parser { s: cfg.strip_suffix(')')? };
+    let res = parser.expr(target)?;
+    parser.skip_whitespace();
+    if parser.s.is_empty() {
+        res
+    } else {
+        None
+    }
+}
+
+/// A parser of cfg expressions.
+///
+/// Each method returns `None` if the input cannot be parsed, and `Some(None)`
+/// if the expression is valid but cannot be evaluated.
+struct Parser<'a> {
+    s: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    #[allow(clippy::option_option)]
+    fn expr(&mut self, target: &str) -> Option<Option<bool>> {
+        let name = self.ident()?;
+        self.skip_whitespace();
+        if let Some(s) = self.s.strip_prefix('(') {
+            self.s = s;
+            let mut values = vec![];
+            loop {
+                self.skip_whitespace();
+                if let Some(s) = self.s.strip_prefix(')') {
+                    self.s = s;
+                    break;
+                }
+                values.push(self.expr(target)?);
+                self.skip_whitespace();
+                if let Some(s) = self.s.strip_prefix(',') {
+                    self.s = s;
+                } else if !self.s.starts_with(')') {
+                    return None;
+                }
+            }
+            return match name {
+                "not" if values.len() == 1 => Some(values[0].map(|v| !v)),
+                "all" => Some(if values.contains(&Some(false)) {
+                    Some(false)
+                } else if values.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }),
+                "any" => Some(if values.contains(&Some(true)) {
+                    Some(true)
+                } else if values.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }),
+                _ => None,
+            };
+        }
+        if let Some(s) = self.s.strip_prefix('=') {
+            self.s = s;
+            self.skip_whitespace();
+            let value = self.s.strip_prefix('"')?;
+            let end = value.find('"')?;
+            self.s = &value[end + 1..];
+            let value = &value[..end];
+            return Some(match name {
+                "target_family" => family(target).map(|family| family == value),
+                "target_os" => os(target).map(|os| os == value),
+                _ => None,
+            });
+        }
+        Some(match name {
+            "windows" | "unix" => family(target).map(|family| family == name),
+            _ => None,
+        })
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_whitespace();
+        let end =
+            self.s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(self.s.len());
+        if end == 0 {
+            return None;
+        }
+        let (ident, rest) = self.s.split_at(end);
+        self.s = rest;
+        Some(ident)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.s = self.s.trim_start();
+    }
+}
+
+/// Returns the `target_os` of the target triple, if known.
+fn os(target: &str) -> Option<&'static str> {
+    const OS: &[(&str, &str)] = &[
+        ("-windows", "windows"),
+        ("-apple-darwin", "macos"),
+        ("-apple-ios", "ios"),
+        ("-linux-android", "android"),
+        ("-linux", "linux"),
+        ("-freebsd", "freebsd"),
+        ("-netbsd", "netbsd"),
+        ("-openbsd", "openbsd"),
+        ("-dragonfly", "dragonfly"),
+        ("-illumos", "illumos"),
+        ("-solaris", "solaris"),
+    ];
+    OS.iter().find(|(pat, _)| target.contains(pat)).map(|&(_, os)| os)
+}
+
+/// Returns the `target_family` of the target triple, if known.
+fn family(target: &str) -> Option<&'static str> {
+    match os(target)? {
+        "windows" => Some("windows"),
+        _ => Some("unix"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn platform_matches() {
+        let linux = "x86_64-unknown-linux-gnu";
+        let windows = "x86_64-pc-windows-msvc";
+        let macos = "aarch64-apple-darwin";
+        let wasm = "wasm32-unknown-unknown";
+
+        assert_eq!(matches(windows, windows), Some(true));
+        assert_eq!(matches(windows, linux), Some(false));
+        assert_eq!(matches("cfg(windows)", windows), Some(true));
+        assert_eq!(matches("cfg(windows)", linux), Some(false));
+        assert_eq!(matches("cfg(unix)", macos), Some(true));
+        assert_eq!(matches("cfg(unix)", windows), Some(false));
+        assert_eq!(matches("cfg(not(windows))", linux), Some(true));
+        assert_eq!(matches("cfg(target_os = \"macos\")", macos), Some(true));
+        assert_eq!(matches("cfg(target_os = \"linux\")", macos), Some(false));
+        assert_eq!(matches("cfg(target_family=\"windows\")", windows), Some(true));
+        assert_eq!(matches("cfg(any(windows, target_os = \"macos\"))", macos), Some(true));
+        assert_eq!(matches("cfg(all(unix, not(target_os = \"macos\")))", linux), Some(true));
+
+        // Cannot be evaluated.
+        assert_eq!(matches("cfg(windows)", wasm), None);
+        assert_eq!(matches("cfg(target_pointer_width = \"64\")", linux), None);
+        assert_eq!(matches("cfg(all(unix, target_pointer_width = \"64\"))", linux), None);
+        // ...but the result is known regardless of the unknown part.
+        assert_eq!(matches("cfg(all(windows, target_pointer_width = \"64\"))", linux), Some(false));
+        assert_eq!(matches("cfg(any(unix, target_pointer_width = \"64\"))", linux), Some(true));
+        // Invalid.
+        assert_eq!(matches("cfg(windows", linux), None);
+        assert_eq!(matches("cfg(all(windows)", linux), None);
+        assert_eq!(matches("cfg(windows unix)", linux), None);
+    }
+}
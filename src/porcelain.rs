@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --porcelain.
+//
+// The records of the runs are written to stdout in a stable, line-oriented
+// format for tools that run cargo-hack, and everything else (the log of
+// cargo-hack and the output of cargo) is written to stderr. The first line is
+// `porcelain-version <VERSION>`, and each of the following lines is a record
+// with tab-separated fields:
+//
+//   RUN   <id>  <package>  <features>  <index>  <total>
+//   OK    <id>  <package>  <features>  <index>  <total>
+//   FAIL  <id>  <package>  <features>  <index>  <total>  <exit code>
+//   SKIP  <id>  <package>  <features>  <index>  <total>  <reason>
+//
+// <id> is the id of the run used by --only-run. <features> is the feature flags of the run (e.g., `--no-default-features
+// --features a`, or `default features`). <exit code> is `-` if the command
+// did not exit with a code (e.g., it was terminated by a signal). <reason> is
+// `partition` (--partition), `only-run` (--only-run), or `budget` (--budget).
+//
+// Changes that break parsers of this format must increment VERSION.
+
+use crate::{process::ProcessError, summary};
+
+/// The version of the format.
+const VERSION: u32 = 2;
+
+/// Prints the first line of the output.
+pub(crate) fn header() {
+    println!("porcelain-version {VERSION}");
+}
+
+/// Prints the record of a run that is started.
+pub(crate) fn start(id: &str, package: &str, features: &str, index: usize, total: usize) {
+    println!("{}", record("RUN", id, package, features, index, total, None));
+}
+
+/// Prints the record of the result of a run.
+pub(crate) fn finish(
+    run: &summary::Run,
+    error: Option<&anyhow::Error>,
+    skip_reason: &str,
+    index: usize,
+    total: usize,
+) {
+    let (kind, last) = match run.status {
+        summary::Status::Passed => ("OK", None),
+        summary::Status::Failed => {
+            let code = error.and_then(|e| e.downcast_ref::<ProcessError>()).and_then(|e| e.code);
+            ("FAIL", Some(code.map_or_else(|| "-".to_owned(), |code| code.to_string())))
+        }
+        summary::Status::Skipped => ("SKIP", Some(skip_reason.to_owned())),
+        summary::Status::NotRun => ("SKIP", Some("budget".to_owned())),
+    };
+    let last = last.as_deref();
+    println!("{}", record(kind, &run.id, &run.package, &run.features, index, total, last));
+}
+
+fn record(
+    kind: &str,
+    id: &str,
+    package: &str,
+    features: &str,
+    index: usize,
+    total: usize,
+    last: Option<&str>,
+) -> String {
+    let mut fields = vec![kind.to_owned(), id.to_owned(), package.to_owned(), features.to_owned()];
+    fields.extend([index.to_string(), total.to_string()]);
+    fields.extend(last.map(str::to_owned));
+    // Package names and feature flags never contain tabs or newlines, but make
+    // sure that a record is always a single line with the expected fields.
+    for field in &mut fields {
+        if field.contains(['\t', '\n', '\r']) {
+            *field = field.replace(['\t', '\n', '\r'], " ");
+        }
+    }
+    fields.join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::record;
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            record("RUN", "0123abcd", "a", "--no-default-features --features x", 1, 3, None),
+            "RUN\t0123abcd\ta\t--no-default-features --features x\t1\t3"
+        );
+        assert_eq!(
+            record("FAIL", "0123abcd", "a", "default features", 2, 3, Some("101")),
+            ["FAIL", "0123abcd", "a", "default features", "2", "3", "101"].join("\t")
+        );
+        assert_eq!(
+            record("SKIP", "0123abcd", "a\tb", "x\ny", 3, 3, Some("budget")),
+            "SKIP\t0123abcd\ta b\tx y\t3\t3\tbudget"
+        );
+    }
+}
@@ -1,18 +1,28 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
     env,
     ffi::{OsStr, OsString},
-    fmt,
-    path::Path,
-    process::{Command, ExitStatus, Output},
+    fmt, fs,
+    io::{self, BufRead as _, Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
     rc::Rc,
     str,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
 };
 
 use anyhow::{Context as _, Error, Result};
 
-use crate::{term, Context, PackageId};
+use crate::{
+    chain,
+    features::{self, Origin},
+    skip::SkipKind,
+    term, Context, PackageId,
+};
 
 macro_rules! cmd {
     ($program:expr $(, $arg:expr)* $(,)?) => {{
@@ -27,25 +37,32 @@ macro_rules! cmd {
 // A builder for an external process, inspired by https://github.com/rust-lang/cargo/blob/0.47.0/src/cargo/util/process_builder.rs
 //
 // The fields will be expanded in the following order:
-//   <program> <leading_args> <propagated_leading_args> <arg> [--features <features>] [ -- <propagated_trailing_args> ]
+//   [<env>] <program> <leading_args> <propagated_leading_args> <arg> [--features <features>] [ -- <propagated_trailing_args> ]
+//
+// If `current_dir` is set, the process will be executed in that directory.
 #[derive(Clone)]
 #[must_use]
 pub(crate) struct ProcessBuilder<'a> {
     /// The program to execute.
     program: Rc<OsStr>,
     /// A list of arguments to pass to the program (until '--').
-    propagated_leading_args: &'a [String],
+    propagated_leading_args: Cow<'a, [String]>,
     /// A list of arguments to pass to the program (after '--').
-    trailing_args: &'a [String],
+    trailing_args: Cow<'a, [String]>,
 
     /// A list of arguments to pass to the program (between `program` and 'propagated_leading_args').
     leading_args: Vec<String>,
     /// A list of arguments to pass to the program (between `propagated_leading_args` and '--').
     args: Vec<OsString>,
-    /// A comma-separated list of features.
-    /// This list always has a trailing comma if it is not empty.
-    // cargo less than Rust 1.38 cannot handle multiple '--features' flags, so it creates another String.
-    features: String,
+    /// A list of features with their origins, passed as a comma-separated list.
+    // cargo less than Rust 1.38 cannot handle multiple '--features' flags, so they are joined into a single flag.
+    features: Vec<(String, Origin)>,
+    /// The working directory of the process.
+    current_dir: Option<PathBuf>,
+    /// Environment variables to set for the process.
+    env: BTreeMap<String, String>,
+    /// A list of cfgs passed via rustflags in `env`.
+    cfgs: Vec<String>,
     pub(crate) strip_program_path: bool,
 }
 
@@ -54,11 +71,14 @@ impl<'a> ProcessBuilder<'a> {
     pub(crate) fn new(program: impl Into<OsString>) -> Self {
         Self {
             program: program.into().into(),
-            propagated_leading_args: &[],
-            trailing_args: &[],
+            propagated_leading_args: Cow::Borrowed(&[]),
+            trailing_args: Cow::Borrowed(&[]),
             leading_args: vec![],
             args: vec![],
-            features: String::new(),
+            features: vec![],
+            current_dir: None,
+            env: BTreeMap::new(),
+            cfgs: vec![],
             strip_program_path: false,
         }
     }
@@ -78,53 +98,340 @@ impl<'a> ProcessBuilder<'a> {
         self
     }
 
+    /// Removes the arguments equal to `arg` added by [`Self::arg`].
+    pub(crate) fn remove_arg(&mut self, arg: &str) {
+        self.args.retain(|a| a != arg);
+    }
+
+    /// Removes an argument propagated to the program before '--'.
+    pub(crate) fn remove_propagated_arg(&mut self, arg: &str) {
+        self.propagated_leading_args.to_mut().retain(|a| a != arg);
+    }
+
     /// Adds an argument to the leading arguments list.
     pub(crate) fn leading_arg(&mut self, arg: impl Into<String>) -> &mut Self {
         self.leading_args.push(arg.into());
         self
     }
 
+    /// Sets the working directory of the process.
+    pub(crate) fn current_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets an environment variable for the process.
+    pub(crate) fn env(&mut self, key: impl Into<String>, val: impl Into<String>) -> &mut Self {
+        self.env.insert(key.into(), val.into());
+        self
+    }
+
+    /// Appends `--cfg <cfg>` for each of `cfgs` to the rustflags in the environment.
+    pub(crate) fn append_cfgs(&mut self, cfgs: &[String]) {
+        if cfgs.is_empty() {
+            return;
+        }
+        self.cfgs.extend_from_slice(cfgs);
+        let flags: Vec<_> = cfgs.iter().flat_map(|cfg| ["--cfg", cfg]).collect();
+        self.append_encoded_flags("RUSTFLAGS", &flags);
+    }
+
+    /// Appends `flags` to the flags passed to rustc.
+    pub(crate) fn append_rustflags(&mut self, flags: &[&str]) {
+        self.append_encoded_flags("RUSTFLAGS", flags);
+    }
+
+    /// Appends `flags` to the flags passed to rustdoc.
+    pub(crate) fn append_rustdocflags(&mut self, flags: &[&str]) {
+        self.append_encoded_flags("RUSTDOCFLAGS", flags);
+    }
+
+    fn append_encoded_flags(&mut self, name: &str, new_flags: &[&str]) {
+        // CARGO_ENCODED_<name> takes precedence over <name> if set.
+        let encoded = format!("CARGO_ENCODED_{name}");
+        let (key, sep) = if env::var_os(&encoded).is_some() {
+            (encoded, '\x1f')
+        } else {
+            (name.to_owned(), ' ')
+        };
+        let mut flags = match self.env.get(&key) {
+            Some(flags) => flags.clone(),
+            None => env::var(&key).unwrap_or_default(),
+        };
+        for flag in new_flags {
+            if !flags.is_empty() {
+                flags.push(sep);
+            }
+            flags.push_str(flag);
+        }
+        self.env(key, flags);
+    }
+
+    /// Gets the cfgs passed via rustflags.
+    pub(crate) fn cfgs(&self) -> &[String] {
+        &self.cfgs
+    }
+
     pub(crate) fn apply_context(&mut self, cx: &'a Context) -> &mut Self {
-        self.propagated_leading_args = &cx.leading_args;
-        self.trailing_args = &cx.trailing_args;
+        self.propagated_leading_args = Cow::Borrowed(&cx.leading_args);
+        self.trailing_args = Cow::Borrowed(&cx.trailing_args);
+        self
+    }
+
+    /// Replaces the subcommand and its args with those of a command chained by
+    /// --and-then.
+    pub(crate) fn apply_chained(&mut self, command: &'a chain::Command) -> &mut Self {
+        self.propagated_leading_args = Cow::Borrowed(&command.leading_args);
+        self.trailing_args = Cow::Borrowed(&command.trailing_args);
         self
     }
 
-    pub(crate) fn append_features(&mut self, features: impl IntoIterator<Item = impl AsRef<str>>) {
+    /// Gets the arguments propagated to the program, before and after '--'.
+    pub(crate) fn propagated_args(&self) -> impl Iterator<Item = &str> {
+        self.propagated_leading_args.iter().chain(&*self.trailing_args).map(String::as_str)
+    }
+
+    /// Replaces each argument propagated to the program with the result of
+    /// `f`, or keeps it as is if `f` returns `None`.
+    pub(crate) fn map_propagated_args(&mut self, f: impl Fn(&str) -> Option<String>) {
+        for args in [&mut self.propagated_leading_args, &mut self.trailing_args] {
+            let mapped: Vec<_> = args.iter().map(|arg| f(arg)).collect();
+            if mapped.iter().any(Option::is_some) {
+                let new =
+                    args.iter().zip(mapped).map(|(arg, new)| new.unwrap_or_else(|| arg.clone()));
+                *args = Cow::Owned(new.collect());
+            }
+        }
+    }
+
+    pub(crate) fn append_features(
+        &mut self,
+        features: impl IntoIterator<Item = impl AsRef<str>>,
+        origin: &Origin,
+    ) {
         for feature in features {
-            self.features.push_str(feature.as_ref());
-            self.features.push(',');
+            self.append_feature(feature, origin.clone());
         }
     }
 
+    pub(crate) fn append_feature(&mut self, feature: impl AsRef<str>, origin: Origin) {
+        self.features.push((feature.as_ref().to_owned(), origin));
+    }
+
     pub(crate) fn append_features_from_args(&mut self, cx: &Context, id: &PackageId) {
         if cx.ignore_unknown_features {
-            self.append_features(cx.features.iter().filter(|&f| {
-                if cx.pkg_features(id).contains(f) {
-                    true
-                } else {
-                    // ignored
-                    info!("skipped applying unknown `{f}` feature to {}", cx.packages(id).name);
-                    false
-                }
-            }));
+            self.append_features(
+                cx.features.iter().filter(|&f| {
+                    if cx.pkg_features(id).contains(f) {
+                        true
+                    } else {
+                        // ignored
+                        let name = &cx.packages(id).name;
+                        info!("skipped applying unknown `{f}` feature to {name}");
+                        cx.skips.record(SkipKind::UnknownFeature, name, format!("feature `{f}`"));
+                        false
+                    }
+                }),
+                &Origin::UserFeatures,
+            );
         } else if !cx.features.is_empty() {
-            self.append_features(&cx.features);
+            self.append_features(&cx.features, &Origin::UserFeatures);
         }
     }
 
     /// Gets the comma-separated features list
-    fn get_features(&self) -> &str {
-        // drop a trailing comma if it is not empty.
-        &self.features[..self.features.len().saturating_sub(1)]
+    fn get_features(&self) -> String {
+        self.features.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>().join(",")
+    }
+
+    /// Gets the list of features passed by `--features`.
+    pub(crate) fn features(&self) -> impl Iterator<Item = &str> {
+        self.features.iter().map(|(f, _)| f.as_str())
+    }
+
+    /// Gets the features passed by `--features` with their origins, such as
+    /// `c [iterated], a,b [group a,b]`.
+    ///
+    /// Consecutive features with the same origin are grouped together.
+    pub(crate) fn feature_origins(&self) -> String {
+        let mut out: Vec<(Vec<Cow<'_, str>>, &Origin)> = vec![];
+        for (feature, origin) in &self.features {
+            let feature = features::quote(feature);
+            match out.last_mut() {
+                Some((features, last)) if *last == origin => features.push(feature),
+                _ => out.push((vec![feature], origin)),
+            }
+        }
+        out.iter()
+            .map(|(features, origin)| format!("{} [{origin}]", features.join(",")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns `true` if `arg` is passed to the program before '--'.
+    pub(crate) fn has_arg(&self, arg: &str) -> bool {
+        self.propagated_leading_args.iter().any(|a| a == arg) || self.args.iter().any(|a| a == arg)
+    }
+
+    /// Returns the value of the first `<flag> <value>` (or `<flag>=<value>`)
+    /// passed to the program before '--'.
+    pub(crate) fn arg_value(&self, flag: &str) -> Option<&str> {
+        let mut args = self
+            .propagated_leading_args
+            .iter()
+            .map(String::as_str)
+            .chain(self.args.iter().filter_map(|a| a.to_str()));
+        while let Some(arg) = args.next() {
+            if arg == flag {
+                return args.next();
+            }
+            if let Some(value) = arg.strip_prefix(flag).and_then(|v| v.strip_prefix('=')) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Gets the feature-related flags, such as `--no-default-features --features a,b`.
+    pub(crate) fn feature_flags(&self) -> String {
+        let mut flags = vec![];
+        for arg in ["--no-default-features", "--all-features"] {
+            if self.has_arg(arg) {
+                flags.push(arg.to_owned());
+            }
+        }
+        if !self.features.is_empty() {
+            flags.push(format!("--features {}", self.get_features()));
+        }
+        if flags.is_empty() {
+            "default features".to_owned()
+        } else {
+            flags.join(" ")
+        }
     }
 
     /// Executes a process, waiting for completion, and mapping non-zero exit
     /// status to an error.
     pub(crate) fn run(&self) -> Result<()> {
-        let status = self.build().status().with_context(|| {
+        let status = self.build().status();
+        self.trace(status.as_ref().ok());
+        let status = status.with_context(|| {
+            process_error(format!("could not execute process {self:#}"), None, None)
+        })?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(process_error(
+                format!("process didn't exit successfully: {self:#}"),
+                Some(status),
+                None,
+            ))
+        }
+    }
+
+    /// Executes a process, waiting for completion, and mapping non-zero exit
+    /// status to an error.
+    ///
+    /// Unlike [`run`](Self::run), this can capture the output of the process:
+    /// - If `stdout` is `Some`, stdout of the process is stored in it instead of
+    ///   being printed.
+    /// - If `stderr_tail` is `Some((max_lines, tail))`, stderr of the process is
+    ///   forwarded to the stderr of cargo-hack line by line, and the last
+    ///   `max_lines` lines are stored in `tail`.
+    /// - If `open_line` is `Some`, the caller has printed a line to stderr
+    ///   without a trailing newline. The output of the process is forwarded line
+    ///   by line, and the line is terminated before the first output; the flag
+    ///   is set to `false` at that point.
+    /// - If `tag` is `Some`, each line of the output of the process is
+    ///   forwarded with the `[tag] ` prefix (see [`write_tagged`]).
+    pub(crate) fn run_with_capture(
+        &self,
+        stdout: Option<&mut Vec<u8>>,
+        stderr_tail: Option<(usize, &mut VecDeque<String>)>,
+        open_line: Option<&AtomicBool>,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        let mut cmd = self.build();
+        if stdout.is_some() || open_line.is_some() || tag.is_some() {
+            cmd.stdout(Stdio::piped());
+        }
+        if stderr_tail.is_some() || open_line.is_some() || tag.is_some() {
+            cmd.stderr(Stdio::piped());
+        }
+        if tag.is_some() {
+            // The progress bar moves the cursor within a line.
+            cmd.env("CARGO_TERM_PROGRESS_WHEN", "never");
+        }
+        let mut child = cmd.spawn().with_context(|| {
+            self.trace(None);
+            process_error(format!("could not execute process {self:#}"), None, None)
+        })?;
+        let child_stdout = child.stdout.take();
+        let child_stderr = child.stderr.take();
+        let close_line = move || {
+            if open_line.is_some_and(|open| open.swap(false, Ordering::Relaxed)) {
+                let _ = io::stderr().write_all(b"\n");
+            }
+        };
+        thread::scope(|s| -> io::Result<()> {
+            // Read stdout in another thread to avoid deadlock when both are piped.
+            let stdout = match (stdout, child_stdout) {
+                (Some(buf), Some(mut child_stdout)) => {
+                    Some(s.spawn(move || child_stdout.read_to_end(buf).map(drop)))
+                }
+                (None, Some(child_stdout)) => Some(
+                    s.spawn(move || forward(child_stdout, io::stdout(), close_line, None, tag)),
+                ),
+                _ => None,
+            };
+            if let Some(child_stderr) = child_stderr {
+                forward(child_stderr, io::stderr(), close_line, stderr_tail, tag)?;
+            }
+            if let Some(stdout) = stdout {
+                stdout.join().unwrap()?;
+            }
+            Ok(())
+        })?;
+        let status = child.wait().with_context(|| {
             process_error(format!("could not execute process {self:#}"), None, None)
         })?;
+        self.trace(Some(&status));
+        if status.success() {
+            Ok(())
+        } else {
+            Err(process_error(
+                format!("process didn't exit successfully: {self:#}"),
+                Some(status),
+                None,
+            ))
+        }
+    }
+
+    /// Executes a process, waiting for completion, and mapping non-zero exit
+    /// status to an error.
+    ///
+    /// Unlike [`run`](Self::run), the output of the process is written to `log`
+    /// instead of being printed. If `stdout` is `Some`, stdout of the process is
+    /// stored in it instead of being written to `log`.
+    pub(crate) fn run_with_log(&self, stdout: Option<&mut Vec<u8>>, log: &fs::File) -> Result<()> {
+        let mut cmd = self.build();
+        let log_error = || process_error(format!("could not execute process {self:#}"), None, None);
+        cmd.stderr(log.try_clone().with_context(log_error)?);
+        if stdout.is_some() {
+            cmd.stdout(Stdio::piped());
+        } else {
+            cmd.stdout(log.try_clone().with_context(log_error)?);
+        }
+        let mut child = cmd.spawn().with_context(|| {
+            self.trace(None);
+            log_error()
+        })?;
+        // stderr is not piped, so reading stdout to the end cannot deadlock.
+        if let (Some(buf), Some(mut child_stdout)) = (stdout, child.stdout.take()) {
+            child_stdout.read_to_end(buf)?;
+        }
+        let status = child.wait().with_context(log_error)?;
+        self.trace(Some(&status));
         if status.success() {
             Ok(())
         } else {
@@ -139,7 +446,9 @@ impl<'a> ProcessBuilder<'a> {
     /// Executes a process, captures its stdio output, returning the captured
     /// output, or an error if non-zero exit status.
     pub(crate) fn run_with_output(&self) -> Result<Output> {
-        let output = self.build().output().with_context(|| {
+        let output = self.build().output();
+        self.trace(output.as_ref().ok().map(|output| &output.status));
+        let output = output.with_context(|| {
             process_error(format!("could not execute process {self:#}"), None, None)
         })?;
         if output.status.success() {
@@ -164,11 +473,32 @@ impl<'a> ProcessBuilder<'a> {
         Ok(output)
     }
 
-    fn build(&self) -> Command {
+    /// Writes the command and its exit status (`None` if it could not be
+    /// executed) to the trace file of --trace-file.
+    fn trace(&self, status: Option<&ExitStatus>) {
+        if !crate::trace::enabled() {
+            return;
+        }
+        // The environment variables may contain secrets (e.g., in RUSTFLAGS).
+        let mut cmd = self.clone();
+        for val in cmd.env.values_mut() {
+            "<redacted>".clone_into(val);
+        }
+        match status {
+            Some(status) => trace!("command", "{cmd:#} exited with {status}"),
+            None => trace!("command", "{cmd:#} could not be executed"),
+        }
+    }
+
+    pub(crate) fn build(&self) -> Command {
         let mut cmd = Command::new(&*self.program);
 
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd.envs(&self.env);
         cmd.args(&*self.leading_args);
-        cmd.args(self.propagated_leading_args);
+        cmd.args(&*self.propagated_leading_args);
         cmd.args(&self.args);
         if !self.features.is_empty() {
             cmd.arg("--features");
@@ -176,7 +506,7 @@ impl<'a> ProcessBuilder<'a> {
         }
         if !self.trailing_args.is_empty() {
             cmd.arg("--");
-            cmd.args(self.trailing_args);
+            cmd.args(&*self.trailing_args);
         }
 
         cmd
@@ -187,6 +517,10 @@ impl fmt::Display for ProcessBuilder<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("`")?;
 
+        for (key, val) in &self.env {
+            write!(f, "{key}=\"{}\" ", val.escape_debug())?;
+        }
+
         if !self.strip_program_path && (f.alternate() || term::verbose()) {
             f.write_str(&self.program.to_string_lossy())?;
         } else {
@@ -197,7 +531,7 @@ impl fmt::Display for ProcessBuilder<'_> {
             write!(f, " {arg}")?;
         }
 
-        for arg in self.propagated_leading_args {
+        for arg in &*self.propagated_leading_args {
             write!(f, " {arg}")?;
         }
 
@@ -224,13 +558,102 @@ impl fmt::Display for ProcessBuilder<'_> {
 
         if !self.trailing_args.is_empty() {
             f.write_str(" --")?;
-            for arg in self.trailing_args {
+            for arg in &*self.trailing_args {
                 write!(f, " {arg}")?;
             }
         }
 
-        f.write_str("`")
+        f.write_str("`")?;
+
+        if let Some(dir) = &self.current_dir {
+            // Like `--manifest-path`, the working directory is only displayed in verbose mode.
+            if f.alternate() || term::verbose() {
+                let dir = env::current_dir()
+                    .ok()
+                    .and_then(|cwd| dir.strip_prefix(cwd).ok())
+                    .unwrap_or(dir);
+                if dir.as_os_str().is_empty() {
+                    f.write_str(" (in .)")?;
+                } else {
+                    write!(f, " (in {})", dir.display())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The error of a process that did not exit successfully.
+#[derive(Debug)]
+pub(crate) struct ProcessError {
+    msg: String,
+    /// The exit code of the process. `None` if the process was never executed
+    /// or terminated by a signal.
+    pub(crate) code: Option<i32>,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// Forwards the output of a process to `writer` line by line, calling
+/// `before_output` before each line, and stores the last `max_lines` lines in
+/// `tail` if `tail` is `Some((max_lines, tail))`. The lines are prefixed with
+/// `tag` if it is `Some` (see [`write_tagged`]).
+fn forward(
+    reader: impl io::Read,
+    mut writer: impl io::Write,
+    before_output: impl Fn(),
+    mut tail: Option<(usize, &mut VecDeque<String>)>,
+    tag: Option<&str>,
+) -> io::Result<()> {
+    let mut reader = io::BufReader::new(reader);
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        before_output();
+        let _ = write_tagged(&mut writer, &buf, tag);
+        if let Some((max_lines, tail)) = &mut tail {
+            if tail.len() == *max_lines {
+                tail.pop_front();
+            }
+            tail.push_back(String::from_utf8_lossy(&buf).trim_end().to_owned());
+        }
+    }
+}
+
+/// Writes `buf` to `writer`, prefixing each line with `[tag] ` if `tag` is
+/// `Some`.
+///
+/// With a tag, each line is written by a single write and terminated with a
+/// newline even if it is the incomplete last line (e.g., when the process was
+/// killed), so that the lines of concurrent writers to the same stream are not
+/// interleaved within a line.
+pub(crate) fn write_tagged(
+    writer: &mut impl io::Write,
+    buf: &[u8],
+    tag: Option<&str>,
+) -> io::Result<()> {
+    let Some(tag) = tag else { return writer.write_all(buf) };
+    let mut line = vec![];
+    for l in buf.split_inclusive(|&b| b == b'\n') {
+        line.clear();
+        write!(line, "[{tag}] ")?;
+        line.extend_from_slice(l);
+        if !l.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        writer.write_all(&line)?;
     }
+    Ok(())
 }
 
 // Based on https://github.com/rust-lang/cargo/blob/0.47.0/src/cargo/util/errors.rs
@@ -265,5 +688,41 @@ fn process_error(mut msg: String, status: Option<ExitStatus>, output: Option<&Ou
         }
     }
 
-    Error::msg(msg)
+    Error::new(ProcessError { msg, code: status.and_then(|s| s.code()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_tagged, ProcessBuilder};
+    use crate::features::Origin;
+
+    #[test]
+    fn feature_origins() {
+        let mut line = ProcessBuilder::new("cargo");
+        line.append_features(["x", "y"], &Origin::UserFeatures);
+        line.append_feature("c", Origin::Iterated);
+        line.append_feature("a", Origin::Group("a,b".to_owned()));
+        line.append_feature("b", Origin::Group("a,b".to_owned()));
+        line.append_feature("d", Origin::OptionalDep);
+        line.append_feature("e/f", Origin::DepFeature);
+        line.append_feature("g", Origin::Iterated);
+        assert_eq!(
+            line.feature_origins(),
+            "x,y [--features], c [iterated], a,b [group a,b], d [optional dependency], \
+             e/f [dependency feature], g [iterated]"
+        );
+        // The command itself is unchanged.
+        assert_eq!(line.feature_flags(), "--features x,y,c,a,b,d,e/f,g");
+        assert_eq!(line.features().collect::<Vec<_>>(), ["x", "y", "c", "a", "b", "d", "e/f", "g"]);
+    }
+
+    #[test]
+    fn tagged() {
+        let mut out = vec![];
+        write_tagged(&mut out, b"a\n\nb\nc", Some("pkg:0123abcd")).unwrap();
+        assert_eq!(out, b"[pkg:0123abcd] a\n[pkg:0123abcd] \n[pkg:0123abcd] b\n[pkg:0123abcd] c\n");
+        out.clear();
+        write_tagged(&mut out, b"a\nb", None).unwrap();
+        assert_eq!(out, b"a\nb");
+    }
 }
@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --report-redundant-packages and --skip-redundant-packages.
+//
+// A package that only re-exports another selected package (e.g., a thin
+// wrapper crate whose src/lib.rs only contains `pub use core_crate::*;`), and
+// whose planned feature combinations are the same as those of that package,
+// runs the same code as it, so the runs on the wrapper are redundant. Chains
+// of wrappers are followed, so only the deepest package of each chain, which
+// is not a wrapper, is kept.
+//
+// Whether a package is a wrapper is determined by a conservative heuristic:
+// its only target is the library, and the source file of the library is short
+// and contains nothing but `pub use` items, attributes, and comments, with at
+// least one of the items re-exporting the dependency. Since this is a
+// heuristic, the redundant packages are only reported by default
+// (--report-redundant-packages), and are skipped by --skip-redundant-packages.
+
+use std::collections::BTreeSet;
+
+use crate::{context::Context, fs, skip::SkipKind, summary, Kind, PackageRuns};
+
+/// The maximum number of lines of the source file of a wrapper.
+const MAX_LINES: usize = 100;
+
+/// A selected package, for finding the redundant packages.
+struct Node<'a> {
+    name: &'a str,
+    /// The planned runs on the package. Packages with the same plan run the
+    /// same feature combinations.
+    plan: String,
+    /// The paths re-exported by the package, or `None` if the package is not a
+    /// wrapper.
+    reexports: Option<Vec<String>>,
+    /// The normal dependencies, as pairs of the package names and the names of
+    /// the crates in the code.
+    deps: Vec<(&'a str, String)>,
+}
+
+/// Reports the redundant packages, and removes them from `packages` with
+/// --skip-redundant-packages.
+pub(crate) fn check<'a>(
+    cx: &Context,
+    packages: Vec<PackageRuns<'a>>,
+    skipped: &mut Vec<summary::SkippedPackage>,
+) -> Vec<PackageRuns<'a>> {
+    let nodes: Vec<_> = packages.iter().map(|pkg| node(cx, pkg)).collect();
+    let redundant = find(&nodes);
+    if redundant.is_empty() {
+        info!("no redundant packages found");
+        return packages;
+    }
+    for &(wrapper, reexported, kept) in &redundant {
+        let via = if reexported == kept {
+            String::new()
+        } else {
+            format!(" (a wrapper of `{}`)", nodes[kept].name)
+        };
+        let reason = format!(
+            "it only re-exports `{}`{via} and has the same feature combinations",
+            nodes[reexported].name
+        );
+        if cx.skip_redundant_packages {
+            info!(
+                "skipped running on package `{}`: {reason} (--skip-redundant-packages)",
+                nodes[wrapper].name
+            );
+            cx.skips.record(SkipKind::ExcludedPackage, nodes[wrapper].name, &reason);
+            skipped.push(summary::SkippedPackage {
+                package: nodes[wrapper].name.to_owned(),
+                reason: format!("{reason} (--skip-redundant-packages)"),
+            });
+        } else {
+            info!(
+                "package `{}` is redundant: {reason}; use --skip-redundant-packages to skip it",
+                nodes[wrapper].name
+            );
+        }
+    }
+    if !cx.skip_redundant_packages {
+        return packages;
+    }
+    let redundant: BTreeSet<_> = redundant.iter().map(|&(wrapper, ..)| wrapper).collect();
+    packages
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !redundant.contains(i))
+        .map(|(_, p)| p)
+        .collect()
+}
+
+fn node<'a>(cx: &'a Context, pkg: &PackageRuns<'_>) -> Node<'a> {
+    let package = cx.packages(pkg.id);
+    let features: Vec<_> = package.features.keys().collect();
+    let kind = match &pkg.kind {
+        Kind::Normal => String::new(),
+        Kind::Each { features } => format!("{features:?}"),
+        Kind::Powerset { features } => format!("{features:?}"),
+    };
+    let plan = format!(
+        "{features:?} {kind} {} {} {} {:?}",
+        pkg.feature_count,
+        pkg.skip_all_features,
+        pkg.skip_no_default_features,
+        cx.rust_version(pkg.id)
+    );
+    let reexports = match &package.targets[..] {
+        [target] if target.kind.iter().all(|k| k == "lib") => {
+            fs::read_to_string(&target.src_path).ok().and_then(|src| reexports(&src))
+        }
+        _ => None,
+    };
+    let deps = package
+        .dependencies
+        .iter()
+        .filter(|dep| dep.kind.is_none())
+        .map(|dep| {
+            // The name of the crate is the name of the library target of the
+            // dependency, which is unknown here, so assume that it is the
+            // package name (unless renamed).
+            let krate = dep.rename.as_deref().unwrap_or(&dep.name).replace('-', "_");
+            (dep.name.as_str(), krate)
+        })
+        .collect();
+    Node { name: &package.name, plan, reexports, deps }
+}
+
+/// Returns the redundant packages, as tuples of the indices of the wrappers,
+/// the packages they re-export, and the packages that are kept instead of
+/// them (the deepest packages of the chains).
+fn find(nodes: &[Node<'_>]) -> Vec<(usize, usize, usize)> {
+    // The package that each wrapper re-exports.
+    let reexported = |i: usize| {
+        let paths = nodes[i].reexports.as_ref()?;
+        nodes[i].deps.iter().find_map(|(name, krate)| {
+            if !paths.iter().any(|path| reexports_crate(path, krate)) {
+                return None;
+            }
+            (0..nodes.len())
+                .find(|&j| j != i && nodes[j].name == *name && nodes[j].plan == nodes[i].plan)
+        })
+    };
+    let mut redundant = vec![];
+    for i in 0..nodes.len() {
+        let Some(direct) = reexported(i) else { continue };
+        let mut kept = direct;
+        // Follow the chain of wrappers to the deepest package.
+        let mut seen = BTreeSet::from([i]);
+        while let Some(next) = reexported(kept) {
+            if !seen.insert(kept) {
+                break;
+            }
+            kept = next;
+        }
+        redundant.push((i, direct, kept));
+    }
+    redundant
+}
+
+/// Returns `true` if `path` of `pub use <path>;` re-exports the crate.
+fn reexports_crate(path: &str, krate: &str) -> bool {
+    path.trim_start_matches("::")
+        .strip_prefix(krate)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::") || rest.starts_with(' '))
+}
+
+/// Returns the paths of the `pub use` items in the source, or `None` if the
+/// source is long or contains anything other than `pub use` items,
+/// attributes, and comments.
+fn reexports(src: &str) -> Option<Vec<String>> {
+    if src.lines().count() > MAX_LINES {
+        return None;
+    }
+    let code = strip_comments(src);
+    let mut paths = vec![];
+    let mut rest = code.trim_start();
+    while !rest.is_empty() {
+        if let Some(attr) = rest.strip_prefix("#!").or_else(|| rest.strip_prefix('#')) {
+            // Skip the attribute.
+            let attr = attr.strip_prefix('[')?;
+            let mut depth = 1;
+            let end = attr.find(|c| {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })?;
+            rest = attr[end + 1..].trim_start();
+            continue;
+        }
+        let (item, next) = rest.split_once(';')?;
+        let item: Vec<_> = item.split_whitespace().collect();
+        match &item[..] {
+            ["pub", "use", path @ ..] if !path.is_empty() => paths.push(path.join(" ")),
+            _ => return None,
+        }
+        rest = next.trim_start();
+    }
+    if paths.is_empty() {
+        None
+    } else {
+        Some(paths)
+    }
+}
+
+/// Removes the comments (including doc comments) from the source.
+fn strip_comments(src: &str) -> String {
+    let mut code = String::with_capacity(src.len());
+    let mut chars = src.chars().peekable();
+    let mut in_str = false;
+    while let Some(c) = chars.next() {
+        if in_str {
+            code.push(c);
+            match c {
+                '\\' => code.extend(chars.next()),
+                '"' => in_str = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_str = true;
+            code.push(c);
+        } else if c == '/' && chars.next_if_eq(&'/').is_some() {
+            while chars.next_if(|&c| c != '\n').is_some() {}
+        } else if c == '/' && chars.next_if_eq(&'*').is_some() {
+            // Block comments can be nested.
+            let mut depth = 1;
+            while depth != 0 {
+                match chars.next() {
+                    Some('/') if chars.next_if_eq(&'*').is_some() => depth += 1,
+                    Some('*') if chars.next_if_eq(&'/').is_some() => depth -= 1,
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            code.push(' ');
+        } else {
+            code.push(c);
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, reexports, Node};
+
+    #[test]
+    fn reexports_heuristic() {
+        assert_eq!(reexports("pub use base::*;\n").unwrap(), ["base::*"]);
+        assert_eq!(
+            reexports(
+                "//! A wrapper of base.\n\
+                 #![no_std]\n\
+                 #![cfg_attr(docsrs, doc(cfg(feature = \"std\")))]\n\
+                 #![doc(html_root_url = \"https://docs.rs/wrapper/0.0.0\")]\n\
+                 /* Re-exports; /* see */\n   base. */\n\
+                 #[doc(inline)]\n\
+                 pub use base::{\n    a,\n    b, // b\n};\n\
+                 pub use ::other as o;\n"
+            )
+            .unwrap(),
+            ["base::{ a, b, }", "::other as o"]
+        );
+        // Not a wrapper.
+        for src in [
+            "",
+            "#![no_std]\n",
+            "pub use base::*;\npub fn f() {}\n",
+            "pub use base::*;\nmod tests;\n",
+            "pub use base::*;\n#[cfg(test)]\nmod tests {}\n",
+            "use base::*;\n",
+            "pub extern crate base;\n",
+            "pub use base::*",
+            "#![no_std\npub use base::*;\n",
+        ] {
+            assert_eq!(reexports(src), None, "{src:?}");
+        }
+        assert_eq!(reexports(&"pub use base::*;\n".repeat(super::MAX_LINES + 1)), None);
+    }
+
+    #[test]
+    fn redundant() {
+        let node = |name, plan: &str, reexports: Option<&[&str]>, deps: &[&'static str]| Node {
+            name,
+            plan: plan.to_owned(),
+            reexports: reexports.map(|r| r.iter().map(|&s| s.to_owned()).collect()),
+            deps: deps.iter().map(|&d| (d, d.replace('-', "_"))).collect(),
+        };
+        let nodes = [
+            node("wrapper2", "std", Some(&["wrapper::*"]), &["wrapper"]),
+            node("wrapper", "std", Some(&["base::*"]), &["base"]),
+            node("base", "std", None, &[]),
+            // Depends on base, but re-exports another package.
+            node("other", "std", Some(&["other_dep::*"]), &["base", "other-dep"]),
+            node("other-dep", "std", None, &[]),
+            // Different feature combinations.
+            node("alloc-wrapper", "alloc", Some(&["base"]), &["base"]),
+            // Re-exports a package that is not selected.
+            node("ext-wrapper", "std", Some(&["ext::*"]), &["ext"]),
+        ];
+        assert_eq!(find(&nodes), [(0, 1, 2), (1, 2, 2), (3, 4, 4)]);
+        assert_eq!(find(&nodes[2..3]), []);
+    }
+}
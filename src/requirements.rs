@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --print-toolchain-requirements and --no-install.
+//
+// The requirements of the runs are the toolchains they use, and the targets
+// and the components to install on each of them: the targets of --target (or
+// those from the cargo config), and the components that the subcommand needs
+// (e.g., clippy for `cargo clippy`). Without --version-range, the runs use the
+// toolchain cargo-hack is run with, whose name is unknown (`null` in the JSON).
+//
+// --print-toolchain-requirements plans the runs without side effects as with
+// --print-command-list, and prints the requirements instead of the commands,
+// so that the runner images of air-gapped CI can be provisioned ahead of time:
+//
+// {"version":1,"toolchains":[{"toolchain":"1.70","targets":[],"components":["clippy"]}]}
+//
+// --no-install checks the requirements against `rustup toolchain list` and
+// `rustup {target,component} list --installed` before the runs, and fails with
+// the missing ones instead of installing them.
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, Result};
+use serde_json::json;
+
+use crate::{context::Context, rustup, targets};
+
+/// The version of the format of the output of --print-toolchain-requirements.
+const VERSION: u32 = 1;
+
+/// The components that the subcommands need.
+const COMPONENTS: &[(&str, &[&str])] =
+    &[("clippy", &["clippy"]), ("fmt", &["rustfmt"]), ("miri", &["miri", "rust-src"])];
+
+/// A toolchain that the runs use, with the targets and the components to
+/// install on it.
+pub(crate) struct Requirement {
+    /// `None` for the toolchain cargo-hack is run with.
+    pub(crate) toolchain: Option<String>,
+    pub(crate) targets: Vec<String>,
+    pub(crate) components: &'static [&'static str],
+}
+
+impl Requirement {
+    /// `targets` are the targets to run with on the toolchain.
+    pub(crate) fn new(cx: &Context, toolchain: Option<String>, targets: &[String]) -> Self {
+        // Targets from cargo config are installed, but not passed to cargo.
+        let targets = if cx.target.is_empty() { &cx.effective_targets } else { targets };
+        let components = COMPONENTS
+            .iter()
+            .find(|&&(subcommand, _)| cx.subcommand.as_deref() == Some(subcommand))
+            .map_or(&[][..], |&(_, components)| components);
+        Self { toolchain, targets: targets.to_vec(), components }
+    }
+}
+
+/// Prints the requirements as JSON.
+pub(crate) fn print(requirements: &[Requirement]) -> Result<()> {
+    let toolchains: Vec<_> = requirements
+        .iter()
+        .map(|r| {
+            json!({
+                "toolchain": r.toolchain,
+                "targets": r.targets,
+                "components": r.components,
+            })
+        })
+        .collect();
+    let out = json!({ "version": VERSION, "toolchains": toolchains });
+    println!("{}", serde_json::to_string_pretty(&out)?);
+    Ok(())
+}
+
+/// Fails with the list of the requirements that are not installed.
+pub(crate) fn check_installed(requirements: &[Requirement]) -> Result<()> {
+    let mut missing = vec![];
+    let toolchains = if requirements.iter().any(|r| r.toolchain.is_some()) {
+        cmd!("rustup", "toolchain", "list").read()?
+    } else {
+        String::new()
+    };
+    for req in requirements {
+        let mut target_list = cmd!("rustup", "target", "list", "--installed");
+        let mut component_list = cmd!("rustup", "component", "list", "--installed");
+        let mut on = String::new();
+        if let Some(toolchain) = &req.toolchain {
+            if !rustup::is_installed(&toolchains, toolchain) {
+                missing.push(format!("toolchain {toolchain}"));
+                continue;
+            }
+            target_list.args(["--toolchain", toolchain]);
+            component_list.args(["--toolchain", toolchain]);
+            on = format!(" on {toolchain}");
+        }
+        // Custom targets are not installed by rustup.
+        let installable: Vec<_> = req.targets.iter().filter(|t| !targets::is_custom(t)).collect();
+        if !installable.is_empty() {
+            let installed = target_list.read()?;
+            for target in installable {
+                if !installed.lines().any(|line| line.trim() == target) {
+                    missing.push(format!("target `{target}`{on}"));
+                }
+            }
+        }
+        if !req.components.is_empty() {
+            let installed = component_list.read()?;
+            for &component in req.components {
+                if !installed.lines().any(|line| is_component(line.trim(), component)) {
+                    missing.push(format!("component `{component}`{on}"));
+                }
+            }
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let mut msg =
+        "the following requirements of the runs are not installed (--no-install):".to_owned();
+    for item in &missing {
+        write!(msg, "\n    {item}").unwrap();
+    }
+    msg.push_str(
+        "\n\nuse --print-toolchain-requirements to list the toolchains, targets, and components \
+         that the runs require",
+    );
+    bail!("{msg}")
+}
+
+/// Returns `true` if the line of `rustup component list` is the component,
+/// which is listed with the host target unless it is target-independent
+/// (e.g., `clippy-x86_64-unknown-linux-gnu` and `rust-src`).
+fn is_component(line: &str, component: &str) -> bool {
+    line.strip_prefix(component).is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_component;
+
+    #[test]
+    fn component() {
+        assert!(is_component("clippy-x86_64-unknown-linux-gnu", "clippy"));
+        assert!(is_component("rust-src", "rust-src"));
+        assert!(is_component("miri-aarch64-apple-darwin", "miri"));
+        assert!(!is_component("rustfmt-x86_64-unknown-linux-gnu", "rust"));
+        assert!(!is_component("rust-std-x86_64-unknown-linux-gnu", "rust-src"));
+    }
+}
@@ -1,26 +1,54 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
     mem,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process,
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, format_err, Context as _, Result};
 
 use crate::{fs, term};
 
+/// The name of the directory next to the lock file, where the original contents
+/// of the registered files are persisted while this process holds the lock.
+///
+/// This allows restoring the files even if cargo-hack is killed (e.g., by the
+/// OOM killer) before restoring them, by `cargo hack restore`.
+const JOURNAL_DIR: &str = "cargo-hack-backup";
+/// The name of the file in the journal directory that maps backups to the
+/// paths of the original files.
+const JOURNAL_INDEX: &str = "index";
+
 #[derive(Clone)]
 pub(crate) struct Manager {
     // A flag that indicates restore is needed.
     needs_restore: bool,
     /// Information on files that need to be restored.
     files: Arc<Mutex<Vec<File>>>,
+    /// Path to the lock file held by this process.
+    lock: Arc<Mutex<Option<PathBuf>>>,
+    /// The journal of the registered files. `None` until the lock is acquired.
+    journal: Arc<Mutex<Option<Journal>>>,
+    /// The backups of the registered files in the directory specified by
+    /// --manifest-backup-dir.
+    backup: Option<Arc<Journal>>,
 }
 
 impl Manager {
-    pub(crate) fn new(needs_restore: bool) -> Self {
-        let this = Self { needs_restore, files: Arc::new(Mutex::new(vec![])) };
+    pub(crate) fn new(needs_restore: bool, backup_dir: Option<&Path>) -> Self {
+        let this = Self {
+            needs_restore,
+            files: Arc::new(Mutex::new(vec![])),
+            lock: Arc::new(Mutex::new(None)),
+            journal: Arc::new(Mutex::new(None)),
+            backup: backup_dir.map(|dir| Arc::new(Journal::in_dir(dir))),
+        };
 
         let cloned = this.clone();
         ctrlc::set_handler(move || {
@@ -33,18 +61,120 @@ impl Manager {
     }
 
     /// Registers the given path if `needs_restore` is `true`.
-    pub(crate) fn register(&self, contents: impl Into<Vec<u8>>, path: impl Into<PathBuf>) {
+    ///
+    /// This must be called before modifying the file.
+    pub(crate) fn register(
+        &self,
+        contents: impl Into<Vec<u8>>,
+        path: impl Into<PathBuf>,
+    ) -> Result<()> {
         if !self.needs_restore {
-            return;
+            return Ok(());
         }
 
-        self.register_always(contents.into(), path.into());
+        self.register_always(contents.into(), path.into())
     }
 
     /// Registers the given path regardless of the value of `needs_restore`.
-    pub(crate) fn register_always(&self, contents: impl Into<Vec<u8>>, path: impl Into<PathBuf>) {
-        let mut files = self.files.lock().unwrap();
-        files.push(File { contents: contents.into(), path: path.into() });
+    ///
+    /// This must be called before modifying the file.
+    pub(crate) fn register_always(
+        &self,
+        contents: impl Into<Vec<u8>>,
+        path: impl Into<PathBuf>,
+    ) -> Result<()> {
+        let file = File { contents: contents.into(), path: path.into() };
+        if let Some(journal) = &*self.journal.lock().unwrap() {
+            journal.add(&file)?;
+        }
+        if let Some(backup) = &self.backup {
+            backup.add(&file)?;
+        }
+        self.files.lock().unwrap().push(file);
+        Ok(())
+    }
+
+    /// Returns `true` if the given path has been registered and not restored yet.
+    pub(crate) fn is_registered(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().iter().any(|file| file.path == path)
+    }
+
+    /// Acquires the lock that prevents other cargo-hack processes from
+    /// modifying the manifests of the same workspace at the same time.
+    ///
+    /// The lock is released after all registered files have been restored.
+    /// If `wait` is `false`, this returns an error instead of waiting for
+    /// another process to release the lock.
+    pub(crate) fn lock(&self, path: &Path, wait: bool) -> Result<()> {
+        let mut lock = self.lock.lock().unwrap();
+        if lock.is_some() {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create directory `{}`", dir.display()))?;
+        }
+        let mut waiting = false;
+        loop {
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    // The process ID is only used in the messages of other processes.
+                    let _ = write!(file, "{}", process::id());
+                    *lock = Some(path.to_owned());
+                    let journal = Journal::new(path);
+                    if !journal.is_empty() {
+                        // The lock file of the instance that left the journal was removed.
+                        info!(
+                            "restoring files modified by a previous cargo-hack instance that \
+                             did not exit cleanly"
+                        );
+                        journal.restore()?;
+                    }
+                    *self.journal.lock().unwrap() = Some(journal);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("failed to create lock file `{}`", path.display())
+                    });
+                }
+            }
+            let stale = if Journal::new(path).is_empty() {
+                format!("remove the stale lock file `{}`", path.display())
+            } else {
+                "run `cargo hack restore` to restore the files modified by it".to_owned()
+            };
+            if !wait {
+                bail!(
+                    "another cargo-hack instance{} is modifying this workspace; if no other \
+                     instance is running, {stale}",
+                    holder(path),
+                );
+            }
+            if !waiting {
+                info!(
+                    "another cargo-hack instance{} is modifying this workspace; waiting for it \
+                     to finish (if no other instance is running, {stale})",
+                    holder(path),
+                );
+                waiting = true;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Unregisters the given path, so that it is left as is instead of being
+    /// restored.
+    pub(crate) fn unregister(&self, path: &Path) -> Result<()> {
+        self.files.lock().unwrap().retain(|file| file.path != path);
+        if let Some(journal) = &*self.journal.lock().unwrap() {
+            journal.remove(path)?;
+        }
+        if let Some(backup) = &self.backup {
+            backup.remove(path)?;
+        }
+        Ok(())
     }
 
     // This takes `&mut self` instead of `&self` to prevent misuse in multi-thread contexts.
@@ -52,17 +182,57 @@ impl Manager {
         let mut files = self.files.lock().unwrap();
         if let Some(file) = files.pop() {
             file.restore()?;
+            if let Some(journal) = &*self.journal.lock().unwrap() {
+                journal.remove(&file.path)?;
+            }
+            if let Some(backup) = &self.backup {
+                backup.remove(&file.path)?;
+            }
         }
         Ok(())
     }
 
     pub(crate) fn restore_all(&self) {
         let mut files = self.files.lock().unwrap();
+        let mut restored = true;
         if !files.is_empty() {
             for file in mem::take(&mut *files) {
                 if let Err(e) = file.restore() {
                     error!("{e:#}");
+                    restored = false;
+                }
+            }
+        }
+        drop(files);
+        if let Some(backup) = &self.backup {
+            if restored {
+                if let Err(e) = backup.clear() {
+                    error!("{e:#}");
                 }
+            } else {
+                error!(
+                    "the original contents of the files are also kept in `{}`",
+                    backup.dir.display()
+                );
+            }
+        }
+        if let Some(journal) = self.journal.lock().unwrap().take() {
+            // Keep the journal if some files could not be restored, so that
+            // they can be restored by `cargo hack restore`.
+            if restored {
+                if let Err(e) = journal.clear() {
+                    error!("{e:#}");
+                }
+            } else {
+                error!("run `cargo hack restore` to retry restoring the files");
+                // Keep the lock file as well, to prevent other instances from
+                // modifying the files before they are restored.
+                self.lock.lock().unwrap().take();
+            }
+        }
+        if let Some(path) = self.lock.lock().unwrap().take() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                error!("failed to remove lock file `{}`: {e}", path.display());
             }
         }
     }
@@ -74,6 +244,44 @@ impl Drop for Manager {
     }
 }
 
+/// Returns the process ID of the holder of the lock for use in messages.
+fn holder(path: &Path) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(pid) if !pid.trim().is_empty() => format!(" (process {})", pid.trim()),
+        _ => String::new(),
+    }
+}
+
+/// Restores the files modified by a cargo-hack instance that did not exit
+/// cleanly, using the journal next to the lock file `lock`.
+///
+/// If `force` is `true`, this restores them even if the lock file exists, and
+/// removes the lock file.
+pub(crate) fn recover(lock: &Path, force: bool) -> Result<()> {
+    let journal = Journal::new(lock);
+    if !force {
+        if !journal.is_empty() && !lock.exists() {
+            info!(
+                "restoring files modified by a previous cargo-hack instance that did not exit \
+                 cleanly"
+            );
+            journal.restore()?;
+        }
+        return Ok(());
+    }
+    if journal.is_empty() {
+        info!("no files to restore");
+    } else {
+        journal.restore()?;
+    }
+    match std::fs::remove_file(lock) {
+        Ok(()) => info!("removed lock file `{}`", lock.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => bail!("failed to remove lock file `{}`: {e}", lock.display()),
+    }
+    Ok(())
+}
+
 struct File {
     /// The original contents of this file.
     contents: Vec<u8>,
@@ -82,10 +290,148 @@ struct File {
 }
 
 impl File {
-    fn restore(self) -> Result<()> {
+    fn restore(&self) -> Result<()> {
         if term::verbose() {
             info!("restoring {}", self.path.display());
         }
+        trace!("manifest", "restoring {}", self.path.display());
         fs::write(&self.path, &self.contents)
     }
 }
+
+/// The original contents of the registered files, persisted in the directory
+/// next to the lock file.
+///
+/// Each file is backed up to `<hash of its path>-<file name>` (e.g.,
+/// `<hash>-Cargo.lock`), and the index file has a line `<hash>\t<path>` for
+/// each backup. The backup is written before the index, so every entry of the
+/// index has its backup.
+///
+/// The same format is used for the backups in the directory specified by
+/// --manifest-backup-dir, except that the directory itself is not removed.
+struct Journal {
+    dir: PathBuf,
+    /// `true` if the directory is only used by cargo-hack and can be removed.
+    owns_dir: bool,
+}
+
+impl Journal {
+    fn new(lock: &Path) -> Self {
+        Self {
+            dir: lock.parent().unwrap_or_else(|| Path::new("")).join(JOURNAL_DIR),
+            owns_dir: true,
+        }
+    }
+
+    fn in_dir(dir: &Path) -> Self {
+        Self { dir: dir.to_owned(), owns_dir: false }
+    }
+
+    fn index(&self) -> PathBuf {
+        self.dir.join(JOURNAL_INDEX)
+    }
+
+    fn backup(&self, path: &Path) -> PathBuf {
+        let name = path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+        self.dir.join(format!("{:016x}-{name}", hash(path)))
+    }
+
+    fn is_empty(&self) -> bool {
+        std::fs::metadata(self.index()).map_or(true, |m| m.len() == 0)
+    }
+
+    fn add(&self, file: &File) -> Result<()> {
+        let path = file.path.to_str().filter(|p| !p.contains('\n')).ok_or_else(|| {
+            format_err!("unsupported path `{}` for restore journal", file.path.display())
+        })?;
+        let backup = self.backup(&file.path);
+        // If the file has already been registered, the existing backup has the
+        // original contents.
+        if backup.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create directory `{}`", self.dir.display()))?;
+        fs::write(&backup, &file.contents)?;
+        let index = self.index();
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index)
+            .and_then(|mut f| writeln!(f, "{:016x}\t{path}", hash(&file.path)))
+            .with_context(|| format!("failed to write to file `{}`", index.display()))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let entries = self.entries()?;
+        let mut index = String::new();
+        for (backup, original) in &entries {
+            if original == path {
+                std::fs::remove_file(backup)
+                    .with_context(|| format!("failed to remove file `{}`", backup.display()))?;
+            } else {
+                let _ = writeln!(index, "{:016x}\t{}", hash(original), original.display());
+            }
+        }
+        fs::write(self.index(), index)
+    }
+
+    /// Returns the pairs of the backup and the path of the original file.
+    fn entries(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let index = match std::fs::read_to_string(self.index()) {
+            Ok(index) => index,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("failed to read from file `{}`", self.index().display())
+                });
+            }
+        };
+        index
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (_, path) = line.split_once('\t').ok_or_else(|| {
+                    format_err!("invalid entry `{line}` in `{}`", self.index().display())
+                })?;
+                let path = PathBuf::from(path);
+                Ok((self.backup(&path), path))
+            })
+            .collect()
+    }
+
+    /// Restores all files in the journal, and removes the journal.
+    fn restore(&self) -> Result<()> {
+        for (backup, path) in self.entries()? {
+            info!("restoring {}", path.display());
+            fs::write(&path, fs::read(&backup)?)?;
+        }
+        self.clear()
+    }
+
+    fn clear(&self) -> Result<()> {
+        if !self.owns_dir {
+            for (backup, _) in self.entries()? {
+                std::fs::remove_file(&backup)
+                    .with_context(|| format!("failed to remove file `{}`", backup.display()))?;
+            }
+            return match std::fs::remove_file(self.index()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)
+                    .with_context(|| format!("failed to remove file `{}`", self.index().display())),
+                _ => Ok(()),
+            };
+        }
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)
+                .with_context(|| format!("failed to remove directory `{}`", self.dir.display())),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the FNV-1a hash of the path, which is stable across processes.
+fn hash(path: &Path) -> u64 {
+    path.to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --only-run.
+//
+// Each run has a short id, hashed from the name of the package, the feature
+// flags, the cfgs of --cfg-matrix, and the toolchain of --version-range. The
+// features are sorted, so the id does not depend on the order in which they
+// are passed, and the toolchain is only included if --version-range (or
+// --rust-version) is used, so the id of a run printed by CI can be used to
+// run it again locally with another version of cargo. The commands chained by
+// --and-then share the id of the run they are chained to.
+//
+// --only-run runs only the runs with the given ids and skips the others, like
+// --partition does.
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+
+use crate::{context::Context, metadata::PackageId, ProcessBuilder};
+
+/// Returns the id of the run of `line` on the package.
+pub(crate) fn compute(
+    cx: &Context,
+    id: &PackageId,
+    toolchain: Option<&str>,
+    line: &ProcessBuilder<'_>,
+) -> String {
+    let mut features: Vec<_> = line.features().collect();
+    features.sort_unstable();
+    features.dedup();
+    let mut cfgs = line.cfgs().to_vec();
+    cfgs.sort_unstable();
+    let flags = ["--all-features", "--no-default-features"].map(|flag| line.has_arg(flag));
+    let key = format!(
+        "{}\0{}\0{flags:?}\0{}\0{}",
+        cx.packages(id).name,
+        toolchain.unwrap_or_default(),
+        features.join(","),
+        cfgs.join(",")
+    );
+    format!("{:08x}", hash(&key))
+}
+
+/// Parses the ids passed to --only-run.
+pub(crate) fn parse(ids: &[String]) -> Result<BTreeSet<String>> {
+    ids.iter()
+        .map(|id| {
+            if id.len() != 8 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                bail!("invalid run id `{id}` for --only-run; run ids are 8 hexadecimal digits");
+            }
+            Ok(id.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// Returns the 32-bit FNV-1a hash of `s`, which is stable across processes
+/// and platforms.
+fn hash(s: &str) -> u32 {
+    s.bytes().fold(0x811c_9dc5, |hash, b| (hash ^ u32::from(b)).wrapping_mul(0x0100_0193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, parse};
+
+    #[test]
+    fn ids() {
+        assert_eq!(hash(""), 0x811c_9dc5);
+        assert_eq!(hash("a"), 0xe40c_292c);
+        assert_eq!(
+            parse(&["0123abcd".to_owned(), "0123ABCD".to_owned()])
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["0123abcd"]
+        );
+        assert!(parse(&["0123abc".to_owned()]).is_err());
+        assert!(parse(&["0123abcg".to_owned()]).is_err());
+    }
+}
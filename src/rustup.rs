@@ -1,16 +1,30 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use std::str;
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    str,
+};
 
-use anyhow::{bail, format_err, Result};
+use anyhow::{bail, format_err, Context as _, Result};
+use fs2::FileExt as _;
 
 use crate::{
     cargo,
     context::Context,
+    process::ProcessBuilder,
     version::{MaybeVersion, Version, VersionRange},
     LogGroup, PackageRuns,
 };
 
+/// The name of the lock file in RUSTUP_HOME, which serializes the toolchain
+/// installations of cargo-hack processes sharing the same RUSTUP_HOME.
+const LOCK_FILE: &str = ".cargo-hack-rustup.lock";
+
+/// The environment variable to override the path of the lock file.
+const LOCK_FILE_ENV: &str = "CARGO_HACK_RUSTUP_LOCK_FILE";
+
 pub(crate) struct Rustup {
     pub(crate) version: u32,
 }
@@ -52,8 +66,18 @@ pub(crate) fn version_range(
         if let Some(stable_version) = stable_version {
             Ok(stable_version)
         } else {
-            let print_output = false;
-            install_toolchain("stable", &[], print_output, LogGroup::None)?;
+            // Do not install the toolchain in --print-command-list mode, which must not have side effects.
+            if cx.no_install {
+                if !is_installed(&cmd!("rustup", "toolchain", "list").read()?, "stable") {
+                    bail!(
+                        "the stable toolchain is required to determine the range of \
+                         --version-range, but is not installed (--no-install)"
+                    );
+                }
+            } else if !cx.print_command_list {
+                let print_output = false;
+                install_toolchain("stable", &[], print_output, LogGroup::None, !cx.no_rustup_lock)?;
+            }
             let version = cargo::version(cmd!("rustup", "run", "stable", "cargo"))?;
             stable_version = Some(version);
             Ok(version)
@@ -67,10 +91,10 @@ pub(crate) fn version_range(
         } else {
             let mut lowest_msrv = None;
             for pkg in packages {
+                // Fall back to the edition if the package has no rust-version.
                 let pkg_msrv = cx
-                    .rust_version(pkg.id)
-                    .map(str::parse::<Version>)
-                    .transpose()?
+                    .msrv(pkg.id)?
+                    .or_else(|| cx.edition_msrv(pkg.id).map(|(_, msrv)| msrv))
                     .map(Version::strip_patch);
                 lowest_msrv = match (lowest_msrv, pkg_msrv) {
                     (Some(workspace), Some(pkg)) => {
@@ -85,7 +109,7 @@ pub(crate) fn version_range(
                 };
             }
             let Some(lowest_msrv) = lowest_msrv else {
-                bail!("no rust-version field in selected Cargo.toml's is specified")
+                bail!("no rust-version or edition field in selected Cargo.toml's is specified")
             };
             rust_version = Some(lowest_msrv);
             Ok(lowest_msrv)
@@ -126,11 +150,34 @@ pub(crate) fn version_range(
     Ok(versions)
 }
 
+/// Returns the versions to run a package with the given MSRV on: the `steps`
+/// at or after the MSRV, and the MSRV itself if any of them exists.
+///
+/// `steps` are sorted, and each version appears at most once in the result.
+pub(crate) fn package_versions(msrv: Option<Version>, steps: &[Version]) -> Vec<Version> {
+    let mut versions: Vec<_> =
+        steps.iter().copied().filter(|&v| msrv.map_or(true, |msrv| v >= msrv)).collect();
+    if let Some(msrv) = msrv {
+        if !versions.is_empty() {
+            versions.push(msrv);
+        }
+    }
+    versions.sort_unstable();
+    versions.dedup();
+    versions
+}
+
+/// Installs the toolchain (and the targets) if it is not installed.
+///
+/// If `lock` is `true`, this holds an exclusive lock on the lock file in
+/// RUSTUP_HOME while running rustup, because concurrent installations may
+/// corrupt the download cache of rustup.
 pub(crate) fn install_toolchain(
     mut toolchain: &str,
     target: &[String],
     print_output: bool,
     log_group: LogGroup,
+    lock: bool,
 ) -> Result<()> {
     toolchain = toolchain.strip_prefix('+').unwrap_or(toolchain);
 
@@ -141,13 +188,10 @@ pub(crate) fn install_toolchain(
         return Ok(());
     }
 
-    // In Github Actions and Azure Pipelines, --no-self-update is necessary
-    // because the windows environment cannot self-update rustup.exe.
-    let mut cmd = cmd!("rustup", "toolchain", "add", toolchain, "--no-self-update");
-    if !target.is_empty() {
-        cmd.args(["--target", &target.join(",")]);
-    }
-
+    let cmd = install_toolchain_command(toolchain, target);
+    // The lock is released when the file is closed, which is also done by the
+    // OS if this process is killed.
+    let _lock = if lock { Some(lock_install()?) } else { None };
     if print_output {
         let _guard = log_group.print(&format!("running {cmd}"));
         // The toolchain installation can take some time, so we'll show users
@@ -159,6 +203,62 @@ pub(crate) fn install_toolchain(
     }
 }
 
+/// Returns `true` if the toolchain is in `list`, the output of
+/// `rustup toolchain list`, where toolchains are listed with the host target
+/// (e.g., `1.70-x86_64-unknown-linux-gnu`).
+pub(crate) fn is_installed(list: &str, toolchain: &str) -> bool {
+    list.lines().filter_map(|line| line.split_whitespace().next()).any(|name| {
+        name.strip_prefix(toolchain).is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    })
+}
+
+/// Acquires the lock for installing toolchains, waiting for other processes
+/// holding it.
+fn lock_install() -> Result<File> {
+    let path = match env::var_os(LOCK_FILE_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let home = cmd!("rustup", "show", "home").read()?;
+            PathBuf::from(home.trim()).join(LOCK_FILE)
+        }
+    };
+    let hint = "use --no-rustup-lock to install toolchains without the lock";
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file `{}`; {hint}", path.display()))?;
+    if let Err(e) = file.try_lock_exclusive() {
+        if e.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+            return Err(e)
+                .with_context(|| format!("failed to lock file `{}`; {hint}", path.display()));
+        }
+        info!(
+            "waiting for another process to finish installing a toolchain (lock file `{}`)",
+            path.display()
+        );
+        file.lock_exclusive()
+            .with_context(|| format!("failed to lock file `{}`; {hint}", path.display()))?;
+    }
+    Ok(file)
+}
+
+/// Returns the command to install the toolchain.
+pub(crate) fn install_toolchain_command(
+    toolchain: &str,
+    target: &[String],
+) -> ProcessBuilder<'static> {
+    // In Github Actions and Azure Pipelines, --no-self-update is necessary
+    // because the windows environment cannot self-update rustup.exe.
+    let mut cmd = cmd!("rustup", "toolchain", "add", toolchain, "--no-self-update");
+    if !target.is_empty() {
+        cmd.args(["--target", &target.join(",")]);
+    }
+    cmd
+}
+
 fn minor_version() -> Result<u32> {
     let cmd = cmd!("rustup", "--version");
     let output = cmd.read()?;
@@ -178,3 +278,49 @@ fn minor_version() -> Result<u32> {
 
     Ok(version.minor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::package_versions;
+    use crate::version::Version;
+
+    fn minors(msrv: Option<u32>, steps: &[u32]) -> Vec<u32> {
+        let version = |minor| Version { major: 1, minor, patch: None };
+        let steps: Vec<_> = steps.iter().map(|&minor| version(minor)).collect();
+        package_versions(msrv.map(version), &steps).iter().map(|v| v.minor).collect()
+    }
+
+    #[test]
+    fn package_versions_dedup() {
+        let steps = [70, 72, 74];
+        assert_eq!(minors(None, &steps), [70, 72, 74]);
+        // MSRV is added before the first step after it.
+        assert_eq!(minors(Some(69), &steps), [69, 70, 72, 74]);
+        assert_eq!(minors(Some(71), &steps), [71, 72, 74]);
+        // MSRV that is also a step appears only once.
+        assert_eq!(minors(Some(72), &steps), [72, 74]);
+        assert_eq!(minors(Some(72), &[70, 72, 72, 74]), [72, 74]);
+        // MSRV out of range.
+        assert_eq!(minors(Some(75), &steps), [0_u32; 0]);
+    }
+
+    #[test]
+    fn package_versions_patch() {
+        let version = |minor, patch| Version { major: 1, minor, patch };
+        let steps = [version(70, None), version(72, None), version(74, None)];
+        // The patch release of MSRV replaces the step of the same minor
+        // release, which may be an older patch release.
+        assert!(version(72, None) < version(72, Some(0)));
+        assert!(version(72, Some(1)) < version(73, None));
+        assert_eq!(package_versions(Some(version(72, Some(1))), &steps), [
+            version(72, Some(1)),
+            version(74, None)
+        ]);
+        assert_eq!(package_versions(Some(version(71, Some(1))), &steps), [
+            version(71, Some(1)),
+            version(72, None),
+            version(74, None)
+        ]);
+        assert_eq!(package_versions(Some(version(74, Some(1))), &steps), []);
+    }
+}
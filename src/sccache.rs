@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --sccache-stats.
+//
+// sccache only provides the statistics accumulated since the server started
+// (or since the last `--zero-stats`), so the statistics are reset before each
+// command and read after it.
+
+use std::{collections::BTreeMap, env, ffi::OsString, ops::AddAssign};
+
+use anyhow::{format_err, Result};
+use serde_json::Value;
+
+use crate::summary::Run;
+
+/// Cache statistics of sccache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) errors: u64,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.hits += rhs.hits;
+        self.misses += rhs.misses;
+        self.errors += rhs.errors;
+    }
+}
+
+/// The sccache to collect statistics from.
+///
+/// This is disabled if --sccache-stats is not used, RUSTC_WRAPPER is not sccache,
+/// or sccache failed once.
+#[derive(Default)]
+pub(crate) struct Sccache {
+    program: Option<OsString>,
+}
+
+impl Sccache {
+    pub(crate) fn new() -> Self {
+        match env::var_os("RUSTC_WRAPPER") {
+            Some(wrapper) if wrapper.to_string_lossy().contains("sccache") => {
+                Self { program: Some(wrapper) }
+            }
+            _ => {
+                warn!("--sccache-stats requires RUSTC_WRAPPER to be sccache; statistics will not be collected");
+                Self::default()
+            }
+        }
+    }
+
+    /// Resets the statistics.
+    pub(crate) fn zero_stats(&mut self) {
+        let Some(program) = &self.program else { return };
+        if let Err(e) = cmd!(program, "--zero-stats").run_with_output() {
+            self.disable(&e);
+        }
+    }
+
+    /// Returns the statistics since the last reset.
+    pub(crate) fn show_stats(&mut self) -> Option<Stats> {
+        let program = self.program.as_ref()?;
+        match cmd!(program, "--show-stats", "--stats-format", "json").read().and_then(|s| parse(&s))
+        {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                self.disable(&e);
+                None
+            }
+        }
+    }
+
+    fn disable(&mut self, e: &anyhow::Error) {
+        warn!(
+            "failed to get statistics from sccache; statistics will no longer be collected: {e:#}"
+        );
+        self.program = None;
+    }
+}
+
+/// Parses the output of `sccache --show-stats --stats-format json`.
+fn parse(s: &str) -> Result<Stats> {
+    let value: Value = serde_json::from_str(s)?;
+    let stats = value
+        .get("stats")
+        .and_then(Value::as_object)
+        .ok_or_else(|| format_err!("unexpected output from sccache: {s}"))?;
+    // The counts are per language, e.g., `{"counts": {"Rust": 3}}`.
+    let count = |key: &str| -> u64 {
+        stats
+            .get(key)
+            .and_then(|v| v.get("counts"))
+            .and_then(Value::as_object)
+            .map_or(0, |counts| counts.values().filter_map(Value::as_u64).sum())
+    };
+    Ok(Stats {
+        hits: count("cache_hits"),
+        misses: count("cache_misses"),
+        errors: count("cache_errors"),
+    })
+}
+
+/// Prints the statistics of each run, each package, and in total.
+pub(crate) fn print(runs: &[Run]) {
+    let mut packages: BTreeMap<&str, Stats> = BTreeMap::new();
+    let mut total = Stats::default();
+    info!("sccache statistics (hits/misses/errors):");
+    for run in runs {
+        let Some(stats) = run.sccache else { continue };
+        eprintln!("    {}: {} on {}", format(stats), run.command, run.package);
+        *packages.entry(&run.package).or_default() += stats;
+        total += stats;
+    }
+    for (package, stats) in packages {
+        eprintln!("    {}: total on {package}", format(stats));
+    }
+    eprintln!("    {}: total", format(total));
+}
+
+fn format(stats: Stats) -> String {
+    format!("{}/{}/{}", stats.hits, stats.misses, stats.errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Stats};
+
+    #[test]
+    fn parse_stats() {
+        let s = r#"{
+            "stats": {
+                "compile_requests": 10,
+                "cache_hits": { "counts": { "Rust": 3, "C/C++": 1 }, "adv_counts": {} },
+                "cache_misses": { "counts": { "Rust": 2 }, "adv_counts": {} },
+                "cache_errors": { "counts": {}, "adv_counts": {} }
+            },
+            "cache_location": "Local disk"
+        }"#;
+        assert_eq!(parse(s).unwrap(), Stats { hits: 4, misses: 2, errors: 0 });
+        assert_eq!(parse(r#"{"stats": {}}"#).unwrap(), Stats::default());
+        assert!(parse("{}").is_err());
+        assert!(parse("sccache: error").is_err());
+    }
+}
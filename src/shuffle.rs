@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --shuffle.
+//
+// The order of the packages and the order of the feature combinations of each
+// package are randomized to find hidden dependencies between runs (e.g., stale
+// outputs of build scripts, or files on disk shared by tests). The order is
+// determined by the seed, which is printed so that a failure can be reproduced
+// with `--shuffle <SEED>`. The runs with just --all-features and just
+// --no-default-features keep their positions.
+//
+// By default, the biggest feature combination is no longer run early to warm
+// the build cache (see exec_on_package_with_cfgs); --shuffle-keep-warmup keeps
+// it first.
+//
+// The generator is SplitMix64, which is good enough for shuffling and keeps the
+// order stable across platforms and versions of the dependencies.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher as _, Hasher as _},
+    time::SystemTime,
+};
+
+use crate::{context::Context, Kind, PackageRuns};
+
+/// Shuffles the packages and their feature combinations.
+pub(crate) fn apply(cx: &Context, seed: u64, packages: &mut [PackageRuns<'_>]) {
+    info!("shuffling the order of runs with seed {seed}; use `--shuffle {seed}` to reproduce it");
+    let mut rng = Rng(seed);
+    rng.shuffle(packages);
+    for pkg in packages {
+        match &mut pkg.kind {
+            Kind::Normal => {}
+            Kind::Each { features } => rng.shuffle(features),
+            Kind::Powerset { features } => {
+                let len = features.len();
+                if cx.shuffle_keep_warmup && len > 1 {
+                    // The last combination is the biggest one, which is run first.
+                    rng.shuffle(&mut features[..len - 1]);
+                } else {
+                    rng.shuffle(features);
+                }
+            }
+        }
+    }
+}
+
+/// Returns a seed taken from the randomly seeded hasher of the standard
+/// library.
+pub(crate) fn random_seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    // Keep the seed short enough to be copied easily.
+    hasher.finish() % 1_000_000_000
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        // The modulo bias is negligible for the number of runs.
+        usize::try_from(self.next_u64() % n as u64).unwrap()
+    }
+
+    /// Shuffles `slice` by the Fisher-Yates algorithm.
+    fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn shuffle() {
+        let shuffled = |seed| {
+            let mut v: Vec<_> = (0..10).collect();
+            Rng(seed).shuffle(&mut v);
+            v
+        };
+        assert_eq!(shuffled(1), shuffled(1));
+        assert_ne!(shuffled(1), shuffled(2));
+        let mut v = shuffled(1);
+        v.sort_unstable();
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+        // The first output of SplitMix64 with seed 0.
+        assert_eq!(Rng(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+    }
+}
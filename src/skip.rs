@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --fail-on-skipped.
+//
+// Some parts of the requested runs may be skipped with only an info or warning
+// message, e.g., a feature unknown to a package with --ignore-unknown-features.
+// Such skips are recorded here, and if any skip of the kinds specified by
+// --fail-on-skipped occurred, the run fails at the end even if all commands
+// succeeded.
+
+use std::{cell::RefCell, collections::BTreeSet, fmt::Write as _, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SkipKind {
+    /// A feature unknown to the package is not applied (--ignore-unknown-features).
+    UnknownFeature,
+    /// A group of --group-features that contains features unknown to the
+    /// package is not applied (--ignore-unknown-features).
+    GroupSkipped,
+    /// A package is not run because its rust-version is not in --version-range.
+    MsrvOutOfRange,
+    /// A package has nothing to run, e.g., it has no features to iterate with
+    /// --each-feature or --feature-powerset, or no binary target for `cargo run`.
+    PackageEmptyPlan,
+    /// A private package is not run (--ignore-private).
+    PrivatePackage,
+    /// A package is not run because it is excluded by --exclude or
+    /// --only-packages-with-feature.
+    ExcludedPackage,
+}
+
+impl SkipKind {
+    const ALL: [Self; 6] = [
+        Self::UnknownFeature,
+        Self::GroupSkipped,
+        Self::MsrvOutOfRange,
+        Self::PackageEmptyPlan,
+        Self::PrivatePackage,
+        Self::ExcludedPackage,
+    ];
+
+    /// The kinds of skips that may leave a package out of the runs.
+    const PACKAGE: [Self; 4] =
+        [Self::MsrvOutOfRange, Self::PackageEmptyPlan, Self::PrivatePackage, Self::ExcludedPackage];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::UnknownFeature => "unknown-feature",
+            Self::GroupSkipped => "group-skipped",
+            Self::MsrvOutOfRange => "msrv-out-of-range",
+            Self::PackageEmptyPlan => "package-empty-plan",
+            Self::PrivatePackage => "private-package",
+            Self::ExcludedPackage => "excluded-package",
+        }
+    }
+}
+
+impl FromStr for SkipKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::ALL.iter().find(|k| k.as_str() == s) {
+            Some(&kind) => Ok(kind),
+            None => bail!(
+                "argument for --fail-on-skipped must be a comma-separated list of {}, but found \
+                 `{s}`",
+                Self::ALL.map(Self::as_str).join(", ")
+            ),
+        }
+    }
+}
+
+/// Returns the kinds of skips that are reported by default.
+///
+/// private-package and excluded-package are not in the default kinds, because
+/// --ignore-private and --exclude are usually used to skip packages
+/// intentionally.
+pub(crate) fn default_kinds() -> Vec<SkipKind> {
+    SkipKind::ALL
+        .into_iter()
+        .filter(|&k| k != SkipKind::PrivatePackage && k != SkipKind::ExcludedPackage)
+        .collect()
+}
+
+/// Parses the value of --fail-on-skipped, or returns the default kinds if the
+/// value is not specified.
+pub(crate) fn parse_kinds(val: Option<&str>) -> Result<Vec<SkipKind>> {
+    match val {
+        None => Ok(default_kinds()),
+        Some(val) => {
+            let mut kinds: Vec<_> =
+                val.split(',').map(str::trim).map(str::parse).collect::<Result<_>>()?;
+            kinds.sort_unstable();
+            kinds.dedup();
+            Ok(kinds)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Skip {
+    kind: SkipKind,
+    package: String,
+    detail: String,
+}
+
+/// Records the skips that occurred during the run.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    // The same skip may be recorded for each command (e.g., an unknown
+    // feature is skipped each time the feature flags are built).
+    skips: RefCell<BTreeSet<Skip>>,
+}
+
+impl Recorder {
+    pub(crate) fn record(&self, kind: SkipKind, package: &str, detail: impl Into<String>) {
+        let detail = detail.into();
+        trace!("plan", "recorded skip {kind:?} for package `{package}`: {detail}");
+        self.skips.borrow_mut().insert(Skip { kind, package: package.to_owned(), detail });
+    }
+
+    /// Returns the skips that may leave a package out of the runs, as pairs of
+    /// the package name and the reason.
+    pub(crate) fn packages(&self) -> Vec<(String, String)> {
+        self.skips
+            .borrow()
+            .iter()
+            .filter(|s| SkipKind::PACKAGE.contains(&s.kind))
+            .map(|s| (s.package.clone(), s.detail.clone()))
+            .collect()
+    }
+
+    /// Returns the summary of the skips of the given kinds, or `None` if there
+    /// are no such skips.
+    pub(crate) fn summary(&self, kinds: &[SkipKind]) -> Option<String> {
+        let skips = self.skips.borrow();
+        let skips: Vec<_> = skips.iter().filter(|s| kinds.contains(&s.kind)).collect();
+        if skips.is_empty() {
+            return None;
+        }
+        let mut out = format!(
+            "{} {} occurred (--fail-on-skipped):",
+            skips.len(),
+            if skips.len() == 1 { "skip" } else { "skips" }
+        );
+        for skip in skips {
+            let _ = write!(out, "\n    {}: {}: {}", skip.kind.as_str(), skip.package, skip.detail);
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_kinds, Recorder, SkipKind};
+
+    #[test]
+    fn kinds() {
+        assert_eq!(parse_kinds(None).unwrap(), [
+            SkipKind::UnknownFeature,
+            SkipKind::GroupSkipped,
+            SkipKind::MsrvOutOfRange,
+            SkipKind::PackageEmptyPlan,
+        ]);
+        assert_eq!(
+            parse_kinds(Some("private-package,unknown-feature,private-package")).unwrap(),
+            [SkipKind::UnknownFeature, SkipKind::PrivatePackage,]
+        );
+        assert!(parse_kinds(Some("unknown"))
+            .unwrap_err()
+            .to_string()
+            .contains("but found `unknown`"));
+        assert!(parse_kinds(Some("")).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let recorder = Recorder::default();
+        assert_eq!(recorder.summary(&[SkipKind::UnknownFeature]), None);
+        recorder.record(SkipKind::UnknownFeature, "a", "feature `x`");
+        recorder.record(SkipKind::UnknownFeature, "a", "feature `x`");
+        recorder.record(SkipKind::PrivatePackage, "b", "private package");
+        assert_eq!(
+            recorder.summary(&[SkipKind::UnknownFeature]).unwrap(),
+            "1 skip occurred (--fail-on-skipped):\n    unknown-feature: a: feature `x`"
+        );
+        assert_eq!(recorder.summary(&[SkipKind::GroupSkipped]), None);
+        assert_eq!(
+            recorder.summary(&[SkipKind::UnknownFeature, SkipKind::PrivatePackage]).unwrap(),
+            "2 skips occurred (--fail-on-skipped):\n    unknown-feature: a: feature `x`\n    \
+             private-package: b: private package"
+        );
+    }
+}
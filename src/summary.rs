@@ -0,0 +1,359 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    env,
+    fmt::Write as _,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Error, Result};
+use serde_json::json;
+
+use crate::{fs, sccache, timing, version::Version};
+
+/// The version of the format of --summary-json.
+pub(crate) const JSON_VERSION: u64 = 1;
+
+/// The number of stderr lines to keep for each failed run.
+pub(crate) const STDERR_TAIL_LINES: usize = 30;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Passed,
+    Failed,
+    Skipped,
+    /// Not run because --budget was exceeded.
+    NotRun,
+}
+
+impl Status {
+    /// Returns `true` if the command was actually run.
+    pub(crate) fn ran(self) -> bool {
+        matches!(self, Self::Passed | Self::Failed)
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+            Self::NotRun => "not-run",
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "passed" => Ok(Self::Passed),
+            "failed" => Ok(Self::Failed),
+            "skipped" => Ok(Self::Skipped),
+            "not-run" => Ok(Self::NotRun),
+            _ => bail!("unknown run status `{s}`"),
+        }
+    }
+}
+
+/// The result of a single cargo invocation.
+pub(crate) struct Run {
+    /// The id of the run, used for --only-run.
+    pub(crate) id: String,
+    /// The name of the package.
+    pub(crate) package: String,
+    /// The toolchain used for this run. `None` if --version-range is not used.
+    pub(crate) toolchain: Option<String>,
+    /// The command, as displayed in the log (with backticks).
+    pub(crate) command: String,
+    /// The feature flags of this run.
+    pub(crate) features: String,
+    /// The features that the requested features imply according to the manifest.
+    pub(crate) requested_features: BTreeSet<String>,
+    /// The features that cargo actually enabled for the package. This is `None`
+    /// unless --check-additivity is used and the package was compiled.
+    pub(crate) enabled_features: Option<BTreeSet<String>>,
+    /// The number of public items in the documentation. This is `None` unless
+    /// --rustdoc-all-features-check is used and the documentation was built.
+    pub(crate) doc_items: Option<usize>,
+    pub(crate) status: Status,
+    /// The time taken to run the command. This is zero if the run was skipped.
+    pub(crate) duration: Duration,
+    /// Whether this is the first run on the package (with the toolchain), which
+    /// builds its dependencies with a cold cache.
+    pub(crate) cold_start: bool,
+    /// The error of the run, including the exit status. `None` unless the run failed.
+    pub(crate) error: Option<String>,
+    /// The last lines of stderr. This is empty unless the run failed and stderr was captured.
+    pub(crate) stderr: VecDeque<String>,
+    /// The cfgs passed via rustflags by --cfg-matrix.
+    pub(crate) cfgs: Vec<String>,
+    /// The statistics of sccache. This is `None` unless --sccache-stats is used.
+    pub(crate) sccache: Option<sccache::Stats>,
+}
+
+/// A package on which no command was run, e.g., because it is private.
+pub(crate) struct SkippedPackage {
+    /// The name of the package.
+    pub(crate) package: String,
+    /// The reason why the package was skipped.
+    pub(crate) reason: String,
+}
+
+#[derive(Default)]
+struct Counts {
+    run: usize,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    not_run: usize,
+}
+
+impl Counts {
+    fn add(&mut self, status: Status) {
+        match status {
+            Status::Passed => {
+                self.run += 1;
+                self.passed += 1;
+            }
+            Status::Failed => {
+                self.run += 1;
+                self.failed += 1;
+            }
+            Status::Skipped => self.skipped += 1,
+            Status::NotRun => self.not_run += 1,
+        }
+    }
+}
+
+/// Writes a GitHub-flavored Markdown report of the given runs to `path`.
+///
+/// `additivity` is the findings of --check-additivity. The slowest commands
+/// are included if `slowest` is `true`.
+pub(crate) fn write_markdown(
+    path: &Path,
+    runs: &[Run],
+    additivity: &[String],
+    slowest: bool,
+) -> Result<()> {
+    fs::write(path, markdown(runs, additivity, slowest))
+}
+
+/// Writes the results of the given runs to `path` as JSON, for --summary-json.
+///
+/// Each run is identified by its id (see run_id.rs), so the file can be
+/// compared with the results of another run by --baseline.
+pub(crate) fn write_json(path: &Path, runs: &[Run], skipped: &[SkippedPackage]) -> Result<()> {
+    let runs: Vec<_> = runs
+        .iter()
+        .map(|run| {
+            json!({
+                "id": run.id,
+                "package": run.package,
+                "toolchain": run.toolchain,
+                "command": run.command.replace('`', ""),
+                "features": run.features,
+                "cfgs": run.cfgs,
+                "status": run.status.as_str(),
+                "duration": run.duration.as_secs_f64(),
+                "error": run.error,
+            })
+        })
+        .collect();
+    let skipped: Vec<_> = skipped
+        .iter()
+        .map(|skipped| json!({ "package": skipped.package, "reason": skipped.reason }))
+        .collect();
+    let summary = json!({
+        "version": JSON_VERSION,
+        "runs": runs,
+        "skipped_packages": skipped,
+    });
+    fs::write(path, serde_json::to_string_pretty(&summary)? + "\n")
+}
+
+fn markdown(runs: &[Run], additivity: &[String], slowest: bool) -> String {
+    let mut out = String::new();
+    let mut total = Counts::default();
+    let mut packages: BTreeMap<&str, Counts> = BTreeMap::new();
+    // Keyed by the parsed version so that the columns follow the order of
+    // --version-range (e.g., 1.9 before 1.10).
+    let mut toolchains: BTreeMap<(Option<Version>, &str), BTreeMap<&str, Counts>> = BTreeMap::new();
+    for run in runs {
+        total.add(run.status);
+        packages.entry(&run.package).or_default().add(run.status);
+        if let Some(toolchain) = &run.toolchain {
+            toolchains
+                .entry((toolchain.parse().ok(), toolchain))
+                .or_default()
+                .entry(&run.package)
+                .or_default()
+                .add(run.status);
+        }
+    }
+
+    let mut args = env::args();
+    args.next(); // cargo-hack
+    let command = args.collect::<Vec<_>>().join(" ");
+    writeln!(out, "## cargo-hack summary\n").unwrap();
+    writeln!(out, "`cargo {command}`\n").unwrap();
+    let count = total.run + total.skipped + total.not_run;
+    write!(
+        out,
+        "{count} {}: {} passed, {} failed, {} skipped",
+        if count == 1 { "command" } else { "commands" },
+        total.passed,
+        total.failed,
+        total.skipped,
+    )
+    .unwrap();
+    if total.not_run > 0 {
+        write!(out, ", {} not run (budget exceeded)", total.not_run).unwrap();
+    }
+    writeln!(out, "\n").unwrap();
+
+    writeln!(out, "| Package | Run | Passed | Failed | Skipped |").unwrap();
+    writeln!(out, "| --- | ---: | ---: | ---: | ---: |").unwrap();
+    for (package, c) in &packages {
+        writeln!(out, "| {package} | {} | {} | {} | {} |", c.run, c.passed, c.failed, c.skipped)
+            .unwrap();
+    }
+
+    if !toolchains.is_empty() {
+        writeln!(out, "\n### Toolchains\n").unwrap();
+        write!(out, "| Package |").unwrap();
+        for (_, toolchain) in toolchains.keys() {
+            write!(out, " {toolchain} |").unwrap();
+        }
+        write!(out, "\n| --- |").unwrap();
+        for _ in toolchains.keys() {
+            write!(out, " --- |").unwrap();
+        }
+        out.push('\n');
+        for package in packages.keys() {
+            write!(out, "| {package} |").unwrap();
+            for results in toolchains.values() {
+                match results.get(package) {
+                    Some(c) if c.failed > 0 => {
+                        write!(out, " :x: {}/{} |", c.passed, c.run).unwrap();
+                    }
+                    Some(c) if c.run > 0 => {
+                        write!(out, " :white_check_mark: {}/{} |", c.passed, c.run).unwrap();
+                    }
+                    Some(_) | None => out.push_str(" - |"),
+                }
+            }
+            out.push('\n');
+        }
+    }
+
+    if slowest {
+        writeln!(out, "\n### Slowest commands\n").unwrap();
+        writeln!(out, "| Package | Command | Duration |").unwrap();
+        writeln!(out, "| --- | --- | ---: |").unwrap();
+        for run in timing::slowest(runs, timing::SLOWEST_COUNT) {
+            writeln!(
+                out,
+                "| {} | {} | {:.1}s{} |",
+                run.package,
+                run.command,
+                run.duration.as_secs_f64(),
+                if run.cold_start { " (cold start)" } else { "" }
+            )
+            .unwrap();
+        }
+    }
+
+    if !additivity.is_empty() {
+        writeln!(out, "\n### Additivity\n").unwrap();
+        for finding in additivity {
+            writeln!(out, "- :warning: {finding}").unwrap();
+        }
+    }
+
+    if let Some(cutoff) = runs.iter().find(|run| run.status == Status::NotRun) {
+        writeln!(out, "\n### Not run (budget exceeded)\n").unwrap();
+        write!(
+            out,
+            "--budget was exceeded, so {} {} not run, starting at <code>{}</code> on {}",
+            total.not_run,
+            if total.not_run == 1 { "command was" } else { "commands were" },
+            cutoff.command.replace('`', ""),
+            cutoff.package
+        )
+        .unwrap();
+        if let Some(toolchain) = &cutoff.toolchain {
+            write!(out, " ({toolchain})").unwrap();
+        }
+        writeln!(out, ".\n").unwrap();
+        writeln!(out, "| Package | Not run |").unwrap();
+        writeln!(out, "| --- | ---: |").unwrap();
+        for (package, c) in packages.iter().filter(|(_, c)| c.not_run > 0) {
+            writeln!(out, "| {package} | {} |", c.not_run).unwrap();
+        }
+    }
+
+    if total.failed > 0 {
+        writeln!(out, "\n### Failures").unwrap();
+        for run in runs.iter().filter(|run| run.status == Status::Failed) {
+            writeln!(out, "\n<details>").unwrap();
+            let command = run.command.replace('`', "");
+            write!(out, "<summary>{}: <code>{command}</code>", run.package).unwrap();
+            if let Some(toolchain) = &run.toolchain {
+                write!(out, " ({toolchain})").unwrap();
+            }
+            writeln!(out, "</summary>\n").unwrap();
+            writeln!(out, "Features: `{}`\n", run.features).unwrap();
+            if !run.stderr.is_empty() {
+                writeln!(out, "```text").unwrap();
+                for line in &run.stderr {
+                    writeln!(out, "{line}").unwrap();
+                }
+                writeln!(out, "```\n").unwrap();
+            }
+            writeln!(out, "</details>").unwrap();
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, VecDeque},
+        time::Duration,
+    };
+
+    use super::{markdown, Run, Status};
+
+    fn run(toolchain: &str) -> Run {
+        Run {
+            id: String::new(),
+            package: "a".to_owned(),
+            toolchain: Some(toolchain.to_owned()),
+            command: "`cargo check`".to_owned(),
+            features: String::new(),
+            requested_features: BTreeSet::new(),
+            enabled_features: None,
+            doc_items: None,
+            status: Status::Passed,
+            duration: Duration::ZERO,
+            cold_start: false,
+            error: None,
+            stderr: VecDeque::new(),
+            cfgs: vec![],
+            sccache: None,
+        }
+    }
+
+    #[test]
+    fn toolchains_in_version_order() {
+        let runs: Vec<_> = ["1.100", "1.10", "1.9", "1.64"].into_iter().map(run).collect();
+        let out = markdown(&runs, &[], false);
+        assert!(out.contains("| Package | 1.9 | 1.10 | 1.64 | 1.100 |\n"), "{out}");
+    }
+}
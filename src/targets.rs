@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Validation of --target.
+//
+// A typo in a target triple makes every run fail, possibly after installing
+// the toolchains for --version-range, so the triples are checked against the
+// targets known to the toolchain before starting the runs. With
+// --version-range, the targets are also checked against the targets available
+// on each toolchain, because old toolchains may not have new (or renamed)
+// targets. The checks are skipped if the list of targets is not available.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::OsString,
+    fmt::Write as _,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{context::Context, flags, rustup, term, version::Version};
+
+/// Targets that were renamed, as pairs of the old name and the new name.
+const RENAMED: &[(&str, &str)] = &[("wasm32-wasi", "wasm32-wasip1")];
+
+/// Checks that the targets specified by --target are known to the toolchain.
+pub(crate) fn validate(targets: &[String]) -> Result<()> {
+    for target in targets {
+        if is_custom(target) || rustup_targets().is_some_and(|known| known.contains(target)) {
+            continue;
+        }
+        // `rustup target list` only lists the targets that rustup can install
+        // the standard library for, so tier 3 targets (used with -Z build-std)
+        // are only listed by rustc.
+        let Some(known) = rustc_targets() else {
+            if rustup_targets().is_none() && term::verbose() {
+                info!("skipped validation of --target: unable to get the list of targets");
+            }
+            continue;
+        };
+        if known.contains(target) {
+            continue;
+        }
+        let known: BTreeSet<_> = known
+            .iter()
+            .chain(rustup_targets().into_iter().flatten())
+            .map(String::as_str)
+            .collect();
+        let suggestion = flags::most_similar(target, &known)
+            .map_or_else(String::new, |similar| format!("; did you mean `{similar}`?"));
+        bail!(
+            "unknown target `{target}` specified by --target{suggestion}\n\n\
+             if this is a custom target, specify the path to its target specification JSON file"
+        );
+    }
+    Ok(())
+}
+
+/// Returns the targets specified by --target to run with on each version.
+///
+/// Fails with the list of the targets that are not available on some of the
+/// versions, or skips them on those versions with --skip-unavailable-targets.
+pub(crate) fn for_versions(
+    cx: &Context,
+    versions: &[Version],
+) -> Result<BTreeMap<Version, Vec<String>>> {
+    let mut targets: BTreeMap<_, _> = versions.iter().map(|&v| (v, cx.target.clone())).collect();
+    if cx.target.is_empty() {
+        return Ok(targets);
+    }
+    // Listing the targets of a toolchain that is not installed installs it,
+    // which --print-command-list and --no-install must not do. The toolchains
+    // that are not installed are reported by --no-install later.
+    let no_install = cx.print_command_list || cx.no_install;
+    let installed = if no_install {
+        cmd!("rustup", "toolchain", "list").read().unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let mut unavailable: BTreeMap<&str, Vec<Version>> = BTreeMap::new();
+    for &version in versions {
+        let toolchain = version.to_string();
+        if no_install {
+            if !rustup::is_installed(&installed, &toolchain) {
+                continue;
+            }
+        } else {
+            // The targets of a toolchain can only be listed after installing it.
+            let print_output = true;
+            let lock = !cx.no_rustup_lock;
+            rustup::install_toolchain(&toolchain, &[], print_output, cx.log_group, lock)?;
+        }
+        let list = match cmd!("rustup", "target", "list", "--toolchain", &toolchain).read() {
+            Ok(list) => list,
+            Err(e) => {
+                if term::verbose() {
+                    info!("skipped checking the targets available on {toolchain}: {e:#}");
+                }
+                continue;
+            }
+        };
+        let Some(known) = parse_list(&list) else { continue };
+        for target in &cx.target {
+            if !is_custom(target) && !known.contains(target) {
+                unavailable.entry(target).or_default().push(version);
+            }
+        }
+    }
+    if unavailable.is_empty() {
+        return Ok(targets);
+    }
+
+    let list = |versions: &[Version]| {
+        versions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    };
+    if cx.skip_unavailable_targets {
+        for (&target, versions) in &unavailable {
+            warn!(
+                "skipping the runs for target `{target}` on {}, because it is not available on \
+                 {} (--skip-unavailable-targets){}",
+                list(versions),
+                if versions.len() == 1 { "this version" } else { "these versions" },
+                renamed_hint(target)
+            );
+            for version in versions {
+                targets.get_mut(version).unwrap().retain(|t| t != target);
+            }
+        }
+        return Ok(targets);
+    }
+    let mut msg =
+        "the following targets are not available on some versions of --version-range:".to_owned();
+    for (&target, versions) in &unavailable {
+        write!(msg, "\n    `{target}` on {}{}", list(versions), renamed_hint(target)).unwrap();
+    }
+    msg.push_str(
+        "\n\nuse --skip-unavailable-targets to skip the runs for these targets on these versions",
+    );
+    bail!("{msg}")
+}
+
+/// Returns a hint about the other name of a renamed target.
+fn renamed_hint(target: &str) -> String {
+    RENAMED
+        .iter()
+        .find_map(|&(old, new)| {
+            if target == old {
+                Some(new)
+            } else if target == new {
+                Some(old)
+            } else {
+                None
+            }
+        })
+        .map_or_else(String::new, |other| {
+            format!(" (the target may be available as `{other}` on these versions)")
+        })
+}
+
+/// Returns `true` if the target is a custom target, which is specified by the
+/// path to its target specification or found in RUST_TARGET_PATH.
+pub(crate) fn is_custom(target: &str) -> bool {
+    if Path::new(target).extension().is_some_and(|ext| ext == "json") {
+        return true;
+    }
+    env::var_os("RUST_TARGET_PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| dir.join(format!("{target}.json")).is_file())
+    })
+}
+
+/// Returns the targets listed by `rustup target list`, or `None` if rustup is
+/// not available.
+fn rustup_targets() -> Option<&'static BTreeSet<String>> {
+    static TARGETS: OnceLock<Option<BTreeSet<String>>> = OnceLock::new();
+    TARGETS.get_or_init(|| parse_list(&cmd!("rustup", "target", "list").read().ok()?)).as_ref()
+}
+
+/// Returns the targets listed by `rustc --print target-list`, or `None` if
+/// failed to get the list.
+fn rustc_targets() -> Option<&'static BTreeSet<String>> {
+    static TARGETS: OnceLock<Option<BTreeSet<String>>> = OnceLock::new();
+    TARGETS
+        .get_or_init(|| {
+            let rustc = env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc"));
+            parse_list(&cmd!(rustc, "--print", "target-list").read().ok()?)
+        })
+        .as_ref()
+}
+
+/// Parses the list of targets, one per line. Each line of the output of
+/// `rustup target list` may be followed by a status, e.g., `(installed)`.
+fn parse_list(list: &str) -> Option<BTreeSet<String>> {
+    let targets: BTreeSet<_> =
+        list.lines().filter_map(|line| line.split_whitespace().next()).map(str::to_owned).collect();
+    (!targets.is_empty()).then_some(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_list, renamed_hint};
+
+    #[test]
+    fn list() {
+        let rustup = "\
+aarch64-apple-darwin
+x86_64-unknown-linux-gnu (installed)
+x86_64-unknown-linux-musl
+";
+        assert_eq!(parse_list(rustup).unwrap().into_iter().collect::<Vec<_>>(), [
+            "aarch64-apple-darwin",
+            "x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-musl",
+        ]);
+        assert_eq!(parse_list(""), None);
+    }
+
+    #[test]
+    fn renamed() {
+        assert_eq!(
+            renamed_hint("wasm32-wasip1"),
+            " (the target may be available as `wasm32-wasi` on these versions)"
+        );
+        assert_eq!(
+            renamed_hint("wasm32-wasi"),
+            " (the target may be available as `wasm32-wasip1` on these versions)"
+        );
+        assert_eq!(renamed_hint("x86_64-unknown-linux-gnu"), "");
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::summary::Run;
+
+/// The number of the slowest commands displayed by --slow-threshold-factor.
+pub(crate) const SLOWEST_COUNT: usize = 5;
+
+/// Runs of a package are compared with the median only after this number of
+/// runs of the package have been completed, to avoid noisy comparisons.
+const MIN_COMPLETED_RUNS: usize = 2;
+
+/// Durations of the completed runs per package.
+#[derive(Default)]
+pub(crate) struct Durations {
+    packages: HashMap<String, Vec<Duration>>,
+    /// --discard-warmup-timing
+    discard_warmup: bool,
+}
+
+impl Durations {
+    pub(crate) fn new(discard_warmup: bool) -> Self {
+        Self { packages: HashMap::new(), discard_warmup }
+    }
+
+    /// Records the duration of a run of `package`.
+    ///
+    /// Returns the median of the previously completed runs of the package and
+    /// the ratio of `duration` to it, if `duration` exceeds the median
+    /// multiplied by `factor`. Cold starts are neither compared nor recorded
+    /// with --discard-warmup-timing.
+    pub(crate) fn record(
+        &mut self,
+        package: &str,
+        duration: Duration,
+        cold_start: bool,
+        factor: f64,
+    ) -> Option<(Duration, f64)> {
+        if cold_start && self.discard_warmup {
+            return None;
+        }
+        let durations = self.packages.entry(package.to_owned()).or_default();
+        let res = if durations.len() >= MIN_COMPLETED_RUNS {
+            exceeds(median(durations), duration, factor)
+        } else {
+            None
+        };
+        durations.push(duration);
+        res
+    }
+}
+
+/// Packages on which a command has been run, used to mark the first run on
+/// each package (and each toolchain of --version-range) as a cold start.
+///
+/// The first run builds the dependencies of the package, so it is usually
+/// much slower than the following runs, which reuse them.
+#[derive(Default)]
+pub(crate) struct Warmup {
+    warmed: HashSet<(String, Option<String>)>,
+}
+
+impl Warmup {
+    /// Returns `true` if no command has been run on the package with the
+    /// toolchain yet.
+    pub(crate) fn cold_start(&mut self, package: &str, toolchain: Option<&str>) -> bool {
+        self.warmed.insert((package.to_owned(), toolchain.map(str::to_owned)))
+    }
+}
+
+fn median(durations: &[Duration]) -> Duration {
+    let mut durations = durations.to_vec();
+    durations.sort_unstable();
+    let mid = durations.len() / 2;
+    if durations.len() % 2 == 0 {
+        (durations[mid - 1] + durations[mid]) / 2
+    } else {
+        durations[mid]
+    }
+}
+
+fn exceeds(median: Duration, duration: Duration, factor: f64) -> Option<(Duration, f64)> {
+    if median.is_zero() {
+        return None;
+    }
+    let ratio = duration.as_secs_f64() / median.as_secs_f64();
+    if ratio > factor {
+        Some((median, ratio))
+    } else {
+        None
+    }
+}
+
+/// Returns the slowest runs, slowest first.
+pub(crate) fn slowest(runs: &[Run], count: usize) -> Vec<&Run> {
+    let mut runs: Vec<_> = runs.iter().filter(|run| run.status.ran()).collect();
+    runs.sort_by_key(|run| Reverse(run.duration));
+    runs.truncate(count);
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{exceeds, median, Durations, Warmup};
+
+    fn secs(secs: &[u64]) -> Vec<Duration> {
+        secs.iter().map(|&s| Duration::from_secs(s)).collect()
+    }
+
+    #[test]
+    fn median_and_threshold() {
+        assert_eq!(median(&secs(&[3])), Duration::from_secs(3));
+        assert_eq!(median(&secs(&[5, 1, 3])), Duration::from_secs(3));
+        assert_eq!(median(&secs(&[4, 1, 2, 100])), Duration::from_secs(3));
+
+        let m = Duration::from_secs(2);
+        assert_eq!(exceeds(m, Duration::from_secs(6), 3.0), None);
+        assert_eq!(exceeds(m, Duration::from_secs(7), 3.0), Some((m, 3.5)));
+        assert_eq!(exceeds(Duration::ZERO, Duration::from_secs(7), 3.0), None);
+
+        let mut durations = Durations::default();
+        // Not compared until enough runs have been completed.
+        assert_eq!(durations.record("a", Duration::from_secs(1), true, 3.0), None);
+        assert_eq!(durations.record("a", Duration::from_secs(100), false, 3.0), None);
+        // Median of [1, 100] is 50.5.
+        assert_eq!(durations.record("a", Duration::from_secs(150), false, 3.0), None);
+        assert_eq!(
+            durations.record("a", Duration::from_secs(1000), false, 3.0),
+            Some((Duration::from_secs(100), 10.0))
+        );
+        // Packages are tracked separately.
+        assert_eq!(durations.record("b", Duration::from_secs(1000), true, 3.0), None);
+    }
+
+    #[test]
+    fn discard_warmup() {
+        // The cold start is excluded from the median.
+        let mut durations = Durations::new(true);
+        assert_eq!(durations.record("a", Duration::from_secs(100), true, 3.0), None);
+        assert_eq!(durations.record("a", Duration::from_secs(10), false, 3.0), None);
+        assert_eq!(durations.record("a", Duration::from_secs(10), false, 3.0), None);
+        assert_eq!(
+            durations.record("a", Duration::from_secs(40), false, 3.0),
+            Some((Duration::from_secs(10), 4.0))
+        );
+        // Cold starts are never compared.
+        assert_eq!(durations.record("a", Duration::from_secs(1000), true, 3.0), None);
+        assert_eq!(
+            durations.record("a", Duration::from_secs(50), false, 3.0),
+            Some((Duration::from_secs(10), 5.0))
+        );
+
+        // Without --discard-warmup-timing, the cold start raises the median
+        // and hides the slow run.
+        let mut durations = Durations::new(false);
+        assert_eq!(durations.record("a", Duration::from_secs(100), true, 3.0), None);
+        assert_eq!(durations.record("a", Duration::from_secs(10), false, 3.0), None);
+        assert_eq!(durations.record("a", Duration::from_secs(40), false, 3.0), None);
+
+        let mut warmup = Warmup::default();
+        assert!(warmup.cold_start("a", None));
+        assert!(!warmup.cold_start("a", None));
+        assert!(warmup.cold_start("a", Some("1.70")));
+        assert!(warmup.cold_start("b", None));
+        assert!(!warmup.cold_start("a", Some("1.70")));
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --trace-file.
+//
+// Writes a log of the decisions of cargo-hack to a file, regardless of
+// --verbose, for bug reports such as "cargo-hack skipped my package" or "the
+// powerset is missing combination X". Each line is prefixed with the time
+// elapsed since the start and the section of the decision:
+//
+// [     0.000s] [args] argv: ["cargo-hack", "hack", "check", "--feature-powerset"]
+// [     0.100s] [metadata] workspace root: /path/to/ws (2 members, 2 packages), ...
+// [     0.145s] [features] package `a`: normal features: [alloc, std], ...
+// [     0.145s] [plan] package `a`: combination [alloc, std] deduplicated because ...
+// [     0.150s] [manifest] removing ["dev-dependencies"] from /path/to/ws/a/Cargo.toml
+// [     0.983s] [command] `cargo check --manifest-path a/Cargo.toml` exited with exit status: 0
+//
+// The values of the environment variables set for the commands (e.g.,
+// RUSTFLAGS, which may contain secrets) are redacted. Lines are written
+// unbuffered, so the file is complete even if cargo-hack is killed.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs::File,
+    io::Write as _,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::cli::Args;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE: Mutex<Option<(File, Instant)>> = Mutex::new(None);
+
+macro_rules! trace {
+    ($section:expr, $($msg:tt)*) => {
+        if crate::trace::enabled() {
+            crate::trace::write($section, format_args!($($msg)*));
+        }
+    };
+}
+
+/// Creates the trace file and starts writing the trace to it.
+pub(crate) fn init(path: &Path) -> Result<()> {
+    let mut file = File::create(path)
+        .with_context(|| format!("failed to create trace file `{}`", path.display()))?;
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(
+        file,
+        "# cargo-hack {} trace, started at {}.{:03} (seconds since the Unix epoch)",
+        env!("CARGO_PKG_VERSION"),
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    )
+    .with_context(|| format!("failed to write to trace file `{}`", path.display()))?;
+    *TRACE.lock().unwrap() = Some((file, Instant::now()));
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Writes the arguments of cargo-hack as parsed, including those from the
+/// `CARGO_HACK_*` environment variables.
+pub(crate) fn args(args: &Args, cargo: &OsStr) {
+    if !enabled() {
+        return;
+    }
+    let argv: Vec<_> = env::args_os().map(|a| a.to_string_lossy().into_owned()).collect();
+    trace!("args", "argv: {argv:?}");
+    trace!("args", "current directory: {:?}", env::current_dir().ok());
+    trace!("args", "cargo: {cargo:?}");
+    trace!("args", "manifest path: {:?}", args.manifest_path);
+    trace!(
+        "args",
+        "subcommand: {:?}, leading args: {:?}, trailing args: {:?}",
+        args.subcommand,
+        args.leading_args,
+        args.trailing_args
+    );
+    trace!(
+        "args",
+        "packages: workspace: {}, package: {:?}, exclude: {:?}, ignore private: {}",
+        args.workspace,
+        args.package,
+        args.exclude,
+        args.ignore_private
+    );
+    trace!(
+        "args",
+        "features: each feature: {}, feature powerset: {}, depth: {:?}, features: {:?}, \
+         include features: {:?}, exclude features: {:?}, group features: {:?}, optional deps: \
+         {:?}, include deps features: {}",
+        args.each_feature,
+        args.feature_powerset,
+        args.depth,
+        args.features,
+        args.include_features,
+        args.exclude_features,
+        args.group_features,
+        args.optional_deps,
+        args.include_deps_features
+    );
+    trace!(
+        "args",
+        "feature constraints: at least one of: {:?}, mutually exclusive: {:?}, exactly one of: \
+         {:?}, exclude no default features: {}, exclude all features: {}",
+        args.at_least_one_of,
+        args.mutually_exclusive_features,
+        args.exactly_one_of,
+        args.exclude_no_default_features,
+        args.exclude_all_features
+    );
+    trace!(
+        "args",
+        "toolchains: version range: [{}], target: {:?}",
+        args.version_range.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+        args.target
+    );
+    trace!(
+        "args",
+        "manifests: no dev deps: {}, remove dev deps: {}, no private: {}",
+        args.no_dev_deps,
+        args.remove_dev_deps,
+        args.no_private
+    );
+}
+
+/// Returns `true` if --trace-file is used.
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes a line to the trace file. Use the `trace!` macro instead, which
+/// does not format the message if --trace-file is not used.
+pub(crate) fn write(section: &str, msg: std::fmt::Arguments<'_>) {
+    let mut trace = TRACE.lock().unwrap();
+    if let Some((file, start)) = &mut *trace {
+        let elapsed = start.elapsed().as_secs_f64();
+        // Write the line at once so that lines from threads are not interleaved.
+        let line = format!("[{elapsed:>10.3}s] [{section}] {msg}\n");
+        // Errors are ignored, because tracing must not change the result of the runs.
+        let _ = file.write_all(line.as_bytes());
+    }
+}
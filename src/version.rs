@@ -4,7 +4,9 @@ use std::{fmt, str::FromStr};
 
 use anyhow::{bail, Context as _, Error, Result};
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+// Note: `1.70` (patch: None) is ordered before `1.70.0`, so a rust-version
+// with a patch version is not satisfied by a toolchain without it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct Version {
     pub(crate) major: u32,
     pub(crate) minor: u32,
@@ -42,6 +44,35 @@ impl FromStr for Version {
     }
 }
 
+/// Parses the value of the `package.rust-version` field.
+///
+/// Cargo only accepts `<major>.<minor>[.<patch>]`, but manifests that are read
+/// without cargo (on cargo 1.57 and older) may have a caret requirement (e.g.,
+/// `^1.65`) or a trailing wildcard (e.g., `1.65.x` or `1.65.*`), which are
+/// treated as the version without them.
+pub(crate) fn parse_rust_version(s: &str) -> Result<Version> {
+    let s = s.trim();
+    let s = s.strip_prefix('^').map_or(s, str::trim_start);
+    let s = [".x", ".X", ".*"].iter().find_map(|w| s.strip_suffix(w)).unwrap_or(s);
+    s.parse()
+}
+
+/// Returns the first Rust version that supports the edition, or `None` if the
+/// edition is unknown.
+///
+/// Edition 2015 is supported by all versions, so this returns 1.26, the oldest
+/// version of Cargo supported by cargo-hack.
+pub(crate) fn edition_msrv(edition: &str) -> Option<Version> {
+    let minor = match edition {
+        "2015" => 26,
+        "2018" => 31,
+        "2021" => 56,
+        "2024" => 85,
+        _ => return None,
+    };
+    Some(Version { major: 1, minor, patch: None })
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub(crate) enum MaybeVersion {
     Version(Version),
@@ -111,6 +142,28 @@ impl FromStr for VersionRange {
     }
 }
 
+/// The order of the runs on the toolchains of --version-range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum VersionOrder {
+    OldestFirst,
+    NewestFirst,
+}
+
+impl FromStr for VersionOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest-first" => Ok(Self::OldestFirst),
+            "newest-first" => Ok(Self::NewestFirst),
+            other => bail!(
+                "argument for --version-order must be newest-first or oldest-first, but found \
+                 `{other}`"
+            ),
+        }
+    }
+}
+
 fn maybe_version(s: &str) -> Result<Option<MaybeVersion>, Error> {
     if s.is_empty() {
         Ok(None)
@@ -118,3 +171,42 @@ fn maybe_version(s: &str) -> Result<Option<MaybeVersion>, Error> {
         s.parse().map(MaybeVersion::Version).map(Some)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_rust_version, Version};
+
+    #[test]
+    fn rust_version() {
+        let v = |minor, patch| Version { major: 1, minor, patch };
+        for (s, expected) in [
+            ("1.65", v(65, None)),
+            ("1.65.0", v(65, Some(0))),
+            ("1.65.2", v(65, Some(2))),
+            ("^1.65", v(65, None)),
+            ("^ 1.65.1", v(65, Some(1))),
+            (" 1.65 ", v(65, None)),
+            ("1.65.x", v(65, None)),
+            ("1.65.X", v(65, None)),
+            ("1.65.*", v(65, None)),
+            ("^1.65.*", v(65, None)),
+        ] {
+            assert_eq!(parse_rust_version(s).unwrap(), expected, "{s}");
+        }
+        for s in [
+            "",
+            "^",
+            "1",
+            "1.x",
+            "1.*",
+            "1.65.x.x",
+            ">=1.65",
+            "~1.65",
+            "1.65-nightly",
+            "1.65.0.1",
+            "x",
+        ] {
+            assert!(parse_rust_version(s).is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Implementation of --why.
+//
+// Explains whether the combination of features specified by --why is in the
+// plan of --feature-powerset for each package, and if not, which rule removed
+// it. Nothing is run.
+
+use std::ptr;
+
+use crate::{
+    context::Context,
+    features::{self, Feature, Removed},
+    PackageId,
+};
+
+/// Prints whether the combination of features specified by --why is run on the package.
+///
+/// `candidates` are the features to combine, `plan` is the powerset of them,
+/// and `removed` are the combinations removed from the powerset.
+pub(crate) fn explain(
+    cx: &Context,
+    id: &PackageId,
+    candidates: &[&Feature],
+    plan: &[Vec<&Feature>],
+    removed: &[(Vec<&Feature>, Removed)],
+) {
+    let why = cx.why.as_deref().unwrap();
+    let name = &cx.packages(id).name;
+    match reason(cx, id, why, candidates, plan, removed) {
+        None => info!("`{}` is run on package `{name}`", why.join(",")),
+        Some(reason) => info!("`{}` is not run on package `{name}`: {reason}", why.join(",")),
+    }
+}
+
+/// Returns the reason why the combination of features `why` is not run, or
+/// `None` if it is run.
+fn reason(
+    cx: &Context,
+    id: &PackageId,
+    why: &[String],
+    candidates: &[&Feature],
+    plan: &[Vec<&Feature>],
+    removed: &[(Vec<&Feature>, Removed)],
+) -> Option<String> {
+    let pkg_features = cx.pkg_features(id);
+    let mut combination: Vec<&Feature> = vec![];
+    for name in why {
+        if let Some(&f) = candidates.iter().find(|f| f.matches(name)) {
+            if !combination.iter().any(|&g| ptr::eq(f, g)) {
+                combination.push(f);
+            }
+        } else if cx.features.contains(name) {
+            return Some(format!("`{name}` is always enabled by --features"));
+        } else if cx.exclude_features.contains(name) {
+            return Some(format!("`{name}` is excluded by --exclude-features"));
+        } else if pkg_features.unused_optional_deps().contains(name) {
+            return Some(format!("optional dependency `{name}` is not used on the target"));
+        } else if !pkg_features.contains(name) {
+            return Some(format!(
+                "it is never generated because feature `{name}` does not exist in the package"
+            ));
+        } else {
+            return Some(format!(
+                "it is never generated because `{name}` is not in the features to combine \
+                 (e.g., an optional dependency without --optional-deps, or a feature not in \
+                 --include-features)"
+            ));
+        }
+    }
+    // A group is always enabled as a whole.
+    for f in &combination {
+        if let Some(other) = f.as_group().iter().find(|&g| !why.contains(g)) {
+            return Some(format!(
+                "`{other}` is always enabled together with the other features in group `{}`",
+                f.as_group().join(",")
+            ));
+        }
+    }
+
+    // The powerset preserves the order of the candidates.
+    combination.sort_by_key(|&f| candidates.iter().position(|&c| ptr::eq(f, c)));
+    let is_same = |fs: &[&Feature]| {
+        fs.len() == combination.len() && fs.iter().zip(&combination).all(|(&a, &b)| ptr::eq(a, b))
+    };
+    if plan.iter().any(|fs| is_same(fs)) {
+        return None;
+    }
+    if let Some((_, reason)) = removed.iter().find(|(fs, _)| is_same(fs)) {
+        return Some(reason.to_string());
+    }
+    if let Some(max) = cx.max_group_combo {
+        let groups = features::group_count(&combination);
+        if groups > max {
+            return Some(format!(
+                "it is never generated because it has {groups} groups of --group-features, more \
+                 than {max} (--max-group-combo)"
+            ));
+        }
+    }
+    let depth =
+        cx.depth.expect("every combination is generated without --depth and --max-group-combo");
+    if cx.depth_counts_group_members {
+        Some(format!(
+            "it is never generated because it has more than {depth} features counting each \
+             member of groups (--depth, --depth-counts-group-members)"
+        ))
+    } else {
+        Some(format!("it is never generated because it has more than {depth} features (--depth)"))
+    }
+}
@@ -0,0 +1,410 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+// Discovery of workspace members without `cargo metadata`.
+//
+// `cargo hack --remove-dev-deps` (without a subcommand) only needs the
+// manifests of workspace members, so it should work even if `cargo metadata`
+// fails, e.g., because a dependency cannot be loaded. This reads the
+// manifests directly, following cargo's rules for members:
+//
+// - The root manifest is a member if it has [package].
+// - `workspace.members` are expanded as globs (only `*` and `?` are supported).
+// - Path dependencies of members in the workspace directory are members.
+// - Glob matches and path dependencies in `workspace.exclude` are not members,
+//   and neither are path dependencies that are workspace roots themselves.
+//
+// If the manifests cannot be handled by this (e.g., unsupported glob patterns),
+// this falls back to `cargo metadata --no-deps --offline`.
+//
+// This also provides `locate_root`, which asks cargo for the root of the
+// workspace so that `cargo metadata` is always run on the same workspace that
+// cargo selects for the current directory or --manifest-path.
+
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, format_err, Context as _, Result};
+
+use crate::fs;
+
+const DEPENDENCY_TABLES: &[&str] = &[
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+
+pub(crate) struct Workspace {
+    /// The directory of the root manifest.
+    pub(crate) root: PathBuf,
+    /// Paths to the manifests of the members.
+    pub(crate) members: Vec<PathBuf>,
+}
+
+impl Workspace {
+    pub(crate) fn discover(manifest_path: Option<&str>, cargo: &OsStr) -> Result<Self> {
+        match Self::from_manifests(manifest_path) {
+            Ok(workspace) => Ok(workspace),
+            Err(e) => {
+                info!("{e:#}; falling back to `cargo metadata --no-deps --offline`");
+                Self::from_metadata(manifest_path, cargo)
+            }
+        }
+    }
+
+    pub(crate) fn from_manifests(manifest_path: Option<&str>) -> Result<Self> {
+        let current_dir = env::current_dir()?;
+        let start = match manifest_path {
+            Some(path) => normalize(&current_dir.join(path)),
+            None => current_dir
+                .ancestors()
+                .map(|dir| dir.join("Cargo.toml"))
+                .find(|path| path.is_file())
+                .ok_or_else(|| {
+                    format_err!(
+                        "could not find `Cargo.toml` in `{}` or any parent directory",
+                        current_dir.display()
+                    )
+                })?,
+        };
+        let doc = read(&start)?;
+
+        let root_manifest = if doc.contains_key("workspace") {
+            start
+        } else if let Some(path) =
+            doc.get("package").and_then(|p| p.get("workspace")).and_then(toml_edit::Item::as_str)
+        {
+            normalize(&start.parent().unwrap().join(path).join("Cargo.toml"))
+        } else {
+            let mut root_manifest = start.clone();
+            for dir in start.parent().unwrap().ancestors().skip(1) {
+                let path = dir.join("Cargo.toml");
+                if !path.is_file() {
+                    continue;
+                }
+                let doc = read(&path)?;
+                if doc.contains_key("workspace") {
+                    if members(&path, &doc)?.contains(&start) {
+                        root_manifest = path;
+                    }
+                    break;
+                }
+            }
+            root_manifest
+        };
+        let root = root_manifest.parent().unwrap().to_owned();
+        let doc = read(&root_manifest)?;
+        let members = if doc.contains_key("workspace") {
+            members(&root_manifest, &doc)?
+        } else {
+            vec![root_manifest]
+        };
+        Ok(Self { root, members })
+    }
+
+    fn from_metadata(manifest_path: Option<&str>, cargo: &OsStr) -> Result<Self> {
+        let mut cmd = cmd!(cargo, "metadata", "--format-version=1", "--no-deps", "--offline");
+        if let Some(manifest_path) = manifest_path {
+            cmd.arg("--manifest-path");
+            cmd.arg(manifest_path);
+        }
+        let metadata: serde_json::Value = serde_json::from_str(&cmd.read()?)
+            .with_context(|| format!("failed to parse output from {cmd}"))?;
+        let root = metadata["workspace_root"]
+            .as_str()
+            .ok_or_else(|| format_err!("failed to parse `workspace_root` field from metadata"))?;
+        // With --no-deps, `packages` only contains the workspace members.
+        let members = metadata["packages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|p| p["manifest_path"].as_str().map(PathBuf::from))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format_err!("failed to parse `packages` field from metadata"))?;
+        Ok(Self { root: root.into(), members })
+    }
+}
+
+/// Returns the path to the root manifest of the workspace, located by
+/// `cargo locate-project --workspace`.
+///
+/// Returns an error if cargo is too old to support `--workspace` flag of
+/// `cargo locate-project`, or if the workspace cannot be located.
+pub(crate) fn locate_root(manifest_path: Option<&str>, cargo: &OsStr) -> Result<PathBuf> {
+    let mut cmd = cmd!(cargo, "locate-project", "--workspace");
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path");
+        cmd.arg(manifest_path);
+    }
+    // Use json format because `--message-format plain` option of
+    // `cargo locate-project` has been added in Rust 1.48.
+    let locate_project: serde_json::Value = serde_json::from_str(&cmd.read()?)
+        .with_context(|| format!("failed to parse output from {cmd}"))?;
+    let root = locate_project["root"]
+        .as_str()
+        .ok_or_else(|| format_err!("failed to parse `root` field from output of {cmd}"))?;
+    Ok(root.into())
+}
+
+/// Returns the manifests of the members of the workspace whose root manifest is `root_manifest`.
+fn members(root_manifest: &Path, doc: &toml_edit::DocumentMut) -> Result<Vec<PathBuf>> {
+    let root = root_manifest.parent().unwrap();
+    let workspace = doc["workspace"].as_table_like().context("`workspace` must be a table")?;
+    let strings = |key: &str| -> Vec<&str> {
+        workspace
+            .get(key)
+            .and_then(toml_edit::Item::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(toml_edit::Value::as_str)
+            .collect()
+    };
+    let patterns = strings("members");
+    let exclude: Vec<_> =
+        strings("exclude").into_iter().map(|e| normalize(&root.join(e))).collect();
+    let explicit: Vec<_> = patterns.iter().map(|p| normalize(&root.join(p))).collect();
+    let is_member = |dir: &Path| {
+        dir.starts_with(root)
+            && (explicit.iter().any(|m| dir.starts_with(m))
+                || !exclude.iter().any(|e| dir.starts_with(e)))
+    };
+
+    let mut queue = vec![];
+    if doc.contains_key("package") {
+        queue.push(root_manifest.to_owned());
+    }
+    for pattern in patterns {
+        for dir in glob(root, pattern)? {
+            let path = dir.join("Cargo.toml");
+            if path.is_file() && is_member(&dir) {
+                queue.push(path);
+            }
+        }
+    }
+
+    let mut members = BTreeSet::new();
+    while let Some(path) = queue.pop() {
+        if members.contains(&path) {
+            continue;
+        }
+        let member = read(&path)?;
+        for dep_dir in path_deps(&member, path.parent().unwrap(), doc, root) {
+            let dep_dir = normalize(&dep_dir);
+            let dep_path = dep_dir.join("Cargo.toml");
+            // Path dependencies that are not loadable are not members.
+            if !is_member(&dep_dir)
+                || !dep_path.is_file()
+                || read(&dep_path)?.contains_key("workspace")
+            {
+                continue;
+            }
+            queue.push(dep_path);
+        }
+        members.insert(path);
+    }
+    Ok(members.into_iter().collect())
+}
+
+/// Returns the directories of the path dependencies of the manifest in `dir`.
+///
+/// Dependencies inherited from the workspace (`workspace = true`) are resolved
+/// with `[workspace.dependencies]` of the root manifest in `root_dir`.
+fn path_deps<'a>(
+    doc: &'a toml_edit::DocumentMut,
+    dir: &'a Path,
+    root: &'a toml_edit::DocumentMut,
+    root_dir: &'a Path,
+) -> impl Iterator<Item = PathBuf> + 'a {
+    let targets = doc.get("target").and_then(toml_edit::Item::as_table_like);
+    let tables = DEPENDENCY_TABLES.iter().filter_map(|&key| doc.get(key)).chain(
+        targets.into_iter().flat_map(|targets| {
+            targets
+                .iter()
+                .flat_map(|(_, target)| DEPENDENCY_TABLES.iter().filter_map(|&key| target.get(key)))
+        }),
+    );
+    let workspace_deps = root.get("workspace").and_then(|w| w.get("dependencies"));
+    tables.filter_map(toml_edit::Item::as_table_like).flat_map(|t| t.iter()).filter_map(
+        move |(name, dep)| {
+            if dep.get("workspace").and_then(toml_edit::Item::as_bool) == Some(true) {
+                Some(root_dir.join(workspace_deps?.get(name)?.get("path")?.as_str()?))
+            } else {
+                Some(dir.join(dep.get("path")?.as_str()?))
+            }
+        },
+    )
+}
+
+/// Returns the directories that match the glob pattern.
+fn glob(root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_owned()];
+    for component in pattern.split(['/', '\\']) {
+        if component.contains("**") || component.contains(['[', ']', '{', '}']) {
+            bail!("unsupported glob pattern `{pattern}` in workspace.members");
+        }
+        if !component.contains(['*', '?']) {
+            for dir in &mut dirs {
+                dir.push(component);
+            }
+            dirs.retain(|dir| dir.is_dir());
+            continue;
+        }
+        let mut matched = vec![];
+        for dir in dirs {
+            for entry in std::fs::read_dir(&dir)
+                .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+            {
+                let entry = entry?;
+                if entry.file_type()?.is_dir()
+                    && entry.file_name().to_str().is_some_and(|name| wildcard(component, name))
+                {
+                    matched.push(entry.path());
+                }
+            }
+        }
+        matched.sort_unstable();
+        dirs = matched;
+    }
+    Ok(dirs.iter().map(|dir| normalize(dir)).collect())
+}
+
+/// Returns whether `name` matches `pattern`, which may contain `*`, `?`, and
+/// `[...]` (e.g., `[abc]`, `[a-z]`, and `[!a-z]`). An unclosed `[` matches
+/// itself.
+pub(crate) fn wildcard(pattern: &str, name: &str) -> bool {
+    enum Token {
+        Char(char),
+        Any,
+        Star,
+        Class { negated: bool, ranges: Vec<(char, char)> },
+    }
+    impl Token {
+        fn matches(&self, c: char) -> bool {
+            match self {
+                Self::Char(p) => *p == c,
+                Self::Any => true,
+                Self::Star => false,
+                Self::Class { negated, ranges } => {
+                    ranges.iter().any(|&(start, end)| start <= c && c <= end) != *negated
+                }
+            }
+        }
+    }
+
+    let chars: Vec<_> = pattern.chars().collect();
+    let mut pattern = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => pattern.push(Token::Star),
+            '?' => pattern.push(Token::Any),
+            '[' => {
+                let mut j = i + 1;
+                let negated = chars.get(j) == Some(&'!');
+                if negated {
+                    j += 1;
+                }
+                // `]` right after `[` or `[!` is a member of the class.
+                let class_start = j;
+                let mut ranges = vec![];
+                while j < chars.len() && (chars[j] != ']' || j == class_start) {
+                    if j + 2 < chars.len() && chars[j + 1] == '-' && chars[j + 2] != ']' {
+                        ranges.push((chars[j], chars[j + 2]));
+                        j += 3;
+                    } else {
+                        ranges.push((chars[j], chars[j]));
+                        j += 1;
+                    }
+                }
+                if j < chars.len() {
+                    pattern.push(Token::Class { negated, ranges });
+                    i = j;
+                } else {
+                    pattern.push(Token::Char('['));
+                }
+            }
+            c => pattern.push(Token::Char(c)),
+        }
+        i += 1;
+    }
+
+    let name: Vec<_> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // The position of the last `*` in the pattern, and the position in the name it matched up to.
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p].matches(name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && matches!(pattern[p], Token::Star) {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|t| matches!(t, Token::Star))
+}
+
+/// Normalizes `.` and `..` in the path without accessing the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn read(path: &Path) -> Result<toml_edit::DocumentMut> {
+    fs::read_to_string(path)?
+        .parse()
+        .with_context(|| format!("failed to parse manifest `{}` as toml", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{normalize, wildcard};
+
+    #[test]
+    fn wildcard_match() {
+        assert!(wildcard("*", "foo"));
+        assert!(wildcard("foo*", "foo"));
+        assert!(wildcard("foo*", "foo-bar"));
+        assert!(wildcard("*-bar", "foo-bar"));
+        assert!(wildcard("f?o*r", "foo-bar"));
+        assert!(wildcard("*o*a*", "foo-bar"));
+        assert!(!wildcard("foo*", "bar-foo"));
+        assert!(!wildcard("f?o", "fo"));
+        assert!(!wildcard("*-baz", "foo-bar"));
+        assert!(wildcard("foo-[ab]*", "foo-bar"));
+        assert!(wildcard("foo-[a-c]ar", "foo-bar"));
+        assert!(wildcard("foo-[!x-z]ar", "foo-bar"));
+        assert!(wildcard("[]]", "]"));
+        assert!(wildcard("[!]]", "a"));
+        assert!(wildcard("foo[", "foo["));
+        assert!(wildcard("foo[-", "foo[-"));
+        assert!(!wildcard("foo-[!ab]*", "foo-bar"));
+        assert!(!wildcard("foo-[c-z]ar", "foo-bar"));
+        assert!(!wildcard("foo[", "foo"));
+
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/a/b/../../c/")), Path::new("/c"));
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::{
     env,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     path::{Path, PathBuf},
     process::Command,
     str,
@@ -34,6 +34,40 @@ pub(crate) fn has_rustup() -> bool {
     Command::new("rustup").arg("--version").output().is_ok()
 }
 
+/// Creates a rustup that fails loudly if cargo-hack runs `rustup toolchain
+/// <subcommand>` (e.g., to install toolchains) with a subcommand other than
+/// `allow`, and returns the directory containing it and PATH with it prepended.
+#[cfg(unix)]
+pub(crate) fn rustup_shim(allow: &[&str]) -> (tempfile::TempDir, OsString) {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let rustup = env::split_paths(&env::var_os("PATH").unwrap())
+        .map(|dir| dir.join("rustup"))
+        .find(|path| path.is_file())
+        .unwrap();
+    let bin_dir = tempfile::tempdir().unwrap();
+    let shim = bin_dir.path().join("rustup");
+    let mut conditions = vec!["[ \"$1\" = toolchain ]".to_owned()];
+    conditions.extend(allow.iter().map(|s| format!("[ \"$2\" != {s} ]")));
+    fs::write(
+        &shim,
+        format!(
+            "#!/bin/sh\nif {}; then\n  echo \"unexpected rustup $*\" >&2\n  exit 1\nfi\nexec {} \"$@\"\n",
+            conditions.join(" && "),
+            rustup.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = env::join_paths(
+        [bin_dir.path().to_owned()]
+            .into_iter()
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    (bin_dir, path)
+}
+
 static TEST_VERSION: LazyLock<Option<u32>> = LazyLock::new(|| {
     let toolchain = env::var_os("CARGO_HACK_TEST_TOOLCHAIN")?.to_string_lossy().parse().unwrap();
     // Install toolchain first to avoid toolchain installation conflicts.
@@ -193,7 +227,7 @@ impl AssertOutput {
 }
 
 #[track_caller]
-fn test_project(model: &str) -> (tempfile::TempDir, PathBuf) {
+pub(crate) fn test_project(model: &str) -> (tempfile::TempDir, PathBuf) {
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.path();
 
@@ -0,0 +1,8 @@
+// `a` uses `b`, but does not enable it.
+#[cfg(feature = "a")]
+pub fn a() {
+    b();
+}
+
+#[cfg(feature = "b")]
+pub fn b() {}
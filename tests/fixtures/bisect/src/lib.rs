@@ -0,0 +1,2 @@
+#[cfg(all(feature = "a", feature = "c"))]
+compile_error!("a and c");
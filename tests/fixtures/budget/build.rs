@@ -0,0 +1,7 @@
+use std::{thread, time::Duration};
+
+fn main() {
+    // Each feature combination reruns the build script, so every run takes at
+    // least this long.
+    thread::sleep(Duration::from_millis(1500));
+}
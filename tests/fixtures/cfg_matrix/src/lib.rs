@@ -0,0 +1,4 @@
+#![allow(unknown_lints, unexpected_cfgs)]
+
+#[cfg(all(foo, feature = "a"))]
+compile_error!("foo with a");
@@ -0,0 +1,5 @@
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_BUILD_INFO").is_some() {
+        println!("cargo:rustc-env=BUILD_INFO=1");
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(feature = "std")]
+pub fn std() {}
+
+pub fn alloc() -> bool {
+    cfg!(feature = "alloc")
+}
+
+#[cfg_attr(feature = "derive", derive(Debug))]
+pub struct S;
@@ -0,0 +1,10 @@
+/// Always documented.
+pub struct Base;
+
+/// Documented with `a`.
+#[cfg(feature = "a")]
+pub struct A;
+
+/// Documented with `c`. See also [`A`], which is not available without `a`.
+#[cfg(feature = "c")]
+pub struct C;
@@ -0,0 +1,12 @@
+/// ```
+/// assert_eq!(doctest::add(1, 2), 3);
+/// ```
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/// ```
+/// helper::help();
+/// ```
+#[cfg(feature = "helper")]
+pub fn help() {}
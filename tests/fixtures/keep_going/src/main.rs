@@ -1,6 +1,9 @@
-#[cfg(feature = "a")]
+#![allow(unknown_lints)]
+#![allow(unexpected_cfgs)]
+
+#[cfg(all(feature = "a", not(pass_a)))]
 compile_error!("`a` feature specified");
-#[cfg(not(feature = "a"))]
+#[cfg(all(not(feature = "a"), not(pass_no_a)))]
 compile_error!("`a` feature not specified");
 
 fn main() {}
@@ -0,0 +1,3 @@
+fn main() {
+    println!("args: {}", std::env::args().skip(1).collect::<Vec<_>>().join(" "));
+}
@@ -0,0 +1,5 @@
+#![no_std]
+
+pub use base::*;
+
+pub fn g() {}
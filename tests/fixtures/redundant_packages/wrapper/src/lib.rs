@@ -0,0 +1,5 @@
+//! Re-exports base.
+
+#![no_std]
+
+pub use base::*;
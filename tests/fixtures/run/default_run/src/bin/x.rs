@@ -0,0 +1 @@
+fn main() { println!("default_run x"); }
@@ -0,0 +1 @@
+fn main() { println!("multi_bin b"); }
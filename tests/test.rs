@@ -5,6 +5,7 @@
 mod auxiliary;
 
 use std::{
+    collections::BTreeSet,
     env::{self, consts::EXE_SUFFIX},
     path::{Path, MAIN_SEPARATOR},
     sync::Mutex,
@@ -72,6 +73,39 @@ fn removed_flags() {
     }
 }
 
+#[test]
+fn unknown_propagated_flags() {
+    cargo_hack(["check", "--each-feature", "--al-features"])
+        .assert_failure("real")
+        .stderr_contains(
+            "
+            unknown flag `--al-features` for `cargo check`; did you mean `--all-features`?
+            use --skip-flag-validation to skip this check
+            ",
+        )
+        .stderr_not_contains("running `cargo check");
+    cargo_hack(["check", "--each-feature", "--no-such-flag=1"])
+        .assert_failure("real")
+        .stderr_contains("unknown flag `--no-such-flag` for `cargo check`")
+        .stderr_not_contains("did you mean");
+
+    cargo_hack(["check", "--all-targets", "--message-format=short", "--config=build.jobs=1"])
+        .assert_success("real");
+    cargo_hack(["check", "--al-features", "--skip-flag-validation"])
+        .assert_failure("real")
+        .stderr_contains("running `cargo check --al-features` on real (1/1)");
+
+    // `cargo clippy --help` only lists the flags of clippy and refers to
+    // `cargo check --help` for the rest.
+    cargo_hack(["clippy", "--tests", "--release"])
+        .assert_success("real")
+        .stderr_contains("running `cargo clippy --tests --release` on real (1/1)");
+    cargo_hack(["clippy", "--tets"])
+        .assert_failure("real")
+        .stderr_contains("unknown flag `--tets` for `cargo clippy`; did you mean `--tests`?")
+        .stderr_not_contains("running `cargo clippy");
+}
+
 #[test]
 fn real_manifest() {
     cargo_hack(["check"])
@@ -162,9 +196,11 @@ fn real_ignore_private() {
                 "--no-private is not supported yet with workspace with private root crate",
             );
         } else {
+            // The root package is the only candidate, so no commands are left to run.
             cargo_hack(["check", flag])
-                .assert_success("real")
+                .assert_failure("real")
                 .stderr_contains("skipped running on private package `real`")
+                .stderr_contains("no commands to run; use --allow-empty-plan")
                 .stderr_not_contains(
                     "
                     running `cargo check` on member1
@@ -304,6 +340,205 @@ fn exclude() {
         running `cargo check` on member2
         ",
     );
+
+    // glob patterns
+    cargo_hack(["check", "--all", "--exclude", "*1"])
+        .assert_success("virtual")
+        .stderr_not_contains("running `cargo check` on member1")
+        .stderr_contains("running `cargo check` on member2");
+    cargo_hack(["check", "--all", "--exclude", "member?"])
+        .assert_success("virtual")
+        .stderr_not_contains(
+            "
+            running `cargo check` on member1
+            running `cargo check` on member2
+            excluded package(s) `member?` not found in workspace
+            ",
+        );
+    cargo_hack(["check", "--all", "--exclude", "[bt]*", "--exclude", "c[!a-n]re"])
+        .assert_success("deps_closure")
+        .stderr_contains(
+            "
+            running `cargo check` on app
+            running `cargo check` on util
+            ",
+        )
+        .stderr_not_contains(
+            "
+            on base
+            on core
+            on tool
+            ",
+        );
+    cargo_hack(["check", "--all", "--exclude", "foo-*"]).assert_failure("virtual").stderr_contains(
+        "
+            excluded package(s) `foo-*` not found in workspace
+            running `cargo check` on member1
+            running `cargo check` on member2
+            ",
+    );
+}
+
+#[test]
+fn package_with_deps() {
+    // app -> core -> base -(build)-> util, and tool -(dev)-> core
+    cargo_hack(["check", "--package-with-deps", "core"])
+        .assert_success("deps_closure")
+        .stderr_contains(
+            "
+            running `cargo check` on core
+            running `cargo check` on base
+            running `cargo check` on util
+            ",
+        )
+        .stderr_not_contains(
+            "
+            on app
+            on tool
+            ",
+        );
+
+    cargo_hack(["check", "--package-with-dependents", "core"])
+        .assert_success("deps_closure")
+        .stderr_contains(
+            "
+            running `cargo check` on app
+            running `cargo check` on core
+            ",
+        )
+        .stderr_not_contains(
+            "
+            on base
+            on tool
+            on util
+            ",
+        );
+    cargo_hack(["check", "--package-with-dependents", "core", "--with-dev-deps-closure"])
+        .assert_success("deps_closure")
+        .stderr_contains(
+            "
+            running `cargo check` on app
+            running `cargo check` on core
+            running `cargo check` on tool
+            ",
+        );
+
+    // The selection is merged with --package and --exclude.
+    cargo_hack(["check", "--package-with-deps", "base", "-p", "app", "--exclude", "util"])
+        .assert_success("deps_closure")
+        .stderr_contains(
+            "
+            running `cargo check` on app
+            running `cargo check` on base
+            ",
+        )
+        .stderr_not_contains(
+            "
+            on core
+            on util
+            ",
+        );
+
+    cargo_hack(["check", "--package-with-deps", "foo"])
+        .assert_failure("deps_closure")
+        .stderr_contains(
+            "package ID specification `foo` specified by --package-with-deps matched no packages",
+        );
+    cargo_hack(["check", "--workspace", "--package-with-dependents", "core"])
+        .assert_failure("deps_closure")
+        .stderr_contains("--workspace may not be used together with --package-with-dependents");
+    cargo_hack(["check", "--with-dev-deps-closure"])
+        .assert_failure("deps_closure")
+        .stderr_contains(
+            "--with-dev-deps-closure can only be used together with either --package-with-deps or \
+         --package-with-dependents",
+        );
+}
+
+#[test]
+fn only_packages_with_feature() {
+    cargo_hack(["check", "--all", "--only-packages-with-feature", "f", "--features", "f"])
+        .assert_success("virtual")
+        .stderr_contains(
+            "
+            skipped running on package `member1` that does not have feature `f`
+            running `cargo check --features f` on member2 (1/1)
+            ",
+        )
+        .stderr_not_contains("on member1")
+        .stderr_not_contains("on not_find_manifest");
+
+    // AND semantics
+    cargo_hack(["check", "--all", "--only-packages-with-feature", "a"])
+        .arg("--only-packages-with-feature")
+        .arg("f")
+        .assert_success("virtual")
+        .stderr_contains("running `cargo check` on member2 (1/1)")
+        .stderr_not_contains("running `cargo check` on member1");
+
+    cargo_hack(["check", "--all", "--exclude", "member2", "--only-packages-with-feature", "f"])
+        .assert_failure("virtual")
+        .stderr_contains(
+            "
+            member1: does not have feature `f` (--only-packages-with-feature)
+            member2: excluded by --exclude
+            ",
+        )
+        .stderr_not_contains("running `cargo check`");
+
+    cargo_hack(["check", "--package", "member1", "--only-packages-with-feature", "f"])
+        .assert_failure("virtual")
+        .stderr_contains("skipped running on package `member1` that does not have feature `f`")
+        .stderr_contains("no commands to run; use --allow-empty-plan")
+        .stderr_not_contains("running `cargo check`");
+
+    // not_found is warning
+    cargo_hack(["check", "--all", "--only-packages-with-feature", "g"])
+        .assert_failure("virtual")
+        .stderr_contains(
+            "feature `g` specified by --only-packages-with-feature not found in workspace",
+        );
+}
+
+#[test]
+fn run_bin_selection() {
+    // default-run requires Rust 1.37.
+    let require = Some(37);
+
+    cargo_hack(["run", "--workspace", "--exclude", "multi_bin"])
+        .assert_success2("run", require)
+        .stderr_contains(
+            "
+            skipped running on package `lib_only` that does not have binary targets
+            running `cargo run` on single_bin (1/2)
+            running `cargo run` on default_run (2/2)
+            ",
+        )
+        .stdout_contains(
+            "
+            single_bin
+            default_run x
+            ",
+        );
+
+    cargo_hack(["run", "--workspace"])
+        .assert_failure2("run", require)
+        .stderr_contains(
+            "package `multi_bin` has multiple binary targets (a, b); use --bin to specify which one to run",
+        )
+        .stderr_not_contains("running `cargo run`");
+
+    cargo_hack(["run", "--workspace", "--bin", "a"])
+        .assert_success2("run", require)
+        .stderr_contains(
+            "
+            skipped running on package `lib_only` that does not have binary target `a`
+            skipped running on package `single_bin` that does not have binary target `a`
+            skipped running on package `default_run` that does not have binary target `a`
+            running `cargo run --bin a` on multi_bin (1/1)
+            ",
+        )
+        .stdout_contains("multi_bin a");
 }
 
 #[test]
@@ -366,6 +601,75 @@ fn no_dev_deps() {
         restores it when finished
         ",
     );
+
+    // the number of removed lines is always shown, the diff only in verbose mode
+    cargo_hack(["check", "--no-dev-deps"])
+        .assert_success("real")
+        .stderr_contains("removed 2 lines from")
+        .stderr_not_contains("-[dev-dependencies]");
+    cargo_hack(["check", "--no-dev-deps", "-v"]).assert_success("real").stderr_contains(
+        "
+        removed 2 lines from
+        diff of
+        @@ -12,8 +12,6 @@
+         [dependencies]
+        -[dev-dependencies]
+        ",
+    );
+}
+
+#[test]
+fn no_dev_deps_selected_packages() {
+    use std::path::MAIN_SEPARATOR;
+
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let manifest = |name: &str| {
+        if name == "real" {
+            cur_dir.join("Cargo.toml")
+        } else {
+            cur_dir.join(name).join("Cargo.toml")
+        }
+    };
+    let snapshot = |name: &str| {
+        let path = manifest(name);
+        (fs::read_to_string(&path).unwrap(), fs::metadata(&path).unwrap().modified().unwrap())
+    };
+    let removed_from = |stderr: &str, name: &str| {
+        stderr.lines().any(|line| {
+            line.contains("removed") && line.ends_with(&format!("{name}{MAIN_SEPARATOR}Cargo.toml"))
+        })
+    };
+
+    // member3 depends on member2, so dev-dependencies are removed from both,
+    // but member1 and the root package are not touched.
+    let before: Vec<_> = ["real", "member1", "member2"].map(snapshot).into();
+    let output = cargo_hack(["check", "--no-dev-deps", "-p", "member3"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(removed_from(&stderr, "member3"), "{stderr}");
+    assert!(removed_from(&stderr, "member2"), "{stderr}");
+    assert!(!removed_from(&stderr, "member1"), "{stderr}");
+    let after: Vec<_> = ["real", "member1", "member2"].map(snapshot).into();
+    assert_eq!(before[..2], after[..2]);
+    // member2 is restored.
+    assert_eq!(before[2].0, after[2].0);
+
+    // Nothing depends on member1 in the opposite direction.
+    let before = snapshot("member2");
+    let output = cargo_hack(["check", "--no-dev-deps", "-p", "member1"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(removed_from(&stderr, "member1"), "{stderr}");
+    assert!(!removed_from(&stderr, "member2"), "{stderr}");
+    assert_eq!(before, snapshot("member2"));
 }
 
 #[test]
@@ -392,6 +696,273 @@ fn no_dev_deps_failure() {
     }
 }
 
+#[cfg(unix)]
+#[test]
+fn no_dev_deps_concurrent() {
+    use std::{os::unix::fs::PermissionsExt as _, process::Stdio, thread, time::Duration};
+
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let manifest = fs::read_to_string(cur_dir.join("Cargo.toml")).unwrap();
+    let wait_for = |path: &Path, pat: &str| {
+        for _ in 0..600 {
+            if fs::read_to_string(path).is_ok_and(|s| s.contains(pat)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("timed out waiting for `{pat}` in {}", path.display());
+    };
+
+    // A cargo that blocks `cargo check` (except for `cargo check --help` used to
+    // validate flags) until the `release` file is created.
+    let tmpdir = tempfile::tempdir().unwrap();
+    let started = tmpdir.path().join("started");
+    let release = tmpdir.path().join("release");
+    let shim = tmpdir.path().join("cargo");
+    fs::write(
+        &shim,
+        format!(
+            "#!/bin/sh\nif [ \"$1\" = check ] && [ \"$2\" != --help ]; then\n  echo started >{}\n  while [ ! -e {} ]; do sleep 0.1; done\nfi\nexec cargo \"$@\"\n",
+            started.display(),
+            release.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut first = cargo_hack(["check", "--no-dev-deps"])
+        .env("CARGO_HACK_CARGO_SRC", &shim)
+        .current_dir(&cur_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for(&started, "started");
+
+    // --no-wait fails instead of waiting for the first instance.
+    let output = cargo_hack(["check", "--no-dev-deps", "--no-wait"])
+        .env("CARGO_HACK_CARGO_SRC", &shim)
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "another cargo-hack instance (process {}) is modifying this workspace",
+        first.id()
+    )));
+
+    // Without --no-wait, the second instance waits for the first instance, and
+    // then aborts because it has read the manifest modified by the first instance.
+    let log = tmpdir.path().join("second.log");
+    let mut second = cargo_hack(["check", "--no-dev-deps"])
+        .env("CARGO_HACK_CARGO_SRC", &shim)
+        .current_dir(&cur_dir)
+        .stdout(Stdio::null())
+        .stderr(fs::File::create(&log).unwrap().into_parts().0)
+        .spawn()
+        .unwrap();
+    wait_for(&log, "waiting for it to finish");
+    fs::write(&release, "").unwrap();
+    assert!(first.wait().unwrap().success());
+    assert!(!second.wait().unwrap().success());
+    assert!(fs::read_to_string(&log).unwrap().contains(
+        "has been modified since cargo-hack started (possibly by another cargo-hack instance)"
+    ));
+
+    // The original manifest survives.
+    assert_eq!(fs::read_to_string(cur_dir.join("Cargo.toml")).unwrap(), manifest);
+    assert!(!cur_dir.join("target/.cargo-hack.lock").exists());
+
+    cargo_hack(["check", "--no-wait"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--no-wait can only be used together with --no-dev-deps, --remove-dev-deps, or --no-private",
+        );
+}
+
+#[cfg(unix)]
+#[test]
+fn restore_after_crash() {
+    use std::{os::unix::fs::PermissionsExt as _, process::Stdio, thread, time::Duration};
+
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let manifest_path = cur_dir.join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let lock = cur_dir.join("target/.cargo-hack.lock");
+    let backup = cur_dir.join("target/cargo-hack-backup");
+
+    // A cargo that blocks `cargo check` (except for `cargo check --help` used to
+    // validate flags) until the `release` file in $SHIM_DIR is created.
+    let tmpdir = tempfile::tempdir().unwrap();
+    let shim = tmpdir.path().join("cargo");
+    fs::write(
+        &shim,
+        "#!/bin/sh
+if [ \"$1\" = check ] && [ \"$2\" != --help ]; then
+  echo started >\"$SHIM_DIR/started\"
+  while [ ! -e \"$SHIM_DIR/release\" ]; do sleep 0.1; done
+  exit 1
+fi
+exec cargo \"$@\"
+",
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mut crashes = 0;
+    // Kills cargo-hack while the manifest is modified.
+    let mut crash = |args: &[&str]| {
+        crashes += 1;
+        let dir = tmpdir.path().join(crashes.to_string());
+        fs::create_dir(&dir).unwrap();
+        let mut child = cargo_hack(args)
+            .env("CARGO_HACK_CARGO_SRC", &shim)
+            .env("SHIM_DIR", &dir)
+            .current_dir(&cur_dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        for _ in 0..600 {
+            if dir.join("started").exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(dir.join("started").exists(), "timed out waiting for cargo check to start");
+        child.kill().unwrap();
+        child.wait().unwrap();
+        // Let the orphaned cargo exit.
+        fs::write(dir.join("release"), "").unwrap();
+    };
+    let run = |args: &[&str]| {
+        let output = cargo_hack(args).current_dir(&cur_dir).output().unwrap();
+        (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+
+    crash(&["check", "--no-dev-deps"]);
+    assert_ne!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+    assert!(lock.exists());
+    assert!(fs::read_to_string(backup.join("index")).unwrap().contains("Cargo.toml"));
+    assert!(fs::read_dir(&backup).unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .ends_with("-Cargo.toml")));
+
+    // The lock file left by the killed instance is reported with the way to restore the files.
+    let (success, stderr) = run(&["check", "--no-dev-deps", "--no-wait"]);
+    assert!(!success);
+    assert!(stderr.contains("run `cargo hack restore` to restore the files modified by it"));
+    assert_ne!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+
+    let (success, stderr) = run(&["restore"]);
+    assert!(success);
+    assert!(stderr.contains(&format!("restoring {}", manifest_path.display())));
+    assert!(stderr.contains("removed lock file"));
+    assert_eq!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+    assert!(!lock.exists());
+    assert!(!backup.exists());
+
+    let (success, stderr) = run(&["restore"]);
+    assert!(success);
+    assert!(stderr.contains("no files to restore"));
+
+    // If the lock file has been removed, the files are restored at startup.
+    crash(&["check", "--no-dev-deps"]);
+    fs::remove_file(&lock).unwrap();
+    let (success, stderr) = run(&["check"]);
+    assert!(success);
+    assert!(stderr.contains(
+        "restoring files modified by a previous cargo-hack instance that did not exit cleanly"
+    ));
+    assert_eq!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+    assert!(!backup.exists());
+
+    // --manifest-backup-dir keeps another copy of the original contents.
+    let backup_dir = tmpdir.path().join("backup");
+    crash(&["check", "--no-dev-deps", "--manifest-backup-dir", backup_dir.to_str().unwrap()]);
+    let index = fs::read_to_string(backup_dir.join("index")).unwrap();
+    let (hash, _) = index
+        .lines()
+        .find_map(|line| {
+            line.split_once('\t').filter(|(_, path)| *path == manifest_path.to_str().unwrap())
+        })
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(backup_dir.join(format!("{hash}-Cargo.toml"))).unwrap(),
+        manifest
+    );
+    let (success, _) = run(&["restore"]);
+    assert!(success);
+    // The backups are removed after the files are restored, but not the directory.
+    let (success, _) =
+        run(&["check", "--no-dev-deps", "--manifest-backup-dir", backup_dir.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(fs::read_dir(&backup_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn no_dev_deps_read_only() {
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    // A dependency of the root package, whose manifest is modified by --no-dev-deps.
+    let read_only = cur_dir.join("member1/Cargo.toml");
+    let mut permissions = fs::metadata(&read_only).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&read_only, permissions).unwrap();
+    let manifests: Vec<_> = ["Cargo.toml", "member1/Cargo.toml"]
+        .iter()
+        .map(|path| (cur_dir.join(path), fs::read_to_string(cur_dir.join(path)).unwrap()))
+        .collect();
+
+    let output = cargo_hack(["check", "--no-dev-deps"]).current_dir(&cur_dir).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(
+            "the following files need to be modified temporarily, but they are not writable:"
+        ),
+        "STDERR:\n{stderr}"
+    );
+    assert!(stderr.contains(&format!("    {}: ", read_only.display())), "STDERR:\n{stderr}");
+    assert!(!stderr.contains("running `cargo check"), "STDERR:\n{stderr}");
+    // Nothing is modified, even the writable manifests.
+    for (path, contents) in manifests {
+        assert_eq!(fs::read_to_string(path).unwrap(), contents);
+    }
+}
+
+#[test]
+fn no_dev_deps_doc() {
+    // `test --doc` is allowed because doctests often do not require dev-dependencies.
+    cargo_hack(["test", "--doc", "--no-dev-deps"])
+        .assert_success("doctest")
+        .stderr_contains(
+            "
+            --no-dev-deps modifies real `Cargo.toml` while cargo-hack is running and \
+            restores it when finished
+            removed 3 lines from
+            running `cargo test --doc` on doctest (1/1)
+            ",
+        )
+        // helper is only used as a dev-dependency, so its manifest is not modified.
+        .stderr_not_contains("removed 2 lines from")
+        .stdout_contains("test result: ok. 1 passed");
+
+    // Doctests that use dev-dependencies fail.
+    cargo_hack(["test", "--doc", "--no-dev-deps", "--features", "helper"])
+        .assert_failure("doctest")
+        .stderr_contains(
+            "if this failure is caused by doctests that use dev-dependencies \
+             (e.g., `use of undeclared crate`), they cannot be run with --no-dev-deps",
+        );
+}
+
 #[test]
 fn remove_dev_deps_failure() {
     // with options requires dev-deps
@@ -411,6 +982,127 @@ fn remove_dev_deps_failure() {
     }
 }
 
+#[test]
+fn remove_dev_deps_without_subcommand() {
+    use std::path::Path;
+
+    use fs_err as fs;
+
+    let has_dev_deps = |dir: &Path, path: &str| {
+        fs::read_to_string(dir.join(path)).unwrap().contains("dev-dependencies")
+    };
+
+    // `cargo metadata` fails because some dependencies cannot be loaded...
+    cargo_hack(["check"]).assert_failure("unresolvable_deps");
+    // ...but --remove-dev-deps only needs the manifests.
+    let (_test_project, cur_dir) = test_project("unresolvable_deps");
+    let output = cargo_hack(["--remove-dev-deps"]).current_dir(&cur_dir).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "STDERR:\n{stderr}");
+    assert!(!stderr.contains("falling back"), "STDERR:\n{stderr}");
+    // A member matched by the glob, and a path dependency of it in the workspace directory.
+    assert!(!has_dev_deps(&cur_dir, "crates/a/Cargo.toml"));
+    assert!(!has_dev_deps(&cur_dir, "b/Cargo.toml"));
+    // Excluded, and a path dependency that is a workspace root.
+    assert!(has_dev_deps(&cur_dir, "crates/excluded/Cargo.toml"));
+    assert!(has_dev_deps(&cur_dir, "nested/Cargo.toml"));
+
+    // In a member of the workspace.
+    let (_test_project, cur_dir) = test_project("unresolvable_deps");
+    let output = cargo_hack(["--remove-dev-deps"]).current_dir(cur_dir.join("b")).output().unwrap();
+    assert!(output.status.success());
+    assert!(!has_dev_deps(&cur_dir, "crates/a/Cargo.toml"));
+    // With --manifest-path.
+    let (_test_project, cur_dir) = test_project("unresolvable_deps");
+    let output = cargo_hack(["--remove-dev-deps", "--manifest-path", "crates/a/Cargo.toml"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!has_dev_deps(&cur_dir, "b/Cargo.toml"));
+    assert!(has_dev_deps(&cur_dir, "crates/excluded/Cargo.toml"));
+
+    // The members are the same as the ones `cargo metadata` reports.
+    let (_test_project, cur_dir) = test_project("real");
+    let output = cargo_hack(["--remove-dev-deps"]).current_dir(&cur_dir).output().unwrap();
+    assert!(output.status.success());
+    for path in ["Cargo.toml", "member1/Cargo.toml", "member2/Cargo.toml", "member3/Cargo.toml"] {
+        assert!(!has_dev_deps(&cur_dir, path), "{path}");
+    }
+}
+
+#[test]
+fn remove_dev_deps_check() {
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let manifest_path = cur_dir.join("member1/Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let run = |args: &[&str]| {
+        let output = cargo_hack(args).current_dir(&cur_dir).output().unwrap();
+        (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+
+    // Dirty: nothing is written.
+    let (success, stderr) = run(&["--remove-dev-deps", "--check", "--verbose"]);
+    assert!(!success);
+    assert!(
+        stderr.contains(&format!(
+            "--remove-dev-deps would remove 2 lines from {}",
+            manifest_path.display()
+        )),
+        "STDERR:\n{stderr}"
+    );
+    assert!(stderr.contains("-[dev-dependencies]"), "STDERR:\n{stderr}");
+    assert!(stderr.contains("4 of 4 manifests would be changed by --remove-dev-deps"));
+    assert_eq!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+
+    // Clean.
+    assert!(run(&["--remove-dev-deps"]).0);
+    let (success, stderr) = run(&["--remove-dev-deps", "--check"]);
+    assert!(success, "STDERR:\n{stderr}");
+    assert!(
+        stderr.contains(&format!("{} has no dev-dependencies to remove", manifest_path.display()))
+    );
+
+    let (success, stderr) = run(&["--check"]);
+    assert!(!success);
+    assert!(stderr.contains("--check can only be used together with --remove-dev-deps"));
+    let (success, stderr) = run(&["--remove-dev-deps", "--check", "--no-private"]);
+    assert!(!success);
+    assert!(stderr.contains("--check may not be used together with --no-private"));
+    // `--check` after the subcommand is passed to cargo.
+    cargo_hack(["fmt", "--check", "--print-command-list"])
+        .assert_success("real")
+        .stdout_contains("cargo fmt --check");
+}
+
+#[test]
+fn feature_insensitive_subcommand() {
+    let (_tmp, cur_dir) = test_project("virtual");
+    let output = cargo_hack(["fmt", "--each-feature", "--workspace"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "--each-feature has no effect with fmt subcommand, which does not depend on features; \
+             it is run only once per package"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(stderr.matches("running `cargo fmt").count(), 3, "{stderr}");
+    for member in ["member1", "member2", "not_find_manifest"] {
+        assert!(stderr.contains(&format!("running `cargo fmt` on {member} (")), "{stderr}");
+    }
+    assert!(!stderr.contains("--features"), "{stderr}");
+
+    cargo_hack(["fmt", "--feature-powerset", "--print-command-list"])
+        .assert_failure("real")
+        .stderr_contains("--feature-powerset has no effect with fmt subcommand");
+}
+
 #[test]
 fn ignore_unknown_features() {
     cargo_hack(["check", "--ignore-unknown-features", "--no-default-features", "--features", "f"])
@@ -440,6 +1132,176 @@ fn ignore_unknown_features() {
     .stderr_not_contains("skipped applying unknown `missing` feature to member2");
 }
 
+#[test]
+fn fail_on_skipped() {
+    // unknown-feature
+    let args = ["check", "--ignore-unknown-features", "--no-default-features", "--features", "f"];
+    cargo_hack(args).arg("--fail-on-skipped").assert_failure("virtual").stderr_contains(
+        "
+        error: 2 skips occurred (--fail-on-skipped):
+            unknown-feature: member1: feature `f`
+            unknown-feature: not_find_manifest: feature `f`
+        ",
+    );
+    cargo_hack(args)
+        .arg("--fail-on-skipped=group-skipped,package-empty-plan")
+        .assert_success("virtual")
+        .stderr_not_contains("--fail-on-skipped");
+
+    // group-skipped
+    let args = [
+        "check",
+        "--ignore-unknown-features",
+        "--feature-powerset",
+        "--group-features=a,missing",
+        "--print-command-list",
+    ];
+    cargo_hack(args)
+        .arg("--fail-on-skipped=group-skipped")
+        .assert_failure("virtual")
+        .stderr_contains(
+            "
+        error: 3 skips occurred (--fail-on-skipped):
+            group-skipped: member1: group `a,missing`
+            group-skipped: member2: group `a,missing`
+            group-skipped: not_find_manifest: group `a,missing`
+        ",
+        );
+    cargo_hack(args)
+        .arg("--fail-on-skipped=unknown-feature")
+        .assert_success("virtual")
+        .stderr_not_contains("--fail-on-skipped");
+
+    // package-empty-plan
+    let args = ["check", "--each-feature", "--print-command-list"];
+    cargo_hack(args).arg("--fail-on-skipped").assert_failure("default_feature_behavior").stderr_contains(
+        "package-empty-plan: no_default: no features to combine; run only with the default features",
+    );
+    cargo_hack(args)
+        .arg("--fail-on-skipped=private-package")
+        .assert_success("default_feature_behavior")
+        .stderr_not_contains("--fail-on-skipped");
+    cargo_hack([
+        "run",
+        "--workspace",
+        "--exclude",
+        "multi_bin",
+        "--print-command-list",
+        "--fail-on-skipped=package-empty-plan",
+    ])
+    .assert_failure2("run", Some(37))
+    .stderr_contains("package-empty-plan: lib_only: no binary target to run");
+
+    // private-package is not checked by default.
+    let args = ["check", "--workspace", "--ignore-private", "--print-command-list"];
+    cargo_hack(args)
+        .arg("--fail-on-skipped")
+        .assert_success("real")
+        .stderr_not_contains("--fail-on-skipped");
+    cargo_hack(args)
+        .arg("--fail-on-skipped=private-package")
+        .assert_failure("real")
+        .stderr_contains(
+            "
+        error: 2 skips occurred (--fail-on-skipped):
+            private-package: member2: private package
+            private-package: real: private package
+        ",
+        );
+
+    // msrv-out-of-range
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    let args = ["check", "--workspace", "--version-range", "1.73..=1.73", "--print-command-list"];
+    // The skips are also warned, so these fail regardless of --fail-on-skipped.
+    cargo_hack(args).arg("--fail-on-skipped").assert_failure("rust-version").stderr_contains(
+        "
+        error: 4 skips occurred (--fail-on-skipped):
+            msrv-out-of-range: member1: rust-version (1.74) is not in 1.73..=1.73
+        ",
+    );
+    cargo_hack(args)
+        .arg("--fail-on-skipped=unknown-feature")
+        .assert_failure("rust-version")
+        .stderr_not_contains("--fail-on-skipped");
+}
+
+#[test]
+fn required_cargo_hack() {
+    // The virtual workspace requires a newer cargo-hack.
+    cargo_hack(["check"])
+        .assert_failure("minimum_version_newer")
+        .stderr_contains(format!(
+            "this workspace requires cargo-hack >= 999.0.0, but you have {}; run `cargo install \
+             cargo-hack --locked` to upgrade (use --ignore-minimum-version to skip this check)",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .stderr_not_contains("running `");
+    cargo_hack(["check", "--ignore-minimum-version"])
+        .assert_success("minimum_version_newer")
+        .stderr_contains("running `cargo check` on member");
+    // In a member of the workspace.
+    let (_test_project, cur_dir) = test_project("minimum_version_newer");
+    let output = cargo_hack(["check"]).current_dir(cur_dir.join("member")).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires cargo-hack >= 999.0.0"));
+
+    cargo_hack(["check"])
+        .assert_success("minimum_version_older")
+        .stderr_not_contains("requires cargo-hack");
+}
+
+#[test]
+fn empty_plan() {
+    let args = [
+        "check",
+        "--workspace",
+        "--exclude",
+        "member1",
+        "--exclude",
+        "member2",
+        "--exclude",
+        "not_find_manifest",
+    ];
+    cargo_hack(args)
+        .assert_failure("virtual")
+        .stderr_contains(
+            "
+            warning: no commands to run; packages were left out for the following reasons:
+                member1: excluded by --exclude
+                member2: excluded by --exclude
+                not_find_manifest: excluded by --exclude
+            error: no commands to run; use --allow-empty-plan to succeed without running anything
+            ",
+        )
+        .stderr_not_contains("running `");
+    cargo_hack(args)
+        .arg("--allow-empty-plan")
+        .assert_failure("virtual") // warn
+        .stderr_contains("warning: no commands to run")
+        .stderr_not_contains("error:");
+
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    cargo_hack(["check", "--workspace", "--version-range", "1.73..=1.73", "--print-command-list"])
+        .assert_failure("rust-version")
+        .stderr_contains(
+            "
+            warning: no commands to run; packages were left out for the following reasons:
+                member1: rust-version (1.74) is not in 1.73..=1.73
+                member2: rust-version (1.74) is not in 1.73..=1.73
+                member3: rust-version (1.75) is not in 1.73..=1.73
+                real: rust-version (1.76) is not in 1.73..=1.73
+            error: no commands to run; use --allow-empty-plan to succeed without running anything
+            ",
+        )
+        .stdout_not_contains("cargo check");
+}
+
 #[test]
 fn ignore_unknown_features_failure() {
     cargo_hack(["check", "--ignore-unknown-features"])
@@ -496,9 +1358,76 @@ fn each_feature() {
 }
 
 #[test]
-fn each_feature_failure() {
-    cargo_hack(["check", "--each-feature", "--feature-powerset"])
-        .assert_failure("real")
+fn redundant_all_features() {
+    // `default` enables all the features, so --all-features is the same as
+    // --features default.
+    cargo_hack(["check", "--each-feature"])
+        .assert_success("redundant_all_features")
+        .stderr_contains(
+            "
+            skipped the run with --all-features on package `redundant_all_features`, because it \
+             enables the same features as --features default
+            running `cargo check --no-default-features` on redundant_all_features (1/4)
+            running `cargo check --no-default-features --features a` on redundant_all_features (2/4)
+            running `cargo check --no-default-features --features b` on redundant_all_features (3/4)
+            running `cargo check --no-default-features --features default` on redundant_all_features (4/4)
+            ",
+        )
+        .stderr_not_contains("--all-features`");
+}
+
+#[test]
+fn only_code_gated_features() {
+    cargo_hack(["check", "--each-feature", "--only-code-gated-features"])
+        .assert_success("code_gated")
+        .stderr_contains(
+            "
+            skipped iterating features `default`, `full` of package `code_gated`, which are not \
+             referenced in the code and only enable other features or dependencies \
+             (--only-code-gated-features)
+            running `cargo check --all-features` on code_gated (1/7)
+            running `cargo check --no-default-features` on code_gated (2/7)
+            running `cargo check --no-default-features --features alloc` on code_gated (3/7)
+            running `cargo check --no-default-features --features build-info` on code_gated (4/7)
+            running `cargo check --no-default-features --features derive` on code_gated (5/7)
+            running `cargo check --no-default-features --features std` on code_gated (6/7)
+            running `cargo check --no-default-features --features unused` on code_gated (7/7)
+            ",
+        )
+        .stderr_not_contains("--features default`")
+        .stderr_not_contains("--features full`");
+
+    cargo_hack(["check", "--each-feature"])
+        .assert_success("code_gated")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features default` on code_gated
+            running `cargo check --no-default-features --features full` on code_gated
+            ",
+        )
+        .stderr_not_contains("skipped iterating features");
+
+    cargo_hack(["check", "--only-code-gated-features"])
+        .assert_failure("code_gated")
+        .stderr_contains(
+            "--only-code-gated-features can only be used together with either --each-feature or \
+             --feature-powerset",
+        );
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--only-code-gated-features",
+        "--include-features",
+        "std",
+    ])
+    .assert_failure("code_gated")
+    .stderr_contains("--include-features may not be used together with --only-code-gated-features");
+}
+
+#[test]
+fn each_feature_failure() {
+    cargo_hack(["check", "--each-feature", "--feature-powerset"])
+        .assert_failure("real")
         .stderr_contains("--each-feature may not be used together with --feature-powerset");
 
     cargo_hack(["check", "--each-feature", "--all-features"])
@@ -797,6 +1726,94 @@ fn depth_failure() {
         .stderr_contains("--depth can only be used together with --feature-powerset");
 }
 
+#[test]
+fn powerset_why() {
+    cargo_hack(["check", "--feature-powerset", "--package", "real", "--why", "a,b"])
+        .assert_success("real")
+        .stderr_contains("`a,b` is run on package `real`")
+        .stderr_not_contains("running");
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--package",
+        "real",
+        "--depth",
+        "2",
+        "--why",
+        "a,b,c",
+    ])
+    .assert_success("real")
+    .stderr_contains(
+        "`a,b,c` is not run on package `real`: it is never generated because it has more \
+             than 2 features (--depth)",
+    )
+    .stderr_not_contains("running");
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--package",
+        "real",
+        "--mutually-exclusive-features",
+        "a,b",
+        "--why",
+        "b,a",
+    ])
+    .assert_success("real")
+    .stderr_contains(
+        "`b,a` is not run on package `real`: removed by --mutually-exclusive-features because \
+         `a` and `b` are in the same group `a,b`",
+    );
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--package",
+        "real",
+        "--group-features",
+        "a,b",
+        "--why",
+        "a",
+    ])
+    .assert_success("real")
+    .stderr_contains(
+        "`a` is not run on package `real`: `b` is always enabled together with the other \
+             features in group `a,b`",
+    );
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--package",
+        "real",
+        "--exclude-features",
+        "c",
+        "--why",
+        "a,c",
+    ])
+    .assert_success("real")
+    .stderr_contains("`a,c` is not run on package `real`: `c` is excluded by --exclude-features");
+
+    cargo_hack(["check", "--feature-powerset", "--package", "real", "--why", "a member1"])
+        .assert_success("real")
+        .stderr_contains(
+            "`a,member1` is not run on package `real`: it is never generated because `member1` is \
+             not in the features to combine",
+        );
+
+    cargo_hack(["check", "--feature-powerset", "--package", "real", "--why", "a,x"])
+        .assert_success("real")
+        .stderr_contains(
+            "`a,x` is not run on package `real`: it is never generated because feature `x` does \
+             not exist in the package",
+        );
+
+    cargo_hack(["check", "--each-feature", "--why", "a"])
+        .assert_failure("real")
+        .stderr_contains("--why can only be used together with --feature-powerset");
+}
+
 #[test]
 fn powerset_group_features() {
     cargo_hack(["check", "--feature-powerset", "--group-features", "a,b"])
@@ -840,6 +1857,46 @@ fn powerset_group_features() {
             ",
         );
 
+    // `default` is expanded to the default features of each package.
+    cargo_hack(["check", "--feature-powerset", "--group-features", "default,b", "-p", "member3"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on member3 (1/4)
+            running `cargo check --no-default-features --features c,a,b` on member3 (2/4)
+            running `cargo check --no-default-features --features c` on member3 (3/4)
+            running `cargo check --no-default-features --features a,b` on member3 (4/4)
+            ",
+        )
+        .stderr_not_contains("default`");
+    cargo_hack(["check", "--feature-powerset", "--group-features", "default,b", "-p", "member1"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features a,c,b` on member1 (2/8)
+            running `cargo check --no-default-features --features a` on member1 (3/8)
+            ",
+        )
+        .stderr_not_contains("default`");
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--group-features",
+        "default,std",
+        "--exclude-features",
+        "alloc,full",
+    ])
+    .assert_failure("exclude_features") // warn
+    .stderr_contains(
+        "
+        warning: package `exclude_features` has no default feature; `default` in --group-features \
+        is ignored for this package
+        running `cargo check --no-default-features --features serde` on exclude_features
+        running `cargo check --no-default-features --features std` on exclude_features
+        running `cargo check --no-default-features --features serde,std` on exclude_features
+        ",
+    );
+
     // overlapping
     // TODO: Maybe we should warn this, but allow it for now.
     cargo_hack([
@@ -873,6 +1930,49 @@ fn powerset_group_features() {
     );
 }
 
+#[test]
+fn group_features_overlap() {
+    let args = [
+        "check",
+        "--feature-powerset",
+        "--group-features",
+        "a,b",
+        "--group-features",
+        "b,c",
+        "--group-features",
+        "a,c",
+    ];
+    cargo_hack(args)
+        .assert_success("real")
+        .stderr_contains(
+            "
+            merged 6 feature combinations of package `real` into other combinations that enable the same features, because groups of features overlap
+            running `cargo check --no-default-features --features a,b,b,c` on real (8/11)
+            running `cargo check --no-default-features --features default,a,b,b,c` on real (9/11)
+            ",
+        )
+        .stderr_not_contains(
+            "
+            --features a,b,a,c`
+            --features b,c,a,c`
+            ",
+        );
+
+    // Each unique command is printed once.
+    let (_dir, cur_dir) = test_project("real");
+    let output = cargo_bin_exe()
+        .arg("hack")
+        .args(args)
+        .arg("--print-command-list")
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 11, "{stdout}");
+    assert_eq!(lines.iter().collect::<BTreeSet<_>>().len(), lines.len(), "{stdout}");
+}
+
 #[test]
 fn group_features_failure() {
     cargo_hack(["check", "--each-feature", "--group-features", "a,b"])
@@ -909,6 +2009,100 @@ fn include_features() {
         );
 }
 
+#[test]
+fn include_features_default_coverage() {
+    // --include-features implies --exclude-no-default-features, so member3,
+    // whose default feature enables `a`, is never run with it disabled.
+    cargo_hack(["check", "--each-feature", "--include-features", "a", "-p", "member3"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features a` on member3 (1/1)
+            package `member3`: no combination tests disabled default features (the run with only \
+             --no-default-features is excluded by --include-features)
+            ",
+        );
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--include-features",
+        "a",
+        "-p",
+        "member3",
+        "--strict-coverage",
+    ])
+    .assert_failure("real")
+    .stderr_contains("warning: package `member3`: no combination tests disabled default features");
+
+    // `b` is a combination that does not enable `a`.
+    cargo_hack(["check", "--each-feature", "--include-features", "a,b", "-p", "member3"])
+        .assert_success("real")
+        .stderr_not_contains("no combination tests disabled default features");
+    // The default feature of real is empty.
+    cargo_hack(["check", "--each-feature", "--include-features", "a"])
+        .assert_success("real")
+        .stderr_not_contains("no combination tests disabled default features");
+
+    cargo_hack(["check", "--strict-coverage"]).assert_failure("real").stderr_contains(
+        "--strict-coverage can only be used together with either --each-feature or \
+             --feature-powerset",
+    );
+}
+
+#[test]
+fn include_features_keywords() {
+    // `@defaults` expands to the members of the default feature of each package.
+    cargo_hack(["check", "--each-feature", "--include-features", "@defaults", "--workspace"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features a` on member3 (3/4)
+            info: --include-features @defaults expanded to no features on package `real`
+            ",
+        )
+        .stderr_not_contains(
+            "
+            --features b
+            --features default
+            ",
+        );
+
+    // `@all` expands to all features except optional dependencies.
+    cargo_hack(["check", "--each-feature", "--include-features", "@all"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features a` on real (1/4)
+            running `cargo check --no-default-features --features b` on real (2/4)
+            running `cargo check --no-default-features --features c` on real (3/4)
+            running `cargo check --no-default-features --features default` on real (4/4)
+            ",
+        )
+        .stderr_not_contains("--features member1");
+    // ...and the excluded features are removed after the expansion.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--include-features",
+        "@all,a",
+        "--exclude-features",
+        "default,b",
+    ])
+    .assert_success("real")
+    .stderr_contains(
+        "
+        running `cargo check --no-default-features --features a` on real (1/2)
+        running `cargo check --no-default-features --features c` on real (2/2)
+        ",
+    );
+
+    cargo_hack(["check", "--each-feature", "--include-features", "@default"])
+        .assert_failure("real")
+        .stderr_contains(
+            "unknown keyword `@default` in --include-features; expected `@defaults` or `@all`",
+        );
+}
+
 #[test]
 fn exclude_features() {
     cargo_hack(["check", "--each-feature", "--exclude-features", "f"])
@@ -916,6 +2110,23 @@ fn exclude_features() {
         .stderr_not_contains("specified feature `f` not found");
 }
 
+#[test]
+fn exclude_features_enabled_by_features() {
+    // `full` enables `serde`, so excluding `serde` has no effect.
+    cargo_hack(["check", "--each-feature", "--features", "full", "--exclude-features", "serde"])
+        .assert_failure("exclude_features") // warn
+        .stderr_contains(
+            "
+            warning: --exclude-features serde has no effect on package `exclude_features`: `full` \
+            (from --features) enables `serde` via full -> serde
+            running `cargo check --no-default-features --features full` on exclude_features (1/3)
+            ",
+        );
+    cargo_hack(["check", "--each-feature", "--features", "std", "--exclude-features", "serde"])
+        .assert_success("exclude_features")
+        .stderr_not_contains("has no effect");
+}
+
 #[test]
 fn exclude_features_failure() {
     cargo_hack(["check", "--exclude-features", "a"])
@@ -1079,6 +2290,46 @@ fn exclude_all_features() {
         .stderr_not_contains("--all-features");
 }
 
+#[test]
+fn exclude_all_features_package() {
+    cargo_hack(["check", "--each-feature", "--workspace", "--exclude-all-features=member1"])
+        .assert_success("virtual")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on member1 (1/18)
+            running `cargo check --no-default-features --features a` on member1 (2/18)
+            running `cargo check --no-default-features --features b` on member1 (3/18)
+            running `cargo check --no-default-features --features c` on member1 (4/18)
+            running `cargo check --no-default-features --features default` on member1 (5/18)
+            running `cargo check --all-features` on member2 (6/18)
+            running `cargo check --no-default-features` on member2 (7/18)
+            ",
+        )
+        .stderr_not_contains("running `cargo check --all-features` on member1");
+
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--workspace",
+        "--exclude-all-features=member1",
+        "--exclude-all-features=member2",
+    ])
+    .assert_success("virtual")
+    .stderr_contains("running `cargo check --all-features` on not_find_manifest (12/17)")
+    .stderr_not_contains(
+        "
+        running `cargo check --all-features` on member1
+        running `cargo check --all-features` on member2
+        ",
+    );
+
+    cargo_hack(["check", "--each-feature", "--workspace", "--exclude-all-features=foo"])
+        .assert_failure("virtual")
+        .stderr_contains(
+            "package `foo` specified by --exclude-all-features not found in workspace",
+        );
+}
+
 #[test]
 fn exclude_all_features_failure() {
     cargo_hack(["check", "--exclude-all-features"])
@@ -1086,6 +2337,86 @@ fn exclude_all_features_failure() {
         .stderr_contains(
             "--exclude-all-features can only be used together with either --each-feature or --feature-powerset",
         );
+
+    cargo_hack(["check", "--exclude-all-features=real"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--exclude-all-features can only be used together with either --each-feature or --feature-powerset",
+        );
+}
+
+#[test]
+fn examples_once() {
+    // Examples are compiled only on the runs with all features and default features.
+    cargo_hack(["test", "--each-feature", "--examples-once", "--print-command-list"])
+        .assert_success("real")
+        .stdout_contains(
+            "
+            cargo test --manifest-path Cargo.toml --all-features  #
+            cargo test --manifest-path Cargo.toml --no-default-features --lib --bins --tests  #
+            cargo test --manifest-path Cargo.toml --no-default-features --lib --bins --tests --features a  #
+            cargo test --manifest-path Cargo.toml --no-default-features --lib --bins --tests --features c  #
+            cargo test --manifest-path Cargo.toml --no-default-features --features default  #
+            ",
+        );
+    cargo_hack(["test", "--each-feature", "--tests-once", "--print-command-list"])
+        .assert_success("real")
+        .stdout_contains(
+            "
+            cargo test --manifest-path Cargo.toml --no-default-features --lib --bins --examples --features a  #
+            cargo test --manifest-path Cargo.toml --no-default-features --features default  #
+            ",
+        );
+    // --all-targets is replaced on the other runs.
+    cargo_hack([
+        "check",
+        "--all-targets",
+        "--feature-powerset",
+        "--depth",
+        "2",
+        "--examples-once",
+        "--tests-once",
+        "-p",
+        "member1",
+    ])
+    .assert_success("real")
+    .stderr_contains(
+        "
+        running `cargo check --no-default-features --lib --bins --benches` on member1
+        running `cargo check --no-default-features --lib --bins --benches --features c,default` on member1
+        running `cargo check --all-targets --no-default-features --features default` on member1
+        ",
+    )
+    .stderr_not_contains("--all-targets --no-default-features --features a");
+    // The run without features is the run with default features if the package
+    // has no `default` feature.
+    cargo_hack([
+        "test",
+        "--each-feature",
+        "--optional-deps",
+        "--examples-once",
+        "--print-command-list",
+    ])
+    .assert_success("optional_deps")
+    .stdout_contains("cargo test --manifest-path Cargo.toml --no-default-features  #")
+    .stdout_not_contains("--no-default-features --lib --bins --tests  #");
+}
+
+#[test]
+fn examples_once_failure() {
+    cargo_hack(["test", "--examples-once"])
+        .assert_failure("real")
+        .stderr_contains("--examples-once can only be used together with either --each-feature");
+    cargo_hack(["check", "--each-feature", "--tests-once"]).assert_failure("real").stderr_contains(
+        "--tests-once requires `test` subcommand or --all-targets flag, because examples and \
+         tests are not compiled otherwise",
+    );
+    cargo_hack(["test", "--each-feature", "--examples-once", "--example=a"])
+        .assert_failure("real")
+        .stderr_contains("--examples-once may not be used together with --example");
+    cargo_hack(["test", "--each-feature", "--examples-once", "--and-then", "check"])
+        .assert_failure("real")
+        .stderr_contains("--examples-once may not be used together with --and-then");
 }
 
 #[test]
@@ -1162,6 +2493,48 @@ fn package_collision() {
     );
 }
 
+#[test]
+fn nested_workspace() {
+    // From the root of the outer workspace.
+    cargo_hack(["check", "--workspace"])
+        .assert_success("nested_workspace")
+        .stderr_contains("running `cargo check` on outer (1/1)")
+        .stderr_not_contains("on inner");
+    cargo_hack(["check", "--workspace", "--manifest-path", "tools/Cargo.toml"])
+        .assert_success("nested_workspace")
+        .stderr_contains("running `cargo check` on inner (1/1)")
+        .stderr_not_contains("on outer");
+
+    // From the root of the inner workspace.
+    cargo_hack(["check", "--workspace"])
+        .assert_success("nested_workspace/tools")
+        .stderr_contains("running `cargo check` on inner (1/1)")
+        .stderr_not_contains("on outer");
+
+    // From a member of the inner workspace.
+    cargo_hack(["check", "--workspace"])
+        .assert_success("nested_workspace/tools/inner")
+        .stderr_contains("running `cargo check` on inner (1/1)")
+        .stderr_not_contains("on outer");
+    cargo_hack(["check"])
+        .assert_success("nested_workspace/tools/inner")
+        .stderr_contains("running `cargo check` on inner (1/1)")
+        .stderr_not_contains("on outer");
+
+    // The selected workspace is logged with --verbose.
+    cargo_hack(["check", "--workspace", "--verbose"])
+        .assert_success("nested_workspace/tools/inner")
+        .stderr_contains(
+            "(located by `cargo locate-project --workspace` from the current directory)",
+        );
+    cargo_hack(["check", "--workspace", "--verbose", "--manifest-path", "tools/Cargo.toml"])
+        .assert_success("nested_workspace")
+        .stderr_contains(
+            "(located by `cargo locate-project --workspace` from --manifest-path \
+             `tools/Cargo.toml`)",
+        );
+}
+
 #[test]
 fn not_find_manifest() {
     cargo_hack(["check"])
@@ -1287,6 +2660,193 @@ fn optional_deps() {
         );
 }
 
+#[test]
+fn args_if_feature() {
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--print-command-list",
+        "--args-if-feature",
+        "a:--profile bench-opt",
+    ])
+    .assert_success("real")
+    .stdout_contains(
+        "
+        cargo check --manifest-path Cargo.toml --all-features --profile bench-opt
+        cargo check --manifest-path Cargo.toml --no-default-features
+        cargo check --manifest-path Cargo.toml --no-default-features --profile bench-opt --features a
+        cargo check --manifest-path Cargo.toml --no-default-features --features b
+        cargo check --manifest-path Cargo.toml --no-default-features --features default
+        ",
+    )
+    .stdout_not_contains(
+        "
+        --profile bench-opt --features b
+        --profile bench-opt --features c
+        --profile bench-opt --features default
+        ",
+    );
+
+    // Enabled via the default features.
+    cargo_hack([
+        "check",
+        "--print-command-list",
+        "--args-if-feature",
+        "a:--release",
+        "-p",
+        "member3",
+    ])
+    .assert_success("real")
+    .stdout_contains(format!(
+        "cargo check --manifest-path member3{MAIN_SEPARATOR}Cargo.toml --release"
+    ));
+
+    cargo_hack(["check", "--args-if-feature", "a:--features b"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--args-if-feature cannot pass `--features`, which is controlled by cargo-hack",
+        );
+    cargo_hack(["check", "--args-if-feature", "a"]).assert_failure("real").stderr_contains(
+        "argument for --args-if-feature must be `<FEATURE>:<ARGS>`, but found `a`",
+    );
+}
+
+#[test]
+fn target_validation() {
+    cargo_hack(["check", "--each-feature", "--target", "x86_64-unknown-linux-gn"])
+        .assert_failure("real")
+        .stderr_contains(
+            "unknown target `x86_64-unknown-linux-gn` specified by --target; did you mean \
+             `x86_64-unknown-linux-gnu`?",
+        )
+        .stderr_not_contains("running `");
+    cargo_hack([
+        "check",
+        "--target",
+        "x86_64-unknown-linux-gnu",
+        "--target",
+        "X86_64-unknown-linux-gnu",
+    ])
+    .assert_failure("real")
+    .stderr_contains(
+        "--target `X86_64-unknown-linux-gnu` and `x86_64-unknown-linux-gnu` differ only in case",
+    )
+    .stderr_not_contains("running `");
+    // Surrounding whitespace is trimmed.
+    cargo_hack(["check", "--print-command-list", "--target", " x86_64-unknown-linux-gnu "])
+        .assert_success("real")
+        .stdout_contains("--target x86_64-unknown-linux-gnu");
+}
+
+#[test]
+fn target_specific_optional_deps() {
+    // `win` is used only on windows, and whether `wide` is used cannot be determined.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--optional-deps",
+        "--print-command-list",
+        "--target",
+        "x86_64-unknown-linux-gnu",
+    ])
+    .assert_success("target_specific_deps")
+    .stdout_contains(
+        "
+        --features a
+        --features any
+        --features wide
+        ",
+    )
+    .stdout_not_contains("--features win")
+    .stderr_contains(
+        "unable to determine whether optional dependency `wide` of package \
+         `target_specific_deps` is used on x86_64-unknown-linux-gnu",
+    );
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--optional-deps",
+        "--print-command-list",
+        "--target",
+        "x86_64-pc-windows-msvc",
+    ])
+    .assert_success("target_specific_deps")
+    .stdout_contains(
+        "
+        --features any
+        --features win
+        --features wide
+        ",
+    );
+    // Used if it is used on any of the targets.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--optional-deps",
+        "--print-command-list",
+        "--target",
+        "x86_64-unknown-linux-gnu",
+        "--target",
+        "x86_64-pc-windows-msvc",
+    ])
+    .assert_success("target_specific_deps")
+    .stdout_contains("--features win");
+
+    // Explicitly specified, but not used on the target.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--optional-deps=win",
+        "--print-command-list",
+        "--target",
+        "x86_64-unknown-linux-gnu",
+    ])
+    .assert_success("target_specific_deps")
+    .stdout_not_contains("--features win")
+    .stderr_contains(
+        "skipped optional dependency `win` of package `target_specific_deps` that is not used on \
+         the target",
+    );
+
+    // Host.
+    if !cfg!(windows) {
+        cargo_hack(["check", "--each-feature", "--optional-deps", "--print-command-list"])
+            .assert_success("target_specific_deps")
+            .stdout_contains("--features any")
+            .stdout_not_contains("--features win");
+    }
+}
+
+#[test]
+fn group_features_optional_deps() {
+    // require Rust 1.31 due to optional_deps uses renamed deps
+    let require = Some(31);
+
+    cargo_hack(["check", "--feature-powerset", "--group-features", "real,renamed"])
+        .assert_failure2("optional_deps", require)
+        .stderr_contains(
+            "group `real,renamed` of --group-features contains `real`, which is an optional \
+             dependency of package `optional_deps`; optional dependencies are only combined with \
+             --optional-deps, so use --optional-deps together with this group",
+        )
+        .stderr_not_contains("running `");
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--optional-deps",
+        "--group-features",
+        "real,renamed",
+    ])
+    .assert_success2("optional_deps", require)
+    .stderr_contains(
+        "
+        running `cargo check --no-default-features` on optional_deps (1/2)
+        running `cargo check --no-default-features --features real,renamed` on optional_deps (2/2)
+        ",
+    );
+}
+
 #[test]
 fn optional_deps_failure() {
     cargo_hack(["check", "--optional-deps"])
@@ -1446,6 +3006,47 @@ fn default_feature_behavior() {
         .stdout_not_contains("has default feature!");
 }
 
+#[test]
+fn unavailable_targets() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    let _r = RUSTUP_TOOLCHAIN_CHANGES.lock().unwrap();
+
+    // wasm32-wasip1 is available since Rust 1.78.
+    cargo_hack(["check", "--version-range", "1.75..=1.76", "--target", "wasm32-wasip1"])
+        .assert_failure("real")
+        .stderr_contains(
+            "
+            the following targets are not available on some versions of --version-range:
+                `wasm32-wasip1` on 1.75, 1.76 (the target may be available as `wasm32-wasi` on these versions)
+            use --skip-unavailable-targets to skip the runs for these targets on these versions
+            ",
+        )
+        .stderr_not_contains("running `");
+
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.75..=1.76",
+        "--target",
+        "wasm32-wasip1",
+        "--skip-unavailable-targets",
+    ])
+    .assert_failure("real") // warn
+    .stderr_contains(
+        "skipping the runs for target `wasm32-wasip1` on 1.75, 1.76, because it is not available \
+         on these versions (--skip-unavailable-targets)",
+    )
+    .stderr_not_contains("running `");
+
+    cargo_hack(["check", "--skip-unavailable-targets"]).assert_failure("real").stderr_contains(
+        "--skip-unavailable-targets can only be used together with either --version-range or \
+             --rust-version",
+    );
+}
+
 #[test]
 fn version_range() {
     // --version-range requires rustup
@@ -1471,6 +3072,29 @@ fn version_range() {
             ",
         );
 
+    // multiple ranges
+    cargo_hack(["check", "--version-range", "1.74..=1.74", "--version-range", "1.76..=1.76"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `rustup run 1.74 cargo check` on real (1/2)
+            running `rustup run 1.76 cargo check` on real (2/2)
+            ",
+        )
+        .stderr_not_contains("rustup run 1.75");
+    // overlapping ranges are deduplicated, and the deprecation warning is emitted per range
+    cargo_hack(["check", "--version-range", "1.74..1.75", "--version-range", "1.75..1.76"])
+        .assert_failure("real") // warn
+        .stderr_contains(
+            "
+            warning: using `..` for inclusive range is deprecated; consider using `1.74..=1.75`
+            warning: using `..` for inclusive range is deprecated; consider using `1.75..=1.76`
+            running `rustup run 1.74 cargo check` on real (1/3)
+            running `rustup run 1.75 cargo check` on real (2/3)
+            running `rustup run 1.76 cargo check` on real (3/3)
+            ",
+        );
+
     cargo_hack(["check", "--version-range", "1.74..=1.75", "--target", TARGET])
         .assert_success("real")
         .stderr_contains(format!(
@@ -1524,6 +3148,33 @@ fn version_range() {
             running `rustup run 1.76 cargo check --locked` on real (7/7)
             ",
     );
+    // member3's MSRV (1.75) is both inserted as its MSRV and a step of the
+    // second range, but each toolchain and package pair runs only once.
+    cargo_hack([
+        "check",
+        "--version-range",
+        "..=1.76",
+        "--version-step",
+        "2",
+        "--version-range",
+        "1.75..=1.75",
+        "--workspace",
+        "--locked",
+    ])
+    .assert_success("rust-version")
+    .stderr_contains(
+        "
+            running `rustup run 1.74 cargo check --locked` on member1 (1/9)
+            running `rustup run 1.74 cargo check --locked` on member2 (2/9)
+            running `rustup run 1.75 cargo check --locked` on member1 (3/9)
+            running `rustup run 1.75 cargo check --locked` on member2 (4/9)
+            running `rustup run 1.75 cargo check --locked` on member3 (5/9)
+            running `rustup run 1.76 cargo check --locked` on member1 (6/9)
+            running `rustup run 1.76 cargo check --locked` on member2 (7/9)
+            running `rustup run 1.76 cargo check --locked` on member3 (8/9)
+            running `rustup run 1.76 cargo check --locked` on real (9/9)
+            ",
+    );
 }
 
 #[test]
@@ -1554,6 +3205,195 @@ fn rust_version() {
         );
 }
 
+#[test]
+fn rust_version_patch() {
+    use fs_err as fs;
+
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    let (_test_project, cur_dir) = test_project("rust-version");
+    let manifest = cur_dir.join("member3/Cargo.toml");
+    let s = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, s.replace("rust-version = \"1.75\"", "rust-version = \"1.75.0\""))
+        .unwrap();
+
+    for args in [&["--rust-version"][..], &["--version-range", "..=1.76"]] {
+        let output = cargo_hack(["check", "--workspace", "--print-command-list"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "STDERR:\n{stderr}");
+        // The patch version is preserved, and no warning is emitted.
+        for pat in [
+            "# rustup toolchain add 1.75.0 --no-self-update",
+            "rustup run 1.75.0 cargo check --manifest-path member3/Cargo.toml",
+        ] {
+            assert!(stdout.contains(pat), "`{pat}` not found in stdout:\n{stdout}");
+        }
+        assert!(!stdout.contains("rustup run 1.75 cargo check --manifest-path member3"));
+    }
+}
+
+#[test]
+fn edition_as_msrv() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // The edition is used as the lower bound if there is no rust-version.
+    cargo_hack(["check", "--version-range", "..=1.57", "--print-command-list"])
+        .assert_failure("edition") // warn
+        .stderr_contains(
+            "warning: no rust-version field in edition's Cargo.toml is specified; using 1.56 \
+             derived from edition 2021 as its minimum Rust version",
+        )
+        .stdout_contains(
+            "
+            rustup run 1.56 cargo check --manifest-path Cargo.toml
+            rustup run 1.57 cargo check --manifest-path Cargo.toml
+            ",
+        )
+        .stdout_not_contains("rustup run 1.55");
+    cargo_hack(["check", "--rust-version", "--workspace", "--print-command-list"])
+        .assert_failure("edition") // warn
+        .stderr_contains(
+            "
+            warning: no rust-version field in old's Cargo.toml is specified; using 1.31 derived from edition 2018 as its minimum Rust version
+            warning: no rust-version field in edition's Cargo.toml is specified; using 1.56 derived from edition 2021 as its minimum Rust version
+            ",
+        )
+        .stderr_not_contains("no rust-version field in pinned's")
+        .stdout_contains(
+            "
+            rustup run 1.31 cargo check --manifest-path old/Cargo.toml
+            rustup run 1.56 cargo check --manifest-path Cargo.toml
+            rustup run 1.60 cargo check --manifest-path pinned/Cargo.toml
+            ",
+        );
+
+    // The edition is not used if the range does not refer to the rust-version.
+    cargo_hack(["check", "--version-range", "1.55..=1.56", "--print-command-list"])
+        .assert_success("edition")
+        .stderr_not_contains("no rust-version field")
+        .stdout_contains(
+            "
+            rustup run 1.55 cargo check --manifest-path Cargo.toml
+            rustup run 1.56 cargo check --manifest-path Cargo.toml
+            ",
+        );
+}
+
+#[test]
+fn feature_min_versions() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // `simd` requires 1.72, and `fast` enables `simd`.
+    cargo_hack(["check", "--each-feature", "--version-range", "..=1.72", "--print-command-list"])
+        .assert_success("feature_min_versions")
+        .stderr_contains(
+            "skipping 3 of 6 feature combinations of feature_min_versions on 1.71, because they \
+             enable features that require a newer Rust: `simd` (1.72) (feature-min-versions)",
+        )
+        .stderr_not_contains("on 1.72, because")
+        .stdout_contains(
+            "
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features a
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features default
+            rustup run 1.72 cargo check --manifest-path Cargo.toml --all-features
+            rustup run 1.72 cargo check --manifest-path Cargo.toml --no-default-features --features fast
+            rustup run 1.72 cargo check --manifest-path Cargo.toml --no-default-features --features simd
+            ",
+        )
+        .stdout_not_contains(
+            "
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --all-features
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features fast
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features simd
+            ",
+        );
+
+    cargo_hack(["check", "--feature-powerset", "--version-range", "..=1.72", "--print-command-list"])
+        .assert_success("feature_min_versions")
+        .stderr_contains("skipping 8 of 12 feature combinations of feature_min_versions on 1.71")
+        .stdout_contains(
+            "
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features a,default
+            rustup run 1.72 cargo check --manifest-path Cargo.toml --no-default-features --features a,fast
+            ",
+        )
+        .stdout_not_contains(
+            "
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features a,fast
+            rustup run 1.71 cargo check --manifest-path Cargo.toml --no-default-features --features a,simd
+            ",
+        );
+
+    // --features is applied to all runs.
+    cargo_hack(["check", "--rust-version", "--features", "fast", "--print-command-list"])
+        .assert_success("feature_min_versions")
+        .stderr_contains("skipping 1 of 1 feature combinations of feature_min_versions on 1.71")
+        .stdout_not_contains("rustup run 1.71");
+    cargo_hack(["check", "--rust-version", "--features", "a", "--print-command-list"])
+        .assert_success("feature_min_versions")
+        .stderr_not_contains("skipping")
+        .stdout_contains("rustup run 1.71 cargo check --manifest-path Cargo.toml --features a");
+}
+
+#[test]
+fn check_msrv_consistency() {
+    use fs_err as fs;
+
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    let _r = RUSTUP_TOOLCHAIN_CHANGES.lock().unwrap();
+
+    let (_test_project, cur_dir) = test_project("rust-version");
+    // member1 works on older versions than its rust-version.
+    let manifest = cur_dir.join("member1/Cargo.toml");
+    let s = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, s.replace("rust-version = \"1.74\"", "rust-version = \"1.75\"")).unwrap();
+    // member2 uses `std::hash::DefaultHasher`, which is stable since 1.76.
+    fs::write(cur_dir.join("member2/src/lib.rs"), "pub use std::hash::DefaultHasher;\n").unwrap();
+
+    let output = cargo_hack([
+        "check",
+        "--version-range",
+        "1.74..=1.76",
+        "--package=member1",
+        "--package=member2",
+        "--keep-going",
+        "--check-msrv-consistency",
+    ])
+    .current_dir(&cur_dir)
+    .output()
+    .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for pat in [
+        "running `rustup run 1.74 cargo check --ignore-rust-version` on member1 (1/6)",
+        "running `rustup run 1.74 cargo check` on member2 (2/6)",
+        "running `rustup run 1.75 cargo check` on member1 (3/6)",
+        "running `rustup run 1.76 cargo check` on member2 (6/6)",
+        "member1: rust-version is 1.75, but all runs passed on 1.74 and later; rust-version may be lowered",
+        "error: member2: rust-version is 1.74, but runs failed on 1.75 (all runs passed on 1.76 and later)",
+    ] {
+        assert!(stderr.contains(pat), "`{pat}` not found in stderr:\n{stderr}");
+    }
+}
+
 #[test]
 fn multi_target() {
     // --version-range requires rustup
@@ -1631,6 +3471,9 @@ fn version_range_failure() {
     cargo_hack(["check", "--version-range", "1.77..=1.76"])
         .assert_failure("real")
         .stderr_contains("specified version range `1.77..=1.76` is empty");
+    cargo_hack(["check", "--version-range", "1.74..=1.75", "--version-range", "1.77..=1.76"])
+        .assert_failure("real")
+        .stderr_contains("specified version range `1.77..=1.76` is empty");
 
     // v0
     cargo_hack(["check", "--version-range", "0.45.."])
@@ -1664,24 +3507,231 @@ fn version_range_failure() {
 }
 
 #[test]
-fn clean_per_version_failure() {
-    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+fn version_range_file() {
+    // --version-range-file requires rustup
+    if !has_rustup() {
         return;
     }
+    let _r = RUSTUP_TOOLCHAIN_CHANGES.lock().unwrap();
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("versions.toml");
 
-    // without --version-range
-    cargo_hack(["check", "--clean-per-version"])
-        .assert_failure("real")
-        .stderr_contains("--clean-per-version can only be used together with --version-range");
-}
-
-#[test]
-fn keep_going() {
-    cargo_hack(["check", "--each-feature", "--keep-going"])
-        .assert_failure("keep_going")
-        .stderr_contains(format!(
+    // The list of versions is run exactly.
+    fs_err::write(&path, "versions = [\"1.74\", \"1.76\"]\n").unwrap();
+    cargo_hack(["check", "--version-range-file"])
+        .arg(&path)
+        .assert_success("real")
+        .stderr_contains(
             "
-            running `cargo check --no-default-features` on keep_going (1/2)
+            running `rustup run 1.74 cargo check` on real (1/2)
+            running `rustup run 1.76 cargo check` on real (2/2)
+            ",
+        )
+        .stderr_not_contains("rustup run 1.75");
+
+    // The range is handled like --version-range.
+    fs_err::write(&path, "start = \"1.74\"\nend = \"1.76\"\n").unwrap();
+    cargo_hack(["check", "--version-step", "2", "--version-range-file"])
+        .arg(&path)
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `rustup run 1.74 cargo check` on real (1/2)
+            running `rustup run 1.76 cargo check` on real (2/2)
+            ",
+        )
+        .stderr_not_contains("rustup run 1.75");
+}
+
+#[test]
+fn version_range_file_failure() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("versions.toml");
+
+    fs_err::write(&path, "versions = [\"1.74\", \"nightly\"]\n").unwrap();
+    cargo_hack(["check", "--version-range-file"])
+        .arg(&path)
+        .assert_failure("real")
+        .stderr_contains(
+            "invalid version `nightly` in `versions`; versions must be `1.<minor>` or `stable`",
+        );
+
+    fs_err::write(&path, "versions = [\"1.74\"]\n").unwrap();
+    cargo_hack(["check", "--version-step", "2", "--version-range-file"])
+        .arg(&path)
+        .assert_failure("real")
+        .stderr_contains(
+            "--version-step may not be used together with `versions` of --version-range-file",
+        );
+    cargo_hack(["check", "--version-range", "1.74..", "--version-range-file"])
+        .arg(&path)
+        .assert_failure("real")
+        .stderr_contains("--version-range-file may not be used together with --version-range");
+    cargo_hack(["check", "--rust-version", "--version-range-file"])
+        .arg(&path)
+        .assert_failure("real")
+        .stderr_contains("--version-range-file may not be used together with --rust-version");
+}
+
+#[test]
+fn compat_strip_lints() {
+    // --print-command-list does not install toolchains, but --version-range requires rustup.
+    if !has_rustup() {
+        return;
+    }
+
+    // Fail before running any commands.
+    cargo_hack(["check", "--version-range", "1.72..=1.74"])
+        .assert_failure("lints")
+        .stderr_contains("the workspace uses `lints` in ")
+        .stderr_contains("`workspace.lints` in ")
+        .stderr_contains(
+            "which cargo older than 1.74 does not support, but the runs include cargo 1.72",
+        )
+        .stderr_contains("; use --compat-strip-lints to remove them")
+        .stderr_not_contains("running `");
+    cargo_hack(["check", "--version-range", "1.74..=1.75", "--print-command-list"])
+        .assert_success("lints")
+        .stdout_contains("rustup run 1.74 cargo check");
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.72..=1.74",
+        "--compat-strip-lints",
+        "--print-command-list",
+    ])
+    .assert_success("lints")
+    .stdout_contains("rustup run 1.72 cargo check");
+}
+
+#[test]
+fn keep_lockfile_changes() {
+    use fs_err as fs;
+
+    // The version of the package in Cargo.lock of the fixture is outdated, so
+    // cargo updates Cargo.lock.
+    let (_test_project, cur_dir) = test_project("stale_lockfile");
+    let lockfile = cur_dir.join("Cargo.lock");
+    let manifest = cur_dir.join("Cargo.toml");
+    let orig = fs::read_to_string(&lockfile).unwrap();
+    let orig_manifest = fs::read_to_string(&manifest).unwrap();
+    let run = |args: &[&str]| {
+        let output = cargo_hack(["check", "--no-dev-deps", "--no-locked-hint"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert_eq!(fs::read_to_string(&manifest).unwrap(), orig_manifest);
+        (output.status.success(), fs::read_to_string(&lockfile).unwrap())
+    };
+
+    // Restored without the flag.
+    assert_eq!(run(&[]), (true, orig.clone()));
+
+    let (success, updated) = run(&["--keep-lockfile-changes"]);
+    assert!(success);
+    assert_ne!(updated, orig);
+    assert!(updated.contains("version = \"0.1.0\""), "{updated}");
+
+    fs::write(&lockfile, &orig).unwrap();
+    assert_eq!(run(&["--keep-lockfile-changes=always", "--bin", "none"]), (false, updated.clone()));
+    fs::write(&lockfile, &orig).unwrap();
+    assert_eq!(run(&["--keep-lockfile-changes=on-success"]), (true, updated));
+    fs::write(&lockfile, &orig).unwrap();
+    // Restored because the run fails.
+    assert_eq!(run(&["--keep-lockfile-changes=on-success", "--bin", "none"]), (false, orig));
+
+    cargo_hack(["check", "--keep-lockfile-changes"])
+        .assert_failure("stale_lockfile")
+        .stderr_contains(
+            "--keep-lockfile-changes can only be used together with either --no-dev-deps or \
+             --no-private",
+        );
+    cargo_hack(["check", "--no-dev-deps", "--keep-lockfile-changes=never"])
+        .assert_failure("stale_lockfile")
+        .stderr_contains(
+            "argument for --keep-lockfile-changes must be always or on-success, but found `never`",
+        );
+    cargo_hack(["check", "--no-dev-deps", "--keep-lockfile-changes", "--version-range", "1.74.."])
+        .assert_failure("stale_lockfile")
+        .stderr_contains(
+            "--keep-lockfile-changes may not be used together with --version-range or \
+             --rust-version unless --lockfile-strategy=keep is used",
+        );
+}
+
+#[test]
+fn locked_hint() {
+    cargo_hack(["check"])
+        .assert_success("stale_lockfile")
+        .stderr_contains("info: Cargo.lock exists but --locked is not used");
+    cargo_hack(["check", "--no-locked-hint"])
+        .assert_success("stale_lockfile")
+        .stderr_not_contains("Cargo.lock exists");
+    cargo_hack(["check", "--locked=auto"])
+        .assert_failure("stale_lockfile") // warn
+        .stderr_not_contains("Cargo.lock exists");
+    // No Cargo.lock.
+    cargo_hack(["check"]).assert_success("real").stderr_not_contains("Cargo.lock exists");
+}
+
+#[test]
+fn auto_locked() {
+    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+        // The lockfile of the fixture cannot be read by old cargo.
+        return;
+    }
+
+    cargo_hack(["check", "--locked"])
+        .assert_failure("stale_lockfile")
+        .stderr_contains("--locked was passed to prevent this")
+        .stderr_not_contains("again without --locked");
+
+    // The lockfile is updated by the first run, so only it is run again.
+    cargo_hack(["check", "--each-feature", "--locked=auto"])
+        .assert_failure("stale_lockfile") // warn
+        .stderr_contains(
+            "
+            running `cargo check --locked --no-default-features` on stale_lockfile (1/2)
+            --locked was passed to prevent this
+            warning: Cargo.lock needs to be updated; running `cargo check --locked \
+            --no-default-features` again without --locked
+            running `cargo check --locked --no-default-features --features a` on stale_lockfile (2/2)
+            ",
+        )
+        .stderr_not_contains("--features a` again without --locked");
+
+    cargo_hack(["check", "--locked", "auto"])
+        .assert_failure("stale_lockfile") // warn
+        .stderr_contains("again without --locked");
+    cargo_hack(["check"])
+        .env("CARGO_HACK_AUTO_LOCKED", "1")
+        .assert_failure("stale_lockfile") // warn
+        .stderr_contains("again without --locked");
+    cargo_hack(["check", "--locked=yes"])
+        .assert_failure("stale_lockfile")
+        .stderr_contains("invalid value `yes` for --locked; the only value is `auto`");
+}
+
+#[test]
+fn clean_per_version_failure() {
+    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+        return;
+    }
+
+    // without --version-range
+    cargo_hack(["check", "--clean-per-version"])
+        .assert_failure("real")
+        .stderr_contains("--clean-per-version can only be used together with --version-range");
+}
+
+#[test]
+fn keep_going() {
+    cargo_hack(["check", "--each-feature", "--keep-going"])
+        .assert_failure("keep_going")
+        .stderr_contains(format!(
+            "
+            running `cargo check --no-default-features` on keep_going (1/2)
             `a` feature not specified
             running `cargo check --no-default-features --features a` on keep_going (2/2)
             `a` feature specified
@@ -1689,216 +3739,2224 @@ fn keep_going() {
             failed commands:
             keep_going:
             cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features`
-            cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features --features a`
+            cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features --features a` (features: a [iterated])
             ",
         ));
+
+    // The origins of features are shown in verbose mode.
+    cargo_hack(["check", "--each-feature", "--keep-going", "-v"])
+        .assert_failure("keep_going")
+        .stderr_contains(
+            "
+            --no-default-features --features a` (2/2)
+            info: features: a [iterated]
+            ",
+        );
 }
 
 #[test]
-fn namespaced_features() {
-    // Namespaced features requires Rust 1.60.
-    let require = Some(60);
+fn and_then() {
+    // `cargo tree` succeeds and `cargo check` fails on this package.
+    cargo_hack(["tree", "--each-feature", "--keep-going", "--and-then", "check", "--and-then", "tree"])
+        .assert_failure("keep_going")
+        .stderr_contains(format!(
+            "
+            running `cargo tree --no-default-features` on keep_going (1/6)
+            running `cargo check --no-default-features` on keep_going (2/6)
+            `a` feature not specified
+            skipped 1 command chained by --and-then on keep_going, because a previous command failed
+            running `cargo tree --no-default-features --features a` on keep_going (4/6)
+            running `cargo check --no-default-features --features a` on keep_going (5/6)
+            `a` feature specified
+            skipped 1 command chained by --and-then on keep_going, because a previous command failed
+            failed to run 2 commands
+            failed commands:
+            keep_going:
+            cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features`
+            cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features --features a` (features: a [iterated])
+            ",
+        ))
+        .stderr_not_contains("(3/6)");
 
+    // Without --keep-going, the first failure stops all runs.
+    cargo_hack(["tree", "--each-feature", "--and-then", "check"])
+        .assert_failure("keep_going")
+        .stderr_contains("running `cargo check --no-default-features` on keep_going (2/4)")
+        .stderr_not_contains("(3/4)");
+
+    // The args after `--` are passed to the chained command.
     cargo_hack([
-        "run",
-        "--features=explicit,implicit,combo,renamed,member1,member2,member3",
-        "--ignore-unknown-features",
+        "check",
+        "--each-feature",
+        "--exclude-features",
+        "b,c,default",
+        "--print-command-list",
+        "--and-then",
+        "clippy --no-deps -- -D warnings",
     ])
-    .assert_success2("namespaced_features", require)
-    .stderr_contains(
+    .assert_success("real")
+    .stdout_contains(
         "
-            skipped applying unknown `member1` feature to namespaced_features
-            skipped applying unknown `member2` feature to namespaced_features
-            skipped applying unknown `member3` feature to namespaced_features
-            running `cargo run --features explicit,implicit,combo,renamed` on namespaced_features
+        cargo check --manifest-path Cargo.toml --no-default-features
+        cargo clippy --no-deps --manifest-path Cargo.toml --no-default-features -- -D warnings
+        cargo check --manifest-path Cargo.toml --no-default-features --features a
+        cargo clippy --no-deps --manifest-path Cargo.toml --no-default-features --features a -- -D warnings
+        ",
+    );
+
+    cargo_hack(["check", "--no-dev-deps", "--and-then", "test"])
+        .assert_failure("real")
+        .stderr_contains("--no-dev-deps may not be used together with test subcommand");
+    cargo_hack(["check", "--no-dev-deps", "--and-then", "check --all-targets"])
+        .assert_failure("real")
+        .stderr_contains("--no-dev-deps may not be used together with --all-targets");
+}
+
+#[test]
+fn placeholders() {
+    // The test of this package prints its args.
+    cargo_hack(["test", "--test", "args", "--each-feature", "--", "{package}:{features}", "{{features}}", "{}"])
+        .assert_success("placeholders")
+        .stderr_contains(
+            "running `cargo test --test args --no-default-features --features a -- placeholders:a {features} \
+             {}` on placeholders",
+        )
+        .stdout_contains(
+            "
+            args: placeholders: {features} {}
+            args: placeholders:a {features} {}
+            args: placeholders:b {features} {}
             ",
-    )
+        );
+    cargo_hack(["test", "--test", "args", "--", "--test-arg-{toolchain}"])
+        .assert_success("placeholders")
+        .stdout_contains("args: --test-arg-1.");
+    // Arguments without placeholders are passed as is.
+    cargo_hack(["test", "--test", "args", "--", "{{x}}"])
+        .assert_success("placeholders")
+        .stdout_contains("args: {{x}}");
+
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    cargo_hack([
+        "test",
+        "--test",
+        "args",
+        "--version-range",
+        "1.75..=1.76",
+        "--print-command-list",
+        "--",
+        "--test-arg-{toolchain}",
+    ])
+    .assert_success("placeholders")
     .stdout_contains(
         "
-            explicit
-            implicit
-            combo
-            renamed
+        rustup run 1.75 cargo test --test args --manifest-path Cargo.toml -- --test-arg-1.75
+        rustup run 1.76 cargo test --test args --manifest-path Cargo.toml -- --test-arg-1.76
+        ",
+    );
+}
+
+#[test]
+fn quiet_success() {
+    // The output of successful commands is not printed.
+    cargo_hack(["check", "--each-feature", "--quiet-success", "-p", "member1"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on member1 (1/6) [29f294b1] ... ok (
+            running `cargo check --no-default-features --features c` on member1 (5/6) [957d88b0] ... ok (
             ",
-    )
-    .stdout_not_contains(
-        "
-            member1
-            member2
-            member3
+        )
+        .stderr_not_contains(
+            "
+            Checking member1
+            Finished
+            ",
+        );
+
+    // The output of failed commands is printed.
+    cargo_hack(["check", "--each-feature", "--keep-going", "--quiet-success"])
+        .assert_failure("keep_going")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on keep_going (1/2) [6ad31eb8] ... failed (
+            `a` feature not specified
+            running `cargo check --no-default-features --features a` on keep_going (2/2) [c76deeb1] ... failed (
+            `a` feature specified
+            failed to run 2 commands
             ",
+        );
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--keep-going",
+        "--quiet-success",
+        "--show-output-lines",
+        "2",
+    ])
+    .assert_failure("keep_going")
+    .stderr_contains(
+        "
+        running `cargo check --no-default-features` on keep_going (1/2) [6ad31eb8] ... failed (
+        lines omitted)
+        error: could not compile `keep_going`
+        ",
+    )
+    .stderr_not_contains("`a` feature not specified");
+}
+
+#[test]
+fn compact_output() {
+    // The result is appended to the line if the command prints nothing.
+    let (_dir, cur_dir) = test_project("real");
+    let output = cargo_bin_exe()
+        .args(["hack", "check", "--each-feature", "--compact-output", "--quiet", "-p", "member1"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("\n\n"), "{stderr}");
+    for pat in [
+        "running `cargo check --quiet --all-features` on member1 (1/6) [29f294b1] ... ok (",
+        "running `cargo check --quiet --no-default-features --features c` on member1 (5/6) [957d88b0] ... ok (",
+    ] {
+        assert!(stderr.contains(pat), "{pat} not found in:\n{stderr}");
+    }
+
+    // The output of cargo starts on a new line.
+    let output = cargo_bin_exe()
+        .args(["hack", "check", "--each-feature", "--compact-output", "-p", "member1"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("\n\n"), "{stderr}");
+    assert!(
+        stderr.contains("running `cargo check --all-features` on member1 (1/6) [29f294b1]\n"),
+        "{stderr}"
     );
+    assert!(!stderr.contains("... ok"), "{stderr}");
 
-    cargo_hack(["check", "--each-feature"])
-        .assert_success2("namespaced_features", require)
+    cargo_hack(["check", "--each-feature", "--keep-going", "--compact-output", "--quiet"])
+        .assert_failure("keep_going")
         .stderr_contains(
             "
-            running `cargo check --all-features` on namespaced_features (1/4)
-            running `cargo check --no-default-features` on namespaced_features (2/4)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/4)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/4)
+            running `cargo check --quiet --no-default-features` on keep_going (1/2)
+            `a` feature not specified
+            failed to run 2 commands
+            ",
+        );
+
+    // The group pairs are not changed.
+    cargo_hack(["check", "--compact-output", "--quiet", "--log-group", "github-actions"])
+        .assert_success("real")
+        .stdout_contains(
+            "
+            ::group::running `cargo check --quiet` on real (1/1)
+            ::endgroup::
             ",
         )
-        .stderr_not_contains(
+        .stderr_not_contains("... ok");
+}
+
+#[test]
+fn tag_output() {
+    let (_dir, cur_dir) = test_project("real");
+    let output = cargo_bin_exe()
+        .args(["hack", "check", "--each-feature", "--tag-output", "-p", "member1", "-p", "member2"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut tag = None;
+    let mut runs = 0;
+    for line in stderr.lines() {
+        if let Some(running) = line.strip_prefix("info: running ") {
+            // e.g., "`cargo check --all-features` on member1 (1/12) [29f294b1]"
+            let (_, rest) = running.rsplit_once("` on ").unwrap();
+            let (package, rest) = rest.split_once(' ').unwrap();
+            let id = rest.rsplit_once('[').unwrap().1.strip_suffix(']').unwrap();
+            tag = Some(format!("[{package}:{id}] "));
+            runs += 1;
+            continue;
+        }
+        if line.is_empty() || line.starts_with("info: ") {
+            continue;
+        }
+        // Every line of cargo is tagged with the current run, and is not split.
+        let tag = tag.as_deref().unwrap();
+        let rest = line.strip_prefix(tag).unwrap_or_else(|| panic!("{line:?} without {tag:?}"));
+        assert!(!rest.contains("[member"), "{line:?}");
+    }
+    assert_eq!(runs, 12, "{stderr}");
+    assert!(stderr.contains("[member2:"), "{stderr}");
+}
+
+#[test]
+fn trace_file() {
+    let (_dir, cur_dir) = test_project("real");
+    let trace = cur_dir.join("trace.log");
+    let output = cargo_bin_exe()
+        .args([
+            "hack",
+            "check",
+            "--feature-powerset",
+            "--depth",
+            "2",
+            "--mutually-exclusive-features",
+            "a,b",
+            "--cfg-matrix",
+            "secret_cfg",
+            "-p",
+            "real",
+            "-p",
+            "member1",
+            "--trace-file",
+        ])
+        .arg(&trace)
+        .current_dir(&cur_dir)
+        // --cfg-matrix (used to set RUSTFLAGS for the commands) warns about the build cache.
+        .env_remove("CARGO_HACK_DENY_WARNINGS")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("[args]"), "{stderr}");
+    let trace = std::fs::read_to_string(trace).unwrap();
+    for expected in [
+        "] [args] argv: [",
+        "] [args] features: each feature: false, feature powerset: true, depth: Some(2)",
+        "] [metadata] workspace root: ",
+        "] [plan] selected packages: [\"member1\", \"real\"]",
+        "] [features] package `real`: normal features: [a, b, c, default]",
+        "] [features] package `member1`: normal features: [a, b, c, default]",
+        "] [plan] package `real`: combination [a, b] removed by --mutually-exclusive-features \
+         because `a` and `b` are in the same group `a,b`",
+        "] [plan] package `real`: powerset of 4 features (depth: Some(2)): kept 9 combinations",
+        "] [plan] package `member1`: powerset of 4 features",
+        "] [command] run ",
+        " on package `member1` with --no-default-features --features a (",
+        "RUSTFLAGS=\"<redacted>\"",
+        " exited with exit status: 0",
+    ] {
+        assert!(trace.contains(expected), "{expected:?} not found in:\n{trace}");
+    }
+    // The values of the environment variables are redacted.
+    assert!(!trace.contains("--cfg secret_cfg"), "{trace}");
+    // Each line has the elapsed time.
+    assert!(trace.lines().skip(1).all(|line| line.starts_with('[') && line.contains("s] [")));
+}
+
+#[test]
+fn env_config() {
+    cargo_hack(["check", "-p", "member1"])
+        .env("CARGO_HACK_EACH_FEATURE", "true")
+        .env("CARGO_HACK_EXCLUDE_FEATURES", "b,c")
+        .assert_success("real")
+        .stderr_contains(
             "
-            --features implicit
-            --features renamed
+            running `cargo check --no-default-features --features a` on member1 (2/3)
+            ",
+        )
+        .stderr_not_contains("--features b");
+    cargo_hack(["check", "-p", "member1", "--no-env-config"])
+        .env("CARGO_HACK_EACH_FEATURE", "true")
+        .assert_success("real")
+        .stderr_contains("running `cargo check` on member1 (1/1)");
+    cargo_hack(["check", "--feature-powerset"])
+        .env("CARGO_HACK_DEPTH", "x")
+        .assert_failure("real")
+        .stderr_contains("CARGO_HACK_DEPTH: failed to parse argument for --depth");
+}
+
+#[test]
+fn deny_warnings_for() {
+    // member1 has a warning, member2 has no warnings.
+    cargo_hack(["check", "--workspace"]).assert_success("deny_warnings");
+
+    cargo_hack(["check", "--workspace", "--deny-warnings-for", "member1", "--keep-going"])
+        .assert_failure("deny_warnings")
+        .stderr_contains(
+            r#"
+            warning: --deny-warnings-for runs commands on some of the packages with different RUSTFLAGS
+            running `RUSTDOCFLAGS="-D warnings" RUSTFLAGS="-D warnings" cargo check` on member1 (1/2)
+            running `cargo check` on member2 (2/2)
+            failed to run 1 commands
+            "#,
+        );
+
+    cargo_hack(["check", "--workspace", "--deny-warnings-for", "member2"])
+        .assert_failure("deny_warnings")
+        .stderr_contains(
+            r#"
+            running `cargo check` on member1 (1/2)
+            running `RUSTDOCFLAGS="-D warnings" RUSTFLAGS="-D warnings" cargo check` on member2 (2/2)
+            "#,
+        )
+        .stderr_not_contains("error: unused");
+
+    cargo_hack(["check", "--package", "member2", "--deny-warnings-for", "member1,member2"])
+        .assert_success("deny_warnings")
+        .stderr_contains(
+            r#"running `RUSTDOCFLAGS="-D warnings" RUSTFLAGS="-D warnings" cargo check` on member2"#,
+        );
+    cargo_hack(["check", "--package", "member1", "--deny-warnings-for", "member1"])
+        .assert_failure("deny_warnings")
+        .stderr_contains("error: unused variable");
+
+    cargo_hack(["check", "--workspace", "--deny-warnings-for", "member3"])
+        .assert_failure("deny_warnings")
+        .stderr_contains(
+            "package `member3` specified by --deny-warnings-for not found in workspace",
+        );
+}
+
+#[test]
+fn redundant_packages() {
+    // wrapper only re-exports base, and wrapper2 only re-exports wrapper.
+    // other also re-exports base, but has its own code.
+    cargo_hack(["check", "--workspace", "--each-feature", "--report-redundant-packages"])
+        .assert_success("redundant_packages")
+        .stderr_contains(
+            "
+            package `wrapper` is redundant: it only re-exports `base` and has the same feature combinations; use --skip-redundant-packages to skip it
+            package `wrapper2` is redundant: it only re-exports `wrapper` (a wrapper of `base`) and has the same feature combinations; use --skip-redundant-packages to skip it
+            running `cargo check --no-default-features` on base (1/8)
+            running `cargo check --no-default-features --features std` on wrapper2 (8/8)
             ",
         );
 
-    cargo_hack(["check", "--each-feature", "--optional-deps"])
-        .assert_success2("namespaced_features", require)
-        .stderr_contains(
+    cargo_hack(["check", "--workspace", "--each-feature", "--skip-redundant-packages"])
+        .assert_success("redundant_packages")
+        .stderr_contains(
+            "
+            skipped running on package `wrapper`: it only re-exports `base` and has the same feature combinations (--skip-redundant-packages)
+            skipped running on package `wrapper2`: it only re-exports `wrapper` (a wrapper of `base`) and has the same feature combinations (--skip-redundant-packages)
+            running `cargo check --no-default-features` on base (1/4)
+            running `cargo check --no-default-features --features std` on base (2/4)
+            running `cargo check --no-default-features` on other (3/4)
+            running `cargo check --no-default-features --features std` on other (4/4)
+            ",
+        )
+        .stderr_not_contains("on wrapper");
+
+    // The package wrapper2 re-exports is not selected.
+    cargo_hack(["check", "-p", "wrapper2", "-p", "other", "--skip-redundant-packages"])
+        .assert_success("redundant_packages")
+        .stderr_contains(
+            "
+            no redundant packages found
+            running `cargo check` on other (1/2)
+            running `cargo check` on wrapper2 (2/2)
+            ",
+        );
+}
+
+#[test]
+fn cfg_matrix() {
+    // The fixture fails to compile only with both `--cfg foo` and feature `a`.
+    cargo_hack(["check", "--each-feature", "--cfg-matrix", "foo,bar", "--keep-going"])
+        .assert_failure("cfg_matrix")
+        .stderr_contains(format!(
+            r#"
+            warning: --cfg-matrix runs commands with different RUSTFLAGS
+            running `cargo check --no-default-features` on cfg_matrix (1/8)
+            running `cargo check --no-default-features --features a` on cfg_matrix (2/8)
+            running `RUSTFLAGS="--cfg foo" cargo check --no-default-features` on cfg_matrix (3/8)
+            running `RUSTFLAGS="--cfg foo" cargo check --no-default-features --features a` on cfg_matrix (4/8)
+            foo with a
+            running `RUSTFLAGS="--cfg bar" cargo check --no-default-features` on cfg_matrix (5/8)
+            running `RUSTFLAGS="--cfg bar" cargo check --no-default-features --features a` on cfg_matrix (6/8)
+            running `RUSTFLAGS="--cfg foo --cfg bar" cargo check --no-default-features` on cfg_matrix (7/8)
+            running `RUSTFLAGS="--cfg foo --cfg bar" cargo check --no-default-features --features a` on cfg_matrix (8/8)
+            failed to run 2 commands
+            failed commands:
+            cfg_matrix:
+            cargo{EXE_SUFFIX} check --manifest-path Cargo.toml --no-default-features --features a` (features: a [iterated])
+            "#,
+        ))
+        .stderr_not_contains("(9/8)");
+
+    // Existing RUSTFLAGS are kept, and --depth limits the number of simultaneous cfgs.
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--depth",
+        "1",
+        "--cfg-matrix",
+        "foo",
+        "--cfg-matrix",
+        "bar",
+        "--print-command-list",
+    ])
+    .env("RUSTFLAGS", "-Dwarnings")
+    .assert_success("cfg_matrix")
+    .stdout_contains(
+        r#"
+        cargo check --manifest-path Cargo.toml --no-default-features --features a
+        RUSTFLAGS="-Dwarnings --cfg foo" cargo check --manifest-path Cargo.toml --no-default-features
+        RUSTFLAGS="-Dwarnings --cfg bar" cargo check --manifest-path Cargo.toml --no-default-features --features a
+        "#,
+    )
+    .stdout_not_contains("--cfg foo --cfg bar");
+
+    cargo_hack(["check", "--cfg-matrix", "foo bar,baz qux"])
+        .assert_failure("cfg_matrix")
+        .stderr_contains("cfg `foo bar` specified by --cfg-matrix may not contain whitespace");
+}
+
+#[test]
+fn verify_lockfile_per_version() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    let _r = RUSTUP_TOOLCHAIN_CHANGES.lock().unwrap();
+
+    // The seeded Cargo.lock has an outdated version of `dep` and an unused package.
+    cargo_hack(["check", "--version-range", "1.74..=1.75", "--verify-lockfile-per-version"])
+        .assert_success("lockfile_drift")
+        .stderr_contains(
+            "
+            running `rustup run 1.74 cargo check` on lockfile_drift (1/2)
+            Cargo.lock changed during runs on 1.74: dep: 0.1.0 -> 0.2.0, stale: 1.0.0 (removed)
+            running `rustup run 1.75 cargo check` on lockfile_drift (2/2)
+            lockfile drift per toolchain:
+                1.74:
+                    dep: 0.1.0 -> 0.2.0
+                    stale: 1.0.0 (removed)
+                1.75: no changes
+            ",
+        )
+        .stderr_not_contains("Cargo.lock changed during runs on 1.75");
+}
+
+#[test]
+fn summary_markdown() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("summary.md");
+    cargo_hack(["check", "--each-feature", "--keep-going", "--summary-markdown"])
+        .arg(&path)
+        .assert_failure("keep_going")
+        .stderr_contains(
+            "
+            `a` feature not specified
+            `a` feature specified
+            failed to run 2 commands
+            ",
+        );
+
+    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+        // The report contains the results per Rust version in this case.
+        return;
+    }
+    // Normalize volatile parts: the path of the report and the output of cargo.
+    let summary = fs_err::read_to_string(&path).unwrap().replace(path.to_str().unwrap(), "<PATH>");
+    let mut normalized = String::new();
+    let mut in_code_block = false;
+    for line in summary.lines() {
+        if line.starts_with("```") {
+            in_code_block = !in_code_block;
+            normalized.push_str(line);
+            normalized.push('\n');
+            if in_code_block {
+                normalized.push_str("...\n");
+            }
+        } else if !in_code_block {
+            normalized.push_str(line);
+            normalized.push('\n');
+        }
+    }
+    assert_eq!(
+        normalized,
+        "\
+## cargo-hack summary
+
+`cargo hack check --each-feature --keep-going --summary-markdown <PATH>`
+
+2 commands: 0 passed, 2 failed, 0 skipped
+
+| Package | Run | Passed | Failed | Skipped |
+| --- | ---: | ---: | ---: | ---: |
+| keep_going | 2 | 0 | 2 | 0 |
+
+### Failures
+
+<details>
+<summary>keep_going: <code>cargo check --no-default-features</code></summary>
+
+Features: `--no-default-features`
+
+```text
+...
+```
+
+</details>
+
+<details>
+<summary>keep_going: <code>cargo check --no-default-features --features a</code></summary>
+
+Features: `--no-default-features --features a`
+
+```text
+...
+```
+
+</details>
+"
+    );
+}
+
+#[test]
+fn junit() {
+    /// Returns the name and the attributes of each start tag in the XML.
+    fn tags(xml: &str) -> Vec<(&str, Vec<(&str, &str)>)> {
+        let mut tags = vec![];
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if tag.starts_with(['/', '?']) {
+                continue;
+            }
+            let tag = tag.trim_end_matches('/');
+            let (name, mut rest) = tag.split_once(' ').unwrap_or((tag, ""));
+            let mut attrs = vec![];
+            while let Some((key, value)) = rest.trim_start().split_once("=\"") {
+                let end = value.find('"').unwrap();
+                attrs.push((key, &value[..end]));
+                rest = &value[end + 1..];
+            }
+            tags.push((name, attrs));
+        }
+        tags
+    }
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("junit.xml");
+    cargo_hack(["check", "--each-feature", "--keep-going", "--partition", "1/2", "--junit"])
+        .arg(&path)
+        .assert_failure("keep_going")
+        .stderr_contains("`a` feature not specified");
+
+    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+        // The names of test cases contain the Rust version in this case.
+        return;
+    }
+    let xml = fs_err::read_to_string(&path).unwrap();
+    let tags = tags(&xml);
+    let names: Vec<_> = tags.iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, [
+        "testsuites",
+        "testsuite",
+        "testcase",
+        "properties",
+        "property",
+        "failure",
+        "testcase",
+        "skipped"
+    ]);
+    let attr = |i: usize, key: &str| {
+        tags[i].1.iter().find(|(k, _)| *k == key).map(|(_, v)| *v).unwrap().to_owned()
+    };
+    assert_eq!(attr(1, "tests"), "2");
+    assert_eq!(attr(1, "failures"), "1");
+    assert_eq!(attr(1, "skipped"), "1");
+    assert_eq!(attr(2, "classname"), "keep_going");
+    assert_eq!(attr(2, "name"), "--no-default-features");
+    assert_eq!(attr(4, "name"), "cold-start");
+    assert!(attr(5, "message").contains("exit status: 101"), "{xml}");
+    assert!(xml.contains("`a` feature not specified"), "{xml}");
+    assert_eq!(attr(6, "name"), "--no-default-features --features a");
+    assert_eq!(attr(6, "time"), "0.000");
+    assert_eq!(attr(7, "message"), "not in the partition specified by --partition");
+}
+
+#[test]
+fn cold_start() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("junit.xml");
+    cargo_hack(["check", "--workspace", "--each-feature", "--junit"])
+        .arg(&path)
+        .assert_success("real");
+    let xml = fs_err::read_to_string(&path).unwrap();
+    // Only the first run on each package is marked as a cold start.
+    let mut seen = BTreeSet::new();
+    let testcases: Vec<_> = xml.split("<testcase ").skip(1).collect();
+    assert_eq!(testcases.len(), 24, "{xml}");
+    for testcase in testcases {
+        let classname = testcase.split('"').nth(1).unwrap();
+        let first = seen.insert(classname);
+        let cold_start = testcase.contains(r#"<property name="cold-start" value="true"/>"#);
+        assert_eq!(cold_start, first, "{classname}: {testcase}");
+    }
+    assert_eq!(seen.len(), 4, "{xml}");
+
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--slow-threshold-factor",
+        "3",
+        "--discard-warmup-timing",
+    ])
+    .assert_success("real")
+    .stderr_contains("slowest commands:");
+    cargo_hack(["check", "--discard-warmup-timing"]).assert_failure("real").stderr_contains(
+        "--discard-warmup-timing can only be used together with --slow-threshold-factor",
+    );
+}
+
+#[test]
+fn badge_json() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("badge.json");
+    let badge = || fs_err::read_to_string(&path).unwrap();
+
+    cargo_hack(["check", "--each-feature", "--badge-json"])
+        .arg(&path)
+        .assert_success("real")
+        .stderr_contains("(6/6)");
+    assert_eq!(
+        badge(),
+        "{\"schemaVersion\":1,\"label\":\"feature matrix\",\"message\":\"6/6 passing\",\"color\":\"brightgreen\"}\n"
+    );
+
+    cargo_hack(["check", "--each-feature", "--keep-going", "--badge-json"])
+        .arg(&path)
+        .assert_failure("keep_going")
+        .stderr_contains("failed to run 2 commands");
+    assert_eq!(
+        badge(),
+        "{\"schemaVersion\":1,\"label\":\"feature matrix\",\"message\":\"0/2 passing, 2 failed\",\"color\":\"red\"}\n"
+    );
+
+    // The commands that were not run because of the first failure are counted.
+    cargo_hack(["check", "--each-feature", "--badge-json"]).arg(&path).assert_failure("keep_going");
+    assert!(badge().contains("\"message\":\"0/2 passing, 1 failed\""), "{}", badge());
+
+    cargo_hack(["check", "--each-feature", "--partition", "2/2", "--badge-json"])
+        .arg(&path)
+        .assert_success("real");
+    assert!(badge().contains("\"message\":\"3/3 (partition 2/2)\""), "{}", badge());
+    cargo_hack(["check", "--each-feature", "--partition", "big=0%..60%", "--badge-json"])
+        .arg(&path)
+        .assert_success("real");
+    assert!(badge().contains("\"message\":\"3/3 (partition big)\""), "{}", badge());
+}
+
+#[test]
+fn baseline() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("baseline.json");
+
+    // On the baseline, only the run with `a` passes.
+    cargo_hack(["check", "--each-feature", "--keep-going", "--summary-json"])
+        .arg(&path)
+        .env("RUSTFLAGS", "--cfg pass_a")
+        .assert_failure("keep_going")
+        .stderr_contains("failed to run 1 commands");
+    let summary: serde_json::Value =
+        serde_json::from_str(&fs_err::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(summary["version"], 1);
+    let runs: Vec<_> = summary["runs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|run| {
+            assert_eq!(run["package"], "keep_going");
+            assert_eq!(run["id"].as_str().unwrap().len(), 8);
+            (run["features"].as_str().unwrap(), run["status"].as_str().unwrap())
+        })
+        .collect();
+    assert_eq!(runs, [
+        ("--no-default-features", "failed"),
+        ("--no-default-features --features a", "passed"),
+    ]);
+
+    // Now, only the run without `a` passes.
+    cargo_hack(["check", "--each-feature", "--keep-going", "--baseline"])
+        .arg(&path)
+        .env("RUSTFLAGS", "--cfg pass_no_a")
+        .assert_failure("keep_going")
+        .stderr_contains(
+            "
+            compared with the baseline
+            : 1 newly failing, 1 newly passing, 0 still failing
+                newly failing:
+                    `cargo check --no-default-features --features a` on keep_going
+                newly passing:
+                    `cargo check --no-default-features` on keep_going
+            ",
+        );
+
+    // Both fail. The new failure still fails the run with --fail-on-new-failures-only.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--keep-going",
+        "--fail-on-new-failures-only",
+        "--baseline",
+    ])
+    .arg(&path)
+    .assert_failure("keep_going")
+    .stderr_contains(
+        "`cargo check --no-default-features` on keep_going also failed in the baseline; \
+         ignoring it (--fail-on-new-failures-only)",
+    )
+    .stderr_contains("failed to run 1 commands")
+    .stderr_contains(
+        "
+        : 1 newly failing, 0 newly passing, 1 still failing
+            newly failing:
+                `cargo check --no-default-features --features a` on keep_going
+            still failing:
+                `cargo check --no-default-features` on keep_going
+        ",
+    );
+
+    // Only the failure that is also in the baseline.
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--keep-going",
+        "--fail-on-new-failures-only",
+        "--baseline",
+    ])
+    .arg(&path)
+    .env("RUSTFLAGS", "--cfg pass_a")
+    .assert_success("keep_going")
+    .stderr_contains(": 0 newly failing, 0 newly passing, 1 still failing")
+    .stderr_not_contains("failed to run");
+
+    cargo_hack(["check", "--each-feature", "--fail-on-new-failures-only"])
+        .assert_failure("keep_going")
+        .stderr_contains("--fail-on-new-failures-only can only be used together with --baseline");
+    cargo_hack(["check", "--each-feature", "--fail-on-new-failures-only", "--baseline"])
+        .arg(&path)
+        .assert_failure("keep_going")
+        .stderr_contains("--fail-on-new-failures-only can only be used together with --keep-going");
+    cargo_hack(["check", "--baseline", "Cargo.toml"])
+        .assert_failure("keep_going")
+        .stderr_contains("failed to load baseline `Cargo.toml`");
+}
+
+#[test]
+fn budget() {
+    if env::var_os("CARGO_HACK_TEST_TOOLCHAIN").is_some() {
+        // The commands contain the Rust version in this case.
+        return;
+    }
+
+    // The build script of the fixture sleeps 1.5s, so the budget is exceeded
+    // after the first run.
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("junit.xml");
+    let (_test_project, cur_dir) = test_project("budget");
+    let output = cargo_hack(["check", "--each-feature", "--budget", "1s", "--junit"])
+        .arg(&path)
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // A distinct exit code is used if no other errors occurred.
+    assert_eq!(output.status.code(), Some(3), "STDERR:\n{stderr}");
+    for pat in [
+        "running `cargo check --all-features` on budget (1/5, 1s of --budget left)",
+        "info: --budget exceeded; not running the remaining 4 commands",
+        "error: --budget (1s) exceeded after running commands for ",
+        "; 4 of 5 commands were not run, starting at `cargo check --no-default-features` on \
+         budget (2/5)",
+    ] {
+        assert!(stderr.contains(pat), "{pat}\nSTDERR:\n{stderr}");
+    }
+    assert!(!stderr.contains("running `cargo check --no-default-features`"), "STDERR:\n{stderr}");
+    let xml = fs_err::read_to_string(&path).unwrap();
+    assert!(xml.contains(r#"tests="5" failures="0" errors="0" skipped="4""#), "{xml}");
+    assert_eq!(xml.matches(r#"<skipped message="not run (budget exceeded)"/>"#).count(), 4);
+
+    // The budget is not exceeded.
+    cargo_hack(["check", "--budget", "1h"])
+        .assert_success("budget")
+        .stderr_contains("running `cargo check` on budget (1/1, 1h of --budget left)");
+
+    cargo_hack(["check", "--budget", "1m1h"])
+        .assert_failure("budget")
+        .stderr_contains("failed to parse argument for --budget: invalid duration `1m1h`");
+}
+
+#[test]
+fn porcelain() {
+    let (_tmp, cur_dir) = test_project("real");
+    let output = cargo_hack(["check", "--each-feature", "--porcelain"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    // The log for humans is printed to stderr.
+    assert!(stderr.contains("cargo check --all-features` on real (1/6)"), "{stderr}");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("porcelain-version 2"));
+    let records: Vec<Vec<&str>> = lines.map(|line| line.split('\t').collect()).collect();
+    assert_eq!(records.len(), 12, "{stdout}");
+    let features = [
+        "--all-features",
+        "--no-default-features",
+        "--no-default-features --features a",
+        "--no-default-features --features b",
+        "--no-default-features --features c",
+        "--no-default-features --features default",
+    ];
+    for (i, pair) in records.chunks(2).enumerate() {
+        let index = (i + 1).to_string();
+        let id = pair[0][1];
+        assert_eq!(id.len(), 8, "{stdout}");
+        assert_eq!(pair[0], ["RUN", id, "real", features[i], &index, "6"], "{stdout}");
+        assert_eq!(pair[1], ["OK", id, "real", features[i], &index, "6"], "{stdout}");
+    }
+
+    let output = cargo_hack(["check", "--each-feature", "--porcelain", "--partition", "2/2"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<Vec<&str>> = stdout.lines().skip(1).map(|l| l.split('\t').collect()).collect();
+    let id = records[0][1];
+    assert_eq!(
+        records[0],
+        ["SKIP", id, "real", "--all-features", "1", "6", "partition"],
+        "{stdout}"
+    );
+    assert_eq!(records.iter().filter(|r| r[0] == "RUN").count(), 3, "{stdout}");
+    assert!(records.iter().all(|r| r.len() == if r[0] == "SKIP" { 7 } else { 6 }), "{stdout}");
+
+    let output = cargo_hack(["check", "--each-feature", "--porcelain", "--only-run", id])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<Vec<&str>> = stdout.lines().skip(1).map(|l| l.split('\t').collect()).collect();
+    assert_eq!(records[0], ["RUN", id, "real", "--all-features", "1", "6"], "{stdout}");
+    assert_eq!(records[2][0], "SKIP", "{stdout}");
+    assert_eq!(records[2][6], "only-run", "{stdout}");
+
+    cargo_hack(["check", "--porcelain", "--print-command-list"])
+        .assert_failure("real")
+        .stderr_contains("--porcelain may not be used together with --print-command-list");
+}
+
+#[test]
+fn capture_stdout_dir() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    cargo_hack(["rustc", "--each-feature", "--lib", "--capture-stdout-dir"])
+        .arg(tmpdir.path())
+        .args(["--", "--print", "cfg"])
+        .assert_success("real")
+        .stdout_contains("feature=\"a\"");
+
+    let dir = tmpdir.path().join("real");
+    let cfg = |name: &str| {
+        let s = fs_err::read_to_string(dir.join(format!("{name}.txt"))).unwrap();
+        s.lines().filter(|l| l.starts_with("feature=")).map(str::to_owned).collect::<Vec<_>>()
+    };
+    // `--features default` and the default features share the same file.
+    assert_eq!(cfg("default"), ["feature=\"default\""]);
+    assert!(cfg("(no-features)").is_empty());
+    assert_eq!(cfg("a"), ["feature=\"a\""]);
+    assert_eq!(cfg("b"), ["feature=\"b\""]);
+    assert_eq!(cfg("c"), ["feature=\"c\""]);
+    assert_eq!(cfg("(all-features)"), [
+        "feature=\"a\"",
+        "feature=\"b\"",
+        "feature=\"c\"",
+        "feature=\"default\"",
+        "feature=\"member1\""
+    ]);
+    assert_eq!(fs_err::read_dir(&dir).unwrap().count(), 6);
+
+    // The canonical sorted form is used regardless of the order of features.
+    let tmpdir = tempfile::tempdir().unwrap();
+    cargo_hack([
+        "rustc",
+        "--lib",
+        "--no-default-features",
+        "--features",
+        "c,a",
+        "--capture-stdout-dir",
+    ])
+    .arg(tmpdir.path())
+    .args(["--", "--print", "cfg"])
+    .assert_success("real");
+    assert!(tmpdir.path().join("real/a,c.txt").is_file());
+
+    cargo_hack(["check", "--each-feature", "--check-additivity", "--capture-stdout-dir", "out"])
+        .assert_failure("real")
+        .stderr_contains("--check-additivity may not be used together with --capture-stdout-dir");
+}
+
+#[test]
+fn capture_stdout_dir_version_range() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+    let _r = RUSTUP_TOOLCHAIN_CHANGES.lock().unwrap();
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    cargo_hack(["rustc", "--lib", "--version-range", "1.74..=1.75", "--capture-stdout-dir"])
+        .arg(tmpdir.path())
+        .args(["--", "--print", "cfg"])
+        .assert_success("real");
+    for toolchain in ["1.74", "1.75"] {
+        let s =
+            fs_err::read_to_string(tmpdir.path().join("real").join(toolchain).join("default.txt"))
+                .unwrap();
+        assert!(s.contains("feature=\"default\""), "{s}");
+    }
+}
+
+#[test]
+fn slow_threshold_factor() {
+    cargo_hack(["check", "--each-feature", "--slow-threshold-factor", "3"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features default` on real (6/6)
+            slowest commands:
+            on real
+            ",
+        );
+
+    cargo_hack(["check", "--slow-threshold-factor", "0"])
+        .assert_failure("real")
+        .stderr_contains("--slow-threshold-factor must be a positive number");
+    cargo_hack(["check", "--slow-threshold-factor", "foo"])
+        .assert_failure("real")
+        .stderr_contains("--slow-threshold-factor must be a positive number");
+}
+
+#[test]
+fn check_additivity() {
+    // --message-format=json-render-diagnostics requires Rust 1.38.
+    let require = Some(38);
+
+    cargo_hack(["check", "--each-feature", "--keep-going", "--check-additivity"])
+        .assert_failure2("additivity", require)
+        .stderr_contains(
+            "
+            running `cargo check --message-format=json-render-diagnostics --no-default-features --features a` on additivity
+            additivity: feature `a` fails alone but passes with `b`, `c` (--all-features); likely missing dependency on one of `b`, `c`
+            additivity: requesting feature `b` also enabled `c` (--no-default-features --features b)
+            ",
+        )
+        .stderr_not_contains("no feature additivity issues found");
+
+    cargo_hack(["check", "--each-feature", "--check-additivity"])
+        .assert_success2("real", require)
+        .stderr_contains("no feature additivity issues found")
+        .stderr_not_contains("fails alone");
+}
+
+#[test]
+fn check_additivity_failure() {
+    cargo_hack(["check", "--check-additivity"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--check-additivity can only be used together with either --each-feature or --feature-powerset",
+        );
+
+    cargo_hack(["test", "--each-feature", "--check-additivity"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--check-additivity may only be used together with check or build subcommand",
+        );
+
+    cargo_hack(["check", "--each-feature", "--check-additivity", "--message-format=json"])
+        .assert_failure("real")
+        .stderr_contains("--check-additivity may not be used together with --message-format=json");
+}
+
+#[test]
+fn rustdoc_all_features_check() {
+    cargo_hack(["doc", "--each-feature", "--rustdoc-all-features-check", "--keep-going"])
+        .assert_failure("doc_features")
+        .stderr_contains(
+            "running `RUSTDOCFLAGS=\"--cfg docsrs -D warnings\" cargo doc --no-deps \
+             --no-default-features --features a` on doc_features",
+        )
+        .stderr_contains(
+            "warning: doc_features: --no-default-features --features b documents no more public \
+             items than the build without features",
+        )
+        .stderr_contains(
+            "warning: doc_features: documentation fails to build with --no-default-features \
+             --features c but builds without features; it may have broken intra-doc links to \
+             items gated by other features",
+        )
+        .stderr_not_contains("--features a documents");
+
+    cargo_hack(["doc", "--rustdoc-all-features-check"])
+        .assert_failure("doc_features")
+        .stderr_contains(
+            "--rustdoc-all-features-check can only be used together with --each-feature",
+        );
+    cargo_hack(["check", "--each-feature", "--rustdoc-all-features-check"])
+        .assert_failure("doc_features")
+        .stderr_contains(
+            "--rustdoc-all-features-check may only be used together with doc subcommand",
+        );
+    cargo_hack(["doc", "--each-feature", "--doc-json"])
+        .assert_failure("doc_features")
+        .stderr_contains("--doc-json can only be used together with --rustdoc-all-features-check");
+}
+
+#[test]
+fn bisect_features() {
+    // Only the combination of `a` and `c` fails.
+    cargo_hack(["check", "--bisect-features"])
+        .assert_success("bisect")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features a,b,c,d,default` on bisect (1)
+            running `cargo check --no-default-features` on bisect (2)
+            running `cargo check --no-default-features --features a,c` on bisect (12)
+            minimal failing feature set of package `bisect`: `a,c` (found in 12 runs)
+            ",
+        )
+        .stderr_not_contains("(12/");
+
+    cargo_hack(["check", "--bisect-features", "--bisect-start", "a,b,c"])
+        .assert_success("bisect")
+        .stderr_contains("minimal failing feature set of package `bisect`: `a,c`");
+
+    cargo_hack(["check", "--bisect-features", "--exclude-features", "c"])
+        .assert_success("bisect")
+        .stderr_contains("package `bisect` does not fail with `a,b,d,default`; nothing to bisect");
+
+    cargo_hack(["check", "--bisect-features", "--bisect-max-runs", "4"])
+        .assert_success("bisect")
+        .stderr_contains(
+            "bisecting package `bisect` stopped after reaching the limit of --bisect-max-runs",
+        )
+        .stderr_not_contains("(5)");
+
+    cargo_hack(["check", "--bisect-features", "--bisect-start", "e"])
+        .assert_failure("bisect")
+        .stderr_contains(
+            "feature `e` specified by --bisect-start is not in the features to bisect of package \
+             `bisect`",
+        );
+
+    cargo_hack(["check", "--bisect-features", "--each-feature"])
+        .assert_failure("bisect")
+        .stderr_contains("--bisect-features may not be used together with --each-feature");
+    cargo_hack(["check", "--bisect-start", "a"])
+        .assert_failure("bisect")
+        .stderr_contains("--bisect-start can only be used together with --bisect-features");
+}
+
+#[test]
+fn ensure_targets_buildable() {
+    cargo_hack(["check", "--each-feature", "--ensure-targets-buildable", "--verbose"])
+        .assert_success("required_features")
+        .stderr_contains(
+            "
+            bin `tool` of package `required_features` (required-features: cli) is built by: --no-default-features --features cli; --no-default-features --features full
+            ",
+        );
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--ensure-targets-buildable",
+        "--exclude-all-features",
+        "--exclude-features",
+        "full",
+    ])
+    .assert_success("required_features")
+    .stderr_contains(
+        "running `cargo check --no-default-features --features cli` on required_features",
+    );
+
+    cargo_hack([
+        "check",
+        "--each-feature",
+        "--ensure-targets-buildable",
+        "--exclude-all-features",
+        "--exclude-features",
+        "cli,full",
+    ])
+    .assert_failure("required_features")
+    .stderr_contains(
+        "
+        the following targets are not built by any of the feature combinations because their required-features are never enabled:
+        bin `tool` of package `required_features` (required-features: cli)
+        ",
+    )
+    .stderr_not_contains("running");
+
+    cargo_hack(["check", "--ensure-targets-buildable"])
+        .assert_failure("required_features")
+        .stderr_contains(
+            "--ensure-targets-buildable can only be used together with either --each-feature or --feature-powerset",
+        );
+}
+
+#[test]
+fn namespaced_features() {
+    // Namespaced features requires Rust 1.60.
+    let require = Some(60);
+
+    cargo_hack([
+        "run",
+        "--features=explicit,implicit,combo,renamed,member1,member2,member3",
+        "--ignore-unknown-features",
+    ])
+    .assert_success2("namespaced_features", require)
+    .stderr_contains(
+        "
+            skipped applying unknown `member1` feature to namespaced_features
+            skipped applying unknown `member2` feature to namespaced_features
+            skipped applying unknown `member3` feature to namespaced_features
+            running `cargo run --features explicit,implicit,combo,renamed` on namespaced_features
+            ",
+    )
+    .stdout_contains(
+        "
+            explicit
+            implicit
+            combo
+            renamed
+            ",
+    )
+    .stdout_not_contains(
+        "
+            member1
+            member2
+            member3
+            ",
+    );
+
+    cargo_hack(["check", "--each-feature"])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/4)
+            running `cargo check --no-default-features` on namespaced_features (2/4)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/4)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/4)
+            ",
+        )
+        .stderr_not_contains(
+            "
+            --features implicit
+            --features renamed
+            ",
+        );
+
+    cargo_hack(["check", "--each-feature", "--optional-deps"])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/6)
+            running `cargo check --no-default-features` on namespaced_features (2/6)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/6)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/6)
+            running `cargo check --no-default-features --features implicit` on namespaced_features (5/6)
+            running `cargo check --no-default-features --features renamed` on namespaced_features (6/6)
+            ",
+        );
+
+    cargo_hack(["check", "--each-feature", "--optional-deps", "implicit"])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/5)
+            running `cargo check --no-default-features` on namespaced_features (2/5)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
+            running `cargo check --no-default-features --features implicit` on namespaced_features (5/5)
+            ",
+        )
+        .stderr_not_contains("--features renamed");
+
+    cargo_hack(["check", "--each-feature", "--optional-deps=renamed"])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/5)
+            running `cargo check --no-default-features` on namespaced_features (2/5)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
+            running `cargo check --no-default-features --features renamed` on namespaced_features (5/5)
+            ",
+        )
+        .stderr_not_contains("--features implicit");
+
+    cargo_hack(["check", "--each-feature", "--optional-deps="])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/4)
+            running `cargo check --no-default-features` on namespaced_features (2/4)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/4)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/4)
+            ",
+        );
+
+    cargo_hack(["check", "--feature-powerset"])
+        .assert_success2("namespaced_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on namespaced_features (1/5)
+            running `cargo check --no-default-features` on namespaced_features (2/5)
+            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
+            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
+            running `cargo check --no-default-features --features combo,explicit` on namespaced_features (5/5)
+            ",
+        );
+
+    // Optional dependencies referenced only with `dep:` (member2 and member3)
+    // cannot be enabled individually, because cargo rejects `--features dep:<name>`.
+    // Therefore, they are not iterated even with --optional-deps.
+    let (_test_project, cur_dir) = test_project("namespaced_features");
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = std::process::Command::new(cargo)
+        .args(["check", "--features", "dep:member2"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("feature `dep:member2` is not allowed to use explicit `dep:` syntax"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    cargo_hack(["check", "--features", "dep:member2"])
+        .assert_failure2("namespaced_features", require)
+        .stderr_contains("invalid feature name `dep:member2` specified by --features")
+        .stderr_not_contains("running `");
+}
+
+#[test]
+fn namespaced_features_group_features() {
+    // Namespaced features requires Rust 1.60.
+    let require = Some(60);
+
+    cargo_hack(["check", "--feature-powerset", "--group-features", "implicit,renamed"])
+        .assert_failure2("namespaced_features", require)
+        .stderr_contains(
+            "group `implicit,renamed` of --group-features contains `implicit`, which is an \
+             optional dependency of package `namespaced_features`; optional dependencies are only \
+             combined with --optional-deps, so use --optional-deps together with this group",
+        )
+        .stderr_not_contains("running `");
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--optional-deps",
+        "--group-features",
+        "implicit,renamed",
+    ])
+    .assert_success2("namespaced_features", require)
+    .stderr_contains(
+        "
+        running `cargo check --no-default-features --features implicit,renamed` on namespaced_features (6/8)
+        running `cargo check --no-default-features --features combo,implicit,renamed` on namespaced_features (7/8)
+        running `cargo check --no-default-features --features explicit,implicit,renamed` on namespaced_features (8/8)
+        ",
+    )
+    .stderr_not_contains(
+        "
+        --features implicit`
+        --features renamed`
+        ",
+    );
+
+    // Optional dependencies referenced only with `dep:` cannot be enabled by
+    // their names even with --optional-deps.
+    for args in [&["check", "--feature-powerset", "--group-features", "explicit,member2"][..], &[
+        "check",
+        "--feature-powerset",
+        "--optional-deps",
+        "--group-features",
+        "explicit,member2",
+    ]] {
+        cargo_hack(args)
+            .assert_failure2("namespaced_features", require)
+            .stderr_contains(
+                "group `explicit,member2` of --group-features contains `member2`, which is an \
+                 optional dependency of package `namespaced_features` that is only enabled via \
+                 `dep:member2` and cannot be enabled by its name; use the feature(s) that enable \
+                 it (`combo`) in the group instead",
+            )
+            .stderr_not_contains("running `");
+    }
+
+    cargo_hack([
+        "check",
+        "--feature-powerset",
+        "--group-features",
+        "member2,member3",
+        "--ignore-unknown-features",
+    ])
+    .assert_success2("namespaced_features", require)
+    .stderr_contains(
+        "
+        skipped applying group `member2,member3` to namespaced_features
+        running `cargo check --no-default-features --features combo,explicit` on namespaced_features (5/5)
+        ",
+    );
+}
+
+#[test]
+fn weak_dep_features() {
+    // Weak dependency features requires Rust 1.60.
+    let require = Some(60);
+
+    cargo_hack(["check", "--feature-powerset"])
+        .assert_success2("weak_dep_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on weak_dep_features (1/4)
+            running `cargo check --no-default-features --features default,easytime` on weak_dep_features (2/4)
+            running `cargo check --no-default-features --features default` on weak_dep_features (3/4)
+            running `cargo check --no-default-features --features easytime` on weak_dep_features (4/4)
+            ",
+        );
+    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
+        .assert_success2("weak_dep_features", require)
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on weak_dep_features (1/4)
+            running `cargo check --no-default-features --features default,easytime` on weak_dep_features (2/4)
+            running `cargo check --no-default-features --features default` on weak_dep_features (3/4)
+            running `cargo check --no-default-features --features easytime` on weak_dep_features (4/4)
+            ",
+        );
+
+    cargo_hack(["check", "--feature-powerset"])
+        .assert_success2("weak_dep_features_namespaced", require)
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on weak_dep_features_namespaced (1/4)
+            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_namespaced (2/4)
+            running `cargo check --no-default-features --features default` on weak_dep_features_namespaced (3/4)
+            running `cargo check --no-default-features --features easytime` on weak_dep_features_namespaced (4/4)
+            ",
+        );
+    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
+        .assert_success2("weak_dep_features_namespaced", require)
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on weak_dep_features_namespaced (1/4)
+            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_namespaced (2/4)
+            running `cargo check --no-default-features --features default` on weak_dep_features_namespaced (3/4)
+            running `cargo check --no-default-features --features easytime` on weak_dep_features_namespaced (4/4)
+            ",
+        );
+
+    cargo_hack(["check", "--feature-powerset"])
+        .assert_success2("weak_dep_features_implicit", require)
+        .stderr_contains(
+            "
+            running `cargo check --all-features` on weak_dep_features_implicit (1/3)
+            running `cargo check --no-default-features` on weak_dep_features_implicit (2/3)
+            running `cargo check --no-default-features --features default` on weak_dep_features_implicit (3/3)
+            ",
+        );
+    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
+        .assert_success2("weak_dep_features_implicit", require)
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features` on weak_dep_features_implicit (1/4)
+            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_implicit (2/4)
+            running `cargo check --no-default-features --features default` on weak_dep_features_implicit (3/4)
+            running `cargo check --no-default-features --features easytime` on weak_dep_features_implicit (4/4)
+            ",
+        );
+}
+
+#[test]
+fn empty_string() {
+    cargo_hack(["check", "--each-feature", "--skip", ""])
+        .assert_success("real")
+        .stderr_not_contains("not found");
+}
+
+#[test]
+fn print_command_list() {
+    cargo_hack(["check", "--each-feature", "--print-command-list"])
+        .assert_success("real")
+        .stdout_contains(
+            "
+            cargo check --manifest-path Cargo.toml --all-features
+            cargo check --manifest-path Cargo.toml --no-default-features
+            cargo check --manifest-path Cargo.toml --no-default-features --features a
+            cargo check --manifest-path Cargo.toml --no-default-features --features b
+            cargo check --manifest-path Cargo.toml --no-default-features --features c
+            cargo check --manifest-path Cargo.toml --no-default-features --features default
+            ",
+        )
+        .stdout_not_contains("`");
+}
+
+#[test]
+fn emit_execution_plan() {
+    use std::path::PathBuf;
+
+    use fs_err as fs;
+    use serde_json::Value;
+
+    fn snapshot(dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                snapshot(&path, files);
+            } else {
+                files.push((path.clone(), fs::read(&path).unwrap()));
+            }
+        }
+    }
+    let fnv1a = |bytes: &[u8]| {
+        let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+        format!("{hash:016x}")
+    };
+
+    let (_test_project, cur_dir) = test_project("real");
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("plan.json");
+    let mut before = vec![];
+    snapshot(&cur_dir, &mut before);
+    let output = cargo_hack([
+        "check",
+        "--no-dev-deps",
+        "--each-feature",
+        "-p",
+        "member1",
+        "--emit-execution-plan",
+    ])
+    .arg(&path)
+    .current_dir(&cur_dir)
+    .output()
+    .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(!stderr.contains("running `cargo"), "{stderr}");
+    assert!(!stderr.contains("modifies real `Cargo.toml`"), "{stderr}");
+    // The working tree is untouched.
+    let mut after = vec![];
+    snapshot(&cur_dir, &mut after);
+    assert_eq!(before, after);
+
+    let plan: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(plan["version"], 1);
+    let manifest = cur_dir.join("member1/Cargo.toml");
+    let orig = fs::read_to_string(&manifest).unwrap();
+    let mutations = plan["mutations"].as_array().unwrap();
+    assert_eq!(mutations.len(), 1);
+    let mutation = &mutations[0];
+    assert_eq!(Path::new(mutation["path"].as_str().unwrap()), manifest);
+    assert_eq!(mutation["restore"], true);
+    assert_eq!(mutation["removes"], serde_json::json!(["dev-dependencies"]));
+    assert_eq!(mutation["original_hash"], fnv1a(orig.as_bytes()));
+    let content = mutation["content"].as_str().unwrap();
+    assert_eq!(mutation["hash"], fnv1a(content.as_bytes()));
+    assert!(orig.contains("[dev-dependencies]"));
+    assert!(!content.contains("[dev-dependencies]"), "{content}");
+    // The restore section mirrors the mutations.
+    let restore = plan["restore"].as_array().unwrap();
+    assert_eq!(restore.len(), 1);
+    assert_eq!(restore[0]["path"], mutation["path"]);
+    assert_eq!(restore[0]["hash"], mutation["original_hash"]);
+    assert_eq!(restore[0]["content"], orig.as_str());
+
+    let runs = plan["runs"].as_array().unwrap();
+    let features: Vec<_> = runs
+        .iter()
+        .map(|run| {
+            assert_eq!(run["kind"], "run");
+            assert_eq!(run["package"], "member1");
+            assert_eq!(run["toolchain"], Value::Null);
+            assert_eq!(Path::new(run["cwd"].as_str().unwrap()), cur_dir);
+            assert_eq!(run["id"].as_str().unwrap().len(), 8);
+            let args: Vec<_> =
+                run["args"].as_array().unwrap().iter().map(|a| a.as_str().unwrap()).collect();
+            assert_eq!(args[..3], ["check", "--manifest-path", "member1/Cargo.toml"]);
+            args[3..].join(" ")
+        })
+        .collect();
+    assert_eq!(features, [
+        "--all-features",
+        "--no-default-features",
+        "--no-default-features --features a",
+        "--no-default-features --features b",
+        "--no-default-features --features c",
+        "--no-default-features --features default",
+    ]);
+
+    cargo_hack(["check", "--print-command-list", "--emit-execution-plan"])
+        .arg(&path)
+        .assert_failure("real")
+        .stderr_contains(
+            "--emit-execution-plan may not be used together with --print-command-list",
+        );
+}
+
+#[test]
+fn config_target() {
+    cargo_hack(["check", "--print-command-list", "--verbose"])
+        .assert_success("config_target")
+        .stderr_contains(
+            "info: detected build.target from cargo config: wasm32-unknown-unknown, x86_64-unknown-linux-gnu",
+        )
+        .stdout_contains("cargo check --manifest-path Cargo.toml")
+        .stdout_not_contains("--target");
+
+    cargo_hack(["check", "--print-command-list"])
+        .assert_success("config_target")
+        .stderr_not_contains("detected build.target");
+
+    cargo_hack([
+        "check",
+        "--print-command-list",
+        "--verbose",
+        "--target",
+        "aarch64-unknown-linux-gnu",
+    ])
+    .assert_success("config_target")
+    .stderr_not_contains("detected build.target")
+    .stdout_contains("--target aarch64-unknown-linux-gnu");
+
+    // CARGO_BUILD_TARGET takes precedence over cargo config.
+    cargo_hack(["check", "--print-command-list", "--verbose"])
+        .env("CARGO_BUILD_TARGET", "aarch64-unknown-linux-gnu")
+        .assert_success("config_target")
+        .stderr_contains(
+            "info: detected build.target from CARGO_BUILD_TARGET environment variable: \
+             aarch64-unknown-linux-gnu",
+        )
+        .stderr_not_contains("detected build.target from cargo config")
+        .stdout_not_contains("--target");
+
+    // The config is loaded from the workspace root, not the current directory.
+    let (_test_project, cur_dir) = test_project("config_target");
+    let output = cargo_hack(["check", "--print-command-list", "--verbose", "--manifest-path"])
+        .arg(cur_dir.join("Cargo.toml"))
+        .current_dir(cur_dir.parent().unwrap())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "STDERR:\n{stderr}");
+    assert!(stderr.contains("detected build.target from cargo config"), "STDERR:\n{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn print_command_list_rustup() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // A rustup that fails loudly if cargo-hack tries to install toolchains.
+    let (_bin_dir, path) = rustup_shim(&[]);
+
+    // do not bump the versions in this test
+    // generate-lockfile is only needed if the oldest version is pre-1.60
+    cargo_hack(["check", "--version-range", "1.59..=1.60", "--print-command-list"])
+        .env("PATH", &path)
+        .assert_success("real")
+        .stdout_contains(
+            "
+            # rustup toolchain add 1.59 --no-self-update
+            rustup run 1.59 cargo generate-lockfile --manifest-path Cargo.toml
+            rustup run 1.59 cargo check --manifest-path Cargo.toml
+            # rustup toolchain add 1.60 --no-self-update
+            rustup run 1.60 cargo check --manifest-path Cargo.toml
+            ",
+        )
+        .stderr_contains(
+            "info: generating Cargo.lock with cargo 1.59 because the oldest toolchain is older \
+             than 1.60",
+        )
+        .stderr_not_contains("unexpected rustup");
+
+    // --lockfile-strategy
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.59..=1.60",
+        "--print-command-list",
+        "--lockfile-strategy=keep",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_not_contains("generate-lockfile")
+    .stderr_not_contains("generating Cargo.lock");
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.59..=1.60",
+        "--print-command-list",
+        "--lockfile-strategy=generate-per-version",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_contains(
+        "
+        # rustup toolchain add 1.59 --no-self-update
+        rustup run 1.59 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.59 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.60 --no-self-update
+        rustup run 1.60 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.60 cargo check --manifest-path Cargo.toml
+        ",
+    )
+    .stderr_contains(
+        "
+        info: generating Cargo.lock with cargo 1.59 because \
+         --lockfile-strategy=generate-per-version
+        info: generating Cargo.lock with cargo 1.60 because \
+         --lockfile-strategy=generate-per-version
+        ",
+    );
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.60..=1.61",
+        "--print-command-list",
+        "--lockfile-strategy=generate-once",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_contains(
+        "
+        # rustup toolchain add 1.60 --no-self-update
+        rustup run 1.60 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.60 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.61 --no-self-update
+        rustup run 1.61 cargo check --manifest-path Cargo.toml
+        ",
+    )
+    .stderr_contains(
+        "info: generating Cargo.lock with cargo 1.60 because --lockfile-strategy=generate-once",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn version_order() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // A rustup that fails loudly if cargo-hack tries to install toolchains.
+    let (_bin_dir, path) = rustup_shim(&[]);
+
+    // do not bump the versions in this test
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.59..=1.60",
+        "--version-order=oldest-first",
+        "--print-command-list",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_contains(
+        "
+        # rustup toolchain add 1.59 --no-self-update
+        rustup run 1.59 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.59 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.60 --no-self-update
+        rustup run 1.60 cargo check --manifest-path Cargo.toml
+        ",
+    );
+    // The lockfile is still generated with the oldest toolchain before any runs.
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.59..=1.60",
+        "--version-order",
+        "newest-first",
+        "--print-command-list",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_contains(
+        "
+        # rustup toolchain add 1.59 --no-self-update
+        rustup run 1.59 cargo generate-lockfile --manifest-path Cargo.toml
+        # rustup toolchain add 1.60 --no-self-update
+        rustup run 1.60 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.59 --no-self-update
+        rustup run 1.59 cargo check --manifest-path Cargo.toml
+        ",
+    )
+    .stderr_contains(
+        "info: generating Cargo.lock with cargo 1.59 because the oldest toolchain is older \
+         than 1.60",
+    )
+    .stderr_not_contains("unexpected rustup");
+
+    // Across 1.51, the runs on toolchains 1.51 or later use the lockfile
+    // regenerated by the first of them, in both orders.
+    cargo_hack(["check", "--version-range", "1.50..=1.52", "--print-command-list"])
+        .env("PATH", &path)
+        .assert_success("real")
+        .stdout_contains(
+            "
+            # rustup toolchain add 1.50 --no-self-update
+            rustup run 1.50 cargo generate-lockfile --manifest-path Cargo.toml
+            rustup run 1.50 cargo check --manifest-path Cargo.toml
+            # rustup toolchain add 1.51 --no-self-update
+            rustup run 1.51 cargo generate-lockfile --manifest-path Cargo.toml
+            rustup run 1.51 cargo check --manifest-path Cargo.toml
+            # rustup toolchain add 1.52 --no-self-update
+            rustup run 1.52 cargo check --manifest-path Cargo.toml
+            ",
+        );
+    cargo_hack([
+        "check",
+        "--version-range",
+        "1.50..=1.52",
+        "--version-order=newest-first",
+        "--print-command-list",
+    ])
+    .env("PATH", &path)
+    .assert_success("real")
+    .stdout_contains(
+        "
+        # rustup toolchain add 1.50 --no-self-update
+        rustup run 1.50 cargo generate-lockfile --manifest-path Cargo.toml
+        # rustup toolchain add 1.52 --no-self-update
+        # rustup toolchain add 1.51 --no-self-update
+        rustup run 1.51 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.52 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.51 --no-self-update
+        rustup run 1.51 cargo check --manifest-path Cargo.toml
+        # rustup toolchain add 1.50 --no-self-update
+        rustup run 1.50 cargo generate-lockfile --manifest-path Cargo.toml
+        rustup run 1.50 cargo check --manifest-path Cargo.toml
+        ",
+    )
+    .stderr_contains(
+        "
+        info: generating Cargo.lock with cargo 1.50 because the oldest toolchain is older than \
+         1.60
+        info: generating Cargo.lock with cargo 1.51 because the lockfile was written by cargo \
+         older than 1.51
+        info: generating Cargo.lock with cargo 1.50 because the lockfile was written by cargo \
+         1.51 or later, and the oldest toolchain is older than 1.60
+        ",
+    )
+    .stderr_not_contains("unexpected rustup");
+}
+
+#[cfg(unix)]
+#[test]
+fn env_target_version_range() {
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // A rustup that fails loudly if cargo-hack tries to install toolchains.
+    let (_bin_dir, path) = rustup_shim(&[]);
+
+    // do not bump the versions in this test
+    // The target from CARGO_BUILD_TARGET is installed on the toolchains before
+    // and after 1.64, but not passed to cargo.
+    cargo_hack(["check", "--version-range", "1.63..=1.64", "--print-command-list", "--verbose"])
+        .env("PATH", &path)
+        .env("CARGO_BUILD_TARGET", "aarch64-unknown-linux-gnu")
+        .assert_success("real")
+        .stdout_contains(
             "
-            running `cargo check --all-features` on namespaced_features (1/6)
-            running `cargo check --no-default-features` on namespaced_features (2/6)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/6)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/6)
-            running `cargo check --no-default-features --features implicit` on namespaced_features (5/6)
-            running `cargo check --no-default-features --features renamed` on namespaced_features (6/6)
+            # rustup toolchain add 1.63 --no-self-update --target aarch64-unknown-linux-gnu
+            rustup run 1.63 cargo check --manifest-path Cargo.toml
+            # rustup toolchain add 1.64 --no-self-update --target aarch64-unknown-linux-gnu
+            rustup run 1.64 cargo check --manifest-path Cargo.toml
             ",
-        );
-
-    cargo_hack(["check", "--each-feature", "--optional-deps", "implicit"])
-        .assert_success2("namespaced_features", require)
+        )
+        .stdout_not_contains("cargo check --manifest-path Cargo.toml --target")
         .stderr_contains(
-            "
-            running `cargo check --all-features` on namespaced_features (1/5)
-            running `cargo check --no-default-features` on namespaced_features (2/5)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
-            running `cargo check --no-default-features --features implicit` on namespaced_features (5/5)
-            ",
+            "info: detected build.target from CARGO_BUILD_TARGET environment variable: \
+             aarch64-unknown-linux-gnu",
         )
-        .stderr_not_contains("--features renamed");
+        .stderr_not_contains("unexpected rustup");
+}
 
-    cargo_hack(["check", "--each-feature", "--optional-deps=renamed"])
-        .assert_success2("namespaced_features", require)
+#[cfg(unix)]
+#[test]
+fn toolchain_requirements() {
+    use serde_json::{json, Value};
+
+    // --version-range requires rustup
+    if !has_rustup() {
+        return;
+    }
+
+    // A rustup that fails loudly if cargo-hack tries to install toolchains.
+    let (_bin_dir, path) = rustup_shim(&["list"]);
+
+    let (_test_project, cur_dir) = test_project("real");
+    let requirements = |args: &[&str]| {
+        let output = cargo_hack(args).env("PATH", &path).current_dir(&cur_dir).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "STDERR:\n{stderr}");
+        assert!(!stderr.contains("unexpected rustup"), "STDERR:\n{stderr}");
+        serde_json::from_slice::<Value>(&output.stdout).unwrap()
+    };
+
+    let toolchain = |toolchain: &str| {
+        json!({
+            "toolchain": toolchain,
+            "targets": ["x86_64-unknown-linux-gnu"],
+            "components": ["clippy"],
+        })
+    };
+    assert_eq!(
+        requirements(&[
+            "clippy",
+            "--version-range",
+            "1.59..=1.60",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--print-toolchain-requirements",
+        ]),
+        json!({ "version": 1, "toolchains": [toolchain("1.59"), toolchain("1.60")] })
+    );
+    assert_eq!(
+        requirements(&["check", "--print-toolchain-requirements"]),
+        json!({
+            "version": 1,
+            "toolchains": [{ "toolchain": null, "targets": [], "components": [] }],
+        })
+    );
+
+    cargo_hack(["check", "--version-range", "1.999..=1.999", "--no-install"])
+        .env("PATH", &path)
+        .assert_failure("real")
         .stderr_contains(
             "
-            running `cargo check --all-features` on namespaced_features (1/5)
-            running `cargo check --no-default-features` on namespaced_features (2/5)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
-            running `cargo check --no-default-features --features renamed` on namespaced_features (5/5)
+            the following requirements of the runs are not installed (--no-install):
+                toolchain 1.999
             ",
         )
-        .stderr_not_contains("--features implicit");
+        .stderr_not_contains("unexpected rustup");
 
-    cargo_hack(["check", "--each-feature", "--optional-deps="])
-        .assert_success2("namespaced_features", require)
+    cargo_hack(["check", "--print-toolchain-requirements", "--no-install"])
+        .assert_failure("real")
         .stderr_contains(
-            "
-            running `cargo check --all-features` on namespaced_features (1/4)
-            running `cargo check --no-default-features` on namespaced_features (2/4)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/4)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/4)
-            ",
+            "--print-toolchain-requirements may not be used together with --no-install",
         );
+}
 
-    cargo_hack(["check", "--feature-powerset"])
-        .assert_success2("namespaced_features", require)
-        .stderr_contains(
-            "
-            running `cargo check --all-features` on namespaced_features (1/5)
-            running `cargo check --no-default-features` on namespaced_features (2/5)
-            running `cargo check --no-default-features --features combo` on namespaced_features (3/5)
-            running `cargo check --no-default-features --features explicit` on namespaced_features (4/5)
-            running `cargo check --no-default-features --features combo,explicit` on namespaced_features (5/5)
-            ",
-        );
+#[test]
+fn generate_feature_docs() {
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let generate = || {
+        let output = cargo_bin_exe()
+            .args(["hack", "--generate-feature-docs", "FEATURES.md", "-p", "real", "-p", "member3"])
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        fs::read_to_string(cur_dir.join("FEATURES.md")).unwrap()
+    };
+    let docs = generate();
+    for row in [
+        "## `member3`",
+        "| `a` | yes |  |  |",
+        "## `real`",
+        "| `a` | no |  |  |",
+        "| `member1` | no | `member1` (optional dependency) |  |",
+    ] {
+        assert!(docs.lines().any(|l| l == row), "{row} not found in:\n{docs}");
+    }
+    assert!(!docs.contains("`default`"), "{docs}");
+    assert!(!docs.contains("## `member1`"), "{docs}");
+
+    // The descriptions are kept, and new features are added.
+    let docs = docs.replace(
+        "## `real`\n\n| Feature | Default | Enables | Description |\n| ------- | ------- | ------- | ----------- |\n| `a` | no |  |  |",
+        "## `real`\n\n| Feature | Default | Enables | Description |\n| ------- | ------- | ------- | ----------- |\n| `a` | no |  | Enables A \\| B. |",
+    );
+    fs::write(cur_dir.join("FEATURES.md"), docs).unwrap();
+    let manifest = fs::read_to_string(cur_dir.join("Cargo.toml")).unwrap();
+    fs::write(
+        cur_dir.join("Cargo.toml"),
+        manifest.replace("c = []", "c = []\nd = [\"a\", \"member1\"]"),
+    )
+    .unwrap();
+    let docs = generate();
+    for row in [
+        "| `a` | no |  | Enables A \\| B. |",
+        "| `d` | no | `a`, `member1` |  |",
+        "| `a` | yes |  |  |",
+    ] {
+        assert!(docs.lines().any(|l| l == row), "{row} not found in:\n{docs}");
+    }
+    // member3 has feature `a` too, but its description is not changed.
+    assert_eq!(docs.matches("Enables A").count(), 1, "{docs}");
+
+    cargo_hack(["check", "--generate-feature-docs", "FEATURES.md"])
+        .assert_failure("real")
+        .stderr_contains("--generate-feature-docs may not be used together with a subcommand");
 }
 
+#[cfg(unix)]
 #[test]
-fn weak_dep_features() {
-    // Weak dependency features requires Rust 1.60.
-    let require = Some(60);
+fn rustup_lock() {
+    use std::{os::unix::fs::PermissionsExt as _, process::Stdio};
+
+    use fs_err as fs;
+
+    // A rustup that records the start and the end of each installation, which
+    // takes a while, and runs other commands with the cargo in PATH.
+    let bin_dir = tempfile::tempdir().unwrap();
+    let log = bin_dir.path().join("install.log");
+    let shim = bin_dir.path().join("rustup");
+    fs::write(
+        &shim,
+        format!(
+            r#"#!/bin/sh
+case "$1" in
+  --version) echo "rustup 1.27.1 (54dd3d00f 2024-04-24)" ;;
+  show) echo "{home}" ;;
+  toolchain)
+    echo "start $PPID" >>"{log}"
+    sleep 1
+    echo "end $PPID" >>"{log}"
+    ;;
+  run)
+    # The toolchain is never installed.
+    if [ "$4" = --version ]; then exit 1; fi
+    shift 2
+    exec "$@"
+    ;;
+esac
+"#,
+            home = bin_dir.path().display(),
+            log = log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = env::join_paths(
+        [bin_dir.path().to_owned()]
+            .into_iter()
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    let projects = [test_project("real"), test_project("real")];
+    let children: Vec<_> = projects
+        .iter()
+        .map(|(_, dir)| {
+            cargo_hack(["check", "--version-range", "1.64..=1.64"])
+                .env("PATH", &path)
+                .current_dir(dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    // The installations do not overlap.
+    let log = fs::read_to_string(&log).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 4, "{log}");
+    assert!(lines[0].starts_with("start ") && lines[2].starts_with("start "), "{log}");
+    assert_eq!(lines[0].replace("start", "end"), lines[1], "{log}");
+    assert_eq!(lines[2].replace("start", "end"), lines[3], "{log}");
+    assert!(bin_dir.path().join(".cargo-hack-rustup.lock").is_file());
+
+    cargo_hack(["check", "--no-rustup-lock"]).assert_failure("real").stderr_contains(
+        "--no-rustup-lock can only be used together with either --version-range or --rust-version",
+    );
+}
 
-    cargo_hack(["check", "--feature-powerset"])
-        .assert_success2("weak_dep_features", require)
-        .stderr_contains(
-            "
-            running `cargo check --no-default-features` on weak_dep_features (1/4)
-            running `cargo check --no-default-features --features default,easytime` on weak_dep_features (2/4)
-            running `cargo check --no-default-features --features default` on weak_dep_features (3/4)
-            running `cargo check --no-default-features --features easytime` on weak_dep_features (4/4)
-            ",
-        );
-    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
-        .assert_success2("weak_dep_features", require)
+#[cfg(unix)]
+#[test]
+fn llvm_cov() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use fs_err as fs;
+
+    // A cargo that records invocations of llvm-cov instead of running them.
+    let tmpdir = tempfile::tempdir().unwrap();
+    let log = tmpdir.path().join("llvm-cov.log");
+    let shim = tmpdir.path().join("cargo");
+    fs::write(
+        &shim,
+        format!(
+            "#!/bin/sh\nif [ \"$1\" = llvm-cov ]; then\n  echo \"$*\" >>{}\n  exit 0\nfi\nexec cargo \"$@\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    cargo_hack([
+        "llvm-cov",
+        "test",
+        "--lcov",
+        "--output-path",
+        "lcov.info",
+        "--each-feature",
+        "--exclude-features",
+        "b,c,default",
+    ])
+    .env("CARGO_HACK_CARGO_SRC", &shim)
+    .assert_success("real")
+    .stderr_contains(
+        "
+        running `cargo llvm-cov clean --workspace`
+        running `cargo llvm-cov test --no-report --no-default-features` on real (1/2)
+        running `cargo llvm-cov test --no-report --no-default-features --features a` on real (2/2)
+        running `cargo llvm-cov report --lcov --output-path lcov.info`
+        ",
+    );
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "llvm-cov test --help
+llvm-cov clean --workspace
+llvm-cov test --no-report --manifest-path Cargo.toml --no-default-features
+llvm-cov test --no-report --manifest-path Cargo.toml --no-default-features --features a
+llvm-cov report --lcov --output-path lcov.info
+"
+    );
+
+    cargo_hack(["llvm-cov", "--each-feature", "--rust-version"])
+        .env("CARGO_HACK_CARGO_SRC", &shim)
+        .assert_failure("real")
+        .stderr_contains("--rust-version may not be used together with llvm-cov subcommand");
+}
+
+#[cfg(unix)]
+#[test]
+fn sccache_stats() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use fs_err as fs;
+
+    // A fake sccache that reports fixed statistics and runs rustc as is.
+    let bin_dir = tempfile::tempdir().unwrap();
+    let shim = bin_dir.path().join("sccache");
+    fs::write(
+        &shim,
+        r#"#!/bin/sh
+case "$1" in
+  --zero-stats) exit 0 ;;
+  --show-stats)
+    echo '{"stats":{"cache_hits":{"counts":{"Rust":3}},"cache_misses":{"counts":{"Rust":1}},"cache_errors":{"counts":{}}}}'
+    exit 0
+    ;;
+esac
+exec "$@"
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    cargo_hack(["check", "--each-feature", "--sccache-stats"])
+        .env("RUSTC_WRAPPER", &shim)
+        .assert_success("real")
         .stderr_contains(
             "
-            running `cargo check --no-default-features` on weak_dep_features (1/4)
-            running `cargo check --no-default-features --features default,easytime` on weak_dep_features (2/4)
-            running `cargo check --no-default-features --features default` on weak_dep_features (3/4)
-            running `cargo check --no-default-features --features easytime` on weak_dep_features (4/4)
+            sccache statistics (hits/misses/errors):
+            3/1/0: `cargo check --no-default-features` on real
+            3/1/0: `cargo check --no-default-features --features a` on real
+            18/6/0: total on real
+            18/6/0: total
             ",
         );
 
-    cargo_hack(["check", "--feature-powerset"])
-        .assert_success2("weak_dep_features_namespaced", require)
+    cargo_hack(["check", "--sccache-stats"])
+        .env_remove("RUSTC_WRAPPER")
+        .assert_failure("real") // warn
+        .stderr_contains("--sccache-stats requires RUSTC_WRAPPER to be sccache");
+}
+
+#[cfg(unix)]
+#[test]
+fn chdir_instead_of_manifest_path() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use fs_err as fs;
+
+    // A cargo plugin that does not support --manifest-path flag.
+    let bin_dir = tempfile::tempdir().unwrap();
+    let plugin = bin_dir.path().join("cargo-print-cwd");
+    fs::write(&plugin, "#!/bin/sh\npwd\n").unwrap();
+    fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = env::join_paths(
+        [bin_dir.path().to_owned()]
+            .into_iter()
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    cargo_hack(["print-cwd", "--workspace", "--chdir-instead-of-manifest-path"])
+        .env("PATH", &path)
+        .assert_success("virtual")
         .stderr_contains(
             "
-            running `cargo check --no-default-features` on weak_dep_features_namespaced (1/4)
-            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_namespaced (2/4)
-            running `cargo check --no-default-features --features default` on weak_dep_features_namespaced (3/4)
-            running `cargo check --no-default-features --features easytime` on weak_dep_features_namespaced (4/4)
+            running `cargo print-cwd` on member1 (1/3)
+            running `cargo print-cwd` on member2 (2/3)
+            running `cargo print-cwd` on not_find_manifest (3/3)
             ",
-        );
-    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
-        .assert_success2("weak_dep_features_namespaced", require)
-        .stderr_contains(
+        )
+        .stdout_contains(
             "
-            running `cargo check --no-default-features` on weak_dep_features_namespaced (1/4)
-            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_namespaced (2/4)
-            running `cargo check --no-default-features --features default` on weak_dep_features_namespaced (3/4)
-            running `cargo check --no-default-features --features easytime` on weak_dep_features_namespaced (4/4)
+            /member1
+            /member2
+            /dir/not_find_manifest
             ",
         );
 
-    cargo_hack(["check", "--feature-powerset"])
-        .assert_success2("weak_dep_features_implicit", require)
-        .stderr_contains(
-            "
-            running `cargo check --all-features` on weak_dep_features_implicit (1/3)
-            running `cargo check --no-default-features` on weak_dep_features_implicit (2/3)
-            running `cargo check --no-default-features --features default` on weak_dep_features_implicit (3/3)
-            ",
-        );
-    cargo_hack(["check", "--feature-powerset", "--optional-deps"])
-        .assert_success2("weak_dep_features_implicit", require)
+    cargo_hack(["print-cwd", "--workspace", "--chdir-instead-of-manifest-path", "-v"])
+        .env("PATH", &path)
+        .assert_success("virtual")
         .stderr_contains(
             "
-            running `cargo check --no-default-features` on weak_dep_features_implicit (1/4)
-            running `cargo check --no-default-features --features default,easytime` on weak_dep_features_implicit (2/4)
-            running `cargo check --no-default-features --features default` on weak_dep_features_implicit (3/4)
-            running `cargo check --no-default-features --features easytime` on weak_dep_features_implicit (4/4)
+            cargo print-cwd` (in member1) (1/3)
+            cargo print-cwd` (in member2) (2/3)
+            cargo print-cwd` (in dir/not_find_manifest) (3/3)
             ",
-        );
-}
+        )
+        .stderr_not_contains("--manifest-path");
 
-#[test]
-fn empty_string() {
-    cargo_hack(["check", "--each-feature", "--skip", ""])
+    cargo_hack(["check", "--chdir-instead-of-manifest-path", "--print-command-list"])
         .assert_success("real")
-        .stderr_not_contains("not found");
-}
+        .stdout_contains("cargo check (in .)")
+        .stdout_not_contains("--manifest-path");
 
-#[test]
-fn print_command_list() {
-    cargo_hack(["check", "--each-feature", "--print-command-list"])
-        .assert_success("real")
-        .stdout_contains(
-            "
-            cargo check --manifest-path Cargo.toml --all-features
-            cargo check --manifest-path Cargo.toml --no-default-features
-            cargo check --manifest-path Cargo.toml --no-default-features --features a
-            cargo check --manifest-path Cargo.toml --no-default-features --features b
-            cargo check --manifest-path Cargo.toml --no-default-features --features c
-            cargo check --manifest-path Cargo.toml --no-default-features --features default
-            ",
-        )
-        .stdout_not_contains("`");
+    cargo_hack(["check", "--chdir-instead-of-manifest-path", "--no-manifest-path"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--no-manifest-path may not be used together with --chdir-instead-of-manifest-path",
+        );
 }
 
 #[test]
@@ -1976,6 +6034,47 @@ fn partition() {
         );
 }
 
+#[test]
+fn partition_named() {
+    let (_test_project, cur_dir) = test_project("real");
+    let running = |partition: &str| {
+        let output = cargo_hack(["check", "--feature-powerset", "--partition", partition])
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter(|line| line.contains("running `cargo check"))
+            .map(|line| {
+                let (_, progress) = line.split_once(" on real (").unwrap();
+                progress.split_once('/').unwrap().0.parse::<usize>().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+    let first = running("first=0%..50%");
+    let second = running("second=50%..100%");
+    assert_eq!(first, (1..=8).collect::<Vec<_>>());
+    assert_eq!(second, (9..=17).collect::<Vec<_>>());
+
+    cargo_hack(["check", "--feature-powerset", "--partition", "big=0%..60%"])
+        .assert_success("real")
+        .stderr_contains(
+            "
+            running `cargo check --no-default-features --features default` on real (10/17)
+            skipping `cargo check --no-default-features --features a,default` on real (11/17)
+            ",
+        );
+
+    cargo_hack(["check", "--feature-powerset", "--partition", "tiny=0%..1%"])
+        .assert_failure("real")
+        .stderr_contains(
+            "partition tiny=0%..1% contains none of the 17 planned runs; no commands are run in \
+             this partition",
+        )
+        .stderr_not_contains("running `cargo check");
+}
+
 #[test]
 fn partition_bad() {
     cargo_hack(["check", "--each-feature", "--partition", "foo/bar"])
@@ -1994,6 +6093,14 @@ fn partition_bad() {
         .assert_failure("real")
         .stderr_contains("bad or out-of-range partition: 4/3");
 
+    for partition in ["=0%..50%", "a=50%..50%", "a=0%..101%", "a=0..50", "a b=0%..50%", "a=50%"] {
+        cargo_hack(["check", "--each-feature", "--partition", partition])
+            .assert_failure("real")
+            .stderr_contains(format!(
+                "bad or out-of-range partition: {partition} (expected M/N or NAME=START%..END%"
+            ));
+    }
+
     cargo_hack([
         "check",
         "--each-feature",
@@ -2008,12 +6115,289 @@ fn partition_bad() {
     );
 }
 
+#[test]
+fn shuffle() {
+    let (_tmp, cur_dir) = test_project("virtual");
+    let list = |seed: &str| {
+        let output =
+            cargo_hack(["check", "--workspace", "--feature-powerset", "--print-command-list"])
+                .args(["--shuffle", seed])
+                .current_dir(&cur_dir)
+                .output()
+                .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        assert!(
+            stderr.contains(&format!(
+                "shuffling the order of runs with seed {seed}; use `--shuffle {seed}` to reproduce \
+                 it"
+            )),
+            "{stderr}"
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let output = cargo_hack(["check", "--workspace", "--feature-powerset", "--print-command-list"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let unshuffled = String::from_utf8(output.stdout).unwrap();
+    let sorted = |s: &str| {
+        let mut lines: Vec<_> = s.lines().map(str::to_owned).collect();
+        lines.sort();
+        lines
+    };
+
+    let first = list("12345");
+    assert_eq!(first, list("12345"));
+    assert_ne!(first, list("54321"));
+    assert_ne!(first, unshuffled);
+    // The same runs are planned.
+    assert_eq!(sorted(&first), sorted(&unshuffled));
+
+    cargo_hack(["check", "--shuffle-keep-warmup"])
+        .assert_failure("real")
+        .stderr_contains("--shuffle-keep-warmup can only be used together with --shuffle");
+    cargo_hack(["check", "--shuffle=x"])
+        .assert_failure("real")
+        .stderr_contains("failed to parse argument for --shuffle");
+}
+
+#[test]
+fn only_run() {
+    let (_tmp, cur_dir) = test_project("real");
+    let output = cargo_hack(["check", "--each-feature", "--print-command-list"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{stdout}");
+    let ids: Vec<_> = stdout.lines().map(|line| line.rsplit_once("  # ").unwrap().1).collect();
+    assert_eq!(ids.len(), 6, "{stdout}");
+    assert!(ids.iter().all(|id| id.len() == 8), "{stdout}");
+    // The ids are stable across invocations.
+    let output = cargo_hack(["check", "--each-feature", "--print-command-list"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), stdout);
+    let line = stdout.lines().find(|line| line.contains("--features b")).unwrap();
+    let id = line.rsplit_once("  # ").unwrap().1;
+
+    let output = cargo_hack(["check", "--each-feature", "--only-run", id])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(stderr.matches("running `").count(), 1, "{stderr}");
+    assert!(
+        stderr.contains(&format!(
+            "running `cargo check --no-default-features --features b` on real (4/6) [{id}]"
+        )),
+        "{stderr}"
+    );
+    assert!(stderr.contains("skipping `cargo check --all-features` on real (1/6)"), "{stderr}");
+
+    // The order of features does not change the id.
+    let output = cargo_hack(["check", "--features", "b,a", "--print-command-list"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout_ab = String::from_utf8(output.stdout).unwrap();
+    let output = cargo_hack(["check", "--features", "a,b", "--print-command-list"])
+        .current_dir(&cur_dir)
+        .output()
+        .unwrap();
+    let stdout_ba = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout_ab.rsplit_once("  # ").unwrap().1, stdout_ba.rsplit_once("  # ").unwrap().1);
+
+    cargo_hack(["check", "--each-feature", "--only-run", id, "--only-run", "0000abcd"])
+        .assert_failure("real")
+        .stderr_contains("the following ids passed to --only-run did not match any run: 0000abcd");
+    cargo_hack(["check", "--each-feature", "--only-run", "xyz"])
+        .assert_failure("real")
+        .stderr_contains("invalid run id `xyz` for --only-run");
+}
+
+#[test]
+fn changed_features_only() {
+    use std::process::Command;
+
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "{}", String::from_utf8_lossy(&status.stderr));
+    };
+    let run = |args: &[&str]| {
+        cargo_hack(["check", "--workspace", "--changed-features-only"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap()
+    };
+    fs::write(cur_dir.join(".gitignore"), "/target\n/Cargo.lock\n").unwrap();
+    git(&["init", "--quiet"]);
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "initial"]);
+
+    // Modify `b` and add `zstd` to member1.
+    let manifest = cur_dir.join("member1/Cargo.toml");
+    let content = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, content.replace("b = []", "b = [\"a\"]\nzstd = []")).unwrap();
+
+    let output = run(&["HEAD", "--each-feature"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    for pat in [
+        "skipped running on package `member2`: no features changed since HEAD",
+        "skipped running on package `member3`: no features changed since HEAD",
+        "skipped running on package `real`: no features changed since HEAD",
+        "running 3 of the feature combinations of package `member1`, which contain features \
+         changed since HEAD: `b`, `zstd`",
+        "running `cargo check --all-features` on member1 (1/3)",
+        "running `cargo check --no-default-features --features b` on member1 (2/3)",
+        "running `cargo check --no-default-features --features zstd` on member1 (3/3)",
+    ] {
+        assert!(stderr.contains(pat), "{pat}\nSTDERR:\n{stderr}");
+    }
+
+    // Every combination of the powerset contains a changed feature.
+    let output = run(&["HEAD", "--feature-powerset"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    let runs: Vec<_> = stderr.lines().filter(|l| l.contains("running `cargo check")).collect();
+    assert_eq!(runs.len(), 16, "{stderr}");
+    for run in runs {
+        let features = run.split("--features ").nth(1).unwrap().split('`').next().unwrap();
+        assert!(features.split(',').any(|f| f == "b" || f == "zstd"), "{run}");
+    }
+
+    // Nothing changed.
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "zstd"]);
+    let output = run(&["HEAD", "--each-feature"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(stderr.contains("no commands to run"), "{stderr}");
+
+    let output = run(&["no-such-rev", "--each-feature"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("--changed-features-only: `no-such-rev` is not a valid git revision"),
+        "{stderr}"
+    );
+
+    cargo_hack(["check", "--changed-features-only", "HEAD"])
+        .assert_failure("real")
+        .stderr_contains(
+            "--changed-features-only can only be used together with either --each-feature or \
+             --feature-powerset",
+        );
+}
+
+#[test]
+fn skip_unless_manifest_changed() {
+    use std::process::Command;
+
+    use fs_err as fs;
+
+    let (_test_project, cur_dir) = test_project("real");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "{}", String::from_utf8_lossy(&status.stderr));
+    };
+    let run = |args: &[&str]| {
+        let output = cargo_hack(["check", "--workspace", "--skip-unless-manifest-changed"])
+            .args(args)
+            .current_dir(&cur_dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    // Cargo.lock generated by the runs is not compared if it is ignored.
+    fs::write(cur_dir.join(".gitignore"), "/target\n/Cargo.lock\n").unwrap();
+    git(&["init", "--quiet"]);
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "initial"]);
+    let skipped = "no manifest changed since HEAD; skipping all runs";
+
+    // Only code changed.
+    fs::write(cur_dir.join("member1/src/lib.rs"), "pub fn f() {}\n").unwrap();
+    let stderr = run(&["HEAD"]);
+    assert!(stderr.contains(skipped), "{stderr}");
+    assert!(!stderr.contains("running `cargo check`"), "{stderr}");
+
+    // A manifest of a selected package changed.
+    let manifest = cur_dir.join("member2/Cargo.toml");
+    fs::write(&manifest, fs::read_to_string(&manifest).unwrap() + "# changed\n").unwrap();
+    let stderr = run(&["HEAD", "-v"]);
+    assert!(!stderr.contains(skipped), "{stderr}");
+    assert!(
+        stderr.contains(&format!("member2{MAIN_SEPARATOR}Cargo.toml` changed since HEAD")),
+        "{stderr}"
+    );
+    assert!(stderr.contains("cargo check --manifest-path member2"), "{stderr}");
+    // The changed package is not selected.
+    let stderr = run(&["HEAD", "--exclude", "member2"]);
+    assert!(stderr.contains(skipped), "{stderr}");
+
+    // The runs are performed if the comparison fails.
+    let stderr = run(&["no-such-rev"]);
+    assert!(
+        stderr.contains("failed to compare manifests with no-such-rev; performing all runs"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("running `cargo check"), "{stderr}");
+}
+
 #[test]
 fn help() {
     let short = &*test_helper::cli::CommandExt::assert_success(&mut cargo_hack(["-h"])).stdout;
     assert_diff(manifest_dir().join("tests/short-help.txt"), short);
     let long = &*test_helper::cli::CommandExt::assert_success(&mut cargo_hack(["--help"])).stdout;
     assert_diff(manifest_dir().join("tests/long-help.txt"), long);
+    for args in
+        [&["help"][..], &["check", "--help-hack"], &["check", "--each-feature", "--help-hack"]]
+    {
+        let long = &*test_helper::cli::CommandExt::assert_success(&mut cargo_hack(args)).stdout;
+        assert_diff(manifest_dir().join("tests/long-help.txt"), long);
+    }
+}
+
+#[test]
+fn forward_list_and_help() {
+    // Other flags are not validated.
+    cargo_hack(["--depth", "2", "--list"])
+        .assert_success("real")
+        .stdout_contains("Installed Commands:")
+        .stderr_not_contains("--depth");
+
+    // The help of the subcommand is shown once, not on each package.
+    cargo_hack(["check", "--each-feature", "--help"])
+        .assert_success("real")
+        .stdout_contains("cargo check [OPTIONS]")
+        .stderr_contains(
+            "info: showing the help of `cargo check`; run `cargo hack check --help-hack` or \
+             `cargo hack help` to show the help of cargo-hack",
+        )
+        .stderr_not_contains("running");
+
+    // `cargo hack help <COMMAND>` is forwarded to cargo.
+    cargo_hack(["help", "check"]).assert_success("real").stdout_contains("cargo-check");
 }
 
 #[test]
@@ -2024,6 +6408,27 @@ fn version() {
         .stdout_eq(expected);
 }
 
+#[test]
+fn generate_completion() {
+    for (shell, expected) in [
+        ("bash", "complete -F _cargo_hack -o bashdefault -o default cargo-hack"),
+        ("zsh", "#compdef cargo-hack"),
+        ("fish", "complete -c cargo -n __cargo_hack_needs_completion -l each-feature"),
+        ("powershell", "Register-ArgumentCompleter -Native -CommandName @('cargo', 'cargo-hack')"),
+    ] {
+        let output = test_helper::cli::CommandExt::assert_success(&mut cargo_hack([
+            "--generate-completion",
+            shell,
+        ]));
+        assert!(output.stdout.contains(expected), "{shell}: {}", output.stdout);
+    }
+
+    cargo_hack(["--generate-completion", "tcsh"]).assert_failure("real").stderr_contains(
+        "argument for --generate-completion must be bash, zsh, fish, or powershell, but found \
+         `tcsh`",
+    );
+}
+
 #[test]
 fn update_readme() {
     let new = test_helper::cli::CommandExt::assert_success(&mut cargo_hack(["--help"])).stdout;